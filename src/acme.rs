@@ -0,0 +1,685 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! TLS certificates obtained and renewed automatically with ACME.
+//!
+//! Only the HTTP-01 challenge is supported: while a certificate is being
+//! obtained, a plain HTTP server answers the challenges on the configured
+//! address. The account key and the certificates are stored in the state
+//! directory, so they're reused across restarts.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use base64;
+use rcgen;
+use reqwest::{self, header};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tiny_http;
+
+use common::prelude::*;
+use common::config::AcmeConfig;
+use web::SharedCertificate;
+
+
+/// How often the certificate is checked for renewal.
+const CHECK_INTERVAL: u64 = 12 * 60 * 60;
+/// How long to wait before trying again if obtaining the certificate failed.
+const RETRY_INTERVAL: u64 = 60 * 60;
+/// How many times the status of orders and authorizations is checked.
+const POLL_ATTEMPTS: usize = 30;
+
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+
+fn invalid_response(reason: &str) -> Error {
+    ErrorKind::AcmeInvalidResponse(reason.into()).into()
+}
+
+
+/// The key of the ACME account, used to sign every request.
+struct AccountKey {
+    pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AccountKey {
+    /// Load the key from the file, creating a new one if it doesn't exist.
+    fn load_or_create(path: &Path) -> Result<Self> {
+        let rng = SystemRandom::new();
+
+        let mut pkcs8 = Vec::new();
+        if path.exists() {
+            File::open(path)?.read_to_end(&mut pkcs8)?;
+        } else {
+            pkcs8 = EcdsaKeyPair::generate_pkcs8(
+                &ECDSA_P256_SHA256_FIXED_SIGNING, &rng,
+            ).map_err(|_| ErrorKind::AcmeInvalidKey)?.as_ref().to_vec();
+            File::create(path)?.write_all(&pkcs8)?;
+        }
+
+        let pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8,
+        ).map_err(|_| ErrorKind::AcmeInvalidKey)?;
+
+        Ok(AccountKey { pair, rng })
+    }
+
+    /// The public key, as a JSON Web Key with the members sorted as
+    /// required to calculate its thumbprint.
+    fn jwk(&self) -> String {
+        // The public key is the uncompressed point on the curve
+        let point = self.pair.public_key().as_ref();
+        format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            b64(&point[1..33]), b64(&point[33..65]),
+        )
+    }
+
+    fn thumbprint(&self) -> String {
+        b64(&Sha256::digest(self.jwk().as_bytes()))
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.pair
+            .sign(&self.rng, data)
+            .map_err(|_| ErrorKind::AcmeInvalidKey)?
+            .as_ref()
+            .to_vec())
+    }
+}
+
+
+/// The tokens of the pending challenges, with their key authorizations.
+type Challenges = Arc<RwLock<HashMap<String, String>>>;
+
+
+/// A plain HTTP server answering the HTTP-01 challenges.
+struct ChallengeServer {
+    should_stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl ChallengeServer {
+    fn start(bind: SocketAddr, challenges: Challenges) -> Result<Self> {
+        let server = tiny_http::Server::http(bind)?;
+        let should_stop = Arc::new(AtomicBool::new(false));
+
+        let thread_should_stop = should_stop.clone();
+        let handle = thread::spawn(move || {
+            while !thread_should_stop.load(Ordering::Relaxed) {
+                let request = match server.recv_timeout(
+                    Duration::from_millis(100)
+                ) {
+                    Ok(Some(request)) => request,
+                    Ok(None) => continue,
+                    Err(_) => break,
+                };
+
+                let key_authorization = if request.url().starts_with(
+                    CHALLENGE_PREFIX
+                ) {
+                    let token = &request.url()[CHALLENGE_PREFIX.len()..];
+                    challenges
+                        .read()
+                        .ok()
+                        .and_then(|challenges| challenges.get(token).cloned())
+                } else {
+                    None
+                };
+
+                let _ = if let Some(key_authorization) = key_authorization {
+                    request.respond(
+                        tiny_http::Response::from_string(key_authorization)
+                    )
+                } else {
+                    request.respond(
+                        tiny_http::Response::from_string("")
+                            .with_status_code(404)
+                    )
+                };
+            }
+        });
+
+        Ok(ChallengeServer { should_stop, handle })
+    }
+
+    fn stop(self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+
+struct AcmeClient {
+    http: reqwest::Client,
+    key: AccountKey,
+    directory: Value,
+    nonce: Option<String>,
+    account_url: Option<String>,
+}
+
+impl AcmeClient {
+    fn new(directory_url: &str, key: AccountKey) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let directory = http.get(directory_url).send()?.json()?;
+
+        Ok(AcmeClient {
+            http,
+            key,
+            directory,
+            nonce: None,
+            account_url: None,
+        })
+    }
+
+    fn endpoint(&self, name: &str) -> Result<String> {
+        self.directory[name]
+            .as_str()
+            .map(|url| url.to_string())
+            .ok_or_else(|| invalid_response(&format!("{} missing", name)))
+    }
+
+    fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let response = self.http.head(&self.endpoint("newNonce")?).send()?;
+        replay_nonce(&response)
+            .ok_or_else(|| invalid_response("Replay-Nonce missing"))
+    }
+
+    /// Sign the payload, identifying the account with its URL after it's
+    /// registered. An empty payload is used to fetch resources.
+    fn jws(&mut self, url: &str, payload: Option<&Value>) -> Result<String> {
+        let mut protected = json!({
+            "alg": "ES256",
+            "nonce": self.nonce()?,
+            "url": url,
+        });
+        if let Some(ref account_url) = self.account_url {
+            protected["kid"] = json!(account_url);
+        } else {
+            protected["jwk"] = ::serde_json::from_str(&self.key.jwk())?;
+        }
+
+        let protected = b64(protected.to_string().as_bytes());
+        let payload = payload
+            .map(|payload| b64(payload.to_string().as_bytes()))
+            .unwrap_or_default();
+        let signature = self.key.sign(
+            format!("{}.{}", protected, payload).as_bytes()
+        )?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(&signature),
+        }).to_string())
+    }
+
+    fn post(
+        &mut self, url: &str, payload: Option<&Value>,
+    ) -> Result<reqwest::Response> {
+        // Requests with an expired nonce are retried once with a new one
+        let mut retried = false;
+        loop {
+            let body = self.jws(url, payload)?;
+            let mut response = self.http
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(body)
+                .send()?;
+            self.nonce = replay_nonce(&response);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let problem: Value = response.json().unwrap_or(Value::Null);
+            let kind = problem["type"].as_str().unwrap_or("");
+            if kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+
+            return Err(ErrorKind::AcmeBadStatus(
+                response.status().as_u16(),
+                problem["detail"].as_str().unwrap_or(kind).to_string(),
+            ).into());
+        }
+    }
+
+    /// Register the account, or find the existing one for the key.
+    fn register(&mut self, email: Option<&str>) -> Result<()> {
+        let mut payload = json!({"termsOfServiceAgreed": true});
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+
+        let url = self.endpoint("newAccount")?;
+        let response = self.post(&url, Some(&payload))?;
+        self.account_url = Some(location(&response)?);
+
+        Ok(())
+    }
+
+    /// Fetch the resource until its status is not pending anymore.
+    fn wait(&mut self, url: &str) -> Result<Value> {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource: Value = self.post(url, None)?.json()?;
+            match resource["status"].as_str() {
+                Some("pending") | Some("processing") => {
+                    thread::sleep(Duration::from_secs(2));
+                }
+                _ => return Ok(resource),
+            }
+        }
+
+        Err(invalid_response("timed out waiting for the ACME server"))
+    }
+
+    fn authorize(
+        &mut self, url: &str, domain: &str, challenges: &Challenges,
+    ) -> Result<()> {
+        let authorization: Value = self.post(url, None)?.json()?;
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+
+        let challenge = authorization["challenges"]
+            .as_array()
+            .and_then(|all| all.iter().find(|c| c["type"] == "http-01"))
+            .ok_or_else(|| invalid_response("HTTP-01 challenge missing"))?;
+        let token = challenge["token"]
+            .as_str()
+            .ok_or_else(|| invalid_response("challenge token missing"))?;
+        let challenge_url = challenge["url"]
+            .as_str()
+            .ok_or_else(|| invalid_response("challenge URL missing"))?;
+
+        challenges.write()?.insert(
+            token.to_string(),
+            format!("{}.{}", token, self.key.thumbprint()),
+        );
+        let result = self
+            .post(challenge_url, Some(&json!({})))
+            .and_then(|_| self.wait(url));
+        challenges.write()?.remove(token);
+
+        if result?["status"] == "valid" {
+            Ok(())
+        } else {
+            Err(ErrorKind::AcmeChallengeFailed(domain.into()).into())
+        }
+    }
+
+    /// Obtain a certificate for the domain, returning the PEM-encoded
+    /// certificate chain and private key.
+    fn obtain(
+        &mut self, domain: &str, challenges: &Challenges,
+    ) -> Result<(String, String)> {
+        let url = self.endpoint("newOrder")?;
+        let mut response = self.post(&url, Some(&json!({
+            "identifiers": [{"type": "dns", "value": domain}],
+        })))?;
+        let order_url = location(&response)?;
+        let order: Value = response.json()?;
+
+        let authorizations = order["authorizations"]
+            .as_array()
+            .ok_or_else(|| invalid_response("authorizations missing"))?;
+        for authorization in authorizations {
+            let url = authorization
+                .as_str()
+                .ok_or_else(|| invalid_response("invalid authorization"))?;
+            self.authorize(url, domain, challenges)?;
+        }
+
+        // The private key of the certificate is generated along with the
+        // signing request
+        let mut params = rcgen::CertificateParams::new(vec![domain.into()]);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        let certificate = rcgen::Certificate::from_params(params)
+            .map_err(|_| ErrorKind::AcmeInvalidKey)?;
+        let csr = certificate
+            .serialize_request_der()
+            .map_err(|_| ErrorKind::AcmeInvalidKey)?;
+
+        let finalize = order["finalize"]
+            .as_str()
+            .ok_or_else(|| invalid_response("finalize URL missing"))?;
+        self.post(finalize, Some(&json!({"csr": b64(&csr)})))?;
+
+        let order = self.wait(&order_url)?;
+        let certificate_url = order["certificate"]
+            .as_str()
+            .ok_or_else(|| invalid_response("the order is not valid"))?;
+        let chain = self.post(certificate_url, None)?.text()?;
+
+        Ok((chain, certificate.serialize_private_key_pem()))
+    }
+}
+
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+
+fn location(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+        .ok_or_else(|| invalid_response("Location missing"))
+}
+
+
+/// The files of the certificate of a domain in the state directory.
+struct StoredCertificate {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl StoredCertificate {
+    fn new(state_dir: &Path, domain: &str) -> Result<Self> {
+        let dir = state_dir.join(domain);
+        fs::create_dir_all(&dir)?;
+
+        Ok(StoredCertificate {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        })
+    }
+
+    fn load(&self) -> Result<Option<(String, String)>> {
+        if !self.cert.exists() || !self.key.exists() {
+            return Ok(None);
+        }
+
+        let mut cert = String::new();
+        File::open(&self.cert)?.read_to_string(&mut cert)?;
+        let mut key = String::new();
+        File::open(&self.key)?.read_to_string(&mut key)?;
+
+        Ok(Some((cert, key)))
+    }
+
+    /// Check if the certificate is missing or older than `renew_after`
+    /// seconds.
+    fn needs_renewal(&self, renew_after: u64) -> bool {
+        let age = fs::metadata(&self.cert)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+        match age {
+            Some(age) => age.as_secs() >= renew_after,
+            None => true,
+        }
+    }
+
+    fn save(&self, cert: &str, key: &str) -> Result<()> {
+        // The key is written first, so the certificate's modification time
+        // is updated only when both are saved
+        File::create(&self.key)?.write_all(key.as_bytes())?;
+        File::create(&self.cert)?.write_all(cert.as_bytes())?;
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct AcmeSettings {
+    domain: String,
+    email: Option<String>,
+    directory: String,
+    state_dir: PathBuf,
+    challenge_bind: SocketAddr,
+    renew_after: u64,
+}
+
+impl AcmeSettings {
+    fn obtain(&self, challenges: &Challenges) -> Result<(String, String)> {
+        let key = AccountKey::load_or_create(
+            &self.state_dir.join("account.der")
+        )?;
+        let mut client = AcmeClient::new(&self.directory, key)?;
+        client.register(self.email.as_deref())?;
+
+        let server = ChallengeServer::start(
+            self.challenge_bind, challenges.clone(),
+        )?;
+        let result = client.obtain(&self.domain, challenges);
+        server.stop();
+
+        result
+    }
+}
+
+
+/// This struct controls the thread obtaining and renewing the certificate.
+pub struct Acme {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Acme {
+    /// Start obtaining and renewing the certificate, if ACME is enabled in
+    /// the configuration. The stored certificate is used right away.
+    pub fn start(
+        config: &AcmeConfig, shared: Arc<SharedCertificate>,
+    ) -> Result<Option<Self>> {
+        let settings = if let Some(ref domain) = config.domain {
+            AcmeSettings {
+                domain: domain.clone(),
+                email: config.email.clone(),
+                directory: config.directory.clone(),
+                state_dir: PathBuf::from(&config.state_dir),
+                challenge_bind: config.challenge_bind,
+                renew_after: config.renew_after.as_u64(),
+            }
+        } else {
+            return Ok(None);
+        };
+
+        let stored = StoredCertificate::new(
+            &settings.state_dir, &settings.domain,
+        )?;
+        if let Some((cert, key)) = stored.load()? {
+            shared.set(&cert, &key)?;
+        }
+
+        let (stop_send, stop_recv) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let challenges = Arc::new(RwLock::new(HashMap::new()));
+
+            loop {
+                let mut wait = CHECK_INTERVAL;
+                if stored.needs_renewal(settings.renew_after) {
+                    let result = settings.obtain(&challenges).and_then(
+                        |(cert, key)| {
+                            stored.save(&cert, &key)?;
+                            shared.set(&cert, &key)
+                        }
+                    );
+
+                    if let Err(err) = result.chain_err(|| {
+                        ErrorKind::AcmeFailed(settings.domain.clone())
+                    }) {
+                        err.pretty_print();
+                        wait = RETRY_INTERVAL;
+                    } else {
+                        println!(
+                            "Obtained a new certificate for {}",
+                            settings.domain,
+                        );
+                    }
+                }
+
+                // Wait for the next check, exiting if Fisher is stopped
+                match stop_recv.recv_timeout(Duration::from_secs(wait)) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Some(Acme {
+            stop: stop_send,
+            handle,
+        }))
+    }
+
+    /// Stop renewing the certificate. This waits for the certificate being
+    /// obtained, if any.
+    pub fn stop(self) {
+        // The thread might be already stopped
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, RwLock};
+
+    use ring::signature::{KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use serde_json::{self, Value};
+    use tempdir::TempDir;
+
+    use super::{b64, AccountKey, ChallengeServer, StoredCertificate};
+
+
+    #[test]
+    fn test_account_key() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("account.der");
+
+        // The key is persisted
+        let key = AccountKey::load_or_create(&path).unwrap();
+        assert!(path.exists());
+        let loaded = AccountKey::load_or_create(&path).unwrap();
+        assert_eq!(key.jwk(), loaded.jwk());
+        assert_eq!(key.thumbprint(), loaded.thumbprint());
+
+        // The JWK contains only the required members, in order
+        let jwk: Value = serde_json::from_str(&key.jwk()).unwrap();
+        assert_eq!(jwk["kty"], json!("EC"));
+        assert_eq!(jwk["crv"], json!("P-256"));
+        assert!(key.jwk().starts_with(r#"{"crv":"P-256","kty":"EC","x":""#));
+
+        // The signatures can be verified with the public key
+        let signature = key.sign(b"payload").unwrap();
+        let public = UnparsedPublicKey::new(
+            &ECDSA_P256_SHA256_FIXED, key.pair.public_key().as_ref().to_vec(),
+        );
+        assert!(public.verify(b"payload", &signature).is_ok());
+        assert!(public.verify(b"other", &signature).is_err());
+    }
+
+
+    #[test]
+    fn test_challenge_server() {
+        let challenges = Arc::new(RwLock::new(HashMap::new()));
+        challenges.write().unwrap().insert(
+            "token".to_string(), "token.thumbprint".to_string(),
+        );
+
+        // Find a free port for the server
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = ChallengeServer::start(addr, challenges).unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\
+                         Connection: close\r\n\r\n", path,
+            ).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = get("/.well-known/acme-challenge/token");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("token.thumbprint"), "{}", response);
+
+        let response = get("/.well-known/acme-challenge/invalid");
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        let response = get("/token");
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+        server.stop();
+    }
+
+
+    #[test]
+    fn test_stored_certificate() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let stored = StoredCertificate::new(dir.path(), "example.com").unwrap();
+
+        assert!(stored.load().unwrap().is_none());
+        assert!(stored.needs_renewal(60));
+
+        stored.save("cert", "key").unwrap();
+        assert_eq!(
+            stored.load().unwrap(), Some(("cert".into(), "key".into()))
+        );
+        assert!(!stored.needs_renewal(60));
+        assert!(stored.needs_renewal(0));
+    }
+
+
+    #[test]
+    fn test_b64() {
+        // Padding is not allowed in ACME
+        assert_eq!(b64(b"a"), "YQ");
+        assert_eq!(b64(&[0xfb, 0xff]), "-_8");
+    }
+}
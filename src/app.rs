@@ -14,43 +14,90 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::net::SocketAddr;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use std::collections::HashMap;
+use std::time::Duration;
 
 use common::prelude::*;
 use common::state::State;
-use common::config::{Config, HttpConfig};
-
-use scripts::{Blueprint, Repository, JobContext};
+use common::config::{
+    AcmeConfig, Config, HeartbeatConfig, HttpConfig, JobsConfig,
+    ScriptsConfig, TracingConfig, VaultConfig,
+};
+use common::structs::BuildInfo;
+
+use scripts::{
+    ArtifactUploads, Blueprint, Repository, JobContext, RunningJobs, Watcher,
+};
 use processor::{Processor, ProcessorApi};
-use web::WebApp;
+use web::{Approvals, Freezes, SharedCertificate, WebApp};
+use heartbeat::Heartbeat;
+use traces::Exporter;
+use audit;
+use utils;
+#[cfg(feature = "acme")]
+use acme::Acme;
+#[cfg(feature = "vault")]
+use vault::Vault;
 
 
 struct InnerApp {
     locked: bool,
-    scripts_blueprint: Blueprint,
+    scripts_blueprint: Arc<Mutex<Blueprint>>,
     processor: Processor<Repository>,
     http: Option<WebApp<ProcessorApi<Repository>>>,
+    heartbeat: Option<Heartbeat>,
+    exporter: Option<Exporter>,
+    watcher: Option<Watcher>,
+    #[cfg(feature = "vault")]
+    vault: Option<Vault>,
+    #[cfg(feature = "acme")]
+    acme: Option<Acme>,
+    acme_cert: Option<Arc<SharedCertificate>>,
+    build_info: Arc<RwLock<BuildInfo>>,
+    approvals: Arc<Mutex<Approvals>>,
+    freezes: Arc<Mutex<Freezes>>,
+    uploads: Arc<Mutex<ArtifactUploads>>,
+    running: Arc<Mutex<RunningJobs>>,
 }
 
 impl InnerApp {
     fn new() -> Result<Self> {
         let state = Arc::new(State::new());
         let blueprint = Blueprint::new(state.clone());
+        let uploads = Arc::new(Mutex::new(ArtifactUploads::new()));
+        let running = Arc::new(Mutex::new(RunningJobs::new()));
 
         let processor = Processor::new(
             0,
             Arc::new(blueprint.repository()),
-            JobContext::default(),
+            JobContext {
+                uploads: uploads.clone(),
+                running: running.clone(),
+                .. JobContext::default()
+            },
             state.clone(),
         )?;
 
         Ok(InnerApp {
             locked: false,
-            scripts_blueprint: blueprint,
+            scripts_blueprint: Arc::new(Mutex::new(blueprint)),
             http: None,
+            heartbeat: None,
+            exporter: None,
+            watcher: None,
+            #[cfg(feature = "vault")]
+            vault: None,
+            #[cfg(feature = "acme")]
+            acme: None,
+            acme_cert: None,
             processor,
+            build_info: Arc::new(RwLock::new(BuildInfo::current())),
+            approvals: Arc::new(Mutex::new(Approvals::new())),
+            freezes: Arc::new(Mutex::new(Freezes::new())),
+            uploads,
+            running,
         })
     }
 
@@ -61,9 +108,15 @@ impl InnerApp {
         }
 
         let http = WebApp::new(
-            Arc::new(self.scripts_blueprint.repository()),
+            self.scripts_blueprint.clone(),
             config,
             self.processor.api(),
+            self.build_info.clone(),
+            self.approvals.clone(),
+            self.freezes.clone(),
+            self.uploads.clone(),
+            self.running.clone(),
+            self.acme_cert.clone(),
         )?;
 
         // Lock the server if it was locked before
@@ -76,29 +129,155 @@ impl InnerApp {
         Ok(())
     }
 
-    fn set_scripts_path<P: AsRef<Path>>(
-        &mut self, path: P, recursive: bool,
-    ) -> Result<()> {
-        self.scripts_blueprint.clear();
-        self.scripts_blueprint.collect_path(path, recursive)?;
+    fn reload_tls(&mut self) -> Result<()> {
+        if let Some(ref mut http) = self.http {
+            http.reload_tls()?;
+        }
+        Ok(())
+    }
+
+    fn restart_heartbeat(&mut self, config: &HeartbeatConfig) -> Result<()> {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+
+        self.heartbeat = Heartbeat::start(config, self.processor.api())?;
+
+        Ok(())
+    }
+
+    fn restart_tracing(&mut self, config: &TracingConfig) -> Result<()> {
+        if let Some(exporter) = self.exporter.take() {
+            exporter.stop();
+        }
+
+        self.exporter = Exporter::start(config)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "vault")]
+    fn restart_vault(&mut self, config: &VaultConfig) -> Result<()> {
+        if let Some(vault) = self.vault.take() {
+            vault.stop();
+        }
+
+        // The providers keep a copy of the secrets they resolved
+        let blueprint = self.scripts_blueprint.clone();
+        let processor = self.processor.api();
+        self.vault = Vault::start(config, move || {
+            blueprint.lock()?.reload()?;
+            processor.cleanup()?;
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "vault"))]
+    fn restart_vault(&mut self, config: &VaultConfig) -> Result<()> {
+        if config.address.is_some() {
+            Err(ErrorKind::VaultDisabled.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "acme")]
+    fn restart_acme(&mut self, config: &AcmeConfig) -> Result<()> {
+        if let Some(acme) = self.acme.take() {
+            acme.stop();
+        }
+
+        let shared = Arc::new(SharedCertificate::new());
+        self.acme = Acme::start(config, shared.clone())?;
+        self.acme_cert = if self.acme.is_some() { Some(shared) } else { None };
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "acme"))]
+    fn restart_acme(&mut self, config: &AcmeConfig) -> Result<()> {
+        if config.domain.is_some() {
+            Err(ErrorKind::AcmeDisabled.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_scripts_path(&mut self, config: &ScriptsConfig) -> Result<()> {
+        // The watcher might be reloading the scripts in the meantime
+        if let Some(watcher) = self.watcher.take() {
+            watcher.stop();
+        }
+
+        {
+            let mut blueprint = self.scripts_blueprint.lock()?;
+            blueprint.clear();
+            blueprint.collect_path(&config.path, config.recursive)?;
+        }
         self.processor.api().cleanup()?;
 
+        if config.watch {
+            let blueprint = self.scripts_blueprint.clone();
+            let processor = self.processor.api();
+            self.watcher = Some(Watcher::start(
+                Path::new(&config.path), config.recursive, move || {
+                    blueprint.lock()?.reload()?;
+                    processor.cleanup()?;
+                    Ok(())
+                },
+            )?);
+        }
+
         Ok(())
     }
 
-    fn set_job_environment(&self, env: HashMap<String, String>) -> Result<()> {
+    fn set_job_context(
+        &self, env: HashMap<String, String>, jobs: &JobsConfig,
+    ) -> Result<()> {
+        // Fail early if the temporary directories can't be created
+        let temp_dir = jobs.temp_dir.as_ref().map(PathBuf::from);
+        if let Some(ref dir) = temp_dir {
+            utils::check_temp_dir(dir, jobs.require_tmpfs)?;
+        }
+
         self.processor.api().update_context(JobContext {
             environment: env,
+            temp_dir,
+            require_tmpfs: jobs.require_tmpfs,
+            uploads: self.uploads.clone(),
+            running: self.running.clone(),
+            before_job: jobs.before_job.clone(),
+            after_job: jobs.after_job.clone(),
             .. JobContext::default()
         })?;
         Ok(())
     }
 
+    fn set_config_hash(&self, hash: String) -> Result<()> {
+        self.build_info.write()?.config_hash = Some(hash);
+        Ok(())
+    }
+
     fn set_threads_count(&self, count: u16) -> Result<()> {
         self.processor.api().set_threads_count(count)?;
         Ok(())
     }
 
+    fn set_max_queued(&self, max: Option<usize>) -> Result<()> {
+        self.processor.api().set_max_queued(max)?;
+        Ok(())
+    }
+
+    fn set_stuck_jobs(&self, jobs: &JobsConfig) -> Result<()> {
+        let threshold = jobs.stuck_after
+            .as_ref()
+            .map(|after| Duration::from_secs(after.as_u64()));
+        self.processor.api().set_stuck_jobs(threshold, jobs.fail_stuck)?;
+        Ok(())
+    }
+
     fn http_addr(&self) -> Option<&SocketAddr> {
         if let Some(ref http) = self.http {
             Some(http.addr())
@@ -134,12 +313,39 @@ impl InnerApp {
             http.lock();
         }
 
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+
+        if let Some(watcher) = self.watcher.take() {
+            watcher.stop();
+        }
+
+        #[cfg(feature = "vault")]
+        {
+            if let Some(vault) = self.vault.take() {
+                vault.stop();
+            }
+        }
+
+        #[cfg(feature = "acme")]
+        {
+            if let Some(acme) = self.acme.take() {
+                acme.stop();
+            }
+        }
+
         self.processor.stop()?;
 
         if let Some(http) = self.http.take() {
             http.stop();
         }
 
+        // Export the spans of the last jobs before exiting
+        if let Some(exporter) = self.exporter.take() {
+            exporter.stop();
+        }
+
         Ok(())
     }
 }
@@ -152,13 +358,23 @@ pub struct Fisher {
 
 impl Fisher {
     pub fn new(config: Config) -> Result<Self> {
+        utils::set_timestamp_format(config.timestamp_format);
+        audit::configure(&config.audit)?;
+
         let mut inner = InnerApp::new()?;
-        inner.set_scripts_path(
-            &config.scripts.path, config.scripts.recursive,
-        )?;
-        inner.set_job_environment(config.env.clone())?;
+        // Vault must be ready before loading the scripts using its secrets
+        inner.restart_vault(&config.vault)?;
+        inner.set_scripts_path(&config.scripts)?;
+        inner.set_job_context(config.env.clone(), &config.jobs)?;
         inner.set_threads_count(config.jobs.threads)?;
+        inner.set_max_queued(config.jobs.max_queued)?;
+        inner.set_stuck_jobs(&config.jobs)?;
+        inner.restart_tracing(&config.tracing)?;
+        // The stored certificate must be loaded before starting the server
+        inner.restart_acme(&config.acme)?;
         inner.restart_http_server(&config.http)?;
+        inner.restart_heartbeat(&config.heartbeat)?;
+        inner.set_config_hash(config.hash())?;
 
         Ok(Fisher {
             config,
@@ -180,14 +396,48 @@ impl Fisher {
     }
 
     fn reload_inner(&mut self, new_config: Config) -> Result<()> {
+        utils::set_timestamp_format(new_config.timestamp_format);
+
+        // Open the audit log again if its configuration changed
+        if self.config.audit != new_config.audit {
+            audit::configure(&new_config.audit)?;
+        }
+
+        // Restart ACME if its configuration changed, along with the HTTP
+        // server using its certificate
+        let acme_changed = self.config.acme != new_config.acme;
+        if acme_changed {
+            self.inner.restart_acme(&new_config.acme)?;
+        }
+
         // Restart the HTTP server if its configuration changed
-        if self.config.http != new_config.http {
+        if acme_changed || self.config.http != new_config.http {
             self.inner.restart_http_server(&new_config.http)?;
+        } else {
+            // The certificates might have been renewed in the meantime
+            self.inner.reload_tls()?;
         }
 
         // Update the job context if the environment is different
-        if self.config.env != new_config.env {
-            self.inner.set_job_environment(new_config.env.clone())?;
+        if self.config.env != new_config.env
+            || self.config.jobs.temp_dir != new_config.jobs.temp_dir
+            || self.config.jobs.require_tmpfs != new_config.jobs.require_tmpfs
+            || self.config.jobs.before_job != new_config.jobs.before_job
+            || self.config.jobs.after_job != new_config.jobs.after_job
+        {
+            self.inner.set_job_context(
+                new_config.env.clone(), &new_config.jobs,
+            )?;
+        }
+
+        // Restart the heartbeat if its configuration changed
+        if self.config.heartbeat != new_config.heartbeat {
+            self.inner.restart_heartbeat(&new_config.heartbeat)?;
+        }
+
+        // Restart the exporter of the traces if its configuration changed
+        if self.config.tracing != new_config.tracing {
+            self.inner.restart_tracing(&new_config.tracing)?;
         }
 
         // Update the threads count if it's different
@@ -195,12 +445,27 @@ impl Fisher {
             self.inner.set_threads_count(new_config.jobs.threads)?;
         }
 
+        // Update the queue size if it's different
+        if self.config.jobs.max_queued != new_config.jobs.max_queued {
+            self.inner.set_max_queued(new_config.jobs.max_queued)?;
+        }
+
+        // Update the stuck jobs detection if it's different
+        if self.config.jobs.stuck_after != new_config.jobs.stuck_after
+            || self.config.jobs.fail_stuck != new_config.jobs.fail_stuck
+        {
+            self.inner.set_stuck_jobs(&new_config.jobs)?;
+        }
+
+        // Restart Vault if its configuration changed
+        if self.config.vault != new_config.vault {
+            self.inner.restart_vault(&new_config.vault)?;
+        }
+
         // Reload hooks, changing the script path
-        self.inner.set_scripts_path(
-            &new_config.scripts.path,
-            new_config.scripts.recursive,
-        )?;
+        self.inner.set_scripts_path(&new_config.scripts)?;
 
+        self.inner.set_config_hash(new_config.hash())?;
         self.config = new_config;
 
         Ok(())
@@ -0,0 +1,340 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Append-only audit log of the webhooks accepted by Fisher.
+//!
+//! Every accepted webhook is recorded with its headers, a hash of its body,
+//! the provider which validated it and the jobs it created, and the final
+//! outcome of each job is recorded when it ends: this way it's possible to
+//! reconstruct which request triggered every execution of a script. Who
+//! approved or rejected the jobs requiring an approval is recorded too. The
+//! records are appended to a file as JSON lines, and the file is rotated
+//! when it grows too big.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
+
+use common::prelude::*;
+use common::config::AuditConfig;
+use common::structs::JobStatus;
+use utils;
+use web::WebRequest;
+
+
+/// How many hex characters of the hash of the body are recorded.
+const HASH_LENGTH: usize = 32;
+
+/// The headers whose values are not recorded, since they contain secrets.
+static REDACTED_HEADERS: &[&str] = &[
+    "authorization", "cookie", "proxy-authorization",
+];
+
+
+lazy_static! {
+    static ref AUDIT_LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+}
+
+
+/// Start recording to the file in the configuration, or stop recording if
+/// the audit log is disabled.
+pub fn configure(config: &AuditConfig) -> Result<()> {
+    let log = if let Some(ref file) = config.file {
+        Some(
+            AuditLog::open(file.into(), config.max_size, config.keep)
+                .chain_err(|| ErrorKind::AuditLogFailed(file.clone()))?,
+        )
+    } else {
+        None
+    };
+
+    *AUDIT_LOG.lock()? = log;
+    Ok(())
+}
+
+
+fn enabled() -> bool {
+    AUDIT_LOG.lock().map(|log| log.is_some()).unwrap_or(false)
+}
+
+
+fn record(entry: &Value) {
+    if let Ok(mut log) = AUDIT_LOG.lock() {
+        if let Some(ref mut log) = *log {
+            let path = log.path.to_string_lossy().to_string();
+            if let Err(err) = log.write(entry)
+                .chain_err(|| ErrorKind::AuditLogFailed(path))
+            {
+                err.pretty_print();
+            }
+        }
+    }
+}
+
+
+/// Record a webhook accepted by a hook, with the jobs it created.
+pub fn webhook(
+    hook: &str, provider: Option<&str>, req: &WebRequest, outcome: &str,
+    job_ids: &[String],
+) {
+    if !enabled() {
+        return;
+    }
+
+    match webhook_entry(hook, provider, req, outcome, job_ids) {
+        Ok(entry) => record(&entry),
+        Err(err) => err.pretty_print(),
+    }
+}
+
+
+fn webhook_entry(
+    hook: &str, provider: Option<&str>, req: &WebRequest, outcome: &str,
+    job_ids: &[String],
+) -> Result<Value> {
+    let headers = req.headers
+        .iter()
+        .map(|(name, value)| {
+            let redacted = REDACTED_HEADERS.contains(
+                &name.to_lowercase().as_str(),
+            );
+            let value = if redacted { "[redacted]" } else { value.as_str() };
+            (name.clone(), value.to_string())
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // Big bodies are hashed without loading them in memory
+    let mut hasher = Sha256::new();
+    let size = if let Some(ref spooled) = req.spooled {
+        let mut file = spooled.open()?;
+        let mut buffer = [0; 8192];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.input(&buffer[..read]);
+        }
+        spooled.size()
+    } else {
+        hasher.input(&req.body);
+        req.body.len() as u64
+    };
+    let hash = utils::to_hex(&hasher.result());
+
+    Ok(json!({
+        "event": "webhook",
+        "timestamp": utils::timestamp_json(utils::unix_now()),
+        "hook": hook,
+        "provider": provider,
+        "client": req.source.to_string(),
+        "headers": headers,
+        "body_size": size,
+        "body_sha256": &hash[..HASH_LENGTH],
+        "outcome": outcome,
+        "job_ids": job_ids,
+    }))
+}
+
+
+/// Record the final outcome of a job.
+pub fn job_ended(job: &JobStatus) {
+    if !enabled() {
+        return;
+    }
+
+    record(&json!({
+        "event": "job_ended",
+        "timestamp": utils::timestamp_json(utils::unix_now()),
+        "job_id": job.job_id,
+        "hook": job.script,
+        "outcome": job.state,
+        "exit_code": job.exit_code,
+    }));
+}
+
+
+/// Record a job waiting for approval being approved or rejected, and who
+/// did it.
+pub fn approval(job_id: &str, hook: &str, approved: bool, by: &str) {
+    if !enabled() {
+        return;
+    }
+
+    record(&json!({
+        "event": if approved { "job_approved" } else { "job_rejected" },
+        "timestamp": utils::timestamp_json(utils::unix_now()),
+        "job_id": job_id,
+        "hook": hook,
+        "by": by,
+    }));
+}
+
+
+struct AuditLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl AuditLog {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(AuditLog {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write(&mut self, entry: &Value) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        // A single record is never split between two files
+        let len = line.len() as u64;
+        if self.max_size > 0 && self.size > 0
+            && self.size + len > self.max_size
+        {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Move the current file to `<path>.1`, shifting the older ones and
+    /// removing the oldest one, and start a new file.
+    fn rotate(&mut self) -> Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for number in (1..self.keep).rev() {
+                let old = rotated(&self.path, number);
+                if old.exists() {
+                    fs::rename(&old, rotated(&self.path, number + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        *self = AuditLog::open(self.path.clone(), self.max_size, self.keep)?;
+        Ok(())
+    }
+}
+
+
+fn rotated(path: &Path, number: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", number));
+    name.into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use tempdir::TempDir;
+
+    use utils::testing::*;
+    use web::SpooledBody;
+
+    use super::{rotated, webhook_entry, AuditLog};
+
+
+    #[test]
+    fn test_webhook_entry() {
+        let mut req = dummy_web_request();
+        req.headers.insert("X-GitHub-Event".into(), "push".into());
+        req.headers.insert("Authorization".into(), "Bearer secret".into());
+        req.body = b"hello".to_vec();
+
+        let ids = vec!["abcd".to_string()];
+        let entry = webhook_entry(
+            "deploy.sh", Some("GitHub"), &req, "queued", &ids,
+        ).unwrap();
+        assert_eq!(entry["event"], "webhook");
+        assert_eq!(entry["hook"], "deploy.sh");
+        assert_eq!(entry["provider"], "GitHub");
+        assert_eq!(entry["outcome"], "queued");
+        assert_eq!(entry["job_ids"], json!(["abcd"]));
+        assert_eq!(entry["headers"]["X-GitHub-Event"], "push");
+        assert_eq!(entry["headers"]["Authorization"], "[redacted]");
+        assert_eq!(entry["body_size"], 5);
+        assert_eq!(
+            entry["body_sha256"], "2cf24dba5fb0a30e26e83b2ac5b9e29e",
+        );
+
+        // Spooled bodies are hashed from their file
+        req.body = Vec::new();
+        req.spooled = Some(Arc::new(
+            SpooledBody::new(b"hel", &b"lo"[..]).unwrap(),
+        ));
+        let entry = webhook_entry(
+            "deploy.sh", None, &req, "queued", &ids,
+        ).unwrap();
+        assert_eq!(entry["provider"], json!(null));
+        assert_eq!(entry["body_size"], 5);
+        assert_eq!(
+            entry["body_sha256"], "2cf24dba5fb0a30e26e83b2ac5b9e29e",
+        );
+    }
+
+    #[test]
+    fn test_audit_log_rotation() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("audit.log");
+        let line = |n: usize| json!({"n": n});
+        let len = (line(0).to_string().len() + 1) as u64;
+
+        let mut log = AuditLog::open(path.clone(), len * 2, 2).unwrap();
+        for n in 0..7 {
+            log.write(&line(n)).unwrap();
+        }
+
+        let read = |number| {
+            let path = if number == 0 {
+                path.clone()
+            } else {
+                rotated(&path, number)
+            };
+            fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(read(0), "{\"n\":6}\n");
+        assert_eq!(read(1), "{\"n\":4}\n{\"n\":5}\n");
+        assert_eq!(read(2), "{\"n\":2}\n{\"n\":3}\n");
+        assert!(!rotated(&path, 3).exists());
+
+        // Records are appended to the existing file after a restart
+        let mut log = AuditLog::open(path.clone(), len * 2, 2).unwrap();
+        log.write(&line(7)).unwrap();
+        assert_eq!(read(0), "{\"n\":6}\n{\"n\":7}\n");
+    }
+}
@@ -15,81 +15,229 @@
 
 extern crate fisher;
 extern crate nix;
+#[macro_use]
+extern crate serde_json;
 extern crate toml;
 
 use std::fs;
 use std::io::Read;
 use std::path::Path;
+use std::process;
+use std::result::Result as StdResult;
 
 use fisher::*;
 use nix::sys::signal::{Signal, SigSet};
+use serde_json::Value;
 
 
 static VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
+/// Everything went fine.
+const EXIT_OK: i32 = 0;
+/// Fisher failed while starting or running.
+const EXIT_ERROR: i32 = 1;
+/// The command line arguments are invalid.
+const EXIT_USAGE: i32 = 2;
+/// The configuration file can't be read or parsed.
+const EXIT_CONFIG: i32 = 3;
 
-fn show_version() {
-    if let Some(version) = VERSION {
-        println!("Fisher {}", version);
-    } else {
-        println!("Fisher (version unknown)");
+static SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+static BASH_COMPLETIONS: &str = r#"_fisher() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    if [[ "${prev}" == "completions" ]]; then
+        COMPREPLY=($(compgen -W "bash zsh fish" -- "${cur}"))
+    elif [[ "${cur}" == -* ]]; then
+        COMPREPLY=($(compgen -W "-h --help --version --json" -- "${cur}"))
+    else
+        COMPREPLY=($(compgen -W "completions" -- "${cur}") \
+                   $(compgen -f -- "${cur}"))
+    fi
+}
+complete -F _fisher fisher
+"#;
+
+static ZSH_COMPLETIONS: &str = r#"#compdef fisher
+
+_fisher_first() {
+    _alternative \
+        'commands:command:(completions)' \
+        'files:config file:_files'
+}
+
+_arguments \
+    '(-h --help)'{-h,--help}'[Show the help message]' \
+    '--version[Show the Fisher version]' \
+    '--json[Show the output as JSON]' \
+    '1:config file or command:_fisher_first' \
+    '2:shell:(bash zsh fish)'
+"#;
+
+static FISH_COMPLETIONS: &str = r#"
+complete -c fisher -s h -l help -d 'Show the help message'
+complete -c fisher -l version -d 'Show the Fisher version'
+complete -c fisher -l json -d 'Show the output as JSON'
+complete -c fisher -n '__fish_use_subcommand' \
+    -a completions -d 'Generate the shell completions'
+complete -c fisher -n '__fish_seen_subcommand_from completions' \
+    -f -a 'bash zsh fish'
+"#;
+
+
+#[derive(Debug)]
+enum Command {
+    Run(String),
+    Completions(String),
+    Help,
+    Version,
+}
+
+
+#[derive(Debug)]
+struct Cli {
+    command: Command,
+    json: bool,
+}
+
+impl Cli {
+    /// Show a message, or its JSON representation if requested.
+    fn output(&self, text: &str, data: Value) {
+        if self.json {
+            println!("{}", data);
+        } else {
+            println!("{}", text);
+        }
     }
+
+    /// Show an error and exit with the provided code.
+    fn fail(&self, exit_code: i32, err: &Error) -> ! {
+        if self.json {
+            let causes = err.iter()
+                .skip(1)
+                .map(|cause| cause.to_string())
+                .collect::<Vec<_>>();
+            println!("{}", json!({
+                "error": err.to_string(),
+                "causes": causes,
+                "exit_code": exit_code,
+            }));
+        } else {
+            err.pretty_print();
+        }
+        process::exit(exit_code);
+    }
+}
+
+
+fn version() -> &'static str {
+    VERSION.unwrap_or("unknown")
 }
 
 
-fn usage(exit_code: i32, error_msg: &str) -> ! {
-    if error_msg.len() > 0 {
-        println!("Error: {}\n", error_msg);
+fn show_help() {
+    println!("Fisher {}", version());
+    println!("Simple webhooks catcher\n");
+
+    println!("USAGE");
+    println!("  fisher [OPTIONS] <config_path>");
+    println!("  fisher [OPTIONS] completions <shell>");
+    println!();
+
+    println!("COMMANDS");
+    println!("  completions   Generate the completions for bash, zsh or fish");
+    println!();
+
+    println!("ARGUMENTS");
+    println!("  config_path   The path to the configuration file");
+    println!();
+
+    println!("OPTIONS");
+    println!("  -h | --help   Show this message");
+    println!("  --version     Show the Fisher version");
+    println!("  --json        Show the output as JSON");
+    println!();
+
+    println!("EXIT CODES");
+    println!("  {}   Success", EXIT_OK);
+    println!("  {}   Fisher failed while starting or running", EXIT_ERROR);
+    println!("  {}   Invalid command line arguments", EXIT_USAGE);
+    println!("  {}   Invalid configuration file", EXIT_CONFIG);
+}
+
+
+fn usage_error(json: bool, message: &str) -> ! {
+    if json {
+        println!("{}", json!({
+            "error": message,
+            "causes": [],
+            "exit_code": EXIT_USAGE,
+        }));
+    } else {
+        println!("Error: {}\n", message);
+        println!("Usage: fisher <config_file>");
+        println!("Execute `fisher --help` for more details");
     }
-    println!("Usage: fisher <config_file>");
-    println!("Execute `fisher --help` for more details");
-    ::std::process::exit(exit_code);
+    process::exit(EXIT_USAGE);
 }
 
 
-fn parse_cli() -> String {
-    // Parse the CLI args
+fn parse_cli<I: Iterator<Item = String>>(args: I) -> StdResult<Cli, String> {
     let mut only_args = false;
+    let mut json = false;
     let mut flag_help = false;
     let mut flag_version = false;
-    let mut config_path = None;
+    let mut commands_allowed = true;
+    let mut positional = Vec::new();
 
-    for arg in ::std::env::args().skip(1) {
-        if !only_args && arg.chars().next() == Some('-') {
+    for arg in args {
+        if !only_args && arg.starts_with('-') {
             match arg.as_str() {
                 "--" => only_args = true,
                 "-h" | "--help" => flag_help = true,
                 "--version" => flag_version = true,
-                _ => usage(1, &format!("invalid flag: {}", arg)),
+                "--json" => json = true,
+                _ => return Err(format!("invalid flag: {}", arg)),
             }
-        } else if config_path.is_none() {
-            config_path = Some(arg);
         } else {
-            usage(1, &format!("unexpected argument: {}", arg));
+            // Commands can't be used after `--`, so configuration files named
+            // like them can still be loaded
+            if positional.is_empty() && only_args {
+                commands_allowed = false;
+            }
+            positional.push(arg);
         }
     }
 
-    if flag_help {
-        show_version();
-        println!("Simple webhooks catcher\n");
-
-        println!("ARGUMENTS");
-        println!("  config_path   The path to the configuration file");
-        println!();
-
-        println!("OPTIONS");
-        println!("  -h | --help   Show this message");
-        println!("  --version     Show the Fisher version");
-
-        ::std::process::exit(0);
+    let command = if flag_help {
+        Command::Help
     } else if flag_version {
-        show_version();
-        ::std::process::exit(0);
-    } else if let Some(path) = config_path {
-        path
+        Command::Version
+    } else if commands_allowed
+        && positional.first().map(|arg| arg.as_str()) == Some("completions")
+    {
+        match positional.len() {
+            1 => return Err("missing the shell to generate".into()),
+            2 if SHELLS.contains(&positional[1].as_str()) => {
+                Command::Completions(positional.remove(1))
+            }
+            2 => return Err(format!("unsupported shell: {}", positional[1])),
+            _ => {
+                return Err(format!("unexpected argument: {}", positional[2]));
+            }
+        }
     } else {
-        usage(1, "too few arguments");
-    }
+        match positional.len() {
+            0 => return Err("too few arguments".into()),
+            1 => Command::Run(positional.remove(0)),
+            _ => {
+                return Err(format!("unexpected argument: {}", positional[1]));
+            }
+        }
+    };
+
+    Ok(Cli { command, json })
 }
 
 
@@ -105,33 +253,48 @@ fn read_config<P: AsRef<Path>>(path: P) -> Result<Config> {
 }
 
 
-fn app() -> Result<()> {
+fn run(cli: &Cli, config_path: &str) -> Result<()> {
     // Capture only the signals Fisher uses
     let mut signals = SigSet::empty();
     signals.add(Signal::SIGINT);
     signals.add(Signal::SIGTERM);
     signals.add(Signal::SIGUSR1);
+    signals.add(Signal::SIGHUP);
     signals.thread_block()?;
 
-    let config_path = parse_cli();
+    let config = match read_config(config_path) {
+        Ok(config) => config,
+        Err(err) => cli.fail(EXIT_CONFIG, &err),
+    };
 
-    let mut app = Fisher::new(read_config(&config_path)?)?;
-    println!("HTTP server listening on {}", app.web_address().unwrap());
+    let mut app = Fisher::new(config)?;
+    let addr = app.web_address().unwrap();
+    cli.output(
+        &format!("HTTP server listening on {}", addr),
+        json!({"event": "listening", "address": addr.to_string()}),
+    );
 
     // Wait for signals while the other threads execute the application
     loop {
         match signals.wait()? {
             Signal::SIGINT | Signal::SIGTERM => break,
-            Signal::SIGUSR1 => {
-                println!("Reloading configuration and scripts...");
+            Signal::SIGUSR1 | Signal::SIGHUP => {
+                cli.output(
+                    "Reloading configuration and scripts...",
+                    json!({"event": "reloading"}),
+                );
 
                 // Don't crash if the reload fails, just show errors
                 // No changes are applied if the reload fails
-                match read_config(&config_path) {
-                    Ok(new_config) => {
-                        if let Err(err) = app.reload(new_config) {
-                            err.pretty_print()
-                        }
+                let result = read_config(config_path)
+                    .and_then(|new_config| app.reload(new_config));
+                match result {
+                    Ok(()) => {}
+                    Err(ref err) if cli.json => {
+                        println!("{}", json!({
+                            "event": "reload-failed",
+                            "error": err.to_string(),
+                        }));
                     }
                     Err(err) => err.pretty_print(),
                 }
@@ -148,8 +311,34 @@ fn app() -> Result<()> {
 
 
 fn main() {
-    if let Err(err) = app() {
-        err.pretty_print();
-        std::process::exit(1);
+    let cli = match parse_cli(::std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            let json = ::std::env::args().any(|arg| arg == "--json");
+            usage_error(json, &message);
+        }
+    };
+
+    match cli.command {
+        Command::Help => show_help(),
+        Command::Version => cli.output(
+            &format!("Fisher {}", version()),
+            json!({"name": "fisher", "version": version()}),
+        ),
+        Command::Completions(ref shell) => {
+            let script = match shell.as_str() {
+                "bash" => BASH_COMPLETIONS,
+                "zsh" => ZSH_COMPLETIONS,
+                _ => FISH_COMPLETIONS,
+            }.trim();
+            cli.output(script, json!({"shell": shell, "script": script}));
+        }
+        Command::Run(ref path) => {
+            if let Err(err) = run(&cli, path) {
+                cli.fail(EXIT_ERROR, &err);
+            }
+        }
     }
+
+    process::exit(EXIT_OK);
 }
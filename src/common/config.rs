@@ -16,13 +16,14 @@
 //! This module contains the deserializable configuration structs used by
 //! Fisher.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 use std::net::SocketAddr;
 use std::fmt;
 use std::result::Result as StdResult;
 
 use serde::de::{Error as DeError, Visitor, Deserialize, Deserializer};
+use sha1::{Digest, Sha1};
 
 use common::prelude::*;
 use utils;
@@ -52,6 +53,9 @@ macro_rules! default_fn {
 /// The Fisher configuration.
 #[derive(Debug, Default, PartialEq, Eq, Deserialize)]
 pub struct Config {
+    /// How the timestamps are shown by Fisher.
+    #[serde(rename="timestamp-format", default)]
+    pub timestamp_format: utils::TimestampFormat,
     /// Configuration for the built-in HTTP webhooks receiver.
     #[serde(default)]
     pub http: HttpConfig,
@@ -61,11 +65,43 @@ pub struct Config {
     /// Configuration for running jobs.
     #[serde(default)]
     pub jobs: JobsConfig,
+    /// Configuration for the heartbeat pings.
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    /// Configuration for exporting the traces.
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// Configuration for the audit log.
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Configuration for the Vault secrets backend.
+    #[serde(default)]
+    pub vault: VaultConfig,
+    /// Configuration for the automatic TLS certificates.
+    #[serde(default)]
+    pub acme: AcmeConfig,
     /// Extra environment variables.
     #[serde(default)]
     pub env: HashMap<String, String>,
 }
 
+impl Config {
+    /// Calculate an hash of the configuration, which is the same every time
+    /// the same configuration is loaded.
+    pub fn hash(&self) -> String {
+        // The environment is sorted to avoid depending on the HashMap order
+        let env = self.env.iter().collect::<BTreeMap<_, _>>();
+        let repr = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            self.timestamp_format, self.http, self.scripts, self.jobs,
+            self.heartbeat, self.tracing, self.audit, self.vault, self.acme,
+            env,
+        );
+
+        utils::to_hex(&Sha1::digest(repr.as_bytes()))
+    }
+}
+
 
 /// Configuration for the built-in HTTP webhooks receiver.
 #[derive(Debug, PartialEq, Eq, Deserialize)]
@@ -73,29 +109,305 @@ pub struct HttpConfig {
     /// The number of proxies Fisher is behind.
     #[serde(rename="behind-proxies", default="default_behind_proxies")]
     pub behind_proxies: u8,
+    /// The addresses of the proxies allowed to set X-Forwarded-For.
+    #[serde(rename="trusted-proxies", default)]
+    pub trusted_proxies: Vec<utils::IpCidr>,
+    /// The only addresses allowed to call the hooks, if any.
+    #[serde(rename="allow-ips", default)]
+    pub allow_ips: Vec<utils::IpCidr>,
+    /// The addresses not allowed to call the hooks.
+    #[serde(rename="deny-ips", default)]
+    pub deny_ips: Vec<utils::IpCidr>,
+    /// The maximum size of the body of every request, in bytes.
+    #[serde(rename="max-body-size", default="default_max_body_size")]
+    pub max_body_size: u64,
+    /// The maximum size of the headers of every request, in bytes.
+    #[serde(rename="max-header-size", default="default_max_header_size")]
+    pub max_header_size: usize,
+    /// How long receiving a request can take.
+    #[serde(rename="read-timeout", default="default_read_timeout")]
+    pub read_timeout: utils::TimeString,
+    /// How long sending a response can go without any progress.
+    #[serde(rename="write-timeout", default="default_write_timeout")]
+    pub write_timeout: utils::TimeString,
+    /// How long a connection can stay idle between two requests.
+    #[serde(rename="keep-alive-timeout", default="default_keep_alive_timeout")]
+    pub keep_alive_timeout: utils::TimeString,
+    /// The maximum number of connections open at the same time.
+    #[serde(rename="max-connections", default="default_max_connections")]
+    pub max_connections: usize,
+    /// The number of threads handling the requests.
+    #[serde(default="default_workers")]
+    pub workers: usize,
+    /// How long the requests waiting for their job to end are held
+    #[serde(rename="max-wait", default="default_max_wait")]
+    pub max_wait: utils::TimeString,
     /// The socket address to bind.
     #[serde(default="default_bind")]
     pub bind: SocketAddr,
     /// The rate limit for bad requests
     #[serde(rename="rate-limit", default)]
     pub rate_limit: RateLimitConfig,
+    /// The rate limit for all the requests to the hooks of every client
+    #[serde(rename="ip-rate-limit", default)]
+    pub ip_rate_limit: Option<RateLimitConfig>,
+    /// How many requests every client can send at once
+    #[serde(rename="ip-rate-burst", default)]
+    pub ip_rate_burst: Option<u64>,
     /// Enable or disable the health endpoint
     #[serde(rename="health-endpoint", default="default_health_endpoint")]
     pub health_endpoint: bool,
+    /// Enable or disable the Prometheus metrics endpoint
+    #[serde(rename="metrics-endpoint", default)]
+    pub metrics_endpoint: bool,
+    /// Avoid processing the same delivery twice
+    #[serde(rename="dedup-deliveries", default)]
+    pub dedup_deliveries: bool,
+    /// The number of deliveries to remember
+    #[serde(rename="dedup-capacity", default="default_dedup_capacity")]
+    pub dedup_capacity: usize,
+    /// The file where the seen deliveries are persisted
+    #[serde(rename="dedup-file", default)]
+    pub dedup_file: Option<String>,
+    /// The number of payloads to keep for every hook, to replay them
+    #[serde(rename="keep-payloads", default)]
+    pub keep_payloads: usize,
+    /// The file where the kept payloads are persisted
+    #[serde(rename="payloads-file", default)]
+    pub payloads_file: Option<String>,
+    /// The token required to access the admin endpoints
+    #[serde(rename="admin-token", default)]
+    pub admin_token: Option<String>,
+    /// The bearer tokens required to call the hooks, if any
+    #[serde(rename="hook-tokens", default)]
+    pub hook_tokens: Option<utils::Secrets>,
+    /// The PEM file with the TLS certificate chain
+    #[serde(rename="tls-cert", default)]
+    pub tls_cert: Option<String>,
+    /// The PEM file with the TLS private key
+    #[serde(rename="tls-key", default)]
+    pub tls_key: Option<String>,
+    /// Start without executing scripts until promoted
+    #[serde(default)]
+    pub standby: bool,
+    /// Disable the admin endpoints changing the state of the instance
+    #[serde(rename="read-only", default)]
+    pub read_only: bool,
+    /// The standby instance the deliveries are mirrored to
+    #[serde(rename="mirror-to", default)]
+    pub mirror_to: Option<String>,
+    /// The admin token of the standby instance
+    #[serde(rename="mirror-token", default)]
+    pub mirror_token: Option<String>,
+    /// The URL this instance is reachable at, used to build links
+    #[serde(rename="public-url", default)]
+    pub public_url: Option<String>,
+    /// How long the links to approve jobs are valid
+    #[serde(
+        rename="approval-links-ttl", default="default_approval_links_ttl"
+    )]
+    pub approval_links_ttl: utils::TimeString,
+    /// The directory the artifacts uploaded by the scripts are stored in
+    #[serde(rename="artifacts-dir", default)]
+    pub artifacts_dir: Option<String>,
+    /// The periods when events of noisy providers are ignored
+    #[serde(default)]
+    pub blackouts: Vec<BlackoutConfig>,
+    /// Custom paths mapped to the hooks reachable at them
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// The key the secret paths of the hooks are derived from
+    #[serde(rename="path-secret", default)]
+    pub path_secret: Option<String>,
+    /// The origins allowed to call the API from the browser
+    #[serde(rename="cors-origins", default)]
+    pub cors_origins: Vec<String>,
+    /// The methods cross-origin requests can use
+    #[serde(rename="cors-methods", default="default_cors_methods")]
+    pub cors_methods: Vec<String>,
+    /// The headers cross-origin requests can include
+    #[serde(rename="cors-headers", default="default_cors_headers")]
+    pub cors_headers: Vec<String>,
+    /// How long browsers can cache the preflight responses
+    #[serde(rename="cors-max-age", default="default_cors_max_age")]
+    pub cors_max_age: utils::TimeString,
+    /// The file the access log is written to, or `-` for the standard output
+    #[serde(rename="access-log", default)]
+    pub access_log: Option<String>,
+    /// The format of the lines of the access log
+    #[serde(rename="access-log-format", default)]
+    pub access_log_format: AccessLogFormat,
+    /// Where the accepted payloads are archived
+    #[serde(default)]
+    pub archive: ArchiveConfig,
 }
 
 default_fn!(default_behind_proxies: u8 = 0);
+default_fn!(default_max_body_size: u64 = 10 * 1024 * 1024);
+default_fn!(default_max_header_size: usize = 8 * 1024);
+default_fn!(default_read_timeout: utils::TimeString = 30.into());
+default_fn!(default_write_timeout: utils::TimeString = 30.into());
+default_fn!(default_keep_alive_timeout: utils::TimeString = 60.into());
+default_fn!(default_max_connections: usize = 1024);
+default_fn!(default_workers: usize = 1);
+default_fn!(default_max_wait: utils::TimeString = 300.into());
 default_fn!(default_bind: SocketAddr = "127.0.0.1:8000".parse().unwrap());
 default_fn!(default_health_endpoint: bool = true);
+default_fn!(default_dedup_capacity: usize = 1000);
+default_fn!(default_approval_links_ttl: utils::TimeString = 3600.into());
+default_fn!(default_cors_methods: Vec<String> = vec![
+    "GET".into(), "POST".into(), "PUT".into(), "DELETE".into(),
+]);
+default_fn!(default_cors_headers: Vec<String> = vec![
+    "Authorization".into(), "Content-Type".into(),
+]);
+default_fn!(default_cors_max_age: utils::TimeString = 600.into());
 
 default!(HttpConfig {
     behind_proxies: default_behind_proxies(),
+    trusted_proxies: Vec::new(),
+    allow_ips: Vec::new(),
+    deny_ips: Vec::new(),
+    max_body_size: default_max_body_size(),
+    max_header_size: default_max_header_size(),
+    read_timeout: default_read_timeout(),
+    write_timeout: default_write_timeout(),
+    keep_alive_timeout: default_keep_alive_timeout(),
+    max_connections: default_max_connections(),
+    workers: default_workers(),
+    max_wait: default_max_wait(),
     bind: default_bind(),
     rate_limit: RateLimitConfig::default(),
+    ip_rate_limit: None,
+    ip_rate_burst: None,
     health_endpoint: default_health_endpoint(),
+    metrics_endpoint: false,
+    dedup_deliveries: false,
+    dedup_capacity: default_dedup_capacity(),
+    dedup_file: None,
+    keep_payloads: 0,
+    payloads_file: None,
+    admin_token: None,
+    hook_tokens: None,
+    tls_cert: None,
+    tls_key: None,
+    standby: false,
+    read_only: false,
+    mirror_to: None,
+    mirror_token: None,
+    public_url: None,
+    approval_links_ttl: default_approval_links_ttl(),
+    artifacts_dir: None,
+    blackouts: Vec::new(),
+    aliases: HashMap::new(),
+    path_secret: None,
+    cors_origins: Vec::new(),
+    cors_methods: default_cors_methods(),
+    cors_headers: default_cors_headers(),
+    cors_max_age: default_cors_max_age(),
+    access_log: None,
+    access_log_format: AccessLogFormat::Json,
+    archive: ArchiveConfig::default(),
+});
+
+
+/// The format of the lines of the access log.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessLogFormat {
+    /// A JSON object on every line.
+    Json,
+    /// The Common Log Format, followed by the latency and the details about
+    /// the hook.
+    Common,
+}
+
+impl Default for AccessLogFormat {
+    fn default() -> Self {
+        AccessLogFormat::Json
+    }
+}
+
+
+/// Configuration for archiving the accepted payloads.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct ArchiveConfig {
+    /// The local directory the payloads are archived to.
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// The S3 bucket the payloads are archived to.
+    #[serde(rename="s3-bucket", default)]
+    pub s3_bucket: Option<String>,
+    /// The URL of the S3-compatible service.
+    #[serde(rename="s3-endpoint", default="default_s3_endpoint")]
+    pub s3_endpoint: String,
+    #[serde(rename="s3-region", default="default_s3_region")]
+    pub s3_region: String,
+    #[serde(rename="s3-access-key", default)]
+    pub s3_access_key: Option<String>,
+    #[serde(rename="s3-secret-key", default)]
+    pub s3_secret_key: Option<String>,
+    /// The prefix of the keys of the payloads in the bucket.
+    #[serde(rename="s3-prefix", default)]
+    pub s3_prefix: String,
+    /// How long the payloads are kept, if not forever.
+    #[serde(default)]
+    pub retention: Option<utils::TimeString>,
+}
+
+default_fn!(default_s3_endpoint: String = "https://s3.amazonaws.com".into());
+default_fn!(default_s3_region: String = "us-east-1".into());
+
+default!(ArchiveConfig {
+    directory: None,
+    s3_bucket: None,
+    s3_endpoint: default_s3_endpoint(),
+    s3_region: default_s3_region(),
+    s3_access_key: None,
+    s3_secret_key: None,
+    s3_prefix: String::new(),
+    retention: None,
 });
 
 
+/// A period when some events are ignored without running any script.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BlackoutConfig {
+    /// The name of the provider whose events are muted, if only one.
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// The names of the muted events, if only some of them.
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+    /// The time of the day the blackout is active, if not always.
+    #[serde(default)]
+    pub hours: Option<utils::ClockWindow>,
+}
+
+impl BlackoutConfig {
+    /// Check if an event received by the provider at the UNIX timestamp
+    /// `now` should be muted.
+    pub fn mutes(
+        &self, provider: Option<&str>, event: Option<&str>, now: u64,
+    ) -> bool {
+        if let Some(ref muted) = self.provider {
+            if Some(muted.as_str()) != provider {
+                return false;
+            }
+        }
+
+        if let Some(ref events) = self.events {
+            match event {
+                Some(event) if events.iter().any(|e| e == event) => {}
+                _ => return false,
+            }
+        }
+
+        self.hours.map(|hours| hours.contains(now)).unwrap_or(true)
+    }
+}
+
+
 /// Configuration for rate limiting.
 #[derive(Debug, PartialEq, Eq)]
 pub struct RateLimitConfig {
@@ -124,9 +436,16 @@ impl RateLimitConfig {
             }),
             1 => {
                 let (requests, interval) = s.split_at(slash_pos[0]);
+                let interval = match &interval[1..] {
+                    "second" => 1.into(),
+                    "minute" => 60.into(),
+                    "hour" => 3600.into(),
+                    "day" => 86400.into(),
+                    other => other.parse()?,
+                };
                 Ok(RateLimitConfig {
                     allowed: requests.parse()?,
-                    interval: (&interval[1..]).parse()?,
+                    interval,
                 })
             },
             _ => Err(ErrorKind::RateLimitConfigTooManySlashes.into()),
@@ -182,12 +501,40 @@ pub struct JobsConfig {
     /// The number of execution threads to use.
     #[serde(default = "default_threads")]
     pub threads: u16,
+    /// The maximum number of jobs waiting in the queue, if any.
+    #[serde(rename = "max-queued", default)]
+    pub max_queued: Option<usize>,
+    /// Where the temporary directories of the jobs are created.
+    #[serde(rename = "temp-dir", default)]
+    pub temp_dir: Option<String>,
+    /// Refuse to create the temporary directories outside of tmpfs.
+    #[serde(rename = "require-tmpfs", default)]
+    pub require_tmpfs: bool,
+    /// The shell command executed before every job.
+    #[serde(rename = "before-job", default)]
+    pub before_job: Option<String>,
+    /// The shell command executed after every job.
+    #[serde(rename = "after-job", default)]
+    pub after_job: Option<String>,
+    /// How long jobs can wait for other jobs before being considered stuck.
+    #[serde(rename = "stuck-after", default)]
+    pub stuck_after: Option<utils::TimeString>,
+    /// Fail the stuck jobs instead of only reporting them.
+    #[serde(rename = "fail-stuck", default)]
+    pub fail_stuck: bool,
 }
 
 default_fn!(default_threads: u16 = 1);
 
 default!(JobsConfig {
     threads: default_threads(),
+    max_queued: None,
+    temp_dir: None,
+    require_tmpfs: false,
+    before_job: None,
+    after_job: None,
+    stuck_after: None,
+    fail_stuck: false,
 });
 
 
@@ -200,6 +547,9 @@ pub struct ScriptsConfig {
     /// Search subdirectories or not.
     #[serde(default = "default_recursive")]
     pub recursive: bool,
+    /// Reload the scripts automatically when they change.
+    #[serde(default)]
+    pub watch: bool,
 }
 
 default_fn!(default_path: String = ".".into());
@@ -208,4 +558,232 @@ default_fn!(default_recursive: bool = false);
 default!(ScriptsConfig {
     path: default_path(),
     recursive: default_recursive(),
+    watch: false,
 });
+
+
+/// The HTTP method used to send heartbeat pings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HeartbeatMethod {
+    /// Send the stats in the query string of a GET request.
+    Get,
+    /// Send the stats as the JSON body of a POST request.
+    Post,
+}
+
+
+/// Configuration for the heartbeat pings.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct HeartbeatConfig {
+    /// The URL to ping, if the heartbeat is enabled.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The interval between two pings.
+    #[serde(default = "default_heartbeat_interval")]
+    pub interval: utils::TimeString,
+    /// The HTTP method to use.
+    #[serde(default = "default_heartbeat_method")]
+    pub method: HeartbeatMethod,
+}
+
+default_fn!(default_heartbeat_interval: utils::TimeString = 60.into());
+default_fn!(default_heartbeat_method: HeartbeatMethod = HeartbeatMethod::Get);
+
+default!(HeartbeatConfig {
+    url: None,
+    interval: default_heartbeat_interval(),
+    method: default_heartbeat_method(),
+});
+
+
+/// Configuration for exporting the traces.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct TracingConfig {
+    /// The OTLP/HTTP endpoint the traces are sent to, if tracing is enabled.
+    #[serde(rename="otlp-endpoint", default)]
+    pub otlp_endpoint: Option<String>,
+    /// The name of the service reported in the traces.
+    #[serde(rename="service-name", default="default_service_name")]
+    pub service_name: String,
+}
+
+default_fn!(default_service_name: String = "fisher".into());
+
+default!(TracingConfig {
+    otlp_endpoint: None,
+    service_name: default_service_name(),
+});
+
+
+/// Configuration for the audit log.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct AuditConfig {
+    /// The file the audit log is appended to, if it's enabled.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// The size in bytes after which the file is rotated.
+    #[serde(rename="max-size", default="default_audit_max_size")]
+    pub max_size: u64,
+    /// How many rotated files are kept.
+    #[serde(default="default_audit_keep")]
+    pub keep: usize,
+}
+
+default_fn!(default_audit_max_size: u64 = 10 * 1024 * 1024);
+default_fn!(default_audit_keep: usize = 5);
+
+default!(AuditConfig {
+    file: None,
+    max_size: default_audit_max_size(),
+    keep: default_audit_keep(),
+});
+
+
+/// Configuration for the Vault secrets backend.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct VaultConfig {
+    /// The address of the Vault server, if Vault is enabled.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// The token used to authenticate.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// The AppRole role ID used to authenticate.
+    #[serde(rename = "role-id", default)]
+    pub role_id: Option<String>,
+    /// The AppRole secret ID used to authenticate.
+    #[serde(rename = "secret-id", default)]
+    pub secret_id: Option<String>,
+    /// The version of the KV secrets engine.
+    #[serde(rename = "kv-version", default = "default_vault_kv_version")]
+    pub kv_version: u8,
+    /// How long secrets without a lease are cached.
+    #[serde(rename = "cache-ttl", default = "default_vault_cache_ttl")]
+    pub cache_ttl: utils::TimeString,
+}
+
+default_fn!(default_vault_kv_version: u8 = 2);
+default_fn!(default_vault_cache_ttl: utils::TimeString = 300.into());
+
+default!(VaultConfig {
+    address: None,
+    token: None,
+    role_id: None,
+    secret_id: None,
+    kv_version: default_vault_kv_version(),
+    cache_ttl: default_vault_cache_ttl(),
+});
+
+
+/// Configuration for the TLS certificates obtained with ACME.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct AcmeConfig {
+    /// The domain to obtain the certificate for, if ACME is enabled.
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// The email address of the ACME account.
+    #[serde(default)]
+    pub email: Option<String>,
+    /// The URL of the ACME directory.
+    #[serde(default = "default_acme_directory")]
+    pub directory: String,
+    /// The directory where the account key and the certificate are stored.
+    #[serde(rename = "state-dir", default = "default_acme_state_dir")]
+    pub state_dir: String,
+    /// The socket address answering the HTTP-01 challenges.
+    #[serde(rename = "challenge-bind", default = "default_acme_challenge_bind")]
+    pub challenge_bind: SocketAddr,
+    /// How old the certificate must be before it's renewed.
+    #[serde(rename = "renew-after", default = "default_acme_renew_after")]
+    pub renew_after: utils::TimeString,
+}
+
+default_fn!(default_acme_directory: String =
+    "https://acme-v02.api.letsencrypt.org/directory".into());
+default_fn!(default_acme_state_dir: String = "/var/lib/fisher/acme".into());
+default_fn!(default_acme_challenge_bind: SocketAddr =
+    "0.0.0.0:80".parse().unwrap());
+default_fn!(default_acme_renew_after: utils::TimeString =
+    (60 * 24 * 60 * 60).into());
+
+default!(AcmeConfig {
+    domain: None,
+    email: None,
+    directory: default_acme_directory(),
+    state_dir: default_acme_state_dir(),
+    challenge_bind: default_acme_challenge_bind(),
+    renew_after: default_acme_renew_after(),
+});
+
+
+#[cfg(test)]
+mod tests {
+    use super::{BlackoutConfig, Config, RateLimitConfig};
+
+
+    #[test]
+    fn test_config_hash() {
+        let mut first = Config::default();
+        first.env.insert("A".into(), "a".into());
+        first.env.insert("B".into(), "b".into());
+
+        let mut second = Config::default();
+        second.env.insert("B".into(), "b".into());
+        second.env.insert("A".into(), "a".into());
+
+        // The hash must be stable
+        assert_eq!(first.hash(), second.hash());
+        assert_eq!(first.hash().len(), 40);
+
+        // The hash must change when the configuration changes
+        second.jobs.threads = 42;
+        assert!(first.hash() != second.hash());
+    }
+
+
+    #[test]
+    fn test_blackout_mutes() {
+        // 1500000000 is 02:40 UTC
+        let now = 1_500_000_000;
+
+        let blackout = BlackoutConfig {
+            provider: Some("GitHub".into()),
+            events: Some(vec!["watch".into(), "fork".into()]),
+            hours: None,
+        };
+        assert!(blackout.mutes(Some("GitHub"), Some("watch"), now));
+        assert!(!blackout.mutes(Some("GitHub"), Some("push"), now));
+        assert!(!blackout.mutes(Some("GitHub"), None, now));
+        assert!(!blackout.mutes(Some("GitLab"), Some("watch"), now));
+
+        let blackout = BlackoutConfig {
+            provider: None,
+            events: Some(vec!["up".into()]),
+            hours: Some("22:00-06:00".parse().unwrap()),
+        };
+        assert!(blackout.mutes(Some("Uptime"), Some("up"), now));
+        assert!(!blackout.mutes(Some("Uptime"), Some("down"), now));
+        assert!(!blackout.mutes(Some("Uptime"), Some("up"), now + 8 * 3600));
+    }
+
+
+    #[test]
+    fn test_rate_limit_config() {
+        let parse = |s: &str| s.parse::<RateLimitConfig>().ok().map(|c| {
+            (c.allowed, c.interval.as_u64())
+        });
+
+        assert_eq!(parse("10"), Some((10, 60)));
+        assert_eq!(parse("10/1h"), Some((10, 3600)));
+        assert_eq!(parse("10/30"), Some((10, 30)));
+        assert_eq!(parse("10/second"), Some((10, 1)));
+        assert_eq!(parse("10/minute"), Some((10, 60)));
+        assert_eq!(parse("10/hour"), Some((10, 3600)));
+        assert_eq!(parse("10/day"), Some((10, 86400)));
+
+        assert_eq!(parse("10/week"), None);
+        assert_eq!(parse("10/1m/1h"), None);
+        assert_eq!(parse("a/1m"), None);
+    }
+}
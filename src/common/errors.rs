@@ -58,6 +58,8 @@ error_chain! {
         AddrParse(::std::net::AddrParseError);
         Json(::serde_json::Error);
         Nix(::nix::Error);
+        Reqwest(::reqwest::Error);
+        UrlParse(::url::ParseError);
     }
 
     errors {
@@ -71,6 +73,22 @@ error_chain! {
             display("odd length for hex string"),
         }
 
+        // Network errors
+        IpCidrInvalid(input: String) {
+            description("invalid IP address range"),
+            display("invalid IP address range: {}", input),
+        }
+
+        // Public keys errors
+        PublicKeyInvalid {
+            description("invalid public key"),
+            display("invalid public key"),
+        }
+        PublicKeyUnsupported {
+            description("unsupported public key type"),
+            display("unsupported public key type"),
+        }
+
         // Time strings
         TimeStringInvalid(string: String) {
             description("invalid time string"),
@@ -85,6 +103,28 @@ error_chain! {
             display("expected a number in position {}", pos),
         }
 
+        // Temporary directories
+        TempDirNotFound(path: String) {
+            description("temporary directory not found"),
+            display("the temporary directory doesn't exist: {}", path),
+        }
+        TempDirNotTmpfs(path: String) {
+            description("temporary directory not on tmpfs"),
+            display("the temporary directory is not on tmpfs: {}", path),
+        }
+
+        // Commands executed around the jobs
+        BeforeJobFailed(hook: String) {
+            description("the command before the job failed"),
+            display("the command before the job of {} failed", hook),
+        }
+
+        // Clock windows
+        ClockWindowInvalid(string: String) {
+            description("invalid clock window"),
+            display("invalid clock window (expected HH:MM-HH:MM): {}", string),
+        }
+
         // Requests errors
         NotBehindProxy {
             description("not behind enough proxies"),
@@ -101,6 +141,44 @@ error_chain! {
             display("too many slashes present"),
         }
 
+        // Web requests errors
+        RequestBodyTooLarge(max: u64) {
+            description("request body too large"),
+            display("the request body is larger than {} bytes", max),
+        }
+        RequestContentTypeNotAllowed(content_type: String) {
+            description("content type not allowed"),
+            display("content type not allowed: {}", content_type),
+        }
+        RequestContentEncodingNotSupported(encoding: String) {
+            description("content encoding not supported"),
+            display("content encoding not supported: {}", encoding),
+        }
+        RequestBodyDecodingFailed(encoding: String) {
+            description("failed to decode the request body"),
+            display("the request body is not valid {} data", encoding),
+        }
+        RequestHeadersTooLarge(max: usize) {
+            description("request headers too large"),
+            display("the request headers are larger than {} bytes", max),
+        }
+        RequestTimedOut {
+            description("request timed out"),
+            display("the request took too long to be received"),
+        }
+        TooManyConnections(max: usize) {
+            description("too many connections"),
+            display("more than {} connections are open", max),
+        }
+        ConnectionIdle {
+            description("connection idle"),
+            display("the connection was idle for too long"),
+        }
+        RequestMalformed {
+            description("malformed request"),
+            display("the request is not valid HTTP"),
+        }
+
         // Providers errors
         ProviderNotFound(name: String) {
             description("provider not found"),
@@ -110,10 +188,256 @@ error_chain! {
             description("invalid GitHub event name"),
             display("invalid GitHub event name: {}", name),
         }
+        ProviderGitHubInvalidRefPattern(pattern: String) {
+            description("invalid GitHub ref pattern"),
+            display("invalid GitHub ref pattern: {}", pattern),
+        }
+        ProviderInvalidPathPattern(pattern: String) {
+            description("invalid path pattern"),
+            display("invalid path pattern: {}", pattern),
+        }
+        ProviderInvalidTagPattern(pattern: String) {
+            description("invalid tag pattern"),
+            display("invalid tag pattern: {}", pattern),
+        }
+        ProviderInvalidVersionRange(range: String) {
+            description("invalid version range"),
+            display("invalid version range: {}", range),
+        }
         ProviderGitLabInvalidEventName(name: String) {
             description("invalid GitLab event name"),
             display("invalid GitLab event name: {}", name),
         }
+        ProviderUptimeInvalidAlertType(alert: String) {
+            description("invalid uptime alert type"),
+            display("invalid uptime alert type: {}", alert),
+        }
+        ProviderS3InvalidEventName(name: String) {
+            description("invalid S3 event name"),
+            display("invalid S3 event name: {}", name),
+        }
+        ProviderDiscordPublicKeyConflict {
+            description("either public_key or public_key_file must be present"),
+            display("either public_key or public_key_file must be present"),
+        }
+        ProviderNotDeclared(name: String) {
+            description("provider not declared by the script"),
+            display("the provider {} isn't declared by the script", name),
+        }
+        ProviderSecretFileConflict {
+            description("both secret and secret_file are present"),
+            display("both secret and secret_file are present"),
+        }
+        ProviderSecretFileNotString {
+            description("secret_file must be a string"),
+            display("secret_file must be a string"),
+        }
+        ProviderSecretFileInsecure(mode: u32) {
+            description("the secret file can be read by everyone"),
+            display("the secret file can be read by everyone (mode {:o})", mode),
+        }
+        ProviderEnvVarMissing(name: String) {
+            description("environment variable not set"),
+            display("environment variable not set: {}", name),
+        }
+        ProviderEnvVarInvalid(string: String) {
+            description("invalid environment variable reference"),
+            display("invalid environment variable reference in '{}'", string),
+        }
+        ProviderCompositeInvalid(reason: String) {
+            description("invalid composite provider configuration"),
+            display("invalid composite provider configuration: {}", reason),
+        }
+
+        // Vault errors
+        VaultDisabled {
+            description("Vault support is not enabled"),
+            display("Vault support is not enabled in this build of Fisher"),
+        }
+        VaultNotConfigured {
+            description("Vault is not configured"),
+            display("a Vault secret is referenced, but Vault is not configured"),
+        }
+        VaultInvalidConfig(reason: String) {
+            description("invalid Vault configuration"),
+            display("invalid Vault configuration: {}", reason),
+        }
+        VaultInvalidReference(reference: String) {
+            description("invalid Vault secret reference"),
+            display("invalid Vault secret reference: {}", reference),
+        }
+        VaultBadStatus(status: u16) {
+            description("Vault returned a bad status code"),
+            display("Vault returned the status code {}", status),
+        }
+        VaultInvalidResponse {
+            description("Vault returned an invalid response"),
+            display("Vault returned an invalid response"),
+        }
+        VaultKeyMissing(reference: String) {
+            description("key missing from the Vault secret"),
+            display("key missing from the Vault secret: {}", reference),
+        }
+
+        // Scripts watcher errors
+        ScriptsWatchUnsupported {
+            description("watching the scripts is not supported"),
+            display("watching the scripts is only supported on Linux"),
+        }
+
+        // Heartbeat errors
+        HeartbeatBadStatus(status: u16) {
+            description("heartbeat ping returned a bad status code"),
+            display("heartbeat ping returned the status code {}", status),
+        }
+
+        // Tracing errors
+        TracingBadStatus(status: u16) {
+            description("exporting the traces returned a bad status code"),
+            display(
+                "exporting the traces returned the status code {}", status,
+            ),
+        }
+
+        // TLS errors
+        TlsConfigIncomplete {
+            description("incomplete TLS configuration"),
+            display("both tls-cert and tls-key must be set to enable TLS"),
+        }
+        TlsInvalidCertificate(path: String) {
+            description("invalid TLS certificate"),
+            display("invalid TLS certificate: {}", path),
+        }
+        TlsInvalidKey(path: String) {
+            description("invalid TLS private key"),
+            display("invalid TLS private key: {}", path),
+        }
+        TlsError(reason: String) {
+            description("TLS error"),
+            display("TLS error: {}", reason),
+        }
+
+        // ACME errors
+        AcmeDisabled {
+            description("ACME support is not enabled"),
+            display("ACME support is not enabled in this build of Fisher"),
+        }
+        AcmeTlsConflict {
+            description("both ACME and a TLS certificate are configured"),
+            display("tls-cert and tls-key can't be set when ACME is enabled"),
+        }
+        AcmeBadStatus(status: u16, detail: String) {
+            description("the ACME server returned an error"),
+            display("the ACME server returned the status code {}: {}", status, detail),
+        }
+        AcmeInvalidResponse(reason: String) {
+            description("the ACME server returned an invalid response"),
+            display("the ACME server returned an invalid response: {}", reason),
+        }
+        AcmeInvalidKey {
+            description("invalid ACME key"),
+            display("invalid ACME key"),
+        }
+        AcmeChallengeFailed(domain: String) {
+            description("the ACME challenge failed"),
+            display("the ACME challenge for {} failed", domain),
+        }
+        AcmeFailed(domain: String) {
+            description("failed to obtain the certificate"),
+            display("failed to obtain the certificate for {}", domain),
+        }
+
+        // Proxies errors
+        ProxiesConfigConflict {
+            description("conflicting proxies configuration"),
+            display("behind-proxies and trusted-proxies can't be used together"),
+        }
+
+        // Custom paths errors
+        InvalidCustomPath(path: String) {
+            description("invalid custom path"),
+            display("invalid or reserved custom path: {}", path),
+        }
+
+        // Mirroring errors
+        MirrorConfigIncomplete {
+            description("incomplete mirroring configuration"),
+            display("mirror-token must be set to mirror deliveries"),
+        }
+        MirrorBadStatus(status: u16) {
+            description("the standby instance returned a bad status code"),
+            display("the standby instance returned the status code {}", status),
+        }
+        MirroredBodyInvalid {
+            description("invalid body of the mirrored delivery"),
+            display("the binary body of the mirrored delivery is invalid"),
+        }
+
+        // Archiving errors
+        ArchiveConfigConflict {
+            description("conflicting archiving configuration"),
+            display("only one of directory and s3-bucket can be set"),
+        }
+        ArchiveConfigIncomplete {
+            description("incomplete archiving configuration"),
+            display(
+                "s3-access-key and s3-secret-key must be set to archive to S3"
+            ),
+        }
+        ArchiveBadStatus(status: u16) {
+            description("the S3 bucket returned a bad status code"),
+            display("the S3 bucket returned the status code {}", status),
+        }
+
+        // Approval errors
+        ApproverMissing {
+            description("the approver is missing"),
+            display("the name of who approved the job must be provided"),
+        }
+
+        // Freeze errors
+        FreezeReasonMissing {
+            description("the reason of the freeze is missing"),
+            display("the reason of the freeze must be provided"),
+        }
+
+        // Artifacts errors
+        ArtifactInvalidName(name: String) {
+            description("invalid name of an artifact"),
+            display(
+                "invalid name of an artifact (only letters, numbers, dots, \
+                 dashes and underscores are allowed): {}", name,
+            ),
+        }
+
+        // Trigger errors
+        TriggerInvalidEnvName(name: String) {
+            description("invalid name of an environment variable"),
+            display(
+                "invalid name of an environment variable (only uppercase \
+                 letters, numbers and underscores are allowed): {}", name,
+            ),
+        }
+
+        // Processor errors
+        JobRejected(hook: String) {
+            description("job rejected because the queue is full"),
+            display("job of the '{}' hook rejected: the queue is full", hook),
+        }
+        CircuitOpen(hook: String) {
+            description("job rejected because the circuit breaker is open"),
+            display(
+                "job of the '{}' hook rejected: the circuit breaker is open",
+                hook,
+            ),
+        }
+        BudgetExhausted(hook: String) {
+            description("job rejected because the daily budget is exhausted"),
+            display(
+                "job of the '{}' hook rejected: its daily budget is exhausted",
+                hook,
+            ),
+        }
 
         // Broken things
         BrokenChannel {
@@ -136,6 +460,37 @@ error_chain! {
             description("script execution failed"),
             display("execution of the '{}' script failed", name),
         }
+        ScriptInvalidEnvPrefix(prefix: String) {
+            description("invalid environment variables prefix"),
+            display(
+                "invalid environment variables prefix (only uppercase \
+                 letters, numbers and underscores are allowed): {}",
+                prefix,
+            ),
+        }
+        ScriptInvalidTempDir(path: String) {
+            description("invalid temporary directory"),
+            display("the temporary directory must be absolute: {}", path),
+        }
+        ScriptInvalidDailyBudget {
+            description("invalid daily budget"),
+            display("the daily budget must be longer than zero seconds"),
+        }
+        ScriptInvalidRateLimit {
+            description("invalid rate limit"),
+            display("the rate limit must allow at least one request"),
+        }
+        ScriptInvalidResponseStatus(status: u16) {
+            description("invalid response status"),
+            display("invalid HTTP status code of the response: {}", status),
+        }
+        ScriptInvalidUmask(umask: String) {
+            description("invalid umask"),
+            display(
+                "invalid umask (expected an octal number like 027): {}",
+                umask,
+            ),
+        }
         ScriptParsingError(file: String, line: u32) {
             description("script parsing error"),
             display(
@@ -143,6 +498,58 @@ error_chain! {
                 relative_to_current(file).to_string_lossy(), line,
             ),
         }
+        ScriptsReloadFailed(path: String) {
+            description("reloading the scripts failed"),
+            display("reloading the scripts in '{}' failed", path),
+        }
+        HeartbeatInvalidUrl(url: String) {
+            description("invalid heartbeat URL"),
+            display("invalid heartbeat URL: {}", url),
+        }
+        MirrorInvalidUrl(url: String) {
+            description("invalid mirroring URL"),
+            display("invalid mirroring URL: {}", url),
+        }
+        MirrorFailed(hook: String) {
+            description("mirroring the delivery failed"),
+            display("mirroring the delivery of the '{}' hook failed", hook),
+        }
+        HeartbeatFailed(url: String) {
+            description("heartbeat ping failed"),
+            display("heartbeat ping to {} failed", url),
+        }
+        TracingInvalidUrl(url: String) {
+            description("invalid OTLP endpoint"),
+            display("invalid OTLP endpoint: {}", url),
+        }
+        TracingExportFailed(url: String) {
+            description("exporting the traces failed"),
+            display("exporting the traces to {} failed", url),
+        }
+        AuditLogFailed(path: String) {
+            description("writing the audit log failed"),
+            display("writing the audit log to {} failed", path),
+        }
+        ArchiveInvalidUrl(url: String) {
+            description("invalid S3 endpoint"),
+            display("invalid S3 endpoint: {}", url),
+        }
+        ArchiveFailed(key: String) {
+            description("archiving the payload failed"),
+            display("archiving the payload at {} failed", key),
+        }
+        ArchiveCleanupFailed {
+            description("removing the expired payloads failed"),
+            display("removing the expired archived payloads failed"),
+        }
+        VaultFetchFailed(path: String) {
+            description("can't fetch the secret from Vault"),
+            display("can't fetch the secret '{}' from Vault", path),
+        }
+        ProviderSecretFileUnreadable(path: String) {
+            description("can't load the secret file"),
+            display("can't load the secret file '{}'", path),
+        }
         RateLimitConfigError(string: String) {
             description("error while parsing the rate limit config"),
             display("error while parsing rate limit config '{}'", string),
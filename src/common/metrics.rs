@@ -0,0 +1,296 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fisher's internal metrics.
+//!
+//! This module keeps global counters about what happened in the current
+//! Fisher instance. Every counter has a name and an optional list of labels,
+//! and it's created the first time it's incremented. Histograms track the
+//! distribution of values like the duration of the jobs, and everything can
+//! be exported in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+
+/// The key identifying a counter: its name and its labels.
+pub type CounterKey = (&'static str, Vec<(&'static str, String)>);
+
+/// The upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+];
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Histogram {
+    /// How many values are lower or equal than each bucket's bound.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+
+lazy_static! {
+    static ref COUNTERS: Mutex<BTreeMap<CounterKey, u64>> =
+        Mutex::new(BTreeMap::new());
+    static ref HISTOGRAMS: Mutex<BTreeMap<CounterKey, Histogram>> =
+        Mutex::new(BTreeMap::new());
+}
+
+
+fn key(name: &'static str, labels: &[(&'static str, &str)]) -> CounterKey {
+    (name, labels.iter().map(|&(k, v)| (k, v.to_string())).collect())
+}
+
+
+/// Increment the counter with the provided name and labels by one.
+pub fn increment(name: &'static str, labels: &[(&'static str, &str)]) {
+    add(name, labels, 1);
+}
+
+
+/// Increment the counter with the provided name and labels by an amount.
+pub fn add(name: &'static str, labels: &[(&'static str, &str)], amount: u64) {
+    // Metrics are not critical, so a poisoned lock is just ignored
+    if let Ok(mut counters) = COUNTERS.lock() {
+        *counters.entry(key(name, labels)).or_insert(0) += amount;
+    }
+}
+
+
+/// Set the counter with the provided name and labels to the value. This is
+/// meant for gauges, whose value can also decrease.
+pub fn set(name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+    if let Ok(mut counters) = COUNTERS.lock() {
+        counters.insert(key(name, labels), value);
+    }
+}
+
+
+/// Set the counter with the provided name and labels to the value, if it's
+/// bigger than the current one. This tracks the highest value ever recorded.
+pub fn record_max(
+    name: &'static str, labels: &[(&'static str, &str)], value: u64,
+) {
+    if let Ok(mut counters) = COUNTERS.lock() {
+        let current = counters.entry(key(name, labels)).or_insert(0);
+        *current = (*current).max(value);
+    }
+}
+
+
+/// Record a value, in seconds, in the histogram with the provided name and
+/// labels.
+pub fn observe(
+    name: &'static str, labels: &[(&'static str, &str)], value: f64,
+) {
+    if let Ok(mut histograms) = HISTOGRAMS.lock() {
+        let histogram = histograms.entry(key(name, labels)).or_insert_with(
+            || Histogram {
+                buckets: vec![0; BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            },
+        );
+
+        for (bound, bucket) in BUCKETS.iter().zip(&mut histogram.buckets) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+
+/// Get the current value of the counter with the provided name and labels.
+pub fn get(name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+    COUNTERS
+        .lock()
+        .ok()
+        .and_then(|counters| counters.get(&key(name, labels)).cloned())
+        .unwrap_or(0)
+}
+
+
+/// Get a copy of all the counters, sorted by their name and labels.
+pub fn snapshot() -> Vec<(CounterKey, u64)> {
+    if let Ok(counters) = COUNTERS.lock() {
+        counters.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+
+/// Export all the counters and histograms in the Prometheus text format.
+/// Counters whose name doesn't end with `_total` are exported as gauges.
+pub fn prometheus() -> String {
+    let mut result = String::new();
+
+    let mut last = "";
+    for ((name, labels), value) in snapshot() {
+        if name != last {
+            let kind = if name.ends_with("_total") {
+                "counter"
+            } else {
+                "gauge"
+            };
+            let _ = writeln!(result, "# TYPE {} {}", name, kind);
+            last = name;
+        }
+        let _ = writeln!(
+            result, "{}{} {}", name, format_labels(&labels), value,
+        );
+    }
+
+    let histograms = match HISTOGRAMS.lock() {
+        Ok(histograms) => histograms.clone(),
+        Err(..) => BTreeMap::new(),
+    };
+    let mut last = "";
+    for (&(name, ref labels), histogram) in &histograms {
+        if name != last {
+            let _ = writeln!(result, "# TYPE {} histogram", name);
+            last = name;
+        }
+
+        let mut bucket = |le: String, count: u64| {
+            let mut bucket_labels = labels.clone();
+            bucket_labels.push(("le", le));
+            let _ = writeln!(
+                result, "{}_bucket{} {}",
+                name, format_labels(&bucket_labels), count,
+            );
+        };
+        for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+            bucket(bound.to_string(), *count);
+        }
+        bucket("+Inf".into(), histogram.count);
+
+        let _ = writeln!(
+            result, "{}_sum{} {}", name, format_labels(labels), histogram.sum,
+        );
+        let _ = writeln!(
+            result, "{}_count{} {}",
+            name, format_labels(labels), histogram.count,
+        );
+    }
+
+    result
+}
+
+
+fn format_labels(labels: &[(&'static str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let formatted = labels
+        .iter()
+        .map(|&(name, ref value)| format!(
+            "{}=\"{}\"", name,
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n"),
+        ))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", formatted.join(","))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{add, get, increment, observe, prometheus, record_max, set};
+    use super::snapshot;
+
+
+    #[test]
+    fn test_counters() {
+        let name = "fisher_test_counters_total";
+        assert_eq!(get(name, &[]), 0);
+
+        increment(name, &[]);
+        increment(name, &[]);
+        add(name, &[("label", "a")], 0);
+        increment(name, &[("label", "a")]);
+
+        assert_eq!(get(name, &[]), 2);
+        assert_eq!(get(name, &[("label", "a")]), 1);
+        assert_eq!(get(name, &[("label", "b")]), 0);
+
+        let found = snapshot()
+            .into_iter()
+            .filter(|&((n, _), _)| n == name)
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ((name, vec![]), 2),
+            ((name, vec![("label", "a".to_string())]), 1),
+        ]);
+    }
+
+    #[test]
+    fn test_record_max() {
+        let name = "fisher_test_record_max";
+
+        record_max(name, &[], 10);
+        record_max(name, &[], 5);
+        assert_eq!(get(name, &[]), 10);
+
+        record_max(name, &[], 20);
+        assert_eq!(get(name, &[]), 20);
+    }
+
+    #[test]
+    fn test_set() {
+        let name = "fisher_test_set";
+
+        set(name, &[], 10);
+        set(name, &[], 5);
+        assert_eq!(get(name, &[]), 5);
+    }
+
+    #[test]
+    fn test_prometheus() {
+        add("fisher_test_prometheus_total", &[("hook", "a\"b")], 3);
+        set("fisher_test_prometheus", &[], 2);
+        observe("fisher_test_prometheus_seconds", &[("hook", "a")], 0.7);
+        observe("fisher_test_prometheus_seconds", &[("hook", "a")], 20.0);
+
+        let exported = prometheus();
+        for line in &[
+            "# TYPE fisher_test_prometheus_total counter",
+            "fisher_test_prometheus_total{hook=\"a\\\"b\"} 3",
+            "# TYPE fisher_test_prometheus gauge",
+            "fisher_test_prometheus 2",
+            "# TYPE fisher_test_prometheus_seconds histogram",
+            "fisher_test_prometheus_seconds_bucket{hook=\"a\",le=\"0.5\"} 0",
+            "fisher_test_prometheus_seconds_bucket{hook=\"a\",le=\"1\"} 1",
+            "fisher_test_prometheus_seconds_bucket{hook=\"a\",le=\"30\"} 2",
+            "fisher_test_prometheus_seconds_bucket{hook=\"a\",le=\"+Inf\"} 2",
+            "fisher_test_prometheus_seconds_sum{hook=\"a\"} 20.7",
+            "fisher_test_prometheus_seconds_count{hook=\"a\"} 2",
+        ] {
+            assert!(
+                exported.lines().any(|l| l == *line),
+                "{} not in {}", line, exported,
+            );
+        }
+    }
+}
@@ -19,6 +19,7 @@
 
 pub mod config;
 pub mod errors;
+pub mod metrics;
 pub mod prelude;
 pub mod serial;
 pub mod state;
@@ -15,10 +15,86 @@
 
 //! Structs used by Fisher.
 
+use std::borrow::Cow;
+use std::ffi::OsStr;
+
+use utils;
+
+
+/// Return the list of Cargo features enabled at build time.
+macro_rules! enabled_features {
+    ($($feature:tt),*) => {{
+        #[allow(unused_mut)]
+        let mut features: Vec<&'static str> = Vec::new();
+        $(
+            if cfg!(feature = $feature) {
+                features.push($feature);
+            }
+        )*
+        features
+    }};
+}
+
+
+/// What to do when a job is queued but the queue is full.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShedPolicy {
+    /// Reject the new job, keeping the queued ones.
+    RejectNew,
+
+    /// Drop the oldest queued job of the same hook to make room for the new
+    /// one. This should be used only by idempotent hooks.
+    DropOldest,
+}
+
+impl ShedPolicy {
+    /// Get the name of the policy, as used in the configuration.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ShedPolicy::RejectNew => "reject-new",
+            ShedPolicy::DropOldest => "drop-oldest",
+        }
+    }
+}
+
+
+/// How the values of the environment variables set by providers, which can
+/// be influenced by whoever sends the webhook, are sanitized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvSanitization {
+    /// Keep the values as they are.
+    None,
+
+    /// Remove the control characters, except newlines and tabs.
+    StripControl,
+
+    /// Remove the control characters and quote the values, so they can be
+    /// safely interpolated in shell commands.
+    ShellQuote,
+}
+
+impl EnvSanitization {
+    /// Sanitize the value, replacing invalid UTF-8 if it's not kept as is.
+    pub fn apply<'a>(&self, value: &'a OsStr) -> Cow<'a, OsStr> {
+        match *self {
+            EnvSanitization::None => Cow::Borrowed(value),
+            EnvSanitization::StripControl => Cow::Owned(
+                utils::strip_control_chars(&value.to_string_lossy()).into()
+            ),
+            EnvSanitization::ShellQuote => Cow::Owned(utils::shell_quote(
+                &utils::strip_control_chars(&value.to_string_lossy())
+            ).into()),
+        }
+    }
+}
+
 
 /// This struct contains some information about how the processor is feeling.
 
-#[derive(Copy, Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct HealthDetails {
     /// The number of jobs in the queue, waiting to be processed.
     pub queued_jobs: usize,
@@ -28,4 +104,289 @@ pub struct HealthDetails {
 
     /// The total number of threads running, either waiting or working.
     pub max_threads: u16,
+
+    /// Whether the processor is paused, not starting new jobs.
+    pub paused: bool,
+
+    /// For how many seconds the processor has been running.
+    pub uptime: u64,
+
+    /// The number of hooks currently loaded.
+    pub hooks: usize,
+
+    /// The number of jobs executed since the processor started.
+    pub processed_jobs: u64,
+
+    /// The number of executed jobs which failed.
+    pub failed_jobs: u64,
+
+    /// The threads of the processor, and whether they're working.
+    pub workers: Vec<WorkerState>,
+
+    /// Information about the running build, filled by the web API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildInfo>,
+
+    /// The freezes currently active, filled by the web API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freezes: Option<Vec<FreezeDetails>>,
+}
+
+
+/// The state of a job tracked by the processor.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    /// The job is waiting to be executed.
+    Queued,
+
+    /// The job is being executed.
+    Running,
+
+    /// The job was executed successfully.
+    Succeeded,
+
+    /// The job failed, or it was dropped before being executed.
+    Failed,
+
+    /// The job was cancelled before being executed.
+    Cancelled,
+}
+
+
+/// This struct contains the details of a job tracked by the processor.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JobStatus {
+    /// The unique ID of the job.
+    pub job_id: String,
+
+    /// The name of the script executed by the job.
+    pub script: String,
+
+    /// The current state of the job.
+    pub state: JobState,
+
+    /// The exit code of the script, if it exited normally.
+    pub exit_code: Option<i32>,
+
+    /// When the job was queued, as a UNIX timestamp.
+    #[serde(serialize_with = "utils::serialize_timestamp")]
+    pub queued_at: u64,
+
+    /// When the job started, as a UNIX timestamp.
+    #[serde(serialize_with = "utils::serialize_optional_timestamp")]
+    pub started_at: Option<u64>,
+
+    /// When the job ended, as a UNIX timestamp.
+    #[serde(serialize_with = "utils::serialize_optional_timestamp")]
+    pub finished_at: Option<u64>,
+}
+
+
+/// This struct contains the details of a job waiting to be executed.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QueuedJob {
+    /// The unique ID of the job.
+    pub job_id: String,
+
+    /// The name of the script executed by the job.
+    pub script: String,
+
+    /// The priority of the job: jobs with a higher priority run first.
+    pub priority: isize,
+
+    /// When the job was queued, as a UNIX timestamp.
+    #[serde(serialize_with = "utils::serialize_timestamp")]
+    pub queued_at: u64,
+
+    /// Why the job isn't running yet: it can be `queued`, `waiting` for the
+    /// running job of its script, or `held` by the circuit breaker or the
+    /// daily budget of its script.
+    pub reason: &'static str,
+}
+
+
+/// This struct contains the details of a thread of the processor.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WorkerDetails {
+    /// The number of the thread, in the order the threads were started.
+    pub worker: usize,
+
+    /// The unique ID of the job being executed, if any.
+    pub job_id: Option<String>,
+
+    /// The name of the script being executed, if any.
+    pub script: Option<String>,
+
+    /// When the current job started, as a UNIX timestamp.
+    #[serde(serialize_with = "utils::serialize_optional_timestamp")]
+    pub started_at: Option<u64>,
+}
+
+
+/// This struct contains whether a thread of the processor is working.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WorkerState {
+    /// The number of the thread, in the order the threads were started.
+    pub worker: usize,
+
+    /// Either `busy` if the thread is executing a job, or `idle`.
+    pub state: &'static str,
+}
+
+
+/// This struct contains the content of the processor queue.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QueueDetails {
+    /// The jobs waiting to be executed, in the order they should be
+    /// executed.
+    pub jobs: Vec<QueuedJob>,
+
+    /// The threads of the processor, with the job they're executing.
+    pub workers: Vec<WorkerDetails>,
+}
+
+
+/// What to do with the webhooks received while a script is frozen.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FreezeAction {
+    /// Keep the jobs until the freeze ends, and execute them after that.
+    Queue,
+
+    /// Reject the webhooks, without executing anything.
+    Reject,
+}
+
+
+/// This struct contains the details of a freeze, which prevents scripts from
+/// being executed for a while.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FreezeDetails {
+    /// The script affected by the freeze, or `None` if it affects all of
+    /// them.
+    pub script: Option<String>,
+
+    /// Why the scripts are frozen.
+    pub reason: String,
+
+    /// What to do with the webhooks received during the freeze.
+    pub action: FreezeAction,
+
+    /// When the freeze ends, as a UNIX timestamp. The freeze lasts until
+    /// it's lifted if this is `None`.
+    #[serde(serialize_with = "utils::serialize_optional_timestamp")]
+    pub expires_at: Option<u64>,
+}
+
+
+/// The configuration of the circuit breaker of a script, which stops
+/// executing it for a while after it failed too many times in a row.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    /// How many consecutive failures open the circuit.
+    pub failures: u32,
+
+    /// For how many seconds the circuit stays open before a trial execution.
+    pub cooldown: u64,
+
+    /// What to do with the jobs of the script while the circuit is open.
+    pub action: FreezeAction,
+}
+
+
+/// The daily budget of a script, which limits for how long its jobs can run
+/// every day.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DailyBudget {
+    /// For how many seconds the jobs of the script can run every day.
+    pub seconds: u64,
+
+    /// What to do with the jobs of the script once the budget is exhausted.
+    pub action: FreezeAction,
+}
+
+
+/// A response returned by a script instead of the default one, for
+/// webhook senders requiring a specific format.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct CustomResponse {
+    /// The HTTP status code, if it's different from the default one.
+    #[serde(default)]
+    pub status: Option<u16>,
+
+    /// The template of the body, if it's different from the default one.
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+
+/// The custom responses returned by a script, depending on what happened to
+/// the webhook.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct CustomResponses {
+    /// Returned when a job is queued.
+    #[serde(default)]
+    pub queued: Option<CustomResponse>,
+
+    /// Returned when the webhook is accepted without queueing a job right
+    /// away, for example if it's a ping or it must be approved.
+    #[serde(default)]
+    pub accepted: Option<CustomResponse>,
+
+    /// Returned when the webhook is not validated by any provider.
+    #[serde(default)]
+    pub rejected: Option<CustomResponse>,
+}
+
+impl CustomResponses {
+    /// Get all the configured responses.
+    pub fn iter(&self) -> impl Iterator<Item = &CustomResponse> {
+        self.queued
+            .iter()
+            .chain(self.accepted.iter())
+            .chain(self.rejected.iter())
+    }
+}
+
+
+/// This struct contains some information about the running Fisher build.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    /// The version of Fisher.
+    pub version: &'static str,
+
+    /// The git commit Fisher was built from, if it's known.
+    pub commit: Option<&'static str>,
+
+    /// The Cargo features enabled when Fisher was built.
+    pub features: Vec<&'static str>,
+
+    /// The hash of the configuration currently loaded, if any.
+    pub config_hash: Option<String>,
+}
+
+impl BuildInfo {
+    /// Get the information about the current build.
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("FISHER_GIT_COMMIT"),
+            features: enabled_features!["vault", "acme"],
+            config_hash: None,
+        }
+    }
 }
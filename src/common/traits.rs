@@ -20,7 +20,10 @@ use std::sync::Arc;
 use std::fmt::Debug;
 
 use super::prelude::*;
-use super::structs::HealthDetails;
+use super::structs::{
+    CircuitBreaker, DailyBudget, HealthDetails, JobStatus, QueueDetails,
+    ShedPolicy,
+};
 
 
 /// This trait represents a script that can be run by Fisher.
@@ -68,6 +71,12 @@ pub trait ScriptsRepositoryTrait: Send + Sync {
         &self,
         output: <Self::Job as JobTrait<Self::Script>>::Output,
     ) -> Option<Self::JobsIter>;
+
+    /// Return all the jobs generated because a script exhausted its daily
+    /// budget, for example to notify someone about it.
+    fn jobs_after_budget_exhausted(
+        &self, script_name: &str, budget: DailyBudget,
+    ) -> Option<Self::JobsIter>;
 }
 
 
@@ -77,27 +86,65 @@ pub trait JobTrait<S: ScriptTrait> {
     type Context: Debug + Send + Sync;
 
     /// The output that will be returned by the job.
-    type Output: Clone + Send + Sync;
+    type Output: JobOutputTrait + Clone + Send + Sync;
 
     /// Execute the job and return the output of it.
     fn execute(&self, ctx: &Self::Context) -> Result<Self::Output>;
 
+    /// Get the unique ID of this job.
+    fn id(&self) -> &str;
+
     /// Get the ID of the underlying script.
     fn script_id(&self) -> S::Id;
 
     /// Get the name of the underlying script.
     fn script_name(&self) -> &str;
+
+    /// Get what to do with this job if the queue is full.
+    fn shed_policy(&self) -> ShedPolicy;
+
+    /// Get the configuration of the circuit breaker of the underlying
+    /// script, if it has one.
+    fn circuit_breaker(&self) -> Option<CircuitBreaker>;
+
+    /// Get the daily budget of the underlying script, if it has one.
+    fn daily_budget(&self) -> Option<DailyBudget>;
+}
+
+
+/// This trait represents the output of a job.
+pub trait JobOutputTrait {
+    /// Return if the job was successful.
+    fn success(&self) -> bool;
+
+    /// Return the exit code of the job, if it exited normally.
+    fn exit_code(&self) -> Option<i32>;
 }
 
 
 /// This trait represents the API of the processor
 pub trait ProcessorApiTrait<S: ScriptsRepositoryTrait>: Send {
-    /// Queue a new job into the processor.
+    /// Queue a new job into the processor. If the queue is full, the job
+    /// might be rejected depending on its shedding policy.
     fn queue(&self, job: S::Job, priority: isize) -> Result<()>;
 
     /// Get some insights about the health of the processor.
     fn health_details(&self) -> Result<HealthDetails>;
 
+    /// Get the jobs waiting in the queue, and the ones being executed.
+    fn queue_details(&self) -> Result<QueueDetails>;
+
+    /// Get the status of a job, if it's queued, running or it ended
+    /// recently.
+    fn job_status(&self, job_id: &str) -> Result<Option<JobStatus>>;
+
+    /// Get the status of the jobs which ended most recently, newest first.
+    fn recent_jobs(&self, limit: usize) -> Result<Vec<JobStatus>>;
+
+    /// Cancel a job if it's still waiting to be executed, returning its
+    /// status afterwards.
+    fn cancel_job(&self, job_id: &str) -> Result<Option<JobStatus>>;
+
     /// Execute periodic cleanup tasks on the processor.
     fn cleanup(&self) -> Result<()>;
 
@@ -106,4 +153,11 @@ pub trait ProcessorApiTrait<S: ScriptsRepositoryTrait>: Send {
 
     /// Unlock the processor, allowing new jobs to be run.
     fn unlock(&self) -> Result<()>;
+
+    /// Pause the processor: jobs are still queued, but no new job is started
+    /// until it's resumed. Unlike locking, pausing survives reloads.
+    fn pause(&self) -> Result<()>;
+
+    /// Resume a paused processor, starting the queued jobs.
+    fn resume(&self) -> Result<()>;
 }
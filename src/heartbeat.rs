@@ -0,0 +1,201 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Periodic heartbeat pings to an external uptime monitoring service.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use reqwest;
+use url::Url;
+
+use common::prelude::*;
+use common::config::{HeartbeatConfig, HeartbeatMethod};
+use common::structs::HealthDetails;
+
+use scripts::Repository;
+
+
+fn send_ping(
+    client: &reqwest::Client,
+    url: &Url,
+    method: HeartbeatMethod,
+    details: &HealthDetails,
+) -> Result<()> {
+    let response = match method {
+        HeartbeatMethod::Get => {
+            let mut url = url.clone();
+            url.query_pairs_mut()
+                .append_pair("queued_jobs", &details.queued_jobs.to_string())
+                .append_pair("busy_threads", &details.busy_threads.to_string())
+                .append_pair("max_threads", &details.max_threads.to_string());
+
+            client.get(url).send()?
+        }
+        HeartbeatMethod::Post => client.post(url.clone()).json(details).send()?,
+    };
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::HeartbeatBadStatus(response.status().as_u16()).into())
+    }
+}
+
+
+/// This struct controls the thread sending the heartbeat pings.
+
+pub struct Heartbeat {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Heartbeat {
+    /// Start sending heartbeat pings, if they're enabled in the
+    /// configuration. The first ping is sent immediately.
+    pub fn start<A: ProcessorApiTrait<Repository> + 'static>(
+        config: &HeartbeatConfig,
+        processor: A,
+    ) -> Result<Option<Self>> {
+        let url = if let Some(ref url) = config.url {
+            Url::parse(url)
+                .chain_err(|| ErrorKind::HeartbeatInvalidUrl(url.clone()))?
+        } else {
+            return Ok(None);
+        };
+        let interval = Duration::from_secs(config.interval.as_u64());
+        let method = config.method;
+
+        let (stop_send, stop_recv) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let client = reqwest::Client::new();
+
+            loop {
+                let result = processor.health_details().and_then(|details| {
+                    send_ping(&client, &url, method, &details)
+                });
+
+                // Failed pings shouldn't stop the heartbeat
+                if let Err(err) = result.chain_err(|| {
+                    ErrorKind::HeartbeatFailed(url.to_string())
+                }) {
+                    err.pretty_print();
+                }
+
+                // Wait for the next ping, exiting if Fisher is stopped
+                match stop_recv.recv_timeout(interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Some(Heartbeat {
+            stop: stop_send,
+            handle,
+        }))
+    }
+
+    /// Stop sending heartbeat pings, returning when the thread exited.
+    pub fn stop(self) {
+        // The thread might be already stopped
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use serde_json;
+    use tiny_http;
+
+    use common::config::{HeartbeatConfig, HeartbeatMethod};
+    use utils::testing::*;
+
+    use super::Heartbeat;
+
+
+    fn start(
+        method: HeartbeatMethod,
+    ) -> (tiny_http::Server, Heartbeat, mpsc::Receiver<ProcessorApiCall>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+
+        let config = HeartbeatConfig {
+            url: Some(format!("http://{}/ping", server.server_addr())),
+            interval: 60.into(),
+            method,
+        };
+        let (processor, calls) = FakeProcessorApi::new();
+        let heartbeat = Heartbeat::start(&config, processor).unwrap().unwrap();
+
+        (server, heartbeat, calls)
+    }
+
+    #[test]
+    fn test_disabled() {
+        let (processor, _) = FakeProcessorApi::new();
+        let config = HeartbeatConfig::default();
+        assert!(Heartbeat::start(&config, processor).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let (processor, _) = FakeProcessorApi::new();
+        let config = HeartbeatConfig {
+            url: Some("not an url".into()),
+            .. HeartbeatConfig::default()
+        };
+        assert!(Heartbeat::start(&config, processor).is_err());
+    }
+
+    #[test]
+    fn test_get_ping() {
+        let (server, heartbeat, _calls) = start(HeartbeatMethod::Get);
+
+        let req = server.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(*req.method(), tiny_http::Method::Get);
+        assert_eq!(
+            req.url(), "/ping?queued_jobs=1&busy_threads=2&max_threads=3"
+        );
+        req.respond(tiny_http::Response::empty(200)).unwrap();
+
+        heartbeat.stop();
+    }
+
+    #[test]
+    fn test_post_ping() {
+        let (server, heartbeat, _calls) = start(HeartbeatMethod::Post);
+
+        let mut req =
+            server.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(*req.method(), tiny_http::Method::Post);
+        assert_eq!(req.url(), "/ping");
+
+        let mut body = String::new();
+        req.as_reader().read_to_string(&mut body).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["queued_jobs"].as_u64(), Some(1));
+        assert_eq!(json["busy_threads"].as_u64(), Some(2));
+        assert_eq!(json["max_threads"].as_u64(), Some(3));
+        req.respond(tiny_http::Response::empty(200)).unwrap();
+
+        heartbeat.stop();
+    }
+}
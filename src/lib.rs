@@ -13,37 +13,60 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-#![recursion_limit="256"]
+#![recursion_limit="512"]
 
 extern crate ansi_term;
+extern crate base64;
 #[macro_use]
 extern crate error_chain;
-#[cfg(test)]
+extern crate flate2;
+extern crate futures;
+extern crate futures_cpupool;
+extern crate glob;
 extern crate hyper;
+#[cfg(test)]
+extern crate hyper010;
 #[macro_use]
 extern crate lazy_static;
 extern crate nix;
 extern crate rand;
+#[cfg(feature = "acme")]
+extern crate rcgen;
 extern crate regex;
+extern crate reqwest;
+extern crate ring;
+extern crate rustls;
+extern crate semver;
 extern crate hmac;
 extern crate sha1;
+extern crate sha2;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate tempdir;
+#[cfg(any(test, feature = "acme"))]
 extern crate tiny_http;
+extern crate tokio;
 extern crate url;
 extern crate users;
+extern crate webpki;
 
 #[macro_use]
 mod utils;
 mod app;
+mod audit;
+#[cfg(feature = "acme")]
+mod acme;
+mod heartbeat;
+#[cfg(feature = "vault")]
+mod vault;
 mod processor;
-mod providers;
+pub mod providers;
 mod requests;
 mod scripts;
+mod traces;
 mod web;
 pub mod common;
 
@@ -14,15 +14,17 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 use common::prelude::*;
 use common::state::State;
-use common::structs::HealthDetails;
+use common::structs::{HealthDetails, JobStatus, QueueDetails};
 
 use processor::scheduler::{Scheduler, SchedulerInput};
 #[cfg(test)]
 use processor::scheduler::DebugDetails;
 use processor::types::{Job, JobContext};
+use utils;
 
 
 /// This struct allows you to spawn a new processor, stop it and get its
@@ -43,6 +45,9 @@ impl<S: ScriptsRepositoryTrait> Processor<S> {
         ctx: JobContext<S>,
         state: Arc<State>,
     ) -> Result<Self> {
+        // The processes left behind by the jobs are reaped in the background
+        utils::start_reaper();
+
         // Retrieve wanted information from the spawned thread
         let (input_send, input_recv) = mpsc::sync_channel(0);
         let (wait_send, wait_recv) = mpsc::channel();
@@ -105,12 +110,26 @@ impl<S: ScriptsRepositoryTrait> ProcessorApi<S> {
         self.input.send(SchedulerInput::SetThreadsCount(count))?;
         Ok(())
     }
+
+    pub fn set_max_queued(&self, max: Option<usize>) -> Result<()> {
+        self.input.send(SchedulerInput::SetMaxQueued(max))?;
+        Ok(())
+    }
+
+    pub fn set_stuck_jobs(
+        &self, threshold: Option<Duration>, fail: bool,
+    ) -> Result<()> {
+        self.input.send(SchedulerInput::SetStuckJobs(threshold, fail))?;
+        Ok(())
+    }
 }
 
 impl<S: ScriptsRepositoryTrait> ProcessorApiTrait<S> for ProcessorApi<S> {
     fn queue(&self, job: Job<S>, priority: isize) -> Result<()> {
-        self.input.send(SchedulerInput::Job(job, priority))?;
-        Ok(())
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::Job(job, priority, res_send))?;
+
+        res_recv.recv()?
     }
 
     fn health_details(&self) -> Result<HealthDetails> {
@@ -119,6 +138,30 @@ impl<S: ScriptsRepositoryTrait> ProcessorApiTrait<S> for ProcessorApi<S> {
         Ok(res_recv.recv()?)
     }
 
+    fn queue_details(&self) -> Result<QueueDetails> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::QueueDetails(res_send))?;
+        Ok(res_recv.recv()?)
+    }
+
+    fn job_status(&self, job_id: &str) -> Result<Option<JobStatus>> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::JobStatus(job_id.into(), res_send))?;
+        Ok(res_recv.recv()?)
+    }
+
+    fn recent_jobs(&self, limit: usize) -> Result<Vec<JobStatus>> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::RecentJobs(limit, res_send))?;
+        Ok(res_recv.recv()?)
+    }
+
+    fn cancel_job(&self, job_id: &str) -> Result<Option<JobStatus>> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::CancelJob(job_id.into(), res_send))?;
+        Ok(res_recv.recv()?)
+    }
+
     fn cleanup(&self) -> Result<()> {
         self.input.send(SchedulerInput::Cleanup)?;
         Ok(())
@@ -133,4 +176,14 @@ impl<S: ScriptsRepositoryTrait> ProcessorApiTrait<S> for ProcessorApi<S> {
         self.input.send(SchedulerInput::Unlock)?;
         Ok(())
     }
+
+    fn pause(&self) -> Result<()> {
+        self.input.send(SchedulerInput::Pause)?;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.input.send(SchedulerInput::Resume)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,287 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Daily budgets, limiting how long the jobs of a hook can run every day.
+//!
+//! The time the jobs of a hook ran for is added up during the day (in UTC),
+//! and once it exceeds the budget the new jobs of the hook are rejected or
+//! kept aside until the next day. Jobs already running are not stopped.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Duration;
+
+use common::prelude::*;
+use common::metrics;
+use common::structs::{DailyBudget, FreezeAction};
+
+use super::scheduled_job::{self, ScheduledJob};
+use super::types::ScriptId;
+
+
+const SECONDS_IN_A_DAY: u64 = 24 * 60 * 60;
+
+
+/// Get the number of the current day since the UNIX epoch, in UTC.
+pub fn day_of(timestamp: u64) -> u64 {
+    timestamp / SECONDS_IN_A_DAY
+}
+
+
+#[derive(Debug)]
+struct Budget<S: ScriptsRepositoryTrait> {
+    hook_name: String,
+    config: DailyBudget,
+    day: u64,
+    used: Duration,
+    held: BinaryHeap<ScheduledJob<S>>,
+}
+
+impl<S: ScriptsRepositoryTrait> Budget<S> {
+    fn exhausted(&self) -> bool {
+        self.used >= Duration::from_secs(self.config.seconds)
+    }
+
+    /// Start counting again if a new day started, returning the held jobs.
+    fn reset(&mut self, day: u64) -> Vec<ScheduledJob<S>> {
+        if self.day == day {
+            return Vec::new();
+        }
+
+        self.day = day;
+        self.used = Duration::from_secs(0);
+        self.held.drain().collect()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Budgets<S: ScriptsRepositoryTrait> {
+    budgets: HashMap<ScriptId<S>, Budget<S>>,
+}
+
+impl<S: ScriptsRepositoryTrait> Budgets<S> {
+    pub fn new() -> Self {
+        Budgets {
+            budgets: HashMap::new(),
+        }
+    }
+
+    /// Start tracking the hook of the job, if it has a daily budget.
+    pub fn track(&mut self, job: &ScheduledJob<S>, day: u64) {
+        let config = if let Some(config) = job.daily_budget() {
+            config
+        } else {
+            return;
+        };
+
+        self.budgets
+            .entry(job.hook_id())
+            .or_insert_with(|| Budget {
+                hook_name: job.hook_name().to_string(),
+                config,
+                day,
+                used: Duration::from_secs(0),
+                held: BinaryHeap::new(),
+            })
+            .config = config;
+    }
+
+    /// Check if the jobs of the hook can't be executed today.
+    pub fn is_exhausted(&self, hook_id: ScriptId<S>) -> bool {
+        self.budgets
+            .get(&hook_id)
+            .map(|budget| budget.exhausted())
+            .unwrap_or(false)
+    }
+
+    /// Check if new jobs of the hook should be rejected.
+    pub fn rejects(&self, hook_id: ScriptId<S>) -> bool {
+        self.budgets
+            .get(&hook_id)
+            .map(|budget| {
+                budget.exhausted()
+                    && budget.config.action == FreezeAction::Reject
+            })
+            .unwrap_or(false)
+    }
+
+    /// Keep a job aside until the budget of its hook is reset.
+    pub fn hold(&mut self, job: ScheduledJob<S>) {
+        if let Some(budget) = self.budgets.get_mut(&job.hook_id()) {
+            budget.held.push(job);
+        }
+    }
+
+    /// Record how long a job of the hook ran for. Returns the name of the
+    /// hook and its budget if this job exhausted it, so someone can be
+    /// notified.
+    pub fn record(
+        &mut self, hook_id: ScriptId<S>, elapsed: Duration, day: u64,
+    ) -> Option<(String, DailyBudget)> {
+        let budget = self.budgets.get_mut(&hook_id)?;
+
+        // Jobs started yesterday count towards today's budget
+        let already_exhausted = budget.day == day && budget.exhausted();
+        budget.reset(day);
+        budget.used += elapsed;
+
+        if already_exhausted || !budget.exhausted() {
+            return None;
+        }
+
+        metrics::increment(
+            "fisher_daily_budget_exhausted_total",
+            &[("hook", &budget.hook_name)],
+        );
+        println!(
+            "Daily budget of the '{}' hook exhausted after {} seconds",
+            budget.hook_name, budget.used.as_secs(),
+        );
+        Some((budget.hook_name.clone(), budget.config))
+    }
+
+    /// Reset the budgets if a new day started, returning the jobs held by
+    /// them.
+    pub fn tick(&mut self, day: u64) -> Vec<ScheduledJob<S>> {
+        let mut released = Vec::new();
+        for budget in self.budgets.values_mut() {
+            released.extend(budget.reset(day));
+        }
+        released
+    }
+
+    /// Get the number of jobs held by the budgets.
+    pub fn held_jobs(&self) -> usize {
+        self.budgets.values().map(|b| b.held.len()).sum()
+    }
+
+    /// Remove a held job, returning it if it was held by a budget.
+    pub fn take(&mut self, job_id: &str) -> Option<ScheduledJob<S>> {
+        self.budgets
+            .values_mut()
+            .filter_map(|b| scheduled_job::take_job(&mut b.held, job_id))
+            .next()
+    }
+
+    /// Iterate over the jobs held by the budgets, in no particular order.
+    pub fn held(&self) -> impl Iterator<Item = &ScheduledJob<S>> {
+        self.budgets.values().flat_map(|b| b.held.iter())
+    }
+
+    /// Stop tracking the hooks which don't exist anymore, unless they still
+    /// have some jobs held.
+    pub fn cleanup<F: Fn(&ScriptId<S>) -> bool>(&mut self, exists: F) {
+        self.budgets.retain(|hook_id, budget| {
+            exists(hook_id) || !budget.held.is_empty()
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::metrics;
+    use common::serial::Serial;
+    use common::structs::{DailyBudget, FreezeAction};
+
+    use super::super::scheduled_job::ScheduledJob;
+    use super::super::test_utils::*;
+    use super::{day_of, Budgets};
+
+
+    #[test]
+    fn test_day_of() {
+        assert_eq!(day_of(0), 0);
+        assert_eq!(day_of(86399), 0);
+        assert_eq!(day_of(86400), 1);
+    }
+
+    #[test]
+    fn test_budget_exhausted_and_reset() {
+        let repo = Repository::<()>::new();
+        repo.add_script_with_daily_budget("limited", DailyBudget {
+            seconds: 60,
+            action: FreezeAction::Queue,
+        }, |_| Ok(()));
+        repo.add_script("plain", true, |_| Ok(()));
+
+        let id = repo.script_id_of("limited").unwrap();
+        let plain_id = repo.script_id_of("plain").unwrap();
+        let job = |name| ScheduledJob::new(
+            repo.job(name, ()).unwrap(), 0, Serial::zero(),
+        );
+        let mut budgets = Budgets::<Repository<()>>::new();
+
+        budgets.track(&job("limited"), 10);
+        budgets.track(&job("plain"), 10);
+        assert!(!budgets.is_exhausted(id));
+
+        // The hook without a budget is never blocked
+        let secs = Duration::from_secs;
+        assert!(budgets.record(plain_id, secs(1000), 10).is_none());
+        assert!(!budgets.is_exhausted(plain_id));
+
+        // Only the job exhausting the budget notifies it
+        let before = metrics::get(
+            "fisher_daily_budget_exhausted_total", &[("hook", "limited")],
+        );
+        assert!(budgets.record(id, secs(40), 10).is_none());
+        assert!(!budgets.is_exhausted(id));
+        assert!(budgets.record(id, secs(40), 10).is_some());
+        assert!(budgets.is_exhausted(id));
+        assert!(budgets.record(id, secs(40), 10).is_none());
+        assert_eq!(metrics::get(
+            "fisher_daily_budget_exhausted_total", &[("hook", "limited")],
+        ), before + 1);
+
+        // Queued jobs are held instead of rejected
+        assert!(!budgets.rejects(id));
+        budgets.hold(job("limited"));
+        budgets.hold(job("limited"));
+        assert_eq!(budgets.held_jobs(), 2);
+
+        // Nothing changes until the next day
+        assert!(budgets.tick(10).is_empty());
+        assert_eq!(budgets.tick(11).len(), 2);
+        assert_eq!(budgets.held_jobs(), 0);
+        assert!(!budgets.is_exhausted(id));
+    }
+
+    #[test]
+    fn test_budget_rejects() {
+        let repo = Repository::<()>::new();
+        repo.add_script_with_daily_budget("limited", DailyBudget {
+            seconds: 10,
+            action: FreezeAction::Reject,
+        }, |_| Ok(()));
+
+        let id = repo.script_id_of("limited").unwrap();
+        let job = ScheduledJob::new(
+            repo.job("limited", ()).unwrap(), 0, Serial::zero(),
+        );
+        let mut budgets = Budgets::<Repository<()>>::new();
+        budgets.track(&job, 10);
+
+        assert!(!budgets.rejects(id));
+        budgets.record(id, Duration::from_secs(20), 10);
+        assert!(budgets.rejects(id));
+
+        // Jobs finishing the next day start counting again
+        budgets.record(id, Duration::from_secs(5), 11);
+        assert!(!budgets.rejects(id));
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Circuit breakers, stopping the execution of hooks failing repeatedly.
+//!
+//! After a hook fails too many times in a row its circuit opens, and its
+//! jobs are rejected or kept aside until the cooldown ends. Then the circuit
+//! half-opens, and a single trial job is executed: if it succeeds the
+//! circuit closes again, otherwise another cooldown starts.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+use common::prelude::*;
+use common::metrics;
+use common::structs::{CircuitBreaker, FreezeAction};
+
+use super::scheduled_job::{self, ScheduledJob};
+use super::types::ScriptId;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CircuitState {
+    Closed,
+    Open(Instant),
+    HalfOpen { trial_running: bool },
+}
+
+
+#[derive(Debug)]
+struct Circuit<S: ScriptsRepositoryTrait> {
+    hook_name: String,
+    config: CircuitBreaker,
+    failures: u32,
+    state: CircuitState,
+    held: BinaryHeap<ScheduledJob<S>>,
+}
+
+impl<S: ScriptsRepositoryTrait> Circuit<S> {
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open(
+            now + Duration::from_secs(self.config.cooldown),
+        );
+
+        metrics::increment(
+            "fisher_circuit_breaker_trips_total",
+            &[("hook", &self.hook_name)],
+        );
+        println!(
+            "Circuit breaker of the '{}' hook opened after {} failures",
+            self.hook_name, self.failures,
+        );
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Circuits<S: ScriptsRepositoryTrait> {
+    circuits: HashMap<ScriptId<S>, Circuit<S>>,
+}
+
+impl<S: ScriptsRepositoryTrait> Circuits<S> {
+    pub fn new() -> Self {
+        Circuits {
+            circuits: HashMap::new(),
+        }
+    }
+
+    /// Start tracking the hook of the job, if it has a circuit breaker.
+    pub fn track(&mut self, job: &ScheduledJob<S>) {
+        let config = if let Some(config) = job.circuit_breaker() {
+            config
+        } else {
+            return;
+        };
+
+        self.circuits
+            .entry(job.hook_id())
+            .or_insert_with(|| Circuit {
+                hook_name: job.hook_name().to_string(),
+                config,
+                failures: 0,
+                state: CircuitState::Closed,
+                held: BinaryHeap::new(),
+            })
+            .config = config;
+    }
+
+    /// Check if the jobs of the hook can't be executed right now.
+    pub fn is_blocked(&self, hook_id: ScriptId<S>) -> bool {
+        matches!(
+            self.circuits.get(&hook_id).map(|c| c.state),
+            Some(CircuitState::Open(..))
+                | Some(CircuitState::HalfOpen { trial_running: true })
+        )
+    }
+
+    /// Check if new jobs of the hook should be rejected.
+    pub fn rejects(&self, hook_id: ScriptId<S>) -> bool {
+        self.is_blocked(hook_id) && self.circuits
+            .get(&hook_id)
+            .map(|c| c.config.action == FreezeAction::Reject)
+            .unwrap_or(false)
+    }
+
+    /// Keep a job aside until the circuit of its hook lets it run.
+    pub fn hold(&mut self, job: ScheduledJob<S>) {
+        if let Some(circuit) = self.circuits.get_mut(&job.hook_id()) {
+            circuit.held.push(job);
+        }
+    }
+
+    /// Record a job of the hook started running, which is the trial one if
+    /// the circuit is half-open.
+    pub fn started(&mut self, hook_id: ScriptId<S>) {
+        if let Some(circuit) = self.circuits.get_mut(&hook_id) {
+            if let CircuitState::HalfOpen { .. } = circuit.state {
+                circuit.state = CircuitState::HalfOpen { trial_running: true };
+            }
+        }
+    }
+
+    /// Record the result of a job of the hook, returning the held jobs
+    /// which can now be executed.
+    pub fn record(
+        &mut self, hook_id: ScriptId<S>, success: bool, now: Instant,
+    ) -> Vec<ScheduledJob<S>> {
+        let circuit = if let Some(circuit) = self.circuits.get_mut(&hook_id) {
+            circuit
+        } else {
+            return Vec::new();
+        };
+
+        if success {
+            circuit.failures = 0;
+            if circuit.state != CircuitState::Closed {
+                circuit.state = CircuitState::Closed;
+                println!(
+                    "Circuit breaker of the '{}' hook closed",
+                    circuit.hook_name,
+                );
+            }
+
+            circuit.held.drain().collect()
+        } else {
+            circuit.failures += 1;
+            match circuit.state {
+                // A failed trial starts another cooldown right away
+                CircuitState::HalfOpen { .. } => circuit.open(now),
+                CircuitState::Closed
+                    if circuit.failures >= circuit.config.failures =>
+                {
+                    circuit.open(now)
+                }
+                _ => {}
+            }
+
+            Vec::new()
+        }
+    }
+
+    /// Half-open the circuits whose cooldown ended, returning the jobs held
+    /// by them: one of those will be the trial job.
+    pub fn tick(&mut self, now: Instant) -> Vec<ScheduledJob<S>> {
+        let mut released = Vec::new();
+        for circuit in self.circuits.values_mut() {
+            if let CircuitState::Open(until) = circuit.state {
+                if until <= now {
+                    circuit.state = CircuitState::HalfOpen {
+                        trial_running: false,
+                    };
+                    released.extend(circuit.held.drain());
+                }
+            }
+        }
+        released
+    }
+
+    /// Get the number of jobs held by the circuits.
+    pub fn held_jobs(&self) -> usize {
+        self.circuits.values().map(|c| c.held.len()).sum()
+    }
+
+    /// Remove a held job, returning it if it was held by a circuit.
+    pub fn take(&mut self, job_id: &str) -> Option<ScheduledJob<S>> {
+        self.circuits
+            .values_mut()
+            .filter_map(|c| scheduled_job::take_job(&mut c.held, job_id))
+            .next()
+    }
+
+    /// Iterate over the jobs held by the circuits, in no particular order.
+    pub fn held(&self) -> impl Iterator<Item = &ScheduledJob<S>> {
+        self.circuits.values().flat_map(|c| c.held.iter())
+    }
+
+    /// Stop tracking the hooks which don't exist anymore, unless they still
+    /// have some jobs held.
+    pub fn cleanup<F: Fn(&ScriptId<S>) -> bool>(&mut self, exists: F) {
+        self.circuits.retain(|hook_id, circuit| {
+            exists(hook_id) || !circuit.held.is_empty()
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use common::serial::Serial;
+    use common::structs::{CircuitBreaker, FreezeAction};
+
+    use super::super::scheduled_job::ScheduledJob;
+    use super::super::test_utils::*;
+    use super::Circuits;
+
+
+    fn circuits() -> (Repository<()>, Circuits<Repository<()>>) {
+        let repo = Repository::<()>::new();
+        repo.add_script_with_circuit_breaker("breaker", CircuitBreaker {
+            failures: 2,
+            cooldown: 60,
+            action: FreezeAction::Queue,
+        }, |_| Ok(()));
+        repo.add_script("plain", true, |_| Ok(()));
+
+        (repo, Circuits::new())
+    }
+
+
+    #[test]
+    fn test_circuit_opens_and_closes() {
+        let (repo, mut circuits) = circuits();
+        let id = repo.script_id_of("breaker").unwrap();
+        let job = || ScheduledJob::new(
+            repo.job("breaker", ()).unwrap(), 0, Serial::zero(),
+        );
+        let now = Instant::now();
+
+        circuits.track(&job());
+        assert!(!circuits.is_blocked(id));
+
+        // A success resets the count of consecutive failures
+        assert!(circuits.record(id, false, now).is_empty());
+        assert!(circuits.record(id, true, now).is_empty());
+        circuits.record(id, false, now);
+        assert!(!circuits.is_blocked(id));
+        circuits.record(id, false, now);
+        assert!(circuits.is_blocked(id));
+        assert!(!circuits.rejects(id));
+
+        // Jobs are held while the circuit is open
+        circuits.hold(job());
+        circuits.hold(job());
+        assert_eq!(circuits.held_jobs(), 2);
+        assert!(circuits.tick(now + Duration::from_secs(30)).is_empty());
+
+        // After the cooldown only the trial job can run
+        let released = circuits.tick(now + Duration::from_secs(60));
+        assert_eq!(released.len(), 2);
+        assert!(!circuits.is_blocked(id));
+        circuits.started(id);
+        assert!(circuits.is_blocked(id));
+        circuits.hold(job());
+
+        // A failed trial opens the circuit again
+        let later = now + Duration::from_secs(60);
+        circuits.record(id, false, later);
+        assert!(circuits.is_blocked(id));
+        assert!(circuits.tick(later + Duration::from_secs(59)).is_empty());
+        assert_eq!(circuits.tick(later + Duration::from_secs(60)).len(), 1);
+
+        // A successful trial closes the circuit
+        circuits.started(id);
+        circuits.hold(job());
+        assert_eq!(circuits.record(id, true, later).len(), 1);
+        assert!(!circuits.is_blocked(id));
+        assert_eq!(circuits.held_jobs(), 0);
+    }
+
+
+    #[test]
+    fn test_hooks_without_circuit_breaker() {
+        let (repo, mut circuits) = circuits();
+        let id = repo.script_id_of("plain").unwrap();
+
+        circuits.track(&ScheduledJob::new(
+            repo.job("plain", ()).unwrap(), 0, Serial::zero(),
+        ));
+        for _ in 0..10 {
+            circuits.record(id, false, Instant::now());
+        }
+        assert!(!circuits.is_blocked(id));
+        assert!(!circuits.rejects(id));
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tracking of the state of the jobs, from when they're queued to when they
+//! end.
+//!
+//! The jobs are tracked by their ID as long as they're queued or running.
+//! Only the most recent ended jobs are remembered, to avoid keeping every
+//! job ever executed in memory.
+
+use std::collections::{HashMap, VecDeque};
+
+use audit;
+use common::structs::{JobState, JobStatus};
+
+
+/// How many ended jobs are remembered.
+const ENDED_JOBS: usize = 1000;
+
+
+#[derive(Debug)]
+pub struct JobsHistory {
+    jobs: HashMap<String, JobStatus>,
+    ended: VecDeque<String>,
+    capacity: usize,
+}
+
+impl JobsHistory {
+    pub fn new() -> Self {
+        JobsHistory::with_capacity(ENDED_JOBS)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        JobsHistory {
+            jobs: HashMap::new(),
+            ended: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Mark a job as queued. Jobs queued again, like the ones released by
+    /// the circuits, keep their original state.
+    pub fn queued(&mut self, job_id: &str, script: &str, now: u64) {
+        self.jobs.entry(job_id.into()).or_insert_with(|| JobStatus {
+            job_id: job_id.into(),
+            script: script.into(),
+            state: JobState::Queued,
+            exit_code: None,
+            queued_at: now,
+            started_at: None,
+            finished_at: None,
+        });
+    }
+
+    /// Mark a job as running.
+    pub fn started(&mut self, job_id: &str, now: u64) {
+        if let Some(job) = self.jobs.get_mut(job_id) {
+            job.state = JobState::Running;
+            job.started_at = Some(now);
+        }
+    }
+
+    /// Mark a job as ended, forgetting the oldest ended job if too many of
+    /// them are remembered. Jobs dropped before being executed end without
+    /// an exit code.
+    pub fn ended(
+        &mut self, job_id: &str, success: bool, exit_code: Option<i32>,
+        now: u64,
+    ) {
+        let state = if success {
+            JobState::Succeeded
+        } else {
+            JobState::Failed
+        };
+        self.end(job_id, state, exit_code, now);
+    }
+
+    /// Mark a job as cancelled before it was executed.
+    pub fn cancelled(&mut self, job_id: &str, now: u64) {
+        self.end(job_id, JobState::Cancelled, None, now);
+    }
+
+    fn end(
+        &mut self, job_id: &str, state: JobState, exit_code: Option<i32>,
+        now: u64,
+    ) {
+        if let Some(job) = self.jobs.get_mut(job_id) {
+            job.state = state;
+            job.exit_code = exit_code;
+            job.finished_at = Some(now);
+            audit::job_ended(job);
+        } else {
+            return;
+        }
+
+        self.ended.push_back(job_id.into());
+        while self.ended.len() > self.capacity {
+            if let Some(oldest) = self.ended.pop_front() {
+                self.jobs.remove(&oldest);
+            }
+        }
+    }
+
+    /// Get the status of a job.
+    pub fn get(&self, job_id: &str) -> Option<JobStatus> {
+        self.jobs.get(job_id).cloned()
+    }
+
+    /// Get the status of the jobs which ended most recently, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<JobStatus> {
+        self.ended
+            .iter()
+            .rev()
+            .take(limit)
+            .filter_map(|job_id| self.get(job_id))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use common::structs::JobState;
+
+    use super::JobsHistory;
+
+
+    #[test]
+    fn test_jobs_history() {
+        let mut history = JobsHistory::with_capacity(2);
+        assert!(history.get("a").is_none());
+
+        history.queued("a", "test.sh", 10);
+        let status = history.get("a").unwrap();
+        assert_eq!(status.script, "test.sh");
+        assert_eq!(status.state, JobState::Queued);
+        assert_eq!(status.queued_at, 10);
+
+        // Queueing the job again doesn't reset it
+        history.started("a", 20);
+        history.queued("a", "test.sh", 30);
+        let status = history.get("a").unwrap();
+        assert_eq!(status.state, JobState::Running);
+        assert_eq!(status.queued_at, 10);
+        assert_eq!(status.started_at, Some(20));
+
+        history.ended("a", false, Some(1), 40);
+        let status = history.get("a").unwrap();
+        assert_eq!(status.state, JobState::Failed);
+        assert_eq!(status.exit_code, Some(1));
+        assert_eq!(status.finished_at, Some(40));
+
+        // Only the most recent ended jobs are remembered
+        for id in &["b", "c", "d"] {
+            history.queued(id, "test.sh", 50);
+        }
+        history.ended("b", true, Some(0), 60);
+        assert!(history.get("a").is_some());
+        history.ended("c", true, Some(0), 60);
+        assert!(history.get("a").is_none());
+        assert_eq!(history.get("b").unwrap().state, JobState::Succeeded);
+        assert_eq!(history.get("d").unwrap().state, JobState::Queued);
+
+        history.cancelled("d", 70);
+        let status = history.get("d").unwrap();
+        assert_eq!(status.state, JobState::Cancelled);
+        assert_eq!(status.finished_at, Some(70));
+        assert!(history.get("b").is_none());
+
+        // Unknown jobs are ignored
+        history.started("e", 80);
+        history.ended("e", true, None, 80);
+        assert!(history.get("e").is_none());
+    }
+
+    #[test]
+    fn test_recent_jobs() {
+        let mut history = JobsHistory::with_capacity(3);
+        for id in &["a", "b", "c", "d", "e"] {
+            history.queued(id, "test.sh", 10);
+        }
+        history.ended("a", true, Some(0), 20);
+        history.cancelled("c", 30);
+        history.ended("b", false, Some(1), 40);
+
+        // Jobs still queued or running are not included
+        let recent = history.recent(10)
+            .into_iter()
+            .map(|job| job.job_id)
+            .collect::<Vec<_>>();
+        assert_eq!(recent, vec!["b", "c", "a"]);
+
+        history.ended("d", true, Some(0), 50);
+        let recent = history.recent(2)
+            .into_iter()
+            .map(|job| job.job_id)
+            .collect::<Vec<_>>();
+        assert_eq!(recent, vec!["d", "b"]);
+    }
+}
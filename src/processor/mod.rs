@@ -19,11 +19,16 @@
 #![warn(missing_docs)]
 
 mod api;
+mod budgets;
+mod circuits;
+mod history;
 mod scheduled_job;
 mod scheduler;
+mod stuck;
 mod thread;
 mod types;
 #[cfg(test)]
 mod test_utils;
 
 pub use processor::api::{Processor, ProcessorApi};
+pub use processor::scheduler::STATUS_EVENTS_PRIORITY;
@@ -14,9 +14,13 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use common::prelude::*;
 use common::serial::Serial;
+use common::structs::{CircuitBreaker, DailyBudget, ShedPolicy};
 
 use super::types::{Job, JobContext, JobOutput, ScriptId};
 
@@ -26,6 +30,7 @@ pub struct ScheduledJob<S: ScriptsRepositoryTrait> {
     job: Job<S>,
     priority: isize,
     serial: Serial,
+    waiting_since: Option<Instant>,
 }
 
 impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
@@ -34,6 +39,7 @@ impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
             job: job,
             priority: priority,
             serial: serial,
+            waiting_since: None,
         }
     }
 
@@ -44,6 +50,10 @@ impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
             })
     }
 
+    pub fn id(&self) -> &str {
+        self.job.id()
+    }
+
     pub fn hook_id(&self) -> ScriptId<S> {
         self.job.script_id()
     }
@@ -51,8 +61,60 @@ impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
     pub fn hook_name(&self) -> &str {
         self.job.script_name()
     }
+
+    pub fn shed_policy(&self) -> ShedPolicy {
+        self.job.shed_policy()
+    }
+
+    pub fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.job.circuit_breaker()
+    }
+
+    pub fn daily_budget(&self) -> Option<DailyBudget> {
+        self.job.daily_budget()
+    }
+
+    pub fn priority(&self) -> isize {
+        self.priority
+    }
+
+    pub fn serial(&self) -> Serial {
+        self.serial
+    }
+
+    /// Record when the job started waiting for another job of its hook, if
+    /// it wasn't already waiting.
+    pub fn start_waiting(&mut self, now: Instant) {
+        if self.waiting_since.is_none() {
+            self.waiting_since = Some(now);
+        }
+    }
+
+    /// Get how long the job has been waiting for another job of its hook.
+    pub fn waiting_for(&self, now: Instant) -> Option<Duration> {
+        self.waiting_since
+            .map(|since| now.saturating_duration_since(since))
+    }
 }
 
+/// Remove the job with the provided ID from the heap, returning it if it
+/// was there.
+pub fn take_job<S: ScriptsRepositoryTrait>(
+    heap: &mut BinaryHeap<ScheduledJob<S>>, job_id: &str,
+) -> Option<ScheduledJob<S>> {
+    if !heap.iter().any(|job| job.id() == job_id) {
+        return None;
+    }
+
+    let (mut taken, kept): (Vec<_>, Vec<_>) =
+        mem::replace(heap, BinaryHeap::new())
+            .into_iter()
+            .partition(|job| job.id() == job_id);
+    *heap = kept.into_iter().collect();
+    taken.pop()
+}
+
+
 impl<S: ScriptsRepositoryTrait> Ord for ScheduledJob<S> {
     fn cmp(&self, other: &ScheduledJob<S>) -> Ordering {
         let priority_ord = self.priority.cmp(&other.priority);
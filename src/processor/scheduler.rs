@@ -14,20 +14,38 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::{BinaryHeap, HashMap, HashSet};
-use std::time::Instant;
+use std::mem;
+use std::time::{Duration, Instant};
 use std::sync::{mpsc, Arc, RwLock};
 
 use common::prelude::*;
+use common::metrics;
 use common::state::{State, UniqueId};
 use common::serial::Serial;
-use common::structs::HealthDetails;
-
+use common::structs::{
+    HealthDetails, JobStatus, QueueDetails, QueuedJob, ShedPolicy,
+    WorkerDetails, WorkerState,
+};
+use utils;
+
+use super::budgets::{self, Budgets};
+use super::circuits::Circuits;
+use super::history::JobsHistory;
 use super::thread::{ProcessResult, Thread, ThreadCompleter};
-use super::scheduled_job::ScheduledJob;
+use super::scheduled_job::{self, ScheduledJob};
+use super::stuck::StuckJobs;
 use super::types::{Job, JobContext, JobOutput, ScriptId};
 
 
-const STATUS_EVENTS_PRIORITY: isize = 1000;
+/// The priority of the jobs of status hooks.
+pub const STATUS_EVENTS_PRIORITY: isize = 1000;
+
+
+/// Get the current day, used to reset the daily budgets.
+#[inline]
+fn today() -> u64 {
+    budgets::day_of(utils::unix_now())
+}
 
 
 #[cfg(test)]
@@ -51,8 +69,12 @@ impl<S: ScriptsRepositoryTrait> DebugDetails<S> {
 
 
 pub enum SchedulerInput<S: ScriptsRepositoryTrait> {
-    Job(Job<S>, isize),
+    Job(Job<S>, isize, mpsc::Sender<Result<()>>),
     HealthStatus(mpsc::Sender<HealthDetails>),
+    QueueDetails(mpsc::Sender<QueueDetails>),
+    JobStatus(String, mpsc::Sender<Option<JobStatus>>),
+    RecentJobs(usize, mpsc::Sender<Vec<JobStatus>>),
+    CancelJob(String, mpsc::Sender<Option<JobStatus>>),
     ProcessOutput(JobOutput<S>),
 
     Cleanup,
@@ -61,27 +83,43 @@ pub enum SchedulerInput<S: ScriptsRepositoryTrait> {
 
     Lock,
     Unlock,
+    Pause,
+    Resume,
 
     UpdateContext(JobContext<S>),
     SetThreadsCount(u16),
+    SetMaxQueued(Option<usize>),
+    SetStuckJobs(Option<Duration>, bool),
 
     StopSignal,
-    JobEnded(ScriptId<S>, ThreadCompleter),
+    JobEnded(
+        ScriptId<S>, String, bool, Option<i32>, Duration, ThreadCompleter,
+    ),
 }
 
 
 #[derive(Debug)]
 pub struct Scheduler<S: ScriptsRepositoryTrait + 'static> {
     max_threads: u16,
+    max_queued: Option<usize>,
     hooks: Arc<S>,
     jobs_context: Arc<RwLock<Arc<JobContext<S>>>>,
     state: Arc<State>,
 
     locked: bool,
+    paused: bool,
     should_stop: bool,
     queue: BinaryHeap<ScheduledJob<S>>,
     waiting: HashMap<ScriptId<S>, BinaryHeap<ScheduledJob<S>>>,
     threads: HashMap<UniqueId, Thread<S>>,
+    circuits: Circuits<S>,
+    budgets: Budgets<S>,
+    stuck: StuckJobs<S>,
+    history: JobsHistory,
+
+    started_at: Instant,
+    processed_jobs: u64,
+    failed_jobs: u64,
 
     input_send: mpsc::Sender<SchedulerInput<S>>,
     input_recv: mpsc::Receiver<SchedulerInput<S>>,
@@ -108,15 +146,25 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
 
         Scheduler {
             max_threads: max_threads,
+            max_queued: None,
             hooks: hooks,
             jobs_context: Arc::new(RwLock::new(Arc::new(ctx))),
             state: state,
 
             locked: false,
+            paused: false,
             should_stop: false,
             queue: BinaryHeap::new(),
             waiting: waiting,
             threads: HashMap::with_capacity(max_threads as usize),
+            circuits: Circuits::new(),
+            budgets: Budgets::new(),
+            stuck: StuckJobs::new(),
+            history: JobsHistory::new(),
+
+            started_at: Instant::now(),
+            processed_jobs: 0,
+            failed_jobs: 0,
 
             input_send: input_send,
             input_recv: input_recv,
@@ -136,7 +184,27 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
 
         let mut serial = Serial::zero();
         let mut to_schedule = Vec::new();
-        while let Ok(input) = self.input_recv.recv() {
+        loop {
+            // Wake up periodically to half-open the circuits whose cooldown
+            // ended, to reset the budgets and to look for stuck jobs, even if
+            // nothing else is happening
+            let input = match self.input_recv.recv_timeout(
+                Duration::from_secs(1),
+            ) {
+                Ok(input) => Some(input),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            self.release_circuits();
+            self.release_budgets();
+            self.check_stuck_jobs();
+
+            let input = if let Some(input) = input {
+                input
+            } else {
+                continue;
+            };
+
             // Check if the periodic cleanup should be done now
             if self.last_cleanup.elapsed().as_secs() > 30 {
                 self.cleanup_threads();
@@ -146,32 +214,77 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
             }
 
             match input {
-                SchedulerInput::Job(job, priority) => {
-                    self.queue_job(
-                        ScheduledJob::new(job, priority, serial.incr()),
-                    );
-                    self.run_jobs();
+                SchedulerInput::Job(job, priority, accepted) => {
+                    let job = ScheduledJob::new(job, priority, serial.incr());
+
+                    let result = if self.circuits.rejects(job.hook_id()) {
+                        Err(ErrorKind::CircuitOpen(
+                            job.hook_name().to_string(),
+                        ).into())
+                    } else if self.budgets.rejects(job.hook_id()) {
+                        Err(ErrorKind::BudgetExhausted(
+                            job.hook_name().to_string(),
+                        ).into())
+                    } else if self.make_room_for(&job) {
+                        self.queue_job(job);
+                        self.run_jobs();
+                        Ok(())
+                    } else {
+                        Err(ErrorKind::JobRejected(
+                            job.hook_name().to_string(),
+                        ).into())
+                    };
+                    let _ = accepted.send(result);
                 }
 
                 SchedulerInput::HealthStatus(return_to) => {
+                    let workers = self.sorted_threads()
+                        .into_iter()
+                        .enumerate()
+                        .map(|(worker, thread)| WorkerState {
+                            worker,
+                            state: if thread.busy() { "busy" } else { "idle" },
+                        })
+                        .collect::<Vec<_>>();
+
                     // Count the busy threads
-                    let busy_threads = self.threads
-                        .values()
-                        .filter(|thread| thread.busy())
+                    let busy_threads = workers
+                        .iter()
+                        .filter(|worker| worker.state == "busy")
                         .count();
 
-                    let mut queued_jobs = self.queue.len();
-                    for waiting in self.waiting.values() {
-                        queued_jobs += waiting.len();
-                    }
-
                     return_to.send(HealthDetails {
-                        queued_jobs: queued_jobs,
+                        queued_jobs: self.queued_jobs(),
                         busy_threads: busy_threads as u16,
                         max_threads: self.max_threads,
+                        paused: self.paused,
+                        uptime: self.started_at.elapsed().as_secs(),
+                        hooks: self.hooks.iter().count(),
+                        processed_jobs: self.processed_jobs,
+                        failed_jobs: self.failed_jobs,
+                        workers,
+                        build: None,
+                        freezes: None,
                     })?;
                 }
 
+                SchedulerInput::QueueDetails(return_to) => {
+                    let _ = return_to.send(self.queue_details());
+                }
+
+                SchedulerInput::JobStatus(job_id, return_to) => {
+                    let _ = return_to.send(self.history.get(&job_id));
+                }
+
+                SchedulerInput::RecentJobs(limit, return_to) => {
+                    let _ = return_to.send(self.history.recent(limit));
+                }
+
+                SchedulerInput::CancelJob(job_id, return_to) => {
+                    self.cancel_job(&job_id);
+                    let _ = return_to.send(self.history.get(&job_id));
+                }
+
                 SchedulerInput::ProcessOutput(output) => {
                     if let Some(jobs) = self.hooks.jobs_after_output(output) {
                         for job in jobs {
@@ -211,6 +324,21 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
                     self.run_jobs();
                 }
 
+                SchedulerInput::Pause => {
+                    if !self.paused {
+                        println!("Processor paused: no new jobs will start");
+                    }
+                    self.paused = true;
+                }
+
+                SchedulerInput::Resume => {
+                    if self.paused {
+                        println!("Processor resumed");
+                    }
+                    self.paused = false;
+                    self.run_jobs();
+                }
+
                 SchedulerInput::UpdateContext(ctx) => {
                     let mut ptr = self.jobs_context.write().unwrap();
                     *ptr = Arc::new(ctx);
@@ -230,8 +358,50 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
                     }
                 }
 
-                SchedulerInput::JobEnded(hook_id, completer) => {
+                SchedulerInput::SetMaxQueued(max) => {
+                    self.max_queued = max;
+                }
+
+                SchedulerInput::SetStuckJobs(threshold, fail) => {
+                    self.stuck.configure(threshold, fail);
+                }
+
+                SchedulerInput::JobEnded(
+                    hook_id, job_id, success, exit_code, elapsed, completer,
+                ) => {
                     completer.manual_complete();
+                    self.history.ended(
+                        &job_id, success, exit_code, utils::unix_now(),
+                    );
+
+                    self.processed_jobs += 1;
+                    if !success {
+                        self.failed_jobs += 1;
+                    }
+
+                    // Notify when the hook exhausts its daily budget
+                    if let Some((name, budget)) = self.budgets.record(
+                        hook_id, elapsed, today(),
+                    ) {
+                        let jobs = self.hooks.jobs_after_budget_exhausted(
+                            &name, budget,
+                        );
+                        for job in jobs.into_iter().flatten() {
+                            self.queue_job(ScheduledJob::new(
+                                job,
+                                STATUS_EVENTS_PRIORITY,
+                                serial.incr(),
+                            ));
+                        }
+                    }
+
+                    // Jobs held by the circuit are queued again if it closed
+                    let released = self.circuits.record(
+                        hook_id, success, Instant::now(),
+                    );
+                    for job in released {
+                        self.queue_job(job);
+                    }
 
                     // Cleanup threads if there are more than enough
                     if self.threads.len() > self.max_threads as usize {
@@ -283,18 +453,36 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
                 completer.manual_mode();
 
                 let ctx = ctx_lock.read().unwrap().clone();
+                let start = Instant::now();
                 let result = job.execute(&ctx);
+                let elapsed = start.elapsed();
 
-                match result {
+                let (success, exit_code) = match result {
                     Ok(output) => {
+                        let result = (output.success(), output.exit_code());
                         input.send(SchedulerInput::ProcessOutput(output))?;
+                        result
                     }
                     Err(error) => {
                         error.pretty_print();
+                        (false, None)
                     }
+                };
+
+                let labels = &[("hook", job.hook_name())];
+                metrics::observe(
+                    "fisher_job_duration_seconds", labels,
+                    elapsed.as_secs() as f64
+                        + elapsed.subsec_nanos() as f64 / 1e9,
+                );
+                if !success {
+                    metrics::increment("fisher_job_failures_total", labels);
                 }
 
-                input.send(SchedulerInput::JobEnded(job.hook_id(), completer))?;
+                input.send(SchedulerInput::JobEnded(
+                    job.hook_id(), job.id().to_string(), success, exit_code,
+                    elapsed, completer,
+                ))?;
 
                 Ok(())
             },
@@ -358,6 +546,10 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
             let _ = self.waiting.remove(&hook_id);
         }
 
+        let hooks = &self.hooks;
+        self.circuits.cleanup(|hook_id| hooks.id_exists(hook_id));
+        self.budgets.cleanup(|hook_id| hooks.id_exists(hook_id));
+
         // Add new hooks
         for hook in self.hooks.iter() {
             if hook.can_be_parallel() {
@@ -372,7 +564,7 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
     }
 
     fn run_jobs(&mut self) {
-        if self.locked {
+        if self.locked || self.paused {
             return;
         }
 
@@ -381,11 +573,18 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
         // all of them are processed
         'main: loop {
             if let Some(mut job) = self.get_job() {
+                let hook_id = job.hook_id();
+                let job_id = job.id().to_string();
+
                 // Try to run the job in a thread
                 for thread in self.threads.values_mut() {
                     match thread.process(job) {
                         ProcessResult::Rejected(j) => job = j,
-                        ProcessResult::Executing => continue 'main,
+                        ProcessResult::Executing => {
+                            self.circuits.started(hook_id);
+                            self.history.started(&job_id, utils::unix_now());
+                            continue 'main;
+                        }
                     }
                 }
                 self.queue_job(job);
@@ -394,13 +593,210 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
         }
     }
 
-    fn queue_job(&mut self, job: ScheduledJob<S>) {
+    fn queued_jobs(&self) -> usize {
+        let mut queued_jobs = self.queue.len();
+        for waiting in self.waiting.values() {
+            queued_jobs += waiting.len();
+        }
+        queued_jobs + self.circuits.held_jobs() + self.budgets.held_jobs()
+    }
+
+    fn queue_details(&self) -> QueueDetails {
+        let mut jobs = self.queue
+            .iter()
+            .map(|job| (job, "queued"))
+            .chain(self.waiting.values().flatten().map(|job| (job, "waiting")))
+            .chain(self.circuits.held().map(|job| (job, "held")))
+            .chain(self.budgets.held().map(|job| (job, "held")))
+            .collect::<Vec<_>>();
+        // The highest-priority jobs are the greatest ones
+        jobs.sort_by(|a, b| b.0.cmp(a.0));
+
+        let now = utils::unix_now();
+        let jobs = jobs
+            .into_iter()
+            .map(|(job, reason)| QueuedJob {
+                job_id: job.id().into(),
+                script: job.hook_name().into(),
+                priority: job.priority(),
+                queued_at: self.history
+                    .get(job.id())
+                    .map(|status| status.queued_at)
+                    .unwrap_or(now),
+                reason,
+            })
+            .collect();
+
+        let workers = self.sorted_threads()
+            .into_iter()
+            .enumerate()
+            .map(|(worker, thread)| {
+                let current = thread.current_job();
+                WorkerDetails {
+                    worker,
+                    job_id: current.map(|(id, _)| id.into()),
+                    script: current.map(|(_, hook)| hook.into()),
+                    started_at: current
+                        .and_then(|(id, _)| self.history.get(id))
+                        .and_then(|status| status.started_at),
+                }
+            })
+            .collect();
+
+        QueueDetails { jobs, workers }
+    }
+
+    /// Get the threads in the order they were started.
+    fn sorted_threads(&self) -> Vec<&Thread<S>> {
+        let mut threads = self.threads.values().collect::<Vec<_>>();
+        threads.sort_by(|a, b| a.id().partial_cmp(&b.id()).unwrap());
+        threads
+    }
+
+    /// Remove a job which is still waiting to be executed.
+    fn cancel_job(&mut self, job_id: &str) {
+        let mut cancelled = scheduled_job::take_job(&mut self.queue, job_id);
+        for waiting in self.waiting.values_mut() {
+            if cancelled.is_some() {
+                break;
+            }
+            cancelled = scheduled_job::take_job(waiting, job_id);
+        }
+        let cancelled = cancelled
+            .or_else(|| self.circuits.take(job_id))
+            .or_else(|| self.budgets.take(job_id));
+
+        if let Some(job) = cancelled {
+            println!(
+                "Job {} of the '{}' script cancelled", job_id, job.hook_name(),
+            );
+            self.history.cancelled(job_id, utils::unix_now());
+        }
+    }
+
+    /// Queue again the jobs held by the circuits whose cooldown ended.
+    fn release_circuits(&mut self) {
+        let released = self.circuits.tick(Instant::now());
+        if released.is_empty() {
+            return;
+        }
+
+        for job in released {
+            self.queue_job(job);
+        }
+        self.run_jobs();
+    }
+
+    /// Queue again the jobs held by the budgets reset for the new day.
+    fn release_budgets(&mut self) {
+        let released = self.budgets.tick(today());
+        if released.is_empty() {
+            return;
+        }
+
+        for job in released {
+            self.queue_job(job);
+        }
+        self.run_jobs();
+    }
+
+    /// Report the jobs waiting for too long for the running job of their
+    /// hook, failing them if configured to do so.
+    fn check_stuck_jobs(&mut self) {
+        let threads = &self.threads;
+        let failed = self.stuck.check(
+            &mut self.waiting,
+            |hook_id| {
+                threads
+                    .values()
+                    .find(|thread| thread.currently_running() == Some(hook_id))
+                    .and_then(|thread| thread.running_for())
+            },
+            Instant::now(),
+        );
+        for job in failed {
+            self.history.ended(job.id(), false, None, utils::unix_now());
+        }
+    }
+
+    /// Ensure there is room in the queue for a new job, applying the
+    /// shedding policy of its hook if the queue is full. Returns false if
+    /// the new job should be rejected instead.
+    fn make_room_for(&mut self, job: &ScheduledJob<S>) -> bool {
+        match self.max_queued {
+            Some(max) if self.queued_jobs() >= max => {}
+            _ => return true,
+        }
+
+        // If there is no job to drop the new one is rejected, even if the
+        // hook would prefer dropping the oldest one
+        let shed = match job.shed_policy() {
+            ShedPolicy::DropOldest if self.drop_oldest(job.hook_id()) => {
+                ShedPolicy::DropOldest
+            }
+            _ => ShedPolicy::RejectNew,
+        };
+
+        metrics::increment(
+            "fisher_jobs_shed_total",
+            &[("hook", job.hook_name()), ("action", shed.name())],
+        );
+
+        shed == ShedPolicy::DropOldest
+    }
+
+    /// Remove the oldest queued job of a hook, returning if a job was found.
+    fn drop_oldest(&mut self, hook_id: ScriptId<S>) -> bool {
+        fn oldest<S: ScriptsRepositoryTrait>(
+            heap: &BinaryHeap<ScheduledJob<S>>, hook_id: ScriptId<S>,
+        ) -> Option<Serial> {
+            heap.iter()
+                .filter(|job| job.hook_id() == hook_id)
+                .map(|job| job.serial())
+                .min()
+        }
+
+        let in_queue = oldest(&self.queue, hook_id);
+        let in_waiting = self.waiting
+            .get(&hook_id)
+            .and_then(|waiting| oldest(waiting, hook_id));
+
+        let (heap, serial) = match (in_queue, in_waiting) {
+            (Some(queue), Some(waiting)) if waiting < queue => {
+                (self.waiting.get_mut(&hook_id), waiting)
+            }
+            (Some(queue), _) => (Some(&mut self.queue), queue),
+            (None, Some(waiting)) => (self.waiting.get_mut(&hook_id), waiting),
+            (None, None) => return false,
+        };
+
+        if let Some(heap) = heap {
+            let (dropped, kept): (Vec<_>, Vec<_>) =
+                mem::replace(heap, BinaryHeap::new())
+                    .into_iter()
+                    .partition(|job| job.serial() == serial);
+            *heap = kept.into_iter().collect();
+
+            for job in dropped {
+                self.history.ended(job.id(), false, None, utils::unix_now());
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn queue_job(&mut self, mut job: ScheduledJob<S>) {
         let hook_id = job.hook_id();
+        self.history.queued(job.id(), job.hook_name(), utils::unix_now());
+        self.circuits.track(&job);
+        self.budgets.track(&job, today());
 
         // Put the job in waiting if it can't be parallel and
         // it's already running
         if self.is_running(hook_id) {
             if let Some(waiting) = self.waiting.get_mut(&hook_id) {
+                job.start_waiting(Instant::now());
                 waiting.push(job);
                 return;
             }
@@ -411,13 +807,26 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
 
     fn get_job(&mut self) -> Option<ScheduledJob<S>> {
         loop {
-            if let Some(job) = self.queue.pop() {
+            if let Some(mut job) = self.queue.pop() {
                 let hook_id = job.hook_id();
 
+                // Keep the job aside if the circuit of the hook is open
+                if self.circuits.is_blocked(hook_id) {
+                    self.circuits.hold(job);
+                    continue;
+                }
+
+                // Keep the job aside if the hook used its whole budget
+                if self.budgets.is_exhausted(hook_id) {
+                    self.budgets.hold(job);
+                    continue;
+                }
+
                 // Put the job in waiting if it can't be parallel and
                 // it's already running
                 if self.is_running(hook_id) {
                     if let Some(waiting) = self.waiting.get_mut(&hook_id) {
+                        job.start_waiting(Instant::now());
                         waiting.push(job);
                         continue;
                     }
@@ -446,12 +855,19 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
 mod tests {
     use std::collections::VecDeque;
     use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
 
     use common::prelude::*;
+    use common::metrics;
     use common::state::State;
+    use common::structs::{
+        CircuitBreaker, DailyBudget, FreezeAction, JobState, ShedPolicy,
+        WorkerState,
+    };
 
     use super::super::test_utils::*;
-    use super::super::Processor;
+    use super::super::{Processor, ProcessorApi};
 
 
     #[test]
@@ -574,6 +990,391 @@ mod tests {
         assert_eq!(output.len(), 10);
     }
 
+    #[test]
+    fn test_queue_shedding() {
+        test_wrapper(|| {
+            let repo = Repository::<char>::new();
+
+            let (append_send, append_recv) = mpsc::channel();
+            let append_send_2 = append_send.clone();
+            repo.add_script_with_policy(
+                "shed-idempotent", true, ShedPolicy::DropOldest,
+                move |arg| {
+                    append_send.send(arg)?;
+                    Ok(())
+                },
+            );
+            repo.add_script_with_policy(
+                "shed-critical", true, ShedPolicy::RejectNew,
+                move |arg| {
+                    append_send_2.send(arg)?;
+                    Ok(())
+                },
+            );
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                1,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+
+            api.set_max_queued(Some(3))?;
+            api.lock()?;
+
+            // Fill the queue
+            api.queue(repo.job("shed-critical", 'a').unwrap(), 0)?;
+            api.queue(repo.job("shed-idempotent", '1').unwrap(), 0)?;
+            api.queue(repo.job("shed-idempotent", '2').unwrap(), 0)?;
+
+            // The oldest idempotent job is dropped to make room
+            api.queue(repo.job("shed-idempotent", '3').unwrap(), 0)?;
+
+            // New critical jobs are rejected
+            let result = api.queue(repo.job("shed-critical", 'b').unwrap(), 0);
+            if let Err(Error(ErrorKind::JobRejected(name), _)) = result {
+                assert_eq!(name.as_str(), "shed-critical");
+            } else {
+                panic!("the job was not rejected: {:?}", result);
+            }
+
+            api.unlock()?;
+            processor.stop()?;
+
+            let mut output = String::new();
+            while let Ok(part) = append_recv.try_recv() {
+                output.push(part);
+            }
+            assert_eq!(output.as_str(), "a23");
+
+            assert_eq!(metrics::get("fisher_jobs_shed_total", &[
+                ("hook", "shed-idempotent"), ("action", "drop-oldest"),
+            ]), 1);
+            assert_eq!(metrics::get("fisher_jobs_shed_total", &[
+                ("hook", "shed-critical"), ("action", "reject-new"),
+            ]), 1);
+
+            Ok(())
+        });
+    }
+
+    fn wait_idle<S: ScriptsRepositoryTrait>(
+        api: &ProcessorApi<S>,
+    ) -> Result<()> {
+        while api.health_details()?.busy_threads > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        test_wrapper(|| {
+            let repo = Repository::<char>::new();
+
+            let (output_send, output_recv) = mpsc::channel();
+            let output_send_2 = output_send.clone();
+            repo.add_script_with_circuit_breaker("reject", CircuitBreaker {
+                failures: 2,
+                cooldown: 3600,
+                action: FreezeAction::Reject,
+            }, move |arg| {
+                output_send.send(arg)?;
+                Err(ErrorKind::BrokenChannel.into())
+            });
+            repo.add_script_with_circuit_breaker("queue", CircuitBreaker {
+                failures: 1,
+                cooldown: 1,
+                action: FreezeAction::Queue,
+            }, move |arg| {
+                output_send_2.send(arg)?;
+                if arg == 'a' {
+                    Err(ErrorKind::BrokenChannel.into())
+                } else {
+                    Ok(())
+                }
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                1,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+
+            // The circuit opens after two failures, rejecting new jobs
+            for arg in &['1', '2'] {
+                api.queue(repo.job("reject", *arg).unwrap(), 0)?;
+                assert_eq!(output_recv.recv()?, *arg);
+                wait_idle(&api)?;
+            }
+            let result = api.queue(repo.job("reject", '3').unwrap(), 0);
+            if let Err(Error(ErrorKind::CircuitOpen(name), _)) = result {
+                assert_eq!(name.as_str(), "reject");
+            } else {
+                panic!("the job was not rejected: {:?}", result);
+            }
+            assert_eq!(metrics::get(
+                "fisher_circuit_breaker_trips_total", &[("hook", "reject")],
+            ), 1);
+
+            // Jobs are kept until the cooldown ends, and then executed after
+            // the trial one succeeds
+            api.queue(repo.job("queue", 'a').unwrap(), 0)?;
+            assert_eq!(output_recv.recv()?, 'a');
+            wait_idle(&api)?;
+            api.queue(repo.job("queue", 'b').unwrap(), 0)?;
+            api.queue(repo.job("queue", 'c').unwrap(), 0)?;
+            assert_eq!(api.health_details()?.queued_jobs, 2);
+
+            let timeout = Duration::from_secs(10);
+            assert_eq!(output_recv.recv_timeout(timeout).unwrap(), 'b');
+            assert_eq!(output_recv.recv_timeout(timeout).unwrap(), 'c');
+
+            processor.stop()?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_daily_budget() {
+        test_wrapper(|| {
+            let repo = Repository::<char>::new();
+
+            let (output_send, output_recv) = mpsc::channel();
+            let output_send_2 = output_send.clone();
+            repo.add_script_with_daily_budget("reject", DailyBudget {
+                seconds: 1,
+                action: FreezeAction::Reject,
+            }, move |arg| {
+                thread::sleep(Duration::from_millis(1100));
+                output_send.send(arg)?;
+                Ok(())
+            });
+            repo.add_script_with_daily_budget("queue", DailyBudget {
+                seconds: 1,
+                action: FreezeAction::Queue,
+            }, move |arg| {
+                thread::sleep(Duration::from_millis(1100));
+                output_send_2.send(arg)?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                2,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+
+            // Both jobs run past the budget of their hooks
+            api.queue(repo.job("reject", 'a').unwrap(), 0)?;
+            api.queue(repo.job("queue", 'b').unwrap(), 0)?;
+            wait_idle(&api)?;
+            let mut executed = vec![output_recv.recv()?, output_recv.recv()?];
+            executed.sort();
+            assert_eq!(executed, vec!['a', 'b']);
+            assert_eq!(metrics::get(
+                "fisher_daily_budget_exhausted_total", &[("hook", "reject")],
+            ), 1);
+
+            // New jobs are rejected or kept until the next day
+            let result = api.queue(repo.job("reject", 'c').unwrap(), 0);
+            if let Err(Error(ErrorKind::BudgetExhausted(name), _)) = result {
+                assert_eq!(name.as_str(), "reject");
+            } else {
+                panic!("the job was not rejected: {:?}", result);
+            }
+            api.queue(repo.job("queue", 'd').unwrap(), 0)?;
+            assert_eq!(api.health_details()?.queued_jobs, 1);
+            assert!(output_recv.try_recv().is_err());
+
+            processor.stop()?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_details() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+
+            repo.add_script("noop", true, |_| Ok(()));
+            repo.add_script("wait", false, |recv| {
+                if let Some(recv) = recv {
+                    recv.lock()?.recv()?;
+                }
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                1,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+
+            let (waiting_send, waiting_recv) = mpsc::channel();
+            let running = repo
+                .job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                .unwrap();
+            let running_id = running.id().to_string();
+            api.queue(running, 0)?;
+
+            // The second job of the non-parallel script waits for the first
+            let mut ids = Vec::new();
+            for &(name, priority) in &[("noop", 0), ("noop", 5), ("wait", 0)] {
+                let job = repo.job(name, None).unwrap();
+                ids.push(job.id().to_string());
+                api.queue(job, priority)?;
+            }
+
+            let details = api.queue_details()?;
+            let jobs = details.jobs
+                .iter()
+                .map(|job| (job.job_id.as_str(), job.priority, job.reason))
+                .collect::<Vec<_>>();
+            assert_eq!(jobs, vec![
+                (ids[1].as_str(), 5, "queued"),
+                (ids[0].as_str(), 0, "queued"),
+                (ids[2].as_str(), 0, "waiting"),
+            ]);
+
+            assert_eq!(details.workers.len(), 1);
+            assert_eq!(details.workers[0].job_id, Some(running_id));
+            assert_eq!(details.workers[0].script, Some("wait".into()));
+            assert!(details.workers[0].started_at.is_some());
+
+            waiting_send.send(())?;
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_cancel_job() {
+        test_wrapper(|| {
+            let repo = Repository::<char>::new();
+
+            let (append_send, append_recv) = mpsc::channel();
+            repo.add_script("append", true, move |arg| {
+                append_send.send(arg)?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                1,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+            assert!(api.cancel_job("unknown")?.is_none());
+
+            api.lock()?;
+            let mut ids = Vec::new();
+            for arg in &['a', 'b', 'c'] {
+                let job = repo.job("append", *arg).unwrap();
+                ids.push(job.id().to_string());
+                api.queue(job, 0)?;
+            }
+
+            // Queued jobs are removed from the queue
+            let status = api.cancel_job(&ids[1])?.unwrap();
+            assert_eq!(status.state, JobState::Cancelled);
+            assert_eq!(api.health_details()?.queued_jobs, 2);
+
+            api.unlock()?;
+            while api.health_details()?.queued_jobs > 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+            wait_idle(&api)?;
+
+            // Jobs which already started can't be cancelled
+            let status = api.cancel_job(&ids[0])?.unwrap();
+            assert_eq!(status.state, JobState::Succeeded);
+
+            processor.stop()?;
+
+            let mut output = String::new();
+            while let Ok(part) = append_recv.try_recv() {
+                output.push(part);
+            }
+            assert_eq!(output.as_str(), "ac");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_job_status() {
+        test_wrapper(|| {
+            let repo = Repository::<bool>::new();
+            repo.add_script("status", true, |fail| if fail {
+                Err(ErrorKind::BrokenChannel.into())
+            } else {
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                1,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+            assert!(api.job_status("unknown")?.is_none());
+
+            // Jobs are tracked from when they're queued
+            api.lock()?;
+            let success = repo.job("status", false).unwrap();
+            let failure = repo.job("status", true).unwrap();
+            let (success_id, failure_id) = (
+                success.id().to_string(), failure.id().to_string(),
+            );
+            api.queue(success, 0)?;
+            api.queue(failure, 0)?;
+
+            let status = api.job_status(&success_id)?.unwrap();
+            assert_eq!(status.script, "status");
+            assert_eq!(status.state, JobState::Queued);
+            assert!(status.started_at.is_none());
+
+            // Ended jobs are still tracked
+            api.unlock()?;
+            while api.health_details()?.queued_jobs > 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+            wait_idle(&api)?;
+
+            let status = api.job_status(&success_id)?.unwrap();
+            assert_eq!(status.state, JobState::Succeeded);
+            assert_eq!(status.exit_code, Some(0));
+            assert!(status.started_at.is_some());
+            assert!(status.finished_at.is_some());
+
+            let status = api.job_status(&failure_id)?.unwrap();
+            assert_eq!(status.state, JobState::Failed);
+            assert_eq!(status.exit_code, None);
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_non_parallel_processing() {
         test_wrapper(|| {
@@ -647,6 +1448,9 @@ mod tests {
                 recv.lock()?.recv()?;
                 Ok(())
             });
+            repo.add_script("fail", true, |_| {
+                Err(ErrorKind::BrokenChannel.into())
+            });
 
             let repo = Arc::new(repo);
             let processor = Processor::new(
@@ -677,10 +1481,81 @@ mod tests {
             assert_eq!(status.queued_jobs, 10);
             assert_eq!(status.busy_threads, 1);
             assert_eq!(status.max_threads, 1);
+            assert_eq!(status.hooks, 3);
+            assert_eq!(status.processed_jobs, 0);
+            assert_eq!(status.failed_jobs, 0);
+            assert_eq!(status.workers, vec![WorkerState {
+                worker: 0,
+                state: "busy",
+            }]);
 
             // Create the file the first job is waiting for
             waiting_send.send(())?;
 
+            // Wait for all the jobs to be executed, and queue a failing one
+            while api.health_details()?.queued_jobs > 0 {}
+            api.queue(repo.job("fail", None).unwrap(), 0)?;
+
+            let mut status = api.health_details()?;
+            while status.processed_jobs < 12 {
+                status = api.health_details()?;
+            }
+            assert_eq!(status.failed_jobs, 1);
+            assert_eq!(status.workers, vec![WorkerState {
+                worker: 0,
+                state: "idle",
+            }]);
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_pause_processor() {
+        test_wrapper(|| {
+            let repo = Repository::<char>::new();
+
+            let (append_send, append_recv) = mpsc::channel();
+            repo.add_script("append", true, move |arg| {
+                append_send.send(arg)?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor = Processor::new(
+                2,
+                repo.clone(),
+                (),
+                Arc::new(State::new()),
+            )?;
+            let api = processor.api();
+
+            api.pause()?;
+            for chr in &['a', 'b', 'c'] {
+                api.queue(repo.job("append", *chr).unwrap(), 0)?;
+            }
+
+            // Unlocking the processor after a reload doesn't resume it
+            api.lock()?;
+            api.unlock()?;
+
+            let status = api.health_details()?;
+            assert!(status.paused);
+            assert_eq!(status.queued_jobs, 3);
+            assert!(append_recv.try_recv().is_err());
+
+            api.resume()?;
+            let mut output = Vec::new();
+            for _ in 0..3 {
+                output.push(append_recv.recv()?);
+            }
+            output.sort();
+            assert_eq!(output, vec!['a', 'b', 'c']);
+            assert!(!api.health_details()?.paused);
+
             processor.stop()?;
 
             Ok(())
@@ -0,0 +1,192 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of the jobs stuck waiting for other jobs.
+//!
+//! The jobs of hooks which can't run in parallel wait for the running job of
+//! the same hook to end. Running jobs never wait for other jobs, so the waits
+//! can't form cycles: a job is stuck either because the job it waits for
+//! takes too long, or because nothing is running anymore. Both cases are
+//! logged with what the jobs are waiting for, and the stuck jobs can be
+//! failed instead of waiting forever.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use common::prelude::*;
+use common::metrics;
+
+use super::scheduled_job::ScheduledJob;
+use super::types::ScriptId;
+
+
+#[derive(Debug)]
+pub struct StuckJobs<S: ScriptsRepositoryTrait> {
+    threshold: Option<Duration>,
+    fail: bool,
+    reported: HashSet<ScriptId<S>>,
+}
+
+impl<S: ScriptsRepositoryTrait> StuckJobs<S> {
+    pub fn new() -> Self {
+        StuckJobs {
+            threshold: None,
+            fail: false,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Consider stuck the jobs waiting for longer than the threshold, and
+    /// fail them if `fail` is true. Nothing is checked without a threshold.
+    pub fn configure(&mut self, threshold: Option<Duration>, fail: bool) {
+        self.threshold = threshold;
+        self.fail = fail;
+    }
+
+    /// Check the jobs waiting for the running job of their hook, reporting
+    /// the hooks with stuck jobs once. `running_for` returns how long the
+    /// job of a hook has been running, if there is one. Returns the stuck
+    /// jobs which were failed.
+    pub fn check<F>(
+        &mut self,
+        waiting: &mut HashMap<ScriptId<S>, BinaryHeap<ScheduledJob<S>>>,
+        running_for: F,
+        now: Instant,
+    ) -> Vec<ScheduledJob<S>>
+    where
+        F: Fn(ScriptId<S>) -> Option<Duration>,
+    {
+        let threshold = if let Some(threshold) = self.threshold {
+            threshold
+        } else {
+            return Vec::new();
+        };
+        let is_stuck = |job: &ScheduledJob<S>| {
+            job.waiting_for(now).map(|w| w >= threshold).unwrap_or(false)
+        };
+
+        let mut failed = Vec::new();
+        for (hook_id, jobs) in waiting.iter_mut() {
+            let stuck: Vec<_> = jobs
+                .iter()
+                .filter_map(|job| job.waiting_for(now))
+                .filter(|waited| *waited >= threshold)
+                .collect();
+            if stuck.is_empty() {
+                self.reported.remove(hook_id);
+                continue;
+            }
+            if !self.fail && !self.reported.insert(*hook_id) {
+                continue;
+            }
+
+            let name = jobs.peek().unwrap().hook_name().to_string();
+            let blocker = match running_for(*hook_id) {
+                Some(running) => format!(
+                    "the job of {} running for {} seconds",
+                    name, running.as_secs(),
+                ),
+                None => "no running job".into(),
+            };
+            println!(
+                "Possible deadlock: {} jobs of {} waiting for up to {} \
+                 seconds -> {}",
+                stuck.len(), name, stuck.iter().max().unwrap().as_secs(),
+                blocker,
+            );
+
+            let action = if self.fail { "failed" } else { "reported" };
+            metrics::add(
+                "fisher_jobs_stuck_total",
+                &[("hook", &name), ("action", action)],
+                stuck.len() as u64,
+            );
+
+            if self.fail {
+                println!("Failing {} stuck jobs of {}", stuck.len(), name);
+                let (failing, kept): (Vec<_>, Vec<_>) =
+                    mem::replace(jobs, BinaryHeap::new())
+                        .into_iter()
+                        .partition(|job| is_stuck(job));
+                *jobs = kept.into_iter().collect();
+                failed.extend(failing);
+            }
+        }
+
+        failed
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BinaryHeap, HashMap};
+    use std::time::{Duration, Instant};
+
+    use common::metrics;
+    use common::serial::Serial;
+
+    use super::super::scheduled_job::ScheduledJob;
+    use super::super::test_utils::*;
+    use super::StuckJobs;
+
+
+    #[test]
+    fn test_stuck_jobs() {
+        let repo = Repository::<()>::new();
+        repo.add_script("wait", false, |_| Ok(()));
+        let id = repo.script_id_of("wait").unwrap();
+
+        let now = Instant::now();
+        let mut serial = Serial::zero();
+        let mut job = |waiting: u64| {
+            let mut job = ScheduledJob::<Repository<()>>::new(
+                repo.job("wait", ()).unwrap(), 0, serial.incr(),
+            );
+            job.start_waiting(now - Duration::from_secs(waiting));
+            job
+        };
+
+        let mut waiting = HashMap::new();
+        waiting.insert(id, vec![job(5), job(20), job(30)]
+            .into_iter()
+            .collect::<BinaryHeap<_>>());
+        let running = |_| Some(Duration::from_secs(40));
+        let reported = || metrics::get(
+            "fisher_jobs_stuck_total",
+            &[("hook", "wait"), ("action", "reported")],
+        );
+
+        // Nothing is checked without a threshold
+        let mut stuck = StuckJobs::new();
+        let before = reported();
+        assert_eq!(stuck.check(&mut waiting, running, now).len(), 0);
+        assert_eq!(reported(), before);
+
+        // The stuck jobs are reported only once
+        stuck.configure(Some(Duration::from_secs(10)), false);
+        assert_eq!(stuck.check(&mut waiting, running, now).len(), 0);
+        assert_eq!(stuck.check(&mut waiting, running, now).len(), 0);
+        assert_eq!(reported(), before + 2);
+        assert_eq!(waiting[&id].len(), 3);
+
+        // Only the stuck jobs are failed
+        stuck.configure(Some(Duration::from_secs(10)), true);
+        assert_eq!(stuck.check(&mut waiting, |_| None, now).len(), 2);
+        assert_eq!(waiting[&id].len(), 1);
+        assert_eq!(stuck.check(&mut waiting, |_| None, now).len(), 0);
+    }
+}
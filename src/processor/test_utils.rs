@@ -19,12 +19,16 @@ use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use common::prelude::*;
+use common::structs::{CircuitBreaker, DailyBudget, ShedPolicy};
 
 
 pub struct Script<I: Send + Sync + Debug + Clone> {
     id: usize,
     name: String,
     can_be_parallel: bool,
+    shed_policy: ShedPolicy,
+    circuit_breaker: Option<CircuitBreaker>,
+    daily_budget: Option<DailyBudget>,
     func: Arc<Mutex<Box<Fn(I) -> Result<()> + Send>>>,
 }
 
@@ -55,6 +59,7 @@ impl<I: Send + Sync + Debug + Clone> Debug for Script<I> {
 
 #[derive(Debug, Clone)]
 pub struct Job<I: Send + Sync + Debug + Clone> {
+    id: String,
     script: Arc<Script<I>>,
     args: I,
 }
@@ -67,6 +72,10 @@ impl<I: Send + Sync + Debug + Clone> JobTrait<Script<I>> for Job<I> {
         (self.script.func.lock().unwrap())(self.args.clone())
     }
 
+    fn id(&self) -> &str {
+        &self.id
+    }
+
     fn script_id(&self) -> usize {
         self.script.id
     }
@@ -74,11 +83,36 @@ impl<I: Send + Sync + Debug + Clone> JobTrait<Script<I>> for Job<I> {
     fn script_name(&self) -> &str {
         &self.script.name
     }
+
+    fn shed_policy(&self) -> ShedPolicy {
+        self.script.shed_policy
+    }
+
+    fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.script.circuit_breaker
+    }
+
+    fn daily_budget(&self) -> Option<DailyBudget> {
+        self.script.daily_budget
+    }
+}
+
+
+// Jobs failing with an error are the only unsuccessful ones
+impl JobOutputTrait for () {
+    fn success(&self) -> bool {
+        true
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        Some(0)
+    }
 }
 
 
 pub struct Repository<I: Send + Sync + Debug + Clone> {
     last_id: AtomicUsize,
+    last_job_id: AtomicUsize,
     scripts: RwLock<HashMap<String, Arc<Script<I>>>>,
     ids: RwLock<Vec<usize>>,
 }
@@ -87,6 +121,7 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
     pub fn new() -> Self {
         Repository {
             last_id: AtomicUsize::new(0),
+            last_job_id: AtomicUsize::new(0),
             ids: RwLock::new(Vec::new()),
             scripts: RwLock::new(HashMap::new()),
         }
@@ -97,6 +132,55 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
         name: &str,
         parallel: bool,
         func: F,
+    ) {
+        self.add_script_with_policy(name, parallel, ShedPolicy::RejectNew, func);
+    }
+
+    pub fn add_script_with_policy<F: Fn(I) -> Result<()> + 'static + Send>(
+        &self,
+        name: &str,
+        parallel: bool,
+        shed_policy: ShedPolicy,
+        func: F,
+    ) {
+        self.insert_script(name, parallel, shed_policy, None, None, func);
+    }
+
+    pub fn add_script_with_circuit_breaker<
+        F: Fn(I) -> Result<()> + 'static + Send,
+    >(
+        &self,
+        name: &str,
+        circuit_breaker: CircuitBreaker,
+        func: F,
+    ) {
+        self.insert_script(
+            name, true, ShedPolicy::RejectNew, Some(circuit_breaker), None,
+            func,
+        );
+    }
+
+    pub fn add_script_with_daily_budget<
+        F: Fn(I) -> Result<()> + 'static + Send,
+    >(
+        &self,
+        name: &str,
+        daily_budget: DailyBudget,
+        func: F,
+    ) {
+        self.insert_script(
+            name, true, ShedPolicy::RejectNew, None, Some(daily_budget), func,
+        );
+    }
+
+    fn insert_script<F: Fn(I) -> Result<()> + 'static + Send>(
+        &self,
+        name: &str,
+        parallel: bool,
+        shed_policy: ShedPolicy,
+        circuit_breaker: Option<CircuitBreaker>,
+        daily_budget: Option<DailyBudget>,
+        func: F,
     ) {
         self.ids
             .write()
@@ -108,6 +192,9 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
                 id: self.last_id.fetch_add(1, Ordering::SeqCst),
                 name: name.to_string(),
                 can_be_parallel: parallel,
+                shed_policy,
+                circuit_breaker,
+                daily_budget,
                 func: Arc::new(Mutex::new(Box::new(func))),
             }),
         );
@@ -119,7 +206,13 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
             .unwrap()
             .get(name)
             .cloned()
-            .map(|script| Job { script, args })
+            .map(|script| Job {
+                id: format!(
+                    "job-{}", self.last_job_id.fetch_add(1, Ordering::SeqCst),
+                ),
+                script,
+                args,
+            })
     }
 
     pub fn script_id_of(&self, name: &str) -> Option<usize> {
@@ -138,7 +231,14 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
         self.scripts.write().unwrap().clear();
 
         for script in scripts.drain(..) {
-            self.add_script(&script.name, script.can_be_parallel, |_| Ok(()));
+            self.insert_script(
+                &script.name,
+                script.can_be_parallel,
+                script.shed_policy,
+                script.circuit_breaker,
+                script.daily_budget,
+                |_| Ok(()),
+            );
         }
     }
 }
@@ -162,6 +262,12 @@ impl<I: Send + Sync + Debug + Clone> ScriptsRepositoryTrait for Repository<I> {
     fn jobs_after_output(&self, _: ()) -> Option<Self::JobsIter> {
         None
     }
+
+    fn jobs_after_budget_exhausted(
+        &self, _: &str, _: DailyBudget,
+    ) -> Option<Self::JobsIter> {
+        None
+    }
 }
 
 
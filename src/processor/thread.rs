@@ -16,6 +16,7 @@
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 use std::fmt;
 
 use common::prelude::*;
@@ -86,6 +87,8 @@ pub struct Thread<S: ScriptsRepositoryTrait + 'static> {
     handle: thread::JoinHandle<()>,
 
     last_running_id: Option<ScriptId<S>>,
+    last_job: Option<(String, String)>,
+    last_started_at: Option<Instant>,
 
     busy: Arc<AtomicBool>,
     should_stop: Arc<AtomicBool>,
@@ -128,6 +131,8 @@ impl<S: ScriptsRepositoryTrait> Thread<S> {
             handle,
 
             last_running_id: None,
+            last_job: None,
+            last_started_at: None,
 
             busy,
             should_stop,
@@ -184,6 +189,8 @@ impl<S: ScriptsRepositoryTrait> Thread<S> {
             // Update the current state
             self.busy.store(true, Ordering::SeqCst);
             self.last_running_id = Some(job.hook_id());
+            self.last_job = Some((job.id().into(), job.hook_name().into()));
+            self.last_started_at = Some(Instant::now());
 
             // Tell the thread what job it should process
             *mutex = Some(job);
@@ -218,6 +225,26 @@ impl<S: ScriptsRepositoryTrait> Thread<S> {
         }
     }
 
+    /// Get the ID and the hook name of the current job, if any.
+    pub fn current_job(&self) -> Option<(&str, &str)> {
+        if self.busy.load(Ordering::SeqCst) {
+            self.last_job
+                .as_ref()
+                .map(|&(ref id, ref hook)| (id.as_str(), hook.as_str()))
+        } else {
+            None
+        }
+    }
+
+    /// Get how long the current job has been running, if any.
+    pub fn running_for(&self) -> Option<Duration> {
+        if self.busy.load(Ordering::SeqCst) {
+            self.last_started_at.map(|started| started.elapsed())
+        } else {
+            None
+        }
+    }
+
     pub fn busy(&self) -> bool {
         self.busy.load(Ordering::SeqCst)
     }
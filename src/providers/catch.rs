@@ -0,0 +1,239 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde_json::{self, Value};
+use url::form_urlencoded;
+
+use providers::prelude::*;
+use utils;
+
+
+fn default_max_depth() -> usize {
+    3
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct CatchProvider {
+    secret: utils::Secrets,
+    #[serde(default = "default_max_depth")]
+    max_depth: usize,
+}
+
+impl CatchProvider {
+    /// Parse the body of the request, returning None if it's not valid.
+    fn parse_body(req: &WebRequest) -> Option<Value> {
+        let is_json = req.headers
+            .get("Content-Type")
+            .map(|ct| ct.starts_with("application/json"))
+            .unwrap_or(false);
+        let body = req.body_str().trim();
+
+        if body.is_empty() {
+            Some(Value::Object(Default::default()))
+        } else if is_json || body.starts_with('{') || body.starts_with('[') {
+            serde_json::from_str(body).ok()
+        } else {
+            Some(Value::Object(
+                form_urlencoded::parse(body.as_bytes())
+                    .into_owned()
+                    .map(|(key, value)| (key, Value::String(value)))
+                    .collect()
+            ))
+        }
+    }
+
+    fn flatten(&self, name: &str, value: &Value, depth: usize, b: &mut EnvBuilder) {
+        let child = |key: &str| {
+            let key = utils::env_var_name(key);
+            if name.is_empty() {
+                key
+            } else {
+                format!("{}_{}", name, key)
+            }
+        };
+
+        match *value {
+            // Values nested too deeply are exported as JSON
+            Value::Object(..) | Value::Array(..) if depth >= self.max_depth => {
+                b.add_env(name, value.to_string());
+            }
+            Value::Object(ref map) => {
+                for (key, value) in map {
+                    self.flatten(&child(key), value, depth + 1, b);
+                }
+            }
+            Value::Array(ref array) => {
+                for (i, value) in array.iter().enumerate() {
+                    self.flatten(&child(&i.to_string()), value, depth + 1, b);
+                }
+            }
+            Value::String(ref string) => b.add_env(name, string),
+            Value::Null => b.add_env(name, ""),
+            ref other => b.add_env(name, other.to_string()),
+        }
+    }
+}
+
+impl ProviderTrait for CatchProvider {
+    fn new(config: &str) -> Result<Self> {
+        let inst = serde_json::from_str(config)?;
+        Ok(inst)
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        // The token must be present in the URL
+        match req.params.get("token") {
+            Some(token) if self.secret.matches(token) => {}
+            _ => return RequestType::Invalid,
+        }
+
+        if CatchProvider::parse_body(req).is_none() {
+            return RequestType::Invalid;
+        }
+
+        RequestType::ExecuteHook
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        if let Some(payload) = CatchProvider::parse_body(req) {
+            // Fields are exported as FISHER_F_*, to keep the names short
+            b.set_prefix(Some("F"));
+            match payload {
+                Value::Object(..) | Value::Array(..) => {
+                    self.flatten("", &payload, 0, b);
+                }
+                // Scalar bodies are exported as the BODY field
+                other => self.flatten("BODY", &other, 0, b),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::CatchProvider;
+
+
+    fn request(body: &str) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.params.insert("token".into(), "abcde".into());
+        req.body = body.into();
+        req
+    }
+
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{"secret": "abcde"}"#,
+            r#"{"secret": "abcde", "max_depth": 1}"#,
+        ] {
+            assert!(CatchProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{}"#,
+            r#"{"secret": 12345}"#,
+            r#"{"secret": "abcde", "max_depth": -1}"#,
+        ] {
+            assert!(CatchProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = CatchProvider::new(r#"{"secret": "abcde"}"#).unwrap();
+
+        for body in &["", r#"{"a": 1}"#, "[1, 2]", "a=b&c=d"] {
+            assert_eq!(
+                p.validate(&request(body).into()), RequestType::ExecuteHook,
+                "{}", body
+            );
+        }
+
+        // Invalid JSON is rejected
+        assert_eq!(p.validate(&request("{").into()), RequestType::Invalid);
+        let mut req = request("a=b");
+        req.headers.insert("Content-Type".into(), "application/json".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // The token is required
+        let mut req = request("");
+        req.params.insert("token".into(), "wrong".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        let mut req = request("");
+        req.params.clear();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = CatchProvider::new(
+            r#"{"secret": "abcde", "max_depth": 2}"#
+        ).unwrap();
+
+        let req = request(&json!({
+            "name": "Pietro",
+            "user-id": 42,
+            "active": true,
+            "missing": null,
+            "tags": ["a", "b"],
+            "nested": {"deep": {"deeper": 1}},
+        }).to_string()).into();
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "FISHER_F_NAME".into() => "Pietro".into(),
+            "FISHER_F_USER_ID".into() => "42".into(),
+            "FISHER_F_ACTIVE".into() => "true".into(),
+            "FISHER_F_MISSING".into() => "".into(),
+            "FISHER_F_TAGS_0".into() => "a".into(),
+            "FISHER_F_TAGS_1".into() => "b".into(),
+            "FISHER_F_NESTED_DEEP".into() => r#"{"deeper":1}"#.into(),
+        });
+
+        // Form bodies are exported too
+        let req = request("first+name=Pietro&x=1").into();
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "FISHER_F_FIRST_NAME".into() => "Pietro".into(),
+            "FISHER_F_X".into() => "1".into(),
+        });
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde_json;
+
+use providers::prelude::*;
+use providers::Provider;
+
+
+type ProvidersList = Vec<HashMap<String, serde_json::Value>>;
+
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CompositeConfig {
+    All(ProvidersList),
+    Any(ProvidersList),
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CompositeMode {
+    All,
+    Any,
+}
+
+
+#[derive(Debug)]
+pub struct CompositeProvider {
+    mode: CompositeMode,
+    providers: Vec<Provider>,
+}
+
+impl CompositeProvider {
+    /// Create a new instance requiring all the providers to be valid
+    pub fn all(providers: Vec<Provider>) -> Self {
+        CompositeProvider {
+            mode: CompositeMode::All,
+            providers,
+        }
+    }
+
+    /// Return the first inner provider which accepts the request
+    fn first_valid(&self, req: &Request) -> Option<&Provider> {
+        self.providers
+            .iter()
+            .find(|prov| prov.validate(req) != RequestType::Invalid)
+    }
+}
+
+impl ProviderTrait for CompositeProvider {
+    fn new(config: &str) -> Result<Self> {
+        let (mode, list) = match serde_json::from_str(config)? {
+            CompositeConfig::All(list) => (CompositeMode::All, list),
+            CompositeConfig::Any(list) => (CompositeMode::Any, list),
+        };
+
+        if list.is_empty() {
+            return Err(ErrorKind::ProviderCompositeInvalid(
+                "no providers were listed".into(),
+            ).into());
+        }
+
+        let mut providers = Vec::with_capacity(list.len());
+        for entry in list {
+            if entry.len() != 1 {
+                return Err(ErrorKind::ProviderCompositeInvalid(
+                    "every entry must contain exactly one provider".into(),
+                ).into());
+            }
+
+            let (name, config) = entry.into_iter().next().unwrap();
+
+            // Status hooks are registered by looking at the top-level
+            // providers, so they can't be nested here
+            if name == "Status" {
+                return Err(ErrorKind::ProviderCompositeInvalid(
+                    "the Status provider can't be combined".into(),
+                ).into());
+            }
+
+            providers.push(
+                Provider::new(&name, &serde_json::to_string(&config)?)?
+            );
+        }
+
+        Ok(CompositeProvider { mode, providers })
+    }
+
+    fn validate_traced(
+        &self, req: &Request, trace: &mut ValidationTrace,
+    ) -> RequestType {
+        // Every provider enforces its own requirements
+        match self.mode {
+            CompositeMode::All => {
+                let mut result = RequestType::ExecuteHook;
+                for provider in &self.providers {
+                    match provider.validate_traced(req, trace) {
+                        RequestType::Invalid => return RequestType::Invalid,
+                        // If any provider thinks it's a ping, then it is
+                        RequestType::Ping => result = RequestType::Ping,
+                        RequestType::ExecuteHook => {}
+                    }
+                }
+                result
+            }
+            CompositeMode::Any => {
+                for provider in &self.providers {
+                    let result = provider.validate_traced(req, trace);
+                    if result != RequestType::Invalid {
+                        return result;
+                    }
+                }
+                RequestType::Invalid
+            }
+        }
+    }
+
+    fn build_env(&self, req: &Request, builder: &mut EnvBuilder) -> Result<()> {
+        // Every provider gets its own prefix, so the environment is the same
+        // as if they were used directly
+        match self.mode {
+            CompositeMode::All => {
+                for provider in &self.providers {
+                    builder.set_prefix(Some(provider.name()));
+                    provider.build_env(req, builder)?;
+                }
+            }
+            CompositeMode::Any => {
+                if let Some(provider) = self.first_valid(req) {
+                    builder.set_prefix(Some(provider.name()));
+                    provider.build_env(req, builder)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delivery_id(&self, req: &Request) -> Option<String> {
+        match self.mode {
+            CompositeMode::All => {
+                self.providers
+                    .iter()
+                    .filter_map(|prov| prov.delivery_id(req))
+                    .next()
+            }
+            CompositeMode::Any => {
+                self.first_valid(req).and_then(|prov| prov.delivery_id(req))
+            }
+        }
+    }
+
+    fn response_body(
+        &self, req: &Request, request_type: RequestType,
+    ) -> Option<serde_json::Value> {
+        match self.mode {
+            CompositeMode::All => {
+                self.providers
+                    .iter()
+                    .filter_map(|prov| prov.response_body(req, request_type))
+                    .next()
+            }
+            CompositeMode::Any => {
+                self.first_valid(req)
+                    .and_then(|prov| prov.response_body(req, request_type))
+            }
+        }
+    }
+
+    fn trigger_status_hooks(&self, req: &Request) -> bool {
+        match self.mode {
+            CompositeMode::All => {
+                self.providers
+                    .iter()
+                    .all(|prov| prov.trigger_status_hooks(req))
+            }
+            CompositeMode::Any => {
+                if let Some(provider) = self.first_valid(req) {
+                    provider.trigger_status_hooks(req)
+                } else {
+                    true
+                }
+            }
+        }
+    }
+
+    fn fan_out(&self, req: &Request) -> Option<Vec<String>> {
+        match self.mode {
+            CompositeMode::All => {
+                self.providers
+                    .iter()
+                    .filter_map(|prov| prov.fan_out(req))
+                    .next()
+            }
+            CompositeMode::Any => {
+                self.first_valid(req).and_then(|prov| prov.fan_out(req))
+            }
+        }
+    }
+
+    fn event_name(&self, req: &Request) -> Option<String> {
+        match self.mode {
+            CompositeMode::All => {
+                self.providers
+                    .iter()
+                    .filter_map(|prov| prov.event_name(req))
+                    .next()
+            }
+            CompositeMode::Any => {
+                self.first_valid(req).and_then(|prov| prov.event_name(req))
+            }
+        }
+    }
+
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        let events = self.providers.iter().map(|prov| prov.accepted_events());
+        match self.mode {
+            // The events must be accepted by every provider filtering them
+            CompositeMode::All => {
+                events.flatten().fold(None, |result, events| Some(
+                    match result {
+                        Some(result) => result
+                            .into_iter()
+                            .filter(|event| events.contains(event))
+                            .collect(),
+                        None => events,
+                    }
+                ))
+            }
+            // Every event is accepted if a provider doesn't filter them
+            CompositeMode::Any => {
+                let mut result: Vec<String> = Vec::new();
+                for events in events {
+                    for event in events? {
+                        if !result.contains(&event) {
+                            result.push(event);
+                        }
+                    }
+                }
+                Some(result)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use requests::RequestType;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::CompositeProvider;
+
+
+    #[test]
+    fn test_new() {
+        // Check if valid config is accepted
+        let right = vec![
+            r#"{"all": [{"Testing": ""}]}"#,
+            r#"{"any": [{"Testing": ""}]}"#,
+            r#"{"all": [{"Testing": ""}, {"Standalone": {"secret": "a"}}]}"#,
+            r#"{"any": [{"Testing": ""}, {"Standalone": {"secret": "a"}}]}"#,
+            r#"{"all": [{"Composite": {"any": [{"Testing": ""}]}}]}"#,
+        ];
+        for one in &right {
+            assert!(CompositeProvider::new(one).is_ok(), "Should be valid: {}", one);
+        }
+
+        let wrong = vec![
+            r#"{}"#,
+            r#"{"all": []}"#,
+            r#"{"none": [{"Testing": ""}]}"#,
+            r#"{"all": [{"Testing": ""}], "any": [{"Testing": ""}]}"#,
+            r#"{"all": [{}]}"#,
+            r#"{"all": [{"Testing": "", "Standalone": {}}]}"#,
+            r#"{"all": [{"Unknown": {}}]}"#,
+            r#"{"all": [{"Standalone": {"secret": 123}}]}"#,
+            r#"{"any": [{"Status": {"events": ["job-failed"]}}]}"#,
+        ];
+        for one in &wrong {
+            assert!(CompositeProvider::new(one).is_err(), "Should be invalid: {}", one);
+        }
+    }
+
+
+    #[test]
+    fn test_accepted_events() {
+        let events = |config| {
+            CompositeProvider::new(config).unwrap().accepted_events()
+        };
+
+        assert_eq!(events(concat!(
+            r#"{"all": [{"Testing": ""}, "#,
+            r#"{"GitHub": {"events": ["push", "fork"]}}, "#,
+            r#"{"GitHub": {"events": ["fork", "watch"]}}]}"#,
+        )), Some(vec!["fork".to_string()]));
+        assert_eq!(events(concat!(
+            r#"{"any": [{"GitHub": {"events": ["push", "fork"]}}, "#,
+            r#"{"GitHub": {"events": ["fork", "watch"]}}]}"#,
+        )), Some(vec![
+            "push".to_string(), "fork".to_string(), "watch".to_string(),
+        ]));
+
+        // Providers which don't filter the events accept all of them
+        assert_eq!(events(r#"{"all": [{"Testing": ""}]}"#), None);
+        assert_eq!(events(concat!(
+            r#"{"any": [{"Testing": ""}, "#,
+            r#"{"GitHub": {"events": ["push"]}}]}"#,
+        )), None);
+    }
+
+    #[test]
+    fn test_validate_all() {
+        let p = CompositeProvider::new(concat!(
+            r#"{"all": [{"Testing": ""}, "#,
+            r#"{"Standalone": {"from": ["10.0.0.1"]}}]}"#,
+        )).unwrap();
+
+        // Only the Testing provider accepts this
+        let mut req = dummy_web_request();
+        req.source = "127.0.0.1".parse().unwrap();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Only the Standalone provider accepts this
+        let mut req = dummy_web_request();
+        req.source = "10.0.0.1".parse().unwrap();
+        req.params.insert("secret".into(), "wrong".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Both providers accept this
+        let mut req = dummy_web_request();
+        req.source = "10.0.0.1".parse().unwrap();
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        // If one of the providers considers this a ping, it's a ping
+        let mut req = dummy_web_request();
+        req.source = "10.0.0.1".parse().unwrap();
+        req.params.insert("request_type".into(), "ping".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Ping);
+    }
+
+
+    #[test]
+    fn test_validate_any() {
+        let p = CompositeProvider::new(concat!(
+            r#"{"any": [{"Standalone": {"secret": "abcde"}}, "#,
+            r#"{"Standalone": {"from": ["10.0.0.1"]}}]}"#,
+        )).unwrap();
+
+        // No provider accepts this
+        let mut req = dummy_web_request();
+        req.source = "127.0.0.1".parse().unwrap();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Only the first provider accepts this
+        let mut req = dummy_web_request();
+        req.source = "127.0.0.1".parse().unwrap();
+        req.params.insert("secret".into(), "abcde".into());
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        // Only the second provider accepts this
+        let mut req = dummy_web_request();
+        req.source = "10.0.0.1".parse().unwrap();
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let mut req = dummy_web_request();
+        req.params.insert("env".into(), "test".into());
+        let req = req.into();
+
+        // All the providers should be able to add their environment
+        let p = CompositeProvider::new(
+            r#"{"all": [{"Standalone": {}}, {"Testing": ""}]}"#
+        ).unwrap();
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "FISHER_TESTING_PREPARED".into() => "prepared".into(),
+            "FISHER_TESTING_ENV".into() => "test".into(),
+        });
+
+        // Only the first valid provider should add its environment
+        let p = CompositeProvider::new(
+            r#"{"any": [{"Standalone": {"secret": "a"}}, {"Testing": ""}]}"#
+        ).unwrap();
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "FISHER_TESTING_PREPARED".into() => "prepared".into(),
+            "FISHER_TESTING_ENV".into() => "test".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_trigger_status_hooks() {
+        let mut req = dummy_web_request();
+        req.params.insert("ignore_status_hooks".into(), "yes".into());
+        let req = req.into();
+
+        let p = CompositeProvider::new(
+            r#"{"all": [{"Standalone": {}}, {"Testing": ""}]}"#
+        ).unwrap();
+        assert!(!p.trigger_status_hooks(&req));
+
+        let p = CompositeProvider::new(
+            r#"{"any": [{"Standalone": {}}, {"Testing": ""}]}"#
+        ).unwrap();
+        assert!(p.trigger_status_hooks(&req));
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Features shared by the configuration of every provider.
+//!
+//! The configuration is preprocessed before being passed to the provider, so
+//! the providers don't need to implement these features themselves.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+
+use serde_json::{self, Map, Value};
+
+use common::prelude::*;
+
+
+#[cfg(feature = "vault")]
+const VAULT_PREFIX: &str = "vault:";
+
+
+/// Prepare the configuration of a provider before loading it. Configurations
+/// which are not JSON objects are returned untouched.
+pub fn prepare_config(config: &str) -> Result<String> {
+    let mut json = match serde_json::from_str::<Value>(config) {
+        Ok(json @ Value::Object(_)) => json,
+        _ => return Ok(config.into()),
+    };
+
+    // Environment variables are expanded first, so they can also be used in
+    // the path of the secret file
+    let mut changed = expand_env_vars(&mut json)?;
+
+    if let Value::Object(ref mut map) = json {
+        if map.contains_key("secret_file") {
+            load_secret_file(map)?;
+            changed = true;
+        }
+    }
+
+    if changed {
+        Ok(serde_json::to_string(&json)?)
+    } else {
+        Ok(config.into())
+    }
+}
+
+
+/// Replace every `${NAME}` in the strings of the configuration with the
+/// content of the environment variable, and every string starting with
+/// `vault:` with the referenced secret, returning if anything changed.
+fn expand_env_vars(value: &mut Value) -> Result<bool> {
+    Ok(match *value {
+        Value::String(ref mut string) => {
+            let mut changed = false;
+            if let Some(expanded) = expand_string(string)? {
+                *string = expanded;
+                changed = true;
+            }
+            changed | resolve_vault(string)?
+        }
+        Value::Array(ref mut array) => {
+            let mut changed = false;
+            for item in array {
+                changed |= expand_env_vars(item)?;
+            }
+            changed
+        }
+        Value::Object(ref mut map) => {
+            let mut changed = false;
+            for (_, item) in map.iter_mut() {
+                changed |= expand_env_vars(item)?;
+            }
+            changed
+        }
+        _ => false,
+    })
+}
+
+
+/// Replace the string with the secret it references, if it starts with
+/// `vault:`, returning if it changed.
+#[cfg(feature = "vault")]
+fn resolve_vault(string: &mut String) -> Result<bool> {
+    if string.starts_with(VAULT_PREFIX) {
+        *string = ::vault::resolve(&string[VAULT_PREFIX.len()..])?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Without Vault support strings starting with `vault:` are not references.
+#[cfg(not(feature = "vault"))]
+fn resolve_vault(_string: &mut String) -> Result<bool> {
+    Ok(false)
+}
+
+
+fn expand_string(input: &str) -> Result<Option<String>> {
+    if !input.contains("${") {
+        return Ok(None);
+    }
+
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        // `$${` is an escaped `${`
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start]);
+            result.push_str("{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let end = after
+            .find('}')
+            .ok_or_else(|| Error::from(ErrorKind::ProviderEnvVarInvalid(
+                input.into(),
+            )))?;
+        let name = &after[..end];
+        if name.is_empty() || !name.chars().all(
+            |c| c.is_ascii_alphanumeric() || c == '_'
+        ) {
+            return Err(ErrorKind::ProviderEnvVarInvalid(input.into()).into());
+        }
+
+        match env::var(name) {
+            Ok(content) => result.push_str(&content),
+            Err(_) => {
+                return Err(ErrorKind::ProviderEnvVarMissing(name.into()).into())
+            }
+        }
+
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(Some(result))
+}
+
+
+fn load_secret_file(json: &mut Map<String, Value>) -> Result<()> {
+    if json.contains_key("secret") {
+        return Err(ErrorKind::ProviderSecretFileConflict.into());
+    }
+
+    let path = match json.remove("secret_file") {
+        Some(Value::String(path)) => path,
+        _ => return Err(ErrorKind::ProviderSecretFileNotString.into()),
+    };
+
+    let secret = read_secret_file(&path)
+        .chain_err(|| ErrorKind::ProviderSecretFileUnreadable(path.clone()))?;
+    json.insert("secret".into(), Value::String(secret));
+
+    Ok(())
+}
+
+
+fn read_secret_file(path: &str) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+
+    // Refuse to load secrets everyone on the system can read
+    let mode = file.metadata()?.permissions().mode();
+    if mode & 0o007 != 0 {
+        return Err(ErrorKind::ProviderSecretFileInsecure(mode & 0o777).into());
+    }
+
+    let mut secret = String::new();
+    file.read_to_string(&mut secret)?;
+
+    // Editors usually add a trailing newline
+    Ok(secret.trim_end_matches(|c| c == '\n' || c == '\r').into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use serde_json::{self, Value};
+    use tempdir::TempDir;
+
+    use super::prepare_config;
+
+
+    fn secret_file(dir: &TempDir, content: &str, mode: u32) -> PathBuf {
+        let path = dir.path().join("secret");
+        write!(fs::File::create(&path).unwrap(), "{}", content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+
+    #[test]
+    fn test_untouched_configs() {
+        for config in &["", "FAIL", "[1, 2]", r#"{"secret": "abcde"}"#] {
+            assert_eq!(&prepare_config(config).unwrap(), config);
+        }
+    }
+
+
+    #[test]
+    fn test_secret_file() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = secret_file(&dir, "abcde\n", 0o600);
+
+        let config = json!({"secret_file": path.to_str().unwrap(), "a": 1});
+        let result = prepare_config(&config.to_string()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&result).unwrap(),
+            json!({"secret": "abcde", "a": 1})
+        );
+    }
+
+
+    #[test]
+    fn test_secret_file_errors() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = secret_file(&dir, "abcde", 0o604);
+        let path = path.to_str().unwrap();
+
+        // World-readable files are refused
+        let config = json!({"secret_file": path});
+        assert!(prepare_config(&config.to_string()).is_err());
+
+        // Missing files are refused
+        let config = json!({"secret_file": "/this/does/not/exist"});
+        assert!(prepare_config(&config.to_string()).is_err());
+
+        // Both secret and secret_file can't be present
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+        let config = json!({"secret_file": path, "secret": "abcde"});
+        assert!(prepare_config(&config.to_string()).is_err());
+
+        // The file path must be a string
+        let config = json!({"secret_file": 42});
+        assert!(prepare_config(&config.to_string()).is_err());
+    }
+
+
+    #[test]
+    fn test_env_vars() {
+        env::set_var("FISHER_TEST_CONFIG_SECRET", "abcde");
+        env::set_var("FISHER_TEST_CONFIG_IP", "10.0.0.1");
+        env::remove_var("FISHER_TEST_CONFIG_MISSING");
+
+        let config = json!({
+            "secret": "${FISHER_TEST_CONFIG_SECRET}",
+            "from": ["${FISHER_TEST_CONFIG_IP}", "127.0.0.1"],
+            "escaped": "a$${FISHER_TEST_CONFIG_SECRET}b",
+            "mixed": "<${FISHER_TEST_CONFIG_SECRET}${FISHER_TEST_CONFIG_IP}>",
+            "number": 42,
+        });
+        let result = prepare_config(&config.to_string()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&result).unwrap(),
+            json!({
+                "secret": "abcde",
+                "from": ["10.0.0.1", "127.0.0.1"],
+                "escaped": "a${FISHER_TEST_CONFIG_SECRET}b",
+                "mixed": "<abcde10.0.0.1>",
+                "number": 42,
+            })
+        );
+
+        for wrong in &[
+            "${FISHER_TEST_CONFIG_MISSING}",
+            "${FISHER_TEST_CONFIG_SECRET",
+            "${}",
+            "${NOT VALID}",
+        ] {
+            let config = json!({"secret": wrong});
+            assert!(prepare_config(&config.to_string()).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_env_vars_secret_file() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = secret_file(&dir, "abcde", 0o600);
+
+        env::set_var("FISHER_TEST_CONFIG_DIR", dir.path().to_str().unwrap());
+        let config = json!({"secret_file": "${FISHER_TEST_CONFIG_DIR}/secret"});
+        let result = prepare_config(&config.to_string()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&result).unwrap(),
+            json!({"secret": "abcde"})
+        );
+        assert!(path.exists());
+    }
+
+
+    #[test]
+    #[cfg(not(feature = "vault"))]
+    fn test_vault_disabled() {
+        let config = json!({"secret": "vault:kv/github#secret"}).to_string();
+        assert_eq!(prepare_config(&config).unwrap(), config);
+    }
+}
@@ -0,0 +1,504 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::Read;
+use std::sync::Arc;
+
+use serde_json::{self, Value};
+
+use providers::prelude::*;
+use utils::{
+    self, verify_public_key_signature, PublicKey, PublicKeyAlgorithm,
+};
+use common::prelude::*;
+
+
+static DISCORD_HEADERS: &[&str] = &[
+    "X-Signature-Ed25519",
+    "X-Signature-Timestamp",
+];
+
+
+const INTERACTION_PING: u64 = 1;
+const INTERACTION_APPLICATION_COMMAND: u64 = 2;
+const INTERACTION_MESSAGE_COMPONENT: u64 = 3;
+const INTERACTION_MODAL_SUBMIT: u64 = 5;
+
+const OPTION_SUB_COMMAND: u64 = 1;
+const OPTION_SUB_COMMAND_GROUP: u64 = 2;
+
+const RESPONSE_PONG: u64 = 1;
+const RESPONSE_DEFERRED_MESSAGE: u64 = 5;
+const RESPONSE_DEFERRED_UPDATE: u64 = 6;
+
+
+fn default_tolerance() -> u64 {
+    300
+}
+
+
+#[derive(Debug, Deserialize)]
+struct DiscordConfig {
+    public_key: Option<String>,
+    public_key_file: Option<String>,
+    commands: Option<Vec<String>>,
+    #[serde(default = "default_tolerance")]
+    tolerance: u64,
+}
+
+
+#[derive(Debug)]
+pub struct DiscordProvider {
+    /// Multiple keys can be loaded from a file, to rotate them.
+    public_keys: Vec<PublicKey>,
+    commands: Option<Vec<String>>,
+    tolerance: u64,
+}
+
+impl DiscordProvider {
+    fn interaction_type(payload: &Value) -> Option<u64> {
+        payload.get("type").and_then(|t| t.as_u64())
+    }
+
+    fn verify(
+        &self, signature: &str, timestamp: &str, req: &WebRequest,
+    ) -> bool {
+        let signature = if let Ok(signature) = utils::from_hex(signature) {
+            signature
+        } else {
+            return false;
+        };
+
+        // Discord signs the timestamp followed by the body. Ed25519 needs
+        // the whole message, so spooled bodies are read back in memory.
+        let mut message = timestamp.as_bytes().to_vec();
+        let read = req.body_reader()
+            .and_then(|mut body| Ok(body.read_to_end(&mut message)?));
+        if read.is_err() {
+            return false;
+        }
+
+        verify_public_key_signature(
+            &self.public_keys, PublicKeyAlgorithm::Ed25519, &message,
+            &signature,
+        )
+    }
+}
+
+impl ProviderTrait for DiscordProvider {
+    fn new(config: &str) -> Result<Self> {
+        let config: DiscordConfig = serde_json::from_str(config)?;
+
+        let public_keys = match (&config.public_key, &config.public_key_file) {
+            (&Some(ref key), &None) => vec![PublicKey::parse(key)?],
+            (&None, &Some(ref path)) => PublicKey::load_file(path)?,
+            _ => {
+                return Err(
+                    ErrorKind::ProviderDiscordPublicKeyConflict.into()
+                );
+            }
+        };
+        if public_keys.is_empty() {
+            return Err(ErrorKind::PublicKeyInvalid.into());
+        }
+
+        // Discord only signs the requests with Ed25519
+        for key in &public_keys {
+            if let PublicKey::Rsa { .. } = *key {
+                return Err(ErrorKind::PublicKeyUnsupported.into());
+            }
+        }
+
+        Ok(DiscordProvider {
+            public_keys,
+            commands: config.commands,
+            tolerance: config.tolerance,
+        })
+    }
+
+    fn requirements<'a>(&'a self) -> Requirements<'a> {
+        Requirements::new().headers(DISCORD_HEADERS).json_body()
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        let timestamp = &req.headers["X-Signature-Timestamp"];
+        if !self.verify(
+            &req.headers["X-Signature-Ed25519"], timestamp, req,
+        ) {
+            return RequestType::Invalid;
+        }
+
+        // Reject old requests, since they might be replayed
+        match timestamp.parse() {
+            Ok(ts) if utils::is_fresh_timestamp("Discord", ts, self.tolerance) => {}
+            _ => return RequestType::Invalid,
+        }
+
+        let payload = match req.json() {
+            Some(payload) => payload,
+            None => return RequestType::Invalid,
+        };
+
+        match DiscordProvider::interaction_type(&payload) {
+            Some(INTERACTION_PING) => RequestType::Ping,
+            Some(INTERACTION_APPLICATION_COMMAND) => {
+                // Check if the command should be accepted
+                if let Some(ref commands) = self.commands {
+                    let name = payload.pointer("/data/name")
+                        .and_then(|name| name.as_str());
+                    match name {
+                        Some(name) if commands.iter().any(|c| c == name) => {}
+                        _ => return RequestType::Invalid,
+                    }
+                }
+                RequestType::ExecuteHook
+            }
+            Some(INTERACTION_MESSAGE_COMPONENT) |
+            Some(INTERACTION_MODAL_SUBMIT) => RequestType::ExecuteHook,
+            _ => RequestType::Invalid,
+        }
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        let payload = match req.json() {
+            Some(payload) => payload,
+            None => Arc::new(serde_json::from_str(req.body_str())?),
+        };
+
+        let interaction_type = match DiscordProvider::interaction_type(&payload) {
+            Some(INTERACTION_PING) => "ping",
+            Some(INTERACTION_APPLICATION_COMMAND) => "application_command",
+            Some(INTERACTION_MESSAGE_COMPONENT) => "message_component",
+            Some(INTERACTION_MODAL_SUBMIT) => "modal_submit",
+            _ => "unknown",
+        };
+        b.add_env("INTERACTION_TYPE", interaction_type);
+
+        for &(name, pointer) in &[
+            ("INTERACTION_ID", "/id"),
+            ("INTERACTION_TOKEN", "/token"),
+            ("APPLICATION_ID", "/application_id"),
+            ("GUILD_ID", "/guild_id"),
+            ("CHANNEL_ID", "/channel_id"),
+            // Interactions in guilds contain the member instead of the user
+            ("USER_ID", "/member/user/id"),
+            ("USER_ID", "/user/id"),
+            ("USER_NAME", "/member/user/username"),
+            ("USER_NAME", "/user/username"),
+            ("COMMAND_NAME", "/data/name"),
+            ("COMPONENT_ID", "/data/custom_id"),
+        ] {
+            if let Some(value) = payload.pointer(pointer).and_then(|v| v.as_str()) {
+                b.add_env(name, value);
+            }
+        }
+
+        if let Some(options) = payload.pointer("/data/options") {
+            add_options_env(options, b);
+        }
+
+        Ok(())
+    }
+
+    fn delivery_id(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            req.json()
+                .and_then(|payload| {
+                    payload.get("id").and_then(|id| id.as_str()).map(Into::into)
+                })
+        } else {
+            None
+        }
+    }
+
+    fn response_body(
+        &self, r: &Request, request_type: RequestType,
+    ) -> Option<Value> {
+        let req = r.web().ok()?;
+        let payload = req.json()?;
+
+        let response = match request_type {
+            RequestType::Ping => RESPONSE_PONG,
+            RequestType::ExecuteHook => {
+                // The script runs in the background, so Discord is told the
+                // response will be sent later
+                match DiscordProvider::interaction_type(&payload) {
+                    Some(INTERACTION_MESSAGE_COMPONENT) => {
+                        RESPONSE_DEFERRED_UPDATE
+                    }
+                    _ => RESPONSE_DEFERRED_MESSAGE,
+                }
+            }
+            RequestType::Invalid => return None,
+        };
+
+        Some(json!({"type": response}))
+    }
+}
+
+
+fn add_options_env(options: &Value, b: &mut EnvBuilder) {
+    let options = if let Some(options) = options.as_array() {
+        options
+    } else {
+        return;
+    };
+
+    for option in options {
+        let name = if let Some(name) = option.get("name").and_then(|n| n.as_str()) {
+            name
+        } else {
+            continue;
+        };
+
+        match option.get("type").and_then(|t| t.as_u64()) {
+            Some(OPTION_SUB_COMMAND_GROUP) => {
+                b.add_env("COMMAND_SUBCOMMAND_GROUP", name);
+            }
+            Some(OPTION_SUB_COMMAND) => {
+                b.add_env("COMMAND_SUBCOMMAND", name);
+            }
+            _ => {
+                let value = match option.get("value") {
+                    Some(&Value::String(ref value)) => value.clone(),
+                    Some(&Value::Null) | None => continue,
+                    Some(other) => other.to_string(),
+                };
+                b.add_env(
+                    format!("COMMAND_OPTION_{}", utils::env_var_name(name)),
+                    value,
+                );
+            }
+        }
+
+        // Subcommands contain their own options
+        if let Some(nested) = option.get("options") {
+            add_options_env(nested, b);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use base64;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use tempdir::TempDir;
+
+    use utils::testing::*;
+    use utils::{to_hex, unix_now};
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::DiscordProvider;
+
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[42; 32]).unwrap()
+    }
+
+
+    fn provider(extra: &str) -> DiscordProvider {
+        let public_key = to_hex(key_pair().public_key().as_ref());
+        DiscordProvider::new(
+            &format!(r#"{{"public_key": "{}"{}}}"#, public_key, extra)
+        ).unwrap()
+    }
+
+
+    fn signed_request(body: &str, timestamp: u64) -> WebRequest {
+        let timestamp = timestamp.to_string();
+        let message = format!("{}{}", timestamp, body);
+        let signature = key_pair().sign(message.as_bytes());
+
+        let mut req = dummy_web_request();
+        req.headers.insert(
+            "X-Signature-Ed25519".into(), to_hex(signature.as_ref()),
+        );
+        req.headers.insert("X-Signature-Timestamp".into(), timestamp);
+        req.body = body.into();
+        req
+    }
+
+
+    const COMMAND: &str = r#"{
+        "id": "123", "type": 2, "token": "tok", "application_id": "456",
+        "guild_id": "789", "channel_id": "101",
+        "member": {"user": {"id": "202", "username": "pietro"}},
+        "data": {"name": "deploy", "options": [
+            {"name": "app", "type": 1, "options": [
+                {"name": "env-name", "type": 3, "value": "prod"},
+                {"name": "force", "type": 5, "value": true}
+            ]}
+        ]}
+    }"#;
+
+
+    #[test]
+    fn test_new() {
+        let public_key = to_hex(key_pair().public_key().as_ref());
+
+        for right in &[
+            format!(r#"{{"public_key": "{}"}}"#, public_key),
+            format!(r#"{{"public_key": "{}", "commands": ["a"]}}"#, public_key),
+            format!(r#"{{"public_key": "{}", "tolerance": 10}}"#, public_key),
+        ] {
+            assert!(DiscordProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{}"#.to_string(),
+            r#"{"public_key": "abcd"}"#.to_string(),
+            r#"{"public_key": 12345}"#.to_string(),
+            format!(r#"{{"public_key": "{}", "commands": "a"}}"#, public_key),
+            format!(
+                r#"{{"public_key": "{}", "public_key_file": "a"}}"#, public_key,
+            ),
+            r#"{"public_key_file": "/fisher-missing"}"#.to_string(),
+        ] {
+            assert!(DiscordProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_public_key_file() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let now = unix_now();
+
+        // Keys can be rotated with a JSON Web Key Set
+        let old = Ed25519KeyPair::from_seed_unchecked(&[1; 32]).unwrap();
+        let encode = |key: &Ed25519KeyPair| base64::encode_config(
+            key.public_key().as_ref(), base64::URL_SAFE_NO_PAD,
+        );
+        let path = dir.path().join("keys.json");
+        write!(fs::File::create(&path).unwrap(), "{}", json!({"keys": [
+            {"kty": "OKP", "crv": "Ed25519", "x": encode(&old)},
+            {"kty": "OKP", "crv": "Ed25519", "x": encode(&key_pair())},
+        ]})).unwrap();
+
+        let p = DiscordProvider::new(&json!({
+            "public_key_file": path.to_str().unwrap(),
+        }).to_string()).unwrap();
+        let req = signed_request(r#"{"type": 1}"#, now).into();
+        assert_eq!(p.validate(&req), RequestType::Ping);
+
+        // Files without any key are rejected
+        let empty = dir.path().join("empty.json");
+        write!(fs::File::create(&empty).unwrap(), r#"{{"keys": []}}"#)
+            .unwrap();
+        assert!(DiscordProvider::new(&json!({
+            "public_key_file": empty.to_str().unwrap(),
+        }).to_string()).is_err());
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = provider("");
+        let now = unix_now();
+
+        // Pings are answered inline
+        let req = signed_request(r#"{"type": 1}"#, now).into();
+        assert_eq!(p.validate(&req), RequestType::Ping);
+        assert_eq!(
+            p.response_body(&req, RequestType::Ping),
+            Some(json!({"type": 1}))
+        );
+
+        // Commands are executed, and their response is deferred
+        let req = signed_request(COMMAND, now).into();
+        assert_eq!(p.validate(&req), RequestType::ExecuteHook);
+        assert_eq!(
+            p.response_body(&req, RequestType::ExecuteHook),
+            Some(json!({"type": 5}))
+        );
+        assert_eq!(p.delivery_id(&req), Some("123".into()));
+
+        // Unknown interaction types are rejected
+        let req = signed_request(r#"{"type": 42}"#, now).into();
+        assert_eq!(p.validate(&req), RequestType::Invalid);
+
+        // Old requests are rejected
+        let req = signed_request(r#"{"type": 1}"#, now - 1000).into();
+        assert_eq!(p.validate(&req), RequestType::Invalid);
+
+        // Tampered requests are rejected
+        let mut req = signed_request(r#"{"type": 1}"#, now);
+        req.body = r#"{"type": 2}"#.into();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Requests without the headers are rejected
+        let mut req = signed_request(r#"{"type": 1}"#, now);
+        req.headers.remove("X-Signature-Ed25519");
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_validate_commands() {
+        let now = unix_now();
+
+        let p = provider(r#", "commands": ["deploy"]"#);
+        let req = signed_request(COMMAND, now).into();
+        assert_eq!(p.validate(&req), RequestType::ExecuteHook);
+
+        let p = provider(r#", "commands": ["other"]"#);
+        assert_eq!(p.validate(&req), RequestType::Invalid);
+
+        // Pings are always accepted
+        let req = signed_request(r#"{"type": 1}"#, now).into();
+        assert_eq!(p.validate(&req), RequestType::Ping);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = provider("");
+        let req = signed_request(COMMAND, unix_now()).into();
+
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "INTERACTION_TYPE".into() => "application_command".into(),
+            "INTERACTION_ID".into() => "123".into(),
+            "INTERACTION_TOKEN".into() => "tok".into(),
+            "APPLICATION_ID".into() => "456".into(),
+            "GUILD_ID".into() => "789".into(),
+            "CHANNEL_ID".into() => "101".into(),
+            "USER_ID".into() => "202".into(),
+            "USER_NAME".into() => "pietro".into(),
+            "COMMAND_NAME".into() => "deploy".into(),
+            "COMMAND_SUBCOMMAND".into() => "app".into(),
+            "COMMAND_OPTION_ENV_NAME".into() => "prod".into(),
+            "COMMAND_OPTION_FORCE".into() => "true".into(),
+        });
+    }
+}
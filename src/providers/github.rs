@@ -13,15 +13,38 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use serde_json;
-use hmac::{Hmac, Mac};
-use sha1;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use glob::{MatchOptions, Pattern};
+use reqwest;
+use serde::Deserialize;
+use serde_json::{self, Value};
+use url::form_urlencoded;
 
 use providers::prelude::*;
-use utils;
+use providers::Signature;
+use providers::keywords;
+use providers::paths::{changed_files, PathFilter, PathRoutes};
+use providers::tags::{self, TagFilter};
+use utils::{self, HmacAlgorithm, IpCidr, SignatureEncoding, TimeString};
 use common::prelude::*;
 
 
+static GITHUB_HEADERS: &[&str] = &[
+    "X-GitHub-Event",
+    "X-Hub-Signature",
+    "X-GitHub-Delivery",
+];
+
+/// Only SHA-1 signatures are sent by GitHub.
+static SIGNATURE_ALGORITHMS: &[HmacAlgorithm] = &[HmacAlgorithm::Sha1];
+
+
 lazy_static! {
     static ref GITHUB_EVENTS: Vec<&'static str> = vec![
         "commit_comment", "create", "delete", "deployment",
@@ -32,11 +55,114 @@ lazy_static! {
         "push", "repository", "release", "status", "team", "team_add", "watch",
     ];
 
-    static ref GITHUB_HEADERS: Vec<&'static str> = vec![
-        "X-GitHub-Event",
-        "X-Hub-Signature",
-        "X-GitHub-Delivery",
-    ];
+    /// The IP ranges GitHub sends webhooks from, for each meta API URL.
+    /// They're refreshed in the background, so validating the requests
+    /// never waits for the meta API.
+    static ref HOOK_RANGES: Mutex<HashMap<String, CachedRanges>> =
+        Mutex::new(HashMap::new());
+}
+
+
+fn default_meta_url() -> String {
+    "https://api.github.com/meta".into()
+}
+
+fn default_meta_refresh() -> TimeString {
+    3600.into()
+}
+
+
+struct CachedRanges {
+    ranges: Arc<Vec<IpCidr>>,
+    refresh: Duration,
+}
+
+
+#[derive(Deserialize)]
+struct Meta {
+    hooks: Vec<String>,
+}
+
+
+/// Return the JSON payload of the webhook. If the webhook is configured to
+/// send form-encoded bodies, the payload is in the `payload` field.
+fn json_payload<'a>(req: &'a WebRequest) -> Cow<'a, str> {
+    if is_form(req) {
+        form_urlencoded::parse(&req.body)
+            .find(|&(ref key, _)| key == "payload")
+            .map(|(_, value)| Cow::Owned(value.into_owned()))
+            .unwrap_or(Cow::Borrowed(""))
+    } else {
+        Cow::Borrowed(req.body_str())
+    }
+}
+
+/// Return the parsed JSON payload of the webhook, or None if it's invalid.
+/// JSON bodies are parsed only once per request.
+fn parsed_payload(req: &WebRequest) -> Option<Arc<Value>> {
+    if is_form(req) {
+        serde_json::from_str(&json_payload(req)).ok().map(Arc::new)
+    } else {
+        req.json()
+    }
+}
+
+fn is_form(req: &WebRequest) -> bool {
+    req.headers.get("Content-Type")
+        .map(|ct| ct.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false)
+}
+
+
+/// Fetch the IP ranges webhooks are sent from with the GitHub meta API.
+fn fetch_hook_ranges(url: &str) -> Result<Vec<IpCidr>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let meta: Meta = client.get(url)
+        .header("User-Agent", "fisher")
+        .send()?
+        .error_for_status()?
+        .json()?;
+
+    meta.hooks.iter().map(|range| range.parse()).collect()
+}
+
+
+/// Start refreshing the IP ranges of a meta API URL in the background, if
+/// they're not refreshed already. If they can't be fetched the old ones are
+/// kept, and the fetch is retried after the refresh interval.
+fn watch_hook_ranges(url: &str, refresh: Duration) {
+    let mut cache = HOOK_RANGES.lock().unwrap();
+    if let Some(cached) = cache.get_mut(url) {
+        // Use the shortest interval between the hooks using this URL
+        if refresh < cached.refresh {
+            cached.refresh = refresh;
+        }
+        return;
+    }
+
+    cache.insert(url.into(), CachedRanges {
+        ranges: Arc::new(Vec::new()),
+        refresh,
+    });
+
+    let url = url.to_string();
+    thread::spawn(move || loop {
+        let fetched = fetch_hook_ranges(&url);
+
+        let refresh = {
+            let mut cache = HOOK_RANGES.lock().unwrap();
+            let cached = cache.get_mut(&url).unwrap();
+            match fetched {
+                Ok(ranges) => cached.ranges = Arc::new(ranges),
+                Err(err) => err.pretty_print(),
+            }
+            cached.refresh
+        };
+
+        thread::sleep(refresh);
+    });
 }
 
 
@@ -47,21 +173,242 @@ struct PushEvent<'src> {
     head_commit: PushCommit<'src>,
 }
 
+#[derive(Deserialize)]
+struct KeywordsEvent {
+    head_commit: Option<CommitMessage>,
+    pull_request: Option<PullRequestTitle>,
+}
+
+#[derive(Deserialize)]
+struct CommitMessage {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequestTitle {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct ActorEvent<'src> {
+    #[serde(borrow)]
+    sender: Option<Sender<'src>>,
+}
+
+#[derive(Deserialize)]
+struct Sender<'src> {
+    login: &'src str,
+}
+
+#[derive(Deserialize)]
+struct PushRef<'src> {
+    #[serde(rename = "ref")]
+    git_ref: &'src str,
+}
+
 #[derive(Deserialize)]
 struct PushCommit<'src> {
     id: &'src str,
 }
 
 
+#[derive(Deserialize)]
+struct PullRequestEvent {
+    action: String,
+    number: u64,
+    pull_request: PullRequest,
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    head: PullRequestBranch,
+    base: PullRequestBranch,
+}
+
+#[derive(Deserialize)]
+struct PullRequestBranch {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    sha: String,
+}
+
+
+#[derive(Deserialize)]
+struct IssuesEvent {
+    action: String,
+    issue: Issue,
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    number: u64,
+}
+
+
+#[derive(Deserialize)]
+struct ReleaseEvent {
+    action: String,
+    release: Release,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
+}
+
+
 #[derive(Debug, Deserialize)]
 pub struct GitHubProvider {
-    secret: Option<String>,
+    secret: Option<utils::Secrets>,
     events: Option<Vec<String>>,
+    refs: Option<Vec<String>>,
+    paths: Option<Vec<String>>,
+    routes: Option<BTreeMap<String, Vec<String>>>,
+    tags: Option<Vec<String>>,
+    versions: Option<String>,
+    actors: Option<Vec<String>>,
+    skip_keywords: Option<Vec<String>>,
+
+    #[serde(default)]
+    verify_source_ip: bool,
+    #[serde(default = "default_meta_url")]
+    meta_url: String,
+    #[serde(default = "default_meta_refresh")]
+    meta_refresh: TimeString,
+
+    #[serde(skip)]
+    ref_patterns: Vec<Pattern>,
+    #[serde(skip)]
+    path_filter: Option<PathFilter>,
+    #[serde(skip)]
+    path_routes: Option<PathRoutes>,
+    #[serde(skip)]
+    tag_filter: Option<TagFilter>,
+}
+
+impl GitHubProvider {
+    /// Check if the request comes from one of the IP ranges GitHub sends
+    /// webhooks from, with the ranges fetched so far.
+    fn is_github_source(&self, source: &IpAddr) -> bool {
+        let ranges = HOOK_RANGES.lock().unwrap()
+            .get(&self.meta_url)
+            .map(|cached| cached.ranges.clone());
+
+        ranges
+            .map(|ranges| ranges.iter().any(|r| r.contains(source)))
+            .unwrap_or(false)
+    }
+
+    /// Check if this is a push event to a ref which shouldn't be processed.
+    fn is_skipped_push(&self, event: &str, payload: &Value) -> bool {
+        if event != "push" || self.refs.is_none() {
+            return false;
+        }
+
+        // `*` doesn't match slashes, while `**` does
+        let options = MatchOptions {
+            require_literal_separator: true,
+            .. MatchOptions::new()
+        };
+
+        match PushRef::deserialize(payload) {
+            Ok(parsed) => !self.ref_patterns.iter().any(|pattern| {
+                pattern.matches_with(parsed.git_ref, options)
+            }),
+            Err(_) => true,
+        }
+    }
+
+    /// Check if this is a push event which didn't change any of the paths
+    /// the hook is interested in.
+    fn is_skipped_paths(&self, event: &str, payload: &Value) -> bool {
+        let filter = match self.path_filter {
+            Some(ref filter) if event == "push" => filter,
+            _ => return false,
+        };
+
+        match changed_files(payload) {
+            Ok(files) => !filter.matches(&files),
+            Err(_) => true,
+        }
+    }
+
+    /// Check if the user who triggered the event is not allowed to execute
+    /// the hook.
+    fn is_skipped_actor(&self, payload: &Value) -> bool {
+        let actors = match self.actors {
+            Some(ref actors) => actors,
+            None => return false,
+        };
+
+        // GitHub logins are case insensitive
+        match ActorEvent::deserialize(payload) {
+            Ok(ActorEvent { sender: Some(sender) }) => !actors
+                .iter()
+                .any(|actor| actor.eq_ignore_ascii_case(sender.login)),
+            _ => true,
+        }
+    }
+
+    /// Check if the head commit of a push or the title of a pull request
+    /// contains one of the skip keywords.
+    fn has_skip_keyword(&self, event: &str, payload: &Value) -> bool {
+        let skip_keywords = match self.skip_keywords {
+            Some(ref skip_keywords) => skip_keywords,
+            None => return false,
+        };
+
+        let parsed = match KeywordsEvent::deserialize(payload) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        let text = match event {
+            "push" => parsed.head_commit.map(|commit| commit.message),
+            "pull_request" => parsed.pull_request.map(|pr| pr.title),
+            _ => None,
+        };
+
+        text.map(|text| keywords::contains_skip_keyword(skip_keywords, &text))
+            .unwrap_or(false)
+    }
+
+    /// Get the scripts a push event should be dispatched to, if routes
+    /// are configured.
+    fn routed_scripts(
+        &self, event: &str, payload: &Value,
+    ) -> Option<Vec<String>> {
+        let routes = match self.path_routes {
+            Some(ref routes) if event == "push" => routes,
+            _ => return None,
+        };
+
+        Some(changed_files(payload)
+            .map(|files| routes.route(&files))
+            .unwrap_or_default())
+    }
+
+    /// Check if this is a push event of a tag which shouldn't be processed.
+    /// Pushes to branches are not affected.
+    fn is_skipped_tag(&self, event: &str, payload: &Value) -> bool {
+        let filter = match self.tag_filter {
+            Some(ref filter) if event == "push" => filter,
+            _ => return false,
+        };
+
+        match PushRef::deserialize(payload) {
+            Ok(parsed) => tags::tag_name(parsed.git_ref)
+                .map(|tag| !filter.matches(tag))
+                .unwrap_or(false),
+            Err(_) => true,
+        }
+    }
 }
 
 impl ProviderTrait for GitHubProvider {
     fn new(input: &str) -> Result<GitHubProvider> {
-        let inst: GitHubProvider = serde_json::from_str(input)?;
+        let mut inst: GitHubProvider = serde_json::from_str(input)?;
 
         if let Some(ref events) = inst.events {
             // Check if the events exists
@@ -75,56 +422,130 @@ impl ProviderTrait for GitHubProvider {
             }
         }
 
-        Ok(inst)
-    }
+        if let Some(ref refs) = inst.refs {
+            for git_ref in refs {
+                inst.ref_patterns.push(Pattern::new(git_ref).chain_err(|| {
+                    ErrorKind::ProviderGitHubInvalidRefPattern(git_ref.clone())
+                })?);
+            }
+        }
 
-    fn validate(&self, request: &Request) -> RequestType {
-        let req;
-        if let Request::Web(ref inner) = *request {
-            req = inner;
-        } else {
-            return RequestType::Invalid;
+        if let Some(ref paths) = inst.paths {
+            inst.path_filter = Some(PathFilter::new(paths)?);
         }
 
-        // Check if the correct headers are present
-        for header in GITHUB_HEADERS.iter() {
-            if !req.headers.contains_key(*header) {
-                return RequestType::Invalid;
-            }
+        if let Some(ref routes) = inst.routes {
+            inst.path_routes = Some(PathRoutes::new(routes)?);
+        }
+
+        if inst.tags.is_some() || inst.versions.is_some() {
+            inst.tag_filter = Some(TagFilter::new(
+                inst.tags.as_deref(),
+                inst.versions.as_deref(),
+            )?);
+        }
+
+        if inst.verify_source_ip {
+            watch_hook_ranges(
+                &inst.meta_url, Duration::from_secs(inst.meta_refresh.as_u64()),
+            );
         }
 
+        Ok(inst)
+    }
+
+    fn requirements<'a>(&'a self) -> Requirements<'a> {
+        let requirements = Requirements::new().headers(GITHUB_HEADERS);
+
         // Check the signature only if a secret key was provided
         if let Some(ref secret) = self.secret {
-            // Check if the signature is valid
-            let signature = &req.headers["X-Hub-Signature"];
-            if !verify_signature(secret, &req.body, signature) {
+            requirements.signature(Signature {
+                header: "X-Hub-Signature",
+                algorithms: SIGNATURE_ALGORITHMS,
+                encoding: SignatureEncoding::Hex,
+                secrets: secret.as_slice(),
+            })
+        } else {
+            requirements
+        }
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, trace: &mut ValidationTrace,
+    ) -> RequestType {
+        // Check if the request comes from GitHub
+        if self.verify_source_ip {
+            let valid = self.is_github_source(&req.source);
+            if !trace.check("source_ip", valid) {
                 return RequestType::Invalid;
             }
         }
 
         // Check if the event is valid
         let event = &req.headers["X-GitHub-Event"];
-        if !(GITHUB_EVENTS.contains(&event.as_ref()) || *event == "ping") {
+        let known = GITHUB_EVENTS.contains(&event.as_ref()) || *event == "ping";
+        if !trace.check("event_known", known) {
             return RequestType::Invalid;
         }
 
         // Check if the event should be accepted
         if let Some(ref events) = self.events {
-            if !(events.contains(event) || *event == "ping") {
+            let allowed = events.contains(event) || *event == "ping";
+            if !trace.check("event_allowed", allowed) {
                 return RequestType::Invalid;
             }
         }
 
         // Check if the JSON in the body is valid
-        if serde_json::from_str::<serde_json::Value>(&req.body).is_err() {
+        let payload = parsed_payload(req);
+        if !trace.check("json_body", payload.is_some()) {
             return RequestType::Invalid;
         }
+        let payload = payload.unwrap();
 
         // The "ping" event is a ping (doh!)
         if event == "ping" {
             return RequestType::Ping;
         }
 
+        // Events triggered by other users are accepted, but the hook is not
+        // executed
+        let skipped = self.is_skipped_actor(&payload);
+        if !trace.check("actor_allowed", !skipped) {
+            return RequestType::Ping;
+        }
+
+        // The author of the event asked not to execute the hook
+        let skipped = self.has_skip_keyword(event, &payload);
+        if !trace.check("no_skip_keyword", !skipped) {
+            return RequestType::Ping;
+        }
+
+        // Pushes to other refs are accepted, but the hook is not executed
+        if !trace.check("ref_allowed", !self.is_skipped_push(event, &payload)) {
+            return RequestType::Ping;
+        }
+
+        // The same happens for pushes which didn't change the paths
+        let skipped = self.is_skipped_paths(event, &payload);
+        if !trace.check("paths_changed", !skipped) {
+            return RequestType::Ping;
+        }
+
+        // Or if no script is interested in the changed paths
+        let routed = self.routed_scripts(event, &payload)
+            .map(|scripts| !scripts.is_empty())
+            .unwrap_or(true);
+        if !trace.check("routes_matched", routed) {
+            return RequestType::Ping;
+        }
+
+        // And for tags which don't match the configured filters
+        let skipped = self.is_skipped_tag(event, &payload);
+        if !trace.check("tag_allowed", !skipped) {
+            return RequestType::Ping;
+        }
+
         // Process the hook in the other cases
         RequestType::ExecuteHook
     }
@@ -140,68 +561,140 @@ impl ProviderTrait for GitHubProvider {
         b.add_env("EVENT", &req.headers["X-GitHub-Event"]);
         b.add_env("DELIVERY_ID", &req.headers["X-GitHub-Delivery"]);
 
-        // Add specific environment variables for the `push` event
+        // Scripts can read any field they need from the full payload
+        let raw_payload = json_payload(req);
+        b.data_file_with(
+            "PAYLOAD_FILE", "payload.json", raw_payload.as_bytes(),
+        )?;
+        let payload = match parsed_payload(req) {
+            Some(payload) => payload,
+            None => Arc::new(serde_json::from_str(&raw_payload)?),
+        };
+
+        // Add specific environment variables for some whitelisted events
         let event = &req.headers["X-GitHub-Event"];
+        if event == "push" {
+            if let Ok(files) = changed_files(&payload) {
+                let file = b.data_file_as("CHANGED_FILES", "changed-files.txt")?;
+                for changed in &files {
+                    writeln!(file, "{}", changed)?;
+                }
+            }
+
+            let tag = PushRef::deserialize(&*payload)
+                .ok()
+                .and_then(|parsed| tags::tag_name(parsed.git_ref));
+            if let Some(tag) = tag {
+                tags::add_tag_env(b, tag);
+            }
+        }
+
         if self.events.as_ref().and_then(|e| Some(e.contains(event))).unwrap_or(false) {
-            if *event == "push" {
-                let parsed: PushEvent = serde_json::from_str(&req.body)?;
-                b.add_env("PUSH_REF", parsed.git_ref);
-                b.add_env("PUSH_HEAD", parsed.head_commit.id);
+            match event.as_str() {
+                "push" => {
+                    let parsed = PushEvent::deserialize(&*payload)?;
+                    b.add_env("PUSH_REF", parsed.git_ref);
+                    b.add_env("PUSH_HEAD", parsed.head_commit.id);
+                }
+                "pull_request" => {
+                    let parsed = PullRequestEvent::deserialize(&*payload)?;
+                    let pr = parsed.pull_request;
+                    b.add_env("PULL_REQUEST_NUMBER", parsed.number.to_string());
+                    b.add_env("PULL_REQUEST_ACTION", parsed.action);
+                    b.add_env("PULL_REQUEST_HEAD_REF", pr.head.git_ref);
+                    b.add_env("PULL_REQUEST_HEAD_SHA", pr.head.sha);
+                    b.add_env("PULL_REQUEST_BASE_REF", pr.base.git_ref);
+                    b.add_env("PULL_REQUEST_BASE_SHA", pr.base.sha);
+                }
+                "issues" => {
+                    let parsed = IssuesEvent::deserialize(&*payload)?;
+                    b.add_env("ISSUE_NUMBER", parsed.issue.number.to_string());
+                    b.add_env("ISSUE_ACTION", parsed.action);
+                }
+                "release" => {
+                    let parsed = ReleaseEvent::deserialize(&*payload)?;
+                    let release = parsed.release;
+                    b.add_env("RELEASE_ACTION", parsed.action);
+                    b.add_env("RELEASE_TAG", release.tag_name);
+                    b.add_env("RELEASE_DRAFT", release.draft.to_string());
+                    b.add_env(
+                        "RELEASE_PRERELEASE", release.prerelease.to_string(),
+                    );
+                }
+                _ => {}
             }
         }
 
         Ok(())
     }
-}
 
+    fn delivery_id(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            req.headers.get("X-GitHub-Delivery").cloned()
+        } else {
+            None
+        }
+    }
 
-fn verify_signature(secret: &str, payload: &str, raw_signature: &str) -> bool {
-    type HmacSha1 = Hmac<sha1::Sha1>;
+    fn event_name(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            req.headers.get("X-GitHub-Event").cloned()
+        } else {
+            None
+        }
+    }
 
-    // The signature must have a =
-    if !raw_signature.contains('=') {
-        return false;
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        self.events.clone()
     }
 
-    // Split the raw signature to get the algorithm and the signature
-    let splitted: Vec<&str> = raw_signature.split('=').collect();
-    let algorithm = &splitted[0];
-    let hex_signature = splitted
-        .iter()
-        .skip(1)
-        .cloned()
-        .collect::<Vec<&str>>()
-        .join("=");
+    fn response_body(
+        &self, r: &Request, request_type: RequestType,
+    ) -> Option<serde_json::Value> {
+        let req = r.web().ok()?;
+        let event = req.headers.get("X-GitHub-Event")?;
+
+        let payload = parsed_payload(req)?;
+        if request_type == RequestType::Ping
+            && (self.is_skipped_actor(&payload)
+                || self.has_skip_keyword(event, &payload)
+                || self.is_skipped_push(event, &payload)
+                || self.is_skipped_paths(event, &payload)
+                || self.is_skipped_tag(event, &payload)
+                || self.routed_scripts(event, &payload) == Some(Vec::new()))
+        {
+            Some(json!({"status": "skipped"}))
+        } else {
+            None
+        }
+    }
 
-    // Convert the signature from hex
-    let signature = if let Ok(converted) = utils::from_hex(&hex_signature) {
-        converted
-    } else {
-        // This is not hex
-        return false;
-    };
+    fn fan_out(&self, r: &Request) -> Option<Vec<String>> {
+        let req = r.web().ok()?;
+        let event = req.headers.get("X-GitHub-Event")?;
 
-    // Only SHA-1 is supported
-    if *algorithm != "sha1" {
-        return false;
+        let payload = parsed_payload(req)?;
+        self.routed_scripts(event, &payload)
     }
-
-    // Verify the HMAC signature
-    let mut mac = HmacSha1::new_varkey(secret.as_bytes()).unwrap();
-    mac.input(payload.as_bytes());
-    mac.verify(&signature).is_ok()
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tiny_http;
+    use url::form_urlencoded;
+
     use utils::testing::*;
+    use utils::{to_hex, HmacAlgorithm};
     use requests::RequestType;
     use web::WebRequest;
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
 
-    use super::{verify_signature, GitHubProvider, GITHUB_EVENTS};
+    use super::{GitHubProvider, GITHUB_EVENTS};
 
 
     #[test]
@@ -210,8 +703,15 @@ mod tests {
         for right in &[
             r#"{}"#,
             r#"{"secret": "abcde"}"#,
+            r#"{"verify_source_ip": true, "meta_refresh": "10m"}"#,
             r#"{"events": ["push", "fork"]}"#,
             r#"{"secret": "abcde", "events": ["push", "fork"]}"#,
+            r#"{"refs": ["refs/heads/master", "refs/tags/*"]}"#,
+            r#"{"paths": ["services/api/**", "*.toml"]}"#,
+            r#"{"tags": ["v*"], "versions": "^1.0"}"#,
+            r#"{"routes": {"deploy-api": ["services/api/**"]}}"#,
+            r#"{"actors": ["dependabot[bot]", "pietroalbini"]}"#,
+            r#"{"skip_keywords": ["[skip deploy]"]}"#,
         ] {
             assert!(GitHubProvider::new(right).is_ok(), right.to_string());
         }
@@ -227,6 +727,18 @@ mod tests {
             r#"{"events": [12345]}"#,
             r#"{"events": [true]}"#,
             r#"{"events": ["invalid_event"]}"#,
+            r#"{"refs": "refs/heads/master"}"#,
+            r#"{"refs": ["refs/heads/***"]}"#,
+            r#"{"paths": "services/**"}"#,
+            r#"{"paths": ["services/***"]}"#,
+            r#"{"tags": ["v***"]}"#,
+            r#"{"routes": ["services/api/**"]}"#,
+            r#"{"actors": "pietroalbini"}"#,
+            r#"{"skip_keywords": "[skip deploy]"}"#,
+            r#"{"routes": {"deploy-api": ["services/***"]}}"#,
+            r#"{"versions": "not a range"}"#,
+            r#"{"verify_source_ip": "yes"}"#,
+            r#"{"meta_refresh": "1x"}"#,
         ] {
             assert!(GitHubProvider::new(wrong).is_err(), wrong.to_string());
         }
@@ -271,6 +783,7 @@ mod tests {
         let mut req = dummy_web_request();
         req.headers.insert("X-GitHub-Event".into(), "ping".into());
         req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
+        req.body = r#"{"zen": "Keep it simple"}"#.into();
 
         let provider = GitHubProvider::new("{}").unwrap();
         let mut b = EnvBuilder::dummy();
@@ -279,8 +792,22 @@ mod tests {
         assert_eq!(b.dummy_data().env, hashmap! {
             "EVENT".into() => "ping".into(),
             "DELIVERY_ID".into() => "12345".into(),
+            "PAYLOAD_FILE".into() => "payload.json".into(),
+        });
+        assert_eq!(b.dummy_data().files, hashmap! {
+            "payload.json".into() => b"{\"zen\": \"Keep it simple\"}".to_vec(),
         });
-        assert_eq!(b.dummy_data().files, hashmap!());
+    }
+
+
+    #[test]
+    fn test_delivery_id() {
+        let provider = GitHubProvider::new("{}").unwrap();
+        assert_eq!(provider.delivery_id(&dummy_web_request().into()), None);
+
+        let mut req = dummy_web_request();
+        req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
+        assert_eq!(provider.delivery_id(&req.into()), Some("12345".into()));
     }
 
 
@@ -294,7 +821,7 @@ mod tests {
             "head_commit": json!({
                 "id": "deadbeef",
             }),
-        })).unwrap();
+        })).unwrap().into_bytes();
 
         req
     }
@@ -345,27 +872,423 @@ mod tests {
     }
 
 
+    fn dummy_event_request(event: &str, body: ::serde_json::Value) -> WebRequest {
+        let mut req = dummy_web_request();
+
+        req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
+        req.headers.insert("X-GitHub-Event".into(), event.into());
+        req.body = ::serde_json::to_string(&body).unwrap().into_bytes();
+
+        req
+    }
+
+
+    fn build_env_whitelisted(req: WebRequest, event: &str) -> EnvBuilder<'static> {
+        let provider = GitHubProvider::new(
+            &format!(r#"{{"events": ["{}"]}}"#, event)
+        ).unwrap();
+
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
+        b
+    }
+
+
     #[test]
-    fn test_verify_signature() {
-        // Check if the function allows invalid signatures
-        for signature in &[
-            "invalid",         // No algorithm
-            "invalid=invalid", // Invalid algorithm
-            "sha1=g",          // The signature is not hex
-            // Invalid signature (the first "e" should be "f")
-            "sha1=e75efc0f29bf50c23f99b30b86f7c78fdaf5f11d",
-        ] {
-            assert!(
-                !verify_signature("secret", "payload", signature),
-                signature.to_string()
-            );
+    fn test_build_env_event_pull_request() {
+        let req = dummy_event_request("pull_request", json!({
+            "action": "opened",
+            "number": 42,
+            "pull_request": {
+                "head": {"ref": "feature", "sha": "deadbeef"},
+                "base": {"ref": "master", "sha": "cafebabe"},
+            },
+        }));
+        let b = build_env_whitelisted(req, "pull_request");
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "pull_request".into(),
+            "DELIVERY_ID".into() => "12345".into(),
+            "PAYLOAD_FILE".into() => "payload.json".into(),
+            "PULL_REQUEST_NUMBER".into() => "42".into(),
+            "PULL_REQUEST_ACTION".into() => "opened".into(),
+            "PULL_REQUEST_HEAD_REF".into() => "feature".into(),
+            "PULL_REQUEST_HEAD_SHA".into() => "deadbeef".into(),
+            "PULL_REQUEST_BASE_REF".into() => "master".into(),
+            "PULL_REQUEST_BASE_SHA".into() => "cafebabe".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_build_env_event_issues() {
+        let req = dummy_event_request("issues", json!({
+            "action": "closed",
+            "issue": {"number": 10},
+        }));
+        let b = build_env_whitelisted(req, "issues");
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "issues".into(),
+            "DELIVERY_ID".into() => "12345".into(),
+            "PAYLOAD_FILE".into() => "payload.json".into(),
+            "ISSUE_NUMBER".into() => "10".into(),
+            "ISSUE_ACTION".into() => "closed".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_build_env_event_release() {
+        let req = dummy_event_request("release", json!({
+            "action": "published",
+            "release": {"tag_name": "v1.0.0", "draft": false, "prerelease": true},
+        }));
+        let b = build_env_whitelisted(req, "release");
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "release".into(),
+            "DELIVERY_ID".into() => "12345".into(),
+            "PAYLOAD_FILE".into() => "payload.json".into(),
+            "RELEASE_ACTION".into() => "published".into(),
+            "RELEASE_TAG".into() => "v1.0.0".into(),
+            "RELEASE_DRAFT".into() => "false".into(),
+            "RELEASE_PRERELEASE".into() => "true".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_refs_filter() {
+        let provider = GitHubProvider::new(
+            r#"{"refs": ["refs/heads/master", "refs/tags/*"]}"#
+        ).unwrap();
+
+        let push = |git_ref: &str| {
+            let mut req = dummy_event_request("push", json!({"ref": git_ref}));
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        assert_eq!(push("refs/heads/master"), (RequestType::ExecuteHook, None));
+        assert_eq!(push("refs/tags/v1.0"), (RequestType::ExecuteHook, None));
+        assert_eq!(push("refs/heads/feature"), skipped);
+        assert_eq!(push("refs/tags/nested/v1.0"), skipped);
+
+        // Other events are not filtered
+        let mut req = dummy_event_request("fork", json!({}));
+        req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+        assert_eq!(provider.validate(&req.into()), RequestType::ExecuteHook);
+    }
+
+
+    #[test]
+    fn test_paths_filter() {
+        let provider = GitHubProvider::new(
+            r#"{"paths": ["services/api/**"]}"#
+        ).unwrap();
+
+        let push = |files: &[&str]| {
+            let mut req = dummy_event_request("push", json!({
+                "ref": "refs/heads/master",
+                "commits": [{"added": [], "modified": files, "removed": []}],
+            }));
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        assert_eq!(
+            push(&["services/api/main.rs", "README.md"]),
+            (RequestType::ExecuteHook, None)
+        );
+        assert_eq!(push(&["services/web/index.html"]), skipped);
+        assert_eq!(push(&[]), skipped);
+
+        // Other events are not filtered
+        let mut req = dummy_event_request("fork", json!({}));
+        req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+        assert_eq!(provider.validate(&req.into()), RequestType::ExecuteHook);
+    }
+
+
+    #[test]
+    fn test_actors_filter() {
+        let provider = GitHubProvider::new(
+            r#"{"actors": ["dependabot[bot]", "PietroAlbini"]}"#
+        ).unwrap();
+
+        let event = |name: &str, body: ::serde_json::Value| {
+            let mut req = dummy_event_request(name, body);
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+        let sent_by = |login: &str| json!({
+            "ref": "refs/heads/master",
+            "sender": {"login": login},
+        });
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        assert_eq!(
+            event("push", sent_by("dependabot[bot]")),
+            (RequestType::ExecuteHook, None)
+        );
+        assert_eq!(
+            event("issues", sent_by("pietroalbini")),
+            (RequestType::ExecuteHook, None)
+        );
+        assert_eq!(event("push", sent_by("someone-else")), skipped);
+        assert_eq!(event("push", json!({"ref": "refs/heads/master"})), skipped);
+    }
+
+
+    #[test]
+    fn test_skip_keywords() {
+        let provider = GitHubProvider::new(
+            r#"{"skip_keywords": ["[skip deploy]", "[wip]"]}"#
+        ).unwrap();
+
+        let event = |name: &str, body: ::serde_json::Value| {
+            let mut req = dummy_event_request(name, body);
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+        let push = |message: &str| json!({
+            "ref": "refs/heads/master",
+            "head_commit": {"id": "deadbeef", "message": message},
+        });
+        let pr = |title: &str| json!({"pull_request": {"title": title}});
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        let executed = (RequestType::ExecuteHook, None);
+        assert_eq!(event("push", push("Fix typo [skip deploy]")), skipped);
+        assert_eq!(event("push", push("Fix typo")), executed);
+        assert_eq!(event("pull_request", pr("[wip] Rewrite")), skipped);
+        assert_eq!(event("pull_request", pr("Rewrite")), executed);
+
+        // Other events don't contain a message
+        assert_eq!(event("issues", json!({"title": "[wip]"})), executed);
+
+        // Branch deletions don't have an head commit
+        let deleted = json!({"ref": "refs/heads/old", "head_commit": null});
+        assert_eq!(event("push", deleted), executed);
+    }
+
+
+    #[test]
+    fn test_routes() {
+        let provider = GitHubProvider::new(r#"{"routes": {
+            "deploy-api": ["services/api/**"],
+            "deploy-web": ["services/web/**"]
+        }}"#).unwrap();
+
+        let push = |files: &[&str]| {
+            let mut req = dummy_event_request("push", json!({
+                "ref": "refs/heads/master",
+                "commits": [{"added": [], "modified": files, "removed": []}],
+            }));
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.fan_out(&req))
+        };
+
+        assert_eq!(
+            push(&["services/api/main.rs", "services/web/index.html"]),
+            (RequestType::ExecuteHook, Some(vec![
+                "deploy-api".into(), "deploy-web".into(),
+            ]))
+        );
+        assert_eq!(
+            push(&["services/web/index.html"]),
+            (RequestType::ExecuteHook, Some(vec!["deploy-web".into()]))
+        );
+
+        // Pushes no script is interested in are skipped
+        assert_eq!(push(&["README.md"]), (RequestType::Ping, Some(vec![])));
+
+        // Other events are not routed
+        let mut req = dummy_event_request("fork", json!({}));
+        req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+        let req = req.into();
+        assert_eq!(provider.validate(&req), RequestType::ExecuteHook);
+        assert_eq!(provider.fan_out(&req), None);
+    }
+
+
+    #[test]
+    fn test_tags_filter() {
+        let provider = GitHubProvider::new(
+            r#"{"tags": ["v*"], "versions": ">= 1.0.0"}"#
+        ).unwrap();
+
+        let push = |git_ref: &str| {
+            let mut req = dummy_event_request("push", json!({"ref": git_ref}));
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        assert_eq!(push("refs/tags/v1.2.0"), (RequestType::ExecuteHook, None));
+        assert_eq!(push("refs/tags/v2.0.0-rc.1"), skipped);
+        assert_eq!(push("refs/tags/v0.9.0"), skipped);
+        assert_eq!(push("refs/tags/1.2.0"), skipped);
+
+        // Pushes to branches are not filtered
+        assert_eq!(push("refs/heads/master"), (RequestType::ExecuteHook, None));
+    }
+
+
+    #[test]
+    fn test_build_env_tag() {
+        let req = dummy_event_request("push", json!({
+            "ref": "refs/tags/v1.2.3",
+        }));
+
+        let provider = GitHubProvider::new("{}").unwrap();
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
+
+        let env = &b.dummy_data().env;
+        assert_eq!(env.get("TAG"), Some(&"v1.2.3".into()));
+        assert_eq!(env.get("VERSION_MAJOR"), Some(&"1".into()));
+        assert_eq!(env.get("VERSION_MINOR"), Some(&"2".into()));
+        assert_eq!(env.get("VERSION_PATCH"), Some(&"3".into()));
+        assert_eq!(env.get("VERSION_PRERELEASE"), Some(&"false".into()));
+    }
+
+
+    #[test]
+    fn test_build_env_changed_files() {
+        let req = dummy_event_request("push", json!({
+            "ref": "refs/heads/master",
+            "commits": [
+                {"added": ["b.txt"], "modified": ["a.txt"], "removed": []},
+                {"added": [], "modified": ["a.txt"], "removed": ["c.txt"]},
+            ],
+        }));
+
+        let provider = GitHubProvider::new("{}").unwrap();
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
+
+        assert_eq!(
+            b.dummy_data().env.get("CHANGED_FILES"),
+            Some(&"changed-files.txt".into())
+        );
+        assert_eq!(
+            b.dummy_data().files.get("changed-files.txt"),
+            Some(&b"a.txt\nb.txt\nc.txt\n".to_vec())
+        );
+    }
+
+
+    #[test]
+    fn test_form_encoded_payload() {
+        let provider = GitHubProvider::new(
+            r#"{"secret": "secret", "events": ["push"]}"#
+        ).unwrap();
+
+        let payload = json!({
+            "ref": "refs/heads/master",
+            "head_commit": {"id": "deadbeef"},
+        }).to_string();
+        let mut req = dummy_event_request("push", json!({}));
+        req.headers.insert(
+            "Content-Type".into(), "application/x-www-form-urlencoded".into(),
+        );
+        req.body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("payload", &payload)
+            .finish()
+            .into_bytes();
+
+        // The signature is calculated over the raw body
+        let signature = HmacAlgorithm::Sha1.sign(b"secret", &req.body);
+        req.headers.insert(
+            "X-Hub-Signature".into(), format!("sha1={}", to_hex(&signature)),
+        );
+        let req = req.into();
+        assert_eq!(provider.validate(&req), RequestType::ExecuteHook);
+
+        // The JSON payload is extracted from the form
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req, &mut b).unwrap();
+        assert_eq!(b.dummy_data().env["PUSH_REF"], "refs/heads/master");
+        assert_eq!(b.dummy_data().files["payload.json"], payload.as_bytes());
+
+        // Forms without the payload are rejected
+        let mut req = dummy_event_request("push", json!({}));
+        req.headers.insert(
+            "Content-Type".into(), "application/x-www-form-urlencoded".into(),
+        );
+        req.body = "a=b".into();
+        let signature = HmacAlgorithm::Sha1.sign(b"secret", &req.body);
+        req.headers.insert(
+            "X-Hub-Signature".into(), format!("sha1={}", to_hex(&signature)),
+        );
+        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_verify_source_ip() {
+        // Serve the meta API only once, to check the ranges are cached
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let meta_url = format!("http://{}/meta", server.server_addr());
+        thread::spawn(move || {
+            let req = server.recv().unwrap();
+            req.respond(tiny_http::Response::from_string(
+                json!({"hooks": ["127.0.0.0/8", "::1/128"]}).to_string()
+            )).unwrap();
+        });
+
+        let provider = GitHubProvider::new(&json!({
+            "verify_source_ip": true,
+            "meta_url": meta_url,
+        }).to_string()).unwrap();
+
+        let from = |ip: &str| {
+            let mut req = dummy_event_request("fork", json!({}));
+            req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+            req.source = ip.parse().unwrap();
+            provider.validate(&req.into())
+        };
+
+        // The ranges are fetched in the background
+        let start = Instant::now();
+        while from("127.0.0.1") != RequestType::ExecuteHook {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
         }
 
-        // This is known to be right
-        assert!(verify_signature(
-            "secret",
-            "payload",
-            "sha1=f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d"
-        ));
+        assert_eq!(from("127.0.0.1"), RequestType::ExecuteHook);
+        assert_eq!(from("::1"), RequestType::ExecuteHook);
+        assert_eq!(from("10.0.0.1"), RequestType::Invalid);
+
+        // Requests are rejected if the ranges can't be fetched
+        let provider = GitHubProvider::new(&json!({
+            "verify_source_ip": true,
+            "meta_url": "http://127.0.0.1:1/meta",
+        }).to_string()).unwrap();
+        let mut req = dummy_event_request("fork", json!({}));
+        req.headers.insert("X-Hub-Signature".into(), "sha1=0".into());
+        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
     }
 }
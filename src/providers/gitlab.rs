@@ -13,9 +13,17 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use serde_json;
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::{self, Value};
 
 use providers::prelude::*;
+use providers::Token;
+use providers::keywords;
+use providers::paths::{changed_files, PathFilter, PathRoutes};
+use providers::tags::{self, TagFilter};
+use utils;
 use common::prelude::*;
 
 
@@ -24,22 +32,152 @@ lazy_static! {
         "Push", "Tag Push", "Issue", "Note", "Merge Request", "Wiki Page",
         "Build", "Pipeline", "Confidential Issue",
     ];
-
-    static ref GITLAB_HEADERS: Vec<&'static str> = vec![
-        "X-Gitlab-Event",
-    ];
 }
 
 
+static GITLAB_HEADERS: &[&str] = &["X-Gitlab-Event"];
+
+
 #[derive(Debug, Deserialize)]
 pub struct GitLabProvider {
-    secret: Option<String>,
+    secret: Option<utils::Secrets>,
     events: Option<Vec<String>>,
+    paths: Option<Vec<String>>,
+    routes: Option<BTreeMap<String, Vec<String>>>,
+    tags: Option<Vec<String>>,
+    versions: Option<String>,
+    actors: Option<Vec<String>>,
+    skip_keywords: Option<Vec<String>>,
+
+    #[serde(skip)]
+    path_filter: Option<PathFilter>,
+    #[serde(skip)]
+    path_routes: Option<PathRoutes>,
+    #[serde(skip)]
+    tag_filter: Option<TagFilter>,
+}
+
+
+/// The user who triggered the event: push events contain only the username,
+/// while the other ones contain the details of the user.
+#[derive(Deserialize)]
+struct ActorEvent {
+    user_username: Option<String>,
+    user: Option<User>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+
+/// The texts which can contain skip keywords: the message of the pushed
+/// commit, or the title of the merge request.
+#[derive(Deserialize)]
+struct KeywordsEvent {
+    after: Option<String>,
+    #[serde(default)]
+    commits: Vec<KeywordsCommit>,
+    object_attributes: Option<KeywordsAttributes>,
+}
+
+#[derive(Deserialize)]
+struct KeywordsCommit {
+    id: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct KeywordsAttributes {
+    title: Option<String>,
+}
+
+
+#[derive(Deserialize)]
+struct PushRef<'src> {
+    #[serde(rename = "ref")]
+    git_ref: &'src str,
+}
+
+
+/// Get the name of the tag pushed by a "Tag Push" event.
+fn pushed_tag(payload: &Value) -> Option<&str> {
+    PushRef::deserialize(payload)
+        .ok()
+        .and_then(|parsed| tags::tag_name(parsed.git_ref))
+}
+
+impl GitLabProvider {
+    /// Check if the user who triggered the event is allowed to execute the
+    /// hook.
+    fn is_allowed_actor(&self, payload: &Value) -> bool {
+        let actors = match self.actors {
+            Some(ref actors) => actors,
+            None => return true,
+        };
+
+        let parsed = match ActorEvent::deserialize(payload) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        let user = parsed.user;
+        let username = parsed.user_username
+            .or_else(|| user.map(|user| user.username));
+
+        // GitLab usernames are case insensitive
+        username
+            .map(|name| actors.iter().any(|a| a.eq_ignore_ascii_case(&name)))
+            .unwrap_or(false)
+    }
+
+    /// Check if the pushed commit or the title of a merge request contains
+    /// one of the skip keywords.
+    fn has_skip_keyword(&self, event: &str, payload: &Value) -> bool {
+        let skip_keywords = match self.skip_keywords {
+            Some(ref skip_keywords) => skip_keywords,
+            None => return false,
+        };
+
+        let parsed = match KeywordsEvent::deserialize(payload) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        let text = match event {
+            "Push" => {
+                let after = parsed.after;
+                parsed.commits
+                    .into_iter()
+                    .find(|commit| Some(&commit.id) == after.as_ref())
+                    .map(|commit| commit.message)
+            }
+            "Merge Request" => parsed.object_attributes.and_then(|a| a.title),
+            _ => None,
+        };
+
+        text.map(|text| keywords::contains_skip_keyword(skip_keywords, &text))
+            .unwrap_or(false)
+    }
+
+    /// Get the scripts a push event should be dispatched to, if routes
+    /// are configured.
+    fn routed_scripts(
+        &self, event: &str, payload: &Value,
+    ) -> Option<Vec<String>> {
+        let routes = match self.path_routes {
+            Some(ref routes) if event == "Push" => routes,
+            _ => return None,
+        };
+
+        Some(changed_files(payload)
+            .map(|files| routes.route(&files))
+            .unwrap_or_default())
+    }
 }
 
 impl ProviderTrait for GitLabProvider {
     fn new(config: &str) -> Result<Self> {
-        let inst: GitLabProvider = serde_json::from_str(config)?;
+        let mut inst: GitLabProvider = serde_json::from_str(config)?;
 
         // Check the validity of the events
         if let Some(ref events) = inst.events {
@@ -54,50 +192,101 @@ impl ProviderTrait for GitLabProvider {
             }
         }
 
-        Ok(inst)
-    }
+        if let Some(ref paths) = inst.paths {
+            inst.path_filter = Some(PathFilter::new(paths)?);
+        }
 
-    fn validate(&self, request: &Request) -> RequestType {
-        let req;
-        if let Request::Web(ref inner) = *request {
-            req = inner;
-        } else {
-            return RequestType::Invalid;
+        if let Some(ref routes) = inst.routes {
+            inst.path_routes = Some(PathRoutes::new(routes)?);
         }
 
-        // Check if the correct headers are provided
-        for header in GITLAB_HEADERS.iter() {
-            if !req.headers.contains_key(*header) {
-                return RequestType::Invalid;
-            }
+        if inst.tags.is_some() || inst.versions.is_some() {
+            inst.tag_filter = Some(TagFilter::new(
+                inst.tags.as_deref(),
+                inst.versions.as_deref(),
+            )?);
         }
 
-        // Check if the secret token is correct
+        Ok(inst)
+    }
+
+    fn requirements<'a>(&'a self) -> Requirements<'a> {
+        let requirements = Requirements::new()
+            .headers(GITLAB_HEADERS)
+            .json_body();
+
+        // Check the secret token only if one was provided
         if let Some(ref secret) = self.secret {
-            // The header with the token must be present
-            if let Some(token) = req.headers.get("X-Gitlab-Token") {
-                // The token must match
-                if token != secret {
-                    return RequestType::Invalid;
-                }
-            } else {
-                return RequestType::Invalid;
-            }
+            requirements.token(Token {
+                header: "X-Gitlab-Token",
+                secrets: secret,
+            })
+        } else {
+            requirements
         }
+    }
 
+    fn validate_web(
+        &self, req: &WebRequest, trace: &mut ValidationTrace,
+    ) -> RequestType {
         let event = normalize_event_name(&*req.headers["X-Gitlab-Event"]);
 
         // Check if the event should be accepted
         if let Some(ref events) = self.events {
             // The event is whitelisted
-            if !events.contains(&event.to_string()) {
+            let allowed = events.contains(&event.to_string());
+            if !trace.check("event_allowed", allowed) {
                 return RequestType::Invalid;
             }
         }
 
-        // Check if the JSON body is valid
-        if !serde_json::from_str::<serde_json::Value>(&req.body).is_ok() {
+        let payload = if let Some(payload) = req.json() {
+            payload
+        } else {
             return RequestType::Invalid;
+        };
+
+        // Events triggered by other users are ignored
+        if !trace.check("actor_allowed", self.is_allowed_actor(&payload)) {
+            return RequestType::Ping;
+        }
+
+        // The author of the event asked not to execute the hook
+        let skipped = self.has_skip_keyword(event, &payload);
+        if !trace.check("no_skip_keyword", !skipped) {
+            return RequestType::Ping;
+        }
+
+        // Pushes which didn't change any interesting path are ignored
+        if let Some(ref filter) = self.path_filter {
+            if event == "Push" {
+                let changed = changed_files(&payload)
+                    .map(|files| filter.matches(&files))
+                    .unwrap_or(false);
+                if !trace.check("paths_changed", changed) {
+                    return RequestType::Ping;
+                }
+            }
+        }
+
+        // Or if no script is interested in the changed paths
+        let routed = self.routed_scripts(event, &payload)
+            .map(|scripts| !scripts.is_empty())
+            .unwrap_or(true);
+        if !trace.check("routes_matched", routed) {
+            return RequestType::Ping;
+        }
+
+        // The same happens for tags which don't match the filters
+        if let Some(ref filter) = self.tag_filter {
+            if event == "Tag Push" {
+                let allowed = pushed_tag(&payload)
+                    .map(|tag| filter.matches(tag))
+                    .unwrap_or(false);
+                if !trace.check("tag_allowed", allowed) {
+                    return RequestType::Ping;
+                }
+            }
         }
 
         RequestType::ExecuteHook
@@ -117,8 +306,68 @@ impl ProviderTrait for GitLabProvider {
 
         b.add_env("EVENT", event_header);
 
+        // The other variables come from the payload, if it's valid
+        let payload = match req.json() {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+
+        if event_header == "Push" {
+            if let Ok(files) = changed_files(&payload) {
+                let file = b.data_file_as("CHANGED_FILES", "changed-files.txt")?;
+                for changed in &files {
+                    writeln!(file, "{}", changed)?;
+                }
+            }
+        } else if event_header == "Tag Push" {
+            if let Some(tag) = pushed_tag(&payload) {
+                tags::add_tag_env(b, tag);
+            }
+        }
+
         Ok(())
     }
+
+    fn delivery_id(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            req.headers.get("X-Gitlab-Event-UUID").cloned()
+        } else {
+            None
+        }
+    }
+
+    fn event_name(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            req.headers
+                .get("X-Gitlab-Event")
+                .map(|event| normalize_event_name(event).to_string())
+        } else {
+            None
+        }
+    }
+
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        self.events.clone()
+    }
+
+    fn response_body(
+        &self, _req: &Request, request_type: RequestType,
+    ) -> Option<serde_json::Value> {
+        // GitLab doesn't send pings, so they're all skipped events
+        if request_type == RequestType::Ping {
+            Some(json!({"status": "skipped"}))
+        } else {
+            None
+        }
+    }
+
+    fn fan_out(&self, r: &Request) -> Option<Vec<String>> {
+        let req = r.web().ok()?;
+        let event = req.headers.get("X-Gitlab-Event")?;
+
+        let payload = req.json()?;
+        self.routed_scripts(normalize_event_name(event), &payload)
+    }
 }
 
 
@@ -150,7 +399,7 @@ mod tests {
 
         base.headers
             .insert("X-Gitlab-Event".to_string(), "Push Hook".to_string());
-        base.body = r#"{"a": "b"}"#.to_string();
+        base.body = r#"{"a": "b"}"#.into();
 
         base
     }
@@ -162,8 +411,14 @@ mod tests {
         for right in &[
             r#"{}"#,
             r#"{"secret": "abcde"}"#,
+            r#"{"secret": ["abcde", "fghij"]}"#,
             r#"{"events": ["Push", "Issue"]}"#,
             r#"{"secret": "abcde", "events": ["Push", "Issue"]}"#,
+            r#"{"paths": ["src/**", "*.toml"]}"#,
+            r#"{"tags": ["v*"], "versions": "^1.0"}"#,
+            r#"{"routes": {"deploy-api": ["services/api/**"]}}"#,
+            r#"{"actors": ["deploy-bot", "pietroalbini"]}"#,
+            r#"{"skip_keywords": ["[skip deploy]"]}"#,
         ] {
             assert!(GitLabProvider::new(right).is_ok(), right.to_string());
         }
@@ -172,12 +427,21 @@ mod tests {
             // Wrong types
             r#"{"secret": 12345}"#,
             r#"{"secret": true}"#,
+            r#"{"secret": []}"#,
+            r#"{"secret": [12345]}"#,
             r#"{"events": 12345}"#,
             r#"{"events": true}"#,
             r#"{"events": {}}"#,
             r#"{"events": [12345]}"#,
             r#"{"events": [true]}"#,
             r#"{"events": ["invalid_event"]}"#,
+            r#"{"paths": "src/**"}"#,
+            r#"{"paths": ["src/***"]}"#,
+            r#"{"tags": "v*"}"#,
+            r#"{"routes": {"deploy-api": "services/api/**"}}"#,
+            r#"{"actors": [12345]}"#,
+            r#"{"skip_keywords": [true]}"#,
+            r#"{"versions": "not a range"}"#,
         ] {
             assert!(GitLabProvider::new(wrong).is_err(), wrong.to_string());
         }
@@ -220,14 +484,14 @@ mod tests {
 
         // Check with a request with missing headers and a JSON body
         let mut req = dummy_web_request();
-        req.body = r#"{"a": "b"}"#.to_string();
+        req.body = r#"{"a": "b"}"#.into();
         assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
 
         // Check with a request with the headers and a JSON body
         let mut req = dummy_web_request();
         req.headers
             .insert("X-Gitlab-Event".to_string(), "Push Hook".to_string());
-        req.body = r#"{"a": "b"}"#.to_string();
+        req.body = r#"{"a": "b"}"#.into();
         assert_eq!(provider.validate(&req.into()), RequestType::ExecuteHook);
     }
 
@@ -263,6 +527,29 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_validate_multiple_secrets() {
+        let provider = GitLabProvider::new(
+            r#"{"secret": ["abcde", "fghij"]}"#
+        ).unwrap();
+
+        // Any of the configured tokens is accepted
+        for token in &["abcde", "fghij"] {
+            let mut req = base_request();
+            req.headers
+                .insert("X-Gitlab-Token".to_string(), token.to_string());
+            assert_eq!(
+                provider.validate(&req.into()), RequestType::ExecuteHook
+            );
+        }
+
+        let mut req = base_request();
+        req.headers
+            .insert("X-Gitlab-Token".to_string(), "12345".to_string());
+        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
+    }
+
+
     #[test]
     fn test_validate_events() {
         let config = r#"{"events": ["Push", "Issue"]}"#;
@@ -270,7 +557,7 @@ mod tests {
 
         fn with_event(name: &str) -> Request {
             let mut base = base_request();
-            base.body = "{}".to_string();
+            base.body = "{}".into();
             base.headers
                 .insert("X-Gitlab-Event".to_string(), name.to_string());
 
@@ -315,11 +602,227 @@ mod tests {
 
         assert_eq!(b.dummy_data().env, hashmap! {
             "EVENT".into() => "Push".into(),
+            "CHANGED_FILES".into() => "changed-files.txt".into(),
         });
+        assert_eq!(b.dummy_data().files, hashmap! {
+            "changed-files.txt".into() => Vec::new(),
+        });
+
+        // The files changed by the push are listed one per line
+        let mut req = base_request();
+        req.body = r#"{"commits": [
+            {"added": ["b.txt"], "modified": ["a.txt"], "removed": []}
+        ]}"#.into();
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
+        assert_eq!(
+            b.dummy_data().files.get("changed-files.txt"),
+            Some(&b"a.txt\nb.txt\n".to_vec())
+        );
+
+        // Other events don't have changed files
+        let mut req = base_request();
+        req.headers.insert("X-Gitlab-Event".into(), "Issue Hook".into());
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
         assert_eq!(b.dummy_data().files, hashmap!());
     }
 
 
+    #[test]
+    fn test_paths_filter() {
+        let provider = GitLabProvider::new(
+            r#"{"paths": ["services/api/**"]}"#
+        ).unwrap();
+
+        let with_files = |event: &str, files: &[&str]| {
+            let mut req = base_request();
+            req.headers.insert("X-Gitlab-Event".into(), event.into());
+            req.body = json!({"commits": [{"modified": files}]}).to_string().into_bytes();
+            provider.validate(&req.into())
+        };
+
+        assert_eq!(
+            with_files("Push Hook", &["services/api/main.rs"]),
+            RequestType::ExecuteHook
+        );
+        assert_eq!(
+            with_files("Push Hook", &["services/web/index.html"]),
+            RequestType::Ping
+        );
+        assert_eq!(with_files("Push Hook", &[]), RequestType::Ping);
+
+        // Other events are not filtered
+        assert_eq!(with_files("Issue Hook", &[]), RequestType::ExecuteHook);
+    }
+
+
+    #[test]
+    fn test_actors_filter() {
+        let provider = GitLabProvider::new(
+            r#"{"actors": ["deploy-bot", "PietroAlbini"]}"#
+        ).unwrap();
+
+        let with_body = |event: &str, body: ::serde_json::Value| {
+            let mut req = base_request();
+            req.headers.insert("X-Gitlab-Event".into(), event.into());
+            req.body = body.to_string().into_bytes();
+            provider.validate(&req.into())
+        };
+
+        // Push events contain only the username
+        assert_eq!(
+            with_body("Push Hook", json!({"user_username": "deploy-bot"})),
+            RequestType::ExecuteHook
+        );
+        assert_eq!(
+            with_body("Push Hook", json!({"user_username": "someone-else"})),
+            RequestType::Ping
+        );
+
+        // The other events contain the user details
+        assert_eq!(
+            with_body("Merge Request Hook", json!({
+                "user": {"username": "pietroalbini"},
+            })),
+            RequestType::ExecuteHook
+        );
+        assert_eq!(
+            with_body("Merge Request Hook", json!({
+                "user": {"username": "someone-else"},
+            })),
+            RequestType::Ping
+        );
+        assert_eq!(with_body("Issue Hook", json!({})), RequestType::Ping);
+    }
+
+
+    #[test]
+    fn test_skip_keywords() {
+        let provider = GitLabProvider::new(
+            r#"{"skip_keywords": ["[skip deploy]"]}"#
+        ).unwrap();
+
+        let with_body = |event: &str, body: ::serde_json::Value| {
+            let mut req = base_request();
+            req.headers.insert("X-Gitlab-Event".into(), event.into());
+            req.body = body.to_string().into_bytes();
+            let req = req.into();
+
+            let request_type = provider.validate(&req);
+            (request_type, provider.response_body(&req, request_type))
+        };
+        let push = |message: &str| json!({
+            "after": "b",
+            "commits": [
+                {"id": "a", "message": "Old commit [skip deploy]"},
+                {"id": "b", "message": message},
+            ],
+        });
+        let mr = |title: &str| json!({"object_attributes": {"title": title}});
+
+        let skipped = (RequestType::Ping, Some(json!({"status": "skipped"})));
+        let executed = (RequestType::ExecuteHook, None);
+        assert_eq!(with_body("Push Hook", push("Fix [skip deploy]")), skipped);
+        assert_eq!(with_body("Push Hook", push("Fix typo")), executed);
+        assert_eq!(
+            with_body("Merge Request Hook", mr("[skip deploy] Rewrite")),
+            skipped
+        );
+        assert_eq!(with_body("Merge Request Hook", mr("Rewrite")), executed);
+        assert_eq!(with_body("Issue Hook", mr("[skip deploy]")), executed);
+    }
+
+
+    #[test]
+    fn test_routes() {
+        let provider = GitLabProvider::new(r#"{"routes": {
+            "deploy-api": ["services/api/**"],
+            "deploy-web": ["services/web/**"]
+        }}"#).unwrap();
+
+        let with_files = |event: &str, files: &[&str]| {
+            let mut req = base_request();
+            req.headers.insert("X-Gitlab-Event".into(), event.into());
+            req.body = json!({"commits": [{"modified": files}]}).to_string().into_bytes();
+            let req = req.into();
+            (provider.validate(&req), provider.fan_out(&req))
+        };
+
+        assert_eq!(
+            with_files("Push Hook", &["services/api/main.rs"]),
+            (RequestType::ExecuteHook, Some(vec!["deploy-api".into()]))
+        );
+        assert_eq!(
+            with_files("Push Hook", &["README.md"]),
+            (RequestType::Ping, Some(vec![]))
+        );
+
+        // Other events are not routed
+        assert_eq!(
+            with_files("Issue Hook", &[]), (RequestType::ExecuteHook, None)
+        );
+    }
+
+
+    #[test]
+    fn test_tags_filter() {
+        let provider = GitLabProvider::new(
+            r#"{"tags": ["v*"], "versions": ">= 1.0.0"}"#
+        ).unwrap();
+
+        let with_ref = |event: &str, git_ref: &str| {
+            let mut req = base_request();
+            req.headers.insert("X-Gitlab-Event".into(), event.into());
+            req.body = json!({"ref": git_ref}).to_string().into_bytes();
+            provider.validate(&req.into())
+        };
+
+        assert_eq!(
+            with_ref("Tag Push Hook", "refs/tags/v1.2.0"),
+            RequestType::ExecuteHook
+        );
+        assert_eq!(
+            with_ref("Tag Push Hook", "refs/tags/v1.3.0-beta.1"),
+            RequestType::Ping
+        );
+        assert_eq!(
+            with_ref("Tag Push Hook", "refs/tags/latest"), RequestType::Ping
+        );
+
+        // Pushes to branches are not filtered
+        assert_eq!(
+            with_ref("Push Hook", "refs/heads/master"), RequestType::ExecuteHook
+        );
+
+        // The version is exported for tag pushes
+        let mut req = base_request();
+        req.headers.insert("X-Gitlab-Event".into(), "Tag Push Hook".into());
+        req.body = json!({"ref": "refs/tags/v1.2.3"}).to_string().into_bytes();
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&req.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "Tag Push".into(),
+            "TAG".into() => "v1.2.3".into(),
+            "VERSION_MAJOR".into() => "1".into(),
+            "VERSION_MINOR".into() => "2".into(),
+            "VERSION_PATCH".into() => "3".into(),
+            "VERSION_PRERELEASE".into() => "false".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_delivery_id() {
+        let provider = GitLabProvider::new("{}").unwrap();
+        assert_eq!(provider.delivery_id(&base_request().into()), None);
+
+        let mut req = base_request();
+        req.headers.insert("X-Gitlab-Event-UUID".into(), "abcde".into());
+        assert_eq!(provider.delivery_id(&req.into()), Some("abcde".into()));
+    }
+
+
     #[test]
     fn test_normalize_event_name() {
         assert_eq!(normalize_event_name("Push"), "Push");
@@ -0,0 +1,41 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Skip keywords, which allow authors to avoid triggering a hook by adding
+//! something like `[skip deploy]` to a commit message or a title.
+
+
+/// Check if the text contains any of the skip keywords.
+pub fn contains_skip_keyword(keywords: &[String], text: &str) -> bool {
+    keywords.iter().any(|keyword| text.contains(keyword.as_str()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::contains_skip_keyword;
+
+
+    #[test]
+    fn test_contains_skip_keyword() {
+        let keywords = vec!["[skip deploy]".to_string(), "[wip]".into()];
+
+        assert!(contains_skip_keyword(&keywords, "Fix typo [skip deploy]"));
+        assert!(contains_skip_keyword(&keywords, "[wip] Rewrite everything"));
+        assert!(!contains_skip_keyword(&keywords, "Fix typo [skip ci]"));
+        assert!(!contains_skip_keyword(&keywords, "Fix typo [Skip Deploy]"));
+        assert!(!contains_skip_keyword(&[], "Fix typo [skip deploy]"));
+    }
+}
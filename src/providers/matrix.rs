@@ -0,0 +1,281 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Deserialize;
+use serde_json;
+
+use providers::prelude::*;
+use utils;
+
+
+#[derive(Deserialize)]
+struct Transaction {
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    event_id: Option<String>,
+    room_id: Option<String>,
+    sender: Option<String>,
+    #[serde(default)]
+    content: MessageContent,
+}
+
+#[derive(Default, Deserialize)]
+struct MessageContent {
+    msgtype: Option<String>,
+    body: Option<String>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct MatrixProvider {
+    secret: utils::Secrets,
+    rooms: Option<Vec<String>>,
+}
+
+impl MatrixProvider {
+    /// Return the first message of the transaction the script should
+    /// process, if any.
+    fn first_message(&self, req: &WebRequest) -> Option<Event> {
+        let transaction = Transaction::deserialize(&*req.json()?).ok()?;
+
+        transaction.events.into_iter().find(|event| {
+            if event.kind != "m.room.message" {
+                return false;
+            }
+
+            if let Some(ref rooms) = self.rooms {
+                match event.room_id {
+                    Some(ref room) => rooms.contains(room),
+                    None => false,
+                }
+            } else {
+                true
+            }
+        })
+    }
+}
+
+impl ProviderTrait for MatrixProvider {
+    fn new(config: &str) -> Result<Self> {
+        let inst = serde_json::from_str(config)?;
+        Ok(inst)
+    }
+
+    fn requirements<'a>(&'a self) -> Requirements<'a> {
+        Requirements::new().json_body()
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        // The homeserver token can be sent either as a query string
+        // parameter or in the Authorization header
+        let token = if let Some(token) = req.params.get("access_token") {
+            token.as_str()
+        } else if let Some(header) = req.headers.get("Authorization") {
+            if let Some(token) = header.strip_prefix("Bearer ") {
+                token
+            } else {
+                return RequestType::Invalid;
+            }
+        } else {
+            return RequestType::Invalid;
+        };
+
+        if !self.secret.matches(token) {
+            return RequestType::Invalid;
+        }
+
+        let valid = req.json()
+            .map(|payload| Transaction::deserialize(&*payload).is_ok())
+            .unwrap_or(false);
+        if !valid {
+            return RequestType::Invalid;
+        }
+
+        // Transactions without interesting messages must still be
+        // acknowledged, otherwise the homeserver will send them again
+        if self.first_message(req).is_some() {
+            RequestType::ExecuteHook
+        } else {
+            RequestType::Ping
+        }
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        if let Some(event) = self.first_message(req) {
+            for &(name, value) in &[
+                ("EVENT_ID", &event.event_id),
+                ("ROOM_ID", &event.room_id),
+                ("SENDER", &event.sender),
+                ("MESSAGE_TYPE", &event.content.msgtype),
+                ("MESSAGE", &event.content.body),
+            ] {
+                if let Some(ref value) = *value {
+                    b.add_env(name, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delivery_id(&self, r: &Request) -> Option<String> {
+        // Homeservers retry a transaction with the same ID, which is the last
+        // part of the path: /hook/?/_matrix/app/v1/transactions/?
+        if let Request::Web(ref req) = *r {
+            req.path_args.get(1).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::MatrixProvider;
+
+
+    fn transaction(events: ::serde_json::Value) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.params.insert("access_token".into(), "abcde".into());
+        req.body = json!({"events": events}).to_string().into_bytes();
+        req
+    }
+
+
+    fn message(room: &str, body: &str) -> ::serde_json::Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": format!("$event-{}", body),
+            "room_id": room,
+            "sender": "@pietro:example.com",
+            "content": {"msgtype": "m.text", "body": body},
+        })
+    }
+
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{"secret": "abcde"}"#,
+            r#"{"secret": "abcde", "rooms": ["!room:example.com"]}"#,
+        ] {
+            assert!(MatrixProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{}"#,
+            r#"{"secret": 12345}"#,
+            r#"{"secret": "abcde", "rooms": "!room:example.com"}"#,
+        ] {
+            assert!(MatrixProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = MatrixProvider::new(
+            r#"{"secret": "abcde", "rooms": ["!a:example.com"]}"#
+        ).unwrap();
+
+        // Messages in whitelisted rooms execute the hook
+        let req = transaction(json!([message("!a:example.com", "hi")]));
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        // The token can also be provided in the header
+        let mut req = transaction(json!([message("!a:example.com", "hi")]));
+        req.params.clear();
+        req.headers.insert("Authorization".into(), "Bearer abcde".into());
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        // Other transactions are only acknowledged
+        let req = transaction(json!([message("!b:example.com", "hi")]));
+        assert_eq!(p.validate(&req.into()), RequestType::Ping);
+        let req = transaction(json!([{"type": "m.room.member"}]));
+        assert_eq!(p.validate(&req.into()), RequestType::Ping);
+
+        // Wrong tokens are rejected
+        let mut req = transaction(json!([]));
+        req.params.insert("access_token".into(), "wrong".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        let mut req = transaction(json!([]));
+        req.params.clear();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Invalid bodies are rejected
+        let mut req = transaction(json!([]));
+        req.body = "{}".into();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = MatrixProvider::new(r#"{"secret": "abcde"}"#).unwrap();
+        let req = transaction(json!([
+            {"type": "m.room.member"},
+            message("!a:example.com", "first"),
+            message("!a:example.com", "second"),
+        ])).into();
+
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT_ID".into() => "$event-first".into(),
+            "ROOM_ID".into() => "!a:example.com".into(),
+            "SENDER".into() => "@pietro:example.com".into(),
+            "MESSAGE_TYPE".into() => "m.text".into(),
+            "MESSAGE".into() => "first".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_delivery_id() {
+        let p = MatrixProvider::new(r#"{"secret": "abcde"}"#).unwrap();
+
+        // Retried transactions have the same ID, even if the events differ
+        let mut req = transaction(json!([message("!a:example.com", "a")]));
+        req.path_args = vec!["example.sh".into(), "42".into()];
+        assert_eq!(p.delivery_id(&req.into()), Some("42".into()));
+
+        // Requests without a transaction ID can't be deduplicated
+        let req = transaction(json!([message("!a:example.com", "a")]));
+        assert_eq!(p.delivery_id(&req.into()), None);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde_json::{self, Value};
+use url::form_urlencoded;
+
+use providers::prelude::*;
+use utils;
+
+
+/// The fields of the outgoing webhook exported to the script.
+const MATTERMOST_FIELDS: &[(&str, &str)] = &[
+    ("team_id", "TEAM_ID"),
+    ("team_domain", "TEAM_DOMAIN"),
+    ("channel_id", "CHANNEL_ID"),
+    ("channel_name", "CHANNEL_NAME"),
+    ("user_id", "USER_ID"),
+    ("user_name", "USER_NAME"),
+    ("post_id", "POST_ID"),
+    ("text", "TEXT"),
+    ("trigger_word", "TRIGGER_WORD"),
+];
+
+
+/// Parse the body of the outgoing webhook, which can be either form-encoded
+/// or JSON depending on the webhook configuration.
+fn parse_body(body: &str) -> Option<HashMap<String, String>> {
+    if body.trim_start().starts_with('{') {
+        let json: HashMap<String, Value> = serde_json::from_str(body).ok()?;
+        Some(json.into_iter().filter_map(|(key, value)| match value {
+            Value::String(string) => Some((key, string)),
+            Value::Number(number) => Some((key, number.to_string())),
+            _ => None,
+        }).collect())
+    } else {
+        Some(form_urlencoded::parse(body.as_bytes()).into_owned().collect())
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct MattermostProvider {
+    secret: Option<utils::Secrets>,
+    channels: Option<Vec<String>>,
+}
+
+impl ProviderTrait for MattermostProvider {
+    fn new(config: &str) -> Result<Self> {
+        let inst = serde_json::from_str(config)?;
+        Ok(inst)
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        let fields = if let Some(fields) = parse_body(req.body_str()) {
+            fields
+        } else {
+            return RequestType::Invalid;
+        };
+
+        // Every outgoing webhook contains the token
+        let token = if let Some(token) = fields.get("token") {
+            token
+        } else {
+            return RequestType::Invalid;
+        };
+
+        // Check if the token is correct
+        if let Some(ref secret) = self.secret {
+            if !secret.matches(token) {
+                return RequestType::Invalid;
+            }
+        }
+
+        // Check if the channel should be accepted
+        if let Some(ref channels) = self.channels {
+            match fields.get("channel_name") {
+                Some(name) if channels.contains(name) => {}
+                _ => return RequestType::Invalid,
+            }
+        }
+
+        RequestType::ExecuteHook
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        if let Some(fields) = parse_body(req.body_str()) {
+            for &(field, env) in MATTERMOST_FIELDS {
+                if let Some(value) = fields.get(field) {
+                    b.add_env(env, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delivery_id(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            parse_body(req.body_str()).and_then(|mut f| f.remove("post_id"))
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::MattermostProvider;
+
+
+    fn form_request(body: &str) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.body = body.into();
+        req
+    }
+
+
+    const BODY: &str = concat!(
+        "token=abcde&team_id=t1&team_domain=team&channel_id=c1&",
+        "channel_name=deploys&user_id=u1&user_name=pietro&post_id=p1&",
+        "text=deploy+now&trigger_word=deploy",
+    );
+
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{}"#,
+            r#"{"secret": "abcde"}"#,
+            r#"{"secret": "abcde", "channels": ["deploys"]}"#,
+        ] {
+            assert!(MattermostProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{"secret": 12345}"#,
+            r#"{"channels": "deploys"}"#,
+            r#"{"channels": [12345]}"#,
+        ] {
+            assert!(MattermostProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = MattermostProvider::new(r#"{"secret": "abcde"}"#).unwrap();
+
+        // Both form-encoded and JSON bodies are accepted
+        assert_eq!(
+            p.validate(&form_request(BODY).into()), RequestType::ExecuteHook
+        );
+        assert_eq!(
+            p.validate(&form_request(r#"{"token": "abcde"}"#).into()),
+            RequestType::ExecuteHook
+        );
+
+        // The token must be correct
+        for body in &["token=wrong", "text=hello", r#"{"token": 42}"#, "{"] {
+            assert_eq!(
+                p.validate(&form_request(body).into()), RequestType::Invalid
+            );
+        }
+
+        // The channel must be whitelisted
+        let p = MattermostProvider::new(r#"{"channels": ["other"]}"#).unwrap();
+        assert_eq!(p.validate(&form_request(BODY).into()), RequestType::Invalid);
+        let p = MattermostProvider::new(r#"{"channels": ["deploys"]}"#).unwrap();
+        assert_eq!(
+            p.validate(&form_request(BODY).into()), RequestType::ExecuteHook
+        );
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = MattermostProvider::new("{}").unwrap();
+        let req = form_request(BODY).into();
+
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req, &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "TEAM_ID".into() => "t1".into(),
+            "TEAM_DOMAIN".into() => "team".into(),
+            "CHANNEL_ID".into() => "c1".into(),
+            "CHANNEL_NAME".into() => "deploys".into(),
+            "USER_ID".into() => "u1".into(),
+            "USER_NAME".into() => "pietro".into(),
+            "POST_ID".into() => "p1".into(),
+            "TEXT".into() => "deploy now".into(),
+            "TRIGGER_WORD".into() => "deploy".into(),
+        });
+        assert_eq!(p.delivery_id(&req), Some("p1".into()));
+    }
+}
@@ -17,24 +17,48 @@ mod status;
 mod standalone;
 mod github;
 mod gitlab;
+mod composite;
+mod catch;
+mod discord;
+mod mattermost;
+mod matrix;
+mod uptime;
+mod s3;
+mod config;
+mod keywords;
+mod paths;
+mod requirements;
+mod tags;
+mod trace;
+pub mod testkit;
 #[cfg(test)]
 pub mod testing;
 
 
 pub mod prelude {
-    pub use providers::ProviderTrait;
+    pub use providers::{ProviderTrait, Requirements, ValidationTrace};
     pub use requests::{Request, RequestType};
     pub use common::prelude::*;
     pub use scripts::EnvBuilder;
+    pub use web::WebRequest;
 }
 
 
-pub use self::status::{StatusEvent, StatusEventKind, StatusProvider};
+pub use self::status::{
+    ExhaustedBudget, PendingApproval, StatusEvent, StatusEventKind,
+    StatusProvider,
+};
+pub use self::composite::CompositeProvider;
+pub use self::requirements::{Requirements, Signature, Token};
+pub use self::trace::ValidationTrace;
 
 
+use serde_json;
+
 use requests::{Request, RequestType};
 use common::prelude::*;
 use scripts::EnvBuilder;
+use web::WebRequest;
 
 
 /// This trait should be implemented by every Fisher provider
@@ -46,20 +70,89 @@ pub trait ProviderTrait: ::std::fmt::Debug {
     where
         Self: Sized;
 
+    /// This method should return the requirements every web request must
+    /// meet, such as the headers which must be present. They're enforced
+    /// before calling `validate_web`, and by default nothing is required
+    fn requirements<'a>(&'a self) -> Requirements<'a> {
+        Requirements::new()
+    }
+
+    /// This method should validate an incoming web request meeting the
+    /// requirements of the provider, returning its type if the request is
+    /// valid. Every check performed should be recorded in the trace
+    fn validate_web(
+        &self, _req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        RequestType::Invalid
+    }
+
     /// This method should validate an incoming request, returning its
-    /// type if the request is valid
-    fn validate(&self, &Request) -> RequestType;
+    /// type if the request is valid. By default web requests are validated
+    /// with `requirements` and `validate_web`
+    fn validate(&self, req: &Request) -> RequestType {
+        self.validate_traced(req, &mut ValidationTrace::new())
+    }
+
+    /// This method should validate an incoming request like `validate`,
+    /// recording every check performed in the trace
+    fn validate_traced(
+        &self, req: &Request, trace: &mut ValidationTrace,
+    ) -> RequestType {
+        match *req {
+            Request::Web(ref req) if self.requirements().check(req, trace) => {
+                self.validate_web(req, trace)
+            }
+            _ => RequestType::Invalid,
+        }
+    }
 
     /// This method should build the environment to process an incoming
     /// request
     fn build_env(&self, req: &Request, builder: &mut EnvBuilder) -> Result<()>;
 
+    /// This method should return an unique identifier of the delivery, if
+    /// the provider supports it. Redeliveries of the same webhook must
+    /// return the same identifier
+    fn delivery_id(&self, _req: &Request) -> Option<String> {
+        None
+    }
+
+    /// This method can return a custom JSON body to reply to a valid request
+    /// with, instead of the default one. This is needed by services which
+    /// expect a specific answer from the webhook
+    fn response_body(
+        &self, _req: &Request, _request_type: RequestType,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+
     /// This method tells the scheduler if the hook should trigger status hooks
     /// after the request is processed. By default this returns true, change it
     /// only if you really know what you're doing
     fn trigger_status_hooks(&self, _req: &Request) -> bool {
         true
     }
+
+    /// This method can return the names of the scripts the request should
+    /// be dispatched to, instead of the script which received it. Each one
+    /// of them gets its own job
+    fn fan_out(&self, _req: &Request) -> Option<Vec<String>> {
+        None
+    }
+
+    /// This method should return the name of the event the request is
+    /// about, if the provider supports multiple kinds of events. It's used
+    /// to mute specific events during blackouts
+    fn event_name(&self, _req: &Request) -> Option<String> {
+        None
+    }
+
+    /// This method should return the names of the events the provider
+    /// accepts, if it's configured to accept only some of them. It's used
+    /// to show what a running instance loaded
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        None
+    }
 }
 
 
@@ -77,6 +170,16 @@ macro_rules! ProviderEnum {
         impl Provider {
 
             pub fn new(name: &str, config: &str) -> Result<Provider> {
+                let config = self::config::prepare_config(config)?;
+                Provider::new_unexpanded(name, &config)
+            }
+
+            /// Create a provider without expanding the environment
+            /// variables, the secret files and the Vault references in its
+            /// configuration.
+            pub fn new_unexpanded(
+                name: &str, config: &str,
+            ) -> Result<Provider> {
                 match name {
                     $(
                         #[cfg($cfg)]
@@ -105,6 +208,20 @@ macro_rules! ProviderEnum {
                 }
             }
 
+            pub fn validate_traced(
+                &self, req: &Request, trace: &mut ValidationTrace,
+            ) -> RequestType {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait)
+                                .validate_traced(req, trace)
+                        },
+                    )*
+                }
+            }
+
             pub fn build_env(
                 &self, req: &Request, builder: &mut EnvBuilder,
             ) -> Result<()> {
@@ -118,6 +235,31 @@ macro_rules! ProviderEnum {
                 }
             }
 
+            pub fn delivery_id(&self, req: &Request) -> Option<String> {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait).delivery_id(req)
+                        }
+                    )*
+                }
+            }
+
+            pub fn response_body(
+                &self, req: &Request, request_type: RequestType,
+            ) -> Option<serde_json::Value> {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait)
+                                .response_body(req, request_type)
+                        }
+                    )*
+                }
+            }
+
             pub fn trigger_status_hooks(&self, req: &Request) -> bool {
                 match *self {
                     $(
@@ -129,6 +271,39 @@ macro_rules! ProviderEnum {
                 }
             }
 
+            pub fn fan_out(&self, req: &Request) -> Option<Vec<String>> {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait).fan_out(req)
+                        }
+                    )*
+                }
+            }
+
+            pub fn event_name(&self, req: &Request) -> Option<String> {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait).event_name(req)
+                        }
+                    )*
+                }
+            }
+
+            pub fn accepted_events(&self) -> Option<Vec<String>> {
+                match *self {
+                    $(
+                        #[cfg($cfg)]
+                        Provider::$name(ref prov) => {
+                            (prov as &ProviderTrait).accepted_events()
+                        }
+                    )*
+                }
+            }
+
             #[allow(dead_code)]
             pub fn name(&self) -> &str {
                 match *self {
@@ -148,5 +323,12 @@ ProviderEnum! {
     any(test, not(test)) | Status => self::status::StatusProvider,
     any(test, not(test)) | GitHub => self::github::GitHubProvider,
     any(test, not(test)) | GitLab => self::gitlab::GitLabProvider,
+    any(test, not(test)) | Discord => self::discord::DiscordProvider,
+    any(test, not(test)) | Mattermost => self::mattermost::MattermostProvider,
+    any(test, not(test)) | Matrix => self::matrix::MatrixProvider,
+    any(test, not(test)) | Uptime => self::uptime::UptimeProvider,
+    any(test, not(test)) | S3 => self::s3::S3Provider,
+    any(test, not(test)) | Catch => self::catch::CatchProvider,
+    any(test, not(test)) | Composite => self::composite::CompositeProvider,
     test | Testing => self::testing::TestingProvider
 }
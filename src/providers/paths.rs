@@ -0,0 +1,187 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filters on the files changed by a push, shared by the providers of git
+//! hosting services.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use serde_json::Value;
+
+use common::prelude::*;
+
+
+#[derive(Deserialize)]
+struct PushPayload {
+    #[serde(default)]
+    commits: Vec<PushedCommit>,
+}
+
+#[derive(Deserialize)]
+struct PushedCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+
+/// Get the files changed by the commits of a push event, sorted and without
+/// duplicates. Both GitHub and GitLab list them in the same way.
+pub fn changed_files(payload: &Value) -> Result<Vec<String>> {
+    let parsed = PushPayload::deserialize(payload)?;
+
+    let mut files = BTreeSet::new();
+    for commit in parsed.commits {
+        files.extend(commit.added);
+        files.extend(commit.modified);
+        files.extend(commit.removed);
+    }
+
+    Ok(files.into_iter().collect())
+}
+
+
+/// A list of glob patterns matched against the changed files.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    patterns: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern).chain_err(|| {
+                ErrorKind::ProviderInvalidPathPattern(pattern.clone())
+            }))
+            .collect::<Result<_>>()?;
+
+        Ok(PathFilter { patterns })
+    }
+
+    /// Check if any of the files matches any of the patterns.
+    pub fn matches<S: AsRef<str>>(&self, files: &[S]) -> bool {
+        // `*` doesn't match slashes, while `**` does
+        let options = MatchOptions {
+            require_literal_separator: true,
+            .. MatchOptions::new()
+        };
+
+        files.iter().any(|file| {
+            self.patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(file.as_ref(), options))
+        })
+    }
+}
+
+
+/// The scripts a push should be dispatched to, each one with the paths it's
+/// interested in.
+#[derive(Debug, Default)]
+pub struct PathRoutes {
+    routes: Vec<(String, PathFilter)>,
+}
+
+impl PathRoutes {
+    pub fn new(routes: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let routes = routes
+            .iter()
+            .map(|(script, patterns)| {
+                Ok((script.clone(), PathFilter::new(patterns)?))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(PathRoutes { routes })
+    }
+
+    /// Get the names of the scripts interested in the changed files.
+    pub fn route<S: AsRef<str>>(&self, files: &[S]) -> Vec<String> {
+        self.routes
+            .iter()
+            .filter(|(_, filter)| filter.matches(files))
+            .map(|(script, _)| script.clone())
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::{changed_files, PathFilter, PathRoutes};
+
+
+    #[test]
+    fn test_changed_files() {
+        let payload = json!({"commits": [
+            {"added": ["b.txt"], "modified": ["src/a.rs"], "removed": []},
+            {"added": [], "modified": ["src/a.rs"], "removed": ["c.txt"]},
+            {"id": "no files"}
+        ]});
+        assert_eq!(
+            changed_files(&payload).unwrap(),
+            vec!["b.txt".to_string(), "c.txt".into(), "src/a.rs".into()]
+        );
+
+        assert!(changed_files(&json!({})).unwrap().is_empty());
+        assert!(changed_files(&json!({"commits": "wrong"})).is_err());
+    }
+
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(&[
+            "services/api/**".into(), "*.toml".into(),
+        ]).unwrap();
+
+        assert!(filter.matches(&["services/api/src/main.rs"]));
+        assert!(filter.matches(&["README.md", "Cargo.toml"]));
+        assert!(!filter.matches(&["services/web/index.html"]));
+        assert!(!filter.matches(&["services/Cargo.toml"]));
+        assert!(!filter.matches::<&str>(&[]));
+
+        assert!(PathFilter::new(&["src/***".into()]).is_err());
+    }
+
+
+    #[test]
+    fn test_path_routes() {
+        let routes = PathRoutes::new(&serde_json::from_str(r#"{
+            "deploy-api": ["services/api/**"],
+            "deploy-web": ["services/web/**", "shared/**"]
+        }"#).unwrap()).unwrap();
+
+        assert_eq!(
+            routes.route(&["services/api/main.rs"]),
+            vec!["deploy-api".to_string()]
+        );
+        assert_eq!(
+            routes.route(&["services/api/main.rs", "shared/style.css"]),
+            vec!["deploy-api".to_string(), "deploy-web".into()]
+        );
+        assert!(routes.route(&["README.md"]).is_empty());
+
+        assert!(PathRoutes::new(&serde_json::from_str(
+            r#"{"invalid": ["src/***"]}"#
+        ).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Requirements common to many providers, declared by them and enforced
+//! before their own validation logic is executed.
+
+use providers::ValidationTrace;
+use utils::{self, HmacAlgorithm, Secrets, SignatureEncoding};
+use web::WebRequest;
+
+
+/// How the body of a request must be signed. The signature is prefixed by
+/// the name of its algorithm, like `sha256=abcdef`.
+#[derive(Debug, Clone)]
+pub struct Signature<'a> {
+    /// The header containing the signature.
+    pub header: &'a str,
+    /// The algorithms the signature can be made with.
+    pub algorithms: &'a [HmacAlgorithm],
+    pub encoding: SignatureEncoding,
+    /// The secrets the signature can be made with, to allow rotating them.
+    pub secrets: &'a [String],
+}
+
+impl<'a> Signature<'a> {
+    fn verify(&self, req: &WebRequest) -> bool {
+        let signature = if let Some(signature) = req.headers.get(self.header) {
+            signature
+        } else {
+            return false;
+        };
+
+        // Try every secret without stopping at the first valid one, to
+        // avoid leaking which one was used through timing. Spooled bodies
+        // are streamed from their file, without loading them in memory.
+        let mut valid = false;
+        for secret in self.secrets {
+            let verified = req.body_reader().and_then(|body| {
+                Ok(utils::verify_prefixed_signature(
+                    self.algorithms,
+                    self.encoding,
+                    secret.as_bytes(),
+                    body,
+                    signature,
+                )?)
+            });
+            valid |= verified.unwrap_or(false);
+        }
+        valid
+    }
+}
+
+
+/// A secret token the request must contain in a header.
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub header: &'a str,
+    pub secrets: &'a Secrets,
+}
+
+impl<'a> Token<'a> {
+    fn verify(&self, req: &WebRequest) -> bool {
+        req.headers
+            .get(self.header)
+            .map(|token| self.secrets.matches(token))
+            .unwrap_or(false)
+    }
+}
+
+
+/// The requirements a web request must meet to be validated by a provider.
+/// The checks are recorded in the trace as `headers`, `signature`, `token`
+/// and `json_body`, in this order.
+#[derive(Debug, Clone, Default)]
+pub struct Requirements<'a> {
+    headers: &'a [&'a str],
+    signature: Option<Signature<'a>>,
+    token: Option<Token<'a>>,
+    json_body: bool,
+}
+
+impl<'a> Requirements<'a> {
+    /// Create requirements accepting every web request.
+    pub fn new() -> Self {
+        Requirements::default()
+    }
+
+    /// Require all these headers to be present.
+    pub fn headers(mut self, headers: &'a [&'a str]) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Require the body to be signed.
+    pub fn signature(mut self, signature: Signature<'a>) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Require a secret token.
+    pub fn token(mut self, token: Token<'a>) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Require the body to be valid JSON.
+    pub fn json_body(mut self) -> Self {
+        self.json_body = true;
+        self
+    }
+
+    /// Get the headers which must be present.
+    pub fn required_headers(&self) -> &[&str] {
+        self.headers
+    }
+
+    /// Get the header containing the signature, if the body must be signed.
+    pub fn signature_header(&self) -> Option<&str> {
+        self.signature.as_ref().map(|signature| signature.header)
+    }
+
+    /// Get the header containing the secret token, if one is required.
+    pub fn token_header(&self) -> Option<&str> {
+        self.token.as_ref().map(|token| token.header)
+    }
+
+    /// Check if the body must be valid JSON.
+    pub fn requires_json_body(&self) -> bool {
+        self.json_body
+    }
+
+    /// Check if the request meets all the requirements.
+    pub fn check(&self, req: &WebRequest, trace: &mut ValidationTrace) -> bool {
+        if !self.headers.is_empty() {
+            let present = self.headers
+                .iter()
+                .all(|header| req.headers.contains_key(*header));
+            if !trace.check("headers", present) {
+                return false;
+            }
+        }
+
+        if let Some(ref signature) = self.signature {
+            if !trace.check("signature", signature.verify(req)) {
+                return false;
+            }
+        }
+
+        if let Some(ref token) = self.token {
+            if !trace.check("token", token.verify(req)) {
+                return false;
+            }
+        }
+
+        if self.json_body && !trace.check("json_body", req.json().is_some()) {
+            return false;
+        }
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use providers::ValidationTrace;
+    use utils::testing::*;
+    use utils::{HmacAlgorithm, Secrets, SignatureEncoding};
+
+    use super::{Requirements, Signature, Token};
+
+
+    // Signature of "payload" with the "secret" key
+    const SIGNATURE: &str = "sha1=f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d";
+
+
+    fn signature<'a>(secrets: &'a [String]) -> Signature<'a> {
+        Signature {
+            header: "X-Signature",
+            algorithms: &[HmacAlgorithm::Sha1],
+            encoding: SignatureEncoding::Hex,
+            secrets,
+        }
+    }
+
+    fn names(trace: &ValidationTrace) -> Vec<(&str, bool)> {
+        trace.checks()
+            .iter()
+            .map(|check| (check.name.as_str(), check.passed))
+            .collect()
+    }
+
+
+    #[test]
+    fn test_no_requirements() {
+        let mut trace = ValidationTrace::new();
+        assert!(Requirements::new().check(&dummy_web_request(), &mut trace));
+        assert!(trace.checks().is_empty());
+    }
+
+    #[test]
+    fn test_headers() {
+        let requirements = Requirements::new().headers(&["A", "B"]);
+
+        let mut req = dummy_web_request();
+        req.headers.insert("A".into(), "a".into());
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![("headers", false)]);
+
+        req.headers.insert("B".into(), "b".into());
+        let mut trace = ValidationTrace::new();
+        assert!(requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![("headers", true)]);
+    }
+
+    #[test]
+    fn test_signature() {
+        let secrets = vec!["other".to_string(), "secret".to_string()];
+        let requirements = Requirements::new().signature(signature(&secrets));
+
+        let mut req = dummy_web_request();
+        req.body = b"payload".to_vec();
+
+        // The signature must be present
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![("signature", false)]);
+
+        for invalid in &[
+            "invalid",         // No algorithm
+            "invalid=invalid", // Invalid algorithm
+            "sha1=g",          // The signature is not hex
+            "sha256=f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d", // Not allowed
+            // Invalid signature (the first "e" should be "f")
+            "sha1=e75efc0f29bf50c23f99b30b86f7c78fdaf5f11d",
+        ] {
+            req.headers.insert("X-Signature".into(), invalid.to_string());
+            let mut trace = ValidationTrace::new();
+            assert!(!requirements.check(&req, &mut trace), "{}", invalid);
+        }
+
+        // Any of the secrets can be used while rotating them
+        req.headers.insert("X-Signature".into(), SIGNATURE.into());
+        let mut trace = ValidationTrace::new();
+        assert!(requirements.check(&req, &mut trace));
+
+        let secrets = vec!["other".to_string(), "another".to_string()];
+        let requirements = Requirements::new().signature(signature(&secrets));
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+    }
+
+    #[test]
+    fn test_token() {
+        let secrets: Secrets = serde_json::from_str(r#"["a", "b"]"#).unwrap();
+        let requirements = Requirements::new().token(Token {
+            header: "X-Token",
+            secrets: &secrets,
+        });
+
+        let mut req = dummy_web_request();
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![("token", false)]);
+
+        req.headers.insert("X-Token".into(), "c".into());
+        assert!(!requirements.check(&req, &mut ValidationTrace::new()));
+
+        req.headers.insert("X-Token".into(), "b".into());
+        assert!(requirements.check(&req, &mut ValidationTrace::new()));
+    }
+
+    #[test]
+    fn test_json_body() {
+        let requirements = Requirements::new().json_body();
+
+        let mut req = dummy_web_request();
+        req.body = b"not json".to_vec();
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![("json_body", false)]);
+
+        let mut req = dummy_web_request();
+        req.body = b"{\"a\": 1}".to_vec();
+        let mut trace = ValidationTrace::new();
+        assert!(requirements.check(&req, &mut trace));
+    }
+
+    #[test]
+    fn test_checks_order() {
+        let secrets = vec!["secret".to_string()];
+        let requirements = Requirements::new()
+            .json_body()
+            .signature(signature(&secrets))
+            .headers(&["X-Signature"]);
+
+        // The checks stop at the first failure
+        let mut req = dummy_web_request();
+        req.body = b"payload".to_vec();
+        req.headers.insert("X-Signature".into(), SIGNATURE.into());
+        let mut trace = ValidationTrace::new();
+        assert!(!requirements.check(&req, &mut trace));
+        assert_eq!(names(&trace), vec![
+            ("headers", true), ("signature", true), ("json_body", false),
+        ]);
+    }
+}
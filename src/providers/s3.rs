@@ -0,0 +1,419 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Deserialize;
+use serde_json::{self, Value};
+use url::percent_encoding::percent_decode;
+
+use providers::prelude::*;
+use utils;
+
+
+lazy_static! {
+    static ref S3_EVENTS: Vec<&'static str> = vec!["created", "removed"];
+}
+
+
+/// Notifications sent by S3-compatible services, like AWS S3 or MinIO.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct S3Notification {
+    records: Vec<S3Record>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct S3Record {
+    event_name: String,
+    s3: S3Entity,
+}
+
+#[derive(Deserialize)]
+struct S3Entity {
+    bucket: S3Bucket,
+    object: S3Object,
+}
+
+#[derive(Deserialize)]
+struct S3Bucket {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct S3Object {
+    key: String,
+    size: Option<u64>,
+}
+
+
+/// Notifications sent by Backblaze B2.
+#[derive(Deserialize)]
+struct B2Notification {
+    events: Vec<B2Event>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2Event {
+    event_type: String,
+    bucket_name: String,
+    object_name: String,
+    object_size: Option<u64>,
+}
+
+
+/// A single event, independent from the service which sent it.
+#[derive(Debug)]
+struct ObjectEvent {
+    name: String,
+    bucket: String,
+    key: String,
+    size: Option<u64>,
+}
+
+impl ObjectEvent {
+    /// Parse all the events contained in the notification.
+    fn parse_all(body: &Value) -> Option<Vec<ObjectEvent>> {
+        if let Ok(s3) = S3Notification::deserialize(body) {
+            Some(s3.records.into_iter().map(|record| ObjectEvent {
+                name: record.event_name,
+                bucket: record.s3.bucket.name,
+                // S3 sends keys URL-encoded
+                key: decode_key(&record.s3.object.key),
+                size: record.s3.object.size,
+            }).collect())
+        } else if let Ok(b2) = B2Notification::deserialize(body) {
+            Some(b2.events.into_iter().map(|event| ObjectEvent {
+                name: event.event_type,
+                bucket: event.bucket_name,
+                key: event.object_name,
+                size: event.object_size,
+            }).collect())
+        } else {
+            None
+        }
+    }
+
+    /// Return the kind of event, either `created` or `removed`.
+    fn kind(&self) -> Option<&'static str> {
+        // Event names look like `ObjectCreated:Put`, optionally prefixed
+        // by the service name (`s3:ObjectCreated:Put`)
+        let name = self.name.split(':')
+            .find(|part| part.starts_with("Object"))
+            .unwrap_or("");
+        match name {
+            "ObjectCreated" => Some("created"),
+            "ObjectRemoved" | "ObjectDeleted" => Some("removed"),
+            _ => None,
+        }
+    }
+}
+
+
+fn decode_key(key: &str) -> String {
+    let key = key.replace('+', " ");
+    percent_decode(key.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct S3Provider {
+    secret: Option<utils::Secrets>,
+    events: Option<Vec<String>>,
+    buckets: Option<Vec<String>>,
+    prefixes: Option<Vec<String>>,
+}
+
+impl S3Provider {
+    fn verify(&self, req: &WebRequest) -> bool {
+        let secrets = if let Some(ref secrets) = self.secret {
+            secrets
+        } else {
+            return true;
+        };
+
+        // Backblaze B2 signs the notifications. Spooled bodies are streamed
+        // from their file.
+        if let Some(sig) = req.headers.get("X-Bz-Event-Notification-Signature") {
+            return sig.starts_with("v1=") && utils::verify_any_signature(
+                utils::HmacAlgorithm::Sha256,
+                utils::SignatureEncoding::Hex,
+                secrets.as_slice(),
+                || req.body_reader().ok(),
+                Some(&sig["v1=".len()..]),
+            );
+        }
+
+        // Other services send a static token in the Authorization header
+        if let Some(header) = req.headers.get("Authorization") {
+            let token = if header.starts_with("Bearer ") {
+                &header["Bearer ".len()..]
+            } else {
+                header.as_str()
+            };
+            return secrets.matches(token);
+        }
+
+        false
+    }
+
+    /// Return the first event of the notification the script should
+    /// process, if any.
+    fn first_event(
+        &self, body: &Value,
+    ) -> Option<(&'static str, ObjectEvent)> {
+        ObjectEvent::parse_all(body)?.into_iter().filter_map(|event| {
+            let kind = event.kind()?;
+
+            if let Some(ref events) = self.events {
+                if !events.iter().any(|e| e == kind) {
+                    return None;
+                }
+            }
+            if let Some(ref buckets) = self.buckets {
+                if !buckets.contains(&event.bucket) {
+                    return None;
+                }
+            }
+            if let Some(ref prefixes) = self.prefixes {
+                if !prefixes.iter().any(|p| event.key.starts_with(p)) {
+                    return None;
+                }
+            }
+
+            Some((kind, event))
+        }).next()
+    }
+}
+
+impl ProviderTrait for S3Provider {
+    fn new(config: &str) -> Result<Self> {
+        let inst: S3Provider = serde_json::from_str(config)?;
+
+        if let Some(ref events) = inst.events {
+            for event in events {
+                if !S3_EVENTS.contains(&event.as_str()) {
+                    return Err(ErrorKind::ProviderS3InvalidEventName(
+                        event.clone()
+                    ).into());
+                }
+            }
+        }
+
+        Ok(inst)
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        if !self.verify(req) {
+            return RequestType::Invalid;
+        }
+
+        let body = match req.json() {
+            Some(body) => body,
+            None => return RequestType::Invalid,
+        };
+        if ObjectEvent::parse_all(&body).is_none() {
+            return RequestType::Invalid;
+        }
+
+        // Notifications without interesting events (including the test
+        // events sent when configuring them) are only acknowledged
+        if self.first_event(&body).is_some() {
+            RequestType::ExecuteHook
+        } else {
+            RequestType::Ping
+        }
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        let event = req.json().and_then(|body| self.first_event(&body));
+        if let Some((kind, event)) = event {
+            b.add_env("EVENT", kind);
+            b.add_env("EVENT_NAME", &event.name);
+            b.add_env("BUCKET", &event.bucket);
+            b.add_env("KEY", &event.key);
+            if let Some(size) = event.size {
+                b.add_env("SIZE", size.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        self.events.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use utils::{to_hex, HmacAlgorithm};
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::S3Provider;
+
+
+    fn s3_request(event: &str, bucket: &str, key: &str) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.headers.insert("Authorization".into(), "Bearer abcde".into());
+        req.body = json!({"Records": [{
+            "eventName": event,
+            "s3": {
+                "bucket": {"name": bucket},
+                "object": {"key": key, "size": 1024},
+            },
+        }]}).to_string().into_bytes();
+        req
+    }
+
+
+    fn b2_request(event: &str, signature: Option<&str>) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.body = json!({"events": [{
+            "eventType": event,
+            "bucketName": "uploads",
+            "objectName": "images/cat.png",
+            "objectSize": 2048,
+        }]}).to_string().into_bytes();
+
+        let signature = signature.map(|s| s.to_string()).unwrap_or_else(|| {
+            to_hex(&HmacAlgorithm::Sha256.sign(b"abcde", &req.body))
+        });
+        req.headers.insert(
+            "X-Bz-Event-Notification-Signature".into(),
+            format!("v1={}", signature),
+        );
+        req
+    }
+
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{}"#,
+            r#"{"secret": "abcde"}"#,
+            r#"{"events": ["created", "removed"]}"#,
+            r#"{"buckets": ["uploads"], "prefixes": ["images/"]}"#,
+        ] {
+            assert!(S3Provider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{"secret": 12345}"#,
+            r#"{"events": ["copied"]}"#,
+            r#"{"buckets": "uploads"}"#,
+            r#"{"prefixes": [12345]}"#,
+        ] {
+            assert!(S3Provider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = S3Provider::new(r#"{"secret": "abcde"}"#).unwrap();
+
+        // Both S3 and B2 notifications are accepted
+        for req in vec![
+            s3_request("ObjectCreated:Put", "uploads", "a.png"),
+            s3_request("s3:ObjectRemoved:Delete", "uploads", "a.png"),
+            b2_request("b2:ObjectCreated:Upload", None),
+        ] {
+            assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+        }
+
+        // Test events are only acknowledged
+        let req = b2_request("b2:TestEvent", None);
+        assert_eq!(p.validate(&req.into()), RequestType::Ping);
+
+        // Wrong secrets are rejected
+        let req = b2_request("b2:ObjectCreated:Upload", Some("abcd"));
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        let mut req = s3_request("ObjectCreated:Put", "uploads", "a.png");
+        req.headers.insert("Authorization".into(), "Bearer wrong".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        let mut req = s3_request("ObjectCreated:Put", "uploads", "a.png");
+        req.headers.remove("Authorization");
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+
+        // Invalid bodies are rejected
+        let mut req = s3_request("ObjectCreated:Put", "uploads", "a.png");
+        req.body = "{}".into();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_validate_filters() {
+        let p = S3Provider::new(r#"{
+            "events": ["created"],
+            "buckets": ["uploads"],
+            "prefixes": ["images/", "videos/"]
+        }"#).unwrap();
+
+        let req = s3_request("ObjectCreated:Put", "uploads", "images/a.png");
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        for req in vec![
+            s3_request("ObjectRemoved:Delete", "uploads", "images/a.png"),
+            s3_request("ObjectCreated:Put", "backups", "images/a.png"),
+            s3_request("ObjectCreated:Put", "uploads", "docs/a.pdf"),
+        ] {
+            assert_eq!(p.validate(&req.into()), RequestType::Ping);
+        }
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = S3Provider::new("{}").unwrap();
+
+        let req = s3_request("ObjectCreated:Put", "uploads", "my+cat%21.png");
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "created".into(),
+            "EVENT_NAME".into() => "ObjectCreated:Put".into(),
+            "BUCKET".into() => "uploads".into(),
+            "KEY".into() => "my cat!.png".into(),
+            "SIZE".into() => "1024".into(),
+        });
+
+        let req = b2_request("b2:ObjectCreated:Upload", None);
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "created".into(),
+            "EVENT_NAME".into() => "b2:ObjectCreated:Upload".into(),
+            "BUCKET".into() => "uploads".into(),
+            "KEY".into() => "images/cat.png".into(),
+            "SIZE".into() => "2048".into(),
+        });
+    }
+}
@@ -18,11 +18,12 @@ use std::net::IpAddr;
 use serde_json;
 
 use providers::prelude::*;
+use utils;
 
 
 #[derive(Debug, Deserialize)]
 pub struct StandaloneProvider {
-    secret: Option<String>,
+    secret: Option<utils::Secrets>,
     from: Option<Vec<IpAddr>>,
 
     param_name: Option<String>,
@@ -52,36 +53,37 @@ impl ProviderTrait for StandaloneProvider {
         Ok(inst)
     }
 
-    fn validate(&self, request: &Request) -> RequestType {
-        let req;
-        if let Request::Web(ref inner) = *request {
-            req = inner;
-        } else {
-            return RequestType::Invalid;
-        }
-
+    fn validate_web(
+        &self, req: &WebRequest, trace: &mut ValidationTrace,
+    ) -> RequestType {
         // Check if the secret code is valid
         if let Some(ref correct_secret) = self.secret {
             let secret = if let Some(found) = req.params.get(&self.param_name()) {
                 // Secret in the request parameters
-                found
-            } else if let Some(found) = req.headers.get(&self.header_name()) {
+                Some(found)
+            } else {
                 // Secret in the HTTP headers
-                found
+                req.headers.get(&self.header_name())
+            };
+
+            // No secret present, abort!
+            let secret = if let Some(secret) = secret {
+                trace.check("secret_present", true);
+                secret
             } else {
-                // No secret present, abort!
+                trace.check("secret_present", false);
                 return RequestType::Invalid;
             };
 
-            // Abort if the secret doesn't match
-            if secret != correct_secret {
+            // Abort if the secret doesn't match any of the correct ones
+            if !trace.check("secret", correct_secret.matches(secret)) {
                 return RequestType::Invalid;
             }
         }
 
         // Check if the IP address is allowed
         if let Some(ref allowed) = self.from {
-            if !allowed.contains(&req.source) {
+            if !trace.check("source_ip", allowed.contains(&req.source)) {
                 return RequestType::Invalid;
             }
         }
@@ -118,6 +120,7 @@ mod tests {
             r#"{"secret": "abcde", "param_name": "a", "header_name": "b"}"#,
             r#"{"from": ["127.0.0.1", "192.168.1.1", "10.0.0.2"]}"#,
             r#"{"from": ["127.0.0.1"], "secret": "abcde"}"#,
+            r#"{"secret": ["a", "b"]}"#,
         ];
         for one in &right {
             assert!(StandaloneProvider::new(one).is_ok(), "Should be valid: {}", one);
@@ -126,7 +129,7 @@ mod tests {
         let wrong = vec![
             r#"{"secret": 123}"#,
             r#"{"secret": true}"#,
-            r#"{"secret": ["a", "b"]}"#,
+            r#"{"secret": []}"#,
             r#"{"secret": {"a": "b"}}"#,
             r#"{"from": "127.0.0.1"}"#,
             r#"{"from": ["256.0.0.1"]}"#,
@@ -148,6 +151,22 @@ mod tests {
         test_validate_inner_secret(config_custom, "a", "X-A");
     }
 
+    #[test]
+    fn test_validate_multiple_secrets() {
+        let p = StandaloneProvider::new(r#"{"secret": ["abcde", "fghij"]}"#)
+            .unwrap();
+
+        for (secret, expected) in &[
+            ("abcde", RequestType::ExecuteHook),
+            ("fghij", RequestType::ExecuteHook),
+            ("12345", RequestType::Invalid),
+        ] {
+            let mut req = dummy_web_request();
+            req.params.insert("secret".into(), secret.to_string());
+            assert_eq!(p.validate(&req.into()), *expected, "{}", secret);
+        }
+    }
+
     fn test_validate_inner_secret(config: &str, param_name: &str, header_name: &str) {
         let p = StandaloneProvider::new(config).unwrap();
 
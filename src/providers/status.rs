@@ -14,18 +14,43 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::slice::Iter as SliceIter;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
 use serde_json;
 
+use common::structs::FreezeAction;
 use providers::prelude::*;
 use scripts::JobOutput;
 
 
+/// Details about a job waiting for approval.
+#[derive(Debug, Clone)]
+pub struct PendingApproval {
+    pub job_id: String,
+    pub script_name: String,
+    pub request_ip: IpAddr,
+    /// The links to approve and reject the job, if they're enabled.
+    pub links: Option<(String, String)>,
+}
+
+
+/// Details about a script which exhausted its daily budget.
+#[derive(Debug, Clone)]
+pub struct ExhaustedBudget {
+    pub script_name: String,
+    /// For how many seconds the script can run every day.
+    pub budget: u64,
+    pub action: FreezeAction,
+}
+
+
 #[derive(Debug, Clone)]
 pub enum StatusEvent {
     JobCompleted(JobOutput),
     JobFailed(JobOutput),
+    JobPendingApproval(PendingApproval),
+    BudgetExhausted(ExhaustedBudget),
 }
 
 impl StatusEvent {
@@ -34,6 +59,12 @@ impl StatusEvent {
         match *self {
             StatusEvent::JobCompleted(..) => StatusEventKind::JobCompleted,
             StatusEvent::JobFailed(..) => StatusEventKind::JobFailed,
+            StatusEvent::JobPendingApproval(..) => {
+                StatusEventKind::JobPendingApproval
+            }
+            StatusEvent::BudgetExhausted(..) => {
+                StatusEventKind::BudgetExhausted
+            }
         }
     }
 
@@ -42,6 +73,12 @@ impl StatusEvent {
         match *self {
             StatusEvent::JobCompleted(ref output) |
             StatusEvent::JobFailed(ref output) => &output.script_name,
+            StatusEvent::JobPendingApproval(ref pending) => {
+                &pending.script_name
+            }
+            StatusEvent::BudgetExhausted(ref exhausted) => {
+                &exhausted.script_name
+            }
         }
     }
 
@@ -50,6 +87,11 @@ impl StatusEvent {
         match *self {
             StatusEvent::JobCompleted(ref output) |
             StatusEvent::JobFailed(ref output) => output.request_ip,
+            StatusEvent::JobPendingApproval(ref pending) => pending.request_ip,
+            // This event is generated by Fisher itself
+            StatusEvent::BudgetExhausted(..) => {
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
+            }
         }
     }
 }
@@ -60,6 +102,8 @@ impl StatusEvent {
 pub enum StatusEventKind {
     JobCompleted,
     JobFailed,
+    JobPendingApproval,
+    BudgetExhausted,
 }
 
 impl StatusEventKind {
@@ -67,6 +111,8 @@ impl StatusEventKind {
         match *self {
             StatusEventKind::JobCompleted => "job-completed",
             StatusEventKind::JobFailed => "job-failed",
+            StatusEventKind::JobPendingApproval => "job-pending-approval",
+            StatusEventKind::BudgetExhausted => "budget-exhausted",
         }
     }
 }
@@ -100,7 +146,9 @@ impl ProviderTrait for StatusProvider {
         Ok(serde_json::from_str(config)?)
     }
 
-    fn validate(&self, request: &Request) -> RequestType {
+    fn validate_traced(
+        &self, request: &Request, _trace: &mut ValidationTrace,
+    ) -> RequestType {
         let req;
         if let Request::Status(ref inner) = *request {
             req = inner;
@@ -139,6 +187,9 @@ impl ProviderTrait for StatusProvider {
 
                 write!(b.data_file("stdout")?, "{}", out.stdout)?;
                 write!(b.data_file("stderr")?, "{}", out.stderr)?;
+                fingerprint_env(out, b);
+                usage_env(out, b);
+                artifacts_env(out, b);
             }
             StatusEvent::JobFailed(ref out) => {
                 b.add_env("SUCCESS", "0");
@@ -155,6 +206,23 @@ impl ProviderTrait for StatusProvider {
 
                 write!(b.data_file("stdout")?, "{}", out.stdout)?;
                 write!(b.data_file("stderr")?, "{}", out.stderr)?;
+                fingerprint_env(out, b);
+                usage_env(out, b);
+                artifacts_env(out, b);
+            }
+            StatusEvent::JobPendingApproval(ref pending) => {
+                b.add_env("JOB_ID", &pending.job_id);
+                if let Some((ref approve, ref reject)) = pending.links {
+                    b.add_env("APPROVE_URL", approve);
+                    b.add_env("REJECT_URL", reject);
+                }
+            }
+            StatusEvent::BudgetExhausted(ref exhausted) => {
+                b.add_env("BUDGET", exhausted.budget.to_string());
+                b.add_env("BUDGET_ACTION", match exhausted.action {
+                    FreezeAction::Queue => "queue",
+                    FreezeAction::Reject => "reject",
+                });
             }
         }
 
@@ -166,6 +234,52 @@ impl ProviderTrait for StatusProvider {
         // That would end really bad
         false
     }
+
+    fn accepted_events(&self) -> Option<Vec<String>> {
+        Some(self.events.iter().map(|e| e.name().to_string()).collect())
+    }
+}
+
+
+/// Add the resources used by the job.
+fn usage_env(out: &JobOutput, b: &mut EnvBuilder) {
+    let millis = |d: Duration| d.as_secs() * 1000 + d.subsec_millis() as u64;
+
+    b.add_env("MAX_RSS_KB", out.usage.max_rss.to_string());
+    b.add_env("CPU_TIME_MS", millis(out.usage.cpu_time()).to_string());
+    b.add_env("WALL_TIME_MS", millis(out.usage.wall_time).to_string());
+}
+
+
+/// Add the names of the artifacts uploaded by the job, if any.
+fn artifacts_env(out: &JobOutput, b: &mut EnvBuilder) {
+    if !out.artifacts.is_empty() {
+        b.add_env("ARTIFACTS", out.artifacts.join(","));
+    }
+}
+
+
+/// Add the fingerprint of the environment the job was executed in.
+fn fingerprint_env(out: &JobOutput, b: &mut EnvBuilder) {
+    let fingerprint = if let Some(ref fingerprint) = out.fingerprint {
+        fingerprint
+    } else {
+        return;
+    };
+
+    b.add_env("SCRIPT_CHECKSUM", &fingerprint.script_checksum);
+    b.add_env(
+        "INTERPRETER", fingerprint.interpreter.as_deref().unwrap_or(""),
+    );
+    b.add_env(
+        "INTERPRETER_VERSION",
+        fingerprint.interpreter_version.as_deref().unwrap_or(""),
+    );
+    b.add_env("HOSTNAME", &fingerprint.hostname);
+    b.add_env("FISHER_VERSION", fingerprint.fisher_version);
+    if let Some(ref changes) = out.fingerprint_changes {
+        b.add_env("FINGERPRINT_CHANGES", changes.join(","));
+    }
 }
 
 
@@ -174,9 +288,13 @@ mod tests {
     use utils::testing::*;
     use requests::RequestType;
     use providers::ProviderTrait;
-    use scripts::EnvBuilder;
+    use scripts::{EnvBuilder, Fingerprint};
 
-    use super::{StatusEvent, StatusProvider};
+    use common::structs::FreezeAction;
+
+    use super::{
+        ExhaustedBudget, PendingApproval, StatusEvent, StatusProvider,
+    };
 
 
     #[test]
@@ -288,6 +406,9 @@ mod tests {
             "SUCCESS".into() => "1".into(),
             "EXIT_CODE".into() => "0".into(),
             "SIGNAL".into() => "".into(),
+            "MAX_RSS_KB".into() => "2048".into(),
+            "CPU_TIME_MS".into() => "1250".into(),
+            "WALL_TIME_MS".into() => "3000".into(),
 
             // File paths
             "STDOUT".into() => "stdout".into(),
@@ -321,6 +442,9 @@ mod tests {
             "SUCCESS".into() => "0".into(),
             "EXIT_CODE".into() => "".into(),
             "SIGNAL".into() => "9".into(),
+            "MAX_RSS_KB".into() => "2048".into(),
+            "CPU_TIME_MS".into() => "1250".into(),
+            "WALL_TIME_MS".into() => "3000".into(),
 
             // File paths
             "STDOUT".into() => "stdout".into(),
@@ -331,4 +455,116 @@ mod tests {
             "stderr".into() => "something happened".into(),
         });
     }
+
+
+    #[test]
+    fn test_env_builder_fingerprint() {
+        let provider = StatusProvider::new(
+            r#"{"events": ["job-completed"]}"#,
+        ).unwrap();
+
+        let mut output = dummy_job_output();
+        output.fingerprint = Some(Fingerprint {
+            script_checksum: "abcd".into(),
+            interpreter: Some("/bin/bash".into()),
+            interpreter_version: None,
+            hostname: "example".into(),
+            fisher_version: "1.0.0",
+        });
+        output.fingerprint_changes = Some(vec!["script_checksum", "hostname"]);
+
+        let event = StatusEvent::JobCompleted(output);
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event.into(), &mut b).unwrap();
+
+        let env = &b.dummy_data().env;
+        assert_eq!(env["SCRIPT_CHECKSUM"], "abcd");
+        assert_eq!(env["INTERPRETER"], "/bin/bash");
+        assert_eq!(env["INTERPRETER_VERSION"], "");
+        assert_eq!(env["HOSTNAME"], "example");
+        assert_eq!(env["FISHER_VERSION"], "1.0.0");
+        assert_eq!(env["FINGERPRINT_CHANGES"], "script_checksum,hostname");
+    }
+
+
+    #[test]
+    fn test_env_builder_artifacts() {
+        let provider = StatusProvider::new(
+            r#"{"events": ["job-completed"]}"#,
+        ).unwrap();
+
+        let mut output = dummy_job_output();
+        output.artifacts = vec!["report.html".into(), "build.log".into()];
+
+        let event = StatusEvent::JobCompleted(output);
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event.into(), &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env["ARTIFACTS"], "report.html,build.log");
+    }
+
+
+    #[test]
+    fn test_env_builder_job_pending_approval() {
+        let provider = StatusProvider::new(
+            r#"{"events": ["job-pending-approval"]}"#,
+        ).unwrap();
+
+        let mut pending = PendingApproval {
+            job_id: "abcd".into(),
+            script_name: "test".into(),
+            request_ip: "127.0.0.1".parse().unwrap(),
+            links: None,
+        };
+
+        // The links are not included if they're disabled
+        let event = StatusEvent::JobPendingApproval(pending.clone());
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "job-pending-approval".into(),
+            "SCRIPT_NAME".into() => "test".into(),
+            "JOB_ID".into() => "abcd".into(),
+        });
+
+        pending.links = Some(("approve".into(), "reject".into()));
+        let event = StatusEvent::JobPendingApproval(pending);
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "job-pending-approval".into(),
+            "SCRIPT_NAME".into() => "test".into(),
+            "JOB_ID".into() => "abcd".into(),
+            "APPROVE_URL".into() => "approve".into(),
+            "REJECT_URL".into() => "reject".into(),
+        });
+        assert!(b.dummy_data().files.is_empty());
+    }
+
+    #[test]
+    fn test_env_builder_budget_exhausted() {
+        let provider = StatusProvider::new(
+            r#"{"events": ["budget-exhausted"]}"#,
+        ).unwrap();
+
+        let event = StatusEvent::BudgetExhausted(ExhaustedBudget {
+            script_name: "test".into(),
+            budget: 3600,
+            action: FreezeAction::Queue,
+        });
+        assert_eq!(
+            provider.validate(&event.clone().into()),
+            RequestType::ExecuteHook
+        );
+
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "EVENT".into() => "budget-exhausted".into(),
+            "SCRIPT_NAME".into() => "test".into(),
+            "BUDGET".into() => "3600".into(),
+            "BUDGET_ACTION".into() => "queue".into(),
+        });
+        assert!(b.dummy_data().files.is_empty());
+    }
 }
@@ -0,0 +1,181 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filters on the tags pushed to git hosting services, with support for
+//! semantic versioning.
+
+use glob::{MatchOptions, Pattern};
+use semver::{Version, VersionReq};
+
+use common::prelude::*;
+use scripts::EnvBuilder;
+
+
+/// Get the name of the tag from a git ref, if the ref points to a tag.
+pub fn tag_name(git_ref: &str) -> Option<&str> {
+    git_ref.strip_prefix("refs/tags/")
+}
+
+
+/// Parse the version contained in a tag name, like `v1.2.3` or `1.2.3`.
+pub fn parse_version(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+
+/// Add the environment variables describing a pushed tag.
+pub fn add_tag_env(b: &mut EnvBuilder, tag: &str) {
+    b.add_env("TAG", tag);
+
+    if let Some(version) = parse_version(tag) {
+        b.add_env("VERSION_MAJOR", version.major.to_string());
+        b.add_env("VERSION_MINOR", version.minor.to_string());
+        b.add_env("VERSION_PATCH", version.patch.to_string());
+        b.add_env("VERSION_PRERELEASE", version.is_prerelease().to_string());
+    }
+}
+
+
+/// Glob patterns and a semver range the pushed tags must match.
+#[derive(Debug)]
+pub struct TagFilter {
+    patterns: Option<Vec<Pattern>>,
+    versions: Option<VersionReq>,
+}
+
+impl TagFilter {
+    pub fn new(
+        patterns: Option<&[String]>, versions: Option<&str>,
+    ) -> Result<Self> {
+        let patterns = if let Some(patterns) = patterns {
+            Some(patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern).chain_err(|| {
+                    ErrorKind::ProviderInvalidTagPattern(pattern.clone())
+                }))
+                .collect::<Result<_>>()?)
+        } else {
+            None
+        };
+
+        let versions = if let Some(range) = versions {
+            Some(VersionReq::parse(range).chain_err(|| {
+                ErrorKind::ProviderInvalidVersionRange(range.to_string())
+            })?)
+        } else {
+            None
+        };
+
+        Ok(TagFilter { patterns, versions })
+    }
+
+    /// Check if the tag matches both the patterns and the version range.
+    /// Pre-releases match a range only if it explicitly mentions them.
+    pub fn matches(&self, tag: &str) -> bool {
+        // `*` doesn't match slashes, while `**` does
+        let options = MatchOptions {
+            require_literal_separator: true,
+            .. MatchOptions::new()
+        };
+
+        if let Some(ref patterns) = self.patterns {
+            if !patterns.iter().any(|p| p.matches_with(tag, options)) {
+                return false;
+            }
+        }
+
+        if let Some(ref versions) = self.versions {
+            return parse_version(tag)
+                .map(|version| versions.matches(&version))
+                .unwrap_or(false);
+        }
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scripts::EnvBuilder;
+
+    use super::{add_tag_env, parse_version, tag_name, TagFilter};
+
+
+    #[test]
+    fn test_tag_name() {
+        assert_eq!(tag_name("refs/tags/v1.0.0"), Some("v1.0.0"));
+        assert_eq!(tag_name("refs/tags/nested/tag"), Some("nested/tag"));
+        assert_eq!(tag_name("refs/heads/master"), None);
+    }
+
+
+    #[test]
+    fn test_parse_version() {
+        let version = parse_version("v1.2.3").unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 2, 3));
+        assert!(!version.is_prerelease());
+
+        assert!(parse_version("1.2.3-beta.1").unwrap().is_prerelease());
+        assert!(parse_version("v1.2").is_none());
+        assert!(parse_version("release").is_none());
+    }
+
+
+    #[test]
+    fn test_tag_filter() {
+        let filter = TagFilter::new(Some(&["v*".into()]), None).unwrap();
+        assert!(filter.matches("v1.0.0"));
+        assert!(filter.matches("v1.0.0-rc.1"));
+        assert!(!filter.matches("1.0.0"));
+        assert!(!filter.matches("v1/nested"));
+
+        // Only stable releases of the 1.x series
+        let filter = TagFilter::new(
+            Some(&["v*".into()]), Some(">= 1.0.0, < 2.0.0"),
+        ).unwrap();
+        assert!(filter.matches("v1.0.0"));
+        assert!(filter.matches("v1.4.2"));
+        assert!(!filter.matches("v1.5.0-beta.1"));
+        assert!(!filter.matches("v2.0.0"));
+        assert!(!filter.matches("vnext"));
+
+        let filter = TagFilter::new(None, None).unwrap();
+        assert!(filter.matches("anything"));
+
+        assert!(TagFilter::new(Some(&["v***".into()]), None).is_err());
+        assert!(TagFilter::new(None, Some("not a range")).is_err());
+    }
+
+
+    #[test]
+    fn test_add_tag_env() {
+        let mut b = EnvBuilder::dummy();
+        add_tag_env(&mut b, "v1.2.3-rc.1");
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "TAG".into() => "v1.2.3-rc.1".into(),
+            "VERSION_MAJOR".into() => "1".into(),
+            "VERSION_MINOR".into() => "2".into(),
+            "VERSION_PATCH".into() => "3".into(),
+            "VERSION_PRERELEASE".into() => "true".into(),
+        });
+
+        let mut b = EnvBuilder::dummy();
+        add_tag_env(&mut b, "release");
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "TAG".into() => "release".into(),
+        });
+    }
+}
@@ -38,14 +38,9 @@ impl ProviderTrait for TestingProvider {
         }
     }
 
-    fn validate(&self, request: &Request) -> RequestType {
-        let req;
-        if let &Request::Web(ref inner) = request {
-            req = inner;
-        } else {
-            return RequestType::Invalid;
-        }
-
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
         // If the secret param is provided, validate it
         if let Some(secret) = req.params.get("secret") {
             if secret != "testing" {
@@ -91,6 +86,14 @@ impl ProviderTrait for TestingProvider {
         Ok(())
     }
 
+    fn delivery_id(&self, request: &Request) -> Option<String> {
+        if let &Request::Web(ref inner) = request {
+            inner.params.get("delivery_id").cloned()
+        } else {
+            None
+        }
+    }
+
     fn trigger_status_hooks(&self, request: &Request) -> bool {
         if let &Request::Web(ref inner) = request {
             !inner.params.contains_key("ignore_status_hooks")
@@ -98,6 +101,24 @@ impl ProviderTrait for TestingProvider {
             true
         }
     }
+
+    fn fan_out(&self, request: &Request) -> Option<Vec<String>> {
+        if let &Request::Web(ref inner) = request {
+            inner.params
+                .get("fan_out")
+                .map(|scripts| scripts.split(',').map(String::from).collect())
+        } else {
+            None
+        }
+    }
+
+    fn event_name(&self, request: &Request) -> Option<String> {
+        if let &Request::Web(ref inner) = request {
+            inner.params.get("event").cloned()
+        } else {
+            None
+        }
+    }
 }
 
 
@@ -0,0 +1,566 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conformance tests every provider should pass, usable by the built-in
+//! providers and by third-party ones alike.
+//!
+//! The battery starts from a valid request, and checks the provider accepts
+//! it and rejects its broken variants: missing headers, bad signatures and
+//! tokens, and invalid JSON. Huge bodies and replayed deliveries are sent
+//! too. The variants are derived from the requirements of the provider, and
+//! the provider must never panic while handling them.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use providers::prelude::*;
+use web::JsonBody;
+
+
+/// The default size of the huge bodies, matching the default limit of the
+/// HTTP server.
+const HUGE_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+
+/// A check of the battery failed by a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The name of the check, like `missing_headers`.
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.check, self.message)
+    }
+}
+
+
+/// The conformance battery, starting from a request the provider must
+/// accept.
+pub struct Conformance<'a> {
+    request: WebRequest,
+    signer: Option<Box<Fn(&mut WebRequest) + 'a>>,
+    huge_body_size: usize,
+}
+
+impl<'a> Conformance<'a> {
+    /// Create a new battery from a valid request, already signed if the
+    /// provider requires a signature.
+    pub fn new(request: WebRequest) -> Self {
+        Conformance {
+            request,
+            signer: None,
+            huge_body_size: HUGE_BODY_SIZE,
+        }
+    }
+
+    /// Sign the requests with a changed body with this function, so they
+    /// fail only for the reason being checked.
+    pub fn signer<F: Fn(&mut WebRequest) + 'a>(mut self, signer: F) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    /// Change the size of the huge bodies, in bytes.
+    pub fn huge_body_size(mut self, size: usize) -> Self {
+        self.huge_body_size = size;
+        self
+    }
+
+    /// Run the whole battery against the provider, returning the failed
+    /// checks.
+    pub fn run<P: ProviderTrait>(&self, provider: &P) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        {
+            let mut fail = |check, message: String| {
+                failures.push(Failure { check, message });
+            };
+
+            self.check_valid_request(provider, &mut fail);
+            self.check_missing_headers(provider, &mut fail);
+            self.check_bad_signature(provider, &mut fail);
+            self.check_bad_token(provider, &mut fail);
+            self.check_invalid_json(provider, &mut fail);
+            self.check_huge_body(provider, &mut fail);
+            self.check_replayed_delivery(provider, &mut fail);
+        }
+        failures
+    }
+
+    /// Run the whole battery against the provider, panicking with all the
+    /// failed checks if there are any.
+    pub fn assert_passes<P: ProviderTrait>(&self, provider: &P) {
+        let failures = self.run(provider);
+        if !failures.is_empty() {
+            let list: Vec<_> = failures.iter().map(|f| f.to_string()).collect();
+            panic!(
+                "{:?} failed {} conformance checks:\n{}",
+                provider, failures.len(), list.join("\n"),
+            );
+        }
+    }
+
+    /// Create a copy of the valid request, with the body replaced and
+    /// signed again if `body` is provided.
+    fn variant(&self, body: Option<Vec<u8>>) -> WebRequest {
+        let mut req = self.request.clone();
+        req.json_body = JsonBody::default();
+        if let Some(body) = body {
+            req.body = body;
+            req.spooled = None;
+            if let Some(ref signer) = self.signer {
+                signer(&mut req);
+            }
+        }
+        req
+    }
+
+    fn check_valid_request<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let check = "valid_request";
+        let req = self.variant(None).into();
+        match validate(provider, &req) {
+            Ok(RequestType::Invalid) => {
+                fail(check, "the valid request was rejected".into());
+            }
+            Ok(RequestType::Ping) => {}
+            Ok(RequestType::ExecuteHook) => {
+                if let Err(message) = build_env(provider, &req) {
+                    fail(check, message);
+                }
+            }
+            Err(message) => fail(check, message),
+        }
+    }
+
+    fn check_missing_headers<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let requirements = provider.requirements();
+        let mut headers = requirements.required_headers().to_vec();
+        headers.extend(requirements.signature_header());
+        headers.extend(requirements.token_header());
+
+        for header in headers {
+            let mut req = self.variant(None);
+            req.headers.remove(header);
+            expect_invalid(
+                provider, &req.into(), "missing_headers",
+                &format!("without the {} header", header), fail,
+            );
+        }
+    }
+
+    fn check_bad_signature<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let header = match provider.requirements().signature_header() {
+            Some(header) => header.to_string(),
+            None => return,
+        };
+
+        let mut req = self.variant(None);
+        req.headers.insert(header.clone(), format!("sha1={}", "0".repeat(40)));
+        expect_invalid(
+            provider, &req.into(), "bad_signature",
+            &format!("with a wrong {} header", header), fail,
+        );
+
+        // The body is changed without signing it again, keeping it valid
+        // JSON if it was
+        let mut req = self.variant(None);
+        req.body.push(b' ');
+        expect_invalid(
+            provider, &req.into(), "bad_signature",
+            "with a tampered body", fail,
+        );
+    }
+
+    fn check_bad_token<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let header = match provider.requirements().token_header() {
+            Some(header) => header.to_string(),
+            None => return,
+        };
+
+        let mut req = self.variant(None);
+        req.headers.insert(header.clone(), "wrong-token".into());
+        expect_invalid(
+            provider, &req.into(), "bad_token",
+            &format!("with a wrong {} header", header), fail,
+        );
+    }
+
+    fn check_invalid_json<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let check = "invalid_json";
+        let req = self.variant(Some(b"{\"invalid\": json".to_vec())).into();
+        if provider.requirements().requires_json_body() {
+            expect_invalid(provider, &req, check, "with invalid JSON", fail);
+        } else if let Err(message) = process(provider, &req) {
+            fail(check, message);
+        }
+    }
+
+    fn check_huge_body<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        // The body is padded with whitespace, keeping it valid JSON if it was
+        let mut body = self.request.body.clone();
+        let padding = self.huge_body_size.saturating_sub(body.len());
+        body.extend(::std::iter::repeat(b' ').take(padding));
+
+        let req = self.variant(Some(body)).into();
+        if let Err(message) = process(provider, &req) {
+            fail("huge_body", message);
+        }
+    }
+
+    fn check_replayed_delivery<P, F>(&self, provider: &P, fail: &mut F)
+    where
+        P: ProviderTrait,
+        F: FnMut(&'static str, String),
+    {
+        let check = "replayed_delivery";
+        let first = self.variant(None).into();
+        let second = self.variant(None).into();
+
+        let types = (validate(provider, &first), validate(provider, &second));
+        if let (Ok(first), Ok(second)) = types {
+            if first != second {
+                fail(check, format!(
+                    "the replay was {:?} instead of {:?}", second, first,
+                ));
+            }
+        }
+
+        // Redeliveries must be recognizable as such
+        let ids = catch(|| {
+            (provider.delivery_id(&first), provider.delivery_id(&second))
+        });
+        match ids {
+            Ok((first, second)) => if first != second {
+                fail(check, format!(
+                    "the replay has the delivery ID {:?} instead of {:?}",
+                    second, first,
+                ));
+            },
+            Err(message) => fail(check, message),
+        }
+    }
+}
+
+
+/// Call the function, converting a panic into an error message.
+fn catch<T, F: FnOnce() -> T>(function: F) -> ::std::result::Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(function)).map_err(|payload| {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".into()
+        };
+        format!("the provider panicked: {}", message)
+    })
+}
+
+fn validate<P: ProviderTrait>(
+    provider: &P, req: &Request,
+) -> ::std::result::Result<RequestType, String> {
+    catch(|| provider.validate(req))
+}
+
+fn build_env<P: ProviderTrait>(
+    provider: &P, req: &Request,
+) -> ::std::result::Result<(), String> {
+    catch(|| provider.build_env(req, &mut EnvBuilder::dummy()))?
+        .map_err(|err| format!("building the environment failed: {}", err))
+}
+
+/// Validate the request and build its environment if it's accepted, which
+/// must not panic. The request might be rejected.
+fn process<P: ProviderTrait>(
+    provider: &P, req: &Request,
+) -> ::std::result::Result<(), String> {
+    if validate(provider, req)? == RequestType::ExecuteHook {
+        build_env(provider, req)?;
+    }
+    Ok(())
+}
+
+fn expect_invalid<P, F>(
+    provider: &P, req: &Request, check: &'static str, variant: &str,
+    fail: &mut F,
+) where
+    P: ProviderTrait,
+    F: FnMut(&'static str, String),
+{
+    match validate(provider, req) {
+        Ok(RequestType::Invalid) => {}
+        Ok(other) => fail(check, format!(
+            "the request {} was accepted as {:?}", variant, other,
+        )),
+        Err(message) => fail(check, message),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use providers::prelude::*;
+    use providers::catch::CatchProvider;
+    use providers::discord::DiscordProvider;
+    use providers::github::GitHubProvider;
+    use providers::gitlab::GitLabProvider;
+    use providers::matrix::MatrixProvider;
+    use providers::mattermost::MattermostProvider;
+    use providers::s3::S3Provider;
+    use providers::standalone::StandaloneProvider;
+    use providers::status::StatusProvider;
+    use providers::uptime::UptimeProvider;
+    use utils::testing::*;
+    use utils::{self, HmacAlgorithm};
+
+    use super::Conformance;
+
+
+    fn sign(req: &mut WebRequest) {
+        let signature = HmacAlgorithm::Sha1.sign(b"secret", &req.body);
+        req.headers.insert(
+            "X-Hub-Signature".into(),
+            format!("sha1={}", utils::to_hex(&signature)),
+        );
+    }
+
+
+    #[derive(Debug)]
+    struct UnwrappingProvider;
+
+    impl ProviderTrait for UnwrappingProvider {
+        fn new(_config: &str) -> Result<Self> {
+            Ok(UnwrappingProvider)
+        }
+
+        fn validate_web(
+            &self, req: &WebRequest, _trace: &mut ValidationTrace,
+        ) -> RequestType {
+            req.json().unwrap();
+            RequestType::ExecuteHook
+        }
+
+        fn build_env(&self, _req: &Request, _b: &mut EnvBuilder) -> Result<()> {
+            Ok(())
+        }
+    }
+
+
+    #[test]
+    fn test_github() {
+        let mut req = dummy_web_request();
+        req.headers.insert("X-GitHub-Event".into(), "ping".into());
+        req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
+        req.body = br#"{"zen": "Keep it simple"}"#.to_vec();
+        sign(&mut req);
+
+        let provider = GitHubProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .signer(sign)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_gitlab() {
+        let mut req = dummy_web_request();
+        req.headers.insert("X-Gitlab-Event".into(), "Push Hook".into());
+        req.headers.insert("X-Gitlab-Token".into(), "secret".into());
+        req.body = br#"{"ref": "refs/heads/master"}"#.to_vec();
+
+        let provider = GitLabProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_standalone() {
+        let mut req = dummy_web_request();
+        req.params.insert("secret".into(), "secret".into());
+
+        let provider = StandaloneProvider::new(
+            r#"{"secret": "secret"}"#,
+        ).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_discord() {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&[42; 32]).unwrap();
+        let sign = |req: &mut WebRequest| {
+            let timestamp = utils::unix_now().to_string();
+            let mut message = timestamp.as_bytes().to_vec();
+            message.extend_from_slice(&req.body);
+            let signature = key_pair.sign(&message);
+
+            req.headers.insert(
+                "X-Signature-Ed25519".into(), utils::to_hex(signature.as_ref()),
+            );
+            req.headers.insert("X-Signature-Timestamp".into(), timestamp);
+        };
+
+        let mut req = dummy_web_request();
+        req.body = br#"{"id": "123", "type": 2, "data": {"name": "a"}}"#
+            .to_vec();
+        sign(&mut req);
+
+        let provider = DiscordProvider::new(&json!({
+            "public_key": utils::to_hex(key_pair.public_key().as_ref()),
+        }).to_string()).unwrap();
+        Conformance::new(req)
+            .signer(sign)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_mattermost() {
+        let mut req = dummy_web_request();
+        req.body = b"token=secret&channel_name=deploys&post_id=p1".to_vec();
+
+        let provider = MattermostProvider::new(
+            r#"{"secret": "secret"}"#,
+        ).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let mut req = dummy_web_request();
+        req.params.insert("access_token".into(), "secret".into());
+        req.body = json!({"events": [{
+            "type": "m.room.message",
+            "event_id": "$event",
+            "room_id": "!room:example.com",
+            "content": {"msgtype": "m.text", "body": "hi"},
+        }]}).to_string().into_bytes();
+
+        let provider = MatrixProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_uptime() {
+        let mut req = dummy_web_request();
+        req.params.insert("token".into(), "secret".into());
+        req.body = b"monitorID=42&monitorFriendlyName=Website&alertType=1"
+            .to_vec();
+
+        let provider = UptimeProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_s3() {
+        let sign = |req: &mut WebRequest| {
+            let signature = HmacAlgorithm::Sha256.sign(b"secret", &req.body);
+            req.headers.insert(
+                "X-Bz-Event-Notification-Signature".into(),
+                format!("v1={}", utils::to_hex(&signature)),
+            );
+        };
+
+        let mut req = dummy_web_request();
+        req.body = json!({"events": [{
+            "eventType": "b2:ObjectCreated:Upload",
+            "bucketName": "uploads",
+            "objectName": "images/cat.png",
+        }]}).to_string().into_bytes();
+        sign(&mut req);
+
+        let provider = S3Provider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .signer(sign)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_catch() {
+        let mut req = dummy_web_request();
+        req.params.insert("token".into(), "secret".into());
+        req.body = br#"{"a": {"b": [1, 2]}}"#.to_vec();
+
+        let provider = CatchProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        Conformance::new(req)
+            .huge_body_size(1024 * 1024)
+            .assert_passes(&provider);
+    }
+
+    #[test]
+    fn test_status() {
+        let provider = StatusProvider::new(
+            r#"{"events": ["job-completed"]}"#,
+        ).unwrap();
+
+        // Status hooks are never executed by webhooks, so the battery must
+        // only complain about the valid request being rejected
+        let failures = Conformance::new(dummy_web_request())
+            .huge_body_size(1024)
+            .run(&provider);
+        let checks: Vec<_> = failures.iter().map(|f| f.check).collect();
+        assert_eq!(checks, vec!["valid_request"]);
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let failures = Conformance::new(dummy_web_request())
+            .huge_body_size(1024)
+            .run(&UnwrappingProvider);
+
+        // The empty body of the valid request isn't valid JSON either
+        let checks: Vec<_> = failures.iter().map(|f| f.check).collect();
+        assert!(checks.contains(&"valid_request"));
+        assert!(checks.contains(&"invalid_json"));
+        assert!(failures.iter().all(|f| f.message.contains("panicked")));
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+/// A single check performed by a provider while validating a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationCheck {
+    pub name: String,
+    pub passed: bool,
+}
+
+
+/// The checks performed by a provider while validating a request, in the
+/// order they were executed. This is used to debug rejected requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationTrace {
+    checks: Vec<ValidationCheck>,
+}
+
+impl ValidationTrace {
+    pub fn new() -> Self {
+        ValidationTrace {
+            checks: Vec::new(),
+        }
+    }
+
+    /// Record the result of a check, and return it.
+    pub fn check(&mut self, name: &str, passed: bool) -> bool {
+        self.checks.push(ValidationCheck {
+            name: name.into(),
+            passed,
+        });
+        passed
+    }
+
+    pub fn checks(&self) -> &[ValidationCheck] {
+        &self.checks
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::ValidationTrace;
+
+
+    #[test]
+    fn test_validation_trace() {
+        let mut trace = ValidationTrace::new();
+        assert!(trace.check("first", true));
+        assert!(!trace.check("second", false));
+
+        let checks = trace.checks();
+        assert_eq!(checks.len(), 2);
+        assert_eq!((checks[0].name.as_str(), checks[0].passed), ("first", true));
+        assert_eq!(
+            (checks[1].name.as_str(), checks[1].passed), ("second", false)
+        );
+    }
+}
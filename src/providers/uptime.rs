@@ -0,0 +1,331 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde_json::{self, Value};
+use url::form_urlencoded;
+
+use providers::prelude::*;
+use utils;
+
+
+lazy_static! {
+    static ref ALERT_KINDS: Vec<&'static str> = vec!["down", "up"];
+}
+
+
+/// An alert sent by one of the supported uptime monitors.
+#[derive(Debug)]
+struct Alert {
+    service: &'static str,
+    status: &'static str,
+    monitor_id: Option<String>,
+    monitor_name: Option<String>,
+    monitor_url: Option<String>,
+    details: Option<String>,
+}
+
+impl Alert {
+    fn parse(req: &WebRequest) -> Option<Alert> {
+        let body = req.body_str().trim();
+
+        if body.starts_with('{') {
+            let json: Value = serde_json::from_str(body).ok()?;
+
+            // Better Uptime sends the incident as a JSON:API document
+            if let Some(attrs) = json.pointer("/data/attributes") {
+                return Alert::parse_better_uptime(attrs);
+            }
+
+            let fields = json.as_object()?.iter()
+                .filter_map(|(key, value)| match *value {
+                    Value::String(ref string) => {
+                        Some((key.clone(), string.clone()))
+                    }
+                    Value::Number(ref num) => {
+                        Some((key.clone(), num.to_string()))
+                    }
+                    _ => None,
+                })
+                .collect();
+            Alert::parse_uptimerobot(&fields)
+        } else {
+            // UptimeRobot can send the alert either in the query string or
+            // in a form-encoded body
+            let mut fields = req.params.clone();
+            fields.extend(form_urlencoded::parse(body.as_bytes()).into_owned());
+            Alert::parse_uptimerobot(&fields)
+        }
+    }
+
+    fn parse_uptimerobot(fields: &HashMap<String, String>) -> Option<Alert> {
+        let status = match fields.get("alertType").map(|s| s.as_str()) {
+            Some("1") => "down",
+            Some("2") => "up",
+            _ => return None,
+        };
+
+        Some(Alert {
+            service: "uptimerobot",
+            status,
+            monitor_id: fields.get("monitorID").cloned(),
+            monitor_name: fields.get("monitorFriendlyName").cloned(),
+            monitor_url: fields.get("monitorURL").cloned(),
+            details: fields.get("alertDetails").cloned(),
+        })
+    }
+
+    fn parse_better_uptime(attrs: &Value) -> Option<Alert> {
+        let string = |key: &str| {
+            attrs.get(key).and_then(|v| v.as_str()).map(|s| s.to_string())
+        };
+
+        // An incident is resolved when the monitor is up again
+        let status = match attrs.get("resolved_at") {
+            Some(&Value::Null) | None => "down",
+            Some(_) => "up",
+        };
+
+        Some(Alert {
+            service: "betteruptime",
+            status,
+            monitor_id: string("monitor_id"),
+            monitor_name: Some(string("name")?),
+            monitor_url: string("url"),
+            details: string("cause"),
+        })
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct UptimeProvider {
+    secret: utils::Secrets,
+    alerts: Option<Vec<String>>,
+}
+
+impl ProviderTrait for UptimeProvider {
+    fn new(config: &str) -> Result<Self> {
+        let inst: UptimeProvider = serde_json::from_str(config)?;
+
+        if let Some(ref alerts) = inst.alerts {
+            for alert in alerts {
+                if !ALERT_KINDS.contains(&alert.as_str()) {
+                    return Err(ErrorKind::ProviderUptimeInvalidAlertType(
+                        alert.clone()
+                    ).into());
+                }
+            }
+        }
+
+        Ok(inst)
+    }
+
+    fn validate_web(
+        &self, req: &WebRequest, _trace: &mut ValidationTrace,
+    ) -> RequestType {
+        // The token must be present in the URL
+        match req.params.get("token") {
+            Some(token) if self.secret.matches(token) => {}
+            _ => return RequestType::Invalid,
+        }
+
+        let alert = if let Some(alert) = Alert::parse(req) {
+            alert
+        } else {
+            return RequestType::Invalid;
+        };
+
+        // Alerts not whitelisted are acknowledged without running the
+        // script, to avoid the monitor retrying them
+        if let Some(ref alerts) = self.alerts {
+            if !alerts.iter().any(|a| a == alert.status) {
+                return RequestType::Ping;
+            }
+        }
+
+        RequestType::ExecuteHook
+    }
+
+    fn build_env(&self, r: &Request, b: &mut EnvBuilder) -> Result<()> {
+        let req;
+        if let Request::Web(ref inner) = *r {
+            req = inner;
+        } else {
+            return Ok(());
+        }
+
+        if let Some(alert) = Alert::parse(req) {
+            b.add_env("SERVICE", alert.service);
+            b.add_env("STATUS", alert.status);
+
+            for &(name, value) in &[
+                ("MONITOR_ID", &alert.monitor_id),
+                ("MONITOR_NAME", &alert.monitor_name),
+                ("MONITOR_URL", &alert.monitor_url),
+                ("DETAILS", &alert.details),
+            ] {
+                if let Some(ref value) = *value {
+                    b.add_env(name, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn event_name(&self, r: &Request) -> Option<String> {
+        if let Request::Web(ref req) = *r {
+            Alert::parse(req).map(|alert| alert.status.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::testing::*;
+    use requests::RequestType;
+    use web::WebRequest;
+    use providers::ProviderTrait;
+    use scripts::EnvBuilder;
+
+    use super::UptimeProvider;
+
+
+    fn request(body: &str) -> WebRequest {
+        let mut req = dummy_web_request();
+        req.params.insert("token".into(), "abcde".into());
+        req.body = body.into();
+        req
+    }
+
+
+    const UPTIMEROBOT_DOWN: &str = concat!(
+        "monitorID=42&monitorURL=https%3A%2F%2Fexample.com&",
+        "monitorFriendlyName=Website&alertType=1&alertDetails=Timeout",
+    );
+
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{"secret": "abcde"}"#,
+            r#"{"secret": "abcde", "alerts": ["down"]}"#,
+            r#"{"secret": "abcde", "alerts": ["down", "up"]}"#,
+        ] {
+            assert!(UptimeProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{}"#,
+            r#"{"secret": 12345}"#,
+            r#"{"secret": "abcde", "alerts": "down"}"#,
+            r#"{"secret": "abcde", "alerts": ["paused"]}"#,
+        ] {
+            assert!(UptimeProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_validate() {
+        let p = UptimeProvider::new(
+            r#"{"secret": "abcde", "alerts": ["down"]}"#
+        ).unwrap();
+
+        // Down alerts from both services execute the hook
+        for body in &[
+            UPTIMEROBOT_DOWN,
+            r#"{"alertType": 1, "monitorFriendlyName": "Website"}"#,
+            r#"{"data": {"attributes": {"name": "Website"}}}"#,
+        ] {
+            assert_eq!(
+                p.validate(&request(body).into()), RequestType::ExecuteHook,
+                "{}", body
+            );
+        }
+
+        // UptimeRobot alerts can also be sent in the query string
+        let mut req = request("");
+        req.params.insert("alertType".into(), "1".into());
+        assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
+
+        // Alerts not whitelisted are only acknowledged
+        for body in &[
+            "alertType=2",
+            r#"{"data": {"attributes": {
+                "name": "Website", "resolved_at": "2017-01-01T00:00:00Z"
+            }}}"#,
+        ] {
+            assert_eq!(
+                p.validate(&request(body).into()), RequestType::Ping,
+                "{}", body
+            );
+        }
+
+        // Unknown bodies are rejected
+        for body in &["", "alertType=3", "{", r#"{"data": {}}"#] {
+            assert_eq!(
+                p.validate(&request(body).into()), RequestType::Invalid,
+                "{}", body
+            );
+        }
+
+        // The token is required
+        let mut req = request(UPTIMEROBOT_DOWN);
+        req.params.insert("token".into(), "wrong".into());
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        let mut req = request(UPTIMEROBOT_DOWN);
+        req.params.clear();
+        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let p = UptimeProvider::new(r#"{"secret": "abcde"}"#).unwrap();
+
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&request(UPTIMEROBOT_DOWN).into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "SERVICE".into() => "uptimerobot".into(),
+            "STATUS".into() => "down".into(),
+            "MONITOR_ID".into() => "42".into(),
+            "MONITOR_NAME".into() => "Website".into(),
+            "MONITOR_URL".into() => "https://example.com".into(),
+            "DETAILS".into() => "Timeout".into(),
+        });
+
+        let req = request(&json!({"data": {"id": "1", "attributes": {
+            "name": "Website",
+            "url": "https://example.com",
+            "cause": "Status 500",
+            "resolved_at": "2017-01-01T00:00:00Z",
+        }}}).to_string());
+        let mut b = EnvBuilder::dummy();
+        p.build_env(&req.into(), &mut b).unwrap();
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "SERVICE".into() => "betteruptime".into(),
+            "STATUS".into() => "up".into(),
+            "MONITOR_NAME".into() => "Website".into(),
+            "MONITOR_URL".into() => "https://example.com".into(),
+            "DETAILS".into() => "Status 500".into(),
+        });
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fingerprint of the environment a job is executed in.
+//!
+//! The fingerprint is recorded with every job output, so when a script
+//! behaves differently between two runs it's possible to see whether the
+//! script itself, its interpreter or the machine changed.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use nix::unistd::{gethostname, Pid};
+use sha2::{Digest, Sha256};
+
+use common::prelude::*;
+use utils;
+
+
+lazy_static! {
+    // Executing the interpreter for every job would be too slow, so the
+    // versions are cached until the interpreter is changed on disk
+    static ref VERSIONS: Mutex<HashMap<String, (Option<SystemTime>, String)>> =
+        Mutex::new(HashMap::new());
+
+    // The fingerprint of the last execution of each script
+    static ref LAST: Mutex<HashMap<String, Fingerprint>> =
+        Mutex::new(HashMap::new());
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The SHA-256 checksum of the script, in hex.
+    pub script_checksum: String,
+    /// The interpreter in the shebang of the script, if it has one.
+    pub interpreter: Option<String>,
+    /// The first line of the output of `<interpreter> --version`.
+    pub interpreter_version: Option<String>,
+    /// The hostname of the machine running Fisher.
+    pub hostname: String,
+    /// The version of Fisher executing the job.
+    pub fisher_version: &'static str,
+}
+
+impl Fingerprint {
+    /// Take the fingerprint of the environment the script is executed in.
+    pub fn take(script_path: &str) -> Result<Self> {
+        let mut content = Vec::new();
+        File::open(script_path)?.read_to_end(&mut content)?;
+
+        let interpreter = shebang(&content);
+        let interpreter_version = interpreter
+            .as_ref()
+            .and_then(|interpreter| interpreter_version(interpreter));
+
+        Ok(Fingerprint {
+            script_checksum: utils::to_hex(&Sha256::digest(&content)),
+            interpreter,
+            interpreter_version,
+            hostname: hostname(),
+            fisher_version: env!("CARGO_PKG_VERSION"),
+        })
+    }
+
+    /// Get the names of the fields which are different in another
+    /// fingerprint.
+    pub fn changes(&self, other: &Fingerprint) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.script_checksum != other.script_checksum {
+            changes.push("script_checksum");
+        }
+        if self.interpreter != other.interpreter {
+            changes.push("interpreter");
+        }
+        if self.interpreter_version != other.interpreter_version {
+            changes.push("interpreter_version");
+        }
+        if self.hostname != other.hostname {
+            changes.push("hostname");
+        }
+        if self.fisher_version != other.fisher_version {
+            changes.push("fisher_version");
+        }
+        changes
+    }
+
+    /// Remember this as the fingerprint of the last execution of the
+    /// script, returning what changed since the previous one. Nothing is
+    /// returned for the first execution.
+    pub fn record(&self, script_name: &str) -> Option<Vec<&'static str>> {
+        LAST.lock()
+            .unwrap()
+            .insert(script_name.to_string(), self.clone())
+            .map(|previous| previous.changes(self))
+    }
+}
+
+
+/// Extract the interpreter from the shebang of the script.
+fn shebang(content: &[u8]) -> Option<String> {
+    if !content.starts_with(b"#!") {
+        return None;
+    }
+
+    let line = BufReader::new(&content[2..]).lines().next()?.ok()?;
+    let interpreter = line.trim();
+    if interpreter.is_empty() {
+        None
+    } else {
+        Some(interpreter.to_string())
+    }
+}
+
+
+fn interpreter_version(interpreter: &str) -> Option<String> {
+    let mut args = interpreter.split_whitespace();
+    let program = args.next()?;
+
+    // Interpreters which can't be executed are not cached, so they're
+    // picked up as soon as they're installed
+    let modified = fs::metadata(program).and_then(|m| m.modified()).ok();
+    if let Some(&(cached_modified, ref version)) =
+        VERSIONS.lock().unwrap().get(interpreter)
+    {
+        if cached_modified == modified {
+            return Some(version.clone());
+        }
+    }
+
+    let child = utils::spawn_tracked(Command::new(program)
+        .args(args)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+    ).ok()?;
+    let pid = Pid::from_raw(child.id() as i32);
+    let output = child.wait_with_output();
+    utils::untrack(pid);
+    let output = output.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // Some interpreters (like Python 2) print the version to stderr
+    let version = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+        .map(|line| line.trim().to_string())
+        .find(|line| !line.is_empty())?;
+
+    VERSIONS.lock().unwrap().insert(
+        interpreter.to_string(), (modified, version.clone()),
+    );
+    Some(version)
+}
+
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    gethostname(&mut buffer)
+        .ok()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::{shebang, Fingerprint};
+
+
+    #[test]
+    fn test_shebang() {
+        assert_eq!(shebang(b"#!/bin/bash\necho hi"), Some("/bin/bash".into()));
+        assert_eq!(
+            shebang(b"#! /usr/bin/env python3 \nprint()"),
+            Some("/usr/bin/env python3".into())
+        );
+        assert_eq!(shebang(b"#!\necho hi"), None);
+        assert_eq!(shebang(b"echo hi"), None);
+        assert_eq!(shebang(b"\x7fELF"), None);
+    }
+
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("script.sh");
+        let write = |content: &str| {
+            File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        };
+
+        write("#!/bin/bash\necho hi\n");
+        let path_str = path.to_str().unwrap();
+        let first = Fingerprint::take(path_str).unwrap();
+        assert_eq!(first.interpreter, Some("/bin/bash".into()));
+        assert!(first.interpreter_version.is_some());
+        assert_eq!(first.script_checksum.len(), 64);
+        assert_eq!(first.fisher_version, env!("CARGO_PKG_VERSION"));
+        assert!(!first.hostname.is_empty());
+
+        // The same script has the same fingerprint
+        let second = Fingerprint::take(path_str).unwrap();
+        assert!(first.changes(&second).is_empty());
+
+        // Changes to the script are detected
+        write("#!/bin/bash\necho hello\n");
+        let third = Fingerprint::take(path_str).unwrap();
+        assert_eq!(first.changes(&third), vec!["script_checksum"]);
+
+        // Missing interpreters don't prevent taking the fingerprint
+        write("#!/missing/interpreter\n");
+        let missing = Fingerprint::take(path_str).unwrap();
+        assert_eq!(missing.interpreter, Some("/missing/interpreter".into()));
+        assert_eq!(missing.interpreter_version, None);
+        assert_eq!(
+            first.changes(&missing),
+            vec!["script_checksum", "interpreter", "interpreter_version"]
+        );
+
+        assert!(Fingerprint::take("/missing").is_err());
+
+        // The changes are tracked between executions
+        assert_eq!(first.record("fingerprint-test.sh"), None);
+        assert_eq!(
+            third.record("fingerprint-test.sh"), Some(vec!["script_checksum"])
+        );
+        assert_eq!(third.record("fingerprint-test.sh"), Some(vec![]));
+    }
+}
@@ -16,24 +16,41 @@
 use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::net::IpAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
-use std::sync::Arc;
-
+use std::process::{Command, Output, Stdio};
+use std::str;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::Signal;
+use nix::sys::stat::{umask, Mode};
 use nix::unistd::{setpgid, Pid};
+use rand;
 use tempdir::TempDir;
 use users;
 
 use common::prelude::*;
+use common::metrics;
 use common::state::UniqueId;
+use common::structs::{
+    CircuitBreaker, DailyBudget, EnvSanitization, ShedPolicy,
+};
 
-use scripts::Script;
+use scripts::{ArtifactUploads, Fingerprint, LiveOutput, Script};
+use scripts::progress::{RunningGuard, RunningJobs};
+use scripts::uploads::UploadsGuard;
 use requests::Request;
 use providers::Provider;
+use traces::{self, Span, TraceContext};
+use utils::{self, ResourceUsage};
 
 
 static DEFAULT_ENV: &[&'static str] = &[
@@ -42,11 +59,31 @@ static DEFAULT_ENV: &[&'static str] = &[
 
 static ENV_PREFIX: &'static str = "FISHER";
 
+/// Values bigger than this, in bytes, are written to data files instead of
+/// being put in the environment, which has a limited size.
+const ENV_SPILLOVER_SIZE: usize = 16 * 1024;
+
+/// Umask applied to the data files and directories if the script doesn't
+/// configure one, to avoid leaking payloads to the other users.
+const DEFAULT_DATA_UMASK: u32 = 0o077;
+
+/// How long the processes of a job which timed out have to exit after
+/// receiving SIGTERM, before being killed.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 
 #[derive(Debug)]
 pub struct Context {
     pub environment: HashMap<String, String>,
     pub username: String,
+    pub temp_dir: Option<PathBuf>,
+    pub require_tmpfs: bool,
+    pub uploads: Arc<Mutex<ArtifactUploads>>,
+    pub running: Arc<Mutex<RunningJobs>>,
+    /// Shell command run by Fisher before every job
+    pub before_job: Option<String>,
+    /// Shell command run by Fisher after every job
+    pub after_job: Option<String>,
 }
 
 impl Default for Context {
@@ -63,6 +100,42 @@ impl Default for Context {
         Context {
             environment: HashMap::new(),
             username,
+            temp_dir: None,
+            require_tmpfs: false,
+            uploads: Arc::new(Mutex::new(ArtifactUploads::new())),
+            running: Arc::new(Mutex::new(RunningJobs::new())),
+            before_job: None,
+            after_job: None,
+        }
+    }
+}
+
+
+/// Where an environment variable comes from, used to report collisions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EnvSource {
+    /// Inherited from the environment Fisher is running in
+    System,
+    /// Set by Fisher itself
+    Fisher,
+    /// Set in the `[env]` section of the configuration
+    Config,
+    /// Set by the provider with this name
+    Provider(String),
+    /// Provided when the job was triggered manually
+    Trigger,
+}
+
+impl fmt::Display for EnvSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnvSource::System => write!(f, "the system"),
+            EnvSource::Fisher => write!(f, "Fisher"),
+            EnvSource::Config => write!(f, "the configuration"),
+            EnvSource::Provider(ref name) => {
+                write!(f, "the {} provider", name)
+            }
+            EnvSource::Trigger => write!(f, "the manual trigger"),
         }
     }
 }
@@ -71,10 +144,10 @@ impl Default for Context {
 struct EnvBuilderReal<'job> {
     command: &'job mut Command,
     data_dir: &'job Path,
+    file_mode: u32,
     last_file: Option<fs::File>,
 }
 
-#[cfg(test)]
 pub struct EnvBuilderDummy {
     pub env: HashMap<String, String>,
     pub files: HashMap<String, Vec<u8>>,
@@ -82,35 +155,53 @@ pub struct EnvBuilderDummy {
 
 enum EnvBuilderInner<'job> {
     Real(EnvBuilderReal<'job>),
-    #[cfg(test)]
     Dummy(EnvBuilderDummy),
 }
 
 pub struct EnvBuilder<'job> {
     inner: EnvBuilderInner<'job>,
+    base_prefix: String,
     prefix: Option<OsString>,
+    source: EnvSource,
+    sources: HashMap<OsString, EnvSource>,
+    sanitization: EnvSanitization,
+    spillover_error: Option<Error>,
 }
 
 impl<'job> EnvBuilder<'job> {
-    fn new(command: &'job mut Command, data_dir: &'job Path) -> Self {
+    fn new(
+        command: &'job mut Command, data_dir: &'job Path, file_mode: u32,
+    ) -> Self {
         EnvBuilder {
             inner: EnvBuilderInner::Real(EnvBuilderReal {
                 command,
                 data_dir,
+                file_mode,
                 last_file: None,
             }),
+            base_prefix: ENV_PREFIX.into(),
             prefix: Some(ENV_PREFIX.into()),
+            source: EnvSource::Fisher,
+            sources: HashMap::new(),
+            sanitization: EnvSanitization::None,
+            spillover_error: None,
         }
     }
 
-    #[cfg(test)]
+    /// Create a builder which doesn't execute anything, keeping the
+    /// environment and the data files in memory.
     pub fn dummy() -> Self {
         EnvBuilder {
             inner: EnvBuilderInner::Dummy(EnvBuilderDummy {
                 env: HashMap::new(),
                 files: HashMap::new(),
             }),
+            base_prefix: ENV_PREFIX.into(),
             prefix: None,
+            source: EnvSource::Fisher,
+            sources: HashMap::new(),
+            sanitization: EnvSanitization::None,
+            spillover_error: None,
         }
     }
 
@@ -123,16 +214,58 @@ impl<'job> EnvBuilder<'job> {
         }
     }
 
-    fn set_prefix(&mut self, prefix: Option<&str>) {
+    pub(crate) fn set_prefix(&mut self, prefix: Option<&str>) {
+        let mut parts = Vec::new();
+        if !self.base_prefix.is_empty() {
+            parts.push(self.base_prefix.clone());
+        }
         if let Some(prefix) = prefix {
-            let prefix = prefix.chars()
+            parts.push(prefix.chars()
                 .map(|c| c.to_uppercase().to_string())
-                .collect::<String>();
+                .collect::<String>());
+        }
 
-            self.prefix = Some(format!("{}_{}", ENV_PREFIX, prefix).into());
+        self.prefix = if parts.is_empty() {
+            None
         } else {
-            self.prefix = Some(ENV_PREFIX.into());
+            Some(parts.join("_").into())
+        };
+    }
+
+    /// Replace the `FISHER` prefix of the following variables, or remove it
+    /// if `base` is empty.
+    pub(crate) fn set_base_prefix(&mut self, base: &str) {
+        self.base_prefix = base.into();
+        self.set_prefix(None);
+    }
+
+    /// Sanitize the values of the following variables added with `add_env`.
+    pub(crate) fn set_sanitization(&mut self, sanitization: EnvSanitization) {
+        self.sanitization = sanitization;
+    }
+
+    /// Attribute the following variables to `source`.
+    pub(crate) fn set_source(&mut self, source: EnvSource) {
+        self.source = source;
+    }
+
+    /// Record the variable is set by the current source, returning false if
+    /// another source already set it. Only the variables inherited from the
+    /// system can be overridden.
+    fn claim(&mut self, name: &OsStr) -> bool {
+        if let Some(previous) = self.sources.get(name) {
+            if *previous != EnvSource::System {
+                println!(
+                    "Environment variable {} set by {} ignored: it was \
+                     already set by {}",
+                    name.to_string_lossy(), self.source, previous,
+                );
+                return false;
+            }
         }
+
+        self.sources.insert(name.into(), self.source.clone());
+        true
     }
 
     fn env_name<N: AsRef<OsStr>>(&self, name: N) -> OsString {
@@ -147,11 +280,11 @@ impl<'job> EnvBuilder<'job> {
     }
 
     fn clear_env(&mut self) {
+        self.sources.clear();
         match self.inner {
             EnvBuilderInner::Real(ref mut inner) => {
                 inner.command.env_clear();
             }
-            #[cfg(test)]
             EnvBuilderInner::Dummy(ref mut inner) => {
                 inner.env.clear();
             }
@@ -161,24 +294,55 @@ impl<'job> EnvBuilder<'job> {
     fn add_env_unprefixed<K: AsRef<OsStr>, V: AsRef<OsStr>>(
         &mut self, k: K, v: V,
     ) {
+        if !self.claim(k.as_ref()) {
+            return;
+        }
+
         match self.inner {
             EnvBuilderInner::Real(ref mut inner) => {
                 inner.command.env(k, v);
             }
-            #[cfg(test)]
             EnvBuilderInner::Dummy(ref mut inner) => {
                 inner.env.insert(
-                    k.as_ref().to_str().unwrap().into(),
-                    v.as_ref().to_str().unwrap().into(),
+                    k.as_ref().to_string_lossy().into_owned(),
+                    v.as_ref().to_string_lossy().into_owned(),
                 );
             }
         }
 
     }
 
+    /// Add an environment variable. Values too big for the environment, or
+    /// containing NUL bytes, are written to a data file instead, and the
+    /// variable contains the path of that file.
     pub fn add_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, k: K, v: V) {
-        let name = self.env_name(k);
-        self.add_env_unprefixed(name, v);
+        let sanitized = self.sanitization.apply(v.as_ref());
+        let value = sanitized.as_bytes();
+        if value.len() <= ENV_SPILLOVER_SIZE && !value.contains(&0) {
+            let name = self.env_name(k);
+            self.add_env_unprefixed(name, &sanitized);
+            return;
+        }
+
+        let file = format!(
+            "env_{}", utils::env_var_name(&self.env_name(&k).to_string_lossy())
+                .to_lowercase(),
+        );
+        if let Err(err) = self.data_file_with(k, file, value) {
+            // The error is reported when the environment is finished
+            if self.spillover_error.is_none() {
+                self.spillover_error = Some(err);
+            }
+        }
+    }
+
+    /// Return the first error occurred while writing a spilled over value.
+    pub(crate) fn check_spillover(&mut self) -> Result<()> {
+        if let Some(err) = self.spillover_error.take() {
+            Err(err)
+        } else {
+            Ok(())
+        }
     }
 
     pub fn data_file<'a, P: AsRef<Path>>(
@@ -188,34 +352,70 @@ impl<'job> EnvBuilder<'job> {
             .chars()
             .map(|c| c.to_uppercase().to_string())
             .collect::<String>();
+        self.data_file_as(env, path)
+    }
+
+    /// Create a data file like `data_file`, but export its path in the
+    /// environment variable with the provided name.
+    pub fn data_file_as<'a, K: AsRef<OsStr>, P: AsRef<Path>>(
+        &'a mut self, env: K, path: P,
+    ) -> Result<&'a mut Write> {
         let name = self.env_name(env);
+        let claimed = self.claim(&name);
 
         match self.inner {
             EnvBuilderInner::Real(ref mut inner) => {
                 let dest = inner.data_dir.join(&path);
-                inner.command.env(name, &dest);
+                if claimed {
+                    inner.command.env(name, &dest);
+                }
+
+                let file = fs::File::create(&dest)?;
+                file.set_permissions(
+                    fs::Permissions::from_mode(inner.file_mode),
+                )?;
 
-                inner.last_file = Some(fs::File::create(&dest)?);
+                inner.last_file = Some(file);
                 Ok(inner.last_file.as_mut().unwrap() as &mut Write)
             }
-            #[cfg(test)]
             EnvBuilderInner::Dummy(ref mut inner) => {
                 let dest = path.as_ref().to_str().unwrap().to_string();
-                inner.env.insert(name.to_str().unwrap().into(), dest.clone());
+                if claimed {
+                    inner.env.insert(
+                        name.to_str().unwrap().into(), dest.clone(),
+                    );
+                }
 
                 inner.files.insert(dest.clone(), Vec::new());
                 Ok(inner.files.get_mut(&dest).unwrap() as &mut Write)
             }
         }
     }
+
+    /// Create a data file with the provided content, which can be binary,
+    /// and export its path in the environment variable with the provided
+    /// name.
+    pub fn data_file_with<K: AsRef<OsStr>, P: AsRef<Path>>(
+        &mut self, env: K, path: P, content: &[u8],
+    ) -> Result<()> {
+        self.data_file_as(env, path)?.write_all(content)?;
+        Ok(())
+    }
 }
 
 
 #[derive(Debug, Clone)]
 pub struct Job {
+    id: String,
     script: Arc<Script>,
     provider: Option<Arc<Provider>>,
     request: Request,
+    requested_hook: Option<String>,
+    trigger_env: HashMap<String, String>,
+    waiter: Option<mpsc::SyncSender<JobOutput>>,
+    trace: TraceContext,
+    trace_parent: Option<String>,
+    queued_at: u64,
 }
 
 impl Job {
@@ -225,12 +425,57 @@ impl Job {
         request: Request,
     ) -> Job {
         Job {
+            id: utils::to_hex(&rand::random::<[u8; 16]>()),
             script,
             provider,
             request,
+            requested_hook: None,
+            trigger_env: HashMap::new(),
+            waiter: None,
+            trace: TraceContext::new(),
+            trace_parent: None,
+            queued_at: traces::now_nanos(),
         }
     }
 
+    /// Set the name of the hook the request was sent to, if it's not the
+    /// one of the script executing it, like for the catch-all script.
+    pub fn set_requested_hook(&mut self, name: &str) {
+        self.requested_hook = Some(name.into());
+    }
+
+    /// Set the environment variables provided by who triggered this job
+    /// manually. They're prefixed like the ones set by the providers.
+    pub fn set_trigger_env(&mut self, env: HashMap<String, String>) {
+        self.trigger_env = env;
+    }
+
+    /// Trace this job as part of the span which created it, instead of
+    /// starting a new trace.
+    pub fn set_trace(&mut self, parent: &TraceContext) {
+        self.trace = parent.child();
+        self.trace_parent = Some(parent.span_id.clone());
+    }
+
+    #[cfg(test)]
+    pub fn trace(&self) -> &TraceContext {
+        &self.trace
+    }
+
+    /// Receive the output of this job once it's executed. The receiver is
+    /// disconnected if the job ends without an output, like when it's
+    /// dropped from the queue or its script can't be started.
+    pub fn watch_output(&mut self) -> mpsc::Receiver<JobOutput> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.waiter = Some(sender);
+        receiver
+    }
+
+    /// Return the unique ID of this job, which can be shown to the outside
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     pub fn request_ip(&self) -> IpAddr {
         match self.request {
             Request::Web(ref req) => req.source,
@@ -238,6 +483,16 @@ impl Job {
         }
     }
 
+    #[cfg(test)]
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Check if the job must be approved before being executed.
+    pub fn requires_approval(&self) -> bool {
+        self.script.requires_approval()
+    }
+
     pub fn trigger_status_hooks(&self) -> bool {
         if let Some(ref provider) = self.provider {
             provider.trigger_status_hooks(&self.request)
@@ -247,66 +502,268 @@ impl Job {
     }
 
     fn process(&self, ctx: &Context) -> Result<JobOutput> {
+        // The job is not executed if the command before it fails, so it can
+        // be used to acquire locks
+        if let Some(ref command) = ctx.before_job {
+            if !self.run_job_command(command, None)? {
+                return Err(ErrorKind::BeforeJobFailed(
+                    self.script_name().into(),
+                ).into());
+            }
+        }
+
+        let result = self.process_script(ctx);
+
+        // The command after the job is executed even if the script couldn't
+        // be started, to release what the command before it acquired
+        if let Some(ref command) = ctx.after_job {
+            match self.run_job_command(command, result.as_ref().ok()) {
+                Ok(true) => {}
+                Ok(false) => println!(
+                    "The command after job {} of {} failed",
+                    self.id, self.script_name(),
+                ),
+                Err(err) => err.pretty_print(),
+            }
+        }
+
+        result
+    }
+
+    /// Run a command configured to be executed around every job, returning
+    /// whether it succeeded. The command runs in the context of Fisher
+    /// itself, with the details of the job (and its result, after it ran)
+    /// in the environment.
+    fn run_job_command(
+        &self, shell_command: &str, output: Option<&JobOutput>,
+    ) -> Result<bool> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(shell_command)
+            .stdin(Stdio::null())
+            .env("FISHER_JOB_ID", &self.id)
+            .env("FISHER_JOB_HOOK", self.script_name())
+            .env("FISHER_REQUEST_IP", self.request_ip().to_string());
+        if let Some(ref name) = self.requested_hook {
+            command.env("FISHER_REQUESTED_HOOK", name);
+        }
+        if let Some(ref provider) = self.provider {
+            command.env("FISHER_PROVIDER", provider.name());
+        }
+
+        // Jobs which couldn't be started are reported as failed
+        if let Some(output) = output {
+            command.env("FISHER_JOB_SUCCESS", output.success.to_string());
+            if let Some(code) = output.exit_code {
+                command.env("FISHER_JOB_EXIT_CODE", code.to_string());
+            }
+            if let Some(signal) = output.signal {
+                command.env("FISHER_JOB_SIGNAL", signal.to_string());
+            }
+        } else {
+            command.env("FISHER_JOB_SUCCESS", "false");
+        }
+
+        let mut child = utils::spawn_tracked(&mut command)?;
+        let pid = Pid::from_raw(child.id() as i32);
+        let status = child.wait();
+        utils::untrack(pid);
+        Ok(status?.success())
+    }
+
+    fn process_script(&self, ctx: &Context) -> Result<JobOutput> {
         let mut command = Command::new(&self.script.exec());
 
-        // Use random directories
-        let working_directory = TempDir::new("fisher")?;
-        let data_directory = TempDir::new("fisher")?;
+        // Failing to take the fingerprint shouldn't prevent the execution
+        let fingerprint = match Fingerprint::take(self.script.exec()) {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(err) => {
+                err.pretty_print();
+                None
+            }
+        };
+
+        // Use random directories, in the configured location if any
+        let base = self.script.temp_dir()
+            .map(PathBuf::from)
+            .or_else(|| ctx.temp_dir.clone())
+            .unwrap_or_else(env::temp_dir);
+        utils::check_temp_dir(&base, ctx.require_tmpfs)?;
+        let working_directory = TempDir::new_in(&base, "fisher")?;
+        let data_directory = TempDir::new_in(&base, "fisher")?;
+
+        // Restrict who can access the data files and directories
+        let mask = self.script.umask().unwrap_or(DEFAULT_DATA_UMASK);
+        let (dir_mode, file_mode) = (0o777 & !mask, 0o666 & !mask);
+        for dir in &[&working_directory, &data_directory] {
+            fs::set_permissions(
+                dir.path(), fs::Permissions::from_mode(dir_mode),
+            )?;
+        }
+
+        // Save the request body
+        let request_body = self.save_request_body(
+            data_directory.path(), file_mode,
+        )?;
+
+        // Create the file the script can report its progress to
+        let progress_file = data_directory.path().join("progress");
+        fs::File::create(&progress_file)?
+            .set_permissions(fs::Permissions::from_mode(file_mode))?;
+        // The uploads are accepted before preparing the environment, since
+        // that includes the token the script uploads its artifacts with
+        let uploads = UploadsGuard::new(&ctx.uploads, &self.id);
 
         // Prepare the command's environment
         {
             let mut builder = EnvBuilder::new(
-                &mut command, &data_directory.path()
+                &mut command, &data_directory.path(), file_mode,
             );
-            self.prepare_env(&mut builder, ctx)?;
+            self.prepare_env(
+                &mut builder, ctx, working_directory.path(),
+                request_body.as_deref(), &progress_file,
+            )?;
         }
 
         command.current_dir(working_directory.path().to_str().unwrap());
-        command.env("HOME", working_directory.path().to_str().unwrap());
 
-        // Set the request IP
-        command.env("FISHER_REQUEST_IP", self.request_ip().to_string());
+        // Make sure the process is isolated. The closure runs between fork
+        // and exec, where only async-signal-safe functions can be called:
+        // setpgid and umask are plain system calls, and nothing is allocated
+        let script_umask = self.script.umask();
+        unsafe {
+            command.pre_exec(move || {
+                // If a new process group is not created, the job still works
+                // fine
+                let _ = setpgid(Pid::this(), Pid::from_raw(0));
+
+                if let Some(mask) = script_umask {
+                    umask(Mode::from_bits_truncate(mask));
+                }
 
-        // Save the request body
-        let request_body = self.save_request_body(data_directory.path())?;
-        if let Some(path) = request_body {
-            command.env("FISHER_REQUEST_BODY", path.to_str().unwrap());
+                Ok(())
+            });
         }
 
-        // Apply the custom environment
-        for (key, value) in ctx.environment.iter() {
-            command.env(&key, &value);
-        }
+        // Execute the hook, tracking its progress while it runs
+        let (output, usage) = {
+            let _running = RunningGuard::new(
+                &ctx.running, &self.id, self.script.name(), &progress_file,
+            );
+            self.run(command, &ctx.running)?
+        };
+        let artifacts = ctx.uploads.lock().unwrap().artifacts(&self.id);
+        drop(uploads);
 
-        // Make sure the process is isolated
-        command.before_exec(|| {
-            // If a new process group is not created, the job still works fine
-            let _ = setpgid(Pid::this(), Pid::from_raw(0));
+        // The temp directory is dropped - and removed - here
 
-            Ok(())
-        });
+        record_usage(self.script_name(), &usage);
 
-        // Execute the hook
-        let output = command.output()?;
+        // Return the job output
+        Ok(JobOutput::new(self, output, usage, fingerprint, artifacts))
+    }
 
-        // The temp directory is dropped - and removed - here
+    /// Run the script, making sure none of the processes it started are left
+    /// behind after it exits or it's killed.
+    fn run(
+        &self, mut command: Command, running: &Mutex<RunningJobs>,
+    ) -> Result<(Output, ResourceUsage)> {
+        let start = Instant::now();
+        let mut child = utils::spawn_tracked(command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+        )?;
+        // The script is the leader of its own process group
+        let pgid = Pid::from_raw(child.id() as i32);
+
+        // The output is read in the background to avoid filling the pipes,
+        // and it's shared as it arrives with who's watching the job
+        let live = running.lock().unwrap().output(&self.id);
+        let stdout = read_pipe(child.stdout.take(), live.clone());
+        let stderr = read_pipe(child.stderr.take(), live);
+
+        // Terminate the job if it takes too long, or if someone asks to kill
+        // it through the API
+        let (cancel, cancel_recv) = mpsc::channel::<()>();
+        running.lock().unwrap().set_killer(&self.id, Some(cancel.clone()));
+        let timeout = self.script.timeout();
+        let watchdog = {
+            let id = self.id.clone();
+            let name = self.script_name().to_string();
+
+            thread::spawn(move || {
+                let result = match timeout {
+                    Some(timeout) => cancel_recv.recv_timeout(timeout),
+                    None => cancel_recv.recv().map_err(|_| {
+                        mpsc::RecvTimeoutError::Disconnected
+                    }),
+                };
+                match result {
+                    Ok(()) => println!(
+                        "Job {} of {} killed through the API, terminating it",
+                        id, name,
+                    ),
+                    Err(mpsc::RecvTimeoutError::Timeout) => println!(
+                        "Job {} of {} timed out, terminating it", id, name,
+                    ),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
 
-        // Return the job output
-        Ok(JobOutput::new(self, output))
+                match utils::terminate_group(
+                    pgid, KILL_GRACE_PERIOD, &cancel_recv,
+                ) {
+                    Ok(killed) => record_force_killed(&name, killed),
+                    Err(err) => err.pretty_print(),
+                }
+            })
+        };
+
+        // The script isn't reaped yet, so its process group can't be reused
+        // by another process until the cleanup is done
+        let exited = utils::wait_exited(pgid);
+        let wall_time = start.elapsed();
+        running.lock().unwrap().set_killer(&self.id, None);
+        drop(cancel);
+        let _ = watchdog.join();
+
+        // Kill what's left behind, like the processes started with nohup,
+        // which might also be keeping the output pipes open
+        record_force_killed(self.script_name(), utils::count_alive(pgid));
+        if let Err(err) = utils::signal_group(pgid, Signal::SIGKILL) {
+            err.pretty_print();
+        }
+        if let Err(err) = utils::reap_group(pgid) {
+            err.pretty_print();
+        }
+
+        // The script is reaped last, and even if waiting for it failed
+        let reaped = utils::reap_with_usage(pgid);
+        utils::untrack(pgid);
+
+        exited?;
+        let (status, mut usage) = reaped?;
+        usage.wall_time = wall_time;
+
+        Ok((Output {
+            status,
+            stdout: stdout.join().unwrap_or_else(|_| Ok(Vec::new()))?,
+            stderr: stderr.join().unwrap_or_else(|_| Ok(Vec::new()))?,
+        }, usage))
     }
 
     fn prepare_env(
-        &self, builder: &mut EnvBuilder, ctx: &Context,
+        &self, builder: &mut EnvBuilder, ctx: &Context, home: &Path,
+        request_body: Option<&Path>, progress_file: &Path,
     ) -> Result<()> {
         // First of all clear the environment
         builder.clear_env();
 
-        // Set the USER environment variable with the correct username
-        builder.add_env_unprefixed("USER", &ctx.username);
-
         // Apply the default environment
         // This is done (instead of the automatic inheritage) to whitelist
         // which environment variables we want
+        builder.set_source(EnvSource::System);
         for (key, value) in env::vars() {
             // Set only whitelisted keys
             if !DEFAULT_ENV.contains(&key.as_str()) {
@@ -316,40 +773,222 @@ impl Job {
             builder.add_env_unprefixed(key, value);
         }
 
+        builder.set_source(EnvSource::Fisher);
+
+        // Set the USER environment variable with the correct username
+        builder.add_env_unprefixed("USER", &ctx.username);
+        builder.add_env_unprefixed("HOME", home);
+
+        builder.add_env_unprefixed("FISHER_JOB_ID", &self.id);
+        builder.add_env_unprefixed(
+            "FISHER_REQUEST_IP", self.request_ip().to_string(),
+        );
+        if let Some(path) = request_body {
+            builder.add_env_unprefixed("FISHER_REQUEST_BODY", path);
+        }
+        builder.add_env_unprefixed("FISHER_PROGRESS", progress_file);
+        if let Some(ref name) = self.requested_hook {
+            builder.add_env_unprefixed("FISHER_REQUESTED_HOOK", name);
+        }
+
+        // Tell the script where to upload its artifacts, if it can
+        let artifacts = {
+            let uploads = ctx.uploads.lock().unwrap();
+            uploads.url().and_then(|url| {
+                uploads.token(&self.id).map(|token| (
+                    format!("{}/jobs/{}/artifacts", url, self.id),
+                    token.to_string(),
+                ))
+            })
+        };
+        if let Some((url, token)) = artifacts {
+            builder.add_env_unprefixed("FISHER_ARTIFACTS_URL", url);
+            builder.add_env_unprefixed("FISHER_ARTIFACTS_TOKEN", token);
+        }
+
+        // Tell the script which provider validated the request
+        if let Some(ref provider) = self.provider {
+            builder.add_env_unprefixed("FISHER_PROVIDER", provider.name());
+        }
+
+        // Allow the script to continue the trace of the job
+        if traces::enabled() {
+            builder.add_env_unprefixed("TRACEPARENT", self.trace.traceparent());
+            builder.add_env_unprefixed("FISHER_TRACE_ID", &self.trace.trace_id);
+        }
+
+        // Apply the custom environment
+        builder.set_source(EnvSource::Config);
+        for (key, value) in &ctx.environment {
+            builder.add_env_unprefixed(key, value);
+        }
+
         if let Some(ref provider) = self.provider {
+            builder.set_source(EnvSource::Provider(provider.name().into()));
+            builder.set_sanitization(self.script.env_sanitization());
+            builder.set_base_prefix(
+                self.script.env_prefix().unwrap_or(ENV_PREFIX),
+            );
             builder.set_prefix(Some(provider.name()));
             provider.build_env(&self.request, builder)?;
         }
 
+        if !self.trigger_env.is_empty() {
+            builder.set_source(EnvSource::Trigger);
+            builder.set_sanitization(self.script.env_sanitization());
+            builder.set_base_prefix(
+                self.script.env_prefix().unwrap_or(ENV_PREFIX),
+            );
+            for (key, value) in &self.trigger_env {
+                builder.add_env(key, value);
+            }
+        }
+
         builder.set_prefix(None);
+        builder.check_spillover()?;
 
         Ok(())
     }
 
-    fn save_request_body(&self, base: &Path) -> Result<Option<PathBuf>> {
+    fn save_request_body(
+        &self, base: &Path, mode: u32,
+    ) -> Result<Option<PathBuf>> {
         // Get the request body, even if some request kinds don't have one
-        let body = match self.request {
-            Request::Web(ref req) => &req.body,
+        let req = match self.request {
+            Request::Web(ref req) => req,
             Request::Status(..) => return Ok(None),
         };
 
         let mut path = base.to_path_buf();
         path.push("request_body");
 
-        // Write the request body on disk
+        // Write the request body on disk, copying spooled bodies without
+        // loading them in memory. Only text bodies end with a newline, to
+        // keep binary ones intact
         let mut file = fs::File::create(&path)?;
-        write!(file, "{}\n", body)?;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        io::copy(&mut req.body_reader()?, &mut file)?;
+        if req.spooled.is_none() && str::from_utf8(&req.body).is_ok() {
+            writeln!(file)?;
+        }
 
         Ok(Some(path))
     }
 }
 
+/// Read all the content of a pipe in a background thread, copying it to the
+/// live output of the job as it's read.
+fn read_pipe<R: Read + Send + 'static>(
+    pipe: Option<R>, live: Option<Arc<Mutex<LiveOutput>>>,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut content = Vec::new();
+        let mut pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return Ok(content),
+        };
+
+        let mut buffer = [0; 8192];
+        loop {
+            let read = match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            content.extend_from_slice(&buffer[..read]);
+            if let Some(ref live) = live {
+                live.lock().unwrap().append(&buffer[..read]);
+            }
+        }
+        Ok(content)
+    })
+}
+
+/// Record the resources used by a script's job.
+fn record_usage(script: &str, usage: &ResourceUsage) {
+    let millis = |d: Duration| d.as_secs() * 1000 + d.subsec_millis() as u64;
+    let labels = &[("hook", script)];
+
+    metrics::increment("fisher_jobs_total", labels);
+    metrics::add(
+        "fisher_job_cpu_milliseconds_total", labels, millis(usage.cpu_time()),
+    );
+    metrics::add(
+        "fisher_job_wall_milliseconds_total", labels,
+        millis(usage.wall_time),
+    );
+    metrics::record_max(
+        "fisher_job_max_rss_kilobytes", labels, usage.max_rss,
+    );
+}
+
+/// Record how many processes of a script's job had to be killed.
+fn record_force_killed(script: &str, count: usize) {
+    if count > 0 {
+        metrics::add(
+            "fisher_processes_force_killed_total", &[("hook", script)],
+            count as u64,
+        );
+    }
+}
+
+
 impl JobTrait<Script> for Job {
     type Context = Context;
     type Output = JobOutput;
 
     fn execute(&self, ctx: &Context) -> Result<JobOutput> {
-        self.process(ctx)
+        // The job is traced from when it was created, including the time it
+        // waited in the queue
+        let mut job_span = Span::start(
+            "job", self.trace.clone(), self.trace_parent.as_deref(),
+        );
+        job_span.started_at(self.queued_at);
+        job_span.attribute("fisher.job_id", &self.id);
+        job_span.attribute("fisher.hook", self.script_name());
+
+        let mut wait_span = Span::start(
+            "queue wait", self.trace.child(), Some(&self.trace.span_id),
+        );
+        wait_span.started_at(self.queued_at);
+        wait_span.end();
+
+        let mut script_span = Span::start(
+            "script", self.trace.child(), Some(&self.trace.span_id),
+        );
+        let result = self.process(ctx);
+        match result {
+            Ok(ref output) => {
+                if let Some(code) = output.exit_code {
+                    script_span.attribute("fisher.exit_code", code);
+                }
+                if !output.success {
+                    script_span.fail();
+                    job_span.fail();
+                }
+            }
+            Err(..) => {
+                script_span.fail();
+                job_span.fail();
+            }
+        }
+        script_span.end();
+        job_span.end();
+
+        let output = result?;
+        if let Some(ref waiter) = self.waiter {
+            // Nobody might be waiting anymore
+            let _ = waiter.try_send(output.clone());
+        }
+        Ok(output)
+    }
+
+    fn id(&self) -> &str {
+        &self.id
     }
 
     fn script_id(&self) -> UniqueId {
@@ -359,6 +998,18 @@ impl JobTrait<Script> for Job {
     fn script_name(&self) -> &str {
         self.script.name()
     }
+
+    fn shed_policy(&self) -> ShedPolicy {
+        self.script.shed_policy()
+    }
+
+    fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.script.circuit_breaker()
+    }
+
+    fn daily_budget(&self) -> Option<DailyBudget> {
+        self.script.daily_budget()
+    }
 }
 
 
@@ -374,11 +1025,29 @@ pub struct JobOutput {
     pub script_name: String,
     pub request_ip: IpAddr,
 
+    pub fingerprint: Option<Fingerprint>,
+    /// What changed in the fingerprint since the previous execution of the
+    /// script, if it was executed before.
+    pub fingerprint_changes: Option<Vec<&'static str>>,
+
+    /// The resources used by the script, and by the processes it waited for.
+    pub usage: ResourceUsage,
+
+    /// The names of the artifacts uploaded by the script.
+    pub artifacts: Vec<String>,
+
     pub trigger_status_hooks: bool,
 }
 
 impl JobOutput {
-    fn new<'a>(job: &'a Job, output: Output) -> Self {
+    fn new<'a>(
+        job: &'a Job, output: Output, usage: ResourceUsage,
+        fingerprint: Option<Fingerprint>, artifacts: Vec<String>,
+    ) -> Self {
+        let fingerprint_changes = fingerprint
+            .as_ref()
+            .and_then(|fingerprint| fingerprint.record(job.script_name()));
+
         JobOutput {
             stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
             stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
@@ -390,30 +1059,55 @@ impl JobOutput {
             script_name: job.script_name().into(),
             request_ip: job.request_ip(),
 
+            fingerprint,
+            fingerprint_changes,
+
+            usage,
+            artifacts,
+
             trigger_status_hooks: job.trigger_status_hooks(),
         }
     }
 }
 
+impl JobOutputTrait for JobOutput {
+    fn success(&self) -> bool {
+        self.success
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use std::env;
     use std::ffi::OsString;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Read;
     use std::path::{Path, PathBuf};
     use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
+    use nix::sys::signal::Signal;
     use users;
 
+    use common::metrics;
     use common::prelude::*;
+    use common::structs::EnvSanitization;
     use requests::Request;
     use scripts::test_utils::*;
+    use traces::TraceContext;
     use utils;
+    use web::SpooledBody;
 
-    use super::{Job, Context, DEFAULT_ENV};
+    use super::{
+        EnvBuilder, EnvSource, Job, Context, DEFAULT_ENV, ENV_SPILLOVER_SIZE,
+    };
 
 
     fn parse_env(content: &str) -> HashMap<&str, &str> {
@@ -544,6 +1238,104 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_env_spillover() {
+        let mut b = EnvBuilder::dummy();
+        b.set_prefix(Some("Test"));
+
+        let big = "a\n".repeat(ENV_SPILLOVER_SIZE);
+        b.add_env("SMALL", "multi\nline");
+        b.add_env("BIG", &big);
+        b.add_env("NUL", "a\0b");
+        b.check_spillover().unwrap();
+
+        let data = b.dummy_data();
+        assert_eq!(data.env["FISHER_TEST_SMALL"], "multi\nline");
+
+        // Spilled values are replaced by the path of their data file
+        assert_eq!(data.env["FISHER_TEST_BIG"], "env_fisher_test_big");
+        assert_eq!(data.files["env_fisher_test_big"], big.as_bytes());
+        assert_eq!(data.env["FISHER_TEST_NUL"], "env_fisher_test_nul");
+        assert_eq!(data.files["env_fisher_test_nul"], b"a\0b");
+    }
+
+
+    #[test]
+    fn test_env_collisions() {
+        let mut b = EnvBuilder::dummy();
+
+        b.set_source(EnvSource::System);
+        b.add_env("PATH", "/usr/bin");
+        b.set_source(EnvSource::Fisher);
+        b.add_env("FISHER_JOB_ID", "abcd");
+        b.set_source(EnvSource::Config);
+        b.add_env("PATH", "/opt/bin");
+
+        // Variables set by other sources can't be overwritten
+        b.set_source(EnvSource::Provider("Test".into()));
+        b.add_env("FISHER_JOB_ID", "efgh");
+        b.add_env("PATH", "/tmp");
+        b.data_file_as("FISHER_JOB_ID", "file").unwrap();
+        b.add_env("NAME", "first");
+        b.add_env("NAME", "second");
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "PATH".into() => "/opt/bin".into(),
+            "FISHER_JOB_ID".into() => "abcd".into(),
+            "NAME".into() => "first".into(),
+        });
+    }
+
+
+    #[test]
+    fn test_env_sanitization() {
+        let value = "it's\x1b[0m\nfine";
+
+        let mut b = EnvBuilder::dummy();
+        b.add_env("NONE", value);
+        b.set_sanitization(EnvSanitization::StripControl);
+        b.add_env("STRIP", value);
+        b.set_sanitization(EnvSanitization::ShellQuote);
+        b.add_env("QUOTE", value);
+
+        // Fisher's own variables are never sanitized
+        b.add_env_unprefixed("UNPREFIXED", value);
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "NONE".into() => value.into(),
+            "STRIP".into() => "it's[0m\nfine".into(),
+            "QUOTE".into() => "'it'\\''s[0m\nfine'".into(),
+            "UNPREFIXED".into() => value.into(),
+        });
+    }
+
+
+    #[test]
+    fn test_env_base_prefix() {
+        let mut b = EnvBuilder::dummy();
+
+        b.set_prefix(Some("GitHub"));
+        b.add_env("EVENT", "push");
+
+        b.set_base_prefix("LEGACY");
+        b.set_prefix(Some("GitHub"));
+        b.add_env("EVENT", "push");
+
+        b.set_base_prefix("");
+        b.set_prefix(Some("GitHub"));
+        b.add_env("EVENT", "push");
+        b.set_prefix(None);
+        b.add_env("BARE", "yes");
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "FISHER_GITHUB_EVENT".into() => "push".into(),
+            "LEGACY_GITHUB_EVENT".into() => "push".into(),
+            "GITHUB_EVENT".into() => "push".into(),
+            "BARE".into() => "yes".into(),
+        });
+    }
+
+
     #[test]
     fn test_job_environment() {
         test_wrapper(|mut env| {
@@ -565,7 +1357,8 @@ mod tests {
             // Calculate the list of expected environment variables
             let extra_env = vec![
                 // Variables set by Fisher
-                "FISHER_TESTING_ENV", "FISHER_REQUEST_IP",
+                "FISHER_TESTING_ENV", "FISHER_REQUEST_IP", "FISHER_PROVIDER",
+                "FISHER_JOB_ID", "FISHER_PROGRESS",
                 "FISHER_REQUEST_BODY", "FISHER_TESTING_PREPARED", "HOME",
                 "USER",
                 // Variables set by bash
@@ -595,6 +1388,7 @@ mod tests {
             // Ensure environment variables are correct
             assert_eq!(&env_vars["FISHER_TESTING_ENV"], &out.to_str().unwrap());
             assert_eq!(&env_vars["FISHER_REQUEST_IP"], &"127.0.0.1");
+            assert_eq!(&env_vars["FISHER_PROVIDER"], &"Testing");
             assert_eq!(&env_vars["HOME"], &working_directory.trim());
             assert_eq!(
                 &env_vars["USER"],
@@ -611,6 +1405,418 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_job_environment_with_artifacts() {
+        test_wrapper(|mut env| {
+            let ctx = Context::default();
+            ctx.uploads.lock().unwrap().set_url(
+                Some("http://127.0.0.1:8000".into()),
+            );
+
+            // Get the execution environment
+            let out = collect_env(&mut env, &ctx)?;
+            let env_content = content(&out, "env")?;
+            let env_vars = parse_env(&env_content);
+
+            // Ensure the script knows where to upload its artifacts
+            assert_eq!(env_vars["FISHER_ARTIFACTS_URL"], format!(
+                "http://127.0.0.1:8000/jobs/{}/artifacts",
+                env_vars["FISHER_JOB_ID"],
+            ));
+            assert_eq!(env_vars["FISHER_ARTIFACTS_TOKEN"].len(), 32);
+
+            // The token is forgotten when the job ends
+            let uploads = ctx.uploads.lock().unwrap();
+            assert!(uploads.token(env_vars["FISHER_JOB_ID"]).is_none());
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_with_spooled_body() {
+        test_wrapper(|env| {
+            env.create_script("spooled.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"cat "${FISHER_REQUEST_BODY}" > "${FISHER_TESTING_ENV}/body""#,
+            ])?;
+            let out = env.tempdir()?;
+
+            let mut req = dummy_web_request();
+            req.params.insert("env".into(), out.to_str().unwrap().into());
+            req.spooled = Some(Arc::new(
+                SpooledBody::new(b"a big ", &b"body!"[..])?,
+            ));
+
+            let job = create_job(env, "spooled.sh", req.into())?;
+            assert!(job.process(&Context::default())?.success);
+
+            // Spooled bodies are copied as-is
+            assert_eq!(&content(&out, "body")?, "a big body!");
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_requested_hook() {
+        test_wrapper(|env| {
+            env.create_script("catchall.sh", &[
+                r#"#!/bin/bash"#,
+                r#"echo "hook: ${FISHER_REQUESTED_HOOK-none}""#,
+            ])?;
+            let req: Request = dummy_web_request().into();
+
+            let job = create_job(env, "catchall.sh", req.clone())?;
+            let result = job.process(&Context::default())?;
+            assert_eq!(result.stdout.trim(), "hook: none");
+
+            let mut job = create_job(env, "catchall.sh", req)?;
+            job.set_requested_hook("unknown.sh");
+            let result = job.process(&Context::default())?;
+            assert_eq!(result.stdout.trim(), "hook: unknown.sh");
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_with_binary_body() {
+        test_wrapper(|env| {
+            env.create_script("binary.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"cat "${FISHER_REQUEST_BODY}" > "${FISHER_TESTING_ENV}/body""#,
+            ])?;
+            let out = env.tempdir()?;
+
+            let mut req = dummy_web_request();
+            req.params.insert("env".into(), out.to_str().unwrap().into());
+            req.body = vec![0x1f, 0x8b, 0xff, 0x00, b'\n'];
+
+            let job = create_job(env, "binary.sh", req.into())?;
+            assert!(job.process(&Context::default())?.success);
+
+            // Binary bodies are written as-is, without a trailing newline
+            let mut body = Vec::new();
+            File::open(out.join("body"))?.read_to_end(&mut body)?;
+            assert_eq!(body, vec![0x1f, 0x8b, 0xff, 0x00, b'\n']);
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_permissions() {
+        test_wrapper(|env| {
+            for &(name, preferences) in &[
+                ("default.sh", r#"## Fisher: {}"#),
+                ("custom.sh", r#"## Fisher: {"umask": "027"}"#),
+            ] {
+                env.create_script(name, &[
+                    r#"#!/bin/bash"#,
+                    preferences,
+                    r#"## Fisher-Testing: {}"#,
+                    r#"b="${FISHER_TESTING_ENV}""#,
+                    r#"body="${FISHER_REQUEST_BODY}""#,
+                    r#"stat -c %a "${body}" > "${b}/body""#,
+                    r#"stat -c %a "$(dirname "${body}")" > "${b}/data""#,
+                    r#"stat -c %a . > "${b}/home""#,
+                    r#"umask > "${b}/umask""#,
+                ])?;
+            }
+
+            let mut results = Vec::new();
+            for name in &["default.sh", "custom.sh"] {
+                let out = env.tempdir()?;
+
+                let mut req = dummy_web_request();
+                req.params.insert("env".into(), out.to_str().unwrap().into());
+
+                let job = create_job(env, name, req.into())?;
+                assert!(job.process(&Context::default())?.success);
+
+                let mut result = Vec::new();
+                for file in &["body", "data", "home", "umask"] {
+                    result.push(content(&out, file)?.trim().to_string());
+                }
+                results.push(result);
+            }
+
+            // Data files are private by default, without changing the umask
+            // of the script itself
+            assert_eq!(&results[0][..3], &["600", "700", "700"]);
+
+            // The configured umask is applied everywhere
+            assert_eq!(results[1], vec!["640", "750", "750", "0027"]);
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_temp_dir() {
+        test_wrapper(|env| {
+            let global = env.tempdir()?;
+            let custom = env.tempdir()?;
+
+            for &(name, ref preferences) in &[
+                ("default.sh", "## Fisher: {}".to_string()),
+                ("custom.sh", format!(
+                    r#"## Fisher: {{"temp_dir": "{}"}}"#,
+                    custom.to_str().unwrap(),
+                )),
+            ] {
+                env.create_script(name, &[
+                    r#"#!/bin/bash"#,
+                    preferences,
+                    r#"## Fisher-Testing: {}"#,
+                    r#"b="${FISHER_TESTING_ENV}""#,
+                    r#"pwd > "${b}/home""#,
+                    r#"dirname "${FISHER_REQUEST_BODY}" > "${b}/data""#,
+                ])?;
+            }
+
+            let mut ctx = Context::default();
+            ctx.temp_dir = Some(global.clone());
+
+            for &(name, base) in &[
+                ("default.sh", &global), ("custom.sh", &custom),
+            ] {
+                let out = env.tempdir()?;
+
+                let mut req = dummy_web_request();
+                req.params.insert("env".into(), out.to_str().unwrap().into());
+
+                let job = create_job(env, name, req.into())?;
+                assert!(job.process(&ctx)?.success);
+
+                for file in &["home", "data"] {
+                    let dir = PathBuf::from(content(&out, file)?.trim());
+                    assert_eq!(dir.parent(), Some(base.as_path()));
+                }
+            }
+
+            // Jobs fail if the directory doesn't exist
+            ctx.temp_dir = Some(global.join("missing"));
+            let req = dummy_web_request().into();
+            let job = create_job(env, "default.sh", req)?;
+            assert!(job.process(&ctx).is_err());
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_commands() {
+        test_wrapper(|env| {
+            let out = env.tempdir()?;
+            env.create_script("fail.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"touch "${FISHER_TESTING_ENV}/executed""#,
+                r#"exit 3"#,
+            ])?;
+
+            let mut req = dummy_web_request();
+            req.params.insert("env".into(), out.to_str().unwrap().into());
+            let job = create_job(env, "fail.sh", req.into())?;
+
+            let mut ctx = Context::default();
+            ctx.before_job = Some(format!(
+                r#"echo "$FISHER_JOB_ID $FISHER_JOB_HOOK" > "{}/before""#,
+                out.to_str().unwrap(),
+            ));
+            ctx.after_job = Some(format!(
+                r#"echo "$FISHER_JOB_SUCCESS $FISHER_JOB_EXIT_CODE" > "{}""#,
+                out.join("after").to_str().unwrap(),
+            ));
+
+            // Both commands receive the details of the job
+            assert!(!job.process(&ctx)?.success);
+            assert_eq!(
+                content(&out, "before")?,
+                format!("{} fail.sh\n", job.id()),
+            );
+            assert_eq!(content(&out, "after")?, "false 3\n");
+            assert!(out.join("executed").exists());
+
+            // The job is not executed if the command before it fails
+            fs::remove_file(out.join("executed"))?;
+            fs::remove_file(out.join("after"))?;
+            ctx.before_job = Some("exit 1".into());
+            assert!(job.process(&ctx).is_err());
+            assert!(!out.join("executed").exists());
+            assert!(!out.join("after").exists());
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_process_group_cleanup() {
+        test_wrapper(|env| {
+            let out = env.tempdir()?;
+            env.create_script("detach.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"nohup sleep 30 &"#,
+                r#"echo $! > "${FISHER_TESTING_ENV}/pid""#,
+            ])?;
+
+            let mut req = dummy_web_request();
+            req.params.insert("env".into(), out.to_str().unwrap().into());
+            let job = create_job(env, "detach.sh", req.into())?;
+
+            let labels = &[("hook", "detach.sh")];
+            let before =
+                metrics::get("fisher_processes_force_killed_total", labels);
+
+            // The job doesn't wait for the detached process to exit
+            let start = Instant::now();
+            assert!(job.process(&Context::default())?.success);
+            assert!(start.elapsed() < Duration::from_secs(10));
+
+            // The detached process is counted as force-killed
+            assert_eq!(
+                metrics::get("fisher_processes_force_killed_total", labels),
+                before + 1,
+            );
+
+            // The detached process was killed, even if it might not be
+            // reaped yet
+            let pid = content(&out, "pid")?;
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .unwrap_or_default();
+            assert!(stat.is_empty() || stat.contains(") Z "));
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_timeout() {
+        test_wrapper(|env| {
+            env.create_script("hang.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher: {"timeout": 1}"#,
+                r#"sleep 30"#,
+            ])?;
+
+            let req = dummy_web_request().into();
+            let job = create_job(env, "hang.sh", req)?;
+
+            let start = Instant::now();
+            let result = job.process(&Context::default())?;
+            assert!(start.elapsed() < Duration::from_secs(10));
+            assert!(!result.success);
+            assert_eq!(result.signal, Some(Signal::SIGTERM as i32));
+
+            // The resources used by the job are recorded
+            assert!(result.usage.wall_time >= Duration::from_secs(1));
+            assert!(result.usage.max_rss > 0);
+            assert!(metrics::get(
+                "fisher_job_wall_milliseconds_total", &[("hook", "hang.sh")],
+            ) >= 1000);
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_kill() {
+        test_wrapper(|env| {
+            env.create_script("hang.sh", &[
+                r#"#!/bin/bash"#,
+                r#"sleep 30"#,
+            ])?;
+
+            let ctx = Context::default();
+            let running = ctx.running.clone();
+
+            let req = dummy_web_request().into();
+            let job = create_job(env, "hang.sh", req)?;
+            let job_id = job.id().to_string();
+            assert!(!running.lock().unwrap().kill(&job_id));
+
+            let start = Instant::now();
+            let handle = thread::spawn(move || job.process(&ctx));
+
+            // Wait for the script to start before killing it
+            while !running.lock().unwrap().kill(&job_id) {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            let result = handle.join().unwrap()?;
+            assert!(start.elapsed() < Duration::from_secs(10));
+            assert!(!result.success);
+            assert_eq!(result.signal, Some(Signal::SIGTERM as i32));
+            assert!(!running.lock().unwrap().kill(&job_id));
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_progress() {
+        test_wrapper(|env| {
+            let out = env.tempdir()?;
+            env.create_script("progress.sh", &[
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"b="${FISHER_TESTING_ENV}""#,
+                r#"echo "25 Fetching" >> "${FISHER_PROGRESS}""#,
+                r#"echo "60% Building" >> "${FISHER_PROGRESS}""#,
+                r#"while ! [ -f "${b}/done" ]; do sleep 0.01; done"#,
+            ])?;
+
+            let ctx = Context::default();
+            let running = ctx.running.clone();
+
+            let mut req = dummy_web_request();
+            req.params.insert("env".into(), out.to_str().unwrap().into());
+            let job = create_job(env, "progress.sh", req.into())?;
+            let job_id = job.id().to_string();
+            let handle = thread::spawn(move || job.process(&ctx));
+
+            // Wait until the script reports its progress
+            let mut attempts = 0;
+            let progress = loop {
+                let progress = running.lock().unwrap().get(&job_id);
+                if let Some(progress) = progress {
+                    if progress.percent == Some(60) {
+                        break progress;
+                    }
+                }
+
+                attempts += 1;
+                assert!(attempts < 500, "the progress was never reported");
+                thread::sleep(Duration::from_millis(10));
+            };
+            assert_eq!(progress.script, "progress.sh");
+            assert_eq!(progress.message, Some("Building".into()));
+
+            // The job isn't tracked anymore after it ends
+            File::create(out.join("done"))?;
+            assert!(handle.join().unwrap()?.success);
+            assert!(running.lock().unwrap().list().is_empty());
+
+            Ok(())
+        });
+    }
+
+
     #[test]
     fn test_job_environment_with_extra_env() {
         test_wrapper(|mut env| {
@@ -637,6 +1843,86 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_job_trace() {
+        test_wrapper(|env| {
+            env.create_script("example.sh", &[r#"#!/bin/bash"#])?;
+
+            let req: Request = dummy_web_request().into();
+            let mut job = create_job(env, "example.sh", req.clone())?;
+            let other = create_job(env, "example.sh", req)?;
+
+            // Jobs start their own trace by default
+            assert_eq!(job.trace_parent, None);
+            assert_ne!(job.trace.trace_id, other.trace.trace_id);
+
+            let parent = TraceContext::new();
+            job.set_trace(&parent);
+            assert_eq!(job.trace.trace_id, parent.trace_id);
+            assert_ne!(job.trace.span_id, parent.span_id);
+            assert_eq!(job.trace_parent, Some(parent.span_id.clone()));
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_environment_with_trigger_env() {
+        test_wrapper(|env| {
+            let out = env.tempdir()?;
+            env.create_script("manual.sh", &[
+                r#"#!/bin/bash"#,
+                r#"env > "${FISHER_OUT}/env""#,
+            ])?;
+
+            let req = dummy_web_request().into();
+            let mut job = create_job(env, "manual.sh", req)?;
+            job.set_trigger_env(hashmap! {
+                "OUT".into() => out.to_str().unwrap().into(),
+                "GITHUB_EVENT".into() => "push".into(),
+                "JOB_ID".into() => "overridden".into(),
+            });
+            assert!(job.process(&Context::default())?.success);
+
+            // The variables can't override the ones set by Fisher
+            let env_content = content(&out, "env")?;
+            let env_vars = parse_env(&env_content);
+            assert_eq!(env_vars["FISHER_GITHUB_EVENT"], "push");
+            assert_eq!(env_vars["FISHER_JOB_ID"], job.id());
+            assert!(!env_vars.contains_key("FISHER_PROVIDER"));
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_environment_with_colliding_extra_env() {
+        test_wrapper(|mut env| {
+            let ctx = Context {
+                environment: {
+                    let mut extra = HashMap::new();
+                    extra.insert("LANG".into(), "C".into());
+                    extra.insert("FISHER_JOB_ID".into(), "custom".into());
+                    extra
+                },
+                .. Context::default()
+            };
+
+            let out = collect_env(&mut env, &ctx)?;
+            let env_content = content(&out, "env")?;
+            let env_vars = parse_env(&env_content);
+
+            // Only the variables inherited from the system can be overridden
+            assert_eq!(&env_vars["LANG"], &"C");
+            assert!(env_vars["FISHER_JOB_ID"] != "custom");
+
+            Ok(())
+        });
+    }
+
+
     #[test]
     fn test_job_environment_with_altered_user() {
         test_wrapper(|mut env| {
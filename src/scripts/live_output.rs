@@ -0,0 +1,114 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Output of the running jobs, captured while their scripts run.
+//!
+//! The standard output and the standard error of the scripts are copied
+//! here as soon as they're read, in the order they arrive, so operators can
+//! watch a job as it happens. Only the end of the output is kept, and it's
+//! forgotten when the job ends: the full output is still sent to the status
+//! hooks as before.
+
+use std::cmp;
+use std::collections::VecDeque;
+
+
+/// How many bytes of the output of every job are kept at most.
+const MAX_LIVE_OUTPUT: usize = 1024 * 1024;
+
+
+#[derive(Debug)]
+pub struct LiveOutput {
+    buffer: VecDeque<u8>,
+    /// How many bytes were dropped from the start of the buffer.
+    dropped: u64,
+    capacity: usize,
+    finished: bool,
+}
+
+impl LiveOutput {
+    pub fn new() -> Self {
+        LiveOutput::with_capacity(MAX_LIVE_OUTPUT)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        LiveOutput {
+            buffer: VecDeque::new(),
+            dropped: 0,
+            capacity,
+            finished: false,
+        }
+    }
+
+    /// Append some output of the script, forgetting the oldest output if
+    /// there is too much of it.
+    pub fn append(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+
+        let excess = self.buffer.len().saturating_sub(self.capacity);
+        if excess > 0 {
+            self.buffer.drain(..excess);
+            self.dropped += excess as u64;
+        }
+    }
+
+    /// Get the output from the offset onwards, and the offset of the output
+    /// which will be appended next. If the output at the offset was already
+    /// forgotten, the oldest output still kept is returned.
+    pub fn read_from(&self, offset: u64) -> (Vec<u8>, u64) {
+        let start = cmp::min(
+            offset.saturating_sub(self.dropped) as usize, self.buffer.len(),
+        );
+        let content = self.buffer.iter().skip(start).cloned().collect();
+
+        (content, self.dropped + self.buffer.len() as u64)
+    }
+
+    /// Mark the job as ended: no more output will be appended.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::LiveOutput;
+
+
+    #[test]
+    fn test_live_output() {
+        let mut output = LiveOutput::with_capacity(8);
+        assert_eq!(output.read_from(0), (Vec::new(), 0));
+
+        output.append(b"hello");
+        assert_eq!(output.read_from(0), (b"hello".to_vec(), 5));
+        assert_eq!(output.read_from(3), (b"lo".to_vec(), 5));
+        assert_eq!(output.read_from(5), (Vec::new(), 5));
+
+        // The oldest output is forgotten
+        output.append(b" world");
+        assert_eq!(output.read_from(0), (b"lo world".to_vec(), 11));
+        assert_eq!(output.read_from(9), (b"ld".to_vec(), 11));
+
+        assert!(!output.is_finished());
+        output.finish();
+        assert!(output.is_finished());
+    }
+}
@@ -16,11 +16,21 @@
 #[cfg(test)]
 mod test_utils;
 mod collector;
+mod fingerprint;
 mod jobs;
+mod live_output;
+mod progress;
 mod repository;
 mod script;
+mod uploads;
+mod watcher;
 
+pub use self::fingerprint::Fingerprint;
 pub use self::repository::{Blueprint, Repository};
 pub use self::repository::{ScriptsIter, StatusJobsIter};
 pub use self::script::{Script, ScriptProvider};
 pub use self::jobs::{Job, JobOutput, Context as JobContext, EnvBuilder};
+pub use self::live_output::LiveOutput;
+pub use self::progress::{JobProgress, RunningJobs};
+pub use self::uploads::ArtifactUploads;
+pub use self::watcher::Watcher;
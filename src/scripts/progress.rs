@@ -0,0 +1,288 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Progress reported by the running jobs.
+//!
+//! Every job gets a progress file, whose path is in `$FISHER_PROGRESS`.
+//! Scripts append lines to it, each one with an optional percentage followed
+//! by a message, and the last line is the current progress of the job. The
+//! file is read only when someone asks for the progress, so scripts can
+//! write to it as often as they want.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+use scripts::LiveOutput;
+use utils;
+
+
+/// Only the end of the progress file is read, to avoid loading huge files
+/// in memory if a script writes a lot of progress updates.
+const MAX_TAIL_SIZE: u64 = 4096;
+
+
+/// The progress of a running job.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JobProgress {
+    pub job_id: String,
+    pub script: String,
+    #[serde(serialize_with = "utils::serialize_timestamp")]
+    pub started_at: u64,
+    pub percent: Option<u8>,
+    pub message: Option<String>,
+}
+
+
+#[derive(Debug)]
+struct RunningJob {
+    script: String,
+    started_at: u64,
+    progress_file: PathBuf,
+    killer: Option<mpsc::Sender<()>>,
+    output: Arc<Mutex<LiveOutput>>,
+}
+
+
+/// Registry of the jobs currently running, shared between the jobs and the
+/// web API.
+#[derive(Debug, Default)]
+pub struct RunningJobs {
+    jobs: HashMap<String, RunningJob>,
+}
+
+impl RunningJobs {
+    pub fn new() -> Self {
+        RunningJobs {
+            jobs: HashMap::new(),
+        }
+    }
+
+    /// Mark a job as running, with its progress stored in the provided file.
+    pub fn start(&mut self, job_id: &str, script: &str, progress_file: &Path) {
+        self.jobs.insert(job_id.into(), RunningJob {
+            script: script.into(),
+            started_at: utils::unix_now(),
+            progress_file: progress_file.to_path_buf(),
+            killer: None,
+            output: Arc::new(Mutex::new(LiveOutput::new())),
+        });
+    }
+
+    /// Set the channel used to ask the script of a running job to be
+    /// killed, or remove it once the script exited.
+    pub(crate) fn set_killer(
+        &mut self, job_id: &str, killer: Option<mpsc::Sender<()>>,
+    ) {
+        if let Some(job) = self.jobs.get_mut(job_id) {
+            job.killer = killer;
+        }
+    }
+
+    /// Ask the script of a running job to be killed. Returns false if the
+    /// job isn't running, or if its script didn't start yet.
+    pub fn kill(&self, job_id: &str) -> bool {
+        self.jobs
+            .get(job_id)
+            .and_then(|job| job.killer.as_ref())
+            .map(|killer| killer.send(()).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// Mark a job as finished.
+    pub fn finish(&mut self, job_id: &str) {
+        if let Some(job) = self.jobs.remove(job_id) {
+            job.output.lock().unwrap().finish();
+        }
+    }
+
+    /// Get the output captured so far from the script of a running job.
+    pub fn output(&self, job_id: &str) -> Option<Arc<Mutex<LiveOutput>>> {
+        self.jobs.get(job_id).map(|job| job.output.clone())
+    }
+
+    /// Get the progress of a running job.
+    pub fn get(&self, job_id: &str) -> Option<JobProgress> {
+        self.jobs.get(job_id).map(|job| progress_of(job_id, job))
+    }
+
+    /// Get the progress of all the running jobs, oldest first.
+    pub fn list(&self) -> Vec<JobProgress> {
+        let mut result = self.jobs
+            .iter()
+            .map(|(id, job)| progress_of(id, job))
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| {
+            (a.started_at, &a.job_id).cmp(&(b.started_at, &b.job_id))
+        });
+        result
+    }
+}
+
+
+/// Marks a job as running until it's dropped, even if the job fails.
+pub(crate) struct RunningGuard<'a> {
+    jobs: &'a Arc<Mutex<RunningJobs>>,
+    job_id: &'a str,
+}
+
+impl<'a> RunningGuard<'a> {
+    pub(crate) fn new(
+        jobs: &'a Arc<Mutex<RunningJobs>>, job_id: &'a str, script: &str,
+        progress_file: &Path,
+    ) -> Self {
+        jobs.lock().unwrap().start(job_id, script, progress_file);
+        RunningGuard { jobs, job_id }
+    }
+}
+
+impl<'a> Drop for RunningGuard<'a> {
+    fn drop(&mut self) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.finish(self.job_id);
+        }
+    }
+}
+
+
+fn progress_of(job_id: &str, job: &RunningJob) -> JobProgress {
+    // The script might not have reported any progress yet
+    let (percent, message) = read_tail(&job.progress_file)
+        .and_then(|tail| {
+            tail.lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .map(parse_line)
+        })
+        .unwrap_or((None, None));
+
+    JobProgress {
+        job_id: job_id.into(),
+        script: job.script.clone(),
+        started_at: job.started_at,
+        percent,
+        message,
+    }
+}
+
+
+fn read_tail(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    if len > MAX_TAIL_SIZE {
+        file.seek(SeekFrom::Start(len - MAX_TAIL_SIZE)).ok()?;
+    }
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).ok()?;
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+
+/// Parse a progress line, in the `[PERCENT[%]] [MESSAGE]` format.
+fn parse_line(line: &str) -> (Option<u8>, Option<String>) {
+    let line = line.trim();
+    let (first, rest) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim_start()),
+        None => (line, ""),
+    };
+
+    let percent = first
+        .trim_end_matches('%')
+        .parse::<u8>()
+        .ok()
+        .filter(|percent| *percent <= 100);
+
+    let message = if percent.is_some() { rest } else { line };
+    let message = if message.is_empty() {
+        None
+    } else {
+        Some(message.to_string())
+    };
+
+    (percent, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use tempdir::TempDir;
+
+    use super::{parse_line, RunningGuard, RunningJobs};
+
+
+    #[test]
+    fn test_parse_line() {
+        let msg = |s: &str| Some(s.to_string());
+
+        assert_eq!(parse_line("40"), (Some(40), None));
+        assert_eq!(parse_line("40%"), (Some(40), None));
+        assert_eq!(parse_line(" 40  Building  "), (Some(40), msg("Building")));
+        assert_eq!(parse_line("100% Done!"), (Some(100), msg("Done!")));
+        assert_eq!(parse_line("Uploading"), (None, msg("Uploading")));
+        assert_eq!(parse_line("101 files"), (None, msg("101 files")));
+        assert_eq!(parse_line("-1 x"), (None, msg("-1 x")));
+    }
+
+    #[test]
+    fn test_running_jobs() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("progress");
+        let jobs = Arc::new(Mutex::new(RunningJobs::new()));
+
+        let output = {
+            let _guard = RunningGuard::new(&jobs, "abc", "deploy.sh", &path);
+
+            // No progress is reported if the file doesn't exist yet
+            let progress = jobs.lock().unwrap().get("abc").unwrap();
+            assert_eq!(progress.script, "deploy.sh");
+            assert_eq!(progress.percent, None);
+            assert_eq!(progress.message, None);
+
+            let mut file = OpenOptions::new()
+                .create(true).append(true).open(&path).unwrap();
+            writeln!(file, "10 Fetching").unwrap();
+            writeln!(file, "50 Building\n").unwrap();
+
+            // Only the last line matters
+            let list = jobs.lock().unwrap().list();
+            assert_eq!(list.len(), 1);
+            assert_eq!(list[0].job_id, "abc");
+            assert_eq!(list[0].percent, Some(50));
+            assert_eq!(list[0].message, Some("Building".into()));
+
+            // Huge files are handled too
+            let long = "x".repeat(10000);
+            writeln!(file, "{}\n90 Almost done", long).unwrap();
+            let progress = jobs.lock().unwrap().get("abc").unwrap();
+            assert_eq!(progress.percent, Some(90));
+
+            let output = jobs.lock().unwrap().output("abc").unwrap();
+            output
+        };
+
+        // The job isn't running anymore after the guard is dropped
+        assert!(jobs.lock().unwrap().get("abc").is_none());
+        assert!(jobs.lock().unwrap().list().is_empty());
+        assert!(jobs.lock().unwrap().output("abc").is_none());
+        assert!(output.lock().unwrap().is_finished());
+    }
+}
@@ -19,7 +19,10 @@ use std::sync::{Arc, RwLock};
 
 use common::prelude::*;
 use common::state::{State, UniqueId};
-use providers::{Provider, StatusEvent, StatusEventKind};
+use common::structs::DailyBudget;
+use providers::{
+    ExhaustedBudget, Provider, StatusEvent, StatusEventKind,
+};
 use requests::Request;
 use scripts::collector::Collector;
 use scripts::jobs::{Job, JobOutput};
@@ -27,12 +30,12 @@ use scripts::script::{Script, ScriptProvider};
 
 
 pub struct ScriptsIter {
-    inner: Arc<RwLock<RepositoryInner>>,
+    inner: Arc<RepositoryInner>,
     count: usize,
 }
 
 impl ScriptsIter {
-    fn new(inner: Arc<RwLock<RepositoryInner>>) -> Self {
+    fn new(inner: Arc<RepositoryInner>) -> Self {
         ScriptsIter { inner, count: 0 }
     }
 }
@@ -42,25 +45,19 @@ impl Iterator for ScriptsIter {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.count += 1;
-
-        match self.inner.read() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        }.scripts
-            .get(self.count - 1)
-            .cloned()
+        self.inner.scripts.get(self.count - 1).cloned()
     }
 }
 
 
 pub struct StatusJobsIter {
-    inner: Arc<RwLock<RepositoryInner>>,
+    inner: Arc<RepositoryInner>,
     event: StatusEvent,
     count: usize,
 }
 
 impl StatusJobsIter {
-    fn new(inner: Arc<RwLock<RepositoryInner>>, event: StatusEvent) -> Self {
+    fn new(inner: Arc<RepositoryInner>, event: StatusEvent) -> Self {
         StatusJobsIter {
             inner,
             event,
@@ -75,12 +72,7 @@ impl Iterator for StatusJobsIter {
     fn next(&mut self) -> Option<Self::Item> {
         self.count += 1;
 
-        let inner = match self.inner.read() {
-            Ok(guard) => guard,
-            Err(poisoned) => poisoned.into_inner(),
-        };
-
-        if let Some(all) = inner.status_hooks.get(&self.event.kind()) {
+        if let Some(all) = self.inner.status_hooks.get(&self.event.kind()) {
             if let Some(hp) = all.get(self.count - 1).cloned() {
                 Some(Job::new(
                     hp.script,
@@ -143,18 +135,30 @@ impl RepositoryInner {
 }
 
 
+/// The scripts are never changed after being loaded: reloading them replaces
+/// the whole content of the repository, so readers only need to hold the lock
+/// while getting the current content.
 #[derive(Debug)]
 pub struct Repository {
-    inner: Arc<RwLock<RepositoryInner>>,
+    inner: Arc<RwLock<Arc<RepositoryInner>>>,
 }
 
 impl Repository {
-    pub fn get_by_name(&self, name: &str) -> Option<Arc<Script>> {
+    fn snapshot(&self) -> Arc<RepositoryInner> {
         match self.inner.read() {
-            Ok(inner) => inner.get_by_name(name),
-            Err(poisoned) => poisoned.get_ref().get_by_name(name),
+            Ok(inner) => inner.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
         }
     }
+
+    pub fn get_by_name(&self, name: &str) -> Option<Arc<Script>> {
+        self.snapshot().get_by_name(name)
+    }
+
+    /// Return the jobs of the status hooks interested in the event.
+    pub fn status_jobs(&self, event: StatusEvent) -> StatusJobsIter {
+        StatusJobsIter::new(self.snapshot(), event)
+    }
 }
 
 impl ScriptsRepositoryTrait for Repository {
@@ -164,14 +168,11 @@ impl ScriptsRepositoryTrait for Repository {
     type JobsIter = StatusJobsIter;
 
     fn id_exists(&self, id: &UniqueId) -> bool {
-        match self.inner.read() {
-            Ok(inner) => inner.by_id.contains_key(id),
-            Err(poisoned) => poisoned.get_ref().by_id.contains_key(id),
-        }
+        self.snapshot().by_id.contains_key(id)
     }
 
     fn iter(&self) -> ScriptsIter {
-        ScriptsIter::new(self.inner.clone())
+        ScriptsIter::new(self.snapshot())
     }
 
     fn jobs_after_output(&self, output: JobOutput) -> Option<StatusJobsIter> {
@@ -185,7 +186,19 @@ impl ScriptsRepositoryTrait for Repository {
             StatusEvent::JobFailed(output)
         };
 
-        Some(StatusJobsIter::new(self.inner.clone(), event))
+        Some(self.status_jobs(event))
+    }
+
+    fn jobs_after_budget_exhausted(
+        &self, script_name: &str, budget: DailyBudget,
+    ) -> Option<StatusJobsIter> {
+        let event = StatusEvent::BudgetExhausted(ExhaustedBudget {
+            script_name: script_name.to_string(),
+            budget: budget.seconds,
+            action: budget.action,
+        });
+
+        Some(self.status_jobs(event))
     }
 }
 
@@ -195,7 +208,7 @@ pub struct Blueprint {
     added: Vec<Arc<Script>>,
     collect_paths: Vec<(PathBuf, bool)>,
 
-    inner: Arc<RwLock<RepositoryInner>>,
+    inner: Arc<RwLock<Arc<RepositoryInner>>>,
     state: Arc<State>,
 }
 
@@ -205,7 +218,7 @@ impl Blueprint {
             added: Vec::new(),
             collect_paths: Vec::new(),
 
-            inner: Arc::new(RwLock::new(RepositoryInner::new())),
+            inner: Arc::new(RwLock::new(Arc::new(RepositoryInner::new()))),
             state: state,
         }
     }
@@ -252,10 +265,8 @@ impl Blueprint {
             }
         }
 
-        {
-            let mut to_update = self.inner.write()?;
-            *to_update = inner;
-        }
+        // Existing iterators keep using the old scripts
+        *self.inner.write()? = Arc::new(inner);
 
         Ok(())
     }
@@ -361,6 +372,33 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_iterators_are_not_affected_by_reloads() {
+        test_wrapper(|env| {
+            for name in &["first.sh", "second.sh"] {
+                env.create_script(name, &[r#"#!/bin/bash"#])?;
+            }
+
+            let mut blueprint = Blueprint::new(env.state());
+            blueprint.insert(Arc::new(env.load_script("first.sh")?))?;
+            blueprint.insert(Arc::new(env.load_script("second.sh")?))?;
+
+            let repository = blueprint.repository();
+            let mut iter = repository.iter();
+            assert_eq!(iter.next().unwrap().name(), "first.sh");
+
+            // The iterator keeps going over the scripts it started with
+            blueprint.clear();
+            blueprint.reload()?;
+            assert!(repository.get_by_name("second.sh").is_none());
+            assert_eq!(iter.next().unwrap().name(), "second.sh");
+            assert!(iter.next().is_none());
+
+            Ok(())
+        });
+    }
+
+
     #[test]
     fn test_blueprint_can_be_reloaded() {
         test_wrapper(|env| {
@@ -15,16 +15,25 @@
 
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use regex::Regex;
 use serde_json;
 
+use common::config::{BlackoutConfig, RateLimitConfig};
 use common::prelude::*;
 use common::state::{IdKind, State, UniqueId};
+use common::structs::{
+    CircuitBreaker, CustomResponses, DailyBudget, EnvSanitization,
+    FreezeAction, ShedPolicy,
+};
 
-use providers::Provider;
+use providers::{CompositeProvider, Provider};
 use requests::{Request, RequestType};
+use utils::{self, TimeString};
+use web::{self, BodyLimits};
 
 
 #[derive(Debug, Clone)]
@@ -44,10 +53,60 @@ lazy_static! {
 }
 
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ProvidersPolicy {
+    FirstMatch,
+    All,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct CircuitBreakerPreferences {
+    failures: u32,
+    cooldown: TimeString,
+    #[serde(default = "default_circuit_action")]
+    action: FreezeAction,
+}
+
+fn default_circuit_action() -> FreezeAction {
+    FreezeAction::Reject
+}
+
+
+#[derive(Debug, Deserialize)]
+struct DailyBudgetPreferences {
+    limit: TimeString,
+    #[serde(default = "default_circuit_action")]
+    action: FreezeAction,
+}
+
+
 #[derive(Debug, Deserialize)]
 struct Preferences {
     priority: Option<isize>,
     parallel: Option<bool>,
+    providers_order: Option<Vec<String>>,
+    providers_policy: Option<ProvidersPolicy>,
+    max_body_size: Option<u64>,
+    content_types: Option<Vec<String>>,
+    spool_threshold: Option<u64>,
+    shed_policy: Option<ShedPolicy>,
+    requires_approval: Option<bool>,
+    circuit_breaker: Option<CircuitBreakerPreferences>,
+    daily_budget: Option<DailyBudgetPreferences>,
+    rate_limit: Option<RateLimitConfig>,
+    blackouts: Option<Vec<BlackoutConfig>>,
+    env_prefix: Option<String>,
+    env_sanitization: Option<EnvSanitization>,
+    umask: Option<String>,
+    temp_dir: Option<String>,
+    timeout: Option<TimeString>,
+    paths: Option<Vec<String>>,
+    secret_path: Option<bool>,
+    responses: Option<CustomResponses>,
+    wait: Option<bool>,
+    wait_output: Option<bool>,
 }
 
 impl Preferences {
@@ -55,6 +114,27 @@ impl Preferences {
         Preferences {
             priority: None,
             parallel: None,
+            providers_order: None,
+            providers_policy: None,
+            max_body_size: None,
+            content_types: None,
+            spool_threshold: None,
+            shed_policy: None,
+            requires_approval: None,
+            circuit_breaker: None,
+            daily_budget: None,
+            rate_limit: None,
+            blackouts: None,
+            env_prefix: None,
+            env_sanitization: None,
+            umask: None,
+            temp_dir: None,
+            timeout: None,
+            paths: None,
+            secret_path: None,
+            responses: None,
+            wait: None,
+            wait_output: None,
         }
     }
 
@@ -67,6 +147,173 @@ impl Preferences {
     fn parallel(&self) -> bool {
         self.parallel.unwrap_or(true)
     }
+
+    #[inline]
+    fn providers_policy(&self) -> ProvidersPolicy {
+        self.providers_policy.unwrap_or(ProvidersPolicy::FirstMatch)
+    }
+
+    #[inline]
+    fn shed_policy(&self) -> ShedPolicy {
+        self.shed_policy.unwrap_or(ShedPolicy::RejectNew)
+    }
+
+    #[inline]
+    fn env_sanitization(&self) -> EnvSanitization {
+        self.env_sanitization.unwrap_or(EnvSanitization::StripControl)
+    }
+
+    #[inline]
+    fn requires_approval(&self) -> bool {
+        self.requires_approval.unwrap_or(false)
+    }
+
+    fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.circuit_breaker.as_ref().map(|prefs| CircuitBreaker {
+            failures: prefs.failures,
+            cooldown: prefs.cooldown.as_u64(),
+            action: prefs.action,
+        })
+    }
+
+    fn daily_budget(&self) -> Option<DailyBudget> {
+        self.daily_budget.as_ref().map(|prefs| DailyBudget {
+            seconds: prefs.limit.as_u64(),
+            action: prefs.action,
+        })
+    }
+
+    fn body_limits(&self) -> BodyLimits {
+        BodyLimits {
+            max_size: self.max_body_size,
+            content_types: self.content_types.clone(),
+            spool_threshold: self.spool_threshold,
+        }
+    }
+
+    #[inline]
+    fn umask(&self) -> Option<u32> {
+        self.umask.as_ref().and_then(|umask| parse_umask(umask))
+    }
+
+    #[inline]
+    fn timeout(&self) -> Option<u64> {
+        // A timeout of zero disables it
+        self.timeout.as_ref().map(|t| t.as_u64()).filter(|t| *t > 0)
+    }
+
+    #[inline]
+    fn secret_path(&self) -> bool {
+        self.secret_path.unwrap_or(false)
+    }
+
+    #[inline]
+    fn wait(&self) -> bool {
+        self.wait.unwrap_or(false)
+    }
+
+    #[inline]
+    fn wait_output(&self) -> bool {
+        self.wait_output.unwrap_or(false)
+    }
+
+    fn check(&self) -> Result<()> {
+        if let Some(ref prefix) = self.env_prefix {
+            if utils::env_var_name(prefix) != *prefix {
+                return Err(
+                    ErrorKind::ScriptInvalidEnvPrefix(prefix.clone()).into()
+                );
+            }
+        }
+
+        if let Some(ref temp_dir) = self.temp_dir {
+            if !Path::new(temp_dir).is_absolute() {
+                return Err(
+                    ErrorKind::ScriptInvalidTempDir(temp_dir.clone()).into()
+                );
+            }
+        }
+
+        if let Some(ref umask) = self.umask {
+            if parse_umask(umask).is_none() {
+                return Err(
+                    ErrorKind::ScriptInvalidUmask(umask.clone()).into()
+                );
+            }
+        }
+
+        for path in self.paths.iter().flatten() {
+            web::check_custom_path(path)?;
+        }
+
+        if let Some(ref budget) = self.daily_budget {
+            if budget.limit.as_u64() == 0 {
+                return Err(ErrorKind::ScriptInvalidDailyBudget.into());
+            }
+        }
+
+        if let Some(ref limit) = self.rate_limit {
+            if limit.allowed == 0 || limit.interval.as_u64() == 0 {
+                return Err(ErrorKind::ScriptInvalidRateLimit.into());
+            }
+        }
+
+        for response in self.responses.iter().flat_map(|r| r.iter()) {
+            if let Some(status) = response.status {
+                if status < 100 || status > 599 {
+                    return Err(
+                        ErrorKind::ScriptInvalidResponseStatus(status).into()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_to_providers(
+        &self, mut providers: Vec<Provider>,
+    ) -> Result<Vec<Provider>> {
+        if let Some(ref order) = self.providers_order {
+            for name in order {
+                if !providers.iter().any(|prov| prov.name() == name) {
+                    return Err(
+                        ErrorKind::ProviderNotDeclared(name.clone()).into()
+                    );
+                }
+            }
+
+            // Providers not present in the order are evaluated last, in the
+            // same order they're declared
+            providers.sort_by_key(|prov| {
+                order
+                    .iter()
+                    .position(|name| name == prov.name())
+                    .unwrap_or(order.len())
+            });
+        }
+
+        if self.providers_policy() == ProvidersPolicy::All {
+            // Status providers only validate status events, so they're kept
+            // separate from the other ones
+            let (mut result, others): (Vec<_>, Vec<_>) = providers
+                .into_iter()
+                .partition(|prov| prov.name() == "Status");
+
+            if !others.is_empty() {
+                result.push(Provider::Composite(CompositeProvider::all(others)));
+            }
+            providers = result;
+        }
+
+        Ok(providers)
+    }
+}
+
+
+/// Parse an umask written in octal, like `077`.
+fn parse_umask(input: &str) -> Option<u32> {
+    u32::from_str_radix(input, 8).ok().filter(|umask| *umask <= 0o777)
 }
 
 
@@ -84,6 +331,7 @@ fn load_headers(file: &str) -> Result<LoadHeadersOutput> {
     let mut line_number: u32 = 0;
     let mut providers = vec![];
     let mut preferences = None;
+    let mut preferences_line = 0;
     for line in reader.lines() {
         line_number += 1;
         content = line.unwrap();
@@ -96,6 +344,7 @@ fn load_headers(file: &str) -> Result<LoadHeadersOutput> {
         if preferences.is_none() {
             if let Some(cap) = PREFERENCES_HEADER_RE.captures(&content) {
                 preferences = Some(serde_json::from_str(&cap[1])?);
+                preferences_line = line_number;
                 continue; // Don't capture anything else for this line
             }
         }
@@ -106,7 +355,7 @@ fn load_headers(file: &str) -> Result<LoadHeadersOutput> {
 
             match Provider::new(name, data) {
                 Ok(provider) => {
-                    providers.push(Arc::new(provider));
+                    providers.push(provider);
                 }
                 Err(mut error) => {
                     Err(error.chain_err(|| ErrorKind::ScriptParsingError(
@@ -117,13 +366,30 @@ fn load_headers(file: &str) -> Result<LoadHeadersOutput> {
         }
     }
 
+    let preferences = if let Some(pref) = preferences {
+        pref
+    } else {
+        Preferences::empty()
+    };
+
+    if let Err(error) = preferences.check() {
+        return Err(error.chain_err(|| ErrorKind::ScriptParsingError(
+            file.into(), preferences_line,
+        )));
+    }
+
+    let providers = match preferences.apply_to_providers(providers) {
+        Ok(providers) => providers,
+        Err(error) => {
+            return Err(error.chain_err(|| ErrorKind::ScriptParsingError(
+                file.into(), preferences_line,
+            )));
+        }
+    };
+
     Ok(LoadHeadersOutput {
-        preferences: if let Some(pref) = preferences {
-            pref
-        } else {
-            Preferences::empty()
-        },
-        providers: providers,
+        preferences: preferences,
+        providers: providers.into_iter().map(Arc::new).collect(),
     })
 }
 
@@ -135,6 +401,23 @@ pub struct Script {
     exec: String,
     priority: isize,
     parallel: bool,
+    body_limits: BodyLimits,
+    shed_policy: ShedPolicy,
+    requires_approval: bool,
+    circuit_breaker: Option<CircuitBreaker>,
+    daily_budget: Option<DailyBudget>,
+    rate_limit: Option<RateLimitConfig>,
+    blackouts: Vec<BlackoutConfig>,
+    env_prefix: Option<String>,
+    env_sanitization: EnvSanitization,
+    umask: Option<u32>,
+    temp_dir: Option<String>,
+    timeout: Option<u64>,
+    paths: Vec<String>,
+    secret_path: bool,
+    responses: CustomResponses,
+    wait: bool,
+    wait_output: bool,
     pub(crate) providers: Vec<Arc<Provider>>,
 }
 
@@ -152,6 +435,23 @@ impl Script {
             exec: exec,
             priority: headers.preferences.priority(),
             parallel: headers.preferences.parallel(),
+            body_limits: headers.preferences.body_limits(),
+            shed_policy: headers.preferences.shed_policy(),
+            requires_approval: headers.preferences.requires_approval(),
+            circuit_breaker: headers.preferences.circuit_breaker(),
+            daily_budget: headers.preferences.daily_budget(),
+            env_sanitization: headers.preferences.env_sanitization(),
+            umask: headers.preferences.umask(),
+            timeout: headers.preferences.timeout(),
+            secret_path: headers.preferences.secret_path(),
+            wait: headers.preferences.wait(),
+            wait_output: headers.preferences.wait_output(),
+            rate_limit: headers.preferences.rate_limit,
+            paths: headers.preferences.paths.unwrap_or_default(),
+            blackouts: headers.preferences.blackouts.unwrap_or_default(),
+            env_prefix: headers.preferences.env_prefix,
+            temp_dir: headers.preferences.temp_dir,
+            responses: headers.preferences.responses.unwrap_or_default(),
             providers: headers.providers,
         })
     }
@@ -186,6 +486,92 @@ impl Script {
     pub fn priority(&self) -> isize {
         self.priority
     }
+
+    pub fn body_limits(&self) -> &BodyLimits {
+        &self.body_limits
+    }
+
+    pub fn shed_policy(&self) -> ShedPolicy {
+        self.shed_policy
+    }
+
+    /// Check if the jobs of this script must be approved before running.
+    pub fn requires_approval(&self) -> bool {
+        self.requires_approval
+    }
+
+    pub fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.circuit_breaker
+    }
+
+    /// Get for how long the jobs of this script can run every day.
+    pub fn daily_budget(&self) -> Option<DailyBudget> {
+        self.daily_budget
+    }
+
+    /// Get how many requests the script accepts, if they're limited.
+    pub fn rate_limit(&self) -> Option<&RateLimitConfig> {
+        self.rate_limit.as_ref()
+    }
+
+    /// Get the prefix replacing `FISHER` in the names of the environment
+    /// variables set by the providers, if it's overridden.
+    pub fn env_prefix(&self) -> Option<&str> {
+        self.env_prefix.as_deref()
+    }
+
+    pub fn env_sanitization(&self) -> EnvSanitization {
+        self.env_sanitization
+    }
+
+    /// Get the umask the script is executed with, if it's overridden.
+    pub fn umask(&self) -> Option<u32> {
+        self.umask
+    }
+
+    /// Get where the temporary directories of the jobs are created, if
+    /// it's overridden.
+    pub fn temp_dir(&self) -> Option<&str> {
+        self.temp_dir.as_ref().map(|dir| dir.as_str())
+    }
+
+    /// Get how long the jobs of this script can run before being killed.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    /// Get the custom paths this script is reachable at, in addition to
+    /// `/hook/<name>`.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Check if this script is also reachable at a secret path.
+    pub fn secret_path(&self) -> bool {
+        self.secret_path
+    }
+
+    /// Get the periods when some events sent to this script are muted.
+    pub fn blackouts(&self) -> &[BlackoutConfig] {
+        &self.blackouts
+    }
+
+    /// Get the responses returned instead of the default ones.
+    pub fn responses(&self) -> &CustomResponses {
+        &self.responses
+    }
+
+    /// Check if the requests to this script wait for their job to end,
+    /// even if they don't ask to.
+    pub fn wait(&self) -> bool {
+        self.wait
+    }
+
+    /// Check if the output of the jobs is returned to the requests waiting
+    /// for them.
+    pub fn wait_output(&self) -> bool {
+        self.wait_output
+    }
 }
 
 impl ScriptTrait for Script {
@@ -203,7 +589,12 @@ impl ScriptTrait for Script {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use common::prelude::*;
+    use common::structs::{
+        CircuitBreaker, DailyBudget, EnvSanitization, FreezeAction,
+    };
     use requests::{Request, RequestType};
     use scripts::test_utils::*;
 
@@ -371,6 +762,366 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_providers_order_and_policy() {
+        test_wrapper(|env| {
+            env.create_script(
+                "ordered.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher-Testing: {}"#,
+                    r#"## Fisher-Standalone: {}"#,
+                    r#"## Fisher: {"providers_order": ["Standalone"]}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "all.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"providers_policy": "all"}"#,
+                    r#"## Fisher-Testing: {}"#,
+                    r#"## Fisher-Standalone: {"secret": "abcde"}"#,
+                    r#"## Fisher-Status: {"events": ["job-failed"]}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "unknown.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"providers_order": ["GitHub"]}"#,
+                    r#"## Fisher-Testing: {}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+
+            // The providers listed in the order are evaluated first
+            let ordered = env.load_script("ordered.sh")?;
+            let names = ordered.providers.iter()
+                .map(|prov| prov.name())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["Standalone", "Testing"]);
+
+            let req = Request::Web(dummy_web_request());
+            let (result, provider) = ordered.validate(&req);
+            assert_eq!(result, RequestType::ExecuteHook);
+            assert_eq!(provider.unwrap().name(), "Standalone");
+
+            // All the providers must match, except for the status ones
+            let all = env.load_script("all.sh")?;
+            let names = all.providers.iter()
+                .map(|prov| prov.name())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["Status", "Composite"]);
+            assert_eq!(all.validate(&req).0, RequestType::Invalid);
+
+            let mut req = dummy_web_request();
+            req.headers.insert("X-Fisher-Secret".into(), "abcde".into());
+            assert_eq!(
+                all.validate(&Request::Web(req)).0,
+                RequestType::ExecuteHook
+            );
+
+            // Providers not declared can't be present in the order
+            assert!(env.load_script("unknown.sh").is_err());
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_env_preferences() {
+        test_wrapper(|env| {
+            env.create_script(
+                "legacy.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"env_prefix": "HOOK"}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "invalid.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"env_prefix": "not-valid"}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+
+            assert_eq!(
+                env.load_script("legacy.sh")?.env_prefix(), Some("HOOK"),
+            );
+            assert!(env.load_script("invalid.sh").is_err());
+
+            // Values are sanitized by default
+            assert_eq!(
+                env.load_script("legacy.sh")?.env_sanitization(),
+                EnvSanitization::StripControl,
+            );
+            env.create_script(
+                "quoted.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"env_sanitization": "shell-quote"}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            assert_eq!(
+                env.load_script("quoted.sh")?.env_sanitization(),
+                EnvSanitization::ShellQuote,
+            );
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_job_directories_preferences() {
+        test_wrapper(|env| {
+            for &(name, umask) in &[
+                ("valid.sh", "027"),
+                ("too-big.sh", "1777"),
+                ("not-octal.sh", "089"),
+            ] {
+                env.create_script(name, &[
+                    "#!/bin/bash",
+                    &format!(r#"## Fisher: {{"umask": "{}"}}"#, umask),
+                    r#"echo "ok""#,
+                ])?;
+            }
+
+            assert_eq!(env.load_script("valid.sh")?.umask(), Some(0o027));
+            assert!(env.load_script("too-big.sh").is_err());
+            assert!(env.load_script("not-octal.sh").is_err());
+
+            // Temporary directories must be absolute
+            for &(name, dir) in &[("abs.sh", "/tmp"), ("rel.sh", "tmp")] {
+                env.create_script(name, &[
+                    "#!/bin/bash",
+                    &format!(r#"## Fisher: {{"temp_dir": "{}"}}"#, dir),
+                ])?;
+            }
+            assert_eq!(env.load_script("abs.sh")?.temp_dir(), Some("/tmp"));
+            assert!(env.load_script("rel.sh").is_err());
+
+            // The umask isn't overridden by default
+            env.create_script("default.sh", &["#!/bin/bash"])?;
+            assert_eq!(env.load_script("default.sh")?.umask(), None);
+            assert_eq!(env.load_script("default.sh")?.timeout(), None);
+
+            // Timeouts can be time strings, and zero disables them
+            for &(name, timeout) in &[("timeout.sh", "1m"), ("zero.sh", "0")] {
+                env.create_script(name, &[
+                    "#!/bin/bash",
+                    &format!(r#"## Fisher: {{"timeout": "{}"}}"#, timeout),
+                ])?;
+            }
+            assert_eq!(
+                env.load_script("timeout.sh")?.timeout(),
+                Some(Duration::from_secs(60)),
+            );
+            assert_eq!(env.load_script("zero.sh")?.timeout(), None);
+
+            // Custom paths can't clash with the ones used by Fisher
+            assert!(env.load_script("default.sh")?.paths().is_empty());
+            assert!(!env.load_script("default.sh")?.secret_path());
+            let header =
+                r#"## Fisher: {"paths": ["PATH"], "secret_path": true}"#;
+            for &(name, path) in &[("paths.sh", "/deploy"), ("bad.sh", "/h")] {
+                env.create_script(name, &[
+                    "#!/bin/bash", &header.replace("PATH", path),
+                ])?;
+            }
+            let script = env.load_script("paths.sh")?;
+            assert_eq!(script.paths(), &["/deploy".to_string()]);
+            assert!(script.secret_path());
+            assert!(env.load_script("bad.sh").is_err());
+
+            // Requests don't wait for the jobs by default
+            assert!(!env.load_script("default.sh")?.wait());
+            assert!(!env.load_script("default.sh")?.wait_output());
+            env.create_script("wait.sh", &[
+                "#!/bin/bash",
+                r#"## Fisher: {"wait": true, "wait_output": true}"#,
+            ])?;
+            let script = env.load_script("wait.sh")?;
+            assert!(script.wait());
+            assert!(script.wait_output());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_circuit_breaker_preferences() {
+        test_wrapper(|env| {
+            env.create_script(
+                "breaker.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"circuit_breaker": {"failures": 3, "cooldown": "5m"}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "queue.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"circuit_breaker": {"failures": 1, "cooldown": 10, "action": "queue"}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+
+            assert_eq!(
+                env.load_script("breaker.sh")?.circuit_breaker(),
+                Some(CircuitBreaker {
+                    failures: 3,
+                    cooldown: 300,
+                    action: FreezeAction::Reject,
+                })
+            );
+            assert_eq!(
+                env.load_script("queue.sh")?.circuit_breaker(),
+                Some(CircuitBreaker {
+                    failures: 1,
+                    cooldown: 10,
+                    action: FreezeAction::Queue,
+                })
+            );
+
+            // Scripts don't have a circuit breaker by default
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#, r#"echo "ok""#])?;
+            assert!(env.load_script("plain.sh")?.circuit_breaker().is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_daily_budget_preferences() {
+        test_wrapper(|env| {
+            env.create_script(
+                "budget.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"daily_budget": {"limit": "2h"}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "queue.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"daily_budget": {"limit": 60, "action": "queue"}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "zero.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"daily_budget": {"limit": 0}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+
+            assert_eq!(
+                env.load_script("budget.sh")?.daily_budget(),
+                Some(DailyBudget {
+                    seconds: 7200,
+                    action: FreezeAction::Reject,
+                })
+            );
+            assert_eq!(
+                env.load_script("queue.sh")?.daily_budget(),
+                Some(DailyBudget {
+                    seconds: 60,
+                    action: FreezeAction::Queue,
+                })
+            );
+            assert!(env.load_script("zero.sh").is_err());
+
+            // Scripts don't have a daily budget by default
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#, r#"echo "ok""#])?;
+            assert!(env.load_script("plain.sh")?.daily_budget().is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rate_limit_preferences() {
+        test_wrapper(|env| {
+            env.create_script(
+                "limited.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"rate_limit": "10/minute"}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "zero.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"rate_limit": "0/minute"}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#, r#"echo "ok""#])?;
+
+            let script = env.load_script("limited.sh")?;
+            let limit = script.rate_limit().unwrap();
+            assert_eq!(limit.allowed, 10);
+            assert_eq!(limit.interval.as_u64(), 60);
+
+            assert!(env.load_script("zero.sh").is_err());
+            assert!(env.load_script("plain.sh")?.rate_limit().is_none());
+
+            Ok(())
+        });
+    }
+
+
+    #[test]
+    fn test_responses_preferences() {
+        test_wrapper(|env| {
+            env.create_script(
+                "custom.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"responses": {"queued": {"status": 201, "body": "{job_id}"}}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script(
+                "invalid.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"responses": {"rejected": {"status": 42}}}"#,
+                    r#"echo "ok""#,
+                ],
+            )?;
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#, r#"echo "ok""#])?;
+
+            let script = env.load_script("custom.sh")?;
+            let queued = script.responses().queued.as_ref().unwrap();
+            assert_eq!(queued.status, Some(201));
+            assert_eq!(queued.body, Some("{job_id}".into()));
+            assert!(script.responses().rejected.is_none());
+
+            assert!(env.load_script("invalid.sh").is_err());
+            assert!(env.load_script("plain.sh")?.responses().queued.is_none());
+
+            Ok(())
+        });
+    }
+
+
     #[test]
     fn test_script_ids_are_unique() {
         test_wrapper(|env| {
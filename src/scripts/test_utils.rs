@@ -26,7 +26,8 @@ use tempdir::TempDir;
 use common::prelude::*;
 use common::state::State;
 use scripts::Script;
-use web::WebRequest;
+use utils::Headers;
+use web::{AccessDetails, JsonBody, WebRequest};
 
 
 pub struct TestEnv {
@@ -100,10 +101,14 @@ impl TestEnv {
 
 pub fn dummy_web_request() -> WebRequest {
     WebRequest {
-        headers: HashMap::new(),
+        headers: Headers::new(),
         params: HashMap::new(),
+        path_args: Vec::new(),
         source: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        body: String::new(),
+        body: Vec::new(),
+        spooled: None,
+        json_body: JsonBody::default(),
+        access: AccessDetails::default(),
     }
 }
 
@@ -0,0 +1,172 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Uploads of the artifacts of the running jobs.
+//!
+//! Every running job gets a random token, which its script uses to upload
+//! its artifacts to the web API. The token is valid only until the job ends,
+//! and the names of the uploaded artifacts are then included in the output
+//! of the job.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rand;
+
+use utils;
+
+
+#[derive(Debug)]
+struct JobUploads {
+    token: String,
+    artifacts: Vec<String>,
+}
+
+
+/// The uploads of the running jobs, shared between the processor and the
+/// web API.
+#[derive(Debug, Default)]
+pub struct ArtifactUploads {
+    jobs: HashMap<String, JobUploads>,
+    /// Where the scripts can upload their artifacts, if the web API accepts
+    /// them.
+    url: Option<String>,
+}
+
+impl ArtifactUploads {
+    pub fn new() -> Self {
+        ArtifactUploads {
+            jobs: HashMap::new(),
+            url: None,
+        }
+    }
+
+    /// Set the base URL of the web API the scripts upload their artifacts
+    /// to, or disable the uploads.
+    pub fn set_url(&mut self, url: Option<String>) {
+        self.url = url;
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    /// Allow the script of a job to upload its artifacts.
+    pub fn start(&mut self, job_id: &str) {
+        self.jobs.insert(job_id.into(), JobUploads {
+            token: utils::to_hex(&rand::random::<[u8; 16]>()),
+            artifacts: Vec::new(),
+        });
+    }
+
+    /// Stop accepting the artifacts of a job, forgetting its token.
+    pub fn finish(&mut self, job_id: &str) {
+        self.jobs.remove(job_id);
+    }
+
+    /// Get the secret the script of a running job uploads its artifacts
+    /// with.
+    pub fn token(&self, job_id: &str) -> Option<&str> {
+        self.jobs.get(job_id).map(|job| job.token.as_str())
+    }
+
+    /// Record an artifact uploaded by the script of a running job.
+    pub fn add(&mut self, job_id: &str, name: &str) {
+        if let Some(job) = self.jobs.get_mut(job_id) {
+            if !job.artifacts.iter().any(|artifact| artifact == name) {
+                job.artifacts.push(name.into());
+            }
+        }
+    }
+
+    /// Get the names of the artifacts uploaded so far by the script of a
+    /// running job.
+    pub fn artifacts(&self, job_id: &str) -> Vec<String> {
+        self.jobs
+            .get(job_id)
+            .map(|job| job.artifacts.clone())
+            .unwrap_or_default()
+    }
+}
+
+
+/// Accept the artifacts of a job until this is dropped, even if the job
+/// fails early.
+pub(crate) struct UploadsGuard<'a> {
+    uploads: &'a Mutex<ArtifactUploads>,
+    job_id: &'a str,
+}
+
+impl<'a> UploadsGuard<'a> {
+    pub(crate) fn new(
+        uploads: &'a Mutex<ArtifactUploads>, job_id: &'a str,
+    ) -> Self {
+        uploads.lock().unwrap().start(job_id);
+        UploadsGuard { uploads, job_id }
+    }
+}
+
+impl<'a> Drop for UploadsGuard<'a> {
+    fn drop(&mut self) {
+        self.uploads.lock().unwrap().finish(self.job_id);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{ArtifactUploads, UploadsGuard};
+
+
+    #[test]
+    fn test_uploads() {
+        let mut uploads = ArtifactUploads::new();
+        assert_eq!(uploads.url(), None);
+        uploads.set_url(Some("http://127.0.0.1:8000".into()));
+        assert_eq!(uploads.url(), Some("http://127.0.0.1:8000"));
+
+        // Every job gets its own token
+        uploads.start("abc");
+        uploads.start("def");
+        let token = uploads.token("abc").unwrap().to_string();
+        assert_eq!(token.len(), 32);
+        assert_ne!(Some(token.as_str()), uploads.token("def"));
+        assert_eq!(uploads.token("ghi"), None);
+
+        // Artifacts uploaded again are listed only once
+        uploads.add("abc", "report.html");
+        uploads.add("abc", "build.log");
+        uploads.add("abc", "report.html");
+        uploads.add("ghi", "report.html");
+        assert_eq!(uploads.artifacts("abc"), vec!["report.html", "build.log"]);
+        assert!(uploads.artifacts("def").is_empty());
+
+        uploads.finish("abc");
+        assert_eq!(uploads.token("abc"), None);
+        assert!(uploads.artifacts("abc").is_empty());
+    }
+
+    #[test]
+    fn test_uploads_guard() {
+        let uploads = Mutex::new(ArtifactUploads::new());
+        {
+            let _guard = UploadsGuard::new(&uploads, "abc");
+            assert!(uploads.lock().unwrap().token("abc").is_some());
+        }
+        assert!(uploads.lock().unwrap().token("abc").is_none());
+    }
+}
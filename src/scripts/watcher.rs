@@ -0,0 +1,264 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Watcher of the scripts directory, reloading the scripts when it changes.
+//!
+//! The directory is watched with inotify, and the scripts are reloaded only
+//! after nothing changed for a little while, so a script written in multiple
+//! steps is loaded once. If the new scripts can't be loaded the old ones are
+//! kept, and the error is shown.
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use common::prelude::*;
+
+
+/// How long nothing must change in the directory before reloading it.
+#[cfg(target_os = "linux")]
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often the watcher checks if it should stop.
+#[cfg(target_os = "linux")]
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::fs;
+    use std::io;
+    use std::os::unix::io::RawFd;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use nix::errno::Errno;
+    use nix::libc;
+    use nix::poll::{poll, EventFlags, PollFd};
+    use nix::unistd;
+    use nix::{self, NixPath};
+
+    use common::prelude::*;
+
+
+    /// The changes which can add, remove or alter a script.
+    const MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE | libc::IN_ATTRIB;
+
+
+    pub struct Inotify {
+        fd: RawFd,
+    }
+
+    impl Inotify {
+        pub fn new() -> Result<Self> {
+            let fd = unsafe {
+                libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            Ok(Inotify { fd })
+        }
+
+        /// Watch the directory, and its subdirectories if `recursive` is
+        /// true. Directories already watched are ignored.
+        pub fn watch(&self, path: &Path, recursive: bool) -> Result<()> {
+            let result = path.with_nix_path(|path| unsafe {
+                libc::inotify_add_watch(self.fd, path.as_ptr(), MASK)
+            })?;
+            if result < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            if recursive {
+                for entry in fs::read_dir(path)? {
+                    let entry = entry?.path();
+                    if entry.is_dir() {
+                        self.watch(&entry, true)?;
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Wait for some changes, returning whether any of them happened
+        /// before the timeout.
+        pub fn wait(&self, timeout: Duration) -> Result<bool> {
+            let millis = timeout.as_secs() * 1000
+                + u64::from(timeout.subsec_millis());
+
+            let mut fds = [PollFd::new(self.fd, EventFlags::POLLIN)];
+            match poll(&mut fds, millis as libc::c_int) {
+                Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => {
+                    return Ok(false);
+                }
+                Ok(_) => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            // The events themselves are not needed, since all the scripts
+            // are reloaded anyway
+            let mut buffer = [0; 4096];
+            loop {
+                match unistd::read(self.fd, &mut buffer) {
+                    Ok(_) => {}
+                    Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(true),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            let _ = unistd::close(self.fd);
+        }
+    }
+}
+
+
+/// This struct controls the thread watching the scripts directory.
+
+pub struct Watcher {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Watcher {
+    /// Start watching the directory, and its subdirectories if `recursive`
+    /// is true, calling `reload` after it changed.
+    #[cfg(target_os = "linux")]
+    pub fn start<F>(path: &Path, recursive: bool, reload: F) -> Result<Self>
+    where
+        F: Fn() -> Result<()> + Send + 'static,
+    {
+        let inotify = inotify::Inotify::new()?;
+        inotify.watch(path, recursive)?;
+
+        let path = path.to_path_buf();
+        let (stop_send, stop_recv) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut changed = false;
+
+            // Exit if Fisher is stopped or the watcher is restarted
+            while let Err(mpsc::TryRecvError::Empty) = stop_recv.try_recv() {
+                let timeout = if changed { DEBOUNCE } else { STOP_INTERVAL };
+                match inotify.wait(timeout) {
+                    Ok(true) => {
+                        changed = true;
+                        continue;
+                    }
+                    Ok(false) if changed => {}
+                    Ok(false) => continue,
+                    Err(err) => {
+                        err.pretty_print();
+                        break;
+                    }
+                }
+                changed = false;
+
+                // New subdirectories must be watched too
+                let result = reload().and_then(|_| {
+                    inotify.watch(&path, recursive)
+                });
+                match result.chain_err(|| {
+                    ErrorKind::ScriptsReloadFailed(
+                        path.to_string_lossy().to_string(),
+                    )
+                }) {
+                    Ok(()) => println!(
+                        "Scripts reloaded after {} changed",
+                        path.to_string_lossy(),
+                    ),
+                    Err(err) => err.pretty_print(),
+                }
+            }
+        });
+
+        Ok(Watcher {
+            stop: stop_send,
+            handle,
+        })
+    }
+
+    /// Start watching the directory, and its subdirectories if `recursive`
+    /// is true, calling `reload` after it changed.
+    #[cfg(not(target_os = "linux"))]
+    pub fn start<F>(_path: &Path, _recursive: bool, _reload: F) -> Result<Self>
+    where
+        F: Fn() -> Result<()> + Send + 'static,
+    {
+        Err(ErrorKind::ScriptsWatchUnsupported.into())
+    }
+
+    /// Stop watching the directory, returning when the thread exited.
+    pub fn stop(self) {
+        // The thread might be already stopped
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use scripts::test_utils::*;
+
+    use super::Watcher;
+
+
+    #[test]
+    fn test_watcher_reloads() {
+        test_wrapper(|env| {
+            let dir = env.tempdir()?;
+            fs::create_dir(dir.join("sub"))?;
+
+            let (send, recv) = mpsc::channel();
+            let watcher = Watcher::start(&dir, true, move || {
+                send.send(())?;
+                Ok(())
+            })?;
+            let wait = || recv.recv_timeout(Duration::from_secs(5)).is_ok();
+
+            // Multiple changes in a row result in a single reload
+            env.create_script_into(&dir, "a.sh", &["#!/bin/bash"])?;
+            env.create_script_into(&dir, "b.sh", &["#!/bin/bash"])?;
+            assert!(wait());
+            assert!(recv.recv_timeout(Duration::from_secs(1)).is_err());
+
+            // Changes in the subdirectories are watched too
+            fs::remove_file(dir.join("a.sh"))?;
+            assert!(wait());
+            env.create_script_into(&dir.join("sub"), "c.sh", &["#!/bin/bash"])?;
+            assert!(wait());
+
+            // Nothing is reloaded after the watcher is stopped
+            watcher.stop();
+            fs::remove_file(dir.join("b.sh"))?;
+            assert!(recv.recv_timeout(Duration::from_secs(1)).is_err());
+
+            Ok(())
+        });
+    }
+}
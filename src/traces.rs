@@ -0,0 +1,401 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tracing of the webhooks, from the request to the end of the script.
+//!
+//! Every request to a hook starts a trace, continuing the one of the caller
+//! if it sent a `traceparent` header. Handling the request, the time its jobs
+//! wait in the queue and the execution of the scripts are recorded as spans,
+//! which are sent in batches to an OpenTelemetry collector with the OTLP/HTTP
+//! JSON protocol. Nothing is recorded if no collector is configured.
+
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand;
+use reqwest;
+use serde_json::Value;
+use url::Url;
+
+use common::prelude::*;
+use common::config::TracingConfig;
+use utils;
+
+
+/// How many spans are sent at most in a single request.
+const BATCH_SIZE: usize = 128;
+
+/// How long spans are buffered before being sent.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+lazy_static! {
+    static ref EXPORTER: Mutex<Option<mpsc::Sender<FinishedSpan>>> =
+        Mutex::new(None);
+}
+
+
+/// Check if the spans are exported.
+pub fn enabled() -> bool {
+    EXPORTER.lock().map(|exporter| exporter.is_some()).unwrap_or(false)
+}
+
+
+/// Get the current time, in nanoseconds since the epoch.
+pub fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+        .unwrap_or(0)
+}
+
+
+/// The identifiers of a span, which are propagated to its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+impl TraceContext {
+    /// Create the context of the first span of a new trace.
+    pub fn new() -> Self {
+        TraceContext {
+            trace_id: utils::to_hex(&rand::random::<[u8; 16]>()),
+            span_id: utils::to_hex(&rand::random::<[u8; 8]>()),
+        }
+    }
+
+    /// Parse a W3C `traceparent` header, ignoring invalid ones.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let parts = header.trim().split('-').collect::<Vec<_>>();
+        if parts.len() < 4 || parts[0] != "00" {
+            return None;
+        }
+
+        let is_id = |id: &str, len: usize| {
+            id.len() == len
+                && id.chars().all(|c| c.is_digit(16) && !c.is_uppercase())
+                && id.chars().any(|c| c != '0')
+        };
+        if !is_id(parts[1], 32) || !is_id(parts[2], 16) {
+            return None;
+        }
+
+        Some(TraceContext {
+            trace_id: parts[1].into(),
+            span_id: parts[2].into(),
+        })
+    }
+
+    /// Create the context of a child span, in the same trace.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id.clone(),
+            span_id: utils::to_hex(&rand::random::<[u8; 8]>()),
+        }
+    }
+
+    /// Format the context as a W3C `traceparent` header.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+
+/// A span being recorded. It's exported when it ends.
+#[derive(Debug)]
+pub struct Span {
+    name: String,
+    context: TraceContext,
+    parent: Option<String>,
+    server: bool,
+    start: u64,
+    attributes: Vec<(&'static str, String)>,
+    failed: bool,
+}
+
+impl Span {
+    /// Start a span now, with the context and the ID of the parent span.
+    pub fn start(
+        name: &str, context: TraceContext, parent: Option<&str>,
+    ) -> Self {
+        Span {
+            name: name.into(),
+            context,
+            parent: parent.map(String::from),
+            server: false,
+            start: now_nanos(),
+            attributes: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Start a span handling a request received by Fisher, continuing the
+    /// trace of the caller if it's known.
+    pub fn server(name: &str, caller: Option<TraceContext>) -> Self {
+        let mut span = match caller {
+            Some(caller) => {
+                Span::start(name, caller.child(), Some(&caller.span_id))
+            }
+            None => Span::start(name, TraceContext::new(), None),
+        };
+        span.server = true;
+        span
+    }
+
+    /// Change when the span started, in nanoseconds since the epoch.
+    pub fn started_at(&mut self, start: u64) {
+        self.start = start;
+    }
+
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    pub fn attribute<V: ToString>(&mut self, key: &'static str, value: V) {
+        self.attributes.push((key, value.to_string()));
+    }
+
+    /// Mark the operation recorded by the span as failed.
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// End the span now, exporting it if tracing is enabled.
+    pub fn end(self) {
+        let sender = match EXPORTER.lock() {
+            Ok(exporter) => exporter.clone(),
+            Err(..) => None,
+        };
+
+        if let Some(sender) = sender {
+            let _ = sender.send(FinishedSpan {
+                span: self,
+                end: now_nanos(),
+            });
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct FinishedSpan {
+    span: Span,
+    end: u64,
+}
+
+impl FinishedSpan {
+    fn to_json(&self) -> Value {
+        let span = &self.span;
+        let attributes = span.attributes
+            .iter()
+            .map(|&(key, ref value)| json!({
+                "key": key,
+                "value": {"stringValue": value},
+            }))
+            .collect::<Vec<_>>();
+
+        let mut result = json!({
+            "traceId": span.context.trace_id,
+            "spanId": span.context.span_id,
+            "name": span.name,
+            // SPAN_KIND_SERVER or SPAN_KIND_INTERNAL
+            "kind": if span.server { 2 } else { 1 },
+            "startTimeUnixNano": span.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": attributes,
+            // STATUS_CODE_ERROR or STATUS_CODE_OK
+            "status": {"code": if span.failed { 2 } else { 1 }},
+        });
+        if let Some(ref parent) = span.parent {
+            result["parentSpanId"] = parent.as_str().into();
+        }
+        result
+    }
+}
+
+
+fn export(
+    client: &reqwest::Client, url: &Url, service: &str,
+    spans: &[FinishedSpan],
+) -> Result<()> {
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": {"stringValue": service},
+                }],
+            },
+            "scopeSpans": [{
+                "scope": {"name": "fisher"},
+                "spans": spans.iter().map(|s| s.to_json()).collect::<Vec<_>>(),
+            }],
+        }],
+    });
+
+    let response = client.post(url.clone()).json(&body).send()?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::TracingBadStatus(response.status().as_u16()).into())
+    }
+}
+
+
+/// This struct controls the thread exporting the spans.
+
+pub struct Exporter {
+    handle: thread::JoinHandle<()>,
+}
+
+impl Exporter {
+    /// Start exporting the spans, if tracing is enabled in the
+    /// configuration.
+    pub fn start(config: &TracingConfig) -> Result<Option<Self>> {
+        let url = if let Some(ref url) = config.otlp_endpoint {
+            Url::parse(url)
+                .chain_err(|| ErrorKind::TracingInvalidUrl(url.clone()))?
+        } else {
+            return Ok(None);
+        };
+        let service = config.service_name.clone();
+
+        let (send, recv) = mpsc::channel::<FinishedSpan>();
+        let handle = thread::spawn(move || {
+            let client = reqwest::Client::new();
+
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            let mut stopped = false;
+            while !stopped {
+                // Wait for the first span of the batch, exiting if the
+                // exporter was stopped
+                match recv.recv() {
+                    Ok(span) => batch.push(span),
+                    Err(..) => break,
+                }
+
+                // Collect the other spans ending in the meantime, to send
+                // them together
+                let deadline = Instant::now() + BATCH_TIMEOUT;
+                while batch.len() < BATCH_SIZE {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    match recv.recv_timeout(deadline - now) {
+                        Ok(span) => batch.push(span),
+                        Err(mpsc::RecvTimeoutError::Timeout) => break,
+                        // The remaining spans are sent before exiting
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            stopped = true;
+                            break;
+                        }
+                    }
+                }
+
+                // Failing to export the spans shouldn't stop the exporter
+                if let Err(err) = export(&client, &url, &service, &batch)
+                    .chain_err(|| {
+                        ErrorKind::TracingExportFailed(url.to_string())
+                    })
+                {
+                    err.pretty_print();
+                }
+                batch.clear();
+            }
+        });
+
+        *EXPORTER.lock()? = Some(send);
+
+        Ok(Some(Exporter { handle }))
+    }
+
+    /// Stop exporting the spans, returning after the recorded ones are sent.
+    pub fn stop(self) {
+        // Dropping the sender stops the thread once the spans are sent
+        if let Ok(mut exporter) = EXPORTER.lock() {
+            *exporter = None;
+        }
+        let _ = self.handle.join();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{FinishedSpan, Span, TraceContext};
+
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext::new();
+        assert_eq!(context.trace_id.len(), 32);
+        assert_eq!(context.span_id.len(), 16);
+        assert_eq!(
+            TraceContext::from_traceparent(&context.traceparent()),
+            Some(context.clone()),
+        );
+
+        let child = context.child();
+        assert_eq!(child.trace_id, context.trace_id);
+        assert_ne!(child.span_id, context.span_id);
+
+        for invalid in &[
+            "", "00-abc-def-01", "01-0af7651916cd43dd8448eb211c80319c-\
+             b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b71692033-01",
+        ] {
+            assert!(
+                TraceContext::from_traceparent(invalid).is_none(), "{}",
+                invalid,
+            );
+        }
+    }
+
+    #[test]
+    fn test_span_json() {
+        let caller = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        ).unwrap();
+
+        let mut span = Span::server("webhook", Some(caller));
+        span.started_at(10);
+        span.attribute("fisher.hook", "deploy.sh");
+        span.fail();
+        let json = FinishedSpan { span, end: 20 }.to_json();
+
+        assert_eq!(json["traceId"], "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(json["parentSpanId"], "b7ad6b7169203331");
+        assert_eq!(json["kind"], 2);
+        assert_eq!(json["startTimeUnixNano"], "10");
+        assert_eq!(json["endTimeUnixNano"], "20");
+        assert_eq!(json["status"]["code"], 2);
+        assert_eq!(json["attributes"], json!([
+            {"key": "fisher.hook", "value": {"stringValue": "deploy.sh"}},
+        ]));
+
+        // Spans starting a new trace don't have a parent
+        let span = Span::start("job", TraceContext::new(), None);
+        let json = FinishedSpan { span, end: 20 }.to_json();
+        assert!(json.get("parentSpanId").is_none());
+        assert_eq!(json["kind"], 1);
+        assert_eq!(json["status"]["code"], 1);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use serde::de::{Error as DeError, Visitor, Deserialize, Deserializer};
+
+use common::prelude::*;
+
+
+const MINUTES_IN_DAY: u32 = 24 * 60;
+
+
+fn parse_clock(input: &str) -> Option<u32> {
+    let mut split = input.trim().splitn(2, ':');
+    let hours: u32 = split.next()?.parse().ok()?;
+    let minutes: u32 = split.next()?.parse().ok()?;
+
+    if hours < 24 && minutes < 60 {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+
+/// A period of the day, in UTC, like "22:00-06:00". Windows ending before
+/// they start wrap past midnight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClockWindow {
+    start: u32,
+    end: u32,
+}
+
+impl ClockWindow {
+    /// Check if the UNIX timestamp falls inside the window.
+    pub fn contains(&self, timestamp: u64) -> bool {
+        let minute = ((timestamp / 60) % u64::from(MINUTES_IN_DAY)) as u32;
+
+        if self.start <= self.end {
+            minute >= self.start && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for ClockWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ClockWindow> {
+        let mut split = s.splitn(2, '-');
+        let start = split.next().and_then(parse_clock);
+        let end = split.next().and_then(parse_clock);
+
+        if let (Some(start), Some(end)) = (start, end) {
+            Ok(ClockWindow { start, end })
+        } else {
+            Err(ErrorKind::ClockWindowInvalid(s.into()).into())
+        }
+    }
+}
+
+struct ClockWindowVisitor;
+
+impl<'de> Visitor<'de> for ClockWindowVisitor {
+    type Value = ClockWindow;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a clock window like \"22:00-06:00\"")
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> StdResult<ClockWindow, E> {
+        s.parse().map_err(|e: Error| E::custom(e.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for ClockWindow {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> StdResult<ClockWindow, D::Error> {
+        deserializer.deserialize_str(ClockWindowVisitor)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::ClockWindow;
+
+
+    fn at(hours: u64, minutes: u64) -> u64 {
+        // Some day in the past, plus the time
+        1_500_000_000 / 86400 * 86400 + hours * 3600 + minutes * 60
+    }
+
+
+    #[test]
+    fn test_parse_clock_window() {
+        assert!("09:00-17:30".parse::<ClockWindow>().is_ok());
+        assert!("9:00 - 17:30".parse::<ClockWindow>().is_ok());
+
+        assert!("09:00".parse::<ClockWindow>().is_err());
+        assert!("24:00-01:00".parse::<ClockWindow>().is_err());
+        assert!("09:60-10:00".parse::<ClockWindow>().is_err());
+        assert!("nine-five".parse::<ClockWindow>().is_err());
+    }
+
+
+    #[test]
+    fn test_clock_window_contains() {
+        let day: ClockWindow = "09:00-17:30".parse().unwrap();
+        assert!(day.contains(at(9, 0)));
+        assert!(day.contains(at(17, 29)));
+        assert!(!day.contains(at(17, 30)));
+        assert!(!day.contains(at(8, 59)));
+
+        // Windows can wrap past midnight
+        let night: ClockWindow = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(at(23, 0)));
+        assert!(night.contains(at(0, 0)));
+        assert!(night.contains(at(5, 59)));
+        assert!(!night.contains(at(6, 0)));
+        assert!(!night.contains(at(12, 0)));
+    }
+}
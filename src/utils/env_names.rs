@@ -0,0 +1,75 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+/// Convert an arbitrary name into a valid environment variable name, by
+/// uppercasing it and replacing every unsupported character with `_`.
+pub fn env_var_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() {
+            c.to_ascii_uppercase()
+        } else {
+            '_'
+        })
+        .collect()
+}
+
+
+/// Remove the control characters from the value, except newlines and tabs.
+pub fn strip_control_chars(value: &str) -> String {
+    value.chars()
+        .filter(|&c| !c.is_control() || c == '\n' || c == '\t')
+        .collect()
+}
+
+
+/// Quote the value for POSIX shells, wrapping it in single quotes.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{env_var_name, shell_quote, strip_control_chars};
+
+
+    #[test]
+    fn test_env_var_name() {
+        assert_eq!(env_var_name("name"), "NAME");
+        assert_eq!(env_var_name("env-name"), "ENV_NAME");
+        assert_eq!(env_var_name("a_b2"), "A_B2");
+        assert_eq!(env_var_name("dotted.näme"), "DOTTED_N_ME");
+    }
+
+
+    #[test]
+    fn test_strip_control_chars() {
+        assert_eq!(strip_control_chars("plain text"), "plain text");
+        assert_eq!(strip_control_chars("multi\nline\tok"), "multi\nline\tok");
+        assert_eq!(
+            strip_control_chars("\x1b[31mred\x1b[0m\r\0"), "[31mred[0m",
+        );
+    }
+
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("simple"), "'simple'");
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
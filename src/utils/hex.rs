@@ -16,6 +16,11 @@
 use common::prelude::*;
 
 
+pub fn to_hex(input: &[u8]) -> String {
+    input.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
 pub fn from_hex(input: &str) -> Result<Vec<u8>> {
     let mut result = Vec::with_capacity(input.len() / 2);
 
@@ -56,7 +61,7 @@ pub fn from_hex(input: &str) -> Result<Vec<u8>> {
 mod tests {
     use common::prelude::*;
 
-    use super::from_hex;
+    use super::{from_hex, to_hex};
 
     #[test]
     fn test_from_hex() {
@@ -65,4 +70,11 @@ mod tests {
         assert_err!(from_hex("0"), ErrorKind::HexInvalidLength);
         assert_err!(from_hex("fg"), ErrorKind::HexInvalidChar('g'));
     }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(b""), "");
+        assert_eq!(to_hex(b"hello"), "68656c6c6f");
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+    }
 }
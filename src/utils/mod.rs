@@ -22,11 +22,40 @@ mod parse_env;
 mod net;
 mod hex;
 mod parse_time;
+mod clock;
+mod timestamps;
+mod secure;
+mod signatures;
+mod public_keys;
+mod env_names;
+mod temp_dirs;
+mod processes;
 
 
 #[cfg(test)]
 pub use utils::parse_env::parse_env;
 
-pub use utils::net::parse_forwarded_for;
-pub use utils::hex::from_hex;
+pub use utils::net::{parse_forwarded_for, Headers, IpCidr};
+pub use utils::hex::{from_hex, to_hex};
 pub use utils::parse_time::{parse_time, TimeString};
+pub use utils::clock::ClockWindow;
+pub use utils::secure::{secure_compare, Secrets};
+pub use utils::public_keys::{
+    verify_public_key_signature, PublicKey, PublicKeyAlgorithm,
+};
+pub use utils::signatures::{
+    verify_any_signature, verify_prefixed_signature, HmacAlgorithm,
+    SignatureEncoding,
+};
+pub use utils::env_names::{env_var_name, shell_quote, strip_control_chars};
+pub use utils::timestamps::{
+    common_log_date, is_fresh_timestamp, serialize_optional_timestamp,
+    serialize_timestamp, set_timestamp_format, timestamp_json, unix_now,
+    TimestampFormat,
+};
+pub use utils::temp_dirs::check_temp_dir;
+pub use utils::processes::{
+    attributed_processes, count_alive, reap_group, reap_with_usage,
+    signal_group, spawn_tracked, start_reaper, terminate_group, untrack,
+    wait_exited, ResourceUsage,
+};
@@ -14,18 +14,102 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
+use std::iter::FromIterator;
 use std::net::IpAddr;
+use std::ops::Index;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error as DeError};
+use serde::ser::{Serialize, Serializer};
 
 use common::prelude::*;
 
 
-pub type Headers = HashMap<String, String>;
+/// The headers of a request. Their names are case insensitive, since some
+/// clients and protocols send them in lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    // The entries are keyed by the lowercase name, but the original one is
+    // kept to show it back
+    entries: HashMap<String, (String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .get(&name.to_lowercase())
+            .map(|&(_, ref value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.entries.contains_key(&name.to_lowercase())
+    }
+
+    /// Set a header, replacing the value of any header with the same name.
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        self.entries
+            .insert(name.to_lowercase(), (name, value))
+            .map(|(_, value)| value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.entries
+            .remove(&name.to_lowercase())
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over the names and values of the headers, in no particular
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.values().map(|&(ref name, ref value)| (name, value))
+    }
+}
+
+impl<'a> Index<&'a str> for Headers {
+    type Output = String;
+
+    fn index(&self, name: &str) -> &String {
+        self.get(name).expect("missing header")
+    }
+}
+
+impl FromIterator<(String, String)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut headers = Headers::new();
+        for (name, value) in iter {
+            headers.insert(name, value);
+        }
+        headers
+    }
+}
+
+impl Serialize for Headers {
+    fn serialize<S: Serializer>(&self, ser: S) -> StdResult<S::Ok, S::Error> {
+        ser.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Headers {
+    fn deserialize<D>(de: D) -> StdResult<Headers, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(HashMap::<String, String>::deserialize(de)?.into_iter().collect())
+    }
+}
 
 
 pub fn parse_forwarded_for(headers: &Headers) -> Result<Vec<IpAddr>> {
     let mut result = vec![];
 
-    if let Some(header) = headers.get("X-Forwarded-For".into()) {
+    if let Some(header) = headers.get("X-Forwarded-For") {
         // Parse the header content
         let splitted: Vec<&str> = header.split(',').collect();
 
@@ -39,11 +123,97 @@ pub fn parse_forwarded_for(headers: &Headers) -> Result<Vec<IpAddr>> {
 }
 
 
+/// A range of IP addresses in the CIDR notation, like `10.0.0.0/8`. A single
+/// IP address is also accepted, and matches only itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Check if the IP address is part of this range.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, *ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = (!0u32).checked_shl(32 - self.prefix as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = (!0u128).checked_shl(128 - self.prefix as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IpCidr> {
+        let invalid = || Error::from(ErrorKind::IpCidrInvalid(s.into()));
+
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap().parse()
+            .map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = if let Some(prefix) = parts.next() {
+            prefix.parse::<u8>().map_err(|_| invalid())?
+        } else {
+            max
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(IpCidr { addr, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for IpCidr {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> StdResult<IpCidr, D::Error> {
+        let string = String::deserialize(de)?;
+        string.parse().map_err(|e: Error| D::Error::custom(e.to_string()))
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::net::IpAddr;
 
-    use super::{parse_forwarded_for, Headers};
+    use super::{parse_forwarded_for, Headers, IpCidr};
+
+
+    #[test]
+    fn test_ip_cidr() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let net: IpCidr = "192.30.252.0/22".parse().unwrap();
+        assert!(net.contains(&ip("192.30.252.1")));
+        assert!(net.contains(&ip("192.30.255.255")));
+        assert!(!net.contains(&ip("192.30.251.255")));
+        assert!(!net.contains(&ip("::1")));
+
+        let net: IpCidr = "2a0a:a440::/29".parse().unwrap();
+        assert!(net.contains(&ip("2a0a:a440::1")));
+        assert!(!net.contains(&ip("2a0a:a450::1")));
+
+        // Single addresses and catch-all ranges
+        let single: IpCidr = "127.0.0.1".parse().unwrap();
+        assert!(single.contains(&ip("127.0.0.1")));
+        assert!(!single.contains(&ip("127.0.0.2")));
+        let all: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&ip("8.8.8.8")));
+
+        for wrong in &["", "abc", "10.0.0.0/", "10.0.0.0/33", "::/129"] {
+            assert!(wrong.parse::<IpCidr>().is_err(), "{}", wrong);
+        }
+    }
 
 
     #[test]
@@ -77,5 +247,35 @@ mod tests {
         let mut headers = Headers::new();
         headers.insert("X-Forwarded-For".into(), "127.0.0.1, hey, 10.0.0.1".into());
         assert!(parse_forwarded_for(&headers).is_err());
+
+        // The name of the header is case insensitive
+        let mut headers = Headers::new();
+        headers.insert("x-forwarded-for".into(), "127.0.0.1".into());
+        assert_eq!(parse_forwarded_for(&headers).unwrap().len(), 1);
+    }
+
+
+    #[test]
+    fn test_headers() {
+        let mut headers = Headers::new();
+        assert!(headers.insert("X-GitHub-Event".into(), "a".into()).is_none());
+        assert_eq!(headers.get("x-github-event"), Some(&"a".to_string()));
+        assert_eq!(&headers["X-GITHUB-EVENT"], "a");
+        assert!(headers.contains_key("X-Github-Event"));
+
+        // The original name is kept
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![(&"X-GitHub-Event".to_string(), &"a".to_string())]
+        );
+
+        // Headers with the same name replace each other
+        assert_eq!(
+            headers.insert("x-github-event".into(), "b".into()),
+            Some("a".into())
+        );
+        assert_eq!(headers.iter().count(), 1);
+        assert_eq!(headers.remove("X-GitHub-Event"), Some("b".into()));
+        assert!(headers.get("X-GitHub-Event").is_none());
     }
 }
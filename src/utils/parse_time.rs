@@ -112,6 +112,10 @@ impl<'de> Visitor<'de> for TimeStringVisitor {
     fn visit_i64<E>(self, num: i64) -> StdResult<TimeString, E> {
         Ok(TimeString(num as u64))
     }
+
+    fn visit_u64<E>(self, num: u64) -> StdResult<TimeString, E> {
+        Ok(TimeString(num))
+    }
 }
 
 impl<'de> Deserialize<'de> for TimeString {
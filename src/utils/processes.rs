@@ -0,0 +1,596 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Helpers to manage the process groups the jobs are executed in, and the
+//! processes left behind by them.
+//!
+//! Fisher reaps the orphans it adopts: the children it waits for itself are
+//! spawned with `spawn_tracked`, so the reaper never steals their exit status.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::{getpid, Pid};
+
+use common::prelude::*;
+
+
+/// How often the orphaned processes are reaped.
+const REAPER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for the killed processes of a group to be adopted by
+/// Fisher, before leaving them to their parents.
+const REAP_GROUP_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+lazy_static! {
+    static ref TRACKED: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
+}
+
+static START_REAPER: Once = Once::new();
+
+
+/// A process attributed to Fisher.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub ppid: i32,
+    pub pgid: i32,
+    pub state: char,
+    pub command: String,
+    /// Whether the process doesn't belong to anything Fisher is waiting for,
+    /// like a running job.
+    pub orphan: bool,
+}
+
+
+/// The resources used by a job.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The peak resident set size, in kilobytes.
+    pub max_rss: u64,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub wall_time: Duration,
+}
+
+impl ResourceUsage {
+    /// The time spent both in user mode and in kernel mode.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+
+#[derive(Debug)]
+struct ProcStat {
+    pid: i32,
+    ppid: i32,
+    pgid: i32,
+    state: char,
+    name: String,
+}
+
+
+fn tracked() -> MutexGuard<'static, HashSet<i32>> {
+    TRACKED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+
+/// Spawn a child whose exit status is collected by the caller, without the
+/// reaper interfering. The child must be untracked after it's reaped.
+pub fn spawn_tracked(command: &mut Command) -> Result<Child> {
+    // The lock is held while spawning, so the reaper can't see the child
+    // before it's tracked
+    let mut tracked = tracked();
+    let child = command.spawn()?;
+    tracked.insert(child.id() as i32);
+    Ok(child)
+}
+
+
+/// Stop tracking a child spawned with `spawn_tracked`.
+pub fn untrack(pid: Pid) {
+    tracked().remove(&pid.as_raw());
+}
+
+
+/// Start reaping the orphaned processes adopted by Fisher in the background.
+/// On Linux Fisher also becomes a subreaper, so the orphans of the jobs are
+/// adopted by it instead of init. This can be called multiple times.
+pub fn start_reaper() {
+    START_REAPER.call_once(|| {
+        if let Err(err) = become_subreaper() {
+            err.pretty_print();
+        }
+
+        thread::spawn(|| loop {
+            thread::sleep(REAPER_INTERVAL);
+            if let Err(err) = reap_orphans() {
+                err.pretty_print();
+            }
+        });
+    });
+}
+
+
+#[cfg(target_os = "linux")]
+fn become_subreaper() -> Result<()> {
+    let result = unsafe {
+        libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0)
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn become_subreaper() -> Result<()> {
+    Ok(())
+}
+
+
+/// Reap the exited children of Fisher nobody else is waiting for, returning
+/// how many of them were reaped.
+fn reap_orphans() -> Result<usize> {
+    let tracked = tracked();
+    let me = getpid().as_raw();
+
+    let mut reaped = 0;
+    for process in processes() {
+        if process.ppid != me || process.state != 'Z' {
+            continue;
+        }
+
+        // Jobs reap their own process groups
+        if tracked.contains(&process.pid) || tracked.contains(&process.pgid) {
+            continue;
+        }
+
+        match waitpid(Pid::from_raw(process.pid), Some(WaitPidFlag::WNOHANG)) {
+            Ok(..) => reaped += 1,
+            Err(::nix::Error::Sys(Errno::ECHILD)) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(reaped)
+}
+
+
+/// Reap the processes of a killed group which were adopted by Fisher, except
+/// for the group leader.
+pub fn reap_group(pgid: Pid) -> Result<()> {
+    let me = getpid().as_raw();
+    let deadline = Instant::now() + REAP_GROUP_TIMEOUT;
+
+    loop {
+        let members = processes()
+            .into_iter()
+            .filter(|p| p.pgid == pgid.as_raw() && p.pid != pgid.as_raw())
+            .collect::<Vec<_>>();
+
+        // Dying processes are adopted only after their parents exit
+        let adopted = members.iter().filter(|p| p.ppid == me).count();
+        if members.is_empty()
+            || (adopted == 0 && Instant::now() >= deadline)
+        {
+            return Ok(());
+        }
+
+        for process in members.iter().filter(|p| p.ppid == me) {
+            match waitpid(Pid::from_raw(process.pid), None) {
+                Ok(..) | Err(::nix::Error::Sys(Errno::ECHILD)) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if adopted == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+
+/// Count the processes of a group which are still alive, ignoring the ones
+/// which exited and are waiting to be reaped.
+pub fn count_alive(pgid: Pid) -> usize {
+    processes()
+        .iter()
+        .filter(|p| p.pgid == pgid.as_raw() && p.state != 'Z')
+        .count()
+}
+
+
+/// List the processes attributed to Fisher, except Fisher itself: the ones
+/// sharing a cgroup with it, and its descendants.
+pub fn attributed_processes() -> Vec<ProcessInfo> {
+    let me = getpid().as_raw();
+    let own_cgroups = cgroups_of("self");
+
+    let all = processes();
+    let mut descendants = HashSet::new();
+    descendants.insert(me);
+    // Parents can have bigger PIDs than their children, so the tree is
+    // walked until there is nothing new to add
+    loop {
+        let before = descendants.len();
+        for process in &all {
+            if descendants.contains(&process.ppid) {
+                descendants.insert(process.pid);
+            }
+        }
+        if descendants.len() == before {
+            break;
+        }
+    }
+
+    let tracked = tracked();
+    all.into_iter()
+        .filter(|p| p.pid != me)
+        .filter(|p| {
+            descendants.contains(&p.pid) || !own_cgroups.is_disjoint(
+                &cgroups_of(&p.pid.to_string()),
+            )
+        })
+        .map(|p| ProcessInfo {
+            orphan: !tracked.contains(&p.pid) && !tracked.contains(&p.pgid),
+            command: command_line(p.pid).unwrap_or_else(|| p.name.clone()),
+            pid: p.pid,
+            ppid: p.ppid,
+            pgid: p.pgid,
+            state: p.state,
+        })
+        .collect()
+}
+
+
+/// Return the cgroups a process is in, except the root ones which contain
+/// every process.
+fn cgroups_of(pid: &str) -> HashSet<String> {
+    let content = match fs::read_to_string(format!("/proc/{}/cgroup", pid)) {
+        Ok(content) => content,
+        Err(..) => return HashSet::new(),
+    };
+
+    content
+        .lines()
+        .filter(|line| !line.ends_with(":/"))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+
+fn command_line(pid: i32) -> Option<String> {
+    let content = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args = content
+        .split(|c| *c == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<_>>();
+
+    // Zombies and kernel threads don't have a command line
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.join(" "))
+    }
+}
+
+
+/// List all the processes on the system. Processes exiting while the list is
+/// built are skipped, and an empty list is returned without /proc.
+fn processes() -> Vec<ProcStat> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(..) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(read_stat)
+        .collect()
+}
+
+
+fn read_stat(pid: i32) -> Option<ProcStat> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat(pid, &content)
+}
+
+
+/// Parse the content of `/proc/PID/stat`. The process name is between
+/// parenthesis and can contain anything, so the fields after it are found
+/// from the last closing parenthesis.
+fn parse_stat(pid: i32, content: &str) -> Option<ProcStat> {
+    let start = content.find('(')?;
+    let end = content.rfind(')')?;
+    let name = content.get(start + 1..end)?.to_string();
+
+    let mut fields = content.get(end + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let pgid = fields.next()?.parse().ok()?;
+
+    Some(ProcStat { pid, ppid, pgid, state, name })
+}
+
+
+/// Wait for a child process to exit, without reaping it. Until the child is
+/// reaped its PID can't be reused, so it's safe to signal its process group.
+pub fn wait_exited(pid: Pid) -> Result<()> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID, pid.as_raw() as libc::id_t, &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+
+        if result == 0 {
+            return Ok(());
+        } else if Errno::last() != Errno::EINTR {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+}
+
+
+/// Reap a child process, returning its exit status and the resources used by
+/// it and by the children it waited for. The wall time is not measured.
+pub fn reap_with_usage(pid: Pid) -> Result<(ExitStatus, ResourceUsage)> {
+    loop {
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::wait4(pid.as_raw(), &mut status, 0, &mut usage)
+        };
+
+        if result == pid.as_raw() {
+            let timeval = |tv: libc::timeval| Duration::new(
+                tv.tv_sec as u64, tv.tv_usec as u32 * 1000,
+            );
+
+            return Ok((ExitStatus::from_raw(status), ResourceUsage {
+                max_rss: max_rss_kb(usage.ru_maxrss as u64),
+                user_time: timeval(usage.ru_utime),
+                system_time: timeval(usage.ru_stime),
+                wall_time: Duration::from_secs(0),
+            }));
+        } else if Errno::last() != Errno::EINTR {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+}
+
+
+/// macOS reports the maximum resident set size in bytes instead of kilobytes.
+#[cfg(target_os = "macos")]
+fn max_rss_kb(value: u64) -> u64 {
+    value / 1024
+}
+
+#[cfg(not(target_os = "macos"))]
+fn max_rss_kb(value: u64) -> u64 {
+    value
+}
+
+
+/// Send a signal to every process in a process group. Returns false if the
+/// group doesn't exist anymore.
+pub fn signal_group(pgid: Pid, signal: Signal) -> Result<bool> {
+    match kill(Pid::from_raw(-pgid.as_raw()), signal) {
+        Ok(()) => Ok(true),
+        Err(::nix::Error::Sys(Errno::ESRCH)) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+
+/// Terminate a process group, killing it if it's still alive after the grace
+/// period. The group isn't killed if the cancel channel is closed during the
+/// grace period, while messages sent to it are ignored. Returns how many
+/// processes had to be killed.
+pub fn terminate_group(
+    pgid: Pid, grace: Duration, cancel: &Receiver<()>,
+) -> Result<usize> {
+    if !signal_group(pgid, Signal::SIGTERM)? {
+        return Ok(0);
+    }
+
+    let deadline = Instant::now() + grace;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match cancel.recv_timeout(left) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return Ok(0),
+        }
+    }
+
+    let alive = count_alive(pgid);
+    signal_group(pgid, Signal::SIGKILL)?;
+    Ok(alive)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use nix::sys::signal::Signal;
+    use nix::unistd::{setpgid, Pid};
+
+    use super::{
+        attributed_processes, become_subreaper, parse_stat, reap_group,
+        reap_with_usage, signal_group, spawn_tracked, terminate_group,
+        untrack, wait_exited,
+    };
+
+
+    fn spawn_group(script: &str) -> (Child, Pid) {
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(script).stdout(Stdio::piped());
+        // setpgid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                let _ = setpgid(Pid::this(), Pid::from_raw(0));
+                Ok(())
+            });
+        }
+        let child = spawn_tracked(&mut command).unwrap();
+
+        let pid = Pid::from_raw(child.id() as i32);
+        (child, pid)
+    }
+
+    fn read_line(child: &mut Child) -> String {
+        let mut line = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        line.trim().to_string()
+    }
+
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = parse_stat(42, "42 (a (weird) name) S 1 42 42 0").unwrap();
+        assert_eq!(stat.name, "a (weird) name");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.ppid, 1);
+        assert_eq!(stat.pgid, 42);
+
+        assert!(parse_stat(42, "42 (name) S").is_none());
+        assert!(parse_stat(42, "").is_none());
+    }
+
+    #[test]
+    fn test_signal_group() {
+        // The grandchild is left running after the shell exits
+        let (mut child, pgid) = spawn_group("sleep 30 & exit 0");
+        wait_exited(pgid).unwrap();
+
+        assert!(signal_group(pgid, Signal::SIGKILL).unwrap());
+        child.wait().unwrap();
+        untrack(pgid);
+    }
+
+    #[test]
+    fn test_terminate_group() {
+        // Processes ignoring SIGTERM are killed after the grace period
+        let (mut child, pgid) = spawn_group(
+            "trap '' TERM; echo ready; sleep 30",
+        );
+        let (cancel_send, cancel_recv) = mpsc::channel();
+
+        // Wait for the trap to be installed
+        read_line(&mut child);
+
+        // Messages sent to the cancel channel don't stop the termination
+        cancel_send.send(()).unwrap();
+
+        let start = Instant::now();
+        let grace = Duration::from_millis(100);
+        let killed = terminate_group(pgid, grace, &cancel_recv).unwrap();
+
+        let status = child.wait().unwrap();
+        untrack(pgid);
+        assert_eq!(status.signal(), Some(Signal::SIGKILL as i32));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(killed >= 1);
+    }
+
+    #[test]
+    fn test_reap_with_usage() {
+        // Burn some CPU time and memory
+        let (_child, pid) = spawn_group(
+            "x=$(head -c 5000000 /dev/zero | tr '\\0' a); \
+             for i in $(seq 20000); do :; done; exit 3",
+        );
+
+        let (status, usage) = reap_with_usage(pid).unwrap();
+        untrack(pid);
+        assert_eq!(status.code(), Some(3));
+        assert!(usage.max_rss >= 5000);
+        assert!(usage.cpu_time() > Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_reap_group() {
+        become_subreaper().unwrap();
+
+        // The grandchild is adopted by the test process once killed
+        let (mut child, pgid) = spawn_group("sleep 30 & echo $!; exit 0");
+        let grandchild = read_line(&mut child);
+        wait_exited(pgid).unwrap();
+
+        signal_group(pgid, Signal::SIGKILL).unwrap();
+        reap_group(pgid).unwrap();
+        assert!(!Path::new(&format!("/proc/{}", grandchild)).exists());
+
+        child.wait().unwrap();
+        untrack(pgid);
+    }
+
+    #[test]
+    fn test_attributed_processes() {
+        // Keep bash around instead of letting it exec the last command, as
+        // the command line can't be read while the exec is in progress
+        let (mut child, pgid) = spawn_group("echo ready; sleep 30; exit 0");
+        read_line(&mut child);
+
+        // Tracked processes are not orphans
+        let found = attributed_processes()
+            .into_iter()
+            .find(|p| p.pid == pgid.as_raw())
+            .unwrap();
+        assert!(!found.orphan);
+        assert!(found.command.contains("sleep 30"));
+
+        untrack(pgid);
+        let found = attributed_processes()
+            .into_iter()
+            .find(|p| p.pid == pgid.as_raw())
+            .unwrap();
+        assert!(found.orphan);
+
+        signal_group(pgid, Signal::SIGKILL).unwrap();
+        child.wait().unwrap();
+        reap_group(pgid).unwrap();
+    }
+}
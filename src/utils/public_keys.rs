@@ -0,0 +1,430 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Public-key signatures verification shared by the providers.
+//!
+//! Some services sign their webhooks with a private key instead of a shared
+//! secret: Discord uses Ed25519, while Travis CI uses RSA. Public keys can be
+//! loaded from hex strings (Ed25519 only), PEM documents or JSON Web Key Sets.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use base64;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_json::{self, Value};
+
+use common::prelude::*;
+use utils::from_hex;
+
+
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_OID: u8 = 0x06;
+const DER_SEQUENCE: u8 = 0x30;
+
+
+/// The algorithm used to create a public-key signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PublicKeyAlgorithm {
+    Ed25519,
+    /// RSA PKCS#1 v1.5 with SHA-1, only for legacy services
+    RsaSha1,
+    /// RSA PKCS#1 v1.5 with SHA-256
+    RsaSha256,
+    /// RSA PKCS#1 v1.5 with SHA-512
+    RsaSha512,
+}
+
+
+/// A public key used to verify signatures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKey {
+    Ed25519(Vec<u8>),
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl PublicKey {
+    /// Parse a public key, either in PEM format or as an hex-encoded Ed25519
+    /// key.
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if input.starts_with("-----BEGIN") {
+            PublicKey::from_pem(input)
+        } else {
+            let key = from_hex(input)?;
+            if key.len() != 32 {
+                return Err(ErrorKind::PublicKeyInvalid.into());
+            }
+            Ok(PublicKey::Ed25519(key))
+        }
+    }
+
+    /// Parse a PEM-encoded SubjectPublicKeyInfo, containing either an
+    /// Ed25519 or an RSA public key.
+    pub fn from_pem(input: &str) -> Result<Self> {
+        let body = input
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.starts_with("-----"))
+            .collect::<String>();
+        let der = base64::decode(&body)
+            .map_err(|_| Error::from(ErrorKind::PublicKeyInvalid))?;
+
+        parse_spki(&der).ok_or_else(|| ErrorKind::PublicKeyInvalid.into())
+    }
+
+    /// Parse a single JSON Web Key.
+    pub fn from_jwk(jwk: &Value) -> Result<Self> {
+        let field = |name: &str| -> Result<Vec<u8>> {
+            let encoded = jwk.get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| Error::from(ErrorKind::PublicKeyInvalid))?;
+            base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
+                .map_err(|_| ErrorKind::PublicKeyInvalid.into())
+        };
+
+        match (jwk.get("kty").and_then(|v| v.as_str()), jwk.get("crv")) {
+            (Some("OKP"), Some(&Value::String(ref crv))) if crv == "Ed25519" => {
+                Ok(PublicKey::Ed25519(field("x")?))
+            }
+            (Some("RSA"), _) => Ok(PublicKey::Rsa {
+                n: strip_leading_zeroes(&field("n")?).to_vec(),
+                e: strip_leading_zeroes(&field("e")?).to_vec(),
+            }),
+            _ => Err(ErrorKind::PublicKeyUnsupported.into()),
+        }
+    }
+
+    /// Parse all the supported keys in a JSON Web Key Set. Unsupported keys
+    /// are ignored.
+    pub fn from_jwks(input: &str) -> Result<Vec<Self>> {
+        let jwks: Value = serde_json::from_str(input)?;
+        let keys = jwks.get("keys")
+            .and_then(|keys| keys.as_array())
+            .ok_or_else(|| Error::from(ErrorKind::PublicKeyInvalid))?;
+
+        let mut result = Vec::new();
+        for key in keys {
+            match PublicKey::from_jwk(key) {
+                Ok(key) => result.push(key),
+                Err(Error(ErrorKind::PublicKeyUnsupported, _)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Load the public keys contained in a file, either a PEM document, an
+    /// hex-encoded Ed25519 key or a JSON Web Key Set.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+        let mut content = String::new();
+        fs::File::open(&path)?.read_to_string(&mut content)?;
+
+        if content.trim_start().starts_with('{') {
+            PublicKey::from_jwks(&content)
+        } else {
+            Ok(vec![PublicKey::parse(&content)?])
+        }
+    }
+
+    /// Verify the signature of the message with this key.
+    pub fn verify(
+        &self,
+        algorithm: PublicKeyAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        let rsa_params = match algorithm {
+            PublicKeyAlgorithm::Ed25519 => {
+                return if let PublicKey::Ed25519(ref key) = *self {
+                    UnparsedPublicKey::new(&signature::ED25519, key)
+                        .verify(message, signature)
+                        .is_ok()
+                } else {
+                    false
+                };
+            }
+            PublicKeyAlgorithm::RsaSha1 => {
+                &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY
+            }
+            PublicKeyAlgorithm::RsaSha256 => {
+                &signature::RSA_PKCS1_2048_8192_SHA256
+            }
+            PublicKeyAlgorithm::RsaSha512 => {
+                &signature::RSA_PKCS1_2048_8192_SHA512
+            }
+        };
+
+        if let PublicKey::Rsa { ref n, ref e } = *self {
+            RsaPublicKeyComponents { n, e }
+                .verify(rsa_params, message, signature)
+                .is_ok()
+        } else {
+            false
+        }
+    }
+}
+
+
+/// Verify the signature with any of the provided keys.
+pub fn verify_public_key_signature(
+    keys: &[PublicKey],
+    algorithm: PublicKeyAlgorithm,
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    keys.iter().any(|key| key.verify(algorithm, message, signature))
+}
+
+
+fn strip_leading_zeroes(input: &[u8]) -> &[u8] {
+    let start = input.iter().position(|b| *b != 0).unwrap_or(input.len());
+    &input[start..]
+}
+
+
+/// Read a DER element, returning its tag, its content and the rest of the
+/// input. Only the lengths used by public keys are supported.
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *input.get(0)?;
+    let first = *input.get(1)? as usize;
+
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let bytes = first & 0x7f;
+        if bytes == 0 || bytes > 3 {
+            return None;
+        }
+        let mut len = 0;
+        for i in 0..bytes {
+            len = (len << 8) | *input.get(2 + i)? as usize;
+        }
+        (len, 2 + bytes)
+    };
+
+    if input.len() < header + len {
+        return None;
+    }
+    Some((tag, &input[header..header + len], &input[header + len..]))
+}
+
+
+fn parse_spki(der: &[u8]) -> Option<PublicKey> {
+    let (tag, spki, _) = der_next(der)?;
+    if tag != DER_SEQUENCE {
+        return None;
+    }
+
+    let (tag, algorithm, rest) = der_next(spki)?;
+    if tag != DER_SEQUENCE {
+        return None;
+    }
+    let (tag, oid, _) = der_next(algorithm)?;
+    if tag != DER_OID {
+        return None;
+    }
+
+    let (tag, bits, _) = der_next(rest)?;
+    if tag != DER_BIT_STRING || bits.get(0) != Some(&0) {
+        return None;
+    }
+    let key = &bits[1..];
+
+    if oid == OID_ED25519 {
+        if key.len() == 32 {
+            Some(PublicKey::Ed25519(key.to_vec()))
+        } else {
+            None
+        }
+    } else if oid == OID_RSA {
+        // The key is a PKCS#1 RSAPublicKey
+        let (tag, rsa, _) = der_next(key)?;
+        if tag != DER_SEQUENCE {
+            return None;
+        }
+        let (tag, n, rest) = der_next(rsa)?;
+        if tag != DER_INTEGER {
+            return None;
+        }
+        let (tag, e, _) = der_next(rest)?;
+        if tag != DER_INTEGER {
+            return None;
+        }
+
+        Some(PublicKey::Rsa {
+            n: strip_leading_zeroes(n).to_vec(),
+            e: strip_leading_zeroes(e).to_vec(),
+        })
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use base64;
+    use tempdir::TempDir;
+
+    use super::{verify_public_key_signature, PublicKey, PublicKeyAlgorithm};
+
+
+    const ED25519_HEX: &str =
+        "b3055cde28b14f85ff038da8cbd713076d89a31947eb257bc5c5592db38dc419";
+    const ED25519_PEM: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAswVc3iixT4X/A42oy9cTB22JoxlH6yV7xcVZLbONxBk=
+-----END PUBLIC KEY-----";
+    const ED25519_SIGNATURE: &str = concat!(
+        "jQJTM5J5HrMfpewDjuhWwwiAdPwDNJ+JTk6MC5gAlGRVese8fW0kmsnnn5IokYRIT2eV",
+        "/YOHg1AC90mfYOdOCg==",
+    );
+
+    const RSA_PEM: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA2DLHIJYZRaZs/f8oRVJD
+qkusA4WqBvOgCzWq4YO4oC1dOkByNusLQH0exWFR/JBMX1zMZCmMjsLeUIHnmaoS
+iQXXlH0yDeThhSBbLtbHqEZSmiSWxrhv5+A5khX6f9azMQwgVZ8ZJn7cyufTPdiM
+bWWjqPUMvgzu8gr0PgBapq06QHc/sviS0zAXYSIMr4ydi0ExEyebV67KnjOR+NnJ
+Bhe1qaW/QmKQyPtuKXBidzc//OHKmNpjd0R+++XGHLKBf1ARs2kY1peChLLmrWrk
+fT/zZEjV/cYbEi5U3pYUkxEASi5RlgoNzyxZ68wPoMDi9D48y8Ewp7gqVptPSU0a
+oQIDAQAB
+-----END PUBLIC KEY-----";
+    const RSA_N: &str = concat!(
+        "2DLHIJYZRaZs_f8oRVJDqkusA4WqBvOgCzWq4YO4oC1dOkByNusLQH0exWFR_JBMX1zM",
+        "ZCmMjsLeUIHnmaoSiQXXlH0yDeThhSBbLtbHqEZSmiSWxrhv5-A5khX6f9azMQwgVZ8Z",
+        "Jn7cyufTPdiMbWWjqPUMvgzu8gr0PgBapq06QHc_sviS0zAXYSIMr4ydi0ExEyebV67K",
+        "njOR-NnJBhe1qaW_QmKQyPtuKXBidzc__OHKmNpjd0R---XGHLKBf1ARs2kY1peChLLm",
+        "rWrkfT_zZEjV_cYbEi5U3pYUkxEASi5RlgoNzyxZ68wPoMDi9D48y8Ewp7gqVptPSU0a",
+        "oQ",
+    );
+    const RSA_SHA1_SIGNATURE: &str = concat!(
+        "y2pqQ/TTXdHN9FbeFYOhT+9RVSvvw6uqhg0H4yzxkpdZyypHS6SMLQ4nD3OqYYWZB1hx",
+        "uHjXeKi+xRlqqLytA8whFD9ecbu/9rKq7MHND8m52WBfvCR/bzO7y9HeE8LufO6o42dj",
+        "nQ0x87j802IedHT7/ltjn/nJgZbSvvA+BBZN8zdUe4Fc5DC+xgzJ8q1Faf+poaMm177+",
+        "zv0qk5sU///T6hU2SAWWFtukqhm9y0YVD2MLoDQFsmrLnBh8GernfOiBi3AlFCuSJtKf",
+        "p2Mg87T7h2rUUKbA1geaWSLuuiJAhCgtK6eamNsSwuedcF3Mjx1OklfAi7SXDvbhEOak",
+        "zA==",
+    );
+    const RSA_SHA256_SIGNATURE: &str = concat!(
+        "nV9YLn9DCIw2nyiB6k15UtN8MToC6AfG+Mo0jituLcMXad7uFC4qgBUriZa+qenrYWSw",
+        "HmkJV5iVt5ddi2f3Ox1S8X4Cf39SRrstc/He592Io6Kkk/XAZshZKV96pYb3lkQofcm5",
+        "Yos+CVvqMYwWJIqm2/ZiTFlQONiNs80yBWyVQ2GpjM7oTeU4oiCjURNj/yHKIhXpKKCL",
+        "5l5sye9JapNLNqsXXKA4B4cwTTQo1ej6NTPtnI5Dj/88cvHysr9nN1xx41H6A9pA2ti9",
+        "d/s7BAJyOAZI+w5EcEeMuzK8Y50nUFlBIlRxGeO/eYXHjn/Fa/XFdBEyxmbsvm3jQbQS",
+        "Ag==",
+    );
+
+
+    fn sig(encoded: &str) -> Vec<u8> {
+        base64::decode(encoded).unwrap()
+    }
+
+
+    #[test]
+    fn test_parse() {
+        let from_hex = PublicKey::parse(ED25519_HEX).unwrap();
+        let from_pem = PublicKey::parse(ED25519_PEM).unwrap();
+        assert_eq!(from_hex, from_pem);
+
+        if let PublicKey::Rsa { ref e, .. } = PublicKey::parse(RSA_PEM).unwrap() {
+            assert_eq!(e, &vec![1, 0, 1]);
+        } else {
+            panic!("the RSA key wasn't detected");
+        }
+
+        for invalid in &["", "abcd", "-----BEGIN PUBLIC KEY-----\nabcd"] {
+            assert!(PublicKey::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_ed25519() {
+        use self::PublicKeyAlgorithm::*;
+
+        let key = PublicKey::parse(ED25519_HEX).unwrap();
+        let signature = sig(ED25519_SIGNATURE);
+
+        assert!(key.verify(Ed25519, b"payload", &signature));
+        assert!(!key.verify(Ed25519, b"wrong", &signature));
+        assert!(!key.verify(RsaSha256, b"payload", &signature));
+    }
+
+    #[test]
+    fn test_rsa() {
+        use self::PublicKeyAlgorithm::*;
+
+        let key = PublicKey::parse(RSA_PEM).unwrap();
+        let sha1 = sig(RSA_SHA1_SIGNATURE);
+        let sha256 = sig(RSA_SHA256_SIGNATURE);
+
+        assert!(key.verify(RsaSha1, b"payload", &sha1));
+        assert!(key.verify(RsaSha256, b"payload", &sha256));
+        assert!(!key.verify(RsaSha256, b"payload", &sha1));
+        assert!(!key.verify(RsaSha256, b"wrong", &sha256));
+        assert!(!key.verify(Ed25519, b"payload", &sha256));
+    }
+
+    #[test]
+    fn test_jwks() {
+        let jwks = json!({"keys": [
+            {"kty": "OKP", "crv": "Ed25519",
+             "x": "swVc3iixT4X_A42oy9cTB22JoxlH6yV7xcVZLbONxBk"},
+            {"kty": "RSA", "n": RSA_N, "e": "AQAB"},
+            {"kty": "EC", "crv": "P-256", "x": "", "y": ""},
+        ]}).to_string();
+
+        let keys = PublicKey::from_jwks(&jwks).unwrap();
+        assert_eq!(keys, vec![
+            PublicKey::parse(ED25519_HEX).unwrap(),
+            PublicKey::parse(RSA_PEM).unwrap(),
+        ]);
+
+        assert!(verify_public_key_signature(
+            &keys,
+            PublicKeyAlgorithm::RsaSha256,
+            b"payload",
+            &sig(RSA_SHA256_SIGNATURE),
+        ));
+
+        assert!(PublicKey::from_jwks("{}").is_err());
+    }
+
+    #[test]
+    fn test_load_file() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+
+        let pem = dir.path().join("key.pem");
+        write!(fs::File::create(&pem).unwrap(), "{}\n", RSA_PEM).unwrap();
+        assert_eq!(
+            PublicKey::load_file(&pem).unwrap(),
+            vec![PublicKey::parse(RSA_PEM).unwrap()]
+        );
+
+        let jwks = dir.path().join("keys.json");
+        write!(fs::File::create(&jwks).unwrap(), r#"{{"keys": []}}"#).unwrap();
+        assert_eq!(PublicKey::load_file(&jwks).unwrap(), vec![]);
+
+        assert!(PublicKey::load_file(dir.path().join("missing")).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::de::{Deserialize, Deserializer, Error as DeError};
+
+
+/// Compare two secrets in constant time.
+///
+/// The time this function takes only depends on the length of the inputs,
+/// not on their content, so it can't be used to guess a secret one byte at a
+/// time. Use this instead of `==` every time a secret is checked.
+pub fn secure_compare<A: AsRef<[u8]>, B: AsRef<[u8]>>(a: A, b: B) -> bool {
+    let a = a.as_ref();
+    let b = b.as_ref();
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut result = 0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        result |= x ^ y;
+    }
+
+    result == 0
+}
+
+
+/// One or more secrets accepted by a provider, deserialized from either a
+/// single string or a list of strings. Multiple secrets allow rotating them
+/// without rejecting any request in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Secrets(Vec<String>);
+
+impl Secrets {
+    /// Check if the token matches one of the secrets, in constant time.
+    pub fn matches<T: AsRef<[u8]>>(&self, token: T) -> bool {
+        // Don't stop at the first match to avoid leaking which secret was
+        // used through timing
+        let mut valid = false;
+        for secret in &self.0 {
+            valid |= secure_compare(secret, token.as_ref());
+        }
+        valid
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for Secrets {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Single(String),
+            Multiple(Vec<String>),
+        }
+
+        match Repr::deserialize(de)? {
+            Repr::Single(secret) => Ok(Secrets(vec![secret])),
+            Repr::Multiple(ref secrets) if secrets.is_empty() => {
+                Err(<D::Error as DeError>::custom("at least one secret is required"))
+            }
+            Repr::Multiple(secrets) => Ok(Secrets(secrets)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::{secure_compare, Secrets};
+
+    #[test]
+    fn test_secure_compare() {
+        assert!(secure_compare("", ""));
+        assert!(secure_compare("abcde", "abcde"));
+        assert!(secure_compare(b"abcde", "abcde"));
+
+        assert!(!secure_compare("abcde", "abcdf"));
+        assert!(!secure_compare("abcde", "bbcde"));
+        assert!(!secure_compare("abcde", "abcd"));
+        assert!(!secure_compare("", "a"));
+    }
+
+
+    #[test]
+    fn test_secrets() {
+        let single: Secrets = serde_json::from_str(r#""abcde""#).unwrap();
+        assert!(single.matches("abcde"));
+        assert!(!single.matches("fghij"));
+
+        let multiple: Secrets = serde_json::from_str(
+            r#"["abcde", "fghij"]"#
+        ).unwrap();
+        assert!(multiple.matches("abcde"));
+        assert!(multiple.matches(b"fghij"));
+        assert!(!multiple.matches("klmno"));
+        assert_eq!(multiple.as_slice(), &["abcde", "fghij"]);
+
+        for wrong in &["[]", "12345", "[12345]", "null"] {
+            assert!(serde_json::from_str::<Secrets>(wrong).is_err(), "{}", wrong);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HMAC signatures verification shared by the providers.
+
+use std::io::{self, Read};
+
+use base64;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use utils::{from_hex, secure_compare};
+
+
+/// The hash function used by an HMAC signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HmacAlgorithm {
+    /// Get the algorithm from its name, as used in prefixed signatures.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha1" => Some(HmacAlgorithm::Sha1),
+            "sha256" => Some(HmacAlgorithm::Sha256),
+            "sha512" => Some(HmacAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Calculate the signature of the payload.
+    pub fn sign(&self, secret: &[u8], payload: &[u8]) -> Vec<u8> {
+        macro_rules! sign {
+            ($hash:ty) => {{
+                let mut mac = Hmac::<$hash>::new_varkey(secret).unwrap();
+                mac.input(payload);
+                mac.result().code().to_vec()
+            }};
+        }
+
+        match *self {
+            HmacAlgorithm::Sha1 => sign!(Sha1),
+            HmacAlgorithm::Sha256 => sign!(Sha256),
+            HmacAlgorithm::Sha512 => sign!(Sha512),
+        }
+    }
+
+    /// Calculate the signature of the payload read from `reader`, without
+    /// loading all of it in memory.
+    pub fn sign_reader<R: Read>(
+        &self, secret: &[u8], mut reader: R,
+    ) -> io::Result<Vec<u8>> {
+        macro_rules! sign {
+            ($hash:ty) => {{
+                let mut mac = Hmac::<$hash>::new_varkey(secret).unwrap();
+                let mut buffer = [0; 8192];
+                loop {
+                    let read = reader.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    mac.input(&buffer[..read]);
+                }
+                mac.result().code().to_vec()
+            }};
+        }
+
+        Ok(match *self {
+            HmacAlgorithm::Sha1 => sign!(Sha1),
+            HmacAlgorithm::Sha256 => sign!(Sha256),
+            HmacAlgorithm::Sha512 => sign!(Sha512),
+        })
+    }
+}
+
+
+/// The encoding used to represent a signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    Hex,
+    Base64,
+}
+
+impl SignatureEncoding {
+    fn decode(&self, input: &str) -> Option<Vec<u8>> {
+        match *self {
+            SignatureEncoding::Hex => from_hex(input).ok(),
+            SignatureEncoding::Base64 => base64::decode(input).ok(),
+        }
+    }
+}
+
+
+/// Verify an encoded HMAC signature of the payload read from `reader`,
+/// without loading all of it in memory.
+pub fn verify_signature<R: Read>(
+    algorithm: HmacAlgorithm,
+    encoding: SignatureEncoding,
+    secret: &[u8],
+    reader: R,
+    signature: &str,
+) -> io::Result<bool> {
+    if let Some(decoded) = encoding.decode(signature.trim()) {
+        let signed = algorithm.sign_reader(secret, reader)?;
+        Ok(secure_compare(signed, decoded))
+    } else {
+        Ok(false)
+    }
+}
+
+
+/// Split a signature prefixed by the name of its algorithm, if the algorithm
+/// is one of the allowed ones.
+fn split_prefixed<'a>(
+    allowed: &[HmacAlgorithm], signature: &'a str,
+) -> Option<(HmacAlgorithm, &'a str)> {
+    let mut parts = signature.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    let signature = parts.next()?;
+
+    match HmacAlgorithm::from_name(name) {
+        Some(algorithm) if allowed.contains(&algorithm) => {
+            Some((algorithm, signature))
+        }
+        _ => None,
+    }
+}
+
+
+/// Verify a signature prefixed by the name of its algorithm, such as
+/// `sha256=abcdef`. Only the algorithms in `allowed` are accepted. The
+/// payload is read from `reader`, without loading all of it in memory.
+pub fn verify_prefixed_signature<R: Read>(
+    allowed: &[HmacAlgorithm],
+    encoding: SignatureEncoding,
+    secret: &[u8],
+    reader: R,
+    signature: &str,
+) -> io::Result<bool> {
+    match split_prefixed(allowed, signature) {
+        Some((algorithm, signature)) => verify_signature(
+            algorithm, encoding, secret, reader, signature,
+        ),
+        None => Ok(false),
+    }
+}
+
+
+/// Verify a list of signatures, accepting the payload if at least one of
+/// them is valid for at least one of the secrets. This is useful when the
+/// sender includes multiple signatures, or while rotating the secret. The
+/// payload is read again for every check from the reader returned by
+/// `payload`, and it's rejected if it can't be read.
+pub fn verify_any_signature<'a, S, I, R, F>(
+    algorithm: HmacAlgorithm,
+    encoding: SignatureEncoding,
+    secrets: &[S],
+    payload: F,
+    signatures: I,
+) -> bool
+where
+    S: AsRef<[u8]>,
+    I: IntoIterator<Item = &'a str>,
+    R: Read,
+    F: Fn() -> Option<R>,
+{
+    // Don't stop at the first valid signature to avoid leaking which one
+    // was valid through timing
+    let mut valid = false;
+    for signature in signatures {
+        for secret in secrets {
+            valid |= payload()
+                .and_then(|reader| {
+                    verify_signature(
+                        algorithm, encoding, secret.as_ref(), reader,
+                        signature,
+                    ).ok()
+                })
+                .unwrap_or(false);
+        }
+    }
+    valid
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // Signatures of "payload" with the "secret" key
+    const SHA1_HEX: &str = "f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d";
+    const SHA256_HEX: &str =
+        "b82fcb791acec57859b989b430a826488ce2e479fdf92326bd0a2e8375a42ba4";
+
+
+    #[test]
+    fn test_algorithm_from_name() {
+        assert_eq!(HmacAlgorithm::from_name("sha1"), Some(HmacAlgorithm::Sha1));
+        assert_eq!(
+            HmacAlgorithm::from_name("sha256"), Some(HmacAlgorithm::Sha256)
+        );
+        assert_eq!(
+            HmacAlgorithm::from_name("sha512"), Some(HmacAlgorithm::Sha512)
+        );
+        assert_eq!(HmacAlgorithm::from_name("md5"), None);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        use self::HmacAlgorithm::*;
+        use self::SignatureEncoding::*;
+
+        let verify = |algorithm, encoding, secret, payload: &[u8], sig| {
+            verify_signature(algorithm, encoding, secret, payload, sig)
+                .unwrap()
+        };
+
+        assert!(verify(Sha1, Hex, b"secret", b"payload", SHA1_HEX));
+        assert!(verify(Sha256, Hex, b"secret", b"payload", SHA256_HEX));
+
+        // Base64 encoded signatures
+        let b64 = base64::encode(&Sha512.sign(b"secret", b"payload"));
+        assert!(verify(Sha512, Base64, b"secret", b"payload", &b64));
+
+        // Wrong algorithm, secret, payload or encoding
+        assert!(!verify(Sha256, Hex, b"secret", b"payload", SHA1_HEX));
+        assert!(!verify(Sha1, Hex, b"wrong", b"payload", SHA1_HEX));
+        assert!(!verify(Sha1, Hex, b"secret", b"wrong", SHA1_HEX));
+        assert!(!verify(Sha1, Base64, b"secret", b"payload", SHA1_HEX));
+        assert!(!verify(Sha1, Hex, b"secret", b"payload", "zz"));
+    }
+
+    #[test]
+    fn test_verify_prefixed_signature() {
+        use self::HmacAlgorithm::*;
+        use self::SignatureEncoding::*;
+
+        let verify = |allowed: &[HmacAlgorithm], payload: &[u8], sig: &str| {
+            verify_prefixed_signature(allowed, Hex, b"secret", payload, sig)
+                .unwrap()
+        };
+
+        let sha1 = format!("sha1={}", SHA1_HEX);
+        let sha256 = format!("sha256={}", SHA256_HEX);
+
+        let all = &[Sha1, Sha256];
+        assert!(verify(all, b"payload", &sha1));
+        assert!(verify(all, b"payload", &sha256));
+        assert!(!verify(all, b"wrong", &sha256));
+
+        // Algorithms not allowed are rejected
+        assert!(!verify(&[Sha256], b"payload", &sha1));
+
+        // Invalid formats are rejected
+        for invalid in &[SHA1_HEX, "sha1", "md5=abcd", "=abcd", "sha1=zz", ""] {
+            assert!(!verify(all, b"payload", invalid));
+        }
+
+        // Payloads bigger than the buffer are signed as a whole
+        let big = vec![b'a'; 20000];
+        assert_eq!(
+            Sha512.sign_reader(b"secret", &big[..]).unwrap(),
+            Sha512.sign(b"secret", &big),
+        );
+    }
+
+    #[test]
+    fn test_verify_any_signature() {
+        use self::HmacAlgorithm::*;
+        use self::SignatureEncoding::*;
+
+        let payload = || Some(&b"payload"[..]);
+        let secrets = &["old", "secret"];
+        assert!(verify_any_signature(
+            Sha1, Hex, secrets, payload, vec!["abcd", SHA1_HEX],
+        ));
+        assert!(!verify_any_signature(
+            Sha1, Hex, secrets, payload, vec!["abcd", SHA256_HEX],
+        ));
+        assert!(!verify_any_signature(
+            Sha1, Hex, &["old"], payload, vec![SHA1_HEX],
+        ));
+
+        // Payloads which can't be read are rejected
+        assert!(!verify_any_signature(
+            Sha1, Hex, secrets, || None::<&[u8]>, vec![SHA1_HEX],
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks for the locations the temporary directories of the jobs are
+//! created in.
+
+use std::path::Path;
+
+use common::prelude::*;
+
+
+/// The magic number identifying tmpfs, from `linux/magic.h`.
+#[cfg(target_os = "linux")]
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+
+
+/// Check whether the provided path is on a tmpfs mount, whose content is
+/// only kept in memory.
+#[cfg(target_os = "linux")]
+pub fn is_tmpfs(path: &Path) -> Result<bool> {
+    use std::mem;
+
+    use nix::libc;
+    use nix::sys::statfs::statfs;
+
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    statfs(path, &mut stat)?;
+
+    Ok(stat.f_type as i64 == TMPFS_MAGIC)
+}
+
+/// Check whether the provided path is on a tmpfs mount, whose content is
+/// only kept in memory.
+#[cfg(not(target_os = "linux"))]
+pub fn is_tmpfs(_path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+
+/// Ensure the temporary directories of the jobs can be created in the
+/// provided path, and that it's on tmpfs if that's required.
+pub fn check_temp_dir(path: &Path, require_tmpfs: bool) -> Result<()> {
+    let display = || path.to_string_lossy().to_string();
+
+    if !path.is_dir() {
+        return Err(ErrorKind::TempDirNotFound(display()).into());
+    }
+    if require_tmpfs && !is_tmpfs(path)? {
+        return Err(ErrorKind::TempDirNotTmpfs(display()).into());
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use common::prelude::*;
+
+    use super::check_temp_dir;
+
+
+    #[test]
+    fn test_check_temp_dir() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        check_temp_dir(dir.path(), false).unwrap();
+
+        let missing = dir.path().join("missing");
+        match check_temp_dir(&missing, false).unwrap_err().kind() {
+            ErrorKind::TempDirNotFound(..) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        // /proc is never on tmpfs
+        match check_temp_dir(Path::new("/proc"), true).unwrap_err().kind() {
+            ErrorKind::TempDirNotTmpfs(..) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Timestamps handling, both for the webhooks and for the outputs of Fisher.
+//!
+//! Some providers sign a timestamp along with the payload, to prevent old
+//! requests from being replayed. The helpers in this module check those
+//! timestamps in a consistent way, allowing some clock skew between Fisher
+//! and the sender.
+//!
+//! The timestamps shown by Fisher itself are all formatted here, with the
+//! format chosen in the configuration.
+
+use std::result::Result as StdResult;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serializer;
+use serde_json::Value;
+
+use common::metrics;
+
+
+const SECONDS_IN_A_DAY: u64 = 24 * 60 * 60;
+
+
+lazy_static! {
+    static ref LAST_NOW: Mutex<u64> = Mutex::new(0);
+    static ref FORMAT: RwLock<TimestampFormat> =
+        RwLock::new(TimestampFormat::Rfc3339);
+}
+
+
+/// How the timestamps are shown in the outputs of Fisher.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimestampFormat {
+    /// Like `2017-10-16T11:20:00Z`, always in UTC.
+    Rfc3339,
+    /// The number of seconds since the UNIX epoch.
+    Unix,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Rfc3339
+    }
+}
+
+impl TimestampFormat {
+    /// Format the UNIX timestamp as a string.
+    pub fn to_string(self, timestamp: u64) -> String {
+        match self {
+            TimestampFormat::Rfc3339 => rfc3339(timestamp),
+            TimestampFormat::Unix => timestamp.to_string(),
+        }
+    }
+
+    /// Format the UNIX timestamp as a JSON value, which is a number for
+    /// UNIX timestamps.
+    pub fn to_json(self, timestamp: u64) -> Value {
+        match self {
+            TimestampFormat::Rfc3339 => Value::String(rfc3339(timestamp)),
+            TimestampFormat::Unix => Value::from(timestamp),
+        }
+    }
+}
+
+
+/// Change the format of all the timestamps shown by Fisher.
+pub fn set_timestamp_format(format: TimestampFormat) {
+    *FORMAT.write().unwrap_or_else(|err| err.into_inner()) = format;
+}
+
+fn timestamp_format() -> TimestampFormat {
+    *FORMAT.read().unwrap_or_else(|err| err.into_inner())
+}
+
+
+/// Format the UNIX timestamp as a JSON value with the configured format.
+pub fn timestamp_json(timestamp: u64) -> Value {
+    timestamp_format().to_json(timestamp)
+}
+
+/// Serialize a UNIX timestamp with the configured format, to be used with
+/// `#[serde(serialize_with = "...")]`.
+pub fn serialize_timestamp<S: Serializer>(
+    timestamp: &u64, serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    match timestamp_format() {
+        TimestampFormat::Rfc3339 => {
+            serializer.serialize_str(&rfc3339(*timestamp))
+        }
+        TimestampFormat::Unix => serializer.serialize_u64(*timestamp),
+    }
+}
+
+/// Like `serialize_timestamp`, for optional timestamps.
+pub fn serialize_optional_timestamp<S: Serializer>(
+    timestamp: &Option<u64>, serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    match *timestamp {
+        Some(ref timestamp) => serialize_timestamp(timestamp, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+
+/// Split the UNIX timestamp in the year, month, day, hours, minutes and
+/// seconds of the date in UTC.
+fn civil_date(timestamp: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let secs = timestamp % SECONDS_IN_A_DAY;
+
+    // Convert the days since the epoch to a civil date, counting in eras of
+    // 400 years starting from March 1st, 0000 (where leap days are the last
+    // day of the year)
+    let days = timestamp / SECONDS_IN_A_DAY + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era
+        - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+
+/// Format the UNIX timestamp as a RFC 3339 date in UTC.
+fn rfc3339(timestamp: u64) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil_date(timestamp);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hours, minutes, seconds,
+    )
+}
+
+
+/// Format the UNIX timestamp as a date of the Common Log Format, like
+/// `16/Oct/2017:11:20:00 +0000`. The date is always in UTC.
+pub fn common_log_date(timestamp: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+        "Nov", "Dec",
+    ];
+
+    let (year, month, day, hours, minutes, seconds) = civil_date(timestamp);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year, hours, minutes, seconds,
+    )
+}
+
+
+/// Get the current UNIX timestamp, in seconds.
+///
+/// The returned value never goes backwards, even if the system clock is
+/// changed while Fisher is running: this way moving the clock back can't be
+/// used to make stale requests look fresh again.
+pub fn unix_now() -> u64 {
+    let system = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut last = LAST_NOW.lock().unwrap_or_else(|err| err.into_inner());
+    if system > *last {
+        *last = system;
+    }
+    *last
+}
+
+
+fn is_fresh_at(now: u64, timestamp: i64, tolerance: u64) -> bool {
+    let delta = (now as i64).saturating_sub(timestamp);
+    delta.checked_abs().map(|d| d as u64 <= tolerance).unwrap_or(false)
+}
+
+
+/// Check if the timestamp (in seconds since the UNIX epoch) is at most
+/// `tolerance` seconds away from the current time, in either direction.
+///
+/// Rejected timestamps are counted in the `fisher_stale_requests_total`
+/// metric, labelled with the name of the provider.
+pub fn is_fresh_timestamp(provider: &str, timestamp: i64, tolerance: u64) -> bool {
+    if is_fresh_at(unix_now(), timestamp, tolerance) {
+        true
+    } else {
+        metrics::increment(
+            "fisher_stale_requests_total", &[("provider", provider)],
+        );
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use common::metrics;
+
+    use serde_json::Value;
+
+    use super::{
+        common_log_date, is_fresh_at, is_fresh_timestamp, rfc3339, unix_now,
+        TimestampFormat,
+    };
+
+
+    #[test]
+    fn test_unix_now() {
+        let first = unix_now();
+        let second = unix_now();
+
+        // 2017-01-01 is a reasonable lower bound
+        assert!(first > 1483228800);
+        assert!(second >= first);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1508152800), "2017-10-16T11:20:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(951868799), "2000-02-29T23:59:59Z");
+        assert_eq!(rfc3339(4107542400), "2100-03-01T00:00:00Z");
+        assert_eq!(rfc3339(253402300799), "9999-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_common_log_date() {
+        assert_eq!(common_log_date(0), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(common_log_date(1508152800), "16/Oct/2017:11:20:00 +0000");
+        assert_eq!(common_log_date(951868799), "29/Feb/2000:23:59:59 +0000");
+    }
+
+    #[test]
+    fn test_timestamp_formats() {
+        let rfc = TimestampFormat::Rfc3339;
+        assert_eq!(rfc.to_string(86400), "1970-01-02T00:00:00Z");
+        assert_eq!(rfc.to_json(86400), Value::from("1970-01-02T00:00:00Z"));
+
+        let unix = TimestampFormat::Unix;
+        assert_eq!(unix.to_string(86400), "86400");
+        assert_eq!(unix.to_json(86400), Value::from(86400));
+    }
+
+    #[test]
+    fn test_is_fresh_at() {
+        assert!(is_fresh_at(1000, 1000, 0));
+        assert!(is_fresh_at(1000, 990, 10));
+        assert!(is_fresh_at(1000, 1010, 10));
+        assert!(!is_fresh_at(1000, 989, 10));
+        assert!(!is_fresh_at(1000, 1011, 10));
+
+        // Extreme values must not overflow
+        assert!(!is_fresh_at(1000, ::std::i64::MIN, 10));
+        assert!(!is_fresh_at(1000, ::std::i64::MAX, 10));
+    }
+
+    #[test]
+    fn test_stale_requests_are_counted() {
+        let labels = &[("provider", "TimestampTest")];
+        let before = metrics::get("fisher_stale_requests_total", labels);
+
+        let now = unix_now() as i64;
+        assert!(is_fresh_timestamp("TimestampTest", now, 10));
+        assert!(!is_fresh_timestamp("TimestampTest", now - 100, 10));
+        assert!(!is_fresh_timestamp("TimestampTest", now + 100, 10));
+
+        assert_eq!(
+            metrics::get("fisher_stale_requests_total", labels),
+            before + 2
+        );
+    }
+}
@@ -0,0 +1,545 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Secrets fetched from a HashiCorp Vault server.
+//!
+//! Provider configurations can reference secrets with `vault:mount/path#key`:
+//! the secrets are fetched when the scripts are loaded and cached, while a
+//! background thread keeps the Vault token and the leased secrets renewed.
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest;
+use serde_json::{Map, Value};
+use url::Url;
+
+use common::prelude::*;
+use common::config::VaultConfig;
+
+
+lazy_static! {
+    static ref CURRENT: RwLock<Option<Arc<VaultClient>>> = RwLock::new(None);
+}
+
+/// How often the background thread checks for expiring leases.
+const MAINTENANCE_INTERVAL: u64 = 10;
+
+
+/// Resolve a `mount/path#key` reference using the running Vault client.
+pub fn resolve(reference: &str) -> Result<String> {
+    let client = CURRENT.read()?.clone();
+    if let Some(client) = client {
+        client.resolve(reference)
+    } else {
+        Err(ErrorKind::VaultNotConfigured.into())
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum VaultAuth {
+    Token(String),
+    AppRole { role_id: String, secret_id: String },
+}
+
+
+#[derive(Debug)]
+struct Lease {
+    value: String,
+    renewable: bool,
+    renew_at: Option<Instant>,
+}
+
+impl Lease {
+    fn new(value: String, duration: u64, renewable: bool) -> Self {
+        Lease {
+            value,
+            renewable,
+            renew_at: deadline(duration),
+        }
+    }
+
+    fn should_renew(&self, now: Instant) -> bool {
+        self.renew_at.map(|at| at <= now).unwrap_or(false)
+    }
+}
+
+
+#[derive(Debug)]
+struct CachedSecret {
+    data: Map<String, Value>,
+    refresh_at: Option<Instant>,
+}
+
+
+/// Renew leases at half their duration, leaving time to retry on failures.
+fn deadline(lease_duration: u64) -> Option<Instant> {
+    if lease_duration > 0 {
+        Some(Instant::now() + Duration::from_secs(lease_duration / 2))
+    } else {
+        None
+    }
+}
+
+
+#[derive(Debug)]
+struct VaultClient {
+    http: reqwest::Client,
+    address: Url,
+    auth: VaultAuth,
+    kv_version: u8,
+    cache_ttl: u64,
+
+    token: Mutex<Lease>,
+    cache: Mutex<HashMap<String, CachedSecret>>,
+}
+
+impl VaultClient {
+    fn new(address: Url, auth: VaultAuth, kv_version: u8, cache_ttl: u64) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        let mut client = VaultClient {
+            http,
+            address,
+            auth,
+            kv_version,
+            cache_ttl,
+
+            token: Mutex::new(Lease::new(String::new(), 0, false)),
+            cache: Mutex::new(HashMap::new()),
+        };
+
+        let token = client.login()?;
+        client.token = Mutex::new(token);
+
+        Ok(client)
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        Ok(self.address.join(&format!("v1/{}", path))?)
+    }
+
+    fn send(&self, request: reqwest::RequestBuilder) -> Result<Value> {
+        let mut response = request.send()?;
+        if !response.status().is_success() {
+            return Err(ErrorKind::VaultBadStatus(
+                response.status().as_u16()
+            ).into());
+        }
+        Ok(response.json()?)
+    }
+
+    fn token(&self) -> Result<String> {
+        Ok(self.token.lock()?.value.clone())
+    }
+
+    /// Authenticate with Vault, returning the token lease.
+    fn login(&self) -> Result<Lease> {
+        match self.auth {
+            VaultAuth::Token(ref token) => {
+                // Looking up the token validates it, and returns its TTL
+                let response = self.send(
+                    self.http.get(self.url("auth/token/lookup-self")?)
+                        .header("X-Vault-Token", token.as_str())
+                )?;
+
+                let data = &response["data"];
+                Ok(Lease::new(
+                    token.clone(),
+                    data["ttl"].as_u64().unwrap_or(0),
+                    data["renewable"].as_bool().unwrap_or(false),
+                ))
+            }
+            VaultAuth::AppRole { ref role_id, ref secret_id } => {
+                let response = self.send(
+                    self.http.post(self.url("auth/approle/login")?)
+                        .json(&json!({
+                            "role_id": role_id,
+                            "secret_id": secret_id,
+                        }))
+                )?;
+                Ok(auth_lease(&response)?)
+            }
+        }
+    }
+
+    /// Renew the token lease, logging in again if it's not renewable.
+    fn renew_token(&self) -> Result<()> {
+        let renewable = self.token.lock()?.renewable;
+
+        let lease = if renewable {
+            let response = self.send(
+                self.http.post(self.url("auth/token/renew-self")?)
+                    .header("X-Vault-Token", self.token()?.as_str())
+            )?;
+            auth_lease(&response)?
+        } else {
+            self.login()?
+        };
+
+        *self.token.lock()? = lease;
+        Ok(())
+    }
+
+    fn fetch(&self, path: &str) -> Result<CachedSecret> {
+        let mut parts = path.splitn(2, '/');
+        let mount = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("");
+
+        let api_path = if self.kv_version == 1 {
+            format!("{}/{}", mount, path)
+        } else {
+            format!("{}/data/{}", mount, path)
+        };
+
+        let mut response = self.send(
+            self.http.get(self.url(&api_path)?)
+                .header("X-Vault-Token", self.token()?.as_str())
+        )?;
+
+        // KV version 2 wraps the secret in another object
+        let data = if self.kv_version == 1 {
+            response["data"].take()
+        } else {
+            response["data"]["data"].take()
+        };
+
+        let lease_duration = match response["lease_duration"].as_u64() {
+            Some(duration) if duration > 0 => duration,
+            _ => self.cache_ttl,
+        };
+
+        if let Value::Object(data) = data {
+            Ok(CachedSecret {
+                data,
+                refresh_at: deadline(lease_duration),
+            })
+        } else {
+            Err(ErrorKind::VaultInvalidResponse.into())
+        }
+    }
+
+    fn resolve(&self, reference: &str) -> Result<String> {
+        let (path, key) = parse_reference(reference)?;
+
+        let mut cache = self.cache.lock()?;
+        if !cache.contains_key(path) {
+            let secret = self.fetch(path)
+                .chain_err(|| ErrorKind::VaultFetchFailed(path.into()))?;
+            cache.insert(path.into(), secret);
+        }
+
+        match cache[path].data.get(key) {
+            Some(&Value::String(ref value)) => Ok(value.clone()),
+            Some(other) => Ok(other.to_string()),
+            None => Err(ErrorKind::VaultKeyMissing(reference.into()).into()),
+        }
+    }
+
+    /// Renew the token and refresh the cached secrets close to expire,
+    /// returning whether any of the secrets changed.
+    fn maintain(&self) -> Result<bool> {
+        let now = Instant::now();
+
+        if self.token.lock()?.should_renew(now) {
+            self.renew_token()?;
+        }
+
+        let expiring = self.cache
+            .lock()?
+            .iter()
+            .filter(|&(_, secret)| {
+                secret.refresh_at.map(|at| at <= now).unwrap_or(false)
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for path in expiring {
+            let secret = self.fetch(&path)
+                .chain_err(|| ErrorKind::VaultFetchFailed(path.clone()))?;
+
+            let mut cache = self.cache.lock()?;
+            changed |= cache.get(&path).map_or(false, |old| {
+                old.data != secret.data
+            });
+            cache.insert(path, secret);
+        }
+
+        Ok(changed)
+    }
+}
+
+
+fn auth_lease(response: &Value) -> Result<Lease> {
+    let auth = &response["auth"];
+    if let Some(token) = auth["client_token"].as_str() {
+        Ok(Lease::new(
+            token.into(),
+            auth["lease_duration"].as_u64().unwrap_or(0),
+            auth["renewable"].as_bool().unwrap_or(false),
+        ))
+    } else {
+        Err(ErrorKind::VaultInvalidResponse.into())
+    }
+}
+
+
+fn parse_reference(reference: &str) -> Result<(&str, &str)> {
+    let mut parts = reference.splitn(2, '#');
+    let path = parts.next().unwrap_or("");
+    let key = parts.next().unwrap_or("");
+
+    if key.is_empty() || !path.contains('/') || path.starts_with('/') {
+        return Err(ErrorKind::VaultInvalidReference(reference.into()).into());
+    }
+
+    Ok((path, key))
+}
+
+
+/// This struct controls the thread renewing the Vault leases.
+
+pub struct Vault {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Vault {
+    /// Authenticate with Vault and start renewing the leases in the
+    /// background, if Vault is configured. The client is then used to
+    /// resolve the secrets referenced by the providers, and `reload` is
+    /// called when any of them changed, to load the scripts again.
+    pub fn start<F>(config: &VaultConfig, reload: F) -> Result<Option<Self>>
+    where
+        F: Fn() -> Result<()> + Send + 'static,
+    {
+        let address = if let Some(ref address) = config.address {
+            Url::parse(address)
+                .chain_err(|| ErrorKind::VaultInvalidConfig(
+                    format!("invalid address: {}", address)
+                ))?
+        } else {
+            *CURRENT.write()? = None;
+            return Ok(None);
+        };
+
+        let auth = match (&config.token, &config.role_id, &config.secret_id) {
+            (&Some(ref token), &None, &None) => VaultAuth::Token(token.clone()),
+            (&None, &Some(ref role_id), &Some(ref secret_id)) => {
+                VaultAuth::AppRole {
+                    role_id: role_id.clone(),
+                    secret_id: secret_id.clone(),
+                }
+            }
+            _ => {
+                return Err(ErrorKind::VaultInvalidConfig(
+                    "either token or role-id and secret-id must be set".into()
+                ).into());
+            }
+        };
+
+        if config.kv_version != 1 && config.kv_version != 2 {
+            return Err(ErrorKind::VaultInvalidConfig(
+                format!("unsupported KV version: {}", config.kv_version)
+            ).into());
+        }
+
+        let client = Arc::new(VaultClient::new(
+            address, auth, config.kv_version, config.cache_ttl.as_u64(),
+        )?);
+        *CURRENT.write()? = Some(client.clone());
+
+        let (stop_send, stop_recv) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let interval = Duration::from_secs(MAINTENANCE_INTERVAL);
+
+            loop {
+                // Failures are retried at the next iteration
+                match client.maintain() {
+                    Ok(true) => match reload() {
+                        Ok(()) => println!(
+                            "Scripts reloaded after the Vault secrets changed"
+                        ),
+                        Err(err) => err.pretty_print(),
+                    },
+                    Ok(false) => {}
+                    Err(err) => err.pretty_print(),
+                }
+
+                match stop_recv.recv_timeout(interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Some(Vault {
+            stop: stop_send,
+            handle,
+        }))
+    }
+
+    /// Stop renewing the leases, returning when the thread exited.
+    pub fn stop(self) {
+        // The thread might be already stopped
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use serde_json::{self, Value};
+    use tiny_http;
+    use url::Url;
+
+    use super::{parse_reference, VaultAuth, VaultClient};
+
+
+    /// Start a fake Vault server, returning its URL and the received
+    /// requests. Every request consumes one of the responses.
+    fn fake_vault(
+        responses: Vec<Value>,
+    ) -> (Url, mpsc::Receiver<(String, Option<String>, String)>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", server.server_addr()))
+            .unwrap();
+
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            for response in responses {
+                let mut req = server.recv().unwrap();
+
+                let token = req.headers()
+                    .iter()
+                    .find(|h| h.field.equiv("X-Vault-Token"))
+                    .map(|h| h.value.to_string());
+                let mut body = String::new();
+                req.as_reader().read_to_string(&mut body).unwrap();
+                send.send((req.url().to_string(), token, body)).unwrap();
+
+                req.respond(tiny_http::Response::from_string(
+                    serde_json::to_string(&response).unwrap()
+                )).unwrap();
+            }
+        });
+
+        (url, recv)
+    }
+
+
+    #[test]
+    fn test_parse_reference() {
+        assert_eq!(
+            parse_reference("kv/path/to#key").unwrap(), ("kv/path/to", "key")
+        );
+
+        for wrong in &["kv/path", "kv#key", "kv/path#", "/kv/path#key", ""] {
+            assert!(parse_reference(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+
+    #[test]
+    fn test_token_auth() {
+        let (url, requests) = fake_vault(vec![
+            json!({"data": {"ttl": 3600, "renewable": true}}),
+            json!({"lease_duration": 0, "data": {"data": {"secret": "abcde"}}}),
+        ]);
+
+        let client = VaultClient::new(
+            url, VaultAuth::Token("tok".into()), 2, 300,
+        ).unwrap();
+        assert_eq!(
+            requests.recv().unwrap(),
+            ("/v1/auth/token/lookup-self".into(), Some("tok".into()), "".into())
+        );
+
+        // The secret is fetched only once
+        for _ in 0..2 {
+            assert_eq!(client.resolve("kv/app/github#secret").unwrap(), "abcde");
+        }
+        assert_eq!(
+            requests.recv().unwrap(),
+            ("/v1/kv/data/app/github".into(), Some("tok".into()), "".into())
+        );
+
+        assert!(client.resolve("kv/app/github#missing").is_err());
+    }
+
+
+    #[test]
+    fn test_maintain() {
+        let (url, requests) = fake_vault(vec![
+            json!({"data": {"ttl": 3600, "renewable": true}}),
+            json!({"lease_duration": 1, "data": {"data": {"secret": "abcde"}}}),
+            json!({"lease_duration": 1, "data": {"data": {"secret": "abcde"}}}),
+            json!({"lease_duration": 1, "data": {"data": {"secret": "fghij"}}}),
+        ]);
+
+        let client = VaultClient::new(
+            url, VaultAuth::Token("tok".into()), 2, 300,
+        ).unwrap();
+        assert_eq!(client.resolve("kv/app/github#secret").unwrap(), "abcde");
+
+        // Only changed secrets are reported
+        assert!(!client.maintain().unwrap());
+        assert!(client.maintain().unwrap());
+        assert_eq!(client.resolve("kv/app/github#secret").unwrap(), "fghij");
+        assert_eq!(requests.iter().take(4).count(), 4);
+    }
+
+
+    #[test]
+    fn test_approle_auth() {
+        let (url, requests) = fake_vault(vec![
+            json!({"auth": {
+                "client_token": "approle-tok",
+                "lease_duration": 3600,
+                "renewable": true,
+            }}),
+            json!({"lease_duration": 60, "data": {"secret": "abcde"}}),
+        ]);
+
+        let client = VaultClient::new(url, VaultAuth::AppRole {
+            role_id: "role".into(),
+            secret_id: "id".into(),
+        }, 1, 300).unwrap();
+
+        let (url, token, body) = requests.recv().unwrap();
+        assert_eq!(url, "/v1/auth/approle/login");
+        assert_eq!(token, None);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({"role_id": "role", "secret_id": "id"})
+        );
+
+        assert_eq!(client.resolve("secret/github#secret").unwrap(), "abcde");
+        assert_eq!(
+            requests.recv().unwrap(),
+            ("/v1/secret/github".into(), Some("approle-tok".into()), "".into())
+        );
+    }
+}
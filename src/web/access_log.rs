@@ -0,0 +1,282 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access log of the requests received by the HTTP server.
+//!
+//! Every request is logged once its response is ready, including the ones
+//! rejected before reaching a hook. For the requests to the hooks, the
+//! handlers also record which hook was called, what its provider decided
+//! and which jobs were created, so they end up in the same line.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::prelude::*;
+use common::config::AccessLogFormat;
+use utils;
+
+
+/// What happened to a request sent to a hook.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HookAccess {
+    pub hook: Option<String>,
+    pub provider: Option<String>,
+    pub outcome: Option<&'static str>,
+    pub job_ids: Vec<String>,
+}
+
+
+/// The details about the hook recorded while handling a request.
+#[derive(Debug, Default)]
+pub struct AccessDetails {
+    details: Mutex<HookAccess>,
+}
+
+impl Clone for AccessDetails {
+    fn clone(&self) -> Self {
+        AccessDetails {
+            details: Mutex::new(self.get()),
+        }
+    }
+}
+
+impl AccessDetails {
+    /// Record the hook handling the request.
+    pub fn hook(&self, hook: &str) {
+        self.details.lock().unwrap().hook = Some(hook.into());
+    }
+
+    /// Record the decision of the provider and the jobs it resulted in.
+    pub fn outcome(
+        &self, provider: &str, outcome: &'static str, job_ids: Vec<String>,
+    ) {
+        let mut details = self.details.lock().unwrap();
+        details.provider = Some(provider.into());
+        details.outcome = Some(outcome);
+        details.job_ids = job_ids;
+    }
+
+    pub fn get(&self) -> HookAccess {
+        self.details.lock().unwrap().clone()
+    }
+}
+
+
+/// A line of the access log.
+#[derive(Debug)]
+pub struct AccessEntry {
+    pub timestamp: u64,
+    pub client: IpAddr,
+    pub method: String,
+    /// The path of the request, without the query string since it might
+    /// contain secrets.
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    /// The size of the body of the response, in bytes.
+    pub size: usize,
+    pub latency: Duration,
+    pub hook: HookAccess,
+}
+
+impl AccessEntry {
+    /// Start the entry of a request received now.
+    pub fn new(
+        client: IpAddr, method: &str, path: &str, version: &str,
+    ) -> Self {
+        AccessEntry {
+            timestamp: utils::unix_now(),
+            client,
+            method: method.into(),
+            path: path.into(),
+            version: version.into(),
+            status: 0,
+            size: 0,
+            latency: Duration::from_secs(0),
+            hook: HookAccess::default(),
+        }
+    }
+
+    fn latency_ms(&self) -> f64 {
+        self.latency.as_secs() as f64 * 1000.0
+            + f64::from(self.latency.subsec_micros()) / 1000.0
+    }
+
+    fn json(&self) -> String {
+        json!({
+            "timestamp": utils::timestamp_json(self.timestamp),
+            "client": self.client.to_string(),
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "size": self.size,
+            "latency_ms": self.latency_ms(),
+            "hook": self.hook.hook,
+            "provider": self.hook.provider,
+            "outcome": self.hook.outcome,
+            "job_ids": self.hook.job_ids,
+        }).to_string()
+    }
+
+    fn common(&self) -> String {
+        let or_dash = |value: &Option<String>| {
+            value.clone().unwrap_or_else(|| "-".into())
+        };
+        let job_ids = if self.hook.job_ids.is_empty() {
+            "-".into()
+        } else {
+            self.hook.job_ids.join(",")
+        };
+
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {} {:.3} {} {} {} {}",
+            self.client, utils::common_log_date(self.timestamp), self.method,
+            self.path, self.version, self.status, self.size, self.latency_ms(),
+            or_dash(&self.hook.hook), or_dash(&self.hook.provider),
+            self.hook.outcome.unwrap_or("-"), job_ids,
+        )
+    }
+}
+
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    /// The file the log is written to, or `None` for the standard output.
+    file: Option<Mutex<File>>,
+}
+
+impl AccessLog {
+    /// Log the requests to the end of the file at `path`, or to the
+    /// standard output if the path is `-`.
+    pub fn open(path: &str, format: AccessLogFormat) -> Result<Self> {
+        let file = if path == "-" {
+            None
+        } else {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some(Mutex::new(file))
+        };
+
+        Ok(AccessLog { format, file })
+    }
+
+    /// Write an entry to the log. Failing to do so doesn't affect the
+    /// request, but the error is shown.
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = match self.format {
+            AccessLogFormat::Json => entry.json(),
+            AccessLogFormat::Common => entry.common(),
+        };
+
+        let result = if let Some(ref file) = self.file {
+            writeln!(file.lock().unwrap(), "{}", line)
+        } else {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            writeln!(stdout, "{}", line).and_then(|_| stdout.flush())
+        };
+        if let Err(err) = result {
+            Error::from(err).pretty_print();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{self, Value};
+
+    use super::{AccessDetails, AccessEntry};
+
+
+    fn entry() -> AccessEntry {
+        let mut entry = AccessEntry::new(
+            "10.0.0.1".parse().unwrap(), "POST", "/hook/deploy.sh", "HTTP/1.1",
+        );
+        entry.timestamp = 1508152800;
+        entry.status = 200;
+        entry.size = 42;
+        entry.latency = Duration::from_micros(12_500);
+        entry
+    }
+
+
+    #[test]
+    fn test_access_details() {
+        let details = AccessDetails::default();
+        assert_eq!(details.get().hook, None);
+
+        details.hook("deploy.sh");
+        details.outcome("GitHub", "queued", vec!["abcd".into()]);
+
+        // Cloned requests keep what was recorded until then
+        let cloned = details.clone();
+        details.hook("other.sh");
+        let recorded = cloned.get();
+        assert_eq!(recorded.hook, Some("deploy.sh".into()));
+        assert_eq!(recorded.provider, Some("GitHub".into()));
+        assert_eq!(recorded.outcome, Some("queued"));
+        assert_eq!(recorded.job_ids, vec!["abcd".to_string()]);
+    }
+
+    #[test]
+    fn test_json_format() {
+        let mut entry = entry();
+        let json: Value = serde_json::from_str(&entry.json()).unwrap();
+        assert_eq!(json["client"], "10.0.0.1");
+        assert_eq!(json["method"], "POST");
+        assert_eq!(json["path"], "/hook/deploy.sh");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["latency_ms"], 12.5);
+        assert_eq!(json["hook"], Value::Null);
+        assert_eq!(json["job_ids"], json!([]));
+
+        let details = AccessDetails::default();
+        details.hook("deploy.sh");
+        details.outcome("GitHub", "queued", vec!["abcd".into()]);
+        entry.hook = details.get();
+
+        let json: Value = serde_json::from_str(&entry.json()).unwrap();
+        assert_eq!(json["hook"], "deploy.sh");
+        assert_eq!(json["provider"], "GitHub");
+        assert_eq!(json["outcome"], "queued");
+        assert_eq!(json["job_ids"], json!(["abcd"]));
+    }
+
+    #[test]
+    fn test_common_format() {
+        let mut entry = entry();
+        assert_eq!(
+            entry.common(),
+            "10.0.0.1 - - [16/Oct/2017:11:20:00 +0000] \
+             \"POST /hook/deploy.sh HTTP/1.1\" 200 42 12.500 - - - -",
+        );
+
+        let details = AccessDetails::default();
+        details.hook("deploy.sh");
+        details.outcome("GitHub", "queued", vec!["a".into(), "b".into()]);
+        entry.hook = details.get();
+        assert_eq!(
+            entry.common(),
+            "10.0.0.1 - - [16/Oct/2017:11:20:00 +0000] \
+             \"POST /hook/deploy.sh HTTP/1.1\" 200 42 12.500 deploy.sh GitHub \
+             queued a,b",
+        );
+    }
+}
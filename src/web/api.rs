@@ -13,90 +13,434 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::net::IpAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
 use common::prelude::*;
-use common::config::RateLimitConfig;
+use common::config::BlackoutConfig;
+use common::metrics;
+use common::structs::{BuildInfo, FreezeAction, JobState};
 
+use base64;
+use futures::{Future, Sink};
+use hyper::{Body, Chunk};
+use serde_json::{self, Value};
+
+use audit;
+use processor::STATUS_EVENTS_PRIORITY;
+use providers::{PendingApproval, Provider, StatusEvent, ValidationTrace};
 use requests::{Request, RequestType};
-use scripts::{Repository, Job};
-use web::rate_limits::RateLimiter;
+use scripts::{
+    ArtifactUploads, Blueprint, Repository, Job, JobOutput, RunningJobs,
+    Script,
+};
+use traces::{Span, TraceContext};
+use utils::{self, Secrets};
+use web::{AccessDetails, BodyLimits, JsonBody, WebRequest};
+use web::approvals::{ApprovalLinks, Approvals};
+use web::artifacts::{self, Artifacts};
+use web::freezes::{self, Freezes};
+use web::ip_filter::IpFilter;
+use web::log_stream;
+use web::rate_limits::{HookLimits, RateLimiter, TokenBuckets};
+use web::deliveries::DeliveriesCache;
+use web::archive::Archive;
+use web::dashboard::{Dashboard, HookStats};
+use web::mirror::{Mirror, MirroredDelivery};
+use web::paths::HookPaths;
+use web::payloads::Payloads;
 use web::responses::Response;
 
 
+/// Name of the script receiving the requests to the unknown hooks.
+const CATCHALL_HOOK: &str = "__catchall__";
+
+/// Only the end of the output of a job is returned to the requests waiting
+/// for it, to avoid huge responses.
+const MAX_WAIT_OUTPUT: usize = 64 * 1024;
+
+/// How many of the jobs which ended recently are shown in the dashboard.
+const DASHBOARD_RECENT_JOBS: usize = 50;
+
+
 #[derive(Clone)]
 pub struct WebApi<A: ProcessorApiTrait<Repository>> {
     processor: Arc<Mutex<A>>,
+    blueprint: Arc<Mutex<Blueprint>>,
     hooks: Arc<Repository>,
     locked: Arc<AtomicBool>,
     limiter: Arc<Mutex<RateLimiter<IpAddr>>>,
+    ip_limiter: Option<Arc<Mutex<TokenBuckets<IpAddr>>>>,
+    hook_limits: Arc<Mutex<HookLimits>>,
+    ip_filter: Arc<IpFilter>,
+    build_info: Arc<RwLock<BuildInfo>>,
+    deliveries: Option<Arc<Mutex<DeliveriesCache>>>,
+    payloads: Arc<Mutex<Payloads>>,
+    standby: Arc<AtomicBool>,
+    mirror: Option<Arc<Mirror>>,
+    archive: Option<Arc<Archive>>,
+    approvals: Arc<Mutex<Approvals>>,
+    approval_links: Option<Arc<ApprovalLinks>>,
+    freezes: Arc<Mutex<Freezes>>,
+    blackouts: Arc<Vec<BlackoutConfig>>,
+    artifacts: Option<Arc<Artifacts>>,
+    uploads: Arc<Mutex<ArtifactUploads>>,
+    /// The address Fisher listens on, which local clients might use.
+    bind_ip: IpAddr,
+    running: Arc<Mutex<RunningJobs>>,
+    paths: Arc<HookPaths>,
 
+    max_wait: Duration,
     health_enabled: bool,
+    metrics_enabled: bool,
+    admin_token: Option<String>,
+    hook_tokens: Option<Secrets>,
+    read_only: bool,
+}
+
+
+/// The options and the shared state of the web API, built by the web app
+/// from the HTTP configuration.
+pub struct WebApiOptions {
+    pub locked: Arc<AtomicBool>,
+    pub limiter: RateLimiter<IpAddr>,
+    pub ip_limiter: Option<TokenBuckets<IpAddr>>,
+    pub ip_filter: IpFilter,
+    pub build_info: Arc<RwLock<BuildInfo>>,
+    pub deliveries: Option<DeliveriesCache>,
+    pub payloads: Payloads,
+    pub standby: bool,
+    pub mirror: Option<Mirror>,
+    pub archive: Option<Archive>,
+    pub approvals: Arc<Mutex<Approvals>>,
+    pub approval_links: Option<ApprovalLinks>,
+    pub freezes: Arc<Mutex<Freezes>>,
+    pub blackouts: Vec<BlackoutConfig>,
+    pub artifacts: Option<Artifacts>,
+    pub uploads: Arc<Mutex<ArtifactUploads>>,
+    pub bind_ip: IpAddr,
+    pub running: Arc<Mutex<RunningJobs>>,
+    pub paths: HookPaths,
+
+    pub max_wait: Duration,
+    pub health_enabled: bool,
+    pub metrics_enabled: bool,
+    pub admin_token: Option<String>,
+    pub hook_tokens: Option<Secrets>,
+    pub read_only: bool,
+}
+
+
+/// The body of a request to the `/admin/verify` endpoint.
+#[derive(Deserialize)]
+struct VerifyRequest {
+    provider: String,
+    config: Value,
+    #[serde(default)]
+    headers: utils::Headers,
+    #[serde(default)]
+    params: HashMap<String, String>,
+    #[serde(default)]
+    body: String,
+}
+
+
+/// The body of a request to the `/admin/approvals/<job>/approve` endpoint.
+#[derive(Deserialize)]
+struct ApproveRequest {
+    approver: String,
+}
+
+
+/// The body of a request to the `/admin/freezes` endpoints.
+#[derive(Deserialize)]
+struct FreezeRequest {
+    reason: String,
+    #[serde(default = "default_freeze_action")]
+    action: FreezeAction,
+    #[serde(default)]
+    expires_in: Option<utils::TimeString>,
+}
+
+fn default_freeze_action() -> FreezeAction {
+    FreezeAction::Reject
+}
+
+
+/// The body of a request to the `/hooks/<name>/trigger` endpoint.
+#[derive(Default, Deserialize)]
+struct TriggerRequest {
+    #[serde(default)]
+    env: HashMap<String, String>,
 }
 
 impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
     pub fn new(
         processor: A,
-        hooks: Arc<Repository>,
-        locked: Arc<AtomicBool>,
-        rate_limit_config: &RateLimitConfig,
-        health_enabled: bool,
+        blueprint: Arc<Mutex<Blueprint>>,
+        options: WebApiOptions,
     ) -> Self {
-        let limiter = Arc::new(Mutex::new(RateLimiter::new(
-            rate_limit_config.allowed,
-            rate_limit_config.interval.as_u64(),
-        )));
+        let WebApiOptions {
+            locked, limiter, ip_limiter, ip_filter, build_info, deliveries,
+            payloads, standby, mirror, archive, approvals, approval_links,
+            freezes, blackouts, artifacts, uploads, bind_ip, running, paths,
+            max_wait, health_enabled, metrics_enabled, admin_token,
+            hook_tokens, read_only,
+        } = options;
+
+        // The repository sees the new scripts after every reload
+        let hooks = Arc::new(blueprint.lock().unwrap().repository());
 
         WebApi {
             processor: Arc::new(Mutex::new(processor)),
-            hooks, locked, limiter, health_enabled,
+            blueprint, hooks, locked, build_info, health_enabled,
+            metrics_enabled, admin_token, hook_tokens, read_only, approvals,
+            freezes, uploads, bind_ip, running,
+            limiter: Arc::new(Mutex::new(limiter)),
+            deliveries: deliveries.map(|cache| Arc::new(Mutex::new(cache))),
+            standby: Arc::new(AtomicBool::new(standby)),
+            mirror: mirror.map(Arc::new),
+            archive: archive.map(Arc::new),
+            approval_links: approval_links.map(Arc::new),
+            ip_limiter: ip_limiter.map(|l| Arc::new(Mutex::new(l))),
+            hook_limits: Arc::new(Mutex::new(HookLimits::new())),
+            ip_filter: Arc::new(ip_filter),
+            blackouts: Arc::new(blackouts),
+            artifacts: artifacts.map(Arc::new),
+            paths: Arc::new(paths),
+            max_wait,
+            payloads: Arc::new(Mutex::new(payloads)),
         }
     }
 
     pub fn process_hook(&self, req: &Request, args: Vec<String>) -> Response {
-        let hook_name = &args[0];
+        self.serve_hook(req, args, false)
+    }
 
-        // Don't process hooks if the web api is locked
-        if self.locked.load(Ordering::Relaxed) {
+    /// Process a request to a hook, reached either by its name or by one of
+    /// its custom paths.
+    fn serve_hook(
+        &self, req: &Request, args: Vec<String>, custom_path: bool,
+    ) -> Response {
+        let requested = &args[0];
+
+        // Don't process hooks if the web api is locked, or if this is a
+        // standby instance not promoted yet
+        if self.locked.load(Ordering::Relaxed)
+            || self.standby.load(Ordering::Relaxed)
+        {
             return Response::Unavailable;
         }
 
-        // Check if the user is not rate limited
+        // Check if the user is allowed to call the hooks, and if it's not
+        // sending too many requests, or too many invalid ones
         if let Ok(r) = req.web() {
+            if !self.ip_filter.allows(&r.source) {
+                return Response::Forbidden;
+            }
+
+            if let Some(ref ip_limiter) = self.ip_limiter {
+                if let Some(wait) = ip_limiter.lock().unwrap().take(r.source) {
+                    return Response::TooManyRequests(wait);
+                }
+            }
+
             let limited = self.limiter.lock().unwrap().is_limited(&r.source);
             if let Some(until) = limited {
                 return Response::TooManyRequests(until);
             }
+
+            // Callers without a valid token are treated like the ones
+            // sending invalid requests, to prevent guessing the tokens
+            if let Some(ref tokens) = self.hook_tokens {
+                if !bearer_token(r).map_or(false, |t| tokens.matches(t)) {
+                    self.limiter.lock().unwrap().increment(r.source);
+                    return Response::Unauthorized;
+                }
+            }
         }
 
-        // Check if the hook exists
-        let hook;
-        if let Some(found) = self.hooks.get_by_name(hook_name) {
-            hook = found;
-        } else {
-            return Response::NotFound;
+        // Check if the hook exists, falling back to the catch-all script
+        let (hook, caught) =
+            if let Some(found) = self.hooks.get_by_name(requested) {
+                // The name of hooks with a secret path is easy to guess
+                if found.secret_path() && !custom_path {
+                    return Response::NotFound;
+                }
+                (found, None)
+            } else if let Some(found) = self.hooks.get_by_name(CATCHALL_HOOK) {
+                (found, Some(requested.as_str()))
+            } else {
+                return Response::NotFound;
+            };
+        let hook_name = &hook.name().to_string();
+        if let Ok(web) = req.web() {
+            web.access.hook(hook_name);
+        }
+
+        // Protect the hook from storms of requests before validating them
+        if let Some(limit) = hook.rate_limit() {
+            let limited = self.hook_limits.lock().unwrap().take(
+                hook_name, limit.allowed, limit.interval.as_u64(),
+            );
+            if let Some(wait) = limited {
+                metrics::increment(
+                    "fisher_rate_limited_requests_total",
+                    &[("hook", hook_name)],
+                );
+                return Response::TooManyRequests(wait);
+            }
         }
 
+        // The jobs created by the request are part of its trace
+        let caller = req.web()
+            .ok()
+            .and_then(|r| r.headers.get("traceparent"))
+            .and_then(|header| TraceContext::from_traceparent(header));
+        let mut span = Span::server("webhook", caller);
+        span.attribute("fisher.hook", hook_name);
+
         // Validate the hook
         let (request_type, provider) = hook.validate(req);
 
+        let response = self.execute_hook(
+            req, hook.clone(), request_type, provider.clone(), requested,
+            caught, span.context(),
+        );
+        let provider_name = provider.as_ref().map_or("none", |p| p.name());
+        let outcome = request_outcome(&response);
+        metrics::increment("fisher_requests_total", &[
+            ("hook", hook_name),
+            ("provider", provider_name),
+            ("outcome", outcome),
+        ]);
+        if let Ok(web) = req.web() {
+            web.access.outcome(provider_name, outcome, response.job_ids());
+        }
+
+        span.attribute("fisher.provider", provider_name);
+        span.attribute("fisher.outcome", outcome);
+        span.attribute("http.status_code", response.status());
+        if response.status() >= 500 {
+            span.fail();
+        }
+        span.end();
+
+        customize_response(&hook, response)
+    }
+
+    /// Execute the hook, if the request needs it.
+    fn execute_hook(
+        &self, req: &Request, hook: Arc<Script>, request_type: RequestType,
+        provider: Option<Arc<Provider>>, requested: &str,
+        caught: Option<&str>, trace: &TraceContext,
+    ) -> Response {
+        let hook_name = &hook.name().to_string();
+
         // Change behavior based on the request type
         match request_type {
             // Don't do anything if it's only a ping
-            RequestType::Ping => Response::Ok,
+            RequestType::Ping => {
+                let response = provider_response(
+                    provider.as_ref(), req, request_type, Response::Ok,
+                );
+                record_skipped(&hook, provider.as_ref(), &response);
+                response
+            }
 
             // Queue a job if the hook should be executed
             RequestType::ExecuteHook => {
-                let job = Job::new(hook.clone(), provider, req.clone());
-                self.processor
+                if self.is_muted(&hook, provider.as_ref(), req) {
+                    return provider_response(
+                        provider.as_ref(), req, request_type, Response::Muted,
+                    );
+                }
+
+                let freeze = self.freezes
                     .lock()
                     .unwrap()
-                    .queue(job, hook.priority())
-                    .unwrap();
+                    .active(hook_name, utils::unix_now())
+                    .cloned();
+
+                // Reject the webhook before remembering the delivery, so it
+                // can be delivered again after the freeze
+                if let Some(ref freeze) = freeze {
+                    if freeze.action == FreezeAction::Reject {
+                        return Response::Frozen(freeze.reason.clone());
+                    }
+                }
+
+                // Don't execute the same delivery twice
+                if let Some(ref provider) = provider {
+                    if !self.is_new_delivery(hook_name, provider, req) {
+                        return provider_response(
+                            Some(provider), req, request_type, Response::Ok,
+                        );
+                    }
+                }
+
+                let fan_out = provider
+                    .as_ref()
+                    .and_then(|provider| provider.fan_out(req));
+
+                let wait = hook.wait() || wants_to_wait(req);
+                let dispatched = if let Some(ref scripts) = fan_out {
+                    self.fan_out(
+                        scripts, &provider, req, freeze.is_some(), trace,
+                    ).map(|response| (response, None))
+                } else {
+                    let mut job =
+                        Job::new(hook.clone(), provider.clone(), req.clone());
+                    job.set_trace(trace);
+                    if let Some(name) = caught {
+                        job.set_requested_hook(name);
+                    }
+                    self.queue_job(&hook, job, freeze.is_some(), wait)
+                };
 
-                Response::Ok
+                // The queue is full and the job was shed, or the hook is
+                // failing too much
+                let (response, output) = match dispatched {
+                    Ok(dispatched) => dispatched,
+                    Err(err) => {
+                        // The client is told to retry, so the delivery must
+                        // not be detected as a duplicate then
+                        if let Some(ref provider) = provider {
+                            self.forget_delivery(hook_name, provider, req);
+                        }
+                        return dispatch_failed(err);
+                    }
+                };
+
+                self.store_payload(
+                    hook_name, provider.as_ref(), req, &response,
+                );
+                if let Ok(web) = req.web() {
+                    audit::webhook(
+                        hook_name, provider.as_ref().map(|p| p.name()), web,
+                        request_outcome(&response), &response.job_ids(),
+                    );
+                }
+                if let Some(ref mirror) = self.mirror {
+                    if let Ok(web) = req.web() {
+                        mirror.send(requested, web);
+                    }
+                }
+
+                // Jobs which can't be queued right away are not waited for
+                let response = match (response, output) {
+                    (Response::Queued(job_id), Some(output)) => {
+                        self.wait_for_job(&hook, job_id, output)
+                    }
+                    (response, _) => response,
+                };
+
+                provider_response(provider.as_ref(), req, request_type, response)
             },
 
             RequestType::Invalid => {
@@ -110,13 +454,1531 @@ impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
         }
     }
 
+    /// Queue a job of the script, holding it if the script is frozen. If
+    /// `wait` is true the output of the job is also returned, to wait for
+    /// it.
+    fn queue_job(
+        &self, hook: &Script, mut job: Job, frozen: bool, wait: bool,
+    ) -> Result<(Response, Option<Receiver<JobOutput>>)> {
+        let job_id = job.id().to_string();
+        let output = if wait { Some(job.watch_output()) } else { None };
+
+        if frozen {
+            // The job will be executed after the freeze ends
+            self.freezes.lock().unwrap().hold(job, hook.priority());
+            println!(
+                "Job {} of the '{}' script is held by a freeze",
+                job_id, hook.name(),
+            );
+
+            Ok((Response::Held(job_id), output))
+        } else {
+            Ok((self.dispatch(job, hook.priority())?, output))
+        }
+    }
+
+    /// Wait for a queued job to end and reply with its result, or with the
+    /// usual response if it takes too long. The wait happens in a separate
+    /// thread, so the request doesn't keep a worker busy.
+    fn wait_for_job(
+        &self, hook: &Script, job_id: String, output: Receiver<JobOutput>,
+    ) -> Response {
+        let (sender, body) = Body::channel();
+
+        let max_wait = self.max_wait;
+        let wait_output = hook.wait_output();
+        let id = job_id.clone();
+        thread::spawn(move || {
+            let result = job_result(id, output, max_wait, wait_output);
+            // The client might have closed the connection in the meantime
+            let _ = sender.send(Chunk::from(result.json())).wait();
+        });
+
+        Response::Waiting(job_id, body)
+    }
+
+    /// Create a separate job for each one of the scripts a request is
+    /// routed to by its provider.
+    fn fan_out(
+        &self, scripts: &[String], provider: &Option<Arc<Provider>>,
+        req: &Request, frozen: bool, trace: &TraceContext,
+    ) -> Result<Response> {
+        let mut job_ids = Vec::with_capacity(scripts.len());
+        for name in scripts {
+            let hook = if let Some(hook) = self.hooks.get_by_name(name) {
+                hook
+            } else {
+                println!(
+                    "Can't route the webhook to the missing '{}' script", name,
+                );
+                continue;
+            };
+
+            let freeze = self.freezes
+                .lock()
+                .unwrap()
+                .active(name, utils::unix_now())
+                .map(|freeze| freeze.action);
+            if freeze == Some(FreezeAction::Reject) {
+                println!("Webhook not routed to the frozen '{}' script", name);
+                continue;
+            }
+
+            let frozen = frozen || freeze.is_some();
+            let mut job = Job::new(hook.clone(), provider.clone(), req.clone());
+            job.set_trace(trace);
+            let queued = self.queue_job(&hook, job, frozen, false);
+            match queued {
+                Ok((Response::Queued(id), _))
+                | Ok((Response::PendingApproval(id), _))
+                | Ok((Response::Held(id), _)) => job_ids.push(id),
+                Err(Error(ErrorKind::CircuitOpen(..), _)) => {
+                    println!(
+                        "Webhook not routed to the '{}' script: its circuit \
+                         breaker is open", name,
+                    );
+                }
+                Err(Error(ErrorKind::BudgetExhausted(..), _)) => {
+                    println!(
+                        "Webhook not routed to the '{}' script: its daily \
+                         budget is exhausted", name,
+                    );
+                }
+                Err(err) => return Err(err),
+                _ => {}
+            }
+        }
+
+        Ok(Response::FannedOut(job_ids))
+    }
+
+    /// Queue a job, or keep it waiting if it must be approved first.
+    fn dispatch(&self, job: Job, priority: isize) -> Result<Response> {
+        let job_id = job.id().to_string();
+
+        if job.requires_approval() {
+            // The job will be queued only after it's approved
+            self.notify_pending_approval(&job);
+            println!(
+                "Job {} of the '{}' script is waiting for approval",
+                job_id, job.script_name(),
+            );
+            self.approvals.lock().unwrap().insert(job, priority);
+
+            Ok(Response::PendingApproval(job_id))
+        } else {
+            self.processor.lock().unwrap().queue(job, priority)?;
+
+            Ok(Response::Queued(job_id))
+        }
+    }
+
+    /// Execute the jobs held by the freezes which ended.
+    pub fn release_held_jobs(&self) {
+        // Don't queue jobs while Fisher is reloading
+        if self.locked.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut freezes = self.freezes.lock().unwrap();
+        let mut released = freezes.release(utils::unix_now()).into_iter();
+        while let Some(held) = released.next() {
+            let failed = match self.dispatch(held.job.clone(), held.priority) {
+                Ok(_) => {
+                    println!(
+                        "Job {} of the '{}' script released after the freeze",
+                        held.job.id(), held.job.script_name(),
+                    );
+                    continue;
+                }
+                Err(err) => dispatch_failed(err),
+            };
+
+            match failed {
+                // The script is still failing, so the job is dropped
+                Response::CircuitOpen => println!(
+                    "Job {} of the '{}' script dropped: its circuit breaker \
+                     is open", held.job.id(), held.job.script_name(),
+                ),
+                Response::BudgetExhausted => println!(
+                    "Job {} of the '{}' script dropped: its daily budget is \
+                     exhausted", held.job.id(), held.job.script_name(),
+                ),
+                // Try again later if the queue is full, or if the job
+                // couldn't be queued for other reasons
+                _ => {
+                    let mut remaining = vec![held];
+                    remaining.extend(released);
+                    freezes.restore(remaining);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Process a request sent to one of the custom paths of a hook.
+    pub fn process_custom_path(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        let path = format!("/{}", args[0]);
+        match self.paths.resolve(&self.hooks, &path) {
+            Some(hook) => {
+                self.serve_hook(req, vec![hook.name().into()], true)
+            }
+            None => Response::NotFound,
+        }
+    }
+
+    /// Return the limits of the requests sent to a custom path.
+    pub fn custom_path_limits(&self, args: &[String]) -> BodyLimits {
+        self.paths
+            .resolve(&self.hooks, &format!("/{}", args[0]))
+            .map(|hook| hook.body_limits().clone())
+            .unwrap_or_default()
+    }
+
+    /// Return the limits of the requests to a hook.
+    pub fn hook_limits(&self, args: &[String]) -> BodyLimits {
+        self.hooks
+            .get_by_name(&args[0])
+            .map(|hook| hook.body_limits().clone())
+            .unwrap_or_default()
+    }
+
+    /// Check if the request is muted by a global or hook blackout, recording
+    /// it in the `fisher_muted_events_total` metric.
+    fn is_muted(
+        &self, hook: &Script, provider: Option<&Arc<Provider>>, req: &Request,
+    ) -> bool {
+        let provider_name = provider.map(|provider| provider.name());
+        let event = provider.and_then(|provider| provider.event_name(req));
+        let now = utils::unix_now();
+
+        let muted = self.blackouts
+            .iter()
+            .chain(hook.blackouts().iter())
+            .any(|b| b.mutes(provider_name, event.as_deref(), now));
+
+        if muted {
+            metrics::increment(
+                "fisher_muted_events_total",
+                &[
+                    ("hook", hook.name()),
+                    ("provider", provider_name.unwrap_or("")),
+                    ("event", event.as_deref().unwrap_or("")),
+                ],
+            );
+        }
+        muted
+    }
+
+    fn is_new_delivery(
+        &self, hook_name: &str, provider: &Provider, req: &Request,
+    ) -> bool {
+        let deliveries = if let Some(ref deliveries) = self.deliveries {
+            deliveries
+        } else {
+            return true;
+        };
+
+        if let Some(key) = delivery_key(hook_name, provider, req) {
+            match deliveries.lock().unwrap().insert(key) {
+                Ok(new) => new,
+                Err(err) => {
+                    // Failing to persist the cache shouldn't drop requests
+                    err.pretty_print();
+                    true
+                }
+            }
+        } else {
+            true
+        }
+    }
+
+    /// Forget a delivery recorded by `is_new_delivery`, when its job
+    /// couldn't be queued.
+    fn forget_delivery(
+        &self, hook_name: &str, provider: &Provider, req: &Request,
+    ) {
+        if let Some(ref deliveries) = self.deliveries {
+            if let Some(key) = delivery_key(hook_name, provider, req) {
+                if let Err(err) = deliveries.lock().unwrap().remove(&key) {
+                    err.pretty_print();
+                }
+            }
+        }
+    }
+
+    /// Keep the payload of a request which created some jobs, so it can be
+    /// replayed later, and archive it if archiving is enabled.
+    fn store_payload(
+        &self, hook_name: &str, provider: Option<&Arc<Provider>>,
+        req: &Request, response: &Response,
+    ) {
+        let job_ids = match *response {
+            Response::Queued(ref id)
+            | Response::PendingApproval(ref id)
+            | Response::Held(ref id) => vec![id.clone()],
+            Response::FannedOut(ref ids) => ids.clone(),
+            _ => return,
+        };
+        let web = if let Ok(web) = req.web() {
+            web
+        } else {
+            return;
+        };
+
+        // Deliveries without an ID are identified by their first job
+        let delivery = provider
+            .and_then(|provider| provider.delivery_id(req))
+            .or_else(|| job_ids.first().cloned());
+        if let Some(delivery) = delivery {
+            let now = utils::unix_now();
+            if let Some(ref archive) = self.archive {
+                archive.store(hook_name, &delivery, job_ids.clone(), web, now);
+            }
+
+            let stored = self.payloads.lock().unwrap().insert(
+                hook_name, &delivery, job_ids, web, now,
+            );
+            if let Err(err) = stored {
+                // Failing to persist the payloads shouldn't drop requests
+                err.pretty_print();
+            }
+        }
+    }
+
+    /// Check if the request is authenticated with the admin token. Admin
+    /// endpoints are disabled if no token is configured.
+    fn is_admin(&self, req: &Request) -> bool {
+        let token = if let Some(ref token) = self.admin_token {
+            token
+        } else {
+            return false;
+        };
+
+        req.web()
+            .ok()
+            .and_then(bearer_token)
+            .map_or(false, |header| utils::secure_compare(header, token))
+    }
+
+    /// Check if the request comes from the machine Fisher is running on.
+    fn is_local(&self, req: &WebRequest) -> bool {
+        req.source.is_loopback() || req.source == self.bind_ip
+    }
+
+    pub fn admin_verify(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let web = req.web().unwrap();
+        let input: VerifyRequest = match serde_json::from_slice(&web.body) {
+            Ok(input) => input,
+            Err(err) => return Response::BadRequest(err.into()),
+        };
+
+        // The configuration can be provided either as the JSON object or as
+        // the string contained in the configuration comment
+        let config = match input.config {
+            Value::String(string) => string,
+            other => other.to_string(),
+        };
+
+        // The configuration is not expanded, to avoid disclosing the
+        // environment, the files and the Vault secrets of this machine
+        let mut trace = ValidationTrace::new();
+        let result = match Provider::new_unexpanded(&input.provider, &config) {
+            Ok(provider) => {
+                trace.check("config", true);
+                let mut simulated = WebRequest {
+                    source: web.source,
+                    headers: input.headers,
+                    params: input.params,
+                    path_args: Vec::new(),
+                    body: input.body.into_bytes(),
+                    spooled: None,
+                    json_body: JsonBody::default(),
+                    access: AccessDetails::default(),
+                };
+                simulated.parse_form_body();
+                provider.validate_traced(&simulated.into(), &mut trace)
+            }
+            Err(err) => {
+                trace.check("config", false);
+                let error = match *err.kind() {
+                    ErrorKind::ProviderNotFound(..) => err.to_string(),
+                    _ => "invalid provider configuration".into(),
+                };
+                return Response::Body(json!({
+                    "status": "ok",
+                    "result": {
+                        "request_type": "invalid",
+                        "checks": trace.checks(),
+                        "error": error,
+                    },
+                }));
+            }
+        };
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": {
+                "request_type": match result {
+                    RequestType::ExecuteHook => "execute_hook",
+                    RequestType::Ping => "ping",
+                    RequestType::Invalid => "invalid",
+                },
+                "checks": trace.checks(),
+            },
+        }))
+    }
+
+    /// Receive a delivery mirrored by the primary instance. The delivery is
+    /// only validated and remembered, without executing the script.
+    pub fn admin_mirror(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        // Running the scripts of mirrored deliveries after the promotion
+        // would execute them twice if the primary is still alive
+        if !self.standby.load(Ordering::Relaxed) {
+            return Response::Forbidden;
+        }
+
+        let hook = if let Some(hook) = self.hooks.get_by_name(&args[0]) {
+            hook
+        } else {
+            return Response::NotFound;
+        };
+
+        let web = req.web().unwrap();
+        let delivery: MirroredDelivery = match serde_json::from_slice(&web.body)
+        {
+            Ok(delivery) => delivery,
+            Err(err) => return Response::BadRequest(err.into()),
+        };
+        let mirrored = match delivery.into_request() {
+            Ok(request) => Request::Web(request),
+            Err(err) => return Response::BadRequest(err),
+        };
+
+        // Remember the delivery, so it's not executed again if it's
+        // redelivered after the promotion
+        let (request_type, provider) = hook.validate(&mirrored);
+        if request_type == RequestType::ExecuteHook {
+            if let Some(ref provider) = provider {
+                self.is_new_delivery(hook.name(), provider, &mirrored);
+            }
+        }
+
+        metrics::increment(
+            "fisher_mirrored_deliveries_total", &[("hook", hook.name())],
+        );
+
+        Response::Ok
+    }
+
+    /// Promote a standby instance, starting to execute scripts.
+    pub fn admin_promote(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        self.standby.store(false, Ordering::SeqCst);
+        Response::Ok
+    }
+
+    /// Load the scripts again from disk, keeping the old ones if the new
+    /// ones are invalid. The running jobs keep using the old scripts.
+    pub fn reload_scripts(
+        &self, req: &Request, _args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        // Fisher might be reloading everything in the meantime
+        if self.locked.load(Ordering::Relaxed) {
+            return Response::Unavailable;
+        }
+
+        if let Err(err) = self.blueprint.lock().unwrap().reload() {
+            err.pretty_print();
+            return Response::BadRequest(err);
+        }
+        self.processor.lock().unwrap().cleanup().unwrap();
+
+        println!(
+            "Scripts reloaded through the API ({} hooks loaded)",
+            self.hooks.iter().count(),
+        );
+        Response::Ok
+    }
+
+    /// Stop starting new jobs, while still accepting and queueing them.
+    pub fn pause_processor(
+        &self, req: &Request, _args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        self.processor.lock().unwrap().pause().unwrap();
+        Response::Ok
+    }
+
+    /// Start executing the queued jobs again after a pause.
+    pub fn resume_processor(
+        &self, req: &Request, _args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        self.processor.lock().unwrap().resume().unwrap();
+        Response::Ok
+    }
+
+    /// List the jobs waiting for approval.
+    pub fn admin_approvals(
+        &self, req: &Request, _args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let approvals = self.approvals.lock().unwrap();
+        let pending = approvals
+            .pending()
+            .iter()
+            .map(|pending| json!({
+                "job_id": pending.job.id(),
+                "script": pending.job.script_name(),
+                "received_at": utils::timestamp_json(pending.received_at),
+            }))
+            .collect::<Vec<_>>();
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": pending,
+        }))
+    }
+
+    /// Approve a job, queueing it for execution.
+    pub fn admin_approve(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let web = req.web().unwrap();
+        let input: ApproveRequest = match serde_json::from_slice(&web.body) {
+            Ok(input) => input,
+            Err(err) => return Response::BadRequest(err.into()),
+        };
+        if input.approver.trim().is_empty() {
+            return Response::BadRequest(ErrorKind::ApproverMissing.into());
+        }
+
+        self.approve_job(&args[0], &input.approver)
+    }
+
+    /// Reject a job, which will never be executed.
+    pub fn admin_reject(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        self.reject_job(&args[0], "the admin API")
+    }
+
+    /// Show the page confirming the approval of a job with a signed link.
+    pub fn approve_link_page(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        self.link_page(req, &args[0], "approve")
+    }
+
+    /// Show the page confirming the rejection of a job with a signed link.
+    pub fn reject_link_page(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        self.link_page(req, &args[0], "reject")
+    }
+
+    fn link_page(&self, req: &Request, job_id: &str, action: &str) -> Response {
+        if !self.is_valid_link(req, job_id, action) {
+            return Response::Forbidden;
+        }
+        let links = self.approval_links.as_ref().unwrap();
+
+        let approvals = self.approvals.lock().unwrap();
+        let pending = if let Some(pending) = approvals.get(job_id) {
+            pending
+        } else {
+            return Response::NotFound;
+        };
+
+        let params = &req.web().unwrap().params;
+        Response::Custom {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: links.confirmation_page(
+                pending, action, &params["expires"], &params["token"],
+            ),
+        }
+    }
+
+    /// Approve a job with a signed link, once its page is confirmed.
+    pub fn approve_link(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_valid_link(req, &args[0], "approve") {
+            return Response::Forbidden;
+        }
+
+        self.approve_job(&args[0], "an approval link")
+    }
+
+    /// Reject a job with a signed link, once its page is confirmed.
+    pub fn reject_link(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_valid_link(req, &args[0], "reject") {
+            return Response::Forbidden;
+        }
+
+        self.reject_job(&args[0], "a rejection link")
+    }
+
+    fn is_valid_link(&self, req: &Request, job_id: &str, action: &str) -> bool {
+        let links = if let Some(ref links) = self.approval_links {
+            links
+        } else {
+            return false;
+        };
+
+        let params = &req.web().unwrap().params;
+        match (params.get("expires"), params.get("token")) {
+            (Some(expires), Some(token)) => {
+                links.verify(job_id, action, expires, token)
+            }
+            _ => false,
+        }
+    }
+
+    fn approve_job(&self, job_id: &str, approver: &str) -> Response {
+        if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        let mut approvals = self.approvals.lock().unwrap();
+        let pending = if let Some(pending) = approvals.take(job_id) {
+            pending
+        } else {
+            return Response::NotFound;
+        };
+
+        let queued = self.processor
+            .lock()
+            .unwrap()
+            .queue(pending.job.clone(), pending.priority);
+
+        // Keep the job around if the queue is full or the script is failing,
+        // so it can be approved again later
+        if let Err(err) = queued {
+            approvals.restore(pending);
+            return dispatch_failed(err);
+        }
+
+        println!(
+            "Job {} of the '{}' script approved by {}",
+            pending.job.id(), pending.job.script_name(), approver,
+        );
+        audit::approval(
+            pending.job.id(), pending.job.script_name(), true, approver,
+        );
+
+        Response::Queued(pending.job.id().to_string())
+    }
+
+    fn reject_job(&self, job_id: &str, rejected_by: &str) -> Response {
+        if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        if let Some(pending) = self.approvals.lock().unwrap().take(job_id) {
+            println!(
+                "Job {} of the '{}' script rejected by {}",
+                pending.job.id(), pending.job.script_name(), rejected_by,
+            );
+            audit::approval(
+                pending.job.id(), pending.job.script_name(), false,
+                rejected_by,
+            );
+            Response::Ok
+        } else {
+            Response::NotFound
+        }
+    }
+
+    /// Notify the status hooks a job is waiting for approval.
+    fn notify_pending_approval(&self, job: &Job) {
+        if !job.trigger_status_hooks() {
+            return;
+        }
+
+        let event = StatusEvent::JobPendingApproval(PendingApproval {
+            job_id: job.id().to_string(),
+            script_name: job.script_name().to_string(),
+            request_ip: job.request_ip(),
+            links: self.approval_links
+                .as_ref()
+                .map(|links| links.create(job.id())),
+        });
+
+        let processor = self.processor.lock().unwrap();
+        for status_job in self.hooks.status_jobs(event) {
+            // Failing to notify shouldn't prevent the approval
+            if let Err(err) = processor.queue(status_job, STATUS_EVENTS_PRIORITY)
+            {
+                err.pretty_print();
+            }
+        }
+    }
+
+    /// List the running jobs, with the progress reported by their scripts.
+    pub fn admin_running(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": self.running.lock().unwrap().list(),
+        }))
+    }
+
+    /// List the jobs waiting in the queue, and the job executed by every
+    /// thread of the processor.
+    pub fn get_queue(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        match self.processor.lock().unwrap().queue_details() {
+            Ok(details) => Response::Body(json!({
+                "status": "ok",
+                "result": details,
+            })),
+            Err(_) => Response::Unavailable,
+        }
+    }
+
+    /// Get the state of a job, with the progress reported by its script if
+    /// it's running.
+    pub fn job_status(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let status = self.processor.lock().unwrap().job_status(&args[0]);
+        let status = match status {
+            Ok(Some(status)) => status,
+            Ok(None) => return Response::NotFound,
+            Err(_) => return Response::Unavailable,
+        };
+
+        let progress = self.running.lock().unwrap().get(&status.job_id);
+        let mut result = serde_json::to_value(&status).unwrap();
+        result["progress"] = json!(progress.map(|progress| json!({
+            "percent": progress.percent,
+            "message": progress.message,
+        })));
+
+        let artifacts = self.artifacts.as_ref().map_or_else(
+            || Ok(Vec::new()), |artifacts| artifacts.list(&status.job_id),
+        );
+        result["artifacts"] = match artifacts {
+            Ok(artifacts) => json!(artifacts),
+            Err(err) => {
+                err.pretty_print();
+                return Response::Unavailable;
+            }
+        };
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": result,
+        }))
+    }
+
+    /// Get the output of a running job captured so far, or stream it until
+    /// the job ends if `follow=true` is passed.
+    pub fn job_logs(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let output = match self.running.lock().unwrap().output(&args[0]) {
+            Some(output) => output,
+            None => return Response::NotFound,
+        };
+
+        let follow = req.web().unwrap().params.get("follow")
+            .map_or(false, |follow| follow == "true");
+        if follow {
+            return Response::Stream(log_stream::follow(output));
+        }
+
+        let (content, _) = output.lock().unwrap().read_from(0);
+        Response::Custom {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body: String::from_utf8_lossy(&content).into_owned(),
+        }
+    }
+
+    /// Cancel a job still waiting to be executed, returning whether it was
+    /// cancelled or it already started.
+    pub fn cancel_job(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        let status = self.processor.lock().unwrap().cancel_job(&args[0]);
+        let status = match status {
+            Ok(Some(status)) => status,
+            Ok(None) => return Response::NotFound,
+            Err(_) => return Response::Unavailable,
+        };
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": {
+                "job_id": status.job_id,
+                "cancelled": status.state == JobState::Cancelled,
+                "state": status.state,
+            },
+        }))
+    }
+
+    /// Kill the script of a running job, terminating its process group and
+    /// killing it after a grace period.
+    pub fn kill_job(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        if !self.running.lock().unwrap().kill(&args[0]) {
+            return Response::NotFound;
+        }
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": {
+                "job_id": args[0],
+                "killed": true,
+            },
+        }))
+    }
+
+    /// List the processes attributed to Fisher, and how many processes of
+    /// each hook had to be killed since Fisher started.
+    pub fn admin_processes(
+        &self, req: &Request, _args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let force_killed = metrics::snapshot()
+            .into_iter()
+            .filter(|&((name, _), _)| {
+                name == "fisher_processes_force_killed_total"
+            })
+            .filter_map(|((_, labels), count)| {
+                labels.into_iter()
+                    .find(|&(key, _)| key == "hook")
+                    .map(|(_, hook)| (hook, count))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": {
+                "processes": utils::attributed_processes(),
+                "force_killed": force_killed,
+            },
+        }))
+    }
+
+    /// Show the status dashboard. Since browsers can't send the admin token
+    /// as a bearer token, they can log in with it as the password.
+    pub fn dashboard(&self, req: &Request, _args: Vec<String>) -> Response {
+        let token = if let Some(ref token) = self.admin_token {
+            token
+        } else {
+            return Response::Forbidden;
+        };
+
+        let logged_in = req.web()
+            .ok()
+            .and_then(basic_password)
+            .map_or(false, |password| utils::secure_compare(password, token));
+        if !logged_in && !self.is_admin(req) {
+            return Response::LoginRequired;
+        }
+
+        let (health, queue, recent) = {
+            let processor = self.processor.lock().unwrap();
+            match (
+                processor.health_details(),
+                processor.queue_details(),
+                processor.recent_jobs(DASHBOARD_RECENT_JOBS),
+            ) {
+                (Ok(health), Ok(queue), Ok(recent)) => (health, queue, recent),
+                _ => return Response::Unavailable,
+            }
+        };
+
+        let mut names = self.hooks
+            .iter()
+            .map(|hook| hook.name().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        let dashboard = Dashboard {
+            health, queue, recent,
+            running: self.running.lock().unwrap().list(),
+            hooks: HookStats::collect(&names),
+        };
+        Response::Custom {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: dashboard.render(),
+        }
+    }
+
+    /// List the loaded hooks, with their providers, the events they accept
+    /// and the URLs they're reachable at.
+    pub fn list_hooks(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let mut hooks = self.hooks
+            .iter()
+            .map(|hook| {
+                let providers = hook.providers
+                    .iter()
+                    .map(|provider| json!({
+                        "name": provider.name(),
+                        "events": provider.accepted_events(),
+                    }))
+                    .collect::<Vec<_>>();
+
+                let mut urls = vec![format!("/hook/{}", hook.name())];
+                urls.extend(self.paths.paths_of(&hook));
+
+                (hook.name().to_string(), json!({
+                    "name": hook.name(),
+                    "providers": providers,
+                    "urls": urls,
+                }))
+            })
+            .collect::<Vec<_>>();
+        hooks.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": hooks.into_iter().map(|(_, hook)| hook)
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    /// List the payloads kept for a hook, newest first.
+    pub fn list_payloads(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let payloads = self.payloads.lock().unwrap();
+        Response::Body(json!({
+            "status": "ok",
+            "result": payloads
+                .list(&args[0])
+                .into_iter()
+                .map(|payload| payload.summary())
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    /// Queue again a payload kept for a hook, as if it was just received.
+    /// The payload is validated again, but it's never treated as a
+    /// duplicate delivery.
+    pub fn replay_payload(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        if self.locked.load(Ordering::Relaxed)
+            || self.standby.load(Ordering::Relaxed)
+        {
+            return Response::Unavailable;
+        }
+
+        let payload = self.payloads.lock().unwrap().get(&args[0], &args[1])
+            .cloned();
+        let (payload, hook) = match (payload, self.hooks.get_by_name(&args[0]))
+        {
+            (Some(payload), Some(hook)) => (payload, hook),
+            _ => return Response::NotFound,
+        };
+        let replayed: Request = match payload.request.into_request() {
+            Ok(request) => request.into(),
+            Err(err) => return Response::BadRequest(err),
+        };
+
+        // The secrets of the hook might have changed since then
+        let (request_type, provider) = hook.validate(&replayed);
+        if request_type != RequestType::ExecuteHook {
+            return Response::Forbidden;
+        }
+
+        let freeze = self.freezes
+            .lock()
+            .unwrap()
+            .active(hook.name(), utils::unix_now())
+            .cloned();
+        if let Some(ref freeze) = freeze {
+            if freeze.action == FreezeAction::Reject {
+                return Response::Frozen(freeze.reason.clone());
+            }
+        }
+
+        let fan_out = provider
+            .as_ref()
+            .and_then(|provider| provider.fan_out(&replayed));
+        let dispatched = if let Some(ref scripts) = fan_out {
+            self.fan_out(
+                scripts, &provider, &replayed, freeze.is_some(),
+                &TraceContext::new(),
+            )
+        } else {
+            let job = Job::new(hook.clone(), provider.clone(), replayed);
+            self.queue_job(&hook, job, freeze.is_some(), false)
+                .map(|(response, _)| response)
+        };
+
+        match dispatched {
+            Ok(response) => {
+                println!(
+                    "Delivery {} of the '{}' hook replayed",
+                    payload.delivery, hook.name(),
+                );
+                response
+            }
+            Err(err) => dispatch_failed(err),
+        }
+    }
+
+    /// Queue a job of a hook without validating the request with its
+    /// providers, setting the environment variables provided in the body.
+    pub fn trigger_hook(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        if self.locked.load(Ordering::Relaxed)
+            || self.standby.load(Ordering::Relaxed)
+        {
+            return Response::Unavailable;
+        }
+
+        let hook = match self.hooks.get_by_name(&args[0]) {
+            Some(hook) => hook,
+            None => return Response::NotFound,
+        };
+
+        // The body is optional
+        let web = req.web().unwrap();
+        let input: TriggerRequest = if web.body.is_empty() {
+            TriggerRequest::default()
+        } else {
+            match serde_json::from_slice(&web.body) {
+                Ok(input) => input,
+                Err(err) => return Response::BadRequest(err.into()),
+            }
+        };
+        for name in input.env.keys() {
+            if name.is_empty() || utils::env_var_name(name) != *name {
+                return Response::BadRequest(
+                    ErrorKind::TriggerInvalidEnvName(name.clone()).into(),
+                );
+            }
+        }
+
+        let freeze = self.freezes
+            .lock()
+            .unwrap()
+            .active(hook.name(), utils::unix_now())
+            .cloned();
+        if let Some(ref freeze) = freeze {
+            if freeze.action == FreezeAction::Reject {
+                return Response::Frozen(freeze.reason.clone());
+            }
+        }
+
+        // The body was meant for Fisher, not for the script
+        let trigger = WebRequest {
+            source: web.source,
+            headers: utils::Headers::new(),
+            params: HashMap::new(),
+            path_args: Vec::new(),
+            body: Vec::new(),
+            spooled: None,
+            json_body: JsonBody::default(),
+            access: AccessDetails::default(),
+        };
+        let mut job = Job::new(hook.clone(), None, trigger.into());
+        job.set_trigger_env(input.env);
+        let job_id = job.id().to_string();
+
+        let wait = hook.wait() || wants_to_wait(req);
+        match self.queue_job(&hook, job, freeze.is_some(), wait) {
+            Ok((response, output)) => {
+                println!(
+                    "Job {} of the '{}' script triggered manually",
+                    job_id, hook.name(),
+                );
+                match (response, output) {
+                    (Response::Queued(job_id), Some(output)) => {
+                        self.wait_for_job(&hook, job_id, output)
+                    }
+                    (response, _) => response,
+                }
+            }
+            Err(err) => dispatch_failed(err),
+        }
+    }
+
+    /// List the custom paths of every hook, including the secret ones.
+    pub fn admin_paths(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let paths = self.hooks
+            .iter()
+            .map(|hook| (hook.name().to_string(), self.paths.paths_of(&hook)))
+            .filter(|&(_, ref paths)| !paths.is_empty())
+            .collect::<HashMap<_, _>>();
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": paths,
+        }))
+    }
+
+    /// List the active freezes and the jobs held by them.
+    pub fn admin_freezes(&self, req: &Request, _args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let freezes = self.freezes.lock().unwrap();
+        let held = freezes
+            .held()
+            .iter()
+            .map(|held| json!({
+                "job_id": held.job.id(),
+                "script": held.job.script_name(),
+                "received_at": utils::timestamp_json(held.received_at),
+            }))
+            .collect::<Vec<_>>();
+
+        Response::Body(json!({
+            "status": "ok",
+            "result": {
+                "freezes": freezes.list(utils::unix_now()),
+                "held_jobs": held,
+            },
+        }))
+    }
+
+    /// Freeze every script, or only the one in the URL.
+    pub fn admin_freeze(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        let script = args.first().map(|s| s.as_str());
+        if let Some(script) = script {
+            if self.hooks.get_by_name(script).is_none() {
+                return Response::NotFound;
+            }
+        }
+
+        let web = req.web().unwrap();
+        let input: FreezeRequest = match serde_json::from_slice(&web.body) {
+            Ok(input) => input,
+            Err(err) => return Response::BadRequest(err.into()),
+        };
+        if input.reason.trim().is_empty() {
+            return Response::BadRequest(ErrorKind::FreezeReasonMissing.into());
+        }
+
+        self.freezes.lock().unwrap().freeze(freezes::new_freeze(
+            script,
+            &input.reason,
+            input.action,
+            input.expires_in.map(|time| time.as_u64()),
+        ));
+        println!(
+            "Froze {}: {}",
+            script.map(|s| format!("the '{}' script", s))
+                .unwrap_or_else(|| "all the scripts".into()),
+            input.reason,
+        );
+
+        Response::Ok
+    }
+
+    /// Lift the global freeze, or the one of the script in the URL.
+    pub fn admin_unfreeze(&self, req: &Request, args: Vec<String>) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        } else if self.read_only {
+            return Response::ReadOnly;
+        }
+
+        let script = args.first().map(|s| s.as_str());
+        if !self.freezes.lock().unwrap().lift(script) {
+            return Response::NotFound;
+        }
+
+        // Don't wait for the held jobs to be released in the background
+        self.release_held_jobs();
+
+        Response::Ok
+    }
+
+    /// Store a file uploaded by the script of a running job. The token of
+    /// the job is known only by its script, and the upload must come from
+    /// the machine Fisher is running on.
+    pub fn upload_artifact(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        let artifacts = if let Some(ref artifacts) = self.artifacts {
+            artifacts
+        } else {
+            return Response::NotFound;
+        };
+
+        let web = req.web().unwrap();
+        if !self.is_local(web) {
+            return Response::Forbidden;
+        }
+
+        let authorized = {
+            let uploads = self.uploads.lock().unwrap();
+            match (uploads.token(&args[0]), bearer_token(web)) {
+                (Some(expected), Some(token)) => {
+                    utils::secure_compare(token, expected)
+                }
+                _ => false,
+            }
+        };
+        if !authorized {
+            return Response::Forbidden;
+        }
+
+        let name = &args[1];
+        if !artifacts::valid_name(name) {
+            return Response::BadRequest(
+                ErrorKind::ArtifactInvalidName(name.clone()).into(),
+            );
+        }
+
+        // Big artifacts might have been spooled to disk
+        let stored = web.body_reader().and_then(|body| {
+            artifacts.store(&args[0], name, body)
+        });
+        if let Err(err) = stored {
+            err.pretty_print();
+            return Response::Unavailable;
+        }
+        self.uploads.lock().unwrap().add(&args[0], name);
+
+        Response::Ok
+    }
+
+    /// Download an artifact uploaded by the script of a job.
+    pub fn download_artifact(
+        &self, req: &Request, args: Vec<String>,
+    ) -> Response {
+        if !self.is_admin(req) {
+            return Response::Forbidden;
+        }
+
+        let artifacts = if let Some(ref artifacts) = self.artifacts {
+            artifacts
+        } else {
+            return Response::NotFound;
+        };
+
+        // Both the arguments end up in the path of the artifact
+        if !artifacts::valid_name(&args[0]) || !artifacts::valid_name(&args[1])
+        {
+            return Response::NotFound;
+        }
+
+        match artifacts.read(&args[0], &args[1]) {
+            Ok(Some(content)) => Response::Download(content),
+            Ok(None) => Response::NotFound,
+            Err(err) => {
+                err.pretty_print();
+                Response::Unavailable
+            }
+        }
+    }
+
     pub fn get_health(&self, _req: &Request, _args: Vec<String>) -> Response {
         if self.health_enabled {
-            Response::HealthStatus(
-                self.processor.lock().unwrap().health_details().unwrap(),
-            )
+            let mut details =
+                self.processor.lock().unwrap().health_details().unwrap();
+            details.build = Some(self.build_info.read().unwrap().clone());
+            details.freezes = Some(
+                self.freezes.lock().unwrap().list(utils::unix_now())
+            );
+
+            Response::HealthStatus(details)
         } else {
             Response::Forbidden
         }
     }
+
+    pub fn get_metrics(&self, _req: &Request, _args: Vec<String>) -> Response {
+        if !self.metrics_enabled {
+            return Response::Forbidden;
+        }
+
+        // The gauges describing the processor are updated when scraped
+        let details = self.processor.lock().unwrap().health_details().unwrap();
+        metrics::set("fisher_queue_depth", &[], details.queued_jobs as u64);
+        metrics::set("fisher_active_jobs", &[], details.busy_threads as u64);
+        metrics::set("fisher_max_threads", &[], details.max_threads as u64);
+        metrics::set("fisher_paused", &[], details.paused as u64);
+
+        Response::Custom {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: metrics::prometheus(),
+        }
+    }
+}
+
+
+/// Reply with the response configured by the hook for the outcome of the
+/// request, if any
+fn customize_response(hook: &Script, response: Response) -> Response {
+    let responses = hook.responses();
+    let custom = match response {
+        Response::Queued(..) | Response::FannedOut(..) => &responses.queued,
+        Response::Ok
+        | Response::Muted
+        | Response::PendingApproval(..)
+        | Response::Held(..) => &responses.accepted,
+        Response::Forbidden => &responses.rejected,
+        _ => &None,
+    };
+
+    match *custom {
+        Some(ref custom) => response.customize(custom, hook.name()),
+        None => response,
+    }
+}
+
+
+/// Reply with the body requested by the provider, if it wants a custom one
+fn provider_response(
+    provider: Option<&Arc<Provider>>,
+    req: &Request,
+    request_type: RequestType,
+    default: Response,
+) -> Response {
+    provider
+        .and_then(|provider| provider.response_body(req, request_type))
+        .map(Response::Body)
+        .unwrap_or(default)
+}
+
+
+/// Record the deliveries the provider accepted without executing the hook,
+/// like the ones containing skip keywords, in the
+/// `fisher_skipped_deliveries_total` metric.
+fn record_skipped(
+    hook: &Script, provider: Option<&Arc<Provider>>, response: &Response,
+) {
+    let skipped = match *response {
+        Response::Body(ref body) => body["status"] == "skipped",
+        _ => false,
+    };
+    if skipped {
+        metrics::increment(
+            "fisher_skipped_deliveries_total",
+            &[
+                ("hook", hook.name()),
+                ("provider", provider.map_or("", |p| p.name())),
+            ],
+        );
+    }
+}
+
+
+/// Wait for a job to end and get its result, or the usual response if it
+/// takes longer than `max_wait`.
+fn job_result(
+    job_id: String, output: Receiver<JobOutput>, max_wait: Duration,
+    wait_output: bool,
+) -> Response {
+    // Jobs ending without an output, like the ones dropped from the queue or
+    // whose script can't be started, just failed
+    let output = match output.recv_timeout(max_wait) {
+        Ok(output) => Some(output),
+        Err(RecvTimeoutError::Timeout) => return Response::Queued(job_id),
+        Err(RecvTimeoutError::Disconnected) => None,
+    };
+
+    let mut result = json!({
+        "success": output.as_ref().map_or(false, |o| o.success),
+        "exit_code": output.as_ref().and_then(|o| o.exit_code),
+        "signal": output.as_ref().and_then(|o| o.signal),
+    });
+    match output {
+        Some(ref output) if wait_output => {
+            let (stdout, stdout_cut) = tail_of(&output.stdout);
+            let (stderr, stderr_cut) = tail_of(&output.stderr);
+            result["stdout"] = stdout.into();
+            result["stderr"] = stderr.into();
+            result["truncated"] = (stdout_cut || stderr_cut).into();
+        }
+        _ => {}
+    }
+
+    Response::Body(json!({
+        "status": "ok",
+        "job_id": job_id,
+        "result": result,
+    }))
+}
+
+
+/// Describe the outcome of a request to a hook, for the metrics.
+fn request_outcome(response: &Response) -> &'static str {
+    match *response {
+        Response::Queued(..)
+        | Response::FannedOut(..)
+        | Response::Waiting(..)
+        | Response::Body(..) => "queued",
+        Response::PendingApproval(..) => "pending-approval",
+        Response::Held(..) => "held",
+        Response::Ok => "accepted",
+        Response::Muted => "muted",
+        Response::Forbidden => "rejected",
+        Response::BadRequest(..) => "invalid",
+        Response::Frozen(..) => "frozen",
+        Response::CircuitOpen => "circuit-open",
+        Response::BudgetExhausted => "budget-exhausted",
+        Response::Unavailable => "unavailable",
+        _ => "other",
+    }
+}
+
+
+/// Get the key a delivery is remembered with, if the provider identifies
+/// its deliveries.
+fn delivery_key(
+    hook_name: &str, provider: &Provider, req: &Request,
+) -> Option<String> {
+    provider.delivery_id(req)
+        .map(|id| format!("{}:{}:{}", hook_name, provider.name(), id))
+}
+
+
+/// Get the response to a request whose job couldn't be queued.
+fn dispatch_failed(err: Error) -> Response {
+    match err {
+        Error(ErrorKind::JobRejected(..), _) => Response::Unavailable,
+        Error(ErrorKind::CircuitOpen(..), _) => Response::CircuitOpen,
+        Error(ErrorKind::BudgetExhausted(..), _) => Response::BudgetExhausted,
+        err => {
+            err.pretty_print();
+            Response::Unavailable
+        }
+    }
+}
+
+
+/// Check if the request asked to wait for its job to end, with the
+/// `wait=true` query string parameter.
+fn wants_to_wait(req: &Request) -> bool {
+    req.web()
+        .ok()
+        .and_then(|r| r.params.get("wait"))
+        .map_or(false, |wait| wait == "true")
+}
+
+
+/// Get the end of an output, and whether it had to be truncated.
+fn tail_of(output: &str) -> (&str, bool) {
+    if output.len() <= MAX_WAIT_OUTPUT {
+        return (output, false);
+    }
+
+    let mut start = output.len() - MAX_WAIT_OUTPUT;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    (&output[start..], true)
+}
+
+
+/// Get the token sent in the `Authorization: Bearer <token>` header
+fn bearer_token(req: &WebRequest) -> Option<&str> {
+    req.headers
+        .get("Authorization")
+        .filter(|header| header.starts_with("Bearer "))
+        .map(|header| &header["Bearer ".len()..])
+}
+
+
+/// Get the password sent with HTTP basic authentication, ignoring the
+/// username.
+fn basic_password(req: &WebRequest) -> Option<String> {
+    req.headers
+        .get("Authorization")
+        .filter(|header| header.starts_with("Basic "))
+        .and_then(|header| base64::decode(&header["Basic ".len()..]).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|credentials| {
+            credentials.splitn(2, ':').nth(1).map(String::from)
+        })
 }
@@ -13,60 +13,446 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
 
-use tiny_http::Method;
+use hyper::Method;
 
 use common::prelude::*;
 use common::config::HttpConfig;
+use common::structs::BuildInfo;
 
-use scripts::Repository;
+use scripts::{ArtifactUploads, Blueprint, Repository, RunningJobs};
 use web::http::HttpServer;
-use web::api::WebApi;
+use web::access_log::AccessLog;
+use web::api::{WebApi, WebApiOptions};
+use web::approvals::{ApprovalLinks, Approvals};
+use web::artifacts::Artifacts;
+use web::cors::Cors;
+use web::deliveries::DeliveriesCache;
+use web::freezes::Freezes;
+use web::ip_filter::IpFilter;
+use web::frontend::ConnectionLimits;
+use web::archive::Archive;
+use web::mirror::Mirror;
+use web::paths::HookPaths;
+use web::payloads::Payloads;
+use web::proxies::ProxySupport;
+use web::rate_limits::{RateLimiter, TokenBuckets};
+use web::tls;
+
+
+/// The prefix of the routes of the current version of the API.
+const API_PREFIX: &str = "/v1";
+
+
+/// Get the URL the scripts can reach the API at, from the machine Fisher is
+/// running on.
+fn local_url(addr: &SocketAddr, tls: bool) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        }
+        ip => ip,
+    };
+
+    format!(
+        "{}://{}{}",
+        if tls { "https" } else { "http" },
+        SocketAddr::new(ip, addr.port()),
+        API_PREFIX,
+    )
+}
 
 
 pub struct WebApp<A: ProcessorApiTrait<Repository> + 'static> {
     server: HttpServer<WebApi<A>>,
     addr: SocketAddr,
     locked: Arc<AtomicBool>,
+    tls_files: Option<(String, String)>,
+    stop_releaser: mpsc::Sender<()>,
+    releaser: thread::JoinHandle<()>,
 }
 
 impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
     pub fn new(
-        hooks: Arc<Repository>,
+        blueprint: Arc<Mutex<Blueprint>>,
         config: &HttpConfig,
         processor: A,
+        build_info: Arc<RwLock<BuildInfo>>,
+        approvals: Arc<Mutex<Approvals>>,
+        freezes: Arc<Mutex<Freezes>>,
+        uploads: Arc<Mutex<ArtifactUploads>>,
+        running: Arc<Mutex<RunningJobs>>,
+        acme_cert: Option<Arc<tls::SharedCertificate>>,
     ) -> Result<Self> {
         let locked = Arc::new(AtomicBool::new(false));
 
+        let deliveries = if config.dedup_deliveries {
+            Some(DeliveriesCache::new(
+                config.dedup_capacity,
+                config.dedup_file.as_ref().map(PathBuf::from),
+            )?)
+        } else {
+            None
+        };
+
+        let mirror = match (&config.mirror_to, &config.mirror_token) {
+            (&Some(ref url), &Some(ref token)) => Some(Mirror::new(url, token)?),
+            (&Some(..), &None) => {
+                return Err(ErrorKind::MirrorConfigIncomplete.into());
+            }
+            _ => None,
+        };
+
+        // Approval links are signed with the admin token
+        let approval_links = match (&config.public_url, &config.admin_token) {
+            (&Some(ref url), &Some(ref token)) => Some(ApprovalLinks::new(
+                url, token, config.approval_links_ttl.as_u64(),
+            )),
+            _ => None,
+        };
+
+        let artifacts = match config.artifacts_dir {
+            Some(ref dir) => Some(Artifacts::new(dir)?),
+            None => None,
+        };
+        let tls = acme_cert.is_some() || config.tls_cert.is_some();
+        let paths = HookPaths::new(
+            config.aliases.clone(), config.path_secret.as_deref(),
+        )?;
+
+        // Every client can have its own rate limit, with bursts as big as
+        // the limit itself by default
+        let ip_limiter = config.ip_rate_limit.as_ref().map(|limit| {
+            TokenBuckets::new(
+                limit.allowed,
+                limit.interval.as_u64(),
+                config.ip_rate_burst.unwrap_or(limit.allowed),
+            )
+        });
+
         // Create the web api
-        let api = WebApi::new(
-            processor, hooks, locked.clone(), &config.rate_limit,
-            config.health_endpoint,
-        );
+        let api = WebApi::new(processor, blueprint, WebApiOptions {
+            locked: locked.clone(),
+            limiter: RateLimiter::new(
+                config.rate_limit.allowed,
+                config.rate_limit.interval.as_u64(),
+            ),
+            ip_limiter,
+            ip_filter: IpFilter::new(
+                config.allow_ips.clone(), config.deny_ips.clone(),
+            ),
+            build_info,
+            deliveries,
+            payloads: Payloads::new(
+                config.keep_payloads,
+                config.payloads_file.as_ref().map(PathBuf::from),
+            )?,
+            standby: config.standby,
+            mirror,
+            archive: Archive::new(&config.archive)?,
+            approvals,
+            approval_links,
+            freezes,
+            blackouts: config.blackouts.clone(),
+            artifacts,
+            uploads: uploads.clone(),
+            bind_ip: config.bind.ip(),
+            running,
+            paths,
+            max_wait: Duration::from_secs(config.max_wait.as_u64()),
+            health_enabled: config.health_endpoint,
+            metrics_enabled: config.metrics_endpoint,
+            admin_token: config.admin_token.clone(),
+            hook_tokens: config.hook_tokens.clone(),
+            read_only: config.read_only,
+        });
 
         // Create the HTTP server
-        let mut server = HttpServer::new(api, config.behind_proxies);
-        server.add_route(Method::Get, "/health", Box::new(WebApi::get_health));
-        server.add_route(
-            Method::Get,
-            "/hook/?",
-            Box::new(WebApi::process_hook),
-        );
-        server.add_route(
-            Method::Post,
-            "/hook/?",
-            Box::new(WebApi::process_hook),
-        );
+        let proxy_support = if config.trusted_proxies.is_empty() {
+            ProxySupport::new(config.behind_proxies)
+        } else if config.behind_proxies == 0 {
+            ProxySupport::trusted(config.trusted_proxies.clone())
+        } else {
+            return Err(ErrorKind::ProxiesConfigConflict.into());
+        };
+        let mut server = HttpServer::new(api, proxy_support);
+        server.set_max_body_size(config.max_body_size);
+        server.set_workers(config.workers);
+
+        // Cross-origin requests are rejected by the browsers by default
+        if !config.cors_origins.is_empty() {
+            server.set_cors(Cors::new(
+                config.cors_origins.clone(),
+                config.cors_methods.clone(),
+                config.cors_headers.clone(),
+                config.cors_max_age.as_u64(),
+            ));
+        }
+
+        if let Some(ref path) = config.access_log {
+            server.set_access_log(
+                AccessLog::open(path, config.access_log_format)?,
+            );
+        }
+
+        // Setting any of the limits to zero disables it
+        let timeout = |secs| match secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let max = |max| match max {
+            0 => None,
+            max => Some(max),
+        };
+        server.set_connection_limits(ConnectionLimits {
+            read_timeout: timeout(config.read_timeout.as_u64()),
+            write_timeout: timeout(config.write_timeout.as_u64()),
+            keep_alive_timeout: timeout(config.keep_alive_timeout.as_u64()),
+            max_header_size: max(config.max_header_size),
+            max_connections: max(config.max_connections),
+        });
+
+        // Every route is available under the current version of the API, and
+        // without any version for compatibility
+        for prefix in &[API_PREFIX, ""] {
+            let url = |path: &str| format!("{}{}", prefix, path);
+
+            server.add_route(
+                Method::GET, &url("/health"), Box::new(WebApi::get_health),
+            );
+            server.add_route(
+                Method::GET, &url("/metrics"), Box::new(WebApi::get_metrics),
+            );
+            server.add_route_with_limits(
+                Method::GET,
+                &url("/hook/?"),
+                Box::new(WebApi::process_hook),
+                Box::new(WebApi::hook_limits),
+            );
+            server.add_route_with_limits(
+                Method::POST,
+                &url("/hook/?"),
+                Box::new(WebApi::process_hook),
+                Box::new(WebApi::hook_limits),
+            );
+            // Matrix homeservers append the transaction path to the hook URL
+            server.add_route_with_limits(
+                Method::PUT,
+                &url("/hook/?/_matrix/app/v1/transactions/?"),
+                Box::new(WebApi::process_hook),
+                Box::new(WebApi::hook_limits),
+            );
+
+            server.add_route(
+                Method::GET, &url("/dashboard"), Box::new(WebApi::dashboard),
+            );
+            server.add_route(
+                Method::GET, &url("/hooks"), Box::new(WebApi::list_hooks),
+            );
+            server.add_route(
+                Method::GET, &url("/hooks/?/payloads"),
+                Box::new(WebApi::list_payloads),
+            );
+            server.add_route(
+                Method::POST, &url("/hooks/?/replay/?"),
+                Box::new(WebApi::replay_payload),
+            );
+            server.add_route(
+                Method::POST, &url("/hooks/?/trigger"),
+                Box::new(WebApi::trigger_hook),
+            );
+            // The logs and artifacts routes must come first, since the
+            // arguments of the routes can contain slashes
+            server.add_route(
+                Method::GET, &url("/jobs/?/logs"), Box::new(WebApi::job_logs),
+            );
+            server.add_route(
+                Method::GET, &url("/jobs/?/artifacts/?"),
+                Box::new(WebApi::download_artifact),
+            );
+            server.add_route(
+                Method::POST, &url("/jobs/?/artifacts/?"),
+                Box::new(WebApi::upload_artifact),
+            );
+            server.add_route(
+                Method::GET, &url("/jobs/?"), Box::new(WebApi::job_status),
+            );
+            server.add_route(
+                Method::DELETE, &url("/jobs/?"), Box::new(WebApi::cancel_job),
+            );
+            server.add_route(
+                Method::POST, &url("/jobs/?/kill"),
+                Box::new(WebApi::kill_job),
+            );
+            server.add_route(
+                Method::GET, &url("/queue"), Box::new(WebApi::get_queue),
+            );
+            server.add_route(
+                Method::POST, &url("/processor/pause"),
+                Box::new(WebApi::pause_processor),
+            );
+            server.add_route(
+                Method::POST, &url("/processor/resume"),
+                Box::new(WebApi::resume_processor),
+            );
+            server.add_route(
+                Method::POST, &url("/reload"),
+                Box::new(WebApi::reload_scripts),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/verify"),
+                Box::new(WebApi::admin_verify),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/mirror/?"),
+                Box::new(WebApi::admin_mirror),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/promote"),
+                Box::new(WebApi::admin_promote),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/admin/approvals"),
+                Box::new(WebApi::admin_approvals),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/approvals/?/approve"),
+                Box::new(WebApi::admin_approve),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/approvals/?/reject"),
+                Box::new(WebApi::admin_reject),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/approvals/?/approve"),
+                Box::new(WebApi::approve_link_page),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/approvals/?/approve"),
+                Box::new(WebApi::approve_link),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/approvals/?/reject"),
+                Box::new(WebApi::reject_link_page),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/approvals/?/reject"),
+                Box::new(WebApi::reject_link),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/admin/running"),
+                Box::new(WebApi::admin_running),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/admin/processes"),
+                Box::new(WebApi::admin_processes),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/admin/paths"),
+                Box::new(WebApi::admin_paths),
+            );
+            server.add_route(
+                Method::GET,
+                &url("/admin/freezes"),
+                Box::new(WebApi::admin_freezes),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/freezes"),
+                Box::new(WebApi::admin_freeze),
+            );
+            server.add_route(
+                Method::POST,
+                &url("/admin/freezes/?"),
+                Box::new(WebApi::admin_freeze),
+            );
+            server.add_route(
+                Method::DELETE,
+                &url("/admin/freezes"),
+                Box::new(WebApi::admin_unfreeze),
+            );
+            server.add_route(
+                Method::DELETE,
+                &url("/admin/freezes/?"),
+                Box::new(WebApi::admin_unfreeze),
+            );
+        }
+
+        // The custom paths of the hooks are checked after all the other routes
+        for method in &[Method::GET, Method::POST] {
+            server.add_route_with_limits(
+                method.clone(),
+                "/?",
+                Box::new(WebApi::process_custom_path),
+                Box::new(WebApi::custom_path_limits),
+            );
+        }
+
+        let tls_files = if let Some(acme_cert) = acme_cert {
+            // The certificate obtained with ACME is renewed automatically
+            if config.tls_cert.is_some() || config.tls_key.is_some() {
+                return Err(ErrorKind::AcmeTlsConflict.into());
+            }
+            server.enable_tls(tls::SharedCertificate::server_config(acme_cert));
+            None
+        } else {
+            match (&config.tls_cert, &config.tls_key) {
+                (&Some(ref cert), &Some(ref key)) => {
+                    server.enable_tls(tls::load_config(cert, key)?);
+                    Some((cert.clone(), key.clone()))
+                }
+                (&None, &None) => None,
+                _ => return Err(ErrorKind::TlsConfigIncomplete.into()),
+            }
+        };
 
         let socket = server.listen(config.bind)?;
 
+        // Tell the scripts where to upload their artifacts
+        uploads.lock().unwrap().set_url(
+            config.artifacts_dir.as_ref().map(|_| local_url(&socket, tls)),
+        );
+
+        // Periodically release the jobs held by the expired freezes
+        let (stop_releaser, stop_recv) = mpsc::channel();
+        let releaser_api = server.app();
+        let releaser = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) =
+                stop_recv.recv_timeout(Duration::from_secs(1))
+            {
+                releaser_api.release_held_jobs();
+            }
+        });
+
         Ok(WebApp {
             server: server,
             addr: socket,
             locked: locked,
+            tls_files,
+            stop_releaser,
+            releaser,
         })
     }
 
@@ -74,6 +460,15 @@ impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
         &self.addr
     }
 
+    /// Load again the TLS certificate and key from their files, for example
+    /// after they're renewed. New connections will use them right away.
+    pub fn reload_tls(&mut self) -> Result<()> {
+        if let Some((ref cert, ref key)) = self.tls_files {
+            self.server.reload_tls(tls::load_config(cert, key)?)?;
+        }
+        Ok(())
+    }
+
     pub fn lock(&self) {
         self.locked.store(true, Ordering::SeqCst);
     }
@@ -84,22 +479,46 @@ impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
 
     pub fn stop(mut self) {
         self.server.stop();
+
+        let _ = self.stop_releaser.send(());
+        let _ = self.releaser.join();
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
 
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
     use serde_json;
-    use hyper::status::StatusCode;
-    use hyper::method::Method;
-    use hyper::header::Headers;
+    use hyper010::status::StatusCode;
+    use hyper010::method::Method;
+    use hyper010::header::Headers;
+    use hyper010::client::Response;
+    use tempdir::TempDir;
 
+    use common::config::{BlackoutConfig, RateLimitConfig};
     use common::prelude::*;
+    use common::metrics;
 
+    use providers::StatusEvent;
+    use requests::Request;
+    use scripts::JobContext;
+    use utils;
     use utils::testing::*;
+    use web::paths::HookPaths;
+
+    use super::local_url;
 
 
     #[test]
@@ -211,6 +630,98 @@ mod tests {
         testing_env.cleanup();
     }
 
+    #[test]
+    fn test_matrix_transactions() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // The transaction path should be stripped from the hook name
+        let res = inst.request(
+            Method::Put,
+            "/hook/example.sh/_matrix/app/v1/transactions/42?secret=testing",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        if let ProcessorApiCall::Queue(job, _) = inst.processor_input().unwrap() {
+            assert_eq!(job.script_name(), "example.sh");
+        } else {
+            panic!("Wrong processor input received");
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "matrix.sh",
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Matrix: {"secret": "abcde"}"#,
+                r#"echo "ok""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        // Retried transactions are detected by their ID
+        for &(txn, queued) in &[("1", true), ("1", false), ("2", true)] {
+            let url = format!(
+                "/hook/matrix.sh/_matrix/app/v1/transactions/{}\
+                 ?access_token=abcde",
+                txn,
+            );
+            let body = json!({"events": [{
+                "type": "m.room.message",
+                "event_id": format!("$event-{}", txn),
+                "room_id": "!a:example.com",
+                "content": {"msgtype": "m.text", "body": "hi"},
+            }]});
+            let res = inst.request(Method::Put, &url)
+                .body(body.to_string().as_str())
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            assert_eq!(inst.processor_input().is_some(), queued);
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_duplicate_deliveries() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // The first delivery should be processed
+        let res = inst.request(Method::Get, "/hook/example.sh?delivery_id=1")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        // A redelivery should be accepted but not processed
+        let res = inst.request(Method::Get, "/hook/example.sh?delivery_id=1")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_none());
+
+        // A different delivery should be processed
+        let res = inst.request(Method::Get, "/hook/example.sh?delivery_id=2")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        // The same delivery to another hook should be processed
+        let res = inst.request(Method::Get, "/hook/sub/hook.sh?delivery_id=1")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
     #[test]
     fn test_health_disabled() {
         // Create the instance with disabled health status
@@ -236,9 +747,7 @@ mod tests {
         assert_eq!(res.status, StatusCode::Ok);
 
         // Decode the output
-        let mut content = String::new();
-        res.read_to_string(&mut content).unwrap();
-        let data = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        let data = json_body(&mut res);
         let data_obj = data.as_object().unwrap();
 
         // Check the content of the returned JSON
@@ -255,6 +764,282 @@ mod tests {
             result.get("max_threads").unwrap().as_u64().unwrap(),
             3 as u64
         );
+        assert_eq!(result.get("paused").unwrap().as_bool(), Some(false));
+        assert_eq!(result.get("uptime").unwrap().as_u64(), Some(60));
+        assert_eq!(result.get("hooks").unwrap().as_u64(), Some(4));
+        assert_eq!(result.get("processed_jobs").unwrap().as_u64(), Some(10));
+        assert_eq!(result.get("failed_jobs").unwrap().as_u64(), Some(1));
+        assert_eq!(
+            result.get("workers").unwrap().as_array().unwrap().len(),
+            3
+        );
+
+        // The build information must be included
+        let build = result.get("build").unwrap().as_object().unwrap();
+        assert_eq!(
+            build.get("version").unwrap().as_str().unwrap(),
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(build.get("features").unwrap().is_array());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_metrics() {
+        let testing_env = TestingEnv::new();
+
+        // The endpoint is disabled by default
+        let mut inst = testing_env.start_web(true, 0);
+        let res = inst.request(Method::Get, "/metrics").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        inst.stop();
+
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.metrics_endpoint = true;
+        });
+
+        let res = inst.request(Method::Post, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let mut res = inst.request(Method::Get, "/metrics").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(
+            res.headers.get_raw("Content-Type").unwrap()[0],
+            b"text/plain; version=0.0.4".to_vec(),
+        );
+
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        for line in &[
+            "# TYPE fisher_requests_total counter",
+            "# TYPE fisher_queue_depth gauge",
+            "fisher_queue_depth 1",
+            "fisher_active_jobs 2",
+        ] {
+            assert!(content.lines().any(|l| l == *line), "{}", line);
+        }
+        assert!(content.lines().any(|l| l.starts_with(
+            "fisher_requests_total{hook=\"example.sh\",provider=\"Testing\",\
+             outcome=\"queued\"} ",
+        )));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_access_log() {
+        let testing_env = TestingEnv::new();
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("access.log");
+
+        let log_path = path.to_str().unwrap().to_string();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.behind_proxies = 1;
+            config.access_log = Some(log_path);
+        });
+
+        // Rejected requests are logged too
+        for &(ref method, url, status) in &[
+            (Method::Post, "/hook/example.sh?secret=testing", StatusCode::Ok),
+            (Method::Post, "/hook/example.sh?secret=no", StatusCode::Forbidden),
+            (Method::Get, "/missing", StatusCode::NotFound),
+        ] {
+            let mut headers = Headers::new();
+            headers.set_raw("X-Forwarded-For", vec![b"10.0.0.1".to_vec()]);
+            let res = inst.request(method.clone(), url)
+                .headers(headers)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, status, "{}", url);
+        }
+        inst.stop();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["client"], "10.0.0.1");
+        assert_eq!(lines[0]["method"], "POST");
+        // The query string is not logged, since it might contain secrets
+        assert_eq!(lines[0]["path"], "/hook/example.sh");
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[0]["hook"], "example.sh");
+        assert_eq!(lines[0]["provider"], "Testing");
+        assert_eq!(lines[0]["outcome"], "queued");
+        assert_eq!(lines[0]["job_ids"].as_array().unwrap().len(), 1);
+
+        assert_eq!(lines[1]["status"], 403);
+        assert_eq!(lines[1]["hook"], "example.sh");
+        assert_eq!(lines[1]["outcome"], "rejected");
+        assert_eq!(lines[1]["job_ids"], json!([]));
+
+        assert_eq!(lines[2]["path"], "/missing");
+        assert_eq!(lines[2]["status"], 404);
+        assert_eq!(lines[2]["hook"], serde_json::Value::Null);
+
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_archive_payloads() {
+        let testing_env = TestingEnv::new();
+        let dir = TempDir::new("fisher-tests").unwrap();
+
+        let directory = dir.path().to_str().unwrap().to_string();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.archive.directory = Some(directory);
+        });
+
+        let res = inst.request(
+            Method::Post, "/hook/example.sh?secret=testing&delivery_id=abc",
+        ).body("payload").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        inst.stop();
+
+        // The payloads are archived in a background thread
+        let date = &utils::TimestampFormat::Rfc3339
+            .to_string(utils::unix_now())[..10];
+        let path = dir.path().join(format!("example.sh/{}/abc.json", date));
+        for _ in 0..50 {
+            if path.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        let archived: serde_json::Value =
+            serde_json::from_str(&content).unwrap();
+        assert_eq!(archived["hook"], "example.sh");
+        assert_eq!(archived["delivery"], "abc");
+        assert_eq!(archived["job_ids"].as_array().unwrap().len(), 1);
+
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_ip_filter() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.behind_proxies = 1;
+            config.allow_ips = vec!["10.0.0.0/8".parse().unwrap()];
+            config.deny_ips = vec!["10.0.0.0/24".parse().unwrap()];
+        });
+
+        // The address of the client is the one forwarded by the proxy
+        for &(ip, status) in &[
+            ("10.1.0.1", StatusCode::Ok),
+            ("10.0.0.1", StatusCode::Forbidden),
+            ("127.0.0.1", StatusCode::Forbidden),
+        ] {
+            let mut headers = Headers::new();
+            headers.set_raw("X-Forwarded-For", vec![ip.as_bytes().to_vec()]);
+
+            let url = "/hook/example.sh?secret=testing";
+            let res = inst.request(Method::Get, url)
+                .headers(headers)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, status, "{}", ip);
+            assert_eq!(
+                inst.processor_input().is_some(), status == StatusCode::Ok,
+            );
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_hook_tokens() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.hook_tokens = serde_json::from_str(r#"["a", "b"]"#).unwrap();
+        });
+
+        for &(header, status) in &[
+            (None, StatusCode::Unauthorized),
+            (Some("Bearer c"), StatusCode::Unauthorized),
+            (Some("b"), StatusCode::Unauthorized),
+            (Some("Bearer b"), StatusCode::Ok),
+        ] {
+            let mut headers = Headers::new();
+            if let Some(header) = header {
+                headers.set_raw("Authorization", vec![header.into()]);
+            }
+
+            let url = "/hook/example.sh?secret=testing";
+            let res = inst.request(Method::Get, url)
+                .headers(headers)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, status, "{:?}", header);
+            assert_eq!(
+                inst.processor_input().is_some(), status == StatusCode::Ok,
+            );
+
+            if status == StatusCode::Unauthorized {
+                assert_eq!(
+                    res.headers.get_raw("WWW-Authenticate").unwrap()[0],
+                    b"Bearer".to_vec(),
+                );
+            }
+        }
+
+        // The token doesn't replace the validation of the providers
+        let mut headers = Headers::new();
+        headers.set_raw("Authorization", vec![b"Bearer a".to_vec()]);
+        let url = "/hook/example.sh?secret=invalid";
+        let res = inst.request(Method::Get, url)
+            .headers(headers)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_versioned_routes() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let url = "/v1/hook/example.sh?secret=testing";
+        let res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                assert_eq!(job.script_name(), "example.sh");
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // The routes without a version are the same as the ones in v1
+        let mut versioned = inst.request(Method::Get, "/v1/health")
+            .send()
+            .unwrap();
+        let mut unversioned = inst.request(Method::Get, "/health")
+            .send()
+            .unwrap();
+        assert_eq!(versioned.status, StatusCode::Ok);
+        assert_eq!(json_body(&mut versioned), json_body(&mut unversioned));
+
+        // Unknown versions don't exist
+        let res = inst.request(Method::Get, "/v2/health").send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
 
         inst.stop();
         testing_env.cleanup();
@@ -290,4 +1075,2031 @@ mod tests {
         inst.stop();
         testing_env.cleanup();
     }
+
+    #[test]
+    fn test_body_limits() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let json_headers = || {
+            let mut headers = Headers::new();
+            headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+            headers
+        };
+
+        // Requests within the limits are accepted
+        let res = inst.request(Method::Post, "/hook/limited.sh")
+            .headers(json_headers())
+            .body("{}")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        // Bodies larger than the limit are rejected
+        let res = inst.request(Method::Post, "/hook/limited.sh")
+            .headers(json_headers())
+            .body(r#"{"a": "this is too long"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+        assert!(inst.processor_input().is_none());
+
+        // Content types not in the allowlist are rejected
+        let res = inst.request(Method::Post, "/hook/limited.sh")
+            .body("a=b")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::UnsupportedMediaType);
+        assert!(inst.processor_input().is_none());
+
+        // Other hooks are not limited
+        let res = inst.request(Method::Post, "/hook/sub/hook.sh")
+            .body(r#"{"a": "this is not too long"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        inst.stop();
+
+        // The global limit applies to every hook
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.max_body_size = 8;
+        });
+
+        let mut res = inst.request(Method::Post, "/hook/sub/hook.sh")
+            .body(r#"{"a": "this is too long"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+        assert_eq!(
+            json_body(&mut res),
+            json!({"status": "payload_too_large", "max_size": 8})
+        );
+        assert!(inst.processor_input().is_none());
+
+        // The lowest limit between the global and the script one is used
+        let res = inst.request(Method::Post, "/hook/limited.sh")
+            .headers(json_headers())
+            .body(r#"{"a": 1}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+        let res = inst.request(Method::Post, "/hook/limited.sh")
+            .headers(json_headers())
+            .body(r#"{"a": 12}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_spooled_signed_bodies() {
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "spooled.sh",
+                r#"#!/bin/bash"#,
+                r#"## Fisher: {"spool_threshold": 16}"#,
+                r#"## Fisher-GitHub: {"secret": "secret"}"#,
+                r#"echo "ok""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        // The signature and the JSON of spooled bodies are checked from the
+        // spooled file
+        let body = json!({"padding": "a".repeat(1024)}).to_string();
+        let signature = utils::to_hex(
+            &utils::HmacAlgorithm::Sha1.sign(b"secret", body.as_bytes()),
+        );
+        for &(ref signature, status) in &[
+            (format!("sha1={}", signature), StatusCode::Ok),
+            ("sha1=0000".to_string(), StatusCode::Forbidden),
+        ] {
+            let mut headers = Headers::new();
+            headers.set_raw("X-GitHub-Event", vec![b"push".to_vec()]);
+            headers.set_raw("X-GitHub-Delivery", vec![b"abc".to_vec()]);
+            headers.set_raw(
+                "X-Hub-Signature", vec![signature.as_bytes().to_vec()],
+            );
+            let res = inst.request(Method::Post, "/hook/spooled.sh")
+                .headers(headers)
+                .body(body.as_str())
+                .send()
+                .unwrap();
+            assert_eq!(res.status, status);
+        }
+
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                assert_eq!(job.script_name(), "spooled.sh");
+            }
+            _ => panic!("the job was not queued"),
+        }
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_skipped_deliveries() {
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "skipping.sh",
+                r#"#!/bin/bash"#,
+                r#"## Fisher-GitHub: {"skip_keywords": ["[skip deploy]"]}"#,
+                r#"echo "ok""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        let labels = &[("hook", "skipping.sh"), ("provider", "GitHub")];
+        let before = metrics::get("fisher_skipped_deliveries_total", labels);
+
+        // Only the deliveries the provider skipped are counted
+        for &(id, message, skipped) in &[
+            ("a", "Fix typo [skip deploy]", true),
+            ("b", "Fix typo", false),
+        ] {
+            let mut headers = Headers::new();
+            headers.set_raw("X-GitHub-Event", vec![b"push".to_vec()]);
+            headers.set_raw("X-GitHub-Delivery", vec![id.as_bytes().to_vec()]);
+            headers.set_raw("X-Hub-Signature", vec![b"sha1=0".to_vec()]);
+            let body = json!({
+                "ref": "refs/heads/master",
+                "head_commit": {"id": "deadbeef", "message": message},
+            });
+            let mut res = inst.request(Method::Post, "/hook/skipping.sh")
+                .headers(headers)
+                .body(body.to_string().as_str())
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+
+            let body = json_body(&mut res);
+            assert_eq!(body["status"] == "skipped", skipped);
+            assert_eq!(inst.processor_input().is_none(), skipped);
+        }
+
+        assert_eq!(
+            metrics::get("fisher_skipped_deliveries_total", labels),
+            before + 1
+        );
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_form_bodies() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "Content-Type",
+            vec![b"application/x-www-form-urlencoded".to_vec()],
+        );
+
+        // The fields of form bodies are added to the params, but they can't
+        // override the querystring
+        let res = inst.request(Method::Post, "/hook/example.sh?a=query")
+            .headers(headers)
+            .body("a=body&b=hello+world&c=%26")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                let req = job.request().web().unwrap();
+                assert_eq!(req.params["a"], "query");
+                assert_eq!(req.params["b"], "hello world");
+                assert_eq!(req.params["c"], "&");
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // Other bodies are not parsed
+        let res = inst.request(Method::Post, "/hook/example.sh")
+            .body("b=hello")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                let req = job.request().web().unwrap();
+                assert!(!req.params.contains_key("b"));
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_compressed_bodies() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.max_body_size = 64;
+        });
+
+        let compressed = |encoding: &str, content: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(content).unwrap();
+            let mut headers = Headers::new();
+            headers.set_raw("Content-Encoding", vec![encoding.into()]);
+            (headers, encoder.finish().unwrap())
+        };
+
+        // Compressed bodies are decoded before reaching the providers
+        let (headers, body) = compressed("gzip", b"hello world");
+        let res = inst.request(Method::Post, "/hook/example.sh")
+            .headers(headers)
+            .body(&body[..])
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                let req = job.request().web().unwrap();
+                assert_eq!(req.body_str(), "hello world");
+                assert!(!req.headers.contains_key("Content-Encoding"));
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // The limit also applies to the decompressed body
+        let (headers, body) = compressed("gzip", &[b'a'; 1024]);
+        assert!(body.len() < 64);
+        let res = inst.request(Method::Post, "/hook/example.sh")
+            .headers(headers)
+            .body(&body[..])
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+        assert!(inst.processor_input().is_none());
+
+        // Bodies not matching their encoding are rejected
+        let (headers, body) = compressed("deflate", b"hello world");
+        let res = inst.request(Method::Post, "/hook/example.sh")
+            .headers(headers)
+            .body(&body[..])
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::BadRequest);
+        assert!(inst.processor_input().is_none());
+
+        // Unknown encodings are not supported
+        let (headers, body) = compressed("br", b"hello world");
+        let res = inst.request(Method::Post, "/hook/example.sh")
+            .headers(headers)
+            .body(&body[..])
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::UnsupportedMediaType);
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let testing_env = TestingEnv::new();
+        let inst = testing_env.start_web_configured(|config| {
+            config.read_timeout = 1.into();
+            config.max_header_size = 256;
+        });
+
+        let addr = inst.url().replace("http://", "");
+        let send = |parts: &[&[u8]], wait: u64| {
+            let mut conn = TcpStream::connect(&addr).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            for part in parts {
+                conn.write_all(part).unwrap();
+                thread::sleep(Duration::from_millis(wait));
+            }
+
+            let mut response = String::new();
+            let _ = conn.read_to_string(&mut response);
+            response
+        };
+
+        // Clients sending the headers too slowly are disconnected
+        let response = send(&[
+            b"POST /hook/example.sh HTTP/1.1\r\n", b"Host: localhost\r\n",
+            b"X-Slow: yes\r\n",
+        ], 600);
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+
+        // The same happens to slow bodies
+        let response = send(&[
+            b"POST /hook/example.sh HTTP/1.1\r\nContent-Length: 10\r\n\r\n",
+            b"12345",
+        ], 0);
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(inst.processor_input().is_none());
+
+        // Headers bigger than the limit are rejected
+        let big = format!("X-Big: {}\r\n\r\n", "a".repeat(256));
+        let response = send(&[
+            b"GET /health HTTP/1.1\r\n", big.as_bytes(),
+        ], 0);
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+        // Well-behaved clients are not affected
+        let response = send(&[
+            b"GET /health HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ], 0);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_connection_keep_alive() {
+        let testing_env = TestingEnv::new();
+        let inst = testing_env.start_web_configured(|config| {
+            config.keep_alive_timeout = 1.into();
+            config.max_connections = 2;
+            config.workers = 4;
+        });
+        let addr = inst.url().replace("http://", "");
+
+        // Idle connections are closed after the keep-alive timeout
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let mut buffer = [0; 1024];
+        assert!(conn.read(&mut buffer).unwrap() > 0);
+        thread::sleep(Duration::from_millis(1500));
+        let mut rest = Vec::new();
+        conn.read_to_end(&mut rest).unwrap();
+        drop(conn);
+
+        // Connections over the limit are rejected
+        let _first = TcpStream::connect(&addr).unwrap();
+        let _second = TcpStream::connect(&addr).unwrap();
+        let mut third = TcpStream::connect(&addr).unwrap();
+        third.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut response = String::new();
+        let _ = third.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_admin_verify() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let body = json!({
+            "provider": "Standalone",
+            "config": {"secret": "abcde"},
+            "params": {"secret": "wrong"},
+        }).to_string();
+
+        // The admin token is required
+        let res = inst.request(Method::Post, "/admin/verify")
+            .body(body.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        // The trace shows which check failed
+        let mut res = inst.admin_request(Method::Post, "/admin/verify")
+            .body(body.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let data = json_body(&mut res);
+        assert_eq!(data["result"], json!({
+            "request_type": "invalid",
+            "checks": [
+                {"name": "config", "passed": true},
+                {"name": "secret_present", "passed": true},
+                {"name": "secret", "passed": false},
+            ],
+        }));
+
+        // Invalid configurations are reported
+        let body = json!({"provider": "Standalone", "config": "{"}).to_string();
+        let mut res = inst.admin_request(Method::Post, "/admin/verify")
+            .body(body.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let data = json_body(&mut res);
+        assert_eq!(data["result"]["request_type"], json!("invalid"));
+        assert_eq!(
+            data["result"]["error"], json!("invalid provider configuration"),
+        );
+
+        // The environment of the machine is not used in the configuration
+        let body = json!({
+            "provider": "Standalone",
+            "config": {"secret": "${HOME}"},
+            "params": {"secret": ::std::env::var("HOME").unwrap()},
+        }).to_string();
+        let mut res = inst.admin_request(Method::Post, "/admin/verify")
+            .body(body.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let data = json_body(&mut res);
+        assert_eq!(data["result"]["request_type"], json!("invalid"));
+
+        // Malformed requests are rejected
+        let res = inst.admin_request(Method::Post, "/admin/verify")
+            .body("{}")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::BadRequest);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_standby() {
+        let testing_env = TestingEnv::new();
+        let mut standby = testing_env.start_web_configured(|config| {
+            config.standby = true;
+        });
+        let standby_url = standby.url().to_string();
+        let mut primary = testing_env.start_web_configured(|config| {
+            config.mirror_to = Some(standby_url);
+            config.mirror_token = Some("admin".into());
+        });
+
+        // Standby instances don't accept deliveries
+        let res = standby.request(Method::Get, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+        assert!(standby.processor_input().is_none());
+
+        // Deliveries to the primary instance are mirrored to the standby one
+        let labels = &[("hook", "example.sh")];
+        let before = metrics::get("fisher_mirrored_deliveries_total", labels);
+        let res = primary.request(Method::Get, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(primary.processor_input().is_some());
+
+        for _ in 0..100 {
+            if metrics::get("fisher_mirrored_deliveries_total", labels) > before {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            metrics::get("fisher_mirrored_deliveries_total", labels),
+            before + 1
+        );
+        assert!(standby.processor_input().is_none());
+
+        // Promoting requires the admin token
+        let res = standby.request(Method::Post, "/admin/promote")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = standby.admin_request(Method::Post, "/admin/promote")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        // After the promotion deliveries are executed
+        let res = standby.request(Method::Get, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(standby.processor_input().is_some());
+
+        // Mirrored deliveries are rejected after the promotion
+        let res = standby.admin_request(
+            Method::Post, "/admin/mirror/example.sh",
+        ).body(r#"{"source": "127.0.0.1"}"#).send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        primary.stop();
+        standby.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_approvals() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // Jobs of the script are not queued right away
+        let url = "/hook/approval.sh?ignore_status_hooks=1";
+        let mut res = inst.request(Method::Get, url)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        assert!(inst.processor_input().is_none());
+        let first = json_body(&mut res)["job_id"].as_str().unwrap().to_string();
+
+        let mut res = inst.request(Method::Get, url)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        let second = json_body(&mut res)["job_id"]
+            .as_str().unwrap().to_string();
+
+        // Listing the pending jobs requires the admin token
+        let res = inst.request(Method::Get, "/admin/approvals")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let mut res = inst.admin_request(Method::Get, "/admin/approvals")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let pending = json_body(&mut res)["result"].clone();
+        assert_eq!(pending.as_array().unwrap().len(), 2);
+        assert_eq!(pending[0]["job_id"], json!(first));
+        assert_eq!(pending[0]["script"], json!("approval.sh"));
+
+        // The approver must be provided
+        let url = format!("/admin/approvals/{}/approve", first);
+        let res = inst.admin_request(Method::Post, &url)
+            .body(r#"{"approver": ""}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::BadRequest);
+        assert!(inst.processor_input().is_none());
+
+        // Approved jobs are queued
+        let res = inst.admin_request(Method::Post, &url)
+            .body(r#"{"approver": "pietro"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        if let ProcessorApiCall::Queue(job, _) = inst.processor_input().unwrap() {
+            assert_eq!(job.id(), first.as_str());
+        } else {
+            panic!("Wrong processor input received");
+        }
+
+        // Jobs can't be approved twice
+        let res = inst.admin_request(Method::Post, &url)
+            .body(r#"{"approver": "pietro"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        // Rejected jobs are never queued
+        let url = format!("/admin/approvals/{}/reject", second);
+        let res = inst.admin_request(Method::Post, &url)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_none());
+
+        let mut res = inst.admin_request(Method::Get, "/admin/approvals")
+            .send()
+            .unwrap();
+        assert_eq!(json_body(&mut res)["result"], json!([]));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_read_only() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.read_only = true;
+        });
+
+        let url = "/hook/approval.sh?ignore_status_hooks=1";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        let job_id = json_body(&mut res)["job_id"]
+            .as_str().unwrap().to_string();
+
+        // The admin token is still checked before anything else
+        let res = inst.request(Method::Post, "/admin/promote").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let approve = format!("/admin/approvals/{}/approve", job_id);
+        let reject = format!("/admin/approvals/{}/reject", job_id);
+        let freeze = r#"{"reason": "release in progress"}"#;
+        for &(ref method, url, body) in &[
+            (Method::Post, "/admin/promote", ""),
+            (Method::Post, approve.as_str(), r#"{"approver": "pietro"}"#),
+            (Method::Post, reject.as_str(), ""),
+            (Method::Post, "/admin/freezes", freeze),
+            (Method::Post, "/admin/freezes/example.sh", freeze),
+            (Method::Delete, "/admin/freezes", ""),
+            (Method::Delete, "/jobs/abcd", ""),
+            (Method::Post, "/jobs/abcd/kill", ""),
+            (Method::Post, "/hooks/example.sh/replay/abcd", ""),
+            (Method::Post, "/hooks/example.sh/trigger", ""),
+            (Method::Post, "/processor/pause", ""),
+            (Method::Post, "/processor/resume", ""),
+        ] {
+            let mut res = inst.admin_request(method.clone(), url)
+                .body(body)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden, "{}", url);
+
+            assert_eq!(
+                json_body(&mut res),
+                json!({"status": "read_only"}),
+            );
+        }
+        assert!(inst.processor_input().is_none());
+
+        // The endpoints which don't change anything still work
+        let res = inst.admin_request(Method::Get, "/admin/approvals")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_cors() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.cors_origins = vec!["https://dashboard.example.com".into()];
+        });
+
+        let header = |res: &::hyper010::client::Response, name| {
+            res.headers.get_raw(name).map(|value| value[0].clone())
+        };
+        let request = |origin: &str| {
+            let mut headers = Headers::new();
+            headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+            headers.set_raw(
+                "Access-Control-Request-Method", vec![b"GET".to_vec()],
+            );
+            headers
+        };
+
+        // Preflight requests from the allowed origins are answered directly
+        let res = inst.request(Method::Options, "/health")
+            .headers(request("https://dashboard.example.com"))
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NoContent);
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some(b"https://dashboard.example.com".to_vec()),
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Methods"),
+            Some(b"GET, POST, PUT, DELETE".to_vec()),
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Headers"),
+            Some(b"Authorization, Content-Type".to_vec()),
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Max-Age"), Some(b"600".to_vec()),
+        );
+
+        // The other origins don't get any of the headers
+        let res = inst.request(Method::Options, "/health")
+            .headers(request("https://evil.example.com"))
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+        assert!(header(&res, "Access-Control-Allow-Origin").is_none());
+
+        // The normal requests are processed as usual
+        let res = inst.request(Method::Get, "/health")
+            .headers(request("https://dashboard.example.com"))
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some(b"https://dashboard.example.com".to_vec()),
+        );
+        assert_eq!(header(&res, "Vary"), Some(b"Origin".to_vec()));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_freezes() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let queued_job = |inst: &WebAppInstance| {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => job,
+                _ => panic!("Wrong processor input received"),
+            }
+        };
+
+        // Freezing requires the admin token and a reason
+        let body = r#"{"reason": "release in progress"}"#;
+        let res = inst.request(Method::Post, "/admin/freezes")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = inst.admin_request(Method::Post, "/admin/freezes")
+            .body(r#"{"reason": " "}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::BadRequest);
+        let res = inst.admin_request(Method::Post, "/admin/freezes/invalid.sh")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        // Webhooks are rejected during a global freeze by default
+        let res = inst.admin_request(Method::Post, "/admin/freezes")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let url = "/hook/example.sh?secret=testing";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+        assert_eq!(json_body(&mut res)["reason"], json!("release in progress"));
+        assert!(inst.processor_input().is_none());
+
+        // The freeze of a script takes precedence over the global one
+        let res = inst.admin_request(Method::Post, "/admin/freezes/example.sh")
+            .body(r#"{"reason": "testing", "action": "queue"}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        let held = json_body(&mut res)["job_id"].as_str().unwrap().to_string();
+        assert!(inst.processor_input().is_none());
+
+        let mut res = inst.admin_request(Method::Get, "/admin/freezes")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let result = json_body(&mut res)["result"].clone();
+        assert_eq!(result["freezes"].as_array().unwrap().len(), 2);
+        assert_eq!(result["held_jobs"][0]["job_id"], json!(held));
+
+        // The freezes are shown in the health status
+        let mut res = inst.request(Method::Get, "/health").send().unwrap();
+        let freezes = json_body(&mut res)["result"]["freezes"].clone();
+        assert_eq!(freezes[0]["reason"], json!("release in progress"));
+        assert_eq!(freezes[0]["script"], json!(null));
+        assert_eq!(freezes[1]["script"], json!("example.sh"));
+        assert_eq!(freezes[1]["action"], json!("queue"));
+        inst.processor_input();
+
+        // Held jobs are executed only after all the freezes end
+        let res = inst.admin_request(
+            Method::Delete, "/admin/freezes/example.sh",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_none());
+
+        let res = inst.admin_request(Method::Delete, "/admin/freezes")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(queued_job(&inst).id(), held.as_str());
+
+        let res = inst.admin_request(Method::Delete, "/admin/freezes")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        // Held jobs are released when the freeze expires
+        let res = inst.admin_request(Method::Post, "/admin/freezes")
+            .body(r#"{"reason": "testing", "action": "queue", "expires_in": 1}"#)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        assert!(inst.processor_input().is_none());
+
+        thread::sleep(Duration::from_millis(2500));
+        assert_eq!(queued_job(&inst).script_name(), "example.sh");
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_running_jobs() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // Listing the running jobs requires the admin token
+        let res = inst.request(Method::Get, "/admin/running").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let progress = dir.path().join("progress");
+        fs::write(&progress, "10 Fetching\n75% Deploying\n").unwrap();
+        inst.running().lock().unwrap().start("abc", "long.sh", &progress);
+
+        let mut res = inst.admin_request(Method::Get, "/admin/running")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        let jobs = body["result"].as_array().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0]["job_id"], "abc");
+        assert_eq!(jobs[0]["script"], "long.sh");
+        assert_eq!(jobs[0]["percent"], 75);
+        assert_eq!(jobs[0]["message"], "Deploying");
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_job_logs() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Get, "/jobs/abc/logs").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        // Only the output of running jobs is available
+        let res = inst.admin_request(Method::Get, "/jobs/abc/logs")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let progress = dir.path().join("progress");
+        inst.running().lock().unwrap().start("abc", "long.sh", &progress);
+        let output = inst.running().lock().unwrap().output("abc").unwrap();
+        output.lock().unwrap().append(b"Fetching\n");
+
+        let mut res = inst.admin_request(Method::Get, "/jobs/abc/logs")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "Fetching\n");
+
+        // Following the output streams it until the job ends
+        let running = inst.running().clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            output.lock().unwrap().append(b"Deploying\n");
+            running.lock().unwrap().finish("abc");
+        });
+
+        let mut res = inst.admin_request(
+            Method::Get, "/jobs/abc/logs?follow=true",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "Fetching\nDeploying\n");
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_processes() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // Listing the processes requires the admin token
+        let res = inst.request(Method::Get, "/admin/processes")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        metrics::increment(
+            "fisher_processes_force_killed_total", &[("hook", "leaky.sh")],
+        );
+
+        let mut res = inst.admin_request(Method::Get, "/admin/processes")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        assert!(body["result"]["processes"].is_array());
+        assert_eq!(body["result"]["force_killed"]["leaky.sh"], 1);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_fan_out() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let queued_job = |inst: &WebAppInstance| {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => job,
+                _ => panic!("Wrong processor input received"),
+            }
+        };
+
+        // Every script gets its own job, and missing ones are ignored
+        let url = "/hook/example.sh?fan_out=long.sh,missing.sh,failing.sh";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        assert_eq!(body["job_ids"].as_array().unwrap().len(), 2);
+
+        let first = queued_job(&inst);
+        let second = queued_job(&inst);
+        assert_eq!(first.script_name(), "long.sh");
+        assert_eq!(second.script_name(), "failing.sh");
+        assert_eq!(body["job_ids"], json!([
+            first.id().to_string(), second.id().to_string(),
+        ]));
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_ip_rate_limit() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.ip_rate_limit = Some(RateLimitConfig {
+                allowed: 1,
+                interval: 3600.into(),
+            });
+            config.ip_rate_burst = Some(2);
+        });
+
+        // Valid requests are limited too
+        let url = "/hook/example.sh?secret=testing";
+        for _ in 0..2 {
+            let res = inst.request(Method::Get, url).send().unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            assert!(inst.processor_input().is_some());
+        }
+
+        let res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::TooManyRequests);
+        assert_eq!(
+            res.headers.get_raw("Retry-After").unwrap()[0],
+            b"3600".to_vec(),
+        );
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_hook_rate_limit() {
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "limited.sh",
+                r#"#!/bin/bash"#,
+                r#"## Fisher: {"rate_limit": "2/hour"}"#,
+                r#"## Fisher-Standalone: {"secret": "testing"}"#,
+                r#"echo "ok""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        let url = "/hook/limited.sh?secret=testing";
+        for _ in 0..2 {
+            let res = inst.request(Method::Get, url).send().unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            assert!(inst.processor_input().is_some());
+        }
+
+        // Requests are rejected before being validated
+        for url in &[url, "/hook/limited.sh?secret=invalid"] {
+            let res = inst.request(Method::Get, url).send().unwrap();
+            assert_eq!(res.status, StatusCode::TooManyRequests);
+            assert_eq!(
+                res.headers.get_raw("Retry-After").unwrap()[0],
+                b"1800".to_vec(),
+            );
+        }
+        assert!(inst.processor_input().is_none());
+
+        // Other hooks are not affected
+        let res = inst.request(Method::Get, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_catchall() {
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "__catchall__",
+                r#"#!/bin/bash"#,
+                r#"## Fisher-Testing: {}"#,
+                r#"echo "${FISHER_REQUESTED_HOOK}""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        // Unknown hooks are sent to the catch-all script
+        let res = inst.request(Method::Get, "/hook/unknown.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                assert_eq!(job.script_name(), "__catchall__");
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // Existing hooks are not affected
+        let res = inst.request(Method::Get, "/hook/example.sh?secret=invalid")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_custom_responses() {
+        let testing_env = TestingEnv::with_hooks(|hooks| {
+            create_hook!(hooks, "custom.sh",
+                r#"#!/bin/bash"#,
+                concat!(
+                    r#"## Fisher: {"responses": {"queued": {"status": 201, "#,
+                    r#""body": "{\"id\": \"{job_id}\", "#,
+                    r#"\"hook\": \"{hook}\"}"}, "#,
+                    r#""rejected": {"body": "{status}"}}}"#
+                ),
+                r#"## Fisher-Testing: {}"#,
+                r#"echo "ok""#
+            );
+        });
+        let mut inst = testing_env.start_web(true, 0);
+
+        let read = |res: &mut Response| {
+            let content_type = res.headers.get_raw("Content-Type").unwrap();
+            let content_type = String::from_utf8(content_type[0].clone());
+
+            let mut content = String::new();
+            res.read_to_string(&mut content).unwrap();
+            (content_type.unwrap(), content)
+        };
+
+        let url = "/hook/custom.sh?secret=testing";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Created);
+        let (content_type, body) = read(&mut res);
+        assert_eq!(content_type, "application/json");
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["hook"], "custom.sh");
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Queue(job, _)) => {
+                assert_eq!(body["id"], job.id());
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // The default status is kept if only the body is customized
+        let url = "/hook/custom.sh?secret=invalid";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert_eq!(read(&mut res), ("text/plain".into(), "forbidden".into()));
+
+        // Other hooks are not affected
+        let url = "/hook/example.sh?secret=testing";
+        let mut res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(read(&mut res).0, "application/json");
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_custom_paths() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.aliases.insert(
+                "/deploy/staging".into(), "example.sh".into(),
+            );
+            config.path_secret = Some("secret".into());
+        });
+
+        let secret = HookPaths::new(HashMap::new(), Some("secret"))
+            .unwrap()
+            .secret_path("custom-paths.sh");
+
+        for &(path, script) in &[
+            ("/deploy/prod", "custom-paths.sh"),
+            ("/deploy/staging", "example.sh"),
+            (&secret, "custom-paths.sh"),
+        ] {
+            let res = inst.request(Method::Post, path).send().unwrap();
+            assert_eq!(res.status, StatusCode::Ok, "{}", path);
+
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => {
+                    assert_eq!(job.script_name(), script);
+                }
+                _ => panic!("Wrong processor input received"),
+            }
+        }
+
+        // Only the exact paths are accepted
+        for path in &["/deploy", "/deploy/prod/more", "/h/wrong", "/example.sh"]
+        {
+            let res = inst.request(Method::Get, path).send().unwrap();
+            assert_eq!(res.status, StatusCode::NotFound, "{}", path);
+        }
+
+        // Hooks with a secret path can't be reached by their name
+        for path in &["/hook/custom-paths.sh", "/v1/hook/custom-paths.sh"] {
+            let res = inst.request(Method::Post, path).send().unwrap();
+            assert_eq!(res.status, StatusCode::NotFound, "{}", path);
+        }
+        assert!(inst.processor_input().is_none());
+
+        // The admin API lists all the custom paths
+        let mut res = inst.admin_request(Method::Get, "/admin/paths")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        assert_eq!(body["result"], json!({
+            "custom-paths.sh": ["/deploy/prod", secret],
+            "example.sh": ["/deploy/staging"],
+        }));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_wait_for_jobs() {
+        let testing_env = TestingEnv::new();
+        let inst = testing_env.start_web(true, 0);
+
+        let call = |url: String| thread::spawn(move || {
+            let mut res = hyper010::Client::new().get(&url).send().unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            json_body(&mut res)
+        });
+        let queued_job = || loop {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => return job,
+                Some(_) => panic!("wrong processor input received"),
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        // The output is returned only if the script allows it
+        let request = call(format!(
+            "{}/hook/example.sh?secret=testing&wait=true", inst.url(),
+        ));
+        let job = queued_job();
+
+        // Waiting requests don't keep the only worker busy
+        let res = hyper010::Client::new()
+            .get(&format!("{}/hook/missing.sh", inst.url()))
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        job.execute(&JobContext::default()).unwrap();
+        let body = request.join().unwrap();
+        assert_eq!(body["job_id"], job.id());
+        assert_eq!(body["result"], json!({
+            "success": true,
+            "exit_code": 0,
+            "signal": null,
+        }));
+
+        // Some scripts always wait for their jobs
+        let request = call(format!("{}/hook/wait.sh", inst.url()));
+        queued_job().execute(&JobContext::default()).unwrap();
+        assert_eq!(request.join().unwrap()["result"], json!({
+            "success": false,
+            "exit_code": 3,
+            "signal": null,
+            "stdout": "Hello world\n",
+            "stderr": "",
+            "truncated": false,
+        }));
+
+        // Jobs ending without an output failed
+        let request = call(format!("{}/hook/wait.sh", inst.url()));
+        drop(queued_job());
+        assert_eq!(request.join().unwrap()["result"], json!({
+            "success": false,
+            "exit_code": null,
+            "signal": null,
+        }));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_replay_payloads() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.keep_payloads = 5;
+        });
+
+        let queued_job = |inst: &WebAppInstance| {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => job,
+                _ => panic!("the job was not queued"),
+            }
+        };
+
+        let res = inst.request(
+            Method::Post, "/hook/example.sh?secret=testing&delivery_id=abc",
+        ).body("payload").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let original = queued_job(&inst);
+
+        let res = inst.request(Method::Get, "/hooks/example.sh/payloads")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let mut res = inst.admin_request(
+            Method::Get, "/hooks/example.sh/payloads",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        let payloads = body["result"].as_array().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["delivery"], "abc");
+        assert_eq!(payloads[0]["job_ids"], json!([original.id()]));
+
+        // Payloads can be replayed by the ID of their delivery or job, even
+        // if the delivery was already processed
+        for id in &["abc", original.id()] {
+            let url = format!("/hooks/example.sh/replay/{}", id);
+            let mut res = inst.admin_request(Method::Post, &url)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+
+            let body = json_body(&mut res);
+
+            let job = queued_job(&inst);
+            assert_eq!(body["job_id"], job.id());
+            assert_ne!(job.id(), original.id());
+            assert_eq!(job.script_name(), "example.sh");
+        }
+
+        // Payloads can't be replayed while the instance is locked
+        inst.lock();
+        let res = inst.admin_request(
+            Method::Post, "/hooks/example.sh/replay/abc",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+        inst.unlock();
+
+        for url in &[
+            "/hooks/example.sh/replay/unknown", "/hooks/missing.sh/replay/abc",
+        ] {
+            let res = inst.admin_request(Method::Post, url)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::NotFound);
+        }
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_jobs_continue_the_trace() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let queued_job = |inst: &WebAppInstance| {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => job,
+                _ => panic!("the job was not queued"),
+            }
+        };
+
+        let mut headers = Headers::new();
+        headers.set_raw("traceparent", vec![
+            b"00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_vec(),
+        ]);
+        let res = inst.request(Method::Post, "/hook/example.sh?secret=testing")
+            .headers(headers)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let job = queued_job(&inst);
+        assert_eq!(job.trace().trace_id, "0af7651916cd43dd8448eb211c80319c");
+        assert_ne!(job.trace().span_id, "b7ad6b7169203331");
+
+        // A new trace is started if the caller didn't send one
+        let res = inst.request(Method::Post, "/hook/example.sh?secret=testing")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let job = queued_job(&inst);
+        assert_ne!(job.trace().trace_id, "0af7651916cd43dd8448eb211c80319c");
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_trigger_hook() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Post, "/hooks/example.sh/trigger")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let res = inst.admin_request(Method::Post, "/hooks/missing.sh/trigger")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        for body in &[
+            r#"{"env": {"a-b": "c"}}"#, r#"{"env": {"": "c"}}"#, "{",
+        ] {
+            let res = inst.admin_request(
+                Method::Post, "/hooks/example.sh/trigger",
+            ).body(*body).send().unwrap();
+            assert_eq!(res.status, StatusCode::BadRequest, "{}", body);
+        }
+        assert!(inst.processor_input().is_none());
+
+        // The providers of the hook are not checked, and the body is optional
+        for body in &[r#"{"env": {"TARGET": "prod"}}"#, ""] {
+            let mut res = inst.admin_request(
+                Method::Post, "/hooks/example.sh/trigger",
+            ).body(*body).send().unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+
+            let body = json_body(&mut res);
+
+            match inst.processor_input() {
+                Some(ProcessorApiCall::Queue(job, _)) => {
+                    assert_eq!(body["job_id"], job.id());
+                    assert_eq!(job.script_name(), "example.sh");
+                }
+                _ => panic!("the job was not queued"),
+            }
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_list_hooks() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.aliases.insert(
+                "/deploy/staging".into(), "example.sh".into(),
+            );
+        });
+
+        let res = inst.request(Method::Get, "/hooks").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let mut res = inst.admin_request(Method::Get, "/hooks")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        let hooks = body["result"].as_array().unwrap();
+        let hook = |name| {
+            hooks.iter().find(|hook| hook["name"] == name).unwrap().clone()
+        };
+
+        // The hooks are sorted by name
+        let names = hooks.iter()
+            .map(|hook| hook["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+
+        assert_eq!(hook("example.sh"), json!({
+            "name": "example.sh",
+            "providers": [{"name": "Testing", "events": null}],
+            "urls": ["/hook/example.sh", "/deploy/staging"],
+        }));
+        assert_eq!(hook("status-example.sh")["providers"], json!([{
+            "name": "Status",
+            "events": ["job-completed", "job-failed"],
+        }]));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_queue() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Get, "/queue").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert!(inst.processor_input().is_none());
+
+        let mut res = inst.admin_request(Method::Get, "/queue")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::QueueDetails) => {}
+            _ => panic!("the processor was not asked for the queue"),
+        }
+
+        let body = json_body(&mut res);
+        assert_eq!(body["result"]["jobs"][0]["job_id"], "abcd");
+        assert_eq!(body["result"]["jobs"][0]["reason"], "queued");
+        assert_eq!(body["result"]["workers"][0]["job_id"], json!(null));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_cancel_job() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Delete, "/jobs/abcd").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert!(inst.processor_input().is_none());
+
+        let res = inst.admin_request(Method::Delete, "/jobs/unknown")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        let mut res = inst.admin_request(Method::Delete, "/jobs/abcd")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        assert_eq!(body["result"], json!({
+            "job_id": "abcd",
+            "cancelled": true,
+            "state": "cancelled",
+        }));
+
+        for job_id in &["unknown", "abcd"] {
+            match inst.processor_input() {
+                Some(ProcessorApiCall::CancelJob(ref id)) if id == job_id => {}
+                _ => panic!("the processor was not asked to cancel the job"),
+            }
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_kill_job() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Post, "/jobs/abc/kill")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        // Only running jobs can be killed
+        let res = inst.admin_request(Method::Post, "/jobs/abc/kill")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        let (killer, killed) = mpsc::channel();
+        {
+            let mut running = inst.running().lock().unwrap();
+            running.start("abc", "long.sh", Path::new("/nonexistent"));
+            running.set_killer("abc", Some(killer));
+        }
+
+        let mut res = inst.admin_request(Method::Post, "/jobs/abc/kill")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let body = json_body(&mut res);
+        assert_eq!(body["result"], json!({"job_id": "abc", "killed": true}));
+        assert!(killed.try_recv().is_ok());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_pause_processor() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        for url in &["/processor/pause", "/processor/resume"] {
+            let res = inst.request(Method::Post, url).send().unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden);
+        }
+        assert!(inst.processor_input().is_none());
+
+        let res = inst.admin_request(Method::Post, "/processor/pause")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Pause) => {}
+            _ => panic!("the processor was not paused"),
+        }
+
+        let res = inst.admin_request(Method::Post, "/processor/resume")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Resume) => {}
+            _ => panic!("the processor was not resumed"),
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_reload_scripts() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let create_script = |name: &str, content: &str| {
+            let path = testing_env.hooks_dir().join(name);
+            fs::write(&path, content).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .unwrap();
+        };
+        let reload = |inst: &mut WebAppInstance| {
+            inst.admin_request(Method::Post, "/reload")
+                .send()
+                .unwrap()
+                .status
+        };
+
+        create_script("new.sh", "#!/bin/bash\n## Fisher-Testing: {}\n");
+        let res = inst.request(Method::Post, "/hook/new.sh").send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        let res = inst.request(Method::Post, "/reload").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        assert_eq!(reload(&mut inst), StatusCode::Ok);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::Cleanup) => {}
+            _ => panic!("the processor was not cleaned up"),
+        }
+
+        let res = inst.request(Method::Post, "/hook/new.sh").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        // The old scripts are kept if the new ones are invalid
+        create_script("invalid.sh", "#!/bin/bash\n## Fisher: {\n");
+        assert_eq!(reload(&mut inst), StatusCode::BadRequest);
+        assert!(inst.processor_input().is_none());
+
+        let res = inst.request(Method::Post, "/hook/new.sh").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_job_status() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let res = inst.request(Method::Get, "/jobs/abcd").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        assert!(inst.processor_input().is_none());
+
+        // The state of the job is asked to the processor
+        let res = inst.admin_request(Method::Get, "/jobs/abcd")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+        match inst.processor_input() {
+            Some(ProcessorApiCall::JobStatus(job_id)) => {
+                assert_eq!(job_id.as_str(), "abcd");
+            }
+            _ => panic!("the processor was not asked for the job"),
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_dashboard() {
+        let testing_env = TestingEnv::new();
+
+        // Browsers are asked to log in
+        let mut inst = testing_env.start_web(true, 0);
+        let res = inst.request(Method::Get, "/dashboard").send().unwrap();
+        assert_eq!(res.status, StatusCode::Unauthorized);
+        assert_eq!(
+            res.headers.get_raw("WWW-Authenticate").unwrap()[0],
+            b"Basic realm=\"Fisher\", charset=\"UTF-8\"".to_vec(),
+        );
+        assert!(inst.processor_input().is_none());
+
+        let mut wrong = Headers::new();
+        wrong.set_raw(
+            "Authorization", vec![b"Basic dXNlcjp3cm9uZw==".to_vec()],
+        );
+        let res = inst.request(Method::Get, "/dashboard")
+            .headers(wrong)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Unauthorized);
+
+        // Browsers log in with the admin token as the password
+        for header in &[&b"Basic dXNlcjphZG1pbg=="[..], b"Bearer admin"] {
+            let mut headers = Headers::new();
+            headers.set_raw("Authorization", vec![header.to_vec()]);
+            let mut res = inst.request(Method::Get, "/dashboard")
+                .headers(headers)
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            assert_eq!(
+                res.headers.get_raw("Content-Type").unwrap()[0],
+                b"text/html; charset=utf-8".to_vec(),
+            );
+
+            let mut content = String::new();
+            res.read_to_string(&mut content).unwrap();
+            assert!(content.contains("<td>abcd</td><td>example.sh</td>"));
+            assert!(content.contains("<td>efgh</td><td>example.sh</td>"));
+            assert!(content.contains("<td>example.sh</td>"));
+
+            // The same data of the admin API is shown
+            match inst.processor_input() {
+                Some(ProcessorApiCall::HealthDetails) => {}
+                _ => panic!("the processor was not asked for its health"),
+            }
+            match inst.processor_input() {
+                Some(ProcessorApiCall::QueueDetails) => {}
+                _ => panic!("the processor was not asked for the queue"),
+            }
+            match inst.processor_input() {
+                Some(ProcessorApiCall::RecentJobs(limit)) => {
+                    assert_eq!(limit, 50);
+                }
+                _ => panic!("the processor was not asked for the jobs"),
+            }
+        }
+        inst.stop();
+
+        // The dashboard is disabled without an admin token
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.admin_token = None;
+        });
+        let res = inst.request(Method::Get, "/dashboard").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        inst.stop();
+
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_blackouts() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.blackouts = vec![BlackoutConfig {
+                provider: Some("Testing".into()),
+                events: Some(vec!["watch".into()]),
+                hours: None,
+            }];
+        });
+
+        let before = metrics::get("fisher_muted_events_total", &[
+            ("hook", "example.sh"), ("provider", "Testing"), ("event", "watch"),
+        ]);
+
+        // Events muted globally are acknowledged without queueing a job
+        let mut res = inst.request(Method::Get, "/hook/example.sh?event=watch")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let body = json_body(&mut res);
+        assert_eq!(body["status"].as_str().unwrap(), "muted");
+        assert!(inst.processor_input().is_none());
+
+        assert_eq!(metrics::get("fisher_muted_events_total", &[
+            ("hook", "example.sh"), ("provider", "Testing"), ("event", "watch"),
+        ]), before + 1);
+
+        // Other events are processed as usual
+        let res = inst.request(Method::Get, "/hook/example.sh?event=push")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        // Hooks can mute events on their own
+        let res = inst.request(Method::Get, "/hook/muted.sh?event=noisy")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_none());
+
+        let res = inst.request(Method::Get, "/hook/example.sh?event=noisy")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_some());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_approval_links() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.public_url = Some("https://fisher.example.com/".into());
+        });
+
+        let pending_links = |inst: &WebAppInstance| {
+            let input = inst.processor_input().unwrap();
+            let job = if let ProcessorApiCall::Queue(job, _) = input {
+                job
+            } else {
+                panic!("Wrong processor input received");
+            };
+            assert_eq!(job.script_name(), "approval-status.sh");
+
+            if let Request::Status(StatusEvent::JobPendingApproval(ref p)) =
+                *job.request()
+            {
+                assert_eq!(p.script_name, "approval.sh");
+                let (ref approve, ref reject) = *p.links.as_ref().unwrap();
+                let strip = |link: &str| {
+                    link["https://fisher.example.com".len()..].to_string()
+                };
+                (p.job_id.clone(), strip(approve), strip(reject))
+            } else {
+                panic!("Wrong status event received");
+            }
+        };
+
+        // The status hooks are notified with the links
+        let res = inst.request(Method::Get, "/hook/approval.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        let (first, approve, reject) = pending_links(&inst);
+        assert!(inst.processor_input().is_none());
+
+        // Opening the link only shows the confirmation page
+        let mut res = inst.request(Method::Get, &approve).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        assert!(content.contains("<form method=\"post\">"));
+        assert!(inst.processor_input().is_none());
+
+        // Tampered links are rejected
+        for method in &[Method::Get, Method::Post] {
+            let res = inst.request(method.clone(), &format!("{}0", approve))
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden);
+            let res = inst.request(
+                method.clone(), &approve.replace("approve", "reject"),
+            ).send().unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden);
+        }
+
+        // The job is approved by confirming the link, only once
+        let res = inst.request(Method::Post, &approve).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        if let ProcessorApiCall::Queue(job, _) = inst.processor_input().unwrap() {
+            assert_eq!(job.id(), first.as_str());
+        } else {
+            panic!("Wrong processor input received");
+        }
+        let res = inst.request(Method::Post, &approve).send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+        let res = inst.request(Method::Get, &approve).send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        // The rejection link of another job doesn't work for this one
+        let res = inst.request(Method::Get, "/hook/approval.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        let (second, _, second_reject) = pending_links(&inst);
+        let res = inst.request(Method::Post, &reject.replace(&first, &second))
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let res = inst.request(Method::Post, &second_reject).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_job_artifacts() {
+        let testing_env = TestingEnv::new();
+        let dir = TempDir::new("fisher-tests").unwrap();
+
+        let directory = dir.path().to_str().unwrap().to_string();
+        let mut inst = testing_env.start_web_configured(|config| {
+            config.artifacts_dir = Some(directory);
+        });
+
+        // The scripts are told where to upload the artifacts
+        let url = inst.uploads().lock().unwrap().url().unwrap().to_string();
+        assert!(url.starts_with("http://127.0.0.1:"));
+
+        inst.uploads().lock().unwrap().start("efgh");
+        let token = inst.uploads().lock().unwrap()
+            .token("efgh")
+            .unwrap()
+            .to_string();
+
+        let mut job_headers = Headers::new();
+        job_headers.set_raw(
+            "Authorization", vec![format!("Bearer {}", token).into_bytes()],
+        );
+
+        // Only the script of the job knows its token
+        let url = "/jobs/efgh/artifacts/report.html";
+        let res = inst.request(Method::Post, url)
+            .body("<p>Hi</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = inst.admin_request(Method::Post, url)
+            .body("<p>Hi</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = inst.request(Method::Post, "/jobs/abcd/artifacts/report.html")
+            .headers(job_headers.clone())
+            .body("<p>Hi</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        // The artifacts can't be stored outside of the job directory
+        let res = inst.request(Method::Post, "/jobs/efgh/artifacts/dir/x")
+            .headers(job_headers.clone())
+            .body("<p>Hi</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::BadRequest);
+
+        let res = inst.request(Method::Post, "/jobs/efgh/artifacts/report.html")
+            .headers(job_headers.clone())
+            .body("<p>Hi</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(
+            inst.uploads().lock().unwrap().artifacts("efgh"),
+            vec!["report.html"],
+        );
+
+        // The token is not valid anymore after the job ends
+        inst.uploads().lock().unwrap().finish("efgh");
+        let res = inst.request(Method::Post, "/jobs/efgh/artifacts/report.html")
+            .headers(job_headers.clone())
+            .body("<p>Bye</p>")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        // The artifacts are listed with the state of the job
+        let mut res = inst.admin_request(Method::Get, "/jobs/efgh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let data = json_body(&mut res);
+        assert_eq!(
+            data["result"]["artifacts"],
+            json!([{"name": "report.html", "size": 9}]),
+        );
+
+        // Only admins can download the artifacts
+        let res = inst.request(Method::Get, "/jobs/efgh/artifacts/report.html")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        let mut res = inst.admin_request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "<p>Hi</p>");
+
+        let res = inst.admin_request(
+            Method::Get, "/jobs/efgh/artifacts/missing",
+        ).send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+
+    #[test]
+    fn test_local_url() {
+        let url = |addr: &str, tls| local_url(&addr.parse().unwrap(), tls);
+
+        assert_eq!(url("0.0.0.0:8000", false), "http://127.0.0.1:8000/v1");
+        assert_eq!(url("[::]:8000", true), "https://[::1]:8000/v1");
+        assert_eq!(url("10.0.0.1:443", true), "https://10.0.0.1:443/v1");
+    }
 }
@@ -0,0 +1,291 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Jobs waiting for a manual approval before being executed.
+//!
+//! Scripts can require every job to be approved by a human through the admin
+//! API. Those jobs are kept here until they're approved or rejected, and
+//! they're shared between restarts of the HTTP server.
+
+use common::prelude::*;
+use scripts::Job;
+use utils::{self, HmacAlgorithm};
+use web::dashboard::escape;
+
+
+/// A job waiting to be approved.
+#[derive(Debug)]
+pub struct PendingJob {
+    pub job: Job,
+    pub priority: isize,
+    pub received_at: u64,
+    serial: u64,
+}
+
+
+#[derive(Debug, Default)]
+pub struct Approvals {
+    pending: Vec<PendingJob>,
+    next_serial: u64,
+}
+
+impl Approvals {
+    pub fn new() -> Self {
+        Approvals {
+            pending: Vec::new(),
+            next_serial: 0,
+        }
+    }
+
+    /// Add a new job to the ones waiting for approval.
+    pub fn insert(&mut self, job: Job, priority: isize) {
+        self.pending.push(PendingJob {
+            job,
+            priority,
+            received_at: utils::unix_now(),
+            serial: self.next_serial,
+        });
+        self.next_serial += 1;
+    }
+
+    /// Get all the jobs waiting for approval, oldest first.
+    pub fn pending(&self) -> &[PendingJob] {
+        &self.pending
+    }
+
+    /// Get a job waiting for approval, without removing it.
+    pub fn get(&self, job_id: &str) -> Option<&PendingJob> {
+        self.pending.iter().find(|p| p.job.id() == job_id)
+    }
+
+    /// Remove a job from the ones waiting for approval, returning it.
+    pub fn take(&mut self, job_id: &str) -> Option<PendingJob> {
+        let pos = self.pending.iter().position(|p| p.job.id() == job_id)?;
+        Some(self.pending.remove(pos))
+    }
+
+    /// Put back a job previously taken, keeping the original order.
+    pub fn restore(&mut self, job: PendingJob) {
+        let pos = self.pending
+            .iter()
+            .position(|p| p.serial > job.serial)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(pos, job);
+    }
+}
+
+
+/// Signed links to approve or reject a job without the admin token, which
+/// expire after a while. They're included in the notifications sent by the
+/// status hooks.
+#[derive(Debug)]
+pub struct ApprovalLinks {
+    base_url: String,
+    key: String,
+    ttl: u64,
+}
+
+impl ApprovalLinks {
+    pub fn new(base_url: &str, key: &str, ttl: u64) -> Self {
+        ApprovalLinks {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            key: key.to_string(),
+            ttl,
+        }
+    }
+
+    fn sign(&self, job_id: &str, action: &str, expires: u64) -> String {
+        let payload = format!("{}:{}:{}", action, job_id, expires);
+        utils::to_hex(
+            &HmacAlgorithm::Sha256.sign(self.key.as_bytes(), payload.as_bytes())
+        )
+    }
+
+    fn link(&self, job_id: &str, action: &str) -> String {
+        let expires = utils::unix_now() + self.ttl;
+        format!(
+            "{}/approvals/{}/{}?expires={}&token={}",
+            self.base_url, job_id, action, expires,
+            self.sign(job_id, action, expires),
+        )
+    }
+
+    /// Create the links to approve and reject the job.
+    pub fn create(&self, job_id: &str) -> (String, String) {
+        (self.link(job_id, "approve"), self.link(job_id, "reject"))
+    }
+
+    /// Check if the token of a link is valid and not expired.
+    pub fn verify(
+        &self, job_id: &str, action: &str, expires: &str, token: &str,
+    ) -> bool {
+        let expires = if let Ok(expires) = expires.parse::<u64>() {
+            expires
+        } else {
+            return false;
+        };
+
+        expires >= utils::unix_now()
+            && utils::secure_compare(self.sign(job_id, action, expires), token)
+    }
+
+    /// Render the page shown when a link is opened, which performs the
+    /// action only when its form is submitted. Links opened by previews or
+    /// scanners don't change anything this way.
+    pub fn confirmation_page(
+        &self, pending: &PendingJob, action: &str, expires: &str, token: &str,
+    ) -> String {
+        let label = if action == "approve" { "Approve" } else { "Reject" };
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{label} the job</title>\n</head>\n<body>\n\
+             <p>{label} the job {job} of the {script} script?</p>\n\
+             <form method=\"post\">\n\
+             <input type=\"hidden\" name=\"expires\" value=\"{expires}\">\n\
+             <input type=\"hidden\" name=\"token\" value=\"{token}\">\n\
+             <button type=\"submit\">{label}</button>\n\
+             </form>\n</body>\n</html>\n",
+            label = label,
+            job = escape(pending.job.id()),
+            script = escape(pending.job.script_name()),
+            expires = escape(expires),
+            token = escape(token),
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common::state::State;
+    use scripts::{Blueprint, Job};
+    use utils;
+    use utils::testing::*;
+
+    use super::{ApprovalLinks, Approvals};
+
+
+    #[test]
+    fn test_approvals() {
+        let hooks_dir = sample_hooks();
+        let mut blueprint = Blueprint::new(Arc::new(State::new()));
+        blueprint.collect_path(&hooks_dir, false).unwrap();
+        let hook = blueprint.repository().get_by_name("example.sh").unwrap();
+
+        let job = |hook| Job::new(hook, None, dummy_web_request().into());
+        let first = job(hook.clone());
+        let second = job(hook.clone());
+        let (first_id, second_id) = (first.id().to_string(), second.id().to_string());
+
+        let mut approvals = Approvals::new();
+        approvals.insert(first, 1);
+        approvals.insert(second, 2);
+
+        let pending = approvals.pending();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].job.id(), first_id.as_str());
+        assert_eq!(pending[1].job.id(), second_id.as_str());
+
+        // Jobs can only be taken once
+        let taken = approvals.take(&second_id).unwrap();
+        assert_eq!(taken.job.id(), second_id.as_str());
+        assert_eq!(taken.priority, 2);
+        assert!(approvals.take(&second_id).is_none());
+        assert!(approvals.take("invalid").is_none());
+        assert_eq!(approvals.pending().len(), 1);
+
+        // Restored jobs are put back in their place
+        let taken = approvals.take(&first_id).unwrap();
+        approvals.insert(job(hook.clone()), 3);
+        approvals.restore(taken);
+        assert_eq!(approvals.pending()[0].job.id(), first_id.as_str());
+        assert_eq!(approvals.pending().len(), 2);
+
+        ::std::fs::remove_dir_all(&hooks_dir).unwrap();
+    }
+
+
+    #[test]
+    fn test_approval_links() {
+        let links = ApprovalLinks::new("https://example.com/", "secret", 60);
+        let (approve, reject) = links.create("abcd");
+        assert!(approve.starts_with("https://example.com/approvals/abcd/approve?"));
+        assert!(reject.starts_with("https://example.com/approvals/abcd/reject?"));
+
+        let params = |link: &str| {
+            let query = link.splitn(2, '?').nth(1).unwrap().to_string();
+            let params = query
+                .split('&')
+                .map(|param| param.splitn(2, '=').nth(1).unwrap().to_string())
+                .collect::<Vec<_>>();
+            (params[0].clone(), params[1].clone())
+        };
+
+        let (expires, token) = params(&approve);
+        assert!(links.verify("abcd", "approve", &expires, &token));
+
+        // The token is valid only for that job and action
+        assert!(!links.verify("abcd", "reject", &expires, &token));
+        assert!(!links.verify("efgh", "approve", &expires, &token));
+        assert!(!links.verify("abcd", "approve", &expires, "invalid"));
+
+        // The expiry date can't be changed
+        let later = (expires.parse::<u64>().unwrap() + 60).to_string();
+        assert!(!links.verify("abcd", "approve", &later, &token));
+        assert!(!links.verify("abcd", "approve", "invalid", &token));
+
+        // Links signed with another key are rejected
+        let other = ApprovalLinks::new("https://example.com", "other", 60);
+        assert!(!other.verify("abcd", "approve", &expires, &token));
+
+        // Expired links are rejected
+        let past = utils::unix_now() - 1;
+        let token = links.sign("abcd", "approve", past);
+        assert!(!links.verify("abcd", "approve", &past.to_string(), &token));
+    }
+
+
+    #[test]
+    fn test_confirmation_page() {
+        let hooks_dir = sample_hooks();
+        let mut blueprint = Blueprint::new(Arc::new(State::new()));
+        blueprint.collect_path(&hooks_dir, false).unwrap();
+        let hook = blueprint.repository().get_by_name("example.sh").unwrap();
+
+        let job = Job::new(hook, None, dummy_web_request().into());
+        let job_id = job.id().to_string();
+        let mut approvals = Approvals::new();
+        approvals.insert(job, 0);
+        assert!(approvals.get("invalid").is_none());
+        let pending = approvals.get(&job_id).unwrap();
+
+        // The form sends the signature of the link back
+        let links = ApprovalLinks::new("https://example.com", "secret", 60);
+        let page = links.confirmation_page(pending, "reject", "123", "<abc>");
+        assert!(page.contains(&format!(
+            "Reject the job {} of the example.sh script?", job_id,
+        )));
+        assert!(page.contains("<form method=\"post\">"));
+        assert!(page.contains("name=\"expires\" value=\"123\""));
+        assert!(page.contains("name=\"token\" value=\"&lt;abc&gt;\""));
+
+        // Showing the page doesn't take the job
+        assert_eq!(approvals.pending().len(), 1);
+
+        ::std::fs::remove_dir_all(&hooks_dir).unwrap();
+    }
+}
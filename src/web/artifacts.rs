@@ -0,0 +1,174 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Files attached to the jobs by their scripts.
+//!
+//! While a job runs, its script can upload files to the URL in
+//! `$FISHER_ARTIFACTS_URL`, authenticating with the token in
+//! `$FISHER_ARTIFACTS_TOKEN`. Every job gets its own directory, and the
+//! artifacts are kept there after the job ends so they can be downloaded
+//! through the admin API.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use common::prelude::*;
+
+
+/// An artifact attached to a job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtifactDetails {
+    pub name: String,
+    pub size: u64,
+}
+
+
+/// Check if the name can be used for an artifact: it must be a plain file
+/// name, which isn't hidden.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+
+#[derive(Debug)]
+pub struct Artifacts {
+    dir: PathBuf,
+}
+
+impl Artifacts {
+    pub fn new(dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Artifacts { dir: dir.into() })
+    }
+
+    /// Store an artifact of a job, replacing the one with the same name.
+    pub fn store<R: Read>(
+        &self, job_id: &str, name: &str, mut content: R,
+    ) -> Result<()> {
+        let dir = self.dir.join(job_id);
+        fs::create_dir_all(&dir)?;
+
+        // Partially written artifacts can't be downloaded
+        let temp = dir.join(format!(".{}.tmp", name));
+        io::copy(&mut content, &mut fs::File::create(&temp)?)?;
+        fs::rename(&temp, dir.join(name))?;
+
+        Ok(())
+    }
+
+    /// List the artifacts of a job, sorted by name.
+    pub fn list(&self, job_id: &str) -> Result<Vec<ArtifactDetails>> {
+        let entries = match fs::read_dir(self.dir.join(job_id)) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Vec::new());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut result = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(..) => continue,
+            };
+
+            // Partially written artifacts are hidden
+            let metadata = entry.metadata()?;
+            if valid_name(&name) && metadata.is_file() {
+                result.push(ArtifactDetails {
+                    name,
+                    size: metadata.len(),
+                });
+            }
+        }
+
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    /// Read an artifact of a job, if it exists.
+    pub fn read(&self, job_id: &str, name: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(job_id).join(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::{valid_name, ArtifactDetails, Artifacts};
+
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("report.html"));
+        assert!(valid_name("build-2017_10_16.tar.gz"));
+
+        assert!(!valid_name(""));
+        assert!(!valid_name(".hidden"));
+        assert!(!valid_name(".."));
+        assert!(!valid_name("../etc/passwd"));
+        assert!(!valid_name("dir/report.html"));
+        assert!(!valid_name("with space"));
+        assert!(!valid_name(&"a".repeat(256)));
+    }
+
+    #[test]
+    fn test_artifacts() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("artifacts");
+        let artifacts = Artifacts::new(path.to_str().unwrap()).unwrap();
+
+        // Jobs without artifacts don't have a directory
+        assert!(artifacts.list("abcd").unwrap().is_empty());
+        assert_eq!(artifacts.read("abcd", "report.html").unwrap(), None);
+
+        artifacts.store("abcd", "report.html", &b"<p>Hello</p>"[..]).unwrap();
+        artifacts.store("abcd", "build.log", &b"Building"[..]).unwrap();
+        artifacts.store("efgh", "build.log", &b"Failed"[..]).unwrap();
+
+        // Artifacts uploaded again are replaced
+        artifacts.store("abcd", "build.log", &b"Built"[..]).unwrap();
+
+        assert_eq!(artifacts.list("abcd").unwrap(), vec![
+            ArtifactDetails { name: "build.log".into(), size: 5 },
+            ArtifactDetails { name: "report.html".into(), size: 12 },
+        ]);
+
+        assert_eq!(
+            artifacts.read("abcd", "report.html").unwrap(),
+            Some(b"<p>Hello</p>".to_vec()),
+        );
+        assert_eq!(
+            artifacts.read("abcd", "build.log").unwrap(),
+            Some(b"Built".to_vec()),
+        );
+        assert_eq!(
+            artifacts.read("efgh", "build.log").unwrap(),
+            Some(b"Failed".to_vec()),
+        );
+    }
+}
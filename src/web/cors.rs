@@ -0,0 +1,131 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cross-origin resource sharing, allowing web pages served by other origins
+//! to call the HTTP API from the browser.
+//!
+//! Browsers send a preflight `OPTIONS` request before the requests which
+//! aren't simple enough, like the ones with the `Authorization` header used
+//! by the admin API. Those are answered directly by the HTTP server, without
+//! reaching any route.
+
+
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    max_age: u64,
+}
+
+impl Cors {
+    /// Create a new policy allowing the provided origins, or every origin if
+    /// `*` is one of them. Preflight responses are cached by the browsers
+    /// for `max_age` seconds.
+    pub fn new(
+        origins: Vec<String>, methods: Vec<String>, headers: Vec<String>,
+        max_age: u64,
+    ) -> Self {
+        Cors {
+            origins,
+            methods,
+            headers,
+            max_age,
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    /// Get the headers to add to the response to a request sent by the
+    /// origin, which are empty if the origin is not allowed.
+    pub fn headers(&self, origin: &str) -> Vec<(&'static str, String)> {
+        if !self.allows(origin) {
+            return Vec::new();
+        }
+
+        // The allowed origin depends on the request, so caches shouldn't
+        // reuse the response for other origins
+        vec![
+            ("Access-Control-Allow-Origin", origin.to_string()),
+            ("Vary", "Origin".into()),
+        ]
+    }
+
+    /// Get the headers to answer a preflight request with, or `None` if the
+    /// origin is not allowed.
+    pub fn preflight(
+        &self, origin: &str,
+    ) -> Option<Vec<(&'static str, String)>> {
+        if !self.allows(origin) {
+            return None;
+        }
+
+        let mut headers = self.headers(origin);
+        headers.push(("Access-Control-Allow-Methods", self.methods.join(", ")));
+        headers.push(("Access-Control-Allow-Headers", self.headers.join(", ")));
+        headers.push(("Access-Control-Max-Age", self.max_age.to_string()));
+        Some(headers)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Cors;
+
+
+    fn policy(origins: &[&str]) -> Cors {
+        Cors::new(
+            origins.iter().map(|o| o.to_string()).collect(),
+            vec!["GET".into(), "POST".into()],
+            vec!["Authorization".into()],
+            600,
+        )
+    }
+
+
+    #[test]
+    fn test_headers() {
+        let cors = policy(&["https://a.example.com", "https://b.example.com"]);
+        assert_eq!(cors.headers("https://b.example.com"), vec![
+            ("Access-Control-Allow-Origin", "https://b.example.com".into()),
+            ("Vary", "Origin".into()),
+        ]);
+        assert!(cors.headers("https://c.example.com").is_empty());
+        assert!(cors.headers("http://a.example.com").is_empty());
+
+        // Every origin can be allowed
+        let cors = policy(&["*"]);
+        assert_eq!(
+            cors.headers("https://c.example.com")[0].1,
+            "https://c.example.com",
+        );
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = policy(&["https://a.example.com"]);
+        assert_eq!(cors.preflight("https://a.example.com"), Some(vec![
+            ("Access-Control-Allow-Origin", "https://a.example.com".into()),
+            ("Vary", "Origin".into()),
+            ("Access-Control-Allow-Methods", "GET, POST".into()),
+            ("Access-Control-Allow-Headers", "Authorization".into()),
+            ("Access-Control-Max-Age", "600".into()),
+        ]));
+        assert!(cors.preflight("https://b.example.com").is_none());
+    }
+}
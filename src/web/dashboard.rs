@@ -0,0 +1,313 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The status dashboard, a small HTML page showing what Fisher is doing.
+//!
+//! The page is rendered from the same data returned by the admin API: the
+//! state of the processor, the running and queued jobs, the jobs which ended
+//! recently and the counters of every hook. It doesn't need JavaScript or
+//! any external resource, and it refreshes itself periodically.
+
+use std::fmt::Write;
+
+use serde_json;
+
+use common::metrics;
+use common::structs::{HealthDetails, JobState, JobStatus, QueueDetails};
+use scripts::JobProgress;
+use utils::TimestampFormat;
+
+
+/// How often browsers reload the page, in seconds.
+const REFRESH_INTERVAL: u64 = 10;
+
+static STYLE: &str = "\
+    body { font-family: sans-serif; margin: 2em; color: #222; }\
+    h1 { color: #00695c; }\
+    table { border-collapse: collapse; margin-bottom: 2em; }\
+    th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: left; }\
+    th { background: #eee; }\
+";
+
+
+/// The counters of a hook, since Fisher started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookStats {
+    pub name: String,
+    pub requests: u64,
+    pub jobs: u64,
+    pub failures: u64,
+}
+
+impl HookStats {
+    /// Collect the counters of the hooks from the metrics.
+    pub fn collect(names: &[String]) -> Vec<HookStats> {
+        let counters = metrics::snapshot();
+        let count = |metric: &str, hook: &str| -> u64 {
+            counters
+                .iter()
+                .filter(|&&((name, ref labels), _)| {
+                    name == metric && labels.iter().any(|&(key, ref value)| {
+                        key == "hook" && value == hook
+                    })
+                })
+                .map(|&(_, value)| value)
+                .sum()
+        };
+
+        names
+            .iter()
+            .map(|name| HookStats {
+                name: name.clone(),
+                requests: count("fisher_requests_total", name),
+                jobs: count("fisher_jobs_total", name),
+                failures: count("fisher_job_failures_total", name),
+            })
+            .collect()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Dashboard {
+    pub health: HealthDetails,
+    pub queue: QueueDetails,
+    pub running: Vec<JobProgress>,
+    pub recent: Vec<JobStatus>,
+    pub hooks: Vec<HookStats>,
+}
+
+impl Dashboard {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"{}\">\n\
+             <title>Fisher dashboard</title>\n<style>{}</style>\n</head>\n\
+             <body>\n<h1>Fisher dashboard</h1>\n",
+            REFRESH_INTERVAL, STYLE,
+        );
+
+        let health = &self.health;
+        table(
+            &mut out, "Processor", &["Queued jobs", "Busy threads", "State"],
+            vec![vec![
+                health.queued_jobs.to_string(),
+                format!("{} of {}", health.busy_threads, health.max_threads),
+                if health.paused { "paused" } else { "running" }.into(),
+            ]],
+        );
+
+        table(
+            &mut out, "Running jobs", &["Job", "Hook", "Started", "Progress"],
+            self.running.iter().map(|job| vec![
+                job.job_id.clone(),
+                job.script.clone(),
+                time(job.started_at),
+                match (job.percent, &job.message) {
+                    (Some(percent), &Some(ref message)) => {
+                        format!("{}% {}", percent, message)
+                    }
+                    (Some(percent), &None) => format!("{}%", percent),
+                    (None, &Some(ref message)) => message.clone(),
+                    (None, &None) => String::new(),
+                },
+            ]).collect(),
+        );
+
+        table(
+            &mut out, "Queue", &["Job", "Hook", "Priority", "Queued", "Why"],
+            self.queue.jobs.iter().map(|job| vec![
+                job.job_id.clone(),
+                job.script.clone(),
+                job.priority.to_string(),
+                time(job.queued_at),
+                job.reason.into(),
+            ]).collect(),
+        );
+
+        table(
+            &mut out, "Recent jobs",
+            &["Job", "Hook", "Outcome", "Exit code", "Queued", "Ended"],
+            self.recent.iter().map(|job| vec![
+                job.job_id.clone(),
+                job.script.clone(),
+                state_name(job.state),
+                job.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+                time(job.queued_at),
+                job.finished_at.map(time).unwrap_or_default(),
+            ]).collect(),
+        );
+
+        table(
+            &mut out, "Hooks", &["Hook", "Requests", "Jobs", "Failures"],
+            self.hooks.iter().map(|hook| vec![
+                hook.name.clone(),
+                hook.requests.to_string(),
+                hook.jobs.to_string(),
+                hook.failures.to_string(),
+            ]).collect(),
+        );
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+
+fn table(
+    out: &mut String, title: &str, headers: &[&str], rows: Vec<Vec<String>>,
+) {
+    let _ = write!(out, "<h2>{}</h2>\n", escape(title));
+    if rows.is_empty() {
+        out.push_str("<p>Nothing to show.</p>\n");
+        return;
+    }
+
+    out.push_str("<table>\n<tr>");
+    for header in headers {
+        let _ = write!(out, "<th>{}</th>", escape(header));
+    }
+    out.push_str("</tr>\n");
+    for row in rows {
+        out.push_str("<tr>");
+        for cell in row {
+            let _ = write!(out, "<td>{}</td>", escape(&cell));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+
+/// Get the name of the state, as it's shown by the admin API.
+fn state_name(state: JobState) -> String {
+    serde_json::to_value(state)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+
+fn time(timestamp: u64) -> String {
+    TimestampFormat::Rfc3339.to_string(timestamp)
+}
+
+
+pub fn escape(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use common::metrics;
+    use common::structs::{
+        HealthDetails, JobState, JobStatus, QueueDetails, QueuedJob,
+    };
+
+    use super::{escape, Dashboard, HookStats};
+
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;",
+        );
+    }
+
+    #[test]
+    fn test_hook_stats() {
+        let hook = "dashboard-stats.sh";
+        metrics::increment("fisher_requests_total", &[
+            ("hook", hook), ("provider", "GitHub"), ("outcome", "queued"),
+        ]);
+        metrics::increment("fisher_requests_total", &[
+            ("hook", hook), ("provider", "GitHub"), ("outcome", "rejected"),
+        ]);
+        metrics::increment("fisher_jobs_total", &[("hook", hook)]);
+
+        let stats = HookStats::collect(&[hook.to_string()]);
+        assert_eq!(stats, vec![HookStats {
+            name: hook.into(),
+            requests: 2,
+            jobs: 1,
+            failures: 0,
+        }]);
+    }
+
+    #[test]
+    fn test_render() {
+        let dashboard = Dashboard {
+            health: HealthDetails {
+                queued_jobs: 1,
+                busy_threads: 0,
+                max_threads: 2,
+                paused: true,
+                uptime: 60,
+                hooks: 1,
+                processed_jobs: 0,
+                failed_jobs: 0,
+                workers: Vec::new(),
+                build: None,
+                freezes: None,
+            },
+            queue: QueueDetails {
+                jobs: vec![QueuedJob {
+                    job_id: "abcd".into(),
+                    script: "<script>.sh".into(),
+                    priority: 0,
+                    queued_at: 1508152800,
+                    reason: "queued",
+                }],
+                workers: Vec::new(),
+            },
+            running: Vec::new(),
+            recent: vec![JobStatus {
+                job_id: "efgh".into(),
+                script: "deploy.sh".into(),
+                state: JobState::Failed,
+                exit_code: Some(1),
+                queued_at: 1508152800,
+                started_at: Some(1508152800),
+                finished_at: Some(1508152810),
+            }],
+            hooks: Vec::new(),
+        };
+
+        let html = dashboard.render();
+        assert!(html.contains("<td>1</td><td>0 of 2</td><td>paused</td>"));
+        assert!(html.contains("<h2>Running jobs</h2>\n<p>Nothing to show."));
+        // The names of the hooks are escaped
+        assert!(html.contains("<td>abcd</td><td>&lt;script&gt;.sh</td>"));
+        assert!(html.contains(
+            "<td>efgh</td><td>deploy.sh</td><td>failed</td><td>1</td>\
+             <td>2017-10-16T11:20:00Z</td><td>2017-10-16T11:20:10Z</td>",
+        ));
+    }
+}
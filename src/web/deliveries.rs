@@ -0,0 +1,225 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of the recently processed deliveries.
+//!
+//! Some providers attach an unique identifier to every delivery, which stays
+//! the same when a webhook is redelivered. Fisher remembers the last
+//! identifiers it processed, so the same delivery isn't executed twice. The
+//! cache is bounded, and the oldest identifiers are forgotten first.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use common::prelude::*;
+
+
+#[derive(Debug)]
+pub struct DeliveriesCache {
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+    capacity: usize,
+    path: Option<PathBuf>,
+    /// The file new deliveries are appended to, opened when it's needed.
+    file: Option<fs::File>,
+    /// How many deliveries are in the file, including the forgotten ones.
+    persisted: usize,
+}
+
+impl DeliveriesCache {
+    pub fn new(capacity: usize, path: Option<PathBuf>) -> Result<Self> {
+        let mut cache = DeliveriesCache {
+            order: VecDeque::with_capacity(capacity),
+            seen: HashSet::with_capacity(capacity),
+            capacity,
+            path,
+            file: None,
+            persisted: 0,
+        };
+
+        // Load the deliveries persisted by a previous instance
+        if let Some(ref path) = cache.path.clone() {
+            if path.exists() {
+                let file = BufReader::new(fs::File::open(path)?);
+                for line in file.lines() {
+                    cache.record(line?);
+                    cache.persisted += 1;
+                }
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Record a new delivery, returning false if it was already seen.
+    pub fn insert(&mut self, id: String) -> Result<bool> {
+        if self.seen.contains(&id) {
+            return Ok(false);
+        }
+
+        self.record(id.clone());
+        self.append(&id)?;
+
+        Ok(true)
+    }
+
+    /// Forget a delivery, so it's processed again if it's delivered again.
+    pub fn remove(&mut self, id: &str) -> Result<()> {
+        if self.seen.remove(id) {
+            self.order.retain(|old| old != id);
+            self.persist()?;
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, id: String) {
+        if self.capacity == 0 || self.seen.contains(&id) {
+            return;
+        }
+
+        // Forget the oldest deliveries if the cache is full
+        while self.order.len() >= self.capacity {
+            if let Some(old) = self.order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+
+        self.seen.insert(id.clone());
+        self.order.push_back(id);
+    }
+
+    fn append(&mut self, id: &str) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) if self.capacity > 0 => path.clone(),
+            _ => return Ok(()),
+        };
+
+        // The forgotten deliveries are removed from the file only once they
+        // are as many as the remembered ones
+        if self.persisted >= self.capacity * 2 {
+            return self.persist();
+        }
+
+        if self.file.is_none() {
+            self.file = Some(
+                fs::OpenOptions::new().create(true).append(true).open(path)?,
+            );
+        }
+        if let Some(ref mut file) = self.file {
+            file.write_all(format!("{}\n", id).as_bytes())?;
+        }
+        self.persisted += 1;
+
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<()> {
+        if let Some(ref path) = self.path {
+            // Write to a temporary file first, to avoid corrupting the cache
+            // if Fisher crashes while writing it
+            let temp = path.with_extension("tmp");
+            {
+                let mut file = fs::File::create(&temp)?;
+                for id in &self.order {
+                    writeln!(file, "{}", id)?;
+                }
+            }
+            fs::rename(&temp, path)?;
+
+            // The old file was replaced, so it has to be opened again
+            self.file = None;
+            self.persisted = self.order.len();
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::DeliveriesCache;
+
+
+    #[test]
+    fn test_duplicates_are_detected() {
+        let mut cache = DeliveriesCache::new(10, None).unwrap();
+
+        assert!(cache.insert("a".into()).unwrap());
+        assert!(cache.insert("b".into()).unwrap());
+        assert!(!cache.insert("a".into()).unwrap());
+        assert!(!cache.insert("b".into()).unwrap());
+
+        // Removed deliveries are accepted again
+        cache.remove("a").unwrap();
+        cache.remove("c").unwrap();
+        assert!(cache.insert("a".into()).unwrap());
+        assert!(!cache.insert("b".into()).unwrap());
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let mut cache = DeliveriesCache::new(2, None).unwrap();
+
+        assert!(cache.insert("a".into()).unwrap());
+        assert!(cache.insert("b".into()).unwrap());
+        assert!(cache.insert("c".into()).unwrap());
+
+        // "a" was the oldest delivery, so it was forgotten
+        assert!(!cache.insert("c".into()).unwrap());
+        assert!(cache.insert("a".into()).unwrap());
+    }
+
+    #[test]
+    fn test_cache_is_persisted() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("deliveries");
+
+        let mut cache = DeliveriesCache::new(10, Some(path.clone())).unwrap();
+        assert!(cache.insert("a".into()).unwrap());
+        assert!(cache.insert("b".into()).unwrap());
+
+        // A new cache should remember the old deliveries
+        let mut cache = DeliveriesCache::new(10, Some(path.clone())).unwrap();
+        assert!(!cache.insert("a".into()).unwrap());
+        assert!(!cache.insert("b".into()).unwrap());
+        assert!(cache.insert("c".into()).unwrap());
+    }
+
+    #[test]
+    fn test_cache_is_compacted() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("deliveries");
+
+        // The forgotten deliveries are removed from the file periodically
+        let mut cache = DeliveriesCache::new(2, Some(path.clone())).unwrap();
+        for id in &["a", "b", "c", "d", "e", "f"] {
+            assert!(cache.insert(id.to_string()).unwrap());
+            assert!(fs::read_to_string(&path).unwrap().lines().count() <= 4);
+        }
+
+        let mut cache = DeliveriesCache::new(2, Some(path.clone())).unwrap();
+        assert!(!cache.insert("e".into()).unwrap());
+        assert!(!cache.insert("f".into()).unwrap());
+        assert!(cache.insert("d".into()).unwrap());
+    }
+}
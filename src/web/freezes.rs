@@ -0,0 +1,242 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Freezes preventing scripts from being executed for a while.
+//!
+//! A freeze can affect every script or only one of them, and it lasts until
+//! it expires or it's lifted through the admin API. Depending on its action,
+//! the webhooks received during a freeze are rejected or their jobs are kept
+//! here until it ends. Freezes are shared between restarts of the HTTP
+//! server.
+
+use common::prelude::*;
+use common::structs::{FreezeAction, FreezeDetails};
+use scripts::Job;
+use utils;
+
+
+/// A job received during a freeze, waiting for it to end.
+#[derive(Debug)]
+pub struct HeldJob {
+    pub job: Job,
+    pub priority: isize,
+    pub received_at: u64,
+}
+
+
+#[derive(Debug, Default)]
+pub struct Freezes {
+    freezes: Vec<FreezeDetails>,
+    held: Vec<HeldJob>,
+}
+
+impl Freezes {
+    pub fn new() -> Self {
+        Freezes {
+            freezes: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    /// Start a freeze, replacing the existing one for the same script.
+    pub fn freeze(&mut self, freeze: FreezeDetails) {
+        self.lift(freeze.script.as_deref());
+        self.freezes.push(freeze);
+    }
+
+    /// Lift the freeze of a script, or the global one if no script is
+    /// provided. Returns false if there was no such freeze.
+    pub fn lift(&mut self, script: Option<&str>) -> bool {
+        let before = self.freezes.len();
+        self.freezes.retain(|f| f.script.as_deref() != script);
+        self.freezes.len() != before
+    }
+
+    /// Get the freeze affecting a script at the provided time, if any. The
+    /// freeze of the script takes precedence over the global one.
+    pub fn active(&self, script: &str, now: u64) -> Option<&FreezeDetails> {
+        let active = || self.freezes.iter().filter(move |f| !expired(f, now));
+
+        active()
+            .find(|f| f.script.as_deref() == Some(script))
+            .or_else(|| active().find(|f| f.script.is_none()))
+    }
+
+    /// Get all the freezes not expired at the provided time.
+    pub fn list(&self, now: u64) -> Vec<FreezeDetails> {
+        self.freezes
+            .iter()
+            .filter(|f| !expired(f, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Keep a job until the freeze affecting its script ends.
+    pub fn hold(&mut self, job: Job, priority: isize) {
+        self.held.push(HeldJob {
+            job,
+            priority,
+            received_at: utils::unix_now(),
+        });
+    }
+
+    /// Get all the jobs waiting for a freeze to end, oldest first.
+    pub fn held(&self) -> &[HeldJob] {
+        &self.held
+    }
+
+    /// Remove the expired freezes, and return the held jobs which aren't
+    /// affected by a freeze anymore.
+    pub fn release(&mut self, now: u64) -> Vec<HeldJob> {
+        self.freezes.retain(|f| !expired(f, now));
+
+        let freezes = &self.freezes;
+        let (released, held): (Vec<_>, Vec<_>) = self.held
+            .drain(..)
+            .partition(|held| !freezes.iter().any(|f| {
+                f.script.is_none()
+                    || f.script.as_deref() == Some(held.job.script_name())
+            }));
+        self.held = held;
+
+        released
+    }
+
+    /// Put back jobs previously released which couldn't be executed, so
+    /// they're released again later.
+    pub fn restore(&mut self, mut jobs: Vec<HeldJob>) {
+        jobs.append(&mut self.held);
+        self.held = jobs;
+    }
+}
+
+
+fn expired(freeze: &FreezeDetails, now: u64) -> bool {
+    freeze.expires_at.map(|expires_at| expires_at <= now).unwrap_or(false)
+}
+
+
+/// Create a new freeze, starting now.
+pub fn new_freeze(
+    script: Option<&str>, reason: &str, action: FreezeAction,
+    duration: Option<u64>,
+) -> FreezeDetails {
+    FreezeDetails {
+        script: script.map(|s| s.to_string()),
+        reason: reason.to_string(),
+        action,
+        expires_at: duration.map(|d| utils::unix_now() + d),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common::prelude::*;
+    use common::state::State;
+    use common::structs::{FreezeAction, FreezeDetails};
+    use scripts::{Blueprint, Job};
+    use utils::testing::*;
+
+    use super::Freezes;
+
+
+    fn freeze(script: Option<&str>, expires_at: Option<u64>) -> FreezeDetails {
+        FreezeDetails {
+            script: script.map(|s| s.to_string()),
+            reason: "testing".into(),
+            action: FreezeAction::Queue,
+            expires_at,
+        }
+    }
+
+
+    #[test]
+    fn test_active_freezes() {
+        let mut freezes = Freezes::new();
+        assert!(freezes.active("example.sh", 10).is_none());
+
+        // Global freezes affect every script
+        freezes.freeze(freeze(None, Some(20)));
+        assert_eq!(freezes.active("example.sh", 10).unwrap().script, None);
+        assert!(freezes.active("example.sh", 20).is_none());
+        assert_eq!(freezes.list(10).len(), 1);
+        assert!(freezes.list(20).is_empty());
+
+        // The freeze of a script takes precedence over the global one
+        freezes.freeze(freeze(Some("example.sh"), None));
+        assert_eq!(
+            freezes.active("example.sh", 10).unwrap().script,
+            Some("example.sh".into())
+        );
+        assert_eq!(freezes.active("other.sh", 10).unwrap().script, None);
+        assert_eq!(freezes.list(10).len(), 2);
+
+        // Freezing again replaces the existing freeze
+        freezes.freeze(freeze(None, None));
+        assert_eq!(freezes.list(20).len(), 2);
+
+        assert!(freezes.lift(Some("example.sh")));
+        assert!(!freezes.lift(Some("example.sh")));
+        assert_eq!(freezes.active("example.sh", 10).unwrap().script, None);
+        assert!(freezes.lift(None));
+        assert!(freezes.active("example.sh", 10).is_none());
+    }
+
+
+    #[test]
+    fn test_held_jobs() {
+        let hooks_dir = sample_hooks();
+        let mut blueprint = Blueprint::new(Arc::new(State::new()));
+        blueprint.collect_path(&hooks_dir, false).unwrap();
+        let repository = blueprint.repository();
+        let job = |name| Job::new(
+            repository.get_by_name(name).unwrap(), None,
+            dummy_web_request().into(),
+        );
+
+        let mut freezes = Freezes::new();
+        freezes.freeze(freeze(None, Some(20)));
+        freezes.freeze(freeze(Some("example.sh"), None));
+        freezes.hold(job("example.sh"), 1);
+        freezes.hold(job("failing.sh"), 2);
+        assert_eq!(freezes.held().len(), 2);
+
+        // Nothing is released while the global freeze is active
+        assert!(freezes.release(10).is_empty());
+
+        // Only the jobs of scripts not frozen anymore are released
+        let released = freezes.release(20);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].job.script_name(), "failing.sh");
+        assert_eq!(released[0].priority, 2);
+        assert_eq!(freezes.held().len(), 1);
+
+        // Restored jobs are released again later
+        freezes.restore(released);
+        assert_eq!(freezes.held()[0].job.script_name(), "failing.sh");
+        assert_eq!(freezes.release(30).len(), 1);
+
+        freezes.lift(Some("example.sh"));
+        let released = freezes.release(30);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].job.script_name(), "example.sh");
+        assert!(freezes.held().is_empty());
+
+        ::std::fs::remove_dir_all(&hooks_dir).unwrap();
+    }
+}
@@ -0,0 +1,837 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Frontend of the HTTP server, preparing the connections it accepts.
+//!
+//! Connections are handled asynchronously, so clients keeping them open
+//! don't need a thread each. The frontend terminates TLS and enforces the
+//! limits on the connections, like how long a client can take to send its
+//! requests, by looking at the data flowing through them before the HTTP
+//! server parses it.
+
+use std::cmp;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::{future, Async, Future, Poll};
+use rustls::ServerConfig;
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::timer::{Delay, Timeout};
+
+use common::prelude::*;
+use web::responses::Response;
+use web::tls::TlsStream;
+
+
+/// Limits applied to every connection, to avoid clients keeping the HTTP
+/// server busy forever.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// How long the headers of a request can take to be received, and how
+    /// long the body can go without receiving any data.
+    pub read_timeout: Option<Duration>,
+    /// How long sending the response can go without any progress.
+    pub write_timeout: Option<Duration>,
+    /// How long a connection can stay idle between two requests.
+    pub keep_alive_timeout: Option<Duration>,
+    /// The maximum size of the headers of a request, in bytes.
+    pub max_header_size: Option<usize>,
+    /// The maximum number of connections open at the same time.
+    pub max_connections: Option<usize>,
+}
+
+
+/// Prepares the connections accepted by the HTTP server.
+pub struct Frontend {
+    tls_config: Option<RwLock<Arc<ServerConfig>>>,
+    limits: ConnectionLimits,
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl Frontend {
+    pub fn new(
+        tls_config: Option<Arc<ServerConfig>>, limits: ConnectionLimits,
+    ) -> Self {
+        Frontend {
+            tls_config: tls_config.map(RwLock::new),
+            limits,
+            open_connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Replace the TLS configuration used by new connections. This does
+    /// nothing if TLS wasn't enabled when the frontend was created.
+    pub fn reload_tls(&self, config: Arc<ServerConfig>) -> Result<()> {
+        if let Some(ref current) = self.tls_config {
+            *current.write()? = config;
+        }
+        Ok(())
+    }
+
+    /// Prepare a connection just accepted by the listener, failing if the
+    /// connection is rejected.
+    pub fn accept(
+        &self, client: TcpStream,
+    ) -> Box<Future<Item = Connection, Error = ()> + Send> {
+        let open = Counted::new(&self.open_connections);
+        if let Some(max) = self.limits.max_connections {
+            if open.count() > max {
+                // TLS clients would need a handshake to receive the
+                // response, so their connection is just closed
+                if self.tls_config.is_some() {
+                    return Box::new(future::err(()));
+                }
+
+                let error = ErrorKind::TooManyConnections(max);
+                let response = rejection(&error.into()).unwrap();
+                return Box::new(
+                    io::write_all(client, response).then(|_| Err(())),
+                );
+            }
+        }
+
+        let peer = match client.peer_addr() {
+            Ok(peer) => peer,
+            Err(..) => return Box::new(future::err(())),
+        };
+        let limits = self.limits;
+        let prepare = move |transport: Box<Transport>, http2| {
+            Connection::new(transport, peer, limits, open, http2)
+        };
+
+        // Connections keep the configuration they started with, even if
+        // it's reloaded in the meantime
+        let config = match self.tls_config {
+            Some(ref config) => match config.read() {
+                Ok(config) => config.clone(),
+                Err(..) => return Box::new(future::err(())),
+            },
+            None => {
+                return Box::new(future::ok(prepare(Box::new(client), false)));
+            }
+        };
+
+        // The handshake must be completed before the read timeout, and
+        // failing it is caused by misbehaving clients
+        let handshake = TlsStream::accept(client, &config);
+        let handshake: Box<Future<Item = _, Error = ()> + Send> =
+            match limits.read_timeout {
+                Some(timeout) => Box::new(
+                    Timeout::new(handshake, timeout).map_err(|_| ()),
+                ),
+                None => Box::new(handshake.map_err(|_| ())),
+            };
+
+        Box::new(handshake.map(move |stream| {
+            let http2 = stream.alpn_protocol() == Some(&b"h2"[..]);
+            prepare(Box::new(stream), http2)
+        }))
+    }
+}
+
+
+/// The underlying connection of a client, either plain or over TLS.
+pub trait Transport: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Transport for T {}
+
+
+/// A connection accepted by the frontend, which enforces the limits on it
+/// while the HTTP server reads the requests and writes the responses.
+pub struct Connection {
+    transport: Box<Transport>,
+    peer: SocketAddr,
+    http2: bool,
+    requests: RequestTracker,
+    responses: ResponseTracker,
+    active_requests: Arc<AtomicUsize>,
+    last_activity: Instant,
+    keep_alive_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_timer: Option<Delay>,
+    write_timer: Option<Delay>,
+    _open: Counted,
+}
+
+impl Connection {
+    fn new(
+        transport: Box<Transport>,
+        peer: SocketAddr,
+        limits: ConnectionLimits,
+        open: Counted,
+        http2: bool,
+    ) -> Self {
+        let (requests, responses) = RequestTracker::new(limits);
+
+        Connection {
+            transport,
+            peer,
+            http2,
+            requests,
+            responses,
+            active_requests: Arc::new(AtomicUsize::new(0)),
+            last_activity: Instant::now(),
+            keep_alive_timeout: limits.keep_alive_timeout,
+            write_timeout: limits.write_timeout,
+            read_timer: None,
+            write_timer: None,
+            _open: open,
+        }
+    }
+
+    /// Get the address of the client.
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Check whether the client negotiated HTTP/2 during the TLS handshake.
+    pub fn is_http2(&self) -> bool {
+        self.http2
+    }
+
+    /// Get a function counting the requests being handled, which keep the
+    /// connection from being considered idle.
+    pub fn request_counter(&self) -> impl Fn() -> Counted {
+        let counter = self.active_requests.clone();
+        move || Counted::new(&counter)
+    }
+
+    /// Get how long the next read can wait, or `None` if there is no limit.
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        if !self.http2 {
+            return self.requests.read_timeout();
+        }
+
+        // HTTP/2 multiplexes the requests over the connection, so only how
+        // long it stays idle is limited
+        let timeout = match self.keep_alive_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(None),
+        };
+        if self.active_requests.load(Ordering::SeqCst) > 0 {
+            return Ok(Some(timeout));
+        }
+
+        let elapsed = self.last_activity.elapsed();
+        if elapsed >= timeout {
+            Err(ErrorKind::ConnectionIdle.into())
+        } else {
+            Ok(Some(timeout - elapsed))
+        }
+    }
+
+    /// Close the connection because of the provided error, sending the
+    /// response for it if there is one.
+    fn reject(&mut self, error: Error) -> io::Result<usize> {
+        // Idle connections are closed as if the client closed them
+        if let ErrorKind::ConnectionIdle = *error.kind() {
+            return Ok(0);
+        }
+
+        if let Some(response) = rejection(&error) {
+            let _ = self.transport.write_all(&response);
+            let _ = self.transport.flush();
+        }
+        Err(io::Error::new(io::ErrorKind::Other, error.to_string()))
+    }
+
+    /// Fail writes blocked for longer than the write timeout.
+    fn check_write<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        match result {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            other => {
+                self.write_timer = None;
+                return other;
+            }
+        }
+
+        if let Some(timeout) = self.write_timeout {
+            // The timeout starts when the client stops receiving data
+            let deadline = Instant::now() + timeout;
+            let timer =
+                self.write_timer.get_or_insert_with(|| Delay::new(deadline));
+            if expired(timer)? {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+
+        result
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let timeout = match self.read_timeout() {
+                Ok(timeout) => timeout,
+                Err(error) => return self.reject(error),
+            };
+
+            match self.transport.read(buf) {
+                Ok(read) => {
+                    self.last_activity = Instant::now();
+                    if self.http2 {
+                        return Ok(read);
+                    }
+
+                    if let Err(error) = self.requests.feed(&buf[..read]) {
+                        return self.reject(error);
+                    }
+                    return Ok(read);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+
+            // Wake the connection up when the timeout expires, to check the
+            // limits again
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => return Err(io::ErrorKind::WouldBlock.into()),
+            };
+            let deadline = Instant::now() + timeout;
+            let timer =
+                self.read_timer.get_or_insert_with(|| Delay::new(deadline));
+            timer.reset(deadline);
+            if !expired(timer)? {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.transport.write(buf);
+        let written = self.check_write(result)?;
+
+        self.last_activity = Instant::now();
+        if self.http2 {
+            return Ok(written);
+        }
+
+        if let Err(error) = self.responses.feed(&buf[..written]) {
+            return Err(io::Error::new(io::ErrorKind::Other, error.to_string()));
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.transport.flush();
+        self.check_write(result)
+    }
+}
+
+impl AsyncRead for Connection {}
+
+impl AsyncWrite for Connection {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.transport.shutdown()
+    }
+}
+
+
+/// Check whether the timer expired, waking the current task up when it
+/// does if it didn't yet.
+fn expired(timer: &mut Delay) -> io::Result<bool> {
+    match timer.poll() {
+        Ok(Async::Ready(())) => Ok(true),
+        Ok(Async::NotReady) => Ok(false),
+        Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+    }
+}
+
+
+/// Something counted by the counter until it's dropped, like an open
+/// connection or a request being handled.
+pub struct Counted {
+    counter: Arc<AtomicUsize>,
+    count: usize,
+}
+
+impl Counted {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        Counted {
+            counter: counter.clone(),
+            count: counter.fetch_add(1, Ordering::SeqCst) + 1,
+        }
+    }
+
+    /// Get the number of counted things, including this one.
+    fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Get the response sent to the client when its connection is closed
+/// because of the provided error, if any.
+fn rejection(error: &Error) -> Option<Vec<u8>> {
+    let (response, reason) = match *error.kind() {
+        ErrorKind::RequestTimedOut => {
+            (Response::RequestTimeout, "Request Timeout")
+        }
+        ErrorKind::RequestHeadersTooLarge(max) => (
+            Response::HeadersTooLarge(max),
+            "Request Header Fields Too Large",
+        ),
+        ErrorKind::TooManyConnections(..) => {
+            (Response::Unavailable, "Service Unavailable")
+        }
+        _ => return None,
+    };
+
+    let body = response.json();
+    Some(format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status(), reason, body.len(), body,
+    ).into_bytes())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameState {
+    Headers,
+    Body(u64),
+    ChunkSize,
+    ChunkData(u64),
+    Trailers,
+}
+
+
+/// Follow the HTTP messages sent in one direction of a connection, to know
+/// where each one of them ends.
+struct Framer {
+    max_header_size: Option<usize>,
+    state: FrameState,
+    started: Option<Instant>,
+    completed: usize,
+    line: Vec<u8>,
+    headers: Vec<u8>,
+}
+
+impl Framer {
+    fn new(max_header_size: Option<usize>) -> Self {
+        Framer {
+            max_header_size,
+            state: FrameState::Headers,
+            started: None,
+            completed: 0,
+            line: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let consumed = match self.state {
+                FrameState::Body(remaining)
+                | FrameState::ChunkData(remaining) => {
+                    let consumed = cmp::min(remaining, data.len() as u64);
+                    let remaining = remaining - consumed;
+                    if let FrameState::Body(..) = self.state {
+                        self.state = FrameState::Body(remaining);
+                    } else {
+                        self.state = FrameState::ChunkData(remaining);
+                    }
+                    consumed as usize
+                }
+                _ => {
+                    self.feed_line(data[0])?;
+                    1
+                }
+            };
+            data = &data[consumed..];
+
+            match self.state {
+                FrameState::Body(0) => self.finish_message(true),
+                FrameState::ChunkData(0) => {
+                    self.state = FrameState::ChunkSize;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn feed_line(&mut self, byte: u8) -> Result<()> {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        if self.state == FrameState::Headers {
+            self.headers.push(byte);
+            if let Some(max) = self.max_header_size {
+                if self.headers.len() > max {
+                    return Err(ErrorKind::RequestHeadersTooLarge(max).into());
+                }
+            }
+        }
+
+        if byte != b'\n' {
+            self.line.push(byte);
+            return Ok(());
+        }
+
+        let line_is_empty = self.line.is_empty() || self.line == b"\r";
+        match self.state {
+            FrameState::Headers => {
+                // Ignore the empty lines before the first line
+                if line_is_empty && self.headers.len() <= 2 {
+                    self.headers.clear();
+                } else if line_is_empty {
+                    self.start_body()?;
+                }
+            }
+            FrameState::ChunkSize => {
+                let size = str::from_utf8(&self.line)
+                    .ok()
+                    .and_then(|line| line.split(';').next())
+                    .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+                    .ok_or_else(|| ErrorKind::RequestMalformed)?;
+
+                if size == 0 {
+                    self.state = FrameState::Trailers;
+                } else {
+                    // The chunk is followed by a line break
+                    self.state = FrameState::ChunkData(size + 2);
+                }
+            }
+            FrameState::Trailers => {
+                if line_is_empty {
+                    self.finish_message(true);
+                }
+            }
+            FrameState::Body(..) | FrameState::ChunkData(..) => {
+                unreachable!();
+            }
+        }
+
+        self.line.clear();
+        Ok(())
+    }
+
+    fn start_body(&mut self) -> Result<()> {
+        // Informational responses are always followed by the real one
+        let informational = self.headers.starts_with(b"HTTP/")
+            && self.headers.splitn(2, |byte| *byte == b' ')
+                .nth(1)
+                .map(|status| status.starts_with(b"1"))
+                .unwrap_or(false);
+
+        let mut length = 0;
+        let mut chunked = false;
+        for line in String::from_utf8_lossy(&self.headers).lines() {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = parts.next().unwrap_or("").trim();
+
+            if name == "content-length" {
+                length = value
+                    .parse()
+                    .map_err(|_| ErrorKind::RequestMalformed)?;
+            } else if name == "transfer-encoding" {
+                chunked = value.to_lowercase().contains("chunked");
+            }
+        }
+        self.headers.clear();
+
+        if informational {
+            self.finish_message(false);
+        } else if chunked {
+            self.state = FrameState::ChunkSize;
+        } else if length > 0 {
+            self.state = FrameState::Body(length);
+        } else {
+            self.finish_message(true);
+        }
+
+        Ok(())
+    }
+
+    fn finish_message(&mut self, complete: bool) {
+        self.state = FrameState::Headers;
+        self.started = None;
+        if complete {
+            self.completed += 1;
+        }
+    }
+}
+
+
+/// Progress of the responses sent over a connection.
+struct ResponsesProgress {
+    completed: usize,
+    last_completed: Instant,
+}
+
+
+/// Follow the requests sent over a connection, to enforce the limits on
+/// them.
+struct RequestTracker {
+    limits: ConnectionLimits,
+    framer: Framer,
+    last_read: Instant,
+    idle_since: Instant,
+    responses: Arc<Mutex<ResponsesProgress>>,
+}
+
+impl RequestTracker {
+    fn new(limits: ConnectionLimits) -> (Self, ResponseTracker) {
+        let now = Instant::now();
+        let responses = Arc::new(Mutex::new(ResponsesProgress {
+            completed: 0,
+            last_completed: now,
+        }));
+
+        let mut framer = Framer::new(limits.max_header_size);
+        // Clients must start sending the first request right away
+        framer.started = Some(now);
+
+        let requests = RequestTracker {
+            limits,
+            framer,
+            last_read: now,
+            idle_since: now,
+            responses: responses.clone(),
+        };
+        let responses = ResponseTracker {
+            framer: Framer::new(None),
+            responses,
+        };
+
+        (requests, responses)
+    }
+
+    fn idle(&self) -> bool {
+        self.framer.state == FrameState::Headers
+            && self.framer.started.is_none()
+    }
+
+    /// Get how long the next read can wait, or `None` if there is no limit.
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        let remaining = |since: Instant, timeout: Duration| {
+            let elapsed = since.elapsed();
+            if elapsed >= timeout {
+                None
+            } else {
+                Some(timeout - elapsed)
+            }
+        };
+
+        if self.idle() {
+            let timeout = match self.limits.keep_alive_timeout {
+                Some(timeout) => timeout,
+                None => return Ok(None),
+            };
+
+            // Connections waiting for a response are not idle, since the
+            // response can take a while to be ready
+            let responses = self.responses.lock()?;
+            if responses.completed < self.framer.completed {
+                return Ok(Some(timeout));
+            }
+
+            let since = cmp::max(self.idle_since, responses.last_completed);
+            return match remaining(since, timeout) {
+                Some(timeout) => Ok(Some(timeout)),
+                None => Err(ErrorKind::ConnectionIdle.into()),
+            };
+        }
+
+        let timeout = match self.limits.read_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(None),
+        };
+
+        // The whole headers must be received before the timeout, to avoid
+        // clients sending them one byte at a time, while the body only
+        // can't stall for longer than it
+        let since = match (self.framer.state, self.framer.started) {
+            (FrameState::Headers, Some(started)) => started,
+            _ => self.last_read,
+        };
+        match remaining(since, timeout) {
+            Some(timeout) => Ok(Some(timeout)),
+            None => Err(ErrorKind::RequestTimedOut.into()),
+        }
+    }
+
+    /// Follow the data received from the client.
+    fn feed(&mut self, data: &[u8]) -> Result<()> {
+        self.last_read = Instant::now();
+        self.framer.feed(data)?;
+        if self.idle() {
+            self.idle_since = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+
+/// Follow the responses sent over a connection, to know when it's idle.
+struct ResponseTracker {
+    framer: Framer,
+    responses: Arc<Mutex<ResponsesProgress>>,
+}
+
+impl ResponseTracker {
+    /// Follow the data sent to the client.
+    fn feed(&mut self, data: &[u8]) -> Result<()> {
+        let completed = self.framer.completed;
+        self.framer.feed(data)?;
+
+        if self.framer.completed != completed {
+            let mut responses = self.responses.lock()?;
+            responses.completed = self.framer.completed;
+            responses.last_completed = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use common::prelude::*;
+
+    use super::{
+        rejection, ConnectionLimits, FrameState, Framer, RequestTracker,
+    };
+
+
+    #[test]
+    fn test_framer() {
+        let mut f = Framer::new(None);
+
+        // Requests without a body end with the headers
+        f.feed(b"GET / HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        assert_eq!(f.state, FrameState::Headers);
+        assert!(f.started.is_some());
+        f.feed(b"\r\n").unwrap();
+        assert_eq!(f.state, FrameState::Headers);
+        assert!(f.started.is_none());
+        assert_eq!(f.completed, 1);
+
+        // Requests with a body
+        f.feed(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab").unwrap();
+        assert_eq!(f.state, FrameState::Body(3));
+        f.feed(b"cde").unwrap();
+        assert!(f.started.is_none());
+        assert_eq!(f.completed, 2);
+
+        // Chunked requests, split in weird places
+        f.feed(b"POST / HTTP/1.1\r\ntransfer-encoding: Chunked\r\n").unwrap();
+        f.feed(b"\r\n3;ext=1\r\nabc\r").unwrap();
+        assert_eq!(f.state, FrameState::ChunkData(1));
+        f.feed(b"\n0\r\nTrailer: yes\r\n").unwrap();
+        assert_eq!(f.state, FrameState::Trailers);
+        f.feed(b"\r\nGET / HTTP/1.1\r\n").unwrap();
+        assert_eq!(f.state, FrameState::Headers);
+        assert!(f.started.is_some());
+        assert_eq!(f.completed, 3);
+
+        // Informational responses are not counted
+        let mut f = Framer::new(None);
+        f.feed(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        assert_eq!(f.completed, 0);
+        f.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+        assert_eq!(f.completed, 1);
+
+        // Invalid chunk sizes are rejected
+        let mut f = Framer::new(None);
+        f.feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .unwrap();
+        assert!(f.feed(b"nope\r\n").is_err());
+    }
+
+
+    #[test]
+    fn test_framer_max_header_size() {
+        let mut f = Framer::new(Some(64));
+        f.feed(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n").unwrap();
+
+        // The body doesn't count
+        f.feed(&[b'a'; 100]).unwrap();
+
+        match f.feed(&[b'a'; 65]).unwrap_err().kind() {
+            ErrorKind::RequestHeadersTooLarge(64) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn test_request_tracker_timeouts() {
+        let (mut requests, mut responses) = RequestTracker::new(
+            ConnectionLimits {
+                read_timeout: Some(Duration::from_secs(10)),
+                keep_alive_timeout: Some(Duration::from_millis(100)),
+                ..ConnectionLimits::default()
+            },
+        );
+        let secs = |secs| Some(Duration::from_secs(secs));
+
+        // The first request must arrive before the read timeout
+        assert!(requests.read_timeout().unwrap() <= secs(10));
+        requests.feed(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n").unwrap();
+        assert!(requests.read_timeout().unwrap() > secs(9));
+
+        // Waiting for the response doesn't count as being idle
+        requests.feed(b"ok").unwrap();
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(
+            requests.read_timeout().unwrap(), Some(Duration::from_millis(100)),
+        );
+
+        // After the response the keep-alive timeout starts
+        responses.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert!(requests.read_timeout().unwrap().is_some());
+        thread::sleep(Duration::from_millis(150));
+        match requests.read_timeout().unwrap_err().kind() {
+            ErrorKind::ConnectionIdle => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn test_rejection() {
+        let error: Error = ErrorKind::RequestTimedOut.into();
+        let response = String::from_utf8(rejection(&error).unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"status\":\"request_timeout\"}"));
+
+        let error: Error = ErrorKind::RequestMalformed.into();
+        assert!(rejection(&error).is_none());
+    }
+}
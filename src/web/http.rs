@@ -13,23 +13,42 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::{Shutdown, SocketAddr, TcpStream};
-use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
-
+use std::cmp;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use futures::{future, Future, Stream};
+use futures_cpupool::CpuPool;
+use hyper::header::{
+    ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE, ORIGIN, SERVER,
+};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{self, Body, Method};
 use regex::{self, Regex};
-use tiny_http::{self, Method};
+use rustls::ServerConfig;
+use tokio;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
 
 use common::prelude::*;
 use requests::Request;
+use web::access_log::{AccessEntry, AccessLog};
+use web::cors::Cors;
+use web::requests::{BodyLimits, WebRequest};
 use web::responses::Response;
 use web::proxies::ProxySupport;
+use web::frontend::{ConnectionLimits, Frontend};
+
+
+/// How many requests a single HTTP/2 connection can send at the same time.
+const MAX_CONCURRENT_STREAMS: u32 = 128;
 
 
 pub type RequestHandler<App> = Box<fn(&App, &Request, Vec<String>) -> Response>;
+pub type LimitsHandler<App> = Box<fn(&App, &[String]) -> BodyLimits>;
 
 
 struct Route {
@@ -93,6 +112,7 @@ impl Route {
 
 struct Handler<App: Send + Sync + 'static> {
     handler: RequestHandler<App>,
+    limits: Option<LimitsHandler<App>>,
     route: Route,
 }
 
@@ -100,6 +120,7 @@ impl<App: Send + Sync + 'static> Handler<App> {
     fn new(handler: RequestHandler<App>, route: Route) -> Self {
         Handler {
             handler: handler,
+            limits: None,
             route: route,
         }
     }
@@ -108,37 +129,251 @@ impl<App: Send + Sync + 'static> Handler<App> {
         self.route.matches(method, url)
     }
 
+    fn limits(&self, app: &App, args: &[String]) -> BodyLimits {
+        match self.limits {
+            Some(ref limits) => limits(app, args),
+            None => BodyLimits::default(),
+        }
+    }
+
     fn call(&self, app: &App, req: &Request, args: Vec<String>) -> Response {
         (self.handler)(app, req, args)
     }
 }
 
 
+/// A request routed to one of the handlers.
+struct MatchedRoute {
+    handler: usize,
+    args: Vec<String>,
+    request: Result<WebRequest>,
+}
+
+
+/// A request whose body was fully received, ready to be handled.
+struct ReceivedRequest {
+    start: Instant,
+    entry: AccessEntry,
+    origin: Option<String>,
+    preflight: bool,
+    route: Option<MatchedRoute>,
+}
+
+
+/// Handles the requests received by the HTTP server. The bodies of the
+/// requests are received without blocking, and the handlers are then run in
+/// a pool of threads since they might block.
+struct Dispatcher<App: Send + Sync + 'static> {
+    app: Arc<App>,
+    handlers: Arc<RwLock<Vec<Handler<App>>>>,
+    proxy_support: Arc<ProxySupport>,
+    max_body_size: Option<u64>,
+    cors: Option<Arc<Cors>>,
+    access_log: Option<Arc<AccessLog>>,
+    workers: CpuPool,
+}
+
+impl<App: Send + Sync + 'static> Dispatcher<App> {
+    /// Route the request and receive its body, only if it's within the
+    /// limits of the route.
+    fn receive(
+        &self, request: hyper::Request<Body>, peer: SocketAddr,
+    ) -> Box<Future<Item = ReceivedRequest, Error = io::Error> + Send> {
+        let mut received = ReceivedRequest {
+            start: Instant::now(),
+            entry: AccessEntry::new(
+                peer.ip(), request.method().as_str(), request.uri().path(),
+                &format!("{:?}", request.version()),
+            ),
+            origin: request
+                .headers()
+                .get(ORIGIN)
+                .and_then(|origin| origin.to_str().ok())
+                .map(|origin| origin.to_string()),
+            preflight: request.method() == Method::OPTIONS
+                && request
+                    .headers()
+                    .contains_key(ACCESS_CONTROL_REQUEST_METHOD),
+            route: None,
+        };
+
+        let method = request.method().clone();
+        let url = request
+            .uri()
+            .path_and_query()
+            .map(|url| url.as_str().to_string())
+            .unwrap_or_else(|| "/".into());
+
+        let handlers = self.handlers.read().unwrap();
+        for (index, handler) in handlers.iter().enumerate() {
+            if let Some(args) = handler.matches(&method, &url) {
+                let mut limits = handler.limits(&self.app, &args);
+                if let Some(max) = self.max_body_size {
+                    limits.cap_size(max);
+                }
+
+                let read = WebRequest::read(request, peer.ip(), &limits);
+                return Box::new(read.then(move |request| {
+                    received.route = Some(MatchedRoute {
+                        handler: index,
+                        args,
+                        request,
+                    });
+                    Ok(received)
+                }));
+            }
+        }
+
+        Box::new(future::ok(received))
+    }
+
+    fn handle(&self, received: ReceivedRequest) -> hyper::Response<Body> {
+        let ReceivedRequest {
+            start, mut entry, origin, preflight, route,
+        } = received;
+
+        let response = self.respond(origin, preflight, route, &mut entry);
+
+        if let Some(ref access_log) = self.access_log {
+            entry.status = response.status().as_u16();
+            entry.latency = start.elapsed();
+            access_log.record(&entry);
+        }
+        response
+    }
+
+    fn respond(
+        &self, origin: Option<String>, preflight: bool,
+        route: Option<MatchedRoute>, entry: &mut AccessEntry,
+    ) -> hyper::Response<Body> {
+        let mut builder = hyper::Response::builder();
+        builder.header(
+            SERVER, format!("Fisher/{}", env!("CARGO_PKG_VERSION")),
+        );
+
+        if let (Some(cors), Some(origin)) = (&self.cors, &origin) {
+            // Preflight requests from allowed origins don't reach any route
+            if preflight {
+                if let Some(headers) = cors.preflight(origin) {
+                    for (name, value) in headers {
+                        builder.header(name, value);
+                    }
+                    return builder.status(204).body(Body::empty()).unwrap();
+                }
+            }
+
+            for (name, value) in cors.headers(origin) {
+                builder.header(name, value);
+            }
+        }
+
+        let response = self.process(route, entry);
+        builder
+            .status(response.status())
+            .header(CONTENT_TYPE, response.content_type());
+
+        // Add custom headers from the response
+        if let Some(headers) = response.headers() {
+            for header in &headers {
+                let mut parts = header.splitn(2, ':');
+                if let (Some(name), Some(value)) = (parts.next(), parts.next())
+                {
+                    builder.header(name.trim(), value.trim());
+                }
+            }
+        }
+
+        // Streamed bodies are sent as they're produced
+        let response = match response {
+            Response::Stream(body) | Response::Waiting(_, body) => {
+                return builder.body(body).unwrap();
+            }
+            Response::Download(content) => {
+                entry.size = content.len();
+                return builder.body(Body::from(content)).unwrap();
+            }
+            other => other,
+        };
+
+        let body = response.body();
+        entry.size = body.len();
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    fn process(
+        &self, route: Option<MatchedRoute>, entry: &mut AccessEntry,
+    ) -> Response {
+        let route = if let Some(route) = route {
+            route
+        } else {
+            return Response::NotFound;
+        };
+
+        let mut req = match route.request {
+            Ok(mut web) => {
+                web.path_args = route.args.clone();
+                Request::Web(web)
+            }
+            Err(e) => return error_response(e),
+        };
+        if let Err(e) = self.proxy_support.fix_request(&mut req) {
+            return Response::BadRequest(e);
+        }
+
+        let handlers = self.handlers.read().unwrap();
+        let response = handlers[route.handler].call(
+            &self.app, &req, route.args,
+        );
+        if let Ok(web) = req.web() {
+            entry.client = web.source;
+            entry.hook = web.access.get();
+        }
+        response
+    }
+}
+
+
 pub struct HttpServer<App: Send + Sync + 'static> {
     app: Arc<App>,
-    handlers: Arc<Mutex<Vec<Handler<App>>>>,
+    handlers: Arc<RwLock<Vec<Handler<App>>>>,
     proxy_support: Arc<ProxySupport>,
+    max_body_size: Option<u64>,
+    limits: Option<ConnectionLimits>,
+    cors: Option<Arc<Cors>>,
+    access_log: Option<Arc<AccessLog>>,
+    workers: usize,
 
-    should_stop: Arc<AtomicBool>,
+    tls_config: Option<Arc<ServerConfig>>,
+    frontend: Option<Arc<Frontend>>,
 
-    listening_to: Option<SocketAddr>,
-    stop_wait: Option<mpsc::Receiver<()>>,
+    runtime: Option<Runtime>,
 }
 
 impl<App: Send + Sync + 'static> HttpServer<App> {
-    pub fn new(app: App, proxies_count: u8) -> Self {
+    pub fn new(app: App, proxy_support: ProxySupport) -> Self {
         HttpServer {
             app: Arc::new(app),
-            handlers: Arc::new(Mutex::new(Vec::new())),
-            proxy_support: Arc::new(ProxySupport::new(proxies_count)),
-
-            should_stop: Arc::new(AtomicBool::new(false)),
-
-            listening_to: None,
-            stop_wait: None,
+            handlers: Arc::new(RwLock::new(Vec::new())),
+            proxy_support: Arc::new(proxy_support),
+            max_body_size: None,
+            limits: None,
+            cors: None,
+            access_log: None,
+            workers: 1,
+
+            tls_config: None,
+            frontend: None,
+
+            runtime: None,
         }
     }
 
+    /// Get the application handling the requests, to share it with other
+    /// parts of Fisher.
+    pub fn app(&self) -> Arc<App> {
+        self.app.clone()
+    }
+
     pub fn add_route(
         &mut self,
         method: Method,
@@ -147,126 +382,147 @@ impl<App: Send + Sync + 'static> HttpServer<App> {
     ) {
         let route = Route::new(method, url);
         self.handlers
-            .try_lock()
+            .try_write()
             .unwrap()
             .push(Handler::new(handler, route));
     }
 
-    pub fn listen(&mut self, bind: SocketAddr) -> Result<SocketAddr> {
-        macro_rules! header {
-            ($value:expr) => {
-                $value.parse::<tiny_http::Header>().unwrap()
-            };
-        }
-
-        // This will move to the thread, and the server will be stopped when
-        // the thread exits
-        let server = tiny_http::Server::http(bind)?;
-
-        // Store the server address into the struct
-        self.listening_to = Some(server.server_addr());
+    /// Add a route whose requests are checked against the limits returned
+    /// by the `limits` function before their body is read.
+    pub fn add_route_with_limits(
+        &mut self,
+        method: Method,
+        url: &str,
+        handler: RequestHandler<App>,
+        limits: LimitsHandler<App>,
+    ) {
+        let mut handler = Handler::new(handler, Route::new(method, url));
+        handler.limits = Some(limits);
+        self.handlers.try_write().unwrap().push(handler);
+    }
 
-        let (stop_send, stop_recv) = mpsc::channel();
-        self.stop_wait = Some(stop_recv);
+    /// Reject the requests of every route with a body larger than `max`
+    /// bytes, in addition to the limits of the route.
+    pub fn set_max_body_size(&mut self, max: u64) {
+        self.max_body_size = Some(max);
+    }
 
-        let app = self.app.clone();
-        let handlers_arc = self.handlers.clone();
-        let proxy_support = self.proxy_support.clone();
-        let should_stop = self.should_stop.clone();
-        thread::spawn(move || {
-            // Get a reference to the handlers
-            let handlers = &*handlers_arc.lock().unwrap();
+    /// Apply the provided limits to the connections of the clients.
+    pub fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = Some(limits);
+    }
 
-            // Prepare some headers which will be sent everytime
-            let server_header = header!(
-                format!("Server: Fisher/{}", env!("CARGO_PKG_VERSION"))
-            );
-            let content_type = header!("Content-Type: application/json");
+    /// Allow web pages served by other origins to call the routes from the
+    /// browser, according to the provided policy.
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.cors = Some(Arc::new(cors));
+    }
 
-            let ignored_method =
-                Method::NonStandard("X_FISHER_IGNORE_THIS".parse().unwrap());
+    /// Log every request received by the server to the access log.
+    pub fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = Some(Arc::new(access_log));
+    }
 
-            for mut request in server.incoming_requests() {
-                // Don't accept any request anymore
-                if should_stop.load(Ordering::Relaxed) {
-                    break;
-                }
+    /// Handle the requests with the provided number of threads, instead of
+    /// only one. The connections themselves don't need a thread each.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = cmp::max(workers, 1);
+    }
 
-                // Convert the request to a Fisher request
-                let mut req = Request::Web((&mut request).into());
-
-                let response = (|| {
-                    if *request.method() == ignored_method {
-                        // This request comes with the non-standard method used
-                        // to shut the server down -- no client should be using
-                        // it
-                        Response::Forbidden
-                    } else if let Err(e) = proxy_support.fix_request(&mut req) {
-                        Response::BadRequest(e)
-                    } else {
-                        let method = request.method();
-                        let url = request.url();
+    /// Serve HTTPS instead of plain HTTP, with the provided configuration.
+    /// Clients supporting HTTP/2 can negotiate it during the handshake.
+    pub fn enable_tls(&mut self, config: ServerConfig) {
+        self.tls_config = Some(negotiate_http2(config));
+    }
 
-                        for handler in handlers {
-                            if let Some(args) = handler.matches(method, url) {
-                                return handler.call(&app, &req, args);
-                            }
-                        }
+    /// Replace the TLS configuration without restarting the server. The
+    /// connections already open are not affected.
+    pub fn reload_tls(&mut self, config: ServerConfig) -> Result<()> {
+        let config = negotiate_http2(config);
+        if let Some(ref frontend) = self.frontend {
+            frontend.reload_tls(config.clone())?;
+        }
+        self.tls_config = Some(config);
 
-                        Response::NotFound
-                    }
-                })();
+        Ok(())
+    }
 
-                let mut tiny_response =
-                    tiny_http::Response::from_data(
-                        response.json().into_bytes(),
-                    ).with_status_code(response.status());
+    pub fn listen(&mut self, bind: SocketAddr) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(&bind)?;
+        let addr = listener.local_addr()?;
+
+        let frontend = Arc::new(Frontend::new(
+            self.tls_config.clone(), self.limits.unwrap_or_default(),
+        ));
+        self.frontend = Some(frontend.clone());
+
+        let dispatcher = Arc::new(Dispatcher {
+            app: self.app.clone(),
+            handlers: self.handlers.clone(),
+            proxy_support: self.proxy_support.clone(),
+            max_body_size: self.max_body_size,
+            cors: self.cors.clone(),
+            access_log: self.access_log.clone(),
+            workers: CpuPool::new(self.workers),
+        });
 
-                // Add custom headers from the response
-                if let Some(headers) = response.headers() {
-                    for header in &headers {
-                        tiny_response.add_header(header!(header));
+        let mut http = Http::new();
+        http.http2_max_concurrent_streams(MAX_CONCURRENT_STREAMS);
+        let server = listener
+            .incoming()
+            // Errors here only affect the connection being accepted
+            .then(|client| Ok::<_, ()>(client.ok()))
+            .filter_map(|client| client)
+            .for_each(move |client| {
+                let dispatcher = dispatcher.clone();
+                let http = http.clone();
+                let connection = frontend.accept(client).and_then(move |conn| {
+                    let mut http = http.clone();
+                    if conn.is_http2() {
+                        http.http2_only(true);
+                    } else {
+                        http.http1_only(true);
                     }
-                }
-
-                tiny_response.add_header(server_header.clone());
-                tiny_response.add_header(content_type.clone());
-
-                let _ = request.respond(tiny_response);
-            }
-
-            stop_send.send(()).unwrap();
-        });
 
-        Ok(self.listening_to.unwrap())
+                    let peer = conn.peer();
+                    let start_request = conn.request_counter();
+                    let service = service_fn(move |request| {
+                        let dispatcher = dispatcher.clone();
+                        let active = start_request();
+
+                        // Only requests already received reach the workers,
+                        // so slow clients can't keep all of them busy
+                        let received = dispatcher.receive(request, peer);
+                        received.and_then(move |received| {
+                            let workers = dispatcher.workers.clone();
+                            workers.spawn_fn(move || {
+                                let response = dispatcher.handle(received);
+                                drop(active);
+                                Ok::<_, io::Error>(response)
+                            })
+                        })
+                    });
+
+                    // Errors here are caused by misbehaving clients
+                    http.serve_connection(conn, service).map_err(|_| ())
+                });
+                tokio::spawn(connection);
+
+                Ok(())
+            });
+
+        let mut runtime = Runtime::new()?;
+        runtime.spawn(server);
+        self.runtime = Some(runtime);
+
+        Ok(addr)
     }
 
     pub fn stop(&mut self) -> bool {
-        if self.stop_wait.is_some() {
-            // Tell the server to stop
-            self.should_stop.store(true, Ordering::Relaxed);
-
-            // Send an HTTP request to force stopping the server
-            match TcpStream::connect(self.listening_to.unwrap()) {
-                Ok(mut conn) => {
-                    (writeln!(conn, "X_FISHER_IGNORE_THIS / HTTP/1.0\r\n\r\n"))
-                        .unwrap();
-                    conn.shutdown(Shutdown::Both).unwrap();
-                }
-                Err(..) => {
-                    return false;
-                }
-            }
-
-            if let Some(ref stop_wait) = self.stop_wait {
-                // Wait for the http server to stop
-                stop_wait.recv().unwrap();
-            } else {
-                unreachable!();
-            }
-
-            self.stop_wait = None;
-            self.listening_to = None;
+        if let Some(runtime) = self.runtime.take() {
+            // Stop listening and close all the open connections
+            let _ = runtime.shutdown_now().wait();
+            self.frontend = None;
 
             true
         } else {
@@ -276,16 +532,41 @@ impl<App: Send + Sync + 'static> HttpServer<App> {
 }
 
 
+/// Allow clients to negotiate HTTP/2 with ALPN, falling back to HTTP/1.1.
+fn negotiate_http2(mut config: ServerConfig) -> Arc<ServerConfig> {
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Arc::new(config)
+}
+
+
+fn error_response(error: Error) -> Response {
+    match *error.kind() {
+        ErrorKind::RequestBodyTooLarge(max) => Response::PayloadTooLarge(max),
+        ErrorKind::RequestContentTypeNotAllowed(..)
+        | ErrorKind::RequestContentEncodingNotSupported(..) => {
+            Response::UnsupportedMediaType
+        }
+        _ => Response::BadRequest(error),
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
     use std::time::Duration;
 
-    use tiny_http::Method;
-    use hyper;
-    use hyper::status::StatusCode;
+    use hyper::Method;
+    use hyper010 as hyper;
+    use hyper010::status::StatusCode;
+    use rustls::{self, ClientConfig, ClientSession, Session, StreamOwned};
+    use webpki::DNSNameRef;
 
     use requests::Request;
     use web::responses::Response;
+    use web::proxies::ProxySupport;
     use utils::testing::*;
     use super::{Handler, HttpServer, Route};
 
@@ -305,7 +586,7 @@ mod tests {
     }
 
     fn dummy_handler() -> Handler<DummyData> {
-        let route = Route::new(Method::Get, "/?");
+        let route = Route::new(Method::GET, "/?");
         Handler::new(Box::new(dummy_handler_fn), route)
     }
 
@@ -329,31 +610,31 @@ mod tests {
     #[test]
     fn test_route_matches() {
         // Test a request with no captures
-        let basic = Route::new(Method::Get, "/url");
-        assert_eq!(basic.matches(&Method::Get, "/url"), Some(vec![]));
+        let basic = Route::new(Method::GET, "/url");
+        assert_eq!(basic.matches(&Method::GET, "/url"), Some(vec![]));
         assert_eq!(
-            basic.matches(&Method::Get, "/url?test"),
+            basic.matches(&Method::GET, "/url?test"),
             Some(vec!["?test".into()])
         );
-        assert_eq!(basic.matches(&Method::Post, "/url"), None);
-        assert_eq!(basic.matches(&Method::Get, "/wrong"), None);
+        assert_eq!(basic.matches(&Method::POST, "/url"), None);
+        assert_eq!(basic.matches(&Method::GET, "/wrong"), None);
 
         // Test a request with some captures
-        let capt = Route::new(Method::Post, "/?/t/?");
+        let capt = Route::new(Method::POST, "/?/t/?");
         assert_eq!(
-            capt.matches(&Method::Post, "/a/t/b"),
+            capt.matches(&Method::POST, "/a/t/b"),
             Some(vec!["a".into(), "b".into()])
         );
         assert_eq!(
-            capt.matches(&Method::Post, "/a/t/b?hey"),
+            capt.matches(&Method::POST, "/a/t/b?hey"),
             Some(vec!["a".into(), "b".into(), "?hey".into()])
         );
         assert_eq!(
-            capt.matches(&Method::Post, "/a/t/b.txt"),
+            capt.matches(&Method::POST, "/a/t/b.txt"),
             Some(vec!["a".into(), "b.txt".into()])
         );
-        assert_eq!(basic.matches(&Method::Post, "/a/t/"), None);
-        assert_eq!(basic.matches(&Method::Get, "/a/t/b"), None);
+        assert_eq!(basic.matches(&Method::POST, "/a/t/"), None);
+        assert_eq!(basic.matches(&Method::GET, "/a/t/b"), None);
     }
 
 
@@ -362,7 +643,7 @@ mod tests {
         let handler = dummy_handler();
 
         assert_eq!(
-            handler.matches(&Method::Get, "/test"),
+            handler.matches(&Method::GET, "/test"),
             Some(vec!["test".into()])
         );
         assert_eq!(
@@ -387,8 +668,10 @@ mod tests {
         }
 
         // Create the server instance
-        let mut server = HttpServer::new(DummyData(vec!["test".into()]), 0);
-        server.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]), ProxySupport::new(0),
+        );
+        server.add_route(Method::GET, "/?", Box::new(dummy_handler_fn));
 
         // Start the server
         let addr = server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
@@ -415,4 +698,172 @@ mod tests {
                 .is_err()
         );
     }
+
+
+    #[test]
+    fn test_slow_clients() {
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]), ProxySupport::new(0),
+        );
+        server.add_route(Method::GET, "/?", Box::new(dummy_handler_fn));
+        server.add_route(Method::POST, "/?", Box::new(dummy_handler_fn));
+        let addr = server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        // A client sending only part of the body of its request
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.set_read_timeout(Some(Duration::new(5, 0))).unwrap();
+        write!(
+            slow,
+            "POST /test HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Length: 10\r\nConnection: close\r\n\r\nabc"
+        ).unwrap();
+
+        // The only worker must still be able to handle other requests
+        let mut client = hyper::Client::new();
+        client.set_read_timeout(Some(Duration::new(1, 0)));
+        let res = client.get(&format!("http://{}/test", addr)).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        // The slow request is handled once its body is complete
+        slow.write_all(b"defghij").unwrap();
+        let mut response = String::new();
+        slow.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        server.stop();
+    }
+
+
+    #[test]
+    fn test_tls_server() {
+        fn request<W: Write>(stream: &mut W) {
+            // Failed handshakes are detected by checking the response
+            let _ = write!(
+                stream,
+                "GET /source HTTP/1.1\r\nHost: localhost\r\n\
+                 Connection: close\r\n\r\n"
+            );
+        }
+
+        fn source_handler_fn(
+            _data: &DummyData,
+            req: &Request,
+            _args: Vec<String>,
+        ) -> Response {
+            // The address of the client must be the real one
+            if req.web().unwrap().source.is_loopback() {
+                Response::Ok
+            } else {
+                Response::Forbidden
+            }
+        }
+
+        let tls_config = || {
+            let mut config = rustls::ServerConfig::new(
+                rustls::NoClientAuth::new()
+            );
+            config.set_single_cert(
+                rustls::internal::pemfile::certs(
+                    &mut TLS_CERT.as_bytes()
+                ).unwrap(),
+                rustls::internal::pemfile::pkcs8_private_keys(
+                    &mut TLS_KEY.as_bytes()
+                ).unwrap().remove(0),
+            ).unwrap();
+            config
+        };
+
+        let mut server = HttpServer::new(
+            DummyData(vec![]), ProxySupport::new(0),
+        );
+        server.add_route(Method::GET, "/source", Box::new(source_handler_fn));
+        server.enable_tls(tls_config());
+        let addr = server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut client_config = ClientConfig::new();
+        client_config.root_store.add_pem_file(
+            &mut TLS_CA.as_bytes()
+        ).unwrap();
+        let client_config = Arc::new(client_config);
+
+        let tls_request = || {
+            let session = ClientSession::new(
+                &client_config,
+                DNSNameRef::try_from_ascii_str("localhost").unwrap(),
+            );
+            let mut tls = StreamOwned::new(
+                session, TcpStream::connect(addr).unwrap(),
+            );
+            request(&mut tls);
+            let mut response = String::new();
+            let _ = tls.read_to_string(&mut response);
+            response
+        };
+
+        // Make a request over TLS
+        let response = tls_request();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // New connections use the reloaded configuration, which in this
+        // case doesn't have any certificate
+        server.reload_tls(
+            rustls::ServerConfig::new(rustls::NoClientAuth::new())
+        ).unwrap();
+        let response = tls_request();
+        assert!(!response.starts_with("HTTP/1.1"), "{}", response);
+
+        server.reload_tls(tls_config()).unwrap();
+        let response = tls_request();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // Clients supporting HTTP/2 negotiate it
+        let mut h2_config = ClientConfig::new();
+        h2_config.root_store.add_pem_file(&mut TLS_CA.as_bytes()).unwrap();
+        h2_config.set_protocols(&[b"h2".to_vec()]);
+        let session = ClientSession::new(
+            &Arc::new(h2_config),
+            DNSNameRef::try_from_ascii_str("localhost").unwrap(),
+        );
+        let mut h2 = StreamOwned::new(
+            session, TcpStream::connect(addr).unwrap(),
+        );
+        h2.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+        // An empty SETTINGS frame
+        h2.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+        // A HEADERS frame on the stream 1, with GET https://localhost/source
+        let mut headers = vec![0x82, 0x87, 0x44, 7];
+        headers.extend_from_slice(b"/source");
+        headers.extend_from_slice(&[0x41, 9]);
+        headers.extend_from_slice(b"localhost");
+        h2.write_all(&[0, 0, headers.len() as u8, 1, 5, 0, 0, 0, 1]).unwrap();
+        h2.write_all(&headers).unwrap();
+        assert_eq!(h2.sess.get_alpn_protocol(), Some(&b"h2"[..]));
+
+        // Wait for the response headers, which must contain the 200 status
+        loop {
+            let mut frame = [0; 9];
+            h2.read_exact(&mut frame).unwrap();
+            let length = (frame[0] as usize) << 16
+                | (frame[1] as usize) << 8
+                | frame[2] as usize;
+            let mut payload = vec![0; length];
+            h2.read_exact(&mut payload).unwrap();
+
+            if frame[3] == 1 && frame[8] == 1 {
+                // The indexed representation of ":status: 200"
+                assert_eq!(payload[0], 0x88);
+                break;
+            }
+        }
+
+        // Plain HTTP requests are not accepted
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        request(&mut plain);
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1"));
+
+        server.stop();
+    }
 }
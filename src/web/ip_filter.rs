@@ -0,0 +1,98 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Restrict the clients allowed to call the hooks by their IP address.
+
+use std::net::IpAddr;
+
+use utils::IpCidr;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<IpCidr>,
+    deny: Vec<IpCidr>,
+}
+
+impl IpFilter {
+    /// Create a new filter. If no allowed ranges are provided every address
+    /// not explicitly denied is allowed.
+    pub fn new(allow: Vec<IpCidr>, deny: Vec<IpCidr>) -> Self {
+        IpFilter { allow, deny }
+    }
+
+    /// Check if a client is allowed, with denied ranges taking precedence.
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty()
+            || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use utils::IpCidr;
+
+    use super::IpFilter;
+
+
+    fn ranges(input: &[&str]) -> Vec<IpCidr> {
+        input.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn ip(input: &str) -> IpAddr {
+        input.parse().unwrap()
+    }
+
+
+    #[test]
+    fn test_empty_filter() {
+        let filter = IpFilter::default();
+        assert!(filter.allows(&ip("127.0.0.1")));
+        assert!(filter.allows(&ip("::1")));
+    }
+
+    #[test]
+    fn test_allow() {
+        let filter = IpFilter::new(
+            ranges(&["10.0.0.0/8", "2001:db8::/32"]), Vec::new(),
+        );
+        assert!(filter.allows(&ip("10.1.2.3")));
+        assert!(filter.allows(&ip("2001:db8::1")));
+        assert!(!filter.allows(&ip("192.168.1.1")));
+        assert!(!filter.allows(&ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn test_deny() {
+        let filter = IpFilter::new(
+            ranges(&["10.0.0.0/8"]), ranges(&["10.0.0.0/24", "192.168.1.1"]),
+        );
+        assert!(filter.allows(&ip("10.1.0.1")));
+        assert!(!filter.allows(&ip("10.0.0.1")));
+        assert!(!filter.allows(&ip("192.168.1.1")));
+
+        // Without allowed ranges only the denied ones are rejected
+        let filter = IpFilter::new(Vec::new(), ranges(&["10.0.0.0/8"]));
+        assert!(!filter.allows(&ip("10.0.0.1")));
+        assert!(filter.allows(&ip("192.168.1.1")));
+    }
+}
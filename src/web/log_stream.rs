@@ -0,0 +1,101 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Streaming of the output of the running jobs to the HTTP clients.
+//!
+//! The output is sent with chunked encoding as soon as the script writes
+//! it, by a thread polling the live output of the job. The response ends
+//! when the job ends, or when the client goes away.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Sink};
+use hyper::{Body, Chunk};
+
+use scripts::LiveOutput;
+
+
+/// How often the output of the job is checked for new content.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+
+/// Get a body streaming the output of the job, starting from what was
+/// captured so far.
+pub fn follow(output: Arc<Mutex<LiveOutput>>) -> Body {
+    let (mut sender, body) = Body::channel();
+
+    thread::spawn(move || {
+        let mut offset = 0;
+        loop {
+            // Checking if the job ended while reading ensures all of its
+            // output is sent before the response ends
+            let (content, next, finished) = {
+                let output = output.lock().unwrap();
+                let (content, next) = output.read_from(offset);
+                (content, next, output.is_finished())
+            };
+            offset = next;
+
+            if !content.is_empty() {
+                sender = match sender.send(Chunk::from(content)).wait() {
+                    Ok(sender) => sender,
+                    // The client closed the connection
+                    Err(..) => return,
+                };
+            }
+
+            if finished {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+
+    body
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+
+    use scripts::LiveOutput;
+
+    use super::follow;
+
+
+    #[test]
+    fn test_follow() {
+        let output = Arc::new(Mutex::new(LiveOutput::new()));
+        output.lock().unwrap().append(b"hello ");
+
+        let body = follow(output.clone());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            output.lock().unwrap().append(b"world\n");
+            output.lock().unwrap().finish();
+        });
+
+        // The body ends when the job ends
+        let content = body.concat2().wait().unwrap();
+        assert_eq!(&content[..], b"hello world\n");
+    }
+}
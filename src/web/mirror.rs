@@ -0,0 +1,198 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mirroring of the received deliveries to a standby instance.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use base64;
+use reqwest;
+use url::Url;
+
+use common::prelude::*;
+use utils::Headers;
+use web::{AccessDetails, JsonBody, WebRequest};
+
+
+/// A delivery sent to the standby instance, as the body of a request to the
+/// `/admin/mirror/<hook>` endpoint. It's also how the stored payloads are
+/// persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirroredDelivery {
+    pub source: IpAddr,
+    #[serde(default)]
+    pub headers: Headers,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    /// The body encoded in base64, if it's not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+impl MirroredDelivery {
+    /// Convert a request, unless its body is spooled to disk: spooled bodies
+    /// are too big to be sent around.
+    pub fn from_request(req: &WebRequest) -> Option<Self> {
+        if req.spooled.is_some() {
+            return None;
+        }
+
+        // Binary bodies can't be sent as JSON strings
+        let (body, body_base64) = match str::from_utf8(&req.body) {
+            Ok(text) => (text.to_string(), None),
+            Err(..) => (String::new(), Some(base64::encode(&req.body))),
+        };
+
+        Some(MirroredDelivery {
+            source: req.source,
+            headers: req.headers.clone(),
+            params: req.params.clone(),
+            body,
+            body_base64,
+        })
+    }
+
+    pub fn into_request(self) -> Result<WebRequest> {
+        let body = if let Some(encoded) = self.body_base64 {
+            base64::decode(&encoded)
+                .chain_err(|| ErrorKind::MirroredBodyInvalid)?
+        } else {
+            self.body.into_bytes()
+        };
+
+        Ok(WebRequest {
+            source: self.source,
+            headers: self.headers,
+            params: self.params,
+            path_args: Vec::new(),
+            body,
+            spooled: None,
+            json_body: JsonBody::default(),
+            access: AccessDetails::default(),
+        })
+    }
+}
+
+
+fn send_delivery(
+    client: &reqwest::Client,
+    url: &Url,
+    token: &str,
+    delivery: &MirroredDelivery,
+) -> Result<()> {
+    let response = client
+        .post(url.clone())
+        .header("Authorization", format!("Bearer {}", token))
+        .json(delivery)
+        .send()?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::MirrorBadStatus(response.status().as_u16()).into())
+    }
+}
+
+
+/// This struct sends the mirrored deliveries to the standby instance in a
+/// background thread, which exits when the struct is dropped.
+#[derive(Debug)]
+pub struct Mirror {
+    sender: Mutex<mpsc::Sender<(String, MirroredDelivery)>>,
+}
+
+impl Mirror {
+    pub fn new(standby_url: &str, token: &str) -> Result<Self> {
+        let base = Url::parse(standby_url)
+            .chain_err(|| ErrorKind::MirrorInvalidUrl(standby_url.into()))?;
+        let token = token.to_string();
+
+        let (sender, receiver) = mpsc::channel::<(String, MirroredDelivery)>();
+        thread::spawn(move || {
+            let client = reqwest::Client::new();
+
+            for (hook, delivery) in receiver.iter() {
+                let result = base
+                    .join(&format!("admin/mirror/{}", hook))
+                    .map_err(Error::from)
+                    .and_then(|url| {
+                        send_delivery(&client, &url, &token, &delivery)
+                    });
+
+                // Mirroring is best effort, the delivery is not retried
+                if let Err(err) = result.chain_err(|| {
+                    ErrorKind::MirrorFailed(hook.clone())
+                }) {
+                    err.pretty_print();
+                }
+            }
+        });
+
+        Ok(Mirror {
+            sender: Mutex::new(sender),
+        })
+    }
+
+    /// Queue a delivery of the hook to be sent to the standby instance.
+    pub fn send(&self, hook: &str, req: &WebRequest) {
+        let delivery = match MirroredDelivery::from_request(req) {
+            Some(delivery) => delivery,
+            None => {
+                println!(
+                    "Not mirroring a spooled delivery of the '{}' hook", hook,
+                );
+                return;
+            }
+        };
+
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send((hook.to_string(), delivery));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::MirroredDelivery;
+
+
+    #[test]
+    fn test_binary_bodies() {
+        let delivery: MirroredDelivery = ::serde_json::from_str(
+            r#"{"source": "127.0.0.1", "body_base64": "H4v/AA=="}"#,
+        ).unwrap();
+        assert_eq!(
+            delivery.into_request().unwrap().body,
+            vec![0x1f, 0x8b, 0xff, 0x00]
+        );
+
+        let delivery: MirroredDelivery = ::serde_json::from_str(
+            r#"{"source": "127.0.0.1", "body_base64": "not base64!"}"#,
+        ).unwrap();
+        assert!(delivery.into_request().is_err());
+
+        let delivery: MirroredDelivery = ::serde_json::from_str(
+            r#"{"source": "127.0.0.1", "body": "text"}"#,
+        ).unwrap();
+        assert_eq!(delivery.into_request().unwrap().body, b"text".to_vec());
+    }
+}
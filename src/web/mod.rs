@@ -15,14 +15,37 @@
 
 mod http;
 mod app;
+mod access_log;
+mod archive;
+mod approvals;
+mod artifacts;
+mod cors;
+mod dashboard;
 mod rate_limits;
+mod deliveries;
+mod freezes;
+mod ip_filter;
+mod log_stream;
+mod frontend;
 mod requests;
 mod responses;
 mod proxies;
+mod mirror;
+mod paths;
+mod payloads;
+mod tls;
 
 // Parts of the webapp
 mod api;
 
+pub use self::access_log::AccessDetails;
 pub use self::http::HttpServer;
 pub use self::app::WebApp;
-pub use self::requests::WebRequest;
+pub use self::approvals::Approvals;
+pub use self::freezes::Freezes;
+pub use self::paths::check_custom_path;
+pub use self::requests::{BodyLimits, JsonBody, WebRequest};
+pub use self::tls::SharedCertificate;
+
+#[cfg(test)]
+pub use self::requests::SpooledBody;
@@ -0,0 +1,175 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Custom paths the hooks are reachable at, in addition to `/hook/<name>`.
+//!
+//! Scripts can list their own paths in the `paths` preference, and the
+//! configuration can map more paths to them. Scripts relying on the secrecy
+//! of their URL can also ask for a secret path, derived from their name and
+//! a key: it's unguessable, but it doesn't change when Fisher restarts.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand;
+use regex::Regex;
+
+use common::prelude::*;
+use scripts::{Repository, Script};
+use utils::{self, HmacAlgorithm};
+
+
+/// The first segments of the paths used by Fisher itself.
+static RESERVED: &[&str] = &[
+    "admin", "approvals", "dashboard", "h", "health", "hook", "hooks", "jobs",
+    "metrics", "processor", "queue", "reload", "v1",
+];
+
+/// Length of the secret part of the secret paths, in hex characters.
+const SECRET_LENGTH: usize = 32;
+
+
+lazy_static! {
+    static ref PATH_RE: Regex = Regex::new(r"^(/[a-zA-Z0-9\._-]+)+$").unwrap();
+}
+
+
+/// Ensure a path can be used as a custom path of a hook.
+pub fn check_custom_path(path: &str) -> Result<()> {
+    let first = path.split('/').nth(1).unwrap_or("");
+    if !PATH_RE.is_match(path) || RESERVED.contains(&first) {
+        return Err(ErrorKind::InvalidCustomPath(path.into()).into());
+    }
+
+    Ok(())
+}
+
+
+#[derive(Debug)]
+pub struct HookPaths {
+    aliases: HashMap<String, String>,
+    key: Vec<u8>,
+}
+
+impl HookPaths {
+    /// Create a new instance with the aliases from the configuration. If no
+    /// key is provided a random one is generated, so the secret paths change
+    /// every time Fisher starts.
+    pub fn new(
+        aliases: HashMap<String, String>, key: Option<&str>,
+    ) -> Result<Self> {
+        for path in aliases.keys() {
+            check_custom_path(path)?;
+        }
+
+        Ok(HookPaths {
+            aliases,
+            key: match key {
+                Some(key) => key.as_bytes().to_vec(),
+                None => rand::random::<[u8; 32]>().to_vec(),
+            },
+        })
+    }
+
+    /// Get the secret path of a hook.
+    pub fn secret_path(&self, hook: &str) -> String {
+        let signature = HmacAlgorithm::Sha256.sign(&self.key, hook.as_bytes());
+        format!("/h/{}", &utils::to_hex(&signature)[..SECRET_LENGTH])
+    }
+
+    /// Get all the custom paths of a hook, sorted.
+    pub fn paths_of(&self, hook: &Script) -> Vec<String> {
+        let mut result = hook.paths().to_vec();
+        result.extend(
+            self.aliases
+                .iter()
+                .filter(|&(_, name)| name == hook.name())
+                .map(|(path, _)| path.clone()),
+        );
+        if hook.secret_path() {
+            result.push(self.secret_path(hook.name()));
+        }
+
+        result.sort();
+        result
+    }
+
+    /// Find the hook reachable at a custom path.
+    pub fn resolve(
+        &self, hooks: &Repository, path: &str,
+    ) -> Option<Arc<Script>> {
+        if let Some(name) = self.aliases.get(path) {
+            return hooks.get_by_name(name);
+        }
+
+        hooks.iter().find(|hook| {
+            hook.paths().iter().any(|p| p == path) || (
+                hook.secret_path()
+                && utils::secure_compare(self.secret_path(hook.name()), path)
+            )
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{check_custom_path, HookPaths};
+
+
+    #[test]
+    fn test_check_custom_path() {
+        for path in &["/deploy", "/deploy/prod", "/a_b/c-d/e.f"] {
+            assert!(check_custom_path(path).is_ok(), "{}", path);
+        }
+
+        for path in &[
+            "", "/", "deploy", "/deploy/", "//deploy", "/deploy?a=b",
+            "/hook/test.sh", "/admin", "/health", "/h/abcd", "/approvals/x",
+            "/v1/hook/test.sh", "/hooks", "/jobs/abcd", "/queue",
+            "/processor/pause", "/reload", "/metrics", "/dashboard",
+        ] {
+            assert!(check_custom_path(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_secret_path() {
+        let paths = HookPaths::new(HashMap::new(), Some("key")).unwrap();
+        let path = paths.secret_path("deploy.sh");
+        assert_eq!(path.len(), "/h/".len() + 32);
+
+        // The same key always results in the same paths
+        let same = HookPaths::new(HashMap::new(), Some("key")).unwrap();
+        assert_eq!(same.secret_path("deploy.sh"), path);
+        assert_ne!(same.secret_path("other.sh"), path);
+
+        let other = HookPaths::new(HashMap::new(), Some("other")).unwrap();
+        assert_ne!(other.secret_path("deploy.sh"), path);
+
+        // Random keys are generated if none is provided
+        let random = HookPaths::new(HashMap::new(), None).unwrap();
+        assert_ne!(random.secret_path("deploy.sh"), path);
+    }
+
+    #[test]
+    fn test_invalid_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("/admin/deploy".to_string(), "deploy.sh".to_string());
+        assert!(HookPaths::new(aliases, None).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage of the payloads recently accepted by the hooks.
+//!
+//! The last payloads of every hook are kept, so they can be replayed without
+//! asking the service sending them to deliver them again, for example to
+//! retry a failed deploy. The storage is bounded for every hook, and it can
+//! be persisted to a file to survive restarts. Spooled bodies are too big to
+//! be stored.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use serde_json;
+
+use common::prelude::*;
+use utils;
+use web::WebRequest;
+use web::mirror::MirroredDelivery;
+
+
+/// A payload accepted by a hook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPayload {
+    pub hook: String,
+    /// The ID of the delivery, provided by the provider or by Fisher.
+    pub delivery: String,
+    /// The jobs created when the payload was received.
+    pub job_ids: Vec<String>,
+    pub received_at: u64,
+    pub request: MirroredDelivery,
+}
+
+impl StoredPayload {
+    pub fn summary<'a>(&'a self) -> PayloadSummary<'a> {
+        PayloadSummary {
+            delivery: &self.delivery,
+            job_ids: &self.job_ids,
+            received_at: self.received_at,
+        }
+    }
+}
+
+
+/// The details of a stored payload returned by the API, without the payload
+/// itself.
+#[derive(Debug, Serialize)]
+pub struct PayloadSummary<'a> {
+    pub delivery: &'a str,
+    pub job_ids: &'a [String],
+    #[serde(serialize_with = "utils::serialize_timestamp")]
+    pub received_at: u64,
+}
+
+
+#[derive(Debug)]
+pub struct Payloads {
+    hooks: HashMap<String, VecDeque<StoredPayload>>,
+    capacity: usize,
+    path: Option<PathBuf>,
+}
+
+impl Payloads {
+    /// Create a new storage keeping `capacity` payloads for every hook,
+    /// loading the ones persisted by a previous instance.
+    pub fn new(capacity: usize, path: Option<PathBuf>) -> Result<Self> {
+        let mut payloads = Payloads {
+            hooks: HashMap::new(),
+            capacity,
+            path,
+        };
+
+        if let Some(ref path) = payloads.path.clone() {
+            if path.exists() {
+                let file = BufReader::new(fs::File::open(path)?);
+                for line in file.lines() {
+                    payloads.record(serde_json::from_str(&line?)?);
+                }
+            }
+        }
+
+        Ok(payloads)
+    }
+
+    /// Store a payload accepted by a hook. Nothing is stored if the body of
+    /// the request is spooled.
+    pub fn insert(
+        &mut self, hook: &str, delivery: &str, job_ids: Vec<String>,
+        req: &WebRequest, now: u64,
+    ) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        let request = match MirroredDelivery::from_request(req) {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        self.record(StoredPayload {
+            hook: hook.into(),
+            delivery: delivery.into(),
+            job_ids,
+            received_at: now,
+            request,
+        });
+        self.persist()
+    }
+
+    fn record(&mut self, payload: StoredPayload) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let stored = self.hooks
+            .entry(payload.hook.clone())
+            .or_insert_with(VecDeque::new);
+
+        // The same delivery might be received again
+        stored.retain(|old| old.delivery != payload.delivery);
+
+        // Forget the oldest payloads of the hook if too many are stored
+        while stored.len() >= self.capacity {
+            stored.pop_front();
+        }
+        stored.push_back(payload);
+    }
+
+    /// Get a payload of a hook, by the ID of its delivery or of one of the
+    /// jobs created for it.
+    pub fn get(&self, hook: &str, id: &str) -> Option<&StoredPayload> {
+        self.hooks.get(hook).and_then(|stored| {
+            stored.iter().rev().find(|payload| {
+                payload.delivery == id
+                    || payload.job_ids.iter().any(|job_id| job_id == id)
+            })
+        })
+    }
+
+    /// List the payloads of a hook, newest first.
+    pub fn list(&self, hook: &str) -> Vec<&StoredPayload> {
+        self.hooks
+            .get(hook)
+            .map(|stored| stored.iter().rev().collect())
+            .unwrap_or_default()
+    }
+
+    fn persist(&self) -> Result<()> {
+        if let Some(ref path) = self.path {
+            // Write to a temporary file first, to avoid corrupting the
+            // payloads if Fisher crashes while writing them
+            let temp = path.with_extension("tmp");
+            {
+                let mut file = fs::File::create(&temp)?;
+                for payload in self.hooks.values().flat_map(|p| p.iter()) {
+                    writeln!(file, "{}", serde_json::to_string(payload)?)?;
+                }
+            }
+            fs::rename(&temp, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempdir::TempDir;
+
+    use utils::testing::*;
+    use web::SpooledBody;
+
+    use super::Payloads;
+
+
+    fn ids(payloads: &Payloads, hook: &str) -> Vec<String> {
+        payloads
+            .list(hook)
+            .iter()
+            .map(|payload| payload.delivery.clone())
+            .collect()
+    }
+
+
+    #[test]
+    fn test_payloads_are_bounded() {
+        let mut payloads = Payloads::new(2, None).unwrap();
+        let mut req = dummy_web_request();
+        req.body = b"payload".to_vec();
+
+        for &(hook, delivery) in &[("a", "1"), ("a", "2"), ("b", "3")] {
+            payloads.insert(
+                hook, delivery, vec![format!("job-{}", delivery)], &req, 10,
+            ).unwrap();
+        }
+        assert_eq!(ids(&payloads, "a"), vec!["2", "1"]);
+        assert_eq!(ids(&payloads, "b"), vec!["3"]);
+        assert!(ids(&payloads, "c").is_empty());
+
+        // The oldest payloads of the hook are forgotten first
+        payloads.insert("a", "4", Vec::new(), &req, 20).unwrap();
+        assert_eq!(ids(&payloads, "a"), vec!["4", "2"]);
+
+        // Receiving the same delivery again doesn't duplicate it
+        payloads.insert("a", "2", Vec::new(), &req, 30).unwrap();
+        assert_eq!(ids(&payloads, "a"), vec!["2", "4"]);
+
+        // Payloads can be found by the IDs of their jobs too
+        assert_eq!(payloads.get("b", "job-3").unwrap().delivery, "3");
+        assert_eq!(payloads.get("b", "3").unwrap().received_at, 10);
+        assert!(payloads.get("a", "3").is_none());
+        assert_eq!(
+            payloads.get("a", "4").unwrap().request.clone()
+                .into_request().unwrap().body,
+            b"payload".to_vec(),
+        );
+
+        // Nothing is stored if the capacity is zero
+        let mut payloads = Payloads::new(0, None).unwrap();
+        payloads.insert("a", "1", Vec::new(), &req, 10).unwrap();
+        assert!(ids(&payloads, "a").is_empty());
+    }
+
+    #[test]
+    fn test_spooled_payloads_are_not_stored() {
+        let mut req = dummy_web_request();
+        req.spooled = Some(Arc::new(
+            SpooledBody::new(b"big", &b" payload"[..]).unwrap(),
+        ));
+
+        let mut payloads = Payloads::new(10, None).unwrap();
+        payloads.insert("a", "1", Vec::new(), &req, 10).unwrap();
+        assert!(ids(&payloads, "a").is_empty());
+    }
+
+    #[test]
+    fn test_payloads_are_persisted() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = dir.path().join("payloads");
+        let mut req = dummy_web_request();
+        req.body = vec![0x1f, 0x8b, 0xff, 0x00];
+
+        {
+            let mut payloads = Payloads::new(2, Some(path.clone())).unwrap();
+            for delivery in &["1", "2", "3"] {
+                payloads.insert("a", delivery, Vec::new(), &req, 10).unwrap();
+            }
+            payloads.insert("b", "4", Vec::new(), &req, 10).unwrap();
+        }
+
+        // A new instance loads the stored payloads
+        let payloads = Payloads::new(2, Some(path)).unwrap();
+        assert_eq!(ids(&payloads, "a"), vec!["3", "2"]);
+        assert_eq!(ids(&payloads, "b"), vec!["4"]);
+        assert_eq!(
+            payloads.get("a", "3").unwrap().request.clone()
+                .into_request().unwrap().body,
+            req.body,
+        );
+    }
+}
@@ -17,23 +17,60 @@ use std::net::IpAddr;
 
 use requests::Request;
 use common::prelude::*;
-use utils;
+use utils::{self, IpCidr};
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ProxySupport {
     behind: u8,
+    trusted: Vec<IpCidr>,
 }
 
 impl ProxySupport {
     pub fn new(behind: u8) -> Self {
-        ProxySupport { behind: behind }
+        ProxySupport {
+            behind: behind,
+            trusted: Vec::new(),
+        }
+    }
+
+    /// Trust the proxies in the provided ranges instead of counting them.
+    pub fn trusted(trusted: Vec<IpCidr>) -> Self {
+        ProxySupport {
+            behind: 0,
+            trusted: trusted,
+        }
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.iter().any(|range| range.contains(ip))
     }
 
     pub fn source_ip(&self, req: &Request) -> Result<IpAddr> {
         let req = req.web()?;
         let original = req.source;
 
+        if !self.trusted.is_empty() {
+            // The header can be forged by anyone not being a trusted proxy
+            if !self.is_trusted(&original) {
+                return Ok(original);
+            }
+
+            let forwarded_ips = utils::parse_forwarded_for(&req.headers)?;
+            if forwarded_ips.is_empty() {
+                return Err(ErrorKind::NotBehindProxy.into());
+            }
+
+            // Walk the chain from the closest proxy, and stop at the first
+            // address which isn't trusted: that's the real client
+            return Ok(forwarded_ips
+                .iter()
+                .rev()
+                .find(|ip| !self.is_trusted(ip))
+                .cloned()
+                .unwrap_or(forwarded_ips[0]));
+        }
+
         // Return the original IP if the proxy support is disabled
         if self.behind == 0 {
             return Ok(original);
@@ -115,6 +152,11 @@ mod tests {
         // Create a new enabled ProxySupport instance
         let proxy = ProxySupport::new(1);
         assert_eq!(proxy.behind, 1);
+
+        // Create a new ProxySupport instance trusting some ranges
+        let proxy = ProxySupport::trusted(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert_eq!(proxy.behind, 0);
+        assert_eq!(proxy.trusted.len(), 1);
     }
 
 
@@ -158,6 +200,41 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_source_ip_trusted() {
+        let ip = |s: &str| IpAddr::from_str(s).unwrap();
+
+        let p = ProxySupport::trusted(vec![
+            "127.1.0.0/16".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ]);
+
+        // Only the trusted proxies are skipped, regardless of their number
+        assert_eq!(p.source_ip(&req!("127.3.3.3")).unwrap(), ip("127.3.3.3"));
+        assert_eq!(
+            p.source_ip(&req!("127.3.3.3, 10.0.0.2, 127.1.2.2")).unwrap(),
+            ip("127.3.3.3")
+        );
+        assert_eq!(
+            p.source_ip(&req!("127.4.4.4, 127.3.3.3, 10.0.0.2")).unwrap(),
+            ip("127.3.3.3")
+        );
+
+        // If every address is trusted the furthest one is the client
+        assert_eq!(
+            p.source_ip(&req!("10.0.0.3, 10.0.0.2")).unwrap(),
+            ip("10.0.0.3")
+        );
+
+        assert_err!(p.source_ip(&req!()), ErrorKind::NotBehindProxy);
+        assert_err!(p.source_ip(&req!("invalid")), ErrorKind::AddrParse(..));
+
+        // The header is ignored if the request doesn't come from a proxy
+        let p = ProxySupport::trusted(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert_eq!(p.source_ip(&req!("127.3.3.3")).unwrap(), ip("127.1.1.1"));
+    }
+
+
     #[test]
     fn test_fix_request() {
         let proxy = ProxySupport::new(1);
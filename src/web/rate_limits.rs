@@ -27,12 +27,22 @@
 //! Then, to check if the user is rate limited the code simply subtracts the
 //! current time to the limiting time, and if the delta is greater than
 //! `interval` the user is rejected.
+//!
+//! Every request can also be limited per client with a token bucket: each
+//! client starts with `burst` tokens, every request takes one of them, and
+//! they're refilled at a steady rate. The refill is calculated when the next
+//! request arrives, so no timer is needed here either. The same buckets are
+//! used for the limits of the hooks, shared by all the clients.
 
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::time::{Instant, Duration};
 
 
+/// Buckets tracked before the full ones are forgotten.
+const MAX_BUCKETS: usize = 4096;
+
+
 #[derive(Debug)]
 enum LimitStatus {
     Unlimited,
@@ -104,12 +114,119 @@ impl<Id: Hash + Eq + PartialEq> RateLimiter<Id> {
 }
 
 
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+
+#[derive(Debug)]
+pub struct TokenBuckets<Id: Hash + Eq + PartialEq> {
+    buckets: HashMap<Id, Bucket>,
+    per_second: f64,
+    burst: f64,
+}
+
+impl<Id: Hash + Eq + PartialEq> TokenBuckets<Id> {
+    /// Allow `allowed` requests every `interval` seconds on average, and up
+    /// to `burst` of them at once.
+    pub fn new(allowed: u64, interval: u64, burst: u64) -> Self {
+        TokenBuckets {
+            buckets: HashMap::new(),
+            per_second: allowed as f64 / interval as f64,
+            burst: burst as f64,
+        }
+    }
+
+    /// Take a token for a request, returning how long to wait before the
+    /// next one if there are no tokens left.
+    pub fn take(&mut self, id: Id) -> Option<Duration> {
+        self.take_at(id, Instant::now())
+    }
+
+    fn take_at(&mut self, id: Id, now: Instant) -> Option<Duration> {
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(&id)
+        {
+            self.forget_full(now);
+        }
+
+        let (per_second, burst) = (self.per_second, self.burst);
+        let bucket = self.buckets.entry(id).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        bucket.tokens = refill(bucket, now, per_second, burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            // Round up, since Retry-After only supports whole seconds
+            let wait = (1.0 - bucket.tokens) / per_second;
+            Some(Duration::from_secs(wait.ceil() as u64))
+        }
+    }
+
+    /// Forget the clients whose buckets are full again, since they would
+    /// start from a full bucket anyway.
+    fn forget_full(&mut self, now: Instant) {
+        let (per_second, burst) = (self.per_second, self.burst);
+        self.buckets.retain(|_, bucket| {
+            refill(bucket, now, per_second, burst) < burst
+        });
+    }
+}
+
+/// The limits configured by the hooks themselves, each one with a bucket as
+/// big as the number of requests allowed in its interval.
+#[derive(Debug)]
+pub struct HookLimits {
+    hooks: HashMap<String, ((u64, u64), TokenBuckets<()>)>,
+}
+
+impl HookLimits {
+    pub fn new() -> Self {
+        HookLimits {
+            hooks: HashMap::new(),
+        }
+    }
+
+    /// Take a token for a request to the hook, returning how long to wait
+    /// before the next one if there are no tokens left.
+    pub fn take(
+        &mut self, hook: &str, allowed: u64, interval: u64,
+    ) -> Option<Duration> {
+        let limit = (allowed, interval);
+        if self.hooks.get(hook).map(|entry| entry.0) != Some(limit) {
+            // Start from scratch if the limit changed after a reload
+            self.hooks.insert(hook.to_string(), (
+                limit, TokenBuckets::new(allowed, interval, allowed),
+            ));
+        }
+
+        self.hooks.get_mut(hook).unwrap().1.take(())
+    }
+}
+
+
+fn refill(bucket: &Bucket, now: Instant, per_second: f64, burst: f64) -> f64 {
+    let elapsed = now.duration_since(bucket.updated);
+    let elapsed = elapsed.as_secs() as f64
+        + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+
+    (bucket.tokens + elapsed * per_second).min(burst)
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
-    use super::RateLimiter;
+    use super::{HookLimits, RateLimiter, TokenBuckets, MAX_BUCKETS};
 
 
     #[test]
@@ -155,4 +272,65 @@ mod tests {
         thread::sleep(Duration::from_secs(1));
         assert!(limiter.is_limited(&1).is_none());
     }
+
+
+    #[test]
+    fn test_token_buckets() {
+        // Allow a request every 2 seconds, with bursts of 3 requests
+        let mut buckets = TokenBuckets::<u8>::new(30, 60, 3);
+        let now = Instant::now();
+        let at = |secs| now + Duration::from_secs(secs);
+
+        for _ in 0..3 {
+            assert!(buckets.take_at(1, now).is_none());
+        }
+        assert_eq!(buckets.take_at(1, now), Some(Duration::from_secs(2)));
+
+        // Other clients have their own bucket
+        assert!(buckets.take_at(2, now).is_none());
+
+        // Tokens are refilled over time, up to the burst
+        assert_eq!(buckets.take_at(1, at(1)), Some(Duration::from_secs(1)));
+        assert!(buckets.take_at(1, at(2)).is_none());
+        assert!(buckets.take_at(1, at(2)).is_some());
+
+        for _ in 0..3 {
+            assert!(buckets.take_at(1, at(100)).is_none());
+        }
+        assert!(buckets.take_at(1, at(100)).is_some());
+    }
+
+    #[test]
+    fn test_token_buckets_forget_full() {
+        let mut buckets = TokenBuckets::<usize>::new(1, 1, 2);
+        let now = Instant::now();
+
+        for id in 0..MAX_BUCKETS {
+            buckets.take_at(id, now);
+        }
+        assert_eq!(buckets.buckets.len(), MAX_BUCKETS);
+
+        // Only the buckets refilled in the meantime are forgotten
+        buckets.take_at(0, now);
+        let later = now + Duration::from_secs(1);
+        buckets.take_at(MAX_BUCKETS, later);
+        assert_eq!(buckets.buckets.len(), 2);
+        assert!(buckets.buckets.contains_key(&0));
+        assert!(buckets.buckets.contains_key(&MAX_BUCKETS));
+    }
+
+    #[test]
+    fn test_hook_limits() {
+        let mut limits = HookLimits::new();
+
+        // Every hook has its own limit
+        for _ in 0..2 {
+            assert!(limits.take("a.sh", 2, 60).is_none());
+        }
+        assert_eq!(limits.take("a.sh", 2, 60), Some(Duration::from_secs(30)));
+        assert!(limits.take("b.sh", 2, 60).is_none());
+
+        // Changing the limit resets it
+        assert!(limits.take("a.sh", 3, 60).is_none());
+    }
 }
@@ -13,63 +13,668 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Read, Write};
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use tiny_http;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::{future, Future, Stream};
+use hyper::{self, Body};
+use serde_json::{self, Value};
+use tempdir::TempDir;
 use url::form_urlencoded;
 
+use common::prelude::*;
+use utils::Headers;
+use web::AccessDetails;
+
 
 #[derive(Debug, Clone)]
 pub struct WebRequest {
     pub source: IpAddr,
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     pub params: HashMap<String, String>,
-    pub body: String,
+    /// The parts of the path captured by the route the request matched,
+    /// starting with the name of the hook.
+    pub path_args: Vec<String>,
+    /// The raw body of the request, which might not be valid UTF-8.
+    pub body: Vec<u8>,
+    /// The body of the request, if it was too big to be kept in memory. In
+    /// that case `body` is empty.
+    pub spooled: Option<Arc<SpooledBody>>,
+    /// The body parsed as JSON, filled the first time `json()` is called.
+    pub json_body: JsonBody,
+    /// What happened to the request, recorded for the access log.
+    pub access: AccessDetails,
 }
 
-
-impl<'a> From<&'a mut tiny_http::Request> for WebRequest {
-    fn from(origin: &'a mut tiny_http::Request) -> WebRequest {
-        // Get the source IP
-        let source = origin.remote_addr().ip();
+impl WebRequest {
+    /// Convert a request received by the HTTP server, checking the limits
+    /// before reading the body of the request. The body is received without
+    /// blocking, so slow clients don't keep the workers busy.
+    pub fn read(
+        origin: hyper::Request<Body>, source: IpAddr, limits: &BodyLimits,
+    ) -> Box<Future<Item = WebRequest, Error = Error> + Send> {
+        let (parts, body) = origin.into_parts();
 
         // Get the headers
-        let mut headers = HashMap::new();
-        for header in origin.headers() {
+        let mut headers = Headers::new();
+        for (name, value) in &parts.headers {
             headers.insert(
-                header.field.as_str().as_str().to_string(),
-                header.value.as_str().to_string(),
+                name.as_str().to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
             );
         }
 
-        // Get the body
-        let mut body = String::new();
-        origin.as_reader().read_to_string(&mut body).unwrap();
+        let encoding = match check_headers(&headers, limits) {
+            Ok(encoding) => encoding,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let limits = limits.clone();
+        let received = body
+            .map_err(|err| {
+                Error::from(io::Error::new(io::ErrorKind::Other, err))
+            })
+            .fold(ReceivedBody::new(&limits), |mut received, chunk| {
+                received.push(&chunk).map(|_| received)
+            });
+
+        Box::new(received.and_then(move |received| {
+            let (body, spooled) = received.finish()?;
+            WebRequest::new(
+                parts.uri, headers, encoding, body, spooled, source, &limits,
+            )
+        }))
+    }
+
+    /// Create the request once its body was received.
+    fn new(
+        uri: hyper::Uri, mut headers: Headers, encoding: ContentEncoding,
+        mut body: Vec<u8>, mut spooled: Option<Arc<SpooledBody>>,
+        source: IpAddr, limits: &BodyLimits,
+    ) -> Result<WebRequest> {
+        // Compressed bodies are decoded before the providers see them, and
+        // the decoded body must respect the same limits
+        if encoding != ContentEncoding::Identity {
+            let raw: Box<Read> = if let Some(ref spooled) = spooled {
+                Box::new(spooled.open()?)
+            } else {
+                Box::new(Cursor::new(body))
+            };
+
+            let decoded = match encoding {
+                ContentEncoding::Gzip => read_body(GzDecoder::new(raw), limits),
+                ContentEncoding::Deflate => {
+                    read_body(ZlibDecoder::new(raw), limits)
+                }
+                ContentEncoding::Identity => unreachable!(),
+            };
+            let (decoded_body, decoded_spooled) = match decoded {
+                Err(Error(ErrorKind::Io(..), _)) => {
+                    return Err(ErrorKind::RequestBodyDecodingFailed(
+                        encoding.name().into()
+                    ).into());
+                }
+                other => other?,
+            };
+
+            body = decoded_body;
+            spooled = decoded_spooled;
+            headers.remove("Content-Encoding");
+        }
 
         // Get the querystring
-        let url = origin.url();
-        let params = if url.contains('?') {
-            let query = url.rsplitn(2, '?').nth(0).unwrap();
-            params_from_query(query)
-        } else {
-            HashMap::new()
+        let params = match uri.query() {
+            Some(query) => params_from_query(query),
+            None => HashMap::new(),
         };
 
-        WebRequest {
+        let mut request = WebRequest {
             source: source,
             headers: headers,
             params: params,
+            path_args: Vec::new(),
             body: body,
+            spooled: spooled,
+            json_body: JsonBody::default(),
+            access: AccessDetails::default(),
+        };
+        request.parse_form_body();
+
+        Ok(request)
+    }
+
+    /// Add the fields of form-encoded bodies to the params. Params from the
+    /// querystring have precedence over the ones in the body.
+    pub fn parse_form_body(&mut self) {
+        let content_type = self.headers
+            .get("Content-Type")
+            .map(|value| value.as_str());
+
+        if self.spooled.is_some() || !is_form(content_type) {
+            return;
+        }
+
+        for (key, value) in params_from_query_bytes(&self.body) {
+            self.params.entry(key).or_insert(value);
+        }
+    }
+
+    /// Get the body of the request as a string, or an empty string if it
+    /// isn't valid UTF-8. Binary payloads must be read from `body`.
+    pub fn body_str(&self) -> &str {
+        str::from_utf8(&self.body).unwrap_or("")
+    }
+
+    /// Get the body of the request parsed as JSON, or None if it isn't valid
+    /// JSON. The body is parsed only once, even if more than one provider
+    /// inspects it.
+    pub fn json(&self) -> Option<Arc<Value>> {
+        let mut parsed = self.json_body.parsed.lock().unwrap();
+        if parsed.is_none() {
+            // Spooled bodies are parsed while they're read from their file
+            let value = if self.spooled.is_some() {
+                self.body_reader()
+                    .ok()
+                    .and_then(|body| {
+                        serde_json::from_reader(BufReader::new(body)).ok()
+                    })
+            } else {
+                serde_json::from_str(self.body_str()).ok()
+            };
+            *parsed = Some(value.map(Arc::new));
+        }
+
+        parsed.clone().unwrap()
+    }
+
+    /// Read the body of the request, whether it's kept in memory or it was
+    /// spooled to disk.
+    pub fn body_reader<'a>(&'a self) -> Result<Box<Read + 'a>> {
+        if let Some(ref spooled) = self.spooled {
+            Ok(Box::new(spooled.open()?))
+        } else {
+            Ok(Box::new(&self.body[..]))
+        }
+    }
+}
+
+
+/// Check the headers of a request against the limits, before its body is
+/// received, returning the encoding of the body.
+fn check_headers(
+    headers: &Headers, limits: &BodyLimits,
+) -> Result<ContentEncoding> {
+    limits.check_content_type(
+        headers.get("Content-Type").map(|value| value.as_str())
+    )?;
+
+    let encoding = ContentEncoding::parse(
+        headers.get("Content-Encoding").map(|value| value.as_str())
+    )?;
+
+    // Clients declaring a body too big are rejected before sending it
+    if let Some(max) = limits.max_size {
+        let length = headers.get("Content-Length")
+            .and_then(|len| len.trim().parse::<u64>().ok());
+        if length.map(|len| len > max).unwrap_or(false) {
+            return Err(ErrorKind::RequestBodyTooLarge(max).into());
+        }
+    }
+
+    Ok(encoding)
+}
+
+
+/// The body of a request being received from the connection, kept in
+/// memory until it's bigger than the spool threshold.
+struct ReceivedBody {
+    limits: BodyLimits,
+    body: Vec<u8>,
+    spooled: Option<(SpooledBody, File)>,
+}
+
+impl ReceivedBody {
+    fn new(limits: &BodyLimits) -> Self {
+        ReceivedBody {
+            limits: limits.clone(),
+            body: Vec::new(),
+            spooled: None,
+        }
+    }
+
+    /// Add a chunk of the body, failing as soon as the body is bigger than
+    /// the maximum size.
+    fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        let size = match self.spooled {
+            Some((ref spooled, _)) => spooled.size,
+            None => self.body.len() as u64,
+        } + chunk.len() as u64;
+        if let Some(max) = self.limits.max_size {
+            if size > max {
+                return Err(ErrorKind::RequestBodyTooLarge(max).into());
+            }
+        }
+
+        if let Some((ref mut spooled, ref mut file)) = self.spooled {
+            file.write_all(chunk)?;
+            spooled.size = size;
+            return Ok(());
+        }
+
+        self.body.extend_from_slice(chunk);
+        if let Some(threshold) = self.limits.spool_threshold {
+            if size > threshold {
+                let spooled = SpooledBody::new(&self.body, io::empty())?;
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(spooled.path())?;
+                self.spooled = Some((spooled, file));
+                self.body = Vec::new();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(Vec<u8>, Option<Arc<SpooledBody>>)> {
+        match self.spooled {
+            Some((spooled, mut file)) => {
+                file.flush()?;
+                Ok((Vec::new(), Some(Arc::new(spooled))))
+            }
+            None => Ok((self.body, None)),
+        }
+    }
+}
+
+
+/// Read the body from the reader, spooling it to disk if it's bigger than
+/// the threshold and failing if it's bigger than the maximum size.
+fn read_body<R: Read>(
+    reader: R, limits: &BodyLimits,
+) -> Result<(Vec<u8>, Option<Arc<SpooledBody>>)> {
+    let mut reader = reader
+        .take(limits.max_size.map(|max| max + 1).unwrap_or(u64::MAX));
+
+    // Bodies bigger than the threshold are spooled to disk
+    let mut body = Vec::new();
+    let mut spooled = None;
+    if let Some(threshold) = limits.spool_threshold {
+        (&mut reader).take(threshold + 1).read_to_end(&mut body)?;
+
+        if body.len() as u64 > threshold {
+            spooled = Some(Arc::new(SpooledBody::new(&body, reader)?));
+            body = Vec::new();
+        }
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+
+    let size = spooled.as_ref().map(|s| s.size()).unwrap_or(body.len() as u64);
+    if let Some(max) = limits.max_size {
+        if size > max {
+            return Err(ErrorKind::RequestBodyTooLarge(max).into());
+        }
+    }
+
+    Ok((body, spooled))
+}
+
+
+/// The encodings of the request body Fisher is able to decode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    fn parse(header: Option<&str>) -> Result<Self> {
+        let header = header.map(|h| h.trim()).unwrap_or("");
+
+        if header.is_empty() || header.eq_ignore_ascii_case("identity") {
+            Ok(ContentEncoding::Identity)
+        } else if header.eq_ignore_ascii_case("gzip")
+            || header.eq_ignore_ascii_case("x-gzip")
+        {
+            Ok(ContentEncoding::Gzip)
+        } else if header.eq_ignore_ascii_case("deflate") {
+            Ok(ContentEncoding::Deflate)
+        } else {
+            Err(ErrorKind::RequestContentEncodingNotSupported(
+                header.into()
+            ).into())
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+}
+
+
+/// The cached result of parsing the body of a request as JSON.
+#[derive(Debug, Default)]
+pub struct JsonBody {
+    parsed: Mutex<Option<Option<Arc<Value>>>>,
+}
+
+impl Clone for JsonBody {
+    fn clone(&self) -> Self {
+        JsonBody {
+            parsed: Mutex::new(self.parsed.lock().unwrap().clone()),
+        }
+    }
+}
+
+
+/// The body of a request stored in a temporary file, which is removed when
+/// the last request referencing it is dropped.
+#[derive(Debug)]
+pub struct SpooledBody {
+    dir: TempDir,
+    size: u64,
+}
+
+impl SpooledBody {
+    /// Store the already read start of the body and the rest of it.
+    pub fn new<R: Read>(start: &[u8], mut rest: R) -> Result<Self> {
+        let dir = TempDir::new("fisher-spool")?;
+
+        let mut file = File::create(dir.path().join("body"))?;
+        file.write_all(start)?;
+        let size = start.len() as u64 + io::copy(&mut rest, &mut file)?;
+
+        Ok(SpooledBody { dir, size })
+    }
+
+    /// The path of the file containing the body.
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join("body")
+    }
+
+    /// The size of the body, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Open the file containing the body for reading.
+    pub fn open(&self) -> Result<File> {
+        Ok(File::open(self.path())?)
+    }
+}
+
+
+/// Limits checked before the body of a request is read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BodyLimits {
+    /// The maximum size of the body, in bytes
+    pub max_size: Option<u64>,
+    /// The allowed media types of the body
+    pub content_types: Option<Vec<String>>,
+    /// The size after which the body is spooled to disk, in bytes
+    pub spool_threshold: Option<u64>,
+}
+
+impl BodyLimits {
+    /// Lower the maximum size of the body to `max`, if it's higher.
+    pub fn cap_size(&mut self, max: u64) {
+        self.max_size = Some(self.max_size.map_or(max, |size| size.min(max)));
+    }
+
+    fn check_content_type(&self, content_type: Option<&str>) -> Result<()> {
+        let allowed = if let Some(ref allowed) = self.content_types {
+            allowed
+        } else {
+            return Ok(());
+        };
+
+        // Parameters like the charset are ignored
+        let media_type = content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|ct| ct.trim())
+            .unwrap_or("");
+
+        if allowed.iter().any(|a| a.eq_ignore_ascii_case(media_type)) {
+            Ok(())
+        } else {
+            Err(ErrorKind::RequestContentTypeNotAllowed(
+                media_type.into()
+            ).into())
         }
     }
 }
 
 
 pub fn params_from_query(query: &str) -> HashMap<String, String> {
+    params_from_query_bytes(query.as_bytes())
+}
+
+
+fn params_from_query_bytes(query: &[u8]) -> HashMap<String, String> {
     let mut hashmap = HashMap::new();
-    for (a, b) in form_urlencoded::parse(query.as_bytes()).into_owned() {
+    for (a, b) in form_urlencoded::parse(query).into_owned() {
         hashmap.insert(a, b);
     }
     hashmap
 }
+
+
+/// Check if the content type is the one of form-encoded bodies.
+fn is_form(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| {
+            ct.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+        .unwrap_or(false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::Arc;
+
+    use common::prelude::*;
+    use utils::testing::dummy_web_request;
+
+    use super::{
+        is_form, read_body, BodyLimits, ContentEncoding, SpooledBody,
+    };
+
+
+    #[test]
+    fn test_check_content_type() {
+        // Everything is allowed by default
+        let limits = BodyLimits::default();
+        assert!(limits.check_content_type(None).is_ok());
+        assert!(limits.check_content_type(Some("text/plain")).is_ok());
+
+        let limits = BodyLimits {
+            max_size: None,
+            content_types: Some(vec!["application/json".into()]),
+            spool_threshold: None,
+        };
+        for right in &[
+            "application/json", "application/json; charset=utf-8",
+            "Application/JSON",
+        ] {
+            assert!(limits.check_content_type(Some(right)).is_ok(), "{}", right);
+        }
+
+        for wrong in &[Some("text/plain"), Some(""), None] {
+            match limits.check_content_type(*wrong) {
+                Err(Error(ErrorKind::RequestContentTypeNotAllowed(..), _)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_content_encoding() {
+        for &(header, expected) in &[
+            (None, ContentEncoding::Identity),
+            (Some("identity"), ContentEncoding::Identity),
+            (Some("gzip"), ContentEncoding::Gzip),
+            (Some("X-Gzip"), ContentEncoding::Gzip),
+            (Some(" deflate "), ContentEncoding::Deflate),
+        ] {
+            assert_eq!(ContentEncoding::parse(header).unwrap(), expected);
+        }
+
+        for wrong in &["br", "gzip, br", "compress"] {
+            match ContentEncoding::parse(Some(wrong)) {
+                Err(Error(
+                    ErrorKind::RequestContentEncodingNotSupported(..), _
+                )) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_is_form() {
+        assert!(is_form(Some("application/x-www-form-urlencoded")));
+        assert!(is_form(
+            Some("Application/X-WWW-Form-Urlencoded; charset=utf-8")
+        ));
+        assert!(!is_form(Some("application/json")));
+        assert!(!is_form(Some("multipart/form-data; boundary=abcd")));
+        assert!(!is_form(None));
+    }
+
+
+    #[test]
+    fn test_read_body() {
+        let limits = BodyLimits {
+            max_size: Some(8),
+            content_types: None,
+            spool_threshold: Some(4),
+        };
+
+        let (body, spooled) = read_body(&b"abc"[..], &limits).unwrap();
+        assert_eq!(body, b"abc");
+        assert!(spooled.is_none());
+
+        let (body, spooled) = read_body(&b"abcdef"[..], &limits).unwrap();
+        assert!(body.is_empty());
+        assert_eq!(spooled.unwrap().size(), 6);
+
+        match read_body(&b"abcdefghi"[..], &limits) {
+            Err(Error(ErrorKind::RequestBodyTooLarge(8), _)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+
+    #[test]
+    fn test_cap_size() {
+        let mut limits = BodyLimits::default();
+        limits.cap_size(100);
+        assert_eq!(limits.max_size, Some(100));
+
+        // The lowest limit wins
+        limits.cap_size(200);
+        assert_eq!(limits.max_size, Some(100));
+        limits.cap_size(50);
+        assert_eq!(limits.max_size, Some(50));
+    }
+
+
+    #[test]
+    fn test_parse_form_body() {
+        let mut req = dummy_web_request();
+        req.headers.insert(
+            "content-type".into(), "application/x-www-form-urlencoded".into(),
+        );
+        req.params.insert("a".into(), "query".into());
+        req.body = b"a=body&b=2".to_vec();
+        req.parse_form_body();
+        assert_eq!(req.params["a"], "query");
+        assert_eq!(req.params["b"], "2");
+
+        // Other content types are ignored
+        let mut req = dummy_web_request();
+        req.headers.insert("Content-Type".into(), "text/plain".into());
+        req.body = b"a=body".to_vec();
+        req.parse_form_body();
+        assert!(req.params.is_empty());
+    }
+
+
+    #[test]
+    fn test_body_str() {
+        let mut req = dummy_web_request();
+        req.body = "some text".into();
+        assert_eq!(req.body_str(), "some text");
+
+        // Binary bodies are kept intact, but they're not a string
+        req.body = vec![0x1f, 0x8b, 0xff, 0x00];
+        assert_eq!(req.body_str(), "");
+        assert_eq!(req.body, vec![0x1f, 0x8b, 0xff, 0x00]);
+    }
+
+
+    #[test]
+    fn test_json_body() {
+        let mut req = dummy_web_request();
+        req.body = "not json".into();
+        assert!(req.json().is_none());
+
+        let mut req = dummy_web_request();
+        req.body = r#"{"a": [1, 2]}"#.into();
+        let parsed = req.json().unwrap();
+        assert_eq!(parsed["a"][1], 2);
+
+        // The body is parsed only once, and clones share the result
+        req.body = "not json anymore".into();
+        assert!(Arc::ptr_eq(&req.json().unwrap(), &parsed));
+        assert!(Arc::ptr_eq(&req.clone().json().unwrap(), &parsed));
+    }
+
+
+    #[test]
+    fn test_spooled_body() {
+        let spooled = SpooledBody::new(b"hello ", &b"world"[..]).unwrap();
+        assert_eq!(spooled.size(), 11);
+
+        let path = spooled.path();
+        assert!(path.exists());
+
+        let mut req = dummy_web_request();
+        req.spooled = Some(Arc::new(spooled));
+
+        let mut body = String::new();
+        req.body_reader().unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello world");
+
+        // The file is removed with the last request referencing it
+        let clone = req.clone();
+        drop(req);
+        assert!(path.exists());
+        drop(clone);
+        assert!(!path.exists());
+
+        // Bodies kept in memory are read as well
+        let mut req = dummy_web_request();
+        req.body = "in memory".into();
+
+        let mut body = String::new();
+        req.body_reader().unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "in memory");
+    }
+}
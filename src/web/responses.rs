@@ -15,37 +15,189 @@
 
 use std::time::Duration;
 
-use serde_json;
+use hyper::Body;
+use serde_json::{self, Value};
 
 use common::prelude::*;
-use common::structs::HealthDetails;
+use common::structs::{CustomResponse, HealthDetails};
 
 
 #[derive(Debug)]
 pub enum Response {
     NotFound,
+    Unauthorized,
+    /// Like `Unauthorized`, but asking browsers to show a login prompt.
+    LoginRequired,
     Forbidden,
+    ReadOnly,
     BadRequest(Error),
     TooManyRequests(Duration),
+    PayloadTooLarge(u64),
+    HeadersTooLarge(usize),
+    RequestTimeout,
+    UnsupportedMediaType,
     Unavailable,
+    CircuitOpen,
+    BudgetExhausted,
     Ok,
+    Muted,
+    Queued(String),
+    PendingApproval(String),
+    Frozen(String),
+    Held(String),
+    FannedOut(Vec<String>),
+    Body(serde_json::Value),
     HealthStatus(HealthDetails),
+    /// Plain text sent as it's produced, until the body is dropped.
+    Stream(Body),
+    /// The JSON result of a job, sent by a background thread once the job
+    /// ends.
+    Waiting(String, Body),
+    /// Binary content, like the artifacts of the jobs.
+    Download(Vec<u8>),
+    Custom {
+        status: u16,
+        content_type: &'static str,
+        body: String,
+    },
 }
 
 impl Response {
     pub fn status(&self) -> u16 {
         match *self {
             Response::NotFound => 404,
+            Response::Unauthorized => 401,
+            Response::LoginRequired => 401,
             Response::Forbidden => 403,
+            Response::ReadOnly => 403,
             Response::BadRequest(..) => 400,
             Response::TooManyRequests(..) => 429,
+            Response::PayloadTooLarge(..) => 413,
+            Response::HeadersTooLarge(..) => 431,
+            Response::RequestTimeout => 408,
+            Response::UnsupportedMediaType => 415,
             Response::Unavailable => 503,
+            Response::CircuitOpen => 503,
+            Response::BudgetExhausted => 429,
+            Response::PendingApproval(..) => 202,
+            Response::Frozen(..) => 503,
+            Response::Held(..) => 202,
+            Response::Custom { status, .. } => status,
             _ => 200,
         }
     }
 
+    /// Get the name of the status, included in the JSON responses.
+    fn status_name(&self) -> &'static str {
+        match *self {
+            Response::NotFound => "not_found",
+            Response::Unauthorized | Response::LoginRequired => "unauthorized",
+            Response::Forbidden => "forbidden",
+            Response::ReadOnly => "read_only",
+            Response::BadRequest(..) => "bad_request",
+            Response::TooManyRequests(..) => "too_many_requests",
+            Response::PayloadTooLarge(..) => "payload_too_large",
+            Response::HeadersTooLarge(..) => "headers_too_large",
+            Response::RequestTimeout => "request_timeout",
+            Response::UnsupportedMediaType => "unsupported_media_type",
+            Response::Unavailable => "unavailable",
+            Response::CircuitOpen => "circuit_open",
+            Response::BudgetExhausted => "budget_exhausted",
+            Response::PendingApproval(..) => "pending_approval",
+            Response::Frozen(..) => "frozen",
+            Response::Held(..) => "held",
+            Response::Muted => "muted",
+            Response::Ok
+            | Response::Queued(..)
+            | Response::FannedOut(..)
+            | Response::Body(..)
+            | Response::HealthStatus(..)
+            | Response::Stream(..)
+            | Response::Waiting(..)
+            | Response::Download(..)
+            | Response::Custom { .. } => "ok",
+        }
+    }
+
+    /// Replace this response with the custom one of a hook. The `{hook}`,
+    /// `{job_id}` and `{status}` variables in the body are replaced with
+    /// the name of the hook, the ID of the job and the name of the status.
+    pub fn customize(self, custom: &CustomResponse, hook: &str) -> Response {
+        let job_id = self.job_ids().join(",");
+
+        let body = if let Some(ref template) = custom.body {
+            template
+                .replace("{hook}", hook)
+                .replace("{job_id}", &job_id)
+                .replace("{status}", self.status_name())
+        } else {
+            self.json()
+        };
+
+        Response::Custom {
+            status: custom.status.unwrap_or_else(|| self.status()),
+            content_type: if serde_json::from_str::<Value>(&body).is_ok() {
+                "application/json"
+            } else {
+                "text/plain"
+            },
+            body,
+        }
+    }
+
+    /// Get the IDs of the jobs created for the request.
+    pub fn job_ids(&self) -> Vec<String> {
+        match *self {
+            Response::Queued(ref id)
+            | Response::PendingApproval(ref id)
+            | Response::Held(ref id)
+            | Response::Waiting(ref id, _) => vec![id.clone()],
+            Response::FannedOut(ref ids) => ids.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the content type of the body.
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            Response::Custom { content_type, .. } => content_type,
+            Response::Stream(..) => "text/plain; charset=utf-8",
+            Response::Download(..) => "application/octet-stream",
+            _ => "application/json",
+        }
+    }
+
+    /// Get the body of the response, which is JSON unless it's customized.
+    pub fn body(&self) -> String {
+        match *self {
+            Response::Custom { ref body, .. } => body.clone(),
+            _ => self.json(),
+        }
+    }
+
     pub fn json(&self) -> String {
         serde_json::to_string(&match *self {
+            Response::Body(ref body) => body.clone(),
+            Response::Queued(ref job_id) => json!({
+                "status": "ok",
+                "job_id": job_id,
+            }),
+            Response::PendingApproval(ref job_id) => json!({
+                "status": "pending_approval",
+                "job_id": job_id,
+            }),
+            Response::Frozen(ref reason) => json!({
+                "status": "frozen",
+                "reason": reason,
+            }),
+            Response::Held(ref job_id) => json!({
+                "status": "held",
+                "job_id": job_id,
+            }),
+            Response::FannedOut(ref job_ids) => json!({
+                "status": "ok",
+                "job_ids": job_ids,
+            }),
             Response::HealthStatus(ref details) => json!({
                 "status": "ok",
                 "result": details,
@@ -54,25 +206,35 @@ impl Response {
                 "status": "bad_request",
                 "error_msg": format!("{}", error),
             }),
+            Response::PayloadTooLarge(max_size) => json!({
+                "status": "payload_too_large",
+                "max_size": max_size,
+            }),
+            Response::HeadersTooLarge(max_size) => json!({
+                "status": "headers_too_large",
+                "max_size": max_size,
+            }),
             Response::TooManyRequests(ref until) => json!({
                 "status": "too_many_requests",
                 "retry_after": until.as_secs(),
             }),
             _ => json!({
-                "status": match *self {
-                    Response::NotFound => "not_found",
-                    Response::Forbidden => "forbidden",
-                    Response::BadRequest(..) => "bad_request",
-                    Response::TooManyRequests(..) => "too_many_requests",
-                    Response::Unavailable => "unavailable",
-                    Response::Ok | Response::HealthStatus(..) => "ok",
-                },
+                "status": self.status_name(),
             }),
         }).unwrap()
     }
 
     pub fn headers(&self) -> Option<Vec<String>> {
         match *self {
+            Response::Unauthorized => {
+                Some(vec!["WWW-Authenticate: Bearer".into()])
+            },
+            Response::LoginRequired => {
+                Some(vec![
+                    "WWW-Authenticate: Basic realm=\"Fisher\", \
+                     charset=\"UTF-8\"".into(),
+                ])
+            },
             Response::TooManyRequests(ref duration) => {
                 Some(vec![
                     format!("Retry-After: {}", duration.as_secs()),
@@ -91,7 +253,7 @@ mod tests {
     use serde_json;
 
     use common::prelude::*;
-    use common::structs::HealthDetails;
+    use common::structs::{CustomResponse, HealthDetails, WorkerState};
 
     use super::Response;
 
@@ -120,6 +282,41 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_unauthorized() {
+        let response = Response::Unauthorized;
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers(),
+            Some(vec!["WWW-Authenticate: Bearer".to_string()])
+        );
+
+        // The status must be "unauthorized"
+        let json = j(response.json());
+        assert_eq!(
+            json.get("status").unwrap().as_str().unwrap(),
+            "unauthorized"
+        );
+    }
+
+
+    #[test]
+    fn test_login_required() {
+        let response = Response::LoginRequired;
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers(),
+            Some(vec![
+                "WWW-Authenticate: Basic realm=\"Fisher\", charset=\"UTF-8\""
+                    .to_string(),
+            ])
+        );
+
+        let json = j(response.json());
+        assert_eq!(json, json!({"status": "unauthorized"}));
+    }
+
+
     #[test]
     fn test_forbidden() {
         let response = Response::Forbidden;
@@ -138,6 +335,17 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_read_only() {
+        let response = Response::ReadOnly;
+        assert_eq!(response.status(), 403);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json, json!({"status": "read_only"}));
+    }
+
+
     #[test]
     fn test_bad_request() {
         // This is just a dummy error
@@ -183,6 +391,80 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_payload_too_large() {
+        let response = Response::PayloadTooLarge(1024);
+        assert_eq!(response.status(), 413);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json, json!({
+            "status": "payload_too_large",
+            "max_size": 1024,
+        }));
+    }
+
+
+    #[test]
+    fn test_headers_too_large() {
+        let response = Response::HeadersTooLarge(8192);
+        assert_eq!(response.status(), 431);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json, json!({
+            "status": "headers_too_large",
+            "max_size": 8192,
+        }));
+    }
+
+
+    #[test]
+    fn test_request_timeout() {
+        let response = Response::RequestTimeout;
+        assert_eq!(response.status(), 408);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json, json!({"status": "request_timeout"}));
+    }
+
+
+    #[test]
+    fn test_customize() {
+        let custom = CustomResponse {
+            status: None,
+            body: Some("{status} {job_id} {hook}".into()),
+        };
+        let response = Response::PendingApproval("abcd".into())
+            .customize(&custom, "deploy.sh");
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.content_type(), "text/plain");
+        assert_eq!(response.body(), "pending_approval abcd deploy.sh");
+
+        // The default body is kept if only the status is customized
+        let custom = CustomResponse {
+            status: Some(200),
+            body: None,
+        };
+        let response = Response::Forbidden.customize(&custom, "deploy.sh");
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.content_type(), "application/json");
+        assert_eq!(j(response.body()), json!({"status": "forbidden"}));
+    }
+
+
+    #[test]
+    fn test_unsupported_media_type() {
+        let response = Response::UnsupportedMediaType;
+        assert_eq!(response.status(), 415);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json, json!({"status": "unsupported_media_type"}));
+    }
+
+
     #[test]
     fn test_unavailable() {
         let response = Response::Unavailable;
@@ -201,6 +483,84 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_circuit_open() {
+        let response = Response::CircuitOpen;
+        assert_eq!(response.status(), 503);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json["status"].as_str().unwrap(), "circuit_open");
+    }
+
+
+    #[test]
+    fn test_budget_exhausted() {
+        let response = Response::BudgetExhausted;
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().is_none());
+
+        let json = j(response.json());
+        assert_eq!(json["status"].as_str().unwrap(), "budget_exhausted");
+    }
+
+
+    #[test]
+    fn test_muted() {
+        let response = Response::Muted;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({"status": "muted"}));
+    }
+
+
+    #[test]
+    fn test_pending_approval() {
+        let response = Response::PendingApproval("abcd".into());
+        assert_eq!(response.status(), 202);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "pending_approval",
+            "job_id": "abcd",
+        }));
+    }
+
+
+    #[test]
+    fn test_frozen() {
+        let response = Response::Frozen("release in progress".into());
+        assert_eq!(response.status(), 503);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "frozen",
+            "reason": "release in progress",
+        }));
+
+        let response = Response::Held("abcd".into());
+        assert_eq!(response.status(), 202);
+        assert_eq!(j(response.json()), json!({
+            "status": "held",
+            "job_id": "abcd",
+        }));
+    }
+
+
+    #[test]
+    fn test_fanned_out() {
+        let response = Response::FannedOut(vec!["abcd".into(), "efgh".into()]);
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "ok",
+            "job_ids": ["abcd", "efgh"],
+        }));
+    }
+
+
     #[test]
     fn test_ok() {
         let response = Response::Ok;
@@ -219,12 +579,44 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_queued() {
+        let response = Response::Queued("abcde".into());
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "ok",
+            "job_id": "abcde",
+        }));
+    }
+
+
+    #[test]
+    fn test_body() {
+        let response = Response::Body(json!({"type": 1}));
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().is_none());
+
+        // The body must be returned as-is
+        assert_eq!(j(response.json()), json!({"type": 1}));
+    }
+
+
     #[test]
     fn test_health_status() {
         let response = Response::HealthStatus(HealthDetails {
             queued_jobs: 1,
             busy_threads: 2,
             max_threads: 3,
+            paused: false,
+            uptime: 60,
+            hooks: 4,
+            processed_jobs: 10,
+            failed_jobs: 1,
+            workers: vec![WorkerState { worker: 0, state: "busy" }],
+            build: None,
+            freezes: None,
         });
 
         // The result must be an object
@@ -256,6 +648,12 @@ mod tests {
         assert_eq!(
             result.get("max_threads").unwrap().as_u64().unwrap(),
             3 as u64
-        )
+        );
+
+        // The state of every worker must be included
+        assert_eq!(
+            result.get("workers").unwrap(),
+            &json!([{"worker": 0, "state": "busy"}])
+        );
     }
 }
@@ -0,0 +1,326 @@
+// Copyright (C) 2016-2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! TLS support for the HTTP server.
+//!
+//! The TLS connections are terminated here by the frontend, before the
+//! decrypted requests are handled by the HTTP server.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::{Arc, RwLock};
+
+use futures::{Async, Future, Poll};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    NoClientAuth, ResolvesServerCert, ServerConfig, ServerSession, Session,
+    SignatureScheme,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use webpki::DNSNameRef;
+
+use common::prelude::*;
+
+
+/// Load the TLS configuration from the certificate chain and private key
+/// files, both PEM-encoded.
+pub fn load_config(cert_path: &str, key_path: &str) -> Result<ServerConfig> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .ok()
+        .and_then(|certs| if certs.is_empty() { None } else { Some(certs) })
+        .ok_or_else(|| ErrorKind::TlsInvalidCertificate(cert_path.into()))?;
+
+    // Both PKCS#8 and RSA keys are supported
+    let mut key_file = Vec::new();
+    File::open(key_path)?.read_to_end(&mut key_file)?;
+    let key = pemfile::pkcs8_private_keys(&mut &key_file[..])
+        .ok()
+        .and_then(|mut keys| keys.pop())
+        .or_else(|| {
+            pemfile::rsa_private_keys(&mut &key_file[..])
+                .ok()
+                .and_then(|mut keys| keys.pop())
+        })
+        .ok_or_else(|| ErrorKind::TlsInvalidKey(key_path.into()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, key)
+        .map_err(|_| ErrorKind::TlsInvalidKey(key_path.into()))?;
+
+    Ok(config)
+}
+
+
+/// A certificate which can be replaced while the server is running, used
+/// when it's obtained automatically with ACME. Until the certificate is set
+/// the TLS handshakes fail.
+#[derive(Default)]
+pub struct SharedCertificate {
+    current: RwLock<Option<CertifiedKey>>,
+}
+
+#[cfg_attr(not(feature = "acme"), allow(dead_code))]
+impl SharedCertificate {
+    pub fn new() -> Self {
+        SharedCertificate {
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Replace the certificate with the provided PEM-encoded certificate
+    /// chain and PKCS#8 private key.
+    pub fn set(&self, cert: &str, key: &str) -> Result<()> {
+        let certs = pemfile::certs(&mut cert.as_bytes())
+            .ok()
+            .and_then(|certs| if certs.is_empty() { None } else { Some(certs) })
+            .ok_or_else(|| ErrorKind::TlsInvalidCertificate("ACME".into()))?;
+        let key = pemfile::pkcs8_private_keys(&mut key.as_bytes())
+            .ok()
+            .and_then(|mut keys| keys.pop())
+            .and_then(|key| sign::any_supported_type(&key).ok())
+            .ok_or_else(|| ErrorKind::TlsInvalidKey("ACME".into()))?;
+
+        *self.current.write()? = Some(CertifiedKey::new(certs, Arc::new(key)));
+        Ok(())
+    }
+
+    /// Create a TLS configuration always using the current certificate.
+    pub fn server_config(shared: Arc<SharedCertificate>) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = shared;
+        config
+    }
+}
+
+impl ResolvesServerCert for SharedCertificate {
+    fn resolve(
+        &self, _server_name: Option<DNSNameRef>, _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        self.current.read().ok().and_then(|current| current.clone())
+    }
+}
+
+
+/// A TLS connection terminated with rustls, whose methods don't block if
+/// the underlying connection doesn't.
+pub(super) struct TlsStream<S> {
+    io: S,
+    session: ServerSession,
+    eof: bool,
+    closing: bool,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    /// Start the handshake with the client, resolving to the stream when
+    /// it's completed.
+    pub(super) fn accept(io: S, config: &Arc<ServerConfig>) -> Handshake<S> {
+        Handshake(Some(TlsStream {
+            io,
+            session: ServerSession::new(config),
+            eof: false,
+            closing: false,
+        }))
+    }
+
+    /// Get the protocol negotiated with the client using ALPN, if any.
+    pub(super) fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.session.get_alpn_protocol()
+    }
+
+    /// Send the pending TLS records to the client.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.io)?;
+        }
+        Ok(())
+    }
+
+    /// Receive some TLS records from the client.
+    fn read_tls(&mut self) -> io::Result<()> {
+        if self.session.read_tls(&mut self.io)? == 0 {
+            self.eof = true;
+        }
+
+        if let Err(err) = self.session.process_new_packets() {
+            // Try to send the alert to the client
+            let _ = self.write_tls();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.write_tls()?;
+
+            match self.session.read(buf) {
+                Ok(0) if !self.eof && !buf.is_empty() => self.read_tls()?,
+                Ok(read) => return Ok(read),
+                // The client closed the connection cleanly
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::ConnectionAborted =>
+                {
+                    return Ok(0);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.session.write(buf)?;
+
+        // The data is buffered by the session even if it can't be sent yet
+        match self.write_tls() {
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock && written > 0 =>
+            {
+                Ok(written)
+            }
+            Err(err) => Err(err),
+            Ok(()) => Ok(written),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()?;
+        self.io.flush()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.closing {
+            self.session.send_close_notify();
+            self.closing = true;
+        }
+
+        match self.write_tls() {
+            Ok(()) => self.io.shutdown(),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                Ok(Async::NotReady)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+
+/// The TLS handshake with a client, resolving to the stream when it's
+/// completed.
+pub(super) struct Handshake<S>(Option<TlsStream<S>>);
+
+impl<S: Read + Write> Future for Handshake<S> {
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<TlsStream<S>, io::Error> {
+        {
+            let stream = self.0.as_mut().expect("handshake already completed");
+            let result = (|| {
+                while stream.session.is_handshaking() {
+                    stream.write_tls()?;
+                    if !stream.session.is_handshaking() {
+                        break;
+                    }
+
+                    if stream.eof {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    stream.read_tls()?;
+                }
+
+                // Send the last messages of the handshake
+                stream.write_tls()
+            })();
+
+            match result {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Async::Ready(self.0.take().unwrap()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use common::prelude::*;
+    use utils::testing::*;
+    use super::{load_config, SharedCertificate};
+    use rustls::ResolvesServerCert;
+
+
+    #[test]
+    fn test_load_config() {
+        let dir = TempDir::new("fisher-tests").unwrap();
+        let path = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+
+        let cert = path("cert.pem", TLS_CERT);
+        let key = path("key.pem", TLS_KEY);
+        let garbage = path("garbage.pem", "not a PEM file");
+
+        assert!(load_config(&cert, &key).is_ok());
+
+        assert_err!(
+            load_config(&garbage, &key), ErrorKind::TlsInvalidCertificate(..)
+        );
+        assert_err!(load_config(&key, &key), ErrorKind::TlsInvalidCertificate(..));
+        assert_err!(load_config(&cert, &garbage), ErrorKind::TlsInvalidKey(..));
+        assert_err!(load_config(&cert, &cert), ErrorKind::TlsInvalidKey(..));
+        assert_err!(load_config(&cert, "/missing"), ErrorKind::Io(..));
+    }
+
+
+    #[test]
+    fn test_shared_certificate() {
+        let shared = SharedCertificate::new();
+        assert!(shared.resolve(None, &[]).is_none());
+
+        assert_err!(
+            shared.set("not a PEM file", TLS_KEY),
+            ErrorKind::TlsInvalidCertificate(..)
+        );
+        assert_err!(shared.set(TLS_CERT, TLS_CERT), ErrorKind::TlsInvalidKey(..));
+        assert!(shared.resolve(None, &[]).is_none());
+
+        shared.set(TLS_CERT, TLS_KEY).unwrap();
+        let current = shared.resolve(None, &[]).unwrap();
+        assert_eq!(current.cert.len(), 1);
+    }
+}
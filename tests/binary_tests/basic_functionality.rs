@@ -16,6 +16,8 @@
 use std::fs::File;
 use std::io::Read;
 
+use regex::Regex;
+
 use reqwest;
 
 use common::prelude::*;
@@ -42,7 +44,11 @@ fn fisher_executes_webhooks() {
         let addr = fisher.server_addr()?;
         let mut resp = reqwest::get(&format!("http://{}/hook/test.sh", addr))?;
         assert_eq!(resp.status().as_u16(), 200);
-        assert_eq!(resp.text()?, r#"{"status":"ok"}"#);
+
+        // The response contains the ID of the queued job
+        let re = Regex::new(r#"^\{"job_id":"[0-9a-f]{32}","status":"ok"\}$"#)
+            .unwrap();
+        assert!(re.is_match(&resp.text()?));
 
         // Wait until the file is executed
         loop_timeout! {
@@ -0,0 +1,78 @@
+// Copyright (C) 2018 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::Write;
+
+use serde_json::{self, Value};
+
+use common::prelude::*;
+use common::command::run_fisher;
+
+
+#[test]
+fn fisher_generates_completions() {
+    for shell in &["bash", "zsh", "fish"] {
+        let (code, output) = run_fisher(&["completions", shell]).unwrap();
+        assert_eq!(code, 0);
+        assert!(output.contains("fisher"), "{}", shell);
+
+        let (code, output) =
+            run_fisher(&["--json", "completions", shell]).unwrap();
+        assert_eq!(code, 0);
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["shell"], Value::from(*shell));
+        assert!(json["script"].as_str().unwrap().contains("fisher"));
+    }
+
+    let (code, _) = run_fisher(&["completions", "tcsh"]).unwrap();
+    assert_eq!(code, 2);
+}
+
+
+#[test]
+fn fisher_shows_the_version_as_json() {
+    let (code, output) = run_fisher(&["--version", "--json"]).unwrap();
+    assert_eq!(code, 0);
+
+    let json: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(json["name"], Value::from("fisher"));
+    assert!(json["version"].is_string());
+}
+
+
+#[test]
+fn fisher_exit_codes() {
+    testing_env(|env| {
+        // Invalid command line arguments
+        let (code, _) = run_fisher(&["--invalid"])?;
+        assert_eq!(code, 2);
+        let (code, _) = run_fisher(&["a.toml", "b.toml"])?;
+        assert_eq!(code, 2);
+
+        // Missing or invalid configuration files
+        let (code, output) = run_fisher(&["--json", "missing.toml"])?;
+        assert_eq!(code, 3);
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["exit_code"], Value::from(3));
+
+        let path = env.tempdir()?.join("invalid.toml");
+        File::create(&path)?.write_all(b"[http\n")?;
+        let (code, _) = run_fisher(&[path.to_str().unwrap()])?;
+        assert_eq!(code, 3);
+
+        Ok(())
+    });
+}
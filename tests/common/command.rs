@@ -114,6 +114,20 @@ impl Command {
 }
 
 
+/// Run the Fisher binary until it exits, returning its exit code and what it
+/// printed to the standard output.
+pub fn run_fisher(args: &[&str]) -> Result<(i32, String)> {
+    let output = process::Command::new(binaries_path()?.join("fisher"))
+        .args(args)
+        .output()?;
+
+    Ok((
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
+
+
 pub struct FisherCommand {
     inner: Command,
 }
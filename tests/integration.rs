@@ -20,6 +20,7 @@ extern crate lazy_static;
 extern crate nix;
 extern crate regex;
 extern crate reqwest;
+extern crate serde_json;
 extern crate tempdir;
 extern crate toml;
 #[macro_use]